@@ -0,0 +1,290 @@
+//! Reading a macOS Photos library (`*.photoslibrary`): where each original
+//! lives under `originals/`, its capture date, favorite flag, location and
+//! album membership, from `database/Photos.sqlite`.
+//!
+//! No access to Terra's database; `import_apple_photos` in `lib.rs` copies
+//! the originals in. The schema is Photos' private Core Data store, so table
+//! and join-column names are looked up rather than assumed.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use rusqlite::{params, Connection, OptionalExtension, Result as SqlResult};
+
+use crate::media;
+use crate::PhotoMetadata;
+use crate::sqlite_snapshot::Snapshot;
+
+/// Core Data timestamps count seconds from 2001-01-01 UTC.
+const CORE_DATA_EPOCH: i64 = 978_307_200;
+/// Photos' "no location" coordinate.
+const NO_LOCATION: f64 = -180.0;
+/// ZGENERICALBUM.ZKIND of albums the user made.
+const USER_ALBUM_KIND: i64 = 2;
+
+/// One photo or video in the Photos library.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AppleAsset {
+    pub id: i64,
+    /// Where the original should be; missing when it's only in iCloud
+    pub original: PathBuf,
+    /// The name it was imported into Photos with (originals are stored by UUID)
+    pub file_name: Option<String>,
+    pub date_taken: Option<i64>,
+    pub width: u32,
+    pub height: u32,
+    pub is_favorite: bool,
+    pub gps: Option<(f64, f64)>,
+}
+
+impl AppleAsset {
+    /// Name to show the user, e.g. when reporting it skipped
+    pub(crate) fn display_name(&self) -> String {
+        self.file_name
+            .clone()
+            .unwrap_or_else(|| self.original.to_string_lossy().to_string())
+    }
+
+    /// Lay what Photos knows over `photo`, read from the original. Photos'
+    /// capture date is recorded as a sidecar date, so rescans of the copy
+    /// keep it rather than falling back to the file's mtime. The image crate
+    /// can't size HEIC, so Photos' dimensions fill in. Location is left to
+    /// the caller, which geocodes it.
+    pub(crate) fn overlay(&self, photo: &mut PhotoMetadata) {
+        if let Some(date) = self.date_taken {
+            photo.date_taken = date;
+            photo.date_source = Some(media::DATE_SOURCE_SIDECAR.to_string());
+            photo.date_confident = Some(true);
+        }
+        if let Some(name) = &self.file_name {
+            photo.name = name.clone();
+        }
+        if photo.width == 0 || photo.height == 0 {
+            photo.width = self.width;
+            photo.height = self.height;
+        }
+        photo.is_favorite = self.is_favorite;
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct AppleAlbum {
+    pub title: String,
+    /// AppleAsset ids
+    pub assets: Vec<i64>,
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct PhotosLibrary {
+    pub assets: Vec<AppleAsset>,
+    pub albums: Vec<AppleAlbum>,
+}
+
+/// Read a `.photoslibrary` bundle. The database is copied aside first:
+/// Photos keeps it open, and this must never write to it.
+pub(crate) fn read_library(library: &Path) -> Result<PhotosLibrary, String> {
+    let database = library.join("database").join("Photos.sqlite");
+    if !database.is_file() {
+        return Err(format!("{} is not a Photos library (no database/Photos.sqlite)", library.display()));
+    }
+
+    let snapshot = Snapshot::take(&database).map_err(|e| {
+        if e.kind() == std::io::ErrorKind::PermissionDenied {
+            "Terra can't read the Photos library. Allow Full Disk Access for Terra in System Settings > Privacy & Security.".to_string()
+        } else {
+            format!("Failed to copy the Photos database: {}", e)
+        }
+    })?;
+    read_database(snapshot.conn(), library).map_err(|e| format!("Failed to read the Photos database: {}", e))
+}
+
+fn table_exists(conn: &Connection, name: &str) -> SqlResult<bool> {
+    conn.query_row(
+        "SELECT 1 FROM sqlite_master WHERE type = 'table' AND name = ?1",
+        params![name],
+        |_| Ok(()),
+    )
+    .optional()
+    .map(|found| found.is_some())
+}
+
+/// Read assets and user albums from an open Photos database. `library` is
+/// the bundle the `originals/` paths are resolved against.
+pub(crate) fn read_database(conn: &Connection, library: &Path) -> SqlResult<PhotosLibrary> {
+    // macOS 10.15 called the table ZGENERICASSET
+    let asset_table = if table_exists(conn, "ZASSET")? { "ZASSET" } else { "ZGENERICASSET" };
+    let query = format!(
+        "SELECT a.Z_PK, a.ZDIRECTORY, a.ZFILENAME, a.ZDATECREATED, a.ZWIDTH, a.ZHEIGHT,
+                a.ZFAVORITE, a.ZLATITUDE, a.ZLONGITUDE, attr.ZORIGINALFILENAME
+         FROM {} a
+         LEFT JOIN ZADDITIONALASSETATTRIBUTES attr ON attr.ZASSET = a.Z_PK
+         WHERE COALESCE(a.ZTRASHEDSTATE, 0) = 0
+           AND a.ZDIRECTORY IS NOT NULL AND a.ZFILENAME IS NOT NULL
+         ORDER BY a.Z_PK",
+        asset_table
+    );
+    let originals = library.join("originals");
+    let mut stmt = conn.prepare(&query)?;
+    let assets = stmt
+        .query_map([], |row| {
+            let directory: String = row.get(1)?;
+            let file_name: String = row.get(2)?;
+            let created: Option<f64> = row.get(3)?;
+            let latitude: Option<f64> = row.get(7)?;
+            let longitude: Option<f64> = row.get(8)?;
+            let gps = match (latitude, longitude) {
+                (Some(lat), Some(lon)) if lat != NO_LOCATION && lon != NO_LOCATION => {
+                    media::finalize_gps(lat, lon, false, false)
+                }
+                _ => None,
+            };
+            Ok(AppleAsset {
+                id: row.get(0)?,
+                original: originals.join(directory).join(file_name),
+                file_name: row
+                    .get::<_, Option<String>>(9)?
+                    .and_then(|n| Path::new(&n).file_name().map(|n| n.to_string_lossy().to_string())),
+                date_taken: created.map(|t| t as i64 + CORE_DATA_EPOCH),
+                width: row.get::<_, Option<i64>>(4)?.unwrap_or(0).max(0) as u32,
+                height: row.get::<_, Option<i64>>(5)?.unwrap_or(0).max(0) as u32,
+                is_favorite: row.get::<_, Option<i64>>(6)?.unwrap_or(0) != 0,
+                gps,
+            })
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+
+    Ok(PhotosLibrary { assets, albums: read_albums(conn)? })
+}
+
+/// User albums and their assets. The album/asset join table is named after
+/// Core Data entity numbers (Z_26ASSETS, Z_28ALBUMS, ...) that change between
+/// macOS releases, so it is found by its columns.
+fn read_albums(conn: &Connection) -> SqlResult<Vec<AppleAlbum>> {
+    if !table_exists(conn, "ZGENERICALBUM")? {
+        return Ok(Vec::new());
+    }
+    let Some((join_table, album_column, asset_column)) = find_album_join(conn)? else {
+        return Ok(Vec::new());
+    };
+
+    let mut titles: Vec<(i64, String)> = Vec::new();
+    {
+        let mut stmt = conn.prepare(
+            "SELECT Z_PK, ZTITLE FROM ZGENERICALBUM
+             WHERE ZKIND = ?1 AND COALESCE(ZTRASHEDSTATE, 0) = 0 AND ZTITLE IS NOT NULL
+             ORDER BY Z_PK",
+        )?;
+        let rows = stmt.query_map(params![USER_ALBUM_KIND], |row| Ok((row.get(0)?, row.get(1)?)))?;
+        for row in rows {
+            titles.push(row?);
+        }
+    }
+
+    let mut members: HashMap<i64, Vec<i64>> = HashMap::new();
+    let query = format!("SELECT {}, {} FROM {}", album_column, asset_column, join_table);
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+    for row in rows {
+        let (album, asset) = row?;
+        members.entry(album).or_default().push(asset);
+    }
+
+    Ok(titles
+        .into_iter()
+        .filter(|(_, title)| !title.trim().is_empty())
+        .map(|(id, title)| AppleAlbum {
+            title: title.trim().to_string(),
+            assets: members.remove(&id).unwrap_or_default(),
+        })
+        .collect())
+}
+
+/// (table, album column, asset column) of the album membership table.
+fn find_album_join(conn: &Connection) -> SqlResult<Option<(String, String, String)>> {
+    let tables: Vec<String> = {
+        let mut stmt = conn.prepare(
+            "SELECT name FROM sqlite_master WHERE type = 'table' AND name LIKE 'Z!_%ASSETS' ESCAPE '!'",
+        )?;
+        let rows = stmt.query_map([], |row| row.get(0))?;
+        rows.collect::<SqlResult<_>>()?
+    };
+    for table in tables {
+        let columns: Vec<String> = {
+            let mut stmt = conn.prepare(&format!("PRAGMA table_info({})", table))?;
+            let rows = stmt.query_map([], |row| row.get(1))?;
+            rows.collect::<SqlResult<_>>()?
+        };
+        let album = columns.iter().find(|c| c.starts_with("Z_") && c.ends_with("ALBUMS"));
+        let asset = columns.iter().find(|c| c.starts_with("Z_") && c.ends_with("ASSETS"));
+        if let (Some(album), Some(asset)) = (album, asset) {
+            return Ok(Some((table, album.clone(), asset.clone())));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parts of a macOS 12 Photos.sqlite that Terra reads
+    fn photos_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE ZASSET (Z_PK INTEGER PRIMARY KEY, ZDIRECTORY TEXT, ZFILENAME TEXT,
+                 ZDATECREATED REAL, ZWIDTH INTEGER, ZHEIGHT INTEGER, ZFAVORITE INTEGER,
+                 ZLATITUDE REAL, ZLONGITUDE REAL, ZTRASHEDSTATE INTEGER);
+             CREATE TABLE ZADDITIONALASSETATTRIBUTES (Z_PK INTEGER PRIMARY KEY, ZASSET INTEGER,
+                 ZORIGINALFILENAME TEXT);
+             CREATE TABLE ZGENERICALBUM (Z_PK INTEGER PRIMARY KEY, ZKIND INTEGER, ZTITLE TEXT,
+                 ZTRASHEDSTATE INTEGER);
+             CREATE TABLE Z_28ASSETS (Z_28ALBUMS INTEGER, Z_3ASSETS INTEGER);
+
+             INSERT INTO ZASSET VALUES (1, 'A', 'AAAA-1111.heic', 600000000.5, 4032, 3024, 1, 47.6, -122.3, 0);
+             INSERT INTO ZASSET VALUES (2, 'B', 'BBBB-2222.mov', 600000100.0, 1920, 1080, 0, -180.0, -180.0, 0);
+             INSERT INTO ZASSET VALUES (3, 'C', 'CCCC-3333.jpeg', 600000200.0, 10, 10, 0, NULL, NULL, 1);
+             INSERT INTO ZADDITIONALASSETATTRIBUTES VALUES (10, 1, 'IMG_0001.HEIC');
+             INSERT INTO ZADDITIONALASSETATTRIBUTES VALUES (11, 2, 'IMG_0002.MOV');
+
+             INSERT INTO ZGENERICALBUM VALUES (20, 2, 'Hawaii', 0);
+             INSERT INTO ZGENERICALBUM VALUES (21, 3, 'Recents', 0);
+             INSERT INTO Z_28ASSETS VALUES (20, 1);
+             INSERT INTO Z_28ASSETS VALUES (20, 2);
+             INSERT INTO Z_28ASSETS VALUES (21, 1);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn reads_assets_without_trashed_ones() {
+        let library = Path::new("/Users/me/Pictures/Photos Library.photoslibrary");
+        let read = read_database(&photos_db(), library).unwrap();
+        assert_eq!(read.assets.len(), 2);
+
+        let heic = &read.assets[0];
+        assert_eq!(heic.original, library.join("originals").join("A").join("AAAA-1111.heic"));
+        assert_eq!(heic.file_name.as_deref(), Some("IMG_0001.HEIC"));
+        assert_eq!(heic.date_taken, Some(600_000_000 + CORE_DATA_EPOCH));
+        assert_eq!((heic.width, heic.height), (4032, 3024));
+        assert!(heic.is_favorite);
+        assert_eq!(heic.gps, Some((47.6, -122.3)));
+
+        // -180/-180 is Photos' "no location"
+        assert_eq!(read.assets[1].gps, None);
+        assert!(!read.assets[1].is_favorite);
+    }
+
+    #[test]
+    fn reads_user_albums_through_the_numbered_join_table() {
+        let read = read_database(&photos_db(), Path::new("/lib")).unwrap();
+        assert_eq!(read.albums, vec![AppleAlbum { title: "Hawaii".to_string(), assets: vec![1, 2] }]);
+    }
+
+    #[test]
+    fn missing_database_is_a_clear_error() {
+        let err = read_library(Path::new("/nonexistent/Photos Library.photoslibrary")).unwrap_err();
+        assert!(err.contains("not a Photos library"));
+    }
+}
@@ -0,0 +1,305 @@
+//! Scheduled rescans of the watch folders, for folders another app fills
+//! while Terra isn't looking (a Dropbox camera-uploads folder, say) or where
+//! file events are unreliable, like network shares.
+//!
+//! A tokio interval ticks every minute and starts a run once the configured
+//! interval has passed since the last. The last run's time is kept in
+//! settings, so an app restarted more often than the interval still scans.
+//! A run that comes due while a manual scan or import is going is skipped
+//! until the next interval. Each folder
+//! is rescanned incrementally and recorded as an "auto_scan" import session;
+//! a folder that can't be read (an unmounted drive) is reported without
+//! stopping the rest.
+
+use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Manager};
+
+use crate::db::{self, DbState};
+use crate::ScanState;
+
+/// Setting holding AutoScanSettings as JSON
+pub const AUTO_SCAN_SETTING: &str = "auto_scan";
+
+/// Setting holding when the last run started, in unix seconds
+const LAST_RUN_SETTING: &str = "auto_scan_last_run";
+
+pub const DEFAULT_INTERVAL_MINUTES: u32 = 60;
+pub const MIN_INTERVAL_MINUTES: u32 = 5;
+pub const MAX_INTERVAL_MINUTES: u32 = 7 * 24 * 60;
+
+/// How often the scheduler checks whether a run is due
+const TICK: Duration = Duration::from_secs(60);
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct AutoScanSettings {
+    pub enabled: bool,
+    pub interval_minutes: u32,
+}
+
+impl Default for AutoScanSettings {
+    fn default() -> Self {
+        AutoScanSettings { enabled: false, interval_minutes: DEFAULT_INTERVAL_MINUTES }
+    }
+}
+
+impl AutoScanSettings {
+    pub fn validate(&self) -> Result<(), String> {
+        if !(MIN_INTERVAL_MINUTES..=MAX_INTERVAL_MINUTES).contains(&self.interval_minutes) {
+            return Err(format!(
+                "Scan interval must be between {} and {} minutes",
+                MIN_INTERVAL_MINUTES, MAX_INTERVAL_MINUTES
+            ));
+        }
+        Ok(())
+    }
+
+    /// When the run after one at `last` (unix seconds) is due, if enabled
+    fn next_run_after(&self, last: i64) -> Option<i64> {
+        self.enabled.then(|| last + self.interval_minutes as i64 * 60)
+    }
+}
+
+/// The stored settings, or the defaults if unset or unreadable
+pub fn load_settings(conn: &rusqlite::Connection) -> AutoScanSettings {
+    db::get_setting(conn, AUTO_SCAN_SETTING)
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+/// When the last run started, from any earlier session
+fn load_last_run(conn: &rusqlite::Connection) -> Option<i64> {
+    db::get_setting(conn, LAST_RUN_SETTING).and_then(|raw| raw.parse().ok())
+}
+
+/// A folder a run couldn't scan
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct FolderFailure {
+    pub folder: String,
+    pub error: String,
+}
+
+/// What one scheduled run did
+#[derive(Serialize, Debug, Clone, Default, PartialEq)]
+pub struct AutoScanRun {
+    pub started_at: i64,
+    pub finished_at: i64,
+    pub folders_scanned: u32,
+    pub added: u32,
+    pub updated: u32,
+    pub missing: u32,
+    pub failed: Vec<FolderFailure>,
+    /// Set when the run was skipped, e.g. because a scan was already running
+    pub skipped: Option<String>,
+}
+
+/// Payload of get_auto_scan_status
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct AutoScanStatus {
+    pub enabled: bool,
+    pub interval_minutes: u32,
+    pub last_run: Option<i64>,
+    pub next_run: Option<i64>,
+    pub last_result: Option<AutoScanRun>,
+}
+
+#[derive(Default)]
+struct Schedule {
+    last_run: Option<i64>,
+    last_result: Option<AutoScanRun>,
+    next_run: Option<i64>,
+    running: bool,
+}
+
+/// The scheduler's state, held in Tauri managed state.
+#[derive(Default)]
+pub struct AutoScanState(Mutex<Schedule>);
+
+impl AutoScanState {
+    /// Count the interval from now after the settings change
+    pub fn reschedule(&self, settings: &AutoScanSettings) {
+        if let Ok(mut schedule) = self.0.lock() {
+            schedule.next_run = settings.next_run_after(chrono::Utc::now().timestamp());
+        }
+    }
+
+    pub fn status(&self, settings: &AutoScanSettings) -> AutoScanStatus {
+        let schedule = self.0.lock().map(|s| (s.last_run, s.last_result.clone(), s.next_run)).unwrap_or_default();
+        AutoScanStatus {
+            enabled: settings.enabled,
+            interval_minutes: settings.interval_minutes,
+            last_run: schedule.0,
+            next_run: schedule.2.filter(|_| settings.enabled),
+            last_result: schedule.1,
+        }
+    }
+
+    /// Claim the next run if it's due at `now` and none is in progress.
+    /// `stored_last_run` (from settings) schedules the first run after launch.
+    fn take_due(&self, settings: &AutoScanSettings, stored_last_run: Option<i64>, now: i64) -> bool {
+        let Ok(mut schedule) = self.0.lock() else {
+            return false;
+        };
+        if schedule.last_run.is_none() {
+            schedule.last_run = stored_last_run;
+        }
+        if !settings.enabled {
+            schedule.next_run = None;
+            return false;
+        }
+        let last_run = schedule.last_run;
+        let next_run =
+            *schedule.next_run.get_or_insert_with(|| settings.next_run_after(last_run.unwrap_or(now)).unwrap_or(now));
+        if schedule.running || now < next_run {
+            return false;
+        }
+        schedule.running = true;
+        true
+    }
+
+    fn finish(&self, run: AutoScanRun, settings: &AutoScanSettings) {
+        if let Ok(mut schedule) = self.0.lock() {
+            schedule.next_run = settings.next_run_after(run.started_at);
+            schedule.last_run = Some(run.started_at);
+            schedule.last_result = Some(run);
+            schedule.running = false;
+        }
+    }
+}
+
+/// Start the scheduler. Called once from `run()`'s setup.
+pub fn start(app: &AppHandle) {
+    let app = app.clone();
+    tauri::async_runtime::spawn(async move {
+        let mut interval = tokio::time::interval(TICK);
+        interval.set_missed_tick_behavior(tokio::time::MissedTickBehavior::Delay);
+        loop {
+            interval.tick().await;
+            let (settings, last_run) = match crate::db_conn(&app.state::<DbState>()) {
+                Ok(conn) => (load_settings(&conn), load_last_run(&conn)),
+                Err(e) => {
+                    warn!("Auto scan: {}", e);
+                    continue;
+                }
+            };
+            let state = app.state::<AutoScanState>();
+            if !state.take_due(&settings, last_run, chrono::Utc::now().timestamp()) {
+                continue;
+            }
+            let worker = app.clone();
+            let run = tauri::async_runtime::spawn_blocking(move || run_once(&worker))
+                .await
+                .unwrap_or_else(|e| AutoScanRun { skipped: Some(format!("Auto scan failed: {}", e)), ..Default::default() });
+            let started_at = run.started_at;
+            state.finish(run, &settings);
+            let saved = crate::with_db_write(&app.state::<DbState>(), "Failed to save the last run time", |conn| {
+                db::set_setting(conn, LAST_RUN_SETTING, &started_at.to_string())
+            });
+            if let Err(e) = saved {
+                warn!("Auto scan: {}", e);
+            }
+        }
+    });
+}
+
+/// Rescan every watch folder now, unless a scan or import is running.
+fn run_once(app: &AppHandle) -> AutoScanRun {
+    let mut run = AutoScanRun { started_at: chrono::Utc::now().timestamp(), ..Default::default() };
+    let scan_state = app.state::<ScanState>();
+    let db_state = app.state::<DbState>();
+    // Held for the whole run, so a manual scan started meanwhile doesn't
+    // clear a cancel made on this one
+    let Some(_active) = scan_state.try_begin() else {
+        info!("Auto scan skipped: a scan or import is running");
+        run.skipped = Some("A scan or import was already running".to_string());
+        run.finished_at = run.started_at;
+        return run;
+    };
+
+    let folders: Vec<PathBuf> = match crate::db_conn(&db_state) {
+        Ok(conn) => db::get_watch_folders(&conn),
+        Err(e) => {
+            run.skipped = Some(e);
+            return run;
+        }
+    };
+    for folder in folders {
+        let folder = folder.to_string_lossy().to_string();
+        match crate::rescan_folder(app, &scan_state, &db_state, &folder, Some("auto_scan")) {
+            Ok(summary) => {
+                run.folders_scanned += 1;
+                run.added += summary.added;
+                run.updated += summary.updated;
+                run.missing += summary.missing;
+            }
+            Err(error) => {
+                warn!("Auto scan of {} failed: {}", folder, error);
+                run.failed.push(FolderFailure { folder, error });
+            }
+        }
+    }
+    run.finished_at = chrono::Utc::now().timestamp();
+    info!(
+        "Auto scan: {} folders, {} added, {} updated, {} missing, {} failed",
+        run.folders_scanned,
+        run.added,
+        run.updated,
+        run.missing,
+        run.failed.len()
+    );
+    run
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn validates_interval_bounds() {
+        assert!(AutoScanSettings::default().validate().is_ok());
+        let too_often = AutoScanSettings { enabled: true, interval_minutes: 1 };
+        assert!(too_often.validate().unwrap_err().contains("between"));
+        let too_rare = AutoScanSettings { interval_minutes: MAX_INTERVAL_MINUTES + 1, ..Default::default() };
+        assert!(too_rare.validate().is_err());
+    }
+
+    #[test]
+    fn runs_come_due_an_interval_apart() {
+        let state = AutoScanState::default();
+        let hourly = AutoScanSettings { enabled: true, interval_minutes: 60 };
+        // The first tick only schedules
+        assert!(!state.take_due(&hourly, None, 1_000));
+        assert!(!state.take_due(&hourly, None, 1_000 + 3_599));
+        assert!(state.take_due(&hourly, None, 1_000 + 3_600));
+        // Not claimed twice while it runs
+        assert!(!state.take_due(&hourly, None, 1_000 + 3_700));
+
+        state.finish(AutoScanRun { started_at: 4_600, ..Default::default() }, &hourly);
+        let status = state.status(&hourly);
+        assert_eq!((status.last_run, status.next_run), (Some(4_600), Some(8_200)));
+
+        let off = AutoScanSettings { enabled: false, ..hourly };
+        assert!(!state.take_due(&off, None, 9_000));
+        assert_eq!(state.status(&off).next_run, None);
+    }
+
+    #[test]
+    fn the_last_run_before_a_restart_counts() {
+        let hourly = AutoScanSettings { enabled: true, interval_minutes: 60 };
+        // Overdue since the last session: runs on the first tick
+        let state = AutoScanState::default();
+        assert!(state.take_due(&hourly, Some(1_000), 1_000 + 7_200));
+        assert_eq!(state.status(&hourly).last_run, Some(1_000));
+
+        // Ran shortly before the restart: waits out the rest of the interval
+        let state = AutoScanState::default();
+        assert!(!state.take_due(&hourly, Some(1_000), 1_000 + 600));
+        assert_eq!(state.status(&hourly).next_run, Some(4_600));
+        assert!(state.take_due(&hourly, Some(1_000), 4_600));
+    }
+}
@@ -0,0 +1,168 @@
+//! On-device tags like "dog", "beach" or "food" from an ONNX image
+//! classifier (a MobileNet-style ImageNet model, say). Nothing leaves the
+//! machine. Running the model needs the `auto-tag` cargo feature, which
+//! pulls in the tract runtime; see onnx.rs.
+//!
+//! The model is a file the user downloads into `models/` beside the
+//! database, with a labels file giving a tag name per output class, one per
+//! line in class order. Blank lines leave a class untagged, and classes can
+//! share a name, so "golden retriever" and "beagle" can both read "dog".
+//! The two files' hash is the model version: photos are tagged once per
+//! version, and replacing either file has them looked at again.
+
+use std::fs;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::onnx;
+use crate::thumbnails;
+
+const MODEL_FILE: &str = "classifier.onnx";
+const LABELS_FILE: &str = "classifier-labels.txt";
+
+/// Side of the square the model takes, as MobileNet and most ImageNet
+/// models do
+const INPUT_SIZE: u32 = 224;
+/// ImageNet channel statistics the input is normalized with
+const MEAN: [f32; 3] = [0.485, 0.456, 0.406];
+const STD: [f32; 3] = [0.229, 0.224, 0.225];
+
+/// Lowest probability that becomes a tag, unless the job says otherwise
+pub const DEFAULT_THRESHOLD: f32 = 0.3;
+/// Most auto tags one photo gets
+const MAX_TAGS_PER_PHOTO: usize = 3;
+/// Color of tags the auto-tagger creates
+pub const AUTO_TAG_COLOR: &str = "#64748b";
+
+/// Where auto-tagging stands, for settings
+#[derive(Serialize, Debug)]
+pub struct AutoTagStatus {
+    /// Whether this build can run a model at all
+    pub available: bool,
+    /// Where the model and labels files go
+    pub model_path: String,
+    pub labels_path: String,
+    /// Set once both files are in place
+    pub model_version: Option<String>,
+    /// Photos the current model has looked at
+    pub processed: i64,
+}
+
+pub fn status(conn: &rusqlite::Connection) -> AutoTagStatus {
+    let dir = onnx::models_dir();
+    let model_version = onnx::files_version(&[&dir.join(MODEL_FILE), &dir.join(LABELS_FILE)]).ok();
+    let processed = model_version
+        .as_deref()
+        .map_or(0, |version| crate::db::count_auto_tagged(conn, version).unwrap_or(0));
+    AutoTagStatus {
+        available: onnx::runtime_available(),
+        model_path: dir.join(MODEL_FILE).to_string_lossy().to_string(),
+        labels_path: dir.join(LABELS_FILE).to_string_lossy().to_string(),
+        model_version,
+        processed,
+    }
+}
+
+/// A loaded model and its labels
+pub struct Classifier {
+    model: onnx::Model,
+    labels: Vec<String>,
+    pub version: String,
+}
+
+impl Classifier {
+    pub fn load() -> Result<Classifier, String> {
+        let dir = onnx::models_dir();
+        let (model_path, labels_path) = (dir.join(MODEL_FILE), dir.join(LABELS_FILE));
+        if !model_path.exists() || !labels_path.exists() {
+            return Err(format!(
+                "Auto-tagging needs a classifier model at {} and its labels at {}",
+                model_path.display(),
+                labels_path.display()
+            ));
+        }
+        let labels = fs::read_to_string(&labels_path)
+            .map_err(|e| format!("Failed to read {}: {}", labels_path.display(), e))?
+            .lines()
+            .map(|line| line.trim().to_lowercase())
+            .collect();
+        let size = INPUT_SIZE as usize;
+        Ok(Classifier {
+            model: onnx::load(&model_path, [1, 3, size, size])?,
+            labels,
+            version: onnx::files_version(&[&model_path, &labels_path])?,
+        })
+    }
+
+    /// Tags and their probabilities for one photo, most likely first. The
+    /// cached thumbnail is read when the photo has one, which also covers
+    /// HEIC and RAW files the image crate can't open.
+    pub fn classify(&self, path: &Path, content_hash: Option<&str>, threshold: f32) -> Result<Vec<(String, f32)>, String> {
+        let source = match content_hash {
+            Some(hash) => thumbnails::generate_thumbnail(path, hash, thumbnails::bucket_size(INPUT_SIZE))?,
+            None => path.to_path_buf(),
+        };
+        let img = image::open(&source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+        let rgb = img.resize_exact(INPUT_SIZE, INPUT_SIZE, image::imageops::FilterType::Triangle).to_rgb8();
+        let size = INPUT_SIZE as usize;
+        let outputs = onnx::run(&self.model, &onnx::planar_rgb(&rgb, MEAN, STD), [1, 3, size, size])?;
+        let scores = outputs.first().ok_or("The classifier gave no output")?;
+        Ok(top_labels(scores, &self.labels, threshold))
+    }
+}
+
+/// Labels scoring at least `threshold`, best first, at most
+/// MAX_TAGS_PER_PHOTO. Raw logits are put through softmax first; classes
+/// sharing a label count once, at their best.
+fn top_labels(scores: &[f32], labels: &[String], threshold: f32) -> Vec<(String, f32)> {
+    let is_distribution = scores.iter().all(|s| (0.0..=1.0).contains(s)) && (scores.iter().sum::<f32>() - 1.0).abs() < 0.01;
+    let probabilities: Vec<f32> = if is_distribution {
+        scores.to_vec()
+    } else {
+        let max = scores.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+        let exps: Vec<f32> = scores.iter().map(|s| (s - max).exp()).collect();
+        let sum: f32 = exps.iter().sum();
+        exps.iter().map(|e| e / sum).collect()
+    };
+
+    let mut best: Vec<(String, f32)> = Vec::new();
+    for (label, &p) in labels.iter().zip(&probabilities) {
+        if label.is_empty() || p < threshold {
+            continue;
+        }
+        match best.iter_mut().find(|(l, _)| l == label) {
+            Some(entry) => entry.1 = entry.1.max(p),
+            None => best.push((label.clone(), p)),
+        }
+    }
+    best.sort_by(|a, b| b.1.total_cmp(&a.1));
+    best.truncate(MAX_TAGS_PER_PHOTO);
+    best
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn labels(names: &[&str]) -> Vec<String> {
+        names.iter().map(|n| n.to_string()).collect()
+    }
+
+    #[test]
+    fn keeps_the_likeliest_labels_over_the_threshold() {
+        let names = labels(&["dog", "", "beach", "dog", "food"]);
+        let picked = top_labels(&[0.05, 0.4, 0.3, 0.2, 0.05], &names, 0.1);
+        // The unnamed class is never a tag; the two dog classes count once
+        assert_eq!(picked, vec![("beach".to_string(), 0.3), ("dog".to_string(), 0.2)]);
+    }
+
+    #[test]
+    fn applies_softmax_to_logits() {
+        let names = labels(&["dog", "cat"]);
+        let picked = top_labels(&[2.0, 0.0], &names, 0.5);
+        assert_eq!(picked.len(), 1);
+        assert_eq!(picked[0].0, "dog");
+        assert!((picked[0].1 - 0.8808).abs() < 1e-3);
+    }
+}
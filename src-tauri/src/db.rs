@@ -1,6 +1,14 @@
-use rusqlite::{Connection, Result as SqlResult, params};
+use rusqlite::{Connection, OptionalExtension, Result as SqlResult, params};
+use std::collections::HashMap;
 use std::path::PathBuf;
+use std::sync::Mutex;
+use std::time::Duration;
 use dirs;
+use crate::edits::PhotoEdits;
+use crate::operations::{Operation, PreviousDate};
+use crate::media;
+use crate::places::Place;
+use crate::metadata_export::{self, ImportMode, PhotoRecord};
 use crate::PhotoMetadata;
 
 /// Get the path to the Terra database file
@@ -12,205 +20,1310 @@ pub fn get_db_path() -> PathBuf {
     path
 }
 
-/// Get the path to the managed Terra library directory.
-/// Checks the settings table for a custom path first, falls back to ~/Pictures/Terra.
-pub fn get_library_path() -> PathBuf {
-    // Try to read custom path from settings
-    if let Ok(conn) = Connection::open(get_db_path()) {
-        if let Some(custom_path) = get_setting(&conn, "library_path") {
-            let path = PathBuf::from(&custom_path);
-            if std::fs::create_dir_all(&path).is_ok() {
-                return path;
-            }
+/// Why the library root can't be used right now.
+#[derive(Debug)]
+pub enum LibraryPathError {
+    /// The configured root is missing, e.g. an external drive that isn't mounted.
+    Unavailable(PathBuf),
+    /// No custom root is set and the OS has no Pictures directory.
+    NoPicturesDir,
+    /// The directory exists (or should) but couldn't be created.
+    Io(PathBuf, std::io::Error),
+}
+
+impl std::fmt::Display for LibraryPathError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LibraryPathError::Unavailable(path) => write!(
+                f,
+                "Library folder {} is not available. Reconnect the drive or choose a new library location.",
+                path.display()
+            ),
+            LibraryPathError::NoPicturesDir => write!(f, "Could not find a Pictures folder for the library"),
+            LibraryPathError::Io(path, e) => write!(f, "Could not create {}: {}", path.display(), e),
         }
     }
+}
+
+impl From<LibraryPathError> for String {
+    fn from(e: LibraryPathError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Get the path to the managed Terra library directory.
+/// Uses the `library_path` setting if set, otherwise ~/Pictures/Terra.
+/// A configured root is never created here: if it's missing the drive is
+/// probably unmounted, and creating it would put imports on the wrong disk.
+pub fn get_library_path(conn: &Connection) -> Result<PathBuf, LibraryPathError> {
+    if let Some(custom_path) = get_setting(conn, "library_path").map(|raw| setting_text(&raw)) {
+        let path = PathBuf::from(&custom_path);
+        return if path.is_dir() {
+            Ok(path)
+        } else {
+            Err(LibraryPathError::Unavailable(path))
+        };
+    }
 
-    let mut path = dirs::picture_dir().expect("Failed to get Pictures directory");
+    let mut path = dirs::picture_dir().ok_or(LibraryPathError::NoPicturesDir)?;
     path.push("Terra");
-    std::fs::create_dir_all(&path).expect("Failed to create Terra library directory");
-    path
+    std::fs::create_dir_all(&path).map_err(|e| LibraryPathError::Io(path.clone(), e))?;
+    Ok(path)
 }
 
-/// Initialize schema on an existing connection.
-/// Used by both init_database() and tests (with in-memory DBs).
-pub fn init_schema(conn: &Connection) -> SqlResult<()> {
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS photos (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            path TEXT NOT NULL UNIQUE,
-            name TEXT NOT NULL,
-            date_taken INTEGER NOT NULL,
-            width INTEGER NOT NULL,
-            height INTEGER NOT NULL,
-            source_type TEXT NOT NULL,
-            created_at INTEGER NOT NULL,
-            is_favorite INTEGER DEFAULT 0,
-            content_hash TEXT,
-            latitude REAL,
-            longitude REAL,
-            location_name TEXT
-        )",
-        [],
-    )?;
+/// Point new imports at `path`. The previous root is remembered so photos
+/// already stored there are still treated as library files.
+pub fn set_library_path(conn: &Connection, path: &std::path::Path) -> SqlResult<()> {
+    let mut previous = get_previous_library_paths(conn);
+    let current = match get_setting(conn, "library_path") {
+        Some(raw) => Some(PathBuf::from(setting_text(&raw))),
+        None => dirs::picture_dir().map(|p| p.join("Terra")),
+    };
+    if let Some(current) = current {
+        if current != path && !previous.contains(&current) {
+            previous.push(current);
+        }
+    }
+    previous.retain(|p| p != path);
 
-    // Attempt to add columns if they don't exist (for existing DBs)
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN is_favorite INTEGER DEFAULT 0", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN content_hash TEXT", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN latitude REAL", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN longitude REAL", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN location_name TEXT", []);
-
-    // New columns for duplicate/screenshot detection
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN dhash_64 INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN is_screenshot INTEGER DEFAULT 0", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN archived_at INTEGER", []);
-
-    // New columns for TerraForm and Smart Collections
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN reviewed_at INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN file_size INTEGER", []);
-
-    // New columns for enriched camera/lens/video metadata
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN camera_make TEXT", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN camera_model TEXT", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN lens_model TEXT", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN iso INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN aperture REAL", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN shutter_us INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN focal_length_mm REAL", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN orientation INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN duration_ms INTEGER", []);
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN codec TEXT", []);
-
-    // Thumbnail generation tracking. NULL = pending, 'ready' = on-disk thumb exists,
-    // 'failed' = decoder rejected (e.g. unsupported HEIC), 'unsupported' = video.
-    let _ = conn.execute("ALTER TABLE photos ADD COLUMN thumb_status TEXT", []);
-
-    // Create albums table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS albums (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL,
-            cover_photo_path TEXT,
-            created_at INTEGER NOT NULL
-        )",
-        [],
-    )?;
+    let previous: Vec<String> = previous.iter().map(|p| p.to_string_lossy().to_string()).collect();
+    let tx = conn.unchecked_transaction()?;
+    write_library_path(&tx, path, &previous)?;
+    tx.commit()
+}
 
-    // Create album_photos table (junction table)
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS album_photos (
-            album_id INTEGER NOT NULL,
-            photo_path TEXT NOT NULL,
-            added_at INTEGER NOT NULL,
-            PRIMARY KEY (album_id, photo_path),
-            FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
-            FOREIGN KEY (photo_path) REFERENCES photos(path) ON DELETE CASCADE
-        )",
-        [],
-    )?;
+fn write_library_path(conn: &Connection, path: &std::path::Path, previous: &[String]) -> SqlResult<()> {
+    set_setting(conn, "library_path", &serde_json::json!(path.to_string_lossy()).to_string())?;
+    set_setting(conn, "previous_library_paths", &serde_json::json!(previous).to_string())
+}
 
-    // Create index on date_taken for faster sorting
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_date_taken ON photos(date_taken DESC)",
-        [],
-    )?;
+fn get_previous_library_paths(conn: &Connection) -> Vec<PathBuf> {
+    get_setting(conn, "previous_library_paths")
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
 
-    // Create index on content_hash for duplicate detection
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_content_hash ON photos(content_hash)",
-        [],
-    )?;
+/// Folders the file watcher follows in addition to the library root.
+pub fn get_watch_folders(conn: &Connection) -> Vec<PathBuf> {
+    get_setting(conn, "watch_folders")
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
 
-    // Create index on location_name for search
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_location_name ON photos(location_name)",
-        [],
-    )?;
+/// Register a watch folder. Returns false if it was already registered.
+pub fn add_watch_folder(conn: &Connection, path: &std::path::Path) -> SqlResult<bool> {
+    let mut folders = get_watch_folders(conn);
+    if folders.iter().any(|p| p == path) {
+        return Ok(false);
+    }
+    folders.push(path.to_path_buf());
+    set_setting(conn, "watch_folders", &serde_json::json!(folders).to_string())?;
+    Ok(true)
+}
 
-    // Create index on dhash for fast duplicate lookup
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_dhash ON photos(dhash_64)",
-        [],
-    )?;
+/// Unregister a watch folder. Returns false if it wasn't registered.
+pub fn remove_watch_folder(conn: &Connection, path: &std::path::Path) -> SqlResult<bool> {
+    let mut folders = get_watch_folders(conn);
+    let before = folders.len();
+    folders.retain(|p| p != path);
+    if folders.len() == before {
+        return Ok(false);
+    }
+    set_setting(conn, "watch_folders", &serde_json::json!(folders).to_string())?;
+    Ok(true)
+}
 
-    // Create index on archived_at for archive management
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_archived ON photos(archived_at)",
-        [],
-    )?;
+/// Every directory whose files Terra owns: the current library, any earlier
+/// library roots, and their archive folders. Unavailable roots are skipped.
+pub fn get_managed_roots(conn: &Connection) -> Vec<PathBuf> {
+    let mut libraries = get_previous_library_paths(conn);
+    if let Ok(current) = get_library_path(conn) {
+        libraries.insert(0, current);
+    }
+    libraries
+        .into_iter()
+        .filter(|p| p.is_dir())
+        .flat_map(|p| [p.join("Archive"), p])
+        .collect()
+}
 
-    // Create index on reviewed_at for TerraForm
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_reviewed ON photos(reviewed_at)",
-        [],
-    )?;
+/// Opening the database failed: a migration broke, or the file is from a
+/// newer build than this one.
+#[derive(Debug)]
+pub enum SchemaError {
+    Sqlite(rusqlite::Error),
+    /// Migration `version` failed and was rolled back, leaving the database
+    /// at `version - 1`.
+    Migration { version: u32, name: &'static str, source: rusqlite::Error },
+    /// The database is at `found`, but this build only knows `supported`.
+    TooNew { found: u32, supported: u32 },
+}
 
-    // Create index on file_size for Smart Collections
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_file_size ON photos(file_size)",
-        [],
-    )?;
+impl std::fmt::Display for SchemaError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SchemaError::Sqlite(e) => write!(f, "{}", e),
+            SchemaError::Migration { version, name, source } => write!(
+                f,
+                "Upgrading the database to version {} ({}) failed: {}. It was left at version {}.",
+                version,
+                name,
+                source,
+                version - 1
+            ),
+            SchemaError::TooNew { found, supported } => write!(
+                f,
+                "The database is at version {}, newer than this build of Terra supports ({})",
+                found, supported
+            ),
+        }
+    }
+}
 
-    // Composite index to support filtering by camera make/model
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_camera ON photos(camera_make, camera_model)",
-        [],
-    )?;
+impl std::error::Error for SchemaError {}
 
-    // Create tags table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS tags (
-            id INTEGER PRIMARY KEY AUTOINCREMENT,
-            name TEXT NOT NULL UNIQUE,
-            color TEXT NOT NULL,
-            created_at INTEGER NOT NULL
-        )",
-        [],
-    )?;
+impl From<rusqlite::Error> for SchemaError {
+    fn from(e: rusqlite::Error) -> Self {
+        SchemaError::Sqlite(e)
+    }
+}
 
-    // Create photo_tags junction table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS photo_tags (
-            tag_id INTEGER NOT NULL,
-            photo_path TEXT NOT NULL,
-            added_at INTEGER NOT NULL,
-            PRIMARY KEY (tag_id, photo_path),
-            FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE,
-            FOREIGN KEY (photo_path) REFERENCES photos(path) ON DELETE CASCADE
-        )",
-        [],
-    )?;
+/// One step of the schema's history. Its version is its position in
+/// MIGRATIONS, counting from 1.
+struct Migration {
+    name: &'static str,
+    apply: fn(&Connection) -> SqlResult<()>,
+}
 
-    // Create indexes for photo_tags
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_photo_tags_photo ON photo_tags(photo_path)",
-        [],
-    )?;
-    conn.execute(
-        "CREATE INDEX IF NOT EXISTS idx_photo_tags_tag ON photo_tags(tag_id)",
-        [],
-    )?;
+/// Every schema change, oldest first. Append new ones; never edit or reorder
+/// a released step. Databases from before versioning start at 0 and replay
+/// them all, so each step skips whatever an older build already did.
+const MIGRATIONS: &[Migration] = &[
+    Migration {
+        name: "photos",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS photos (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    path TEXT NOT NULL UNIQUE,
+                    name TEXT NOT NULL,
+                    date_taken INTEGER NOT NULL,
+                    width INTEGER NOT NULL,
+                    height INTEGER NOT NULL,
+                    source_type TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            // Faster sorting by date
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_date_taken ON photos(date_taken DESC)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "favorites",
+        apply: |conn| add_column(conn, "photos", "is_favorite", "INTEGER DEFAULT 0").map(drop),
+    },
+    Migration {
+        name: "content hash",
+        apply: |conn| {
+            add_column(conn, "photos", "content_hash", "TEXT")?;
+            // Duplicate detection
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_content_hash ON photos(content_hash)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "gps",
+        apply: |conn| {
+            add_column(conn, "photos", "latitude", "REAL")?;
+            add_column(conn, "photos", "longitude", "REAL")?;
+            add_column(conn, "photos", "location_name", "TEXT")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_location_name ON photos(location_name)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "albums",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS albums (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    cover_photo_path TEXT,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            // Keyed by path until "memberships by photo id"
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS album_photos (
+                    album_id INTEGER NOT NULL,
+                    photo_path TEXT NOT NULL,
+                    added_at INTEGER NOT NULL,
+                    PRIMARY KEY (album_id, photo_path),
+                    FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
+                    FOREIGN KEY (photo_path) REFERENCES photos(path) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "duplicates, screenshots and archive",
+        apply: |conn| {
+            add_column(conn, "photos", "dhash_64", "INTEGER")?;
+            add_column(conn, "photos", "is_screenshot", "INTEGER DEFAULT 0")?;
+            add_column(conn, "photos", "archived_at", "INTEGER")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_dhash ON photos(dhash_64)", [])?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_archived ON photos(archived_at)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "terraform and smart collections",
+        apply: |conn| {
+            add_column(conn, "photos", "reviewed_at", "INTEGER")?;
+            add_column(conn, "photos", "file_size", "INTEGER")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_reviewed ON photos(reviewed_at)", [])?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_file_size ON photos(file_size)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "camera and video metadata",
+        apply: |conn| {
+            for (column, definition) in [
+                ("camera_make", "TEXT"),
+                ("camera_model", "TEXT"),
+                ("lens_model", "TEXT"),
+                ("iso", "INTEGER"),
+                ("aperture", "REAL"),
+                ("shutter_us", "INTEGER"),
+                ("focal_length_mm", "REAL"),
+                ("orientation", "INTEGER"),
+                ("duration_ms", "INTEGER"),
+                ("codec", "TEXT"),
+            ] {
+                add_column(conn, "photos", column, definition)?;
+            }
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_camera ON photos(camera_make, camera_model)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "tags",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS tags (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL UNIQUE,
+                    color TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            // Keyed by path until "memberships by photo id"
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS photo_tags (
+                    tag_id INTEGER NOT NULL,
+                    photo_path TEXT NOT NULL,
+                    added_at INTEGER NOT NULL,
+                    PRIMARY KEY (tag_id, photo_path),
+                    FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE,
+                    FOREIGN KEY (photo_path) REFERENCES photos(path) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_photo_tags_tag ON photo_tags(tag_id)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "case-insensitive tags",
+        apply: |conn| {
+            // Older databases may hold "Beach" and "beach" as separate tags;
+            // fold them into the oldest before indexing.
+            merge_case_duplicate_tags(conn)?;
+            conn.execute("CREATE UNIQUE INDEX IF NOT EXISTS idx_tags_name_nocase ON tags(name COLLATE NOCASE)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "settings",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS settings (
+                    key TEXT PRIMARY KEY,
+                    value TEXT NOT NULL
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "thumbnail status",
+        apply: |conn| {
+            // NULL = pending, 'ready' = on-disk thumb exists, 'failed' = decoder
+            // rejected (e.g. unsupported HEIC) or ffmpeg couldn't read a video.
+            add_column(conn, "photos", "thumb_status", "TEXT")?;
+            // Videos used to be marked 'unsupported'; ffmpeg posters make them pending again.
+            conn.execute("UPDATE photos SET thumb_status = NULL WHERE thumb_status = 'unsupported'", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "incremental rescan",
+        apply: |conn| {
+            // file mtime alongside file_size detects unchanged files, is_missing
+            // flags rows whose file disappeared from a rescanned folder.
+            add_column(conn, "photos", "file_mtime", "INTEGER")?;
+            add_column(conn, "photos", "is_missing", "INTEGER DEFAULT 0")?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "perceptual hashes",
+        apply: |conn| {
+            // DCT hash of the image as stored plus its 90/180/270 degree
+            // rotations, so rotated copies still match.
+            for column in ["phash", "phash_r90", "phash_r180", "phash_r270"] {
+                add_column(conn, "photos", column, "INTEGER")?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        name: "trash",
+        apply: |conn| {
+            // deleted_at marks a soft-deleted row; trash_path is where the file
+            // was moved under the library's .trash folder (NULL if it was left
+            // in place). `path` keeps the original location.
+            add_column(conn, "photos", "deleted_at", "INTEGER")?;
+            add_column(conn, "photos", "trash_path", "TEXT")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_deleted ON photos(deleted_at)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "descriptions",
+        // User-written caption, seeded from XMP/EXIF on import
+        apply: |conn| add_column(conn, "photos", "description", "TEXT").map(drop),
+    },
+    Migration {
+        name: "ratings",
+        apply: |conn| {
+            // 0-5 stars, seeded from XMP/EXIF Rating on import
+            add_column(conn, "photos", "rating", "INTEGER DEFAULT 0")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_rating ON photos(rating)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "original paths",
+        // Where an uploaded file was copied or moved from (NULL for scanned files)
+        apply: |conn| add_column(conn, "photos", "original_path", "TEXT").map(drop),
+    },
+    Migration {
+        name: "file formats",
+        apply: |conn| {
+            // Container format from the extension ("jpeg", "cr2"); lets the grid
+            // mark RAW files and tell a RAW+JPEG pair apart.
+            add_column(conn, "photos", "file_format", "TEXT")?;
+            backfill_file_formats(conn)
+        },
+    },
+    Migration {
+        name: "name sort",
+        apply: |conn| {
+            // Supports the name sort in get_photos_page
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_name ON photos(name COLLATE NOCASE)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "search index",
+        apply: init_search_index,
+    },
+    Migration {
+        name: "live photos",
+        apply: |conn| {
+            // Set on the still, naming its motion video. The video's own row
+            // stays, hidden from the grid while its still is there.
+            add_column(conn, "photos", "paired_video_path", "TEXT")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_paired_video ON photos(paired_video_path)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "filename date mismatches",
+        // Filename date that disagreed with the embedded one by over a year.
+        // The embedded date is used; this is kept so the user can review it.
+        apply: |conn| add_column(conn, "photos", "filename_date_mismatch", "INTEGER").map(drop),
+    },
+    Migration {
+        name: "capture time zones",
+        apply: |conn| {
+            // UTC offset the capture time was recorded in, in minutes. Dates used
+            // to be read as UTC, so every image is queued for a re-read.
+            if add_column(conn, "photos", "tz_offset_minutes", "INTEGER")? {
+                conn.execute("UPDATE photos SET file_mtime = NULL WHERE duration_ms IS NULL", [])?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        name: "date sources",
+        apply: |conn| {
+            // Where date_taken came from (see media::DATE_SOURCE_*) and whether
+            // it is a real capture date. Undated photos used to get the import
+            // time; those rows are found and fixed here.
+            add_column(conn, "photos", "date_confident", "INTEGER")?;
+            if add_column(conn, "photos", "date_source", "TEXT")? {
+                flag_import_stamped_dates(conn)?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        name: "smart albums",
+        apply: |conn| {
+            // A saved SmartAlbumFilter, as JSON, evaluated on read
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS smart_albums (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL,
+                    filter TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "album order",
+        // Position in the user's album list; NULL until the list is reordered
+        apply: |conn| add_column(conn, "albums", "sort_order", "INTEGER").map(drop),
+    },
+    Migration {
+        name: "nested albums",
+        // Albums nest like folders ("Trips/2023/Japan"); NULL is the top level
+        apply: |conn| add_column(conn, "albums", "parent_id", "INTEGER REFERENCES albums(id)").map(drop),
+    },
+    Migration {
+        name: "memberships by photo id",
+        apply: |conn| {
+            // Membership survives the file being renamed or moved
+            migrate_to_photo_ids(conn, "album_photos", ALBUM_PHOTOS_SCHEMA)?;
+            migrate_to_photo_ids(conn, "photo_tags", PHOTO_TAGS_SCHEMA)?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_album_photos_photo ON album_photos(photo_id)", [])?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_photo_tags_photo ON photo_tags(photo_id)", [])?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_photo_tags_tag ON photo_tags(tag_id)", [])?;
+            create_search_triggers(conn)
+        },
+    },
+    Migration {
+        name: "offline volumes",
+        // The drive or folder the file lives on isn't mounted. Unlike
+        // is_missing the file is expected back; set by refresh_availability.
+        apply: |conn| add_column(conn, "photos", "is_offline", "INTEGER DEFAULT 0").map(drop),
+    },
+    Migration {
+        name: "library relocation",
+        apply: |conn| {
+            // Files of an unfinished relocate_library run; `copied` is set once
+            // the copy at `dest` has been verified
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS relocation_files (
+                    source TEXT PRIMARY KEY,
+                    dest TEXT NOT NULL,
+                    size INTEGER NOT NULL,
+                    copied INTEGER NOT NULL DEFAULT 0
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "duplicate resolutions",
+        apply: |conn| {
+            // One row per resolve_duplicates group, kept until it is undone
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS duplicate_resolutions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    kept_photo_id INTEGER NOT NULL,
+                    kept_was_favorite INTEGER NOT NULL,
+                    resolved_at INTEGER NOT NULL,
+                    FOREIGN KEY (kept_photo_id) REFERENCES photos(id) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            // What a resolution did: each discarded copy ('discarded'), and each
+            // album, tag or album cover ('album', 'tag', 'cover') the kept photo
+            // took over from one. photo_id is the discarded copy; no foreign key
+            // so emptying the trash doesn't erase the record.
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS duplicate_resolution_changes (
+                    resolution_id INTEGER NOT NULL,
+                    kind TEXT NOT NULL,
+                    photo_id INTEGER NOT NULL,
+                    target_id INTEGER,
+                    FOREIGN KEY (resolution_id) REFERENCES duplicate_resolutions(id) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_duplicate_resolution_changes ON duplicate_resolution_changes(resolution_id)",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "screenshot overrides",
+        // The user's own verdict from set_is_screenshot, 0 or 1; NULL leaves
+        // is_screenshot to detection
+        apply: |conn| add_column(conn, "photos", "screenshot_override", "INTEGER").map(drop),
+    },
+    Migration {
+        name: "hidden photos",
+        // Kept in the library but out of the grid, albums and search; only
+        // get_hidden_photos lists them
+        apply: |conn| add_column(conn, "photos", "is_hidden", "INTEGER DEFAULT 0").map(drop),
+    },
+    Migration {
+        name: "timeline archive flag",
+        // Off the main timeline but still in albums and search. Unrelated to
+        // archived_at, which marks files moved out for deletion
+        apply: |conn| add_column(conn, "photos", "is_archived", "INTEGER DEFAULT 0").map(drop),
+    },
+    Migration {
+        name: "import sessions",
+        apply: |conn| {
+            // One row per scan or upload that saved to the library
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS import_sessions (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    started_at INTEGER NOT NULL,
+                    source_type TEXT NOT NULL,
+                    source TEXT NOT NULL,
+                    imported INTEGER NOT NULL DEFAULT 0,
+                    failed INTEGER NOT NULL DEFAULT 0,
+                    duration_ms INTEGER NOT NULL DEFAULT 0
+                )",
+                [],
+            )?;
+            // The session that first added the photo; rescans don't move it
+            add_column(conn, "photos", "session_id", "INTEGER REFERENCES import_sessions(id) ON DELETE SET NULL")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_photos_session ON photos(session_id)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "photo edits",
+        apply: |conn| {
+            // A crop/rotate recipe as JSON; the original file is never changed
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS photo_edits (
+                    photo_id INTEGER PRIMARY KEY REFERENCES photos(id) ON DELETE CASCADE,
+                    recipe TEXT NOT NULL,
+                    updated_at INTEGER NOT NULL
+                )",
+                [],
+            )
+            .map(drop)
+        },
+    },
+    Migration {
+        name: "date shifts",
+        apply: |conn| {
+            // One row per apply_date_shift, kept until it is undone
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS date_shifts (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    delta_seconds INTEGER NOT NULL,
+                    shifted_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            // Each photo's date fields before the shift, and where its file
+            // went if it was refiled (new_path NULL if it stayed put)
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS date_shift_photos (
+                    shift_id INTEGER NOT NULL REFERENCES date_shifts(id) ON DELETE CASCADE,
+                    photo_id INTEGER NOT NULL REFERENCES photos(id) ON DELETE CASCADE,
+                    old_date_taken INTEGER NOT NULL,
+                    old_date_source TEXT,
+                    old_date_confident INTEGER,
+                    old_filename_date_mismatch INTEGER,
+                    old_path TEXT NOT NULL,
+                    new_path TEXT
+                )",
+                [],
+            )?;
+            conn.execute(
+                "CREATE INDEX IF NOT EXISTS idx_date_shift_photos ON date_shift_photos(shift_id)",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "operations",
+        apply: |conn| {
+            // Undo log: targets is a JSON list of photo ids, before_state the
+            // serialized operations::Operation that can reverse it
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS operations (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    kind TEXT NOT NULL,
+                    targets TEXT NOT NULL,
+                    before_state TEXT NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )
+            .map(drop)
+        },
+    },
+    Migration {
+        name: "mime types",
+        // From the file's format at import; populate_file_sizes fills older rows
+        apply: |conn| add_column(conn, "photos", "mime_type", "TEXT").map(drop),
+    },
+    Migration {
+        name: "composed unicode paths",
+        // Scans now store macOS paths as NFC (library::stored_path); bring
+        // older decomposed rows in line so a rescan doesn't add them again
+        apply: |conn| {
+            if cfg!(target_os = "macos") {
+                rewrite_stored_paths(conn, crate::library::compose_unicode)
+            } else {
+                Ok(())
+            }
+        },
+    },
+    Migration {
+        name: "windows plain paths",
+        // Paths stored with canonicalize's \\?\ prefix, which the asset
+        // protocol can't serve; library::stored_path now strips it
+        apply: |conn| {
+            if cfg!(windows) {
+                rewrite_stored_paths(conn, crate::library::windows_form)?;
+                rewrite_path_settings(conn, crate::library::windows_form)
+            } else {
+                Ok(())
+            }
+        },
+    },
+    Migration {
+        name: "case-insensitive paths",
+        // One row per file where the filesystem ignores case
+        apply: |conn| {
+            if CASE_INSENSITIVE_PATHS {
+                merge_case_variants(conn)?;
+                conn.execute("CREATE UNIQUE INDEX idx_photos_path_nocase ON photos(path COLLATE NOCASE)", [])?;
+            }
+            Ok(())
+        },
+    },
+    Migration {
+        name: "background jobs",
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS jobs (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    kind TEXT NOT NULL,
+                    params TEXT NOT NULL DEFAULT '{}',
+                    status TEXT NOT NULL,
+                    processed INTEGER NOT NULL DEFAULT 0,
+                    total INTEGER NOT NULL DEFAULT 0,
+                    error TEXT,
+                    created_at INTEGER NOT NULL,
+                    started_at INTEGER,
+                    finished_at INTEGER
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "place names",
+        // Filled by the geocode job; see places.rs
+        apply: |conn| {
+            add_column(conn, "photos", "place_city", "TEXT")?;
+            add_column(conn, "photos", "place_country", "TEXT")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_photos_place ON photos(place_country, place_city)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "location source",
+        // Set when coordinates come from somewhere other than the file,
+        // e.g. "gpx"; rescans then keep them
+        apply: |conn| {
+            add_column(conn, "photos", "location_source", "TEXT")?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "dismissed suggestions",
+        // Album suggestions the user turned down or already made, by the
+        // suggestion's stable id (see trips.rs)
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS dismissed_suggestions (
+                    id TEXT PRIMARY KEY,
+                    dismissed_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "auto tags",
+        // photo_tags.source is 'auto' for the classifier's tags (NULL for
+        // the user's), and auto_tagged records which model version has
+        // looked at each photo; see auto_tag.rs
+        apply: |conn| {
+            add_column(conn, "photo_tags", "source", "TEXT")?;
+            add_column(conn, "photo_tags", "confidence", "REAL")?;
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS auto_tagged (
+                    photo_id INTEGER PRIMARY KEY,
+                    model_version TEXT NOT NULL,
+                    tagged_at INTEGER NOT NULL,
+                    FOREIGN KEY (photo_id) REFERENCES photos(id) ON DELETE CASCADE
+                )",
+                [],
+            )?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "faces",
+        // Face regions from the detector, named by hand; see faces.rs.
+        // face_scans records which model version has looked at each photo.
+        apply: |conn| {
+            conn.execute_batch(
+                "CREATE TABLE IF NOT EXISTS people (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    name TEXT NOT NULL UNIQUE COLLATE NOCASE,
+                    created_at INTEGER NOT NULL
+                );
+                CREATE TABLE IF NOT EXISTS faces (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    photo_id INTEGER NOT NULL,
+                    x REAL NOT NULL,
+                    y REAL NOT NULL,
+                    w REAL NOT NULL,
+                    h REAL NOT NULL,
+                    confidence REAL,
+                    person_id INTEGER,
+                    -- Left for recognition: a vector from a face embedding model
+                    embedding BLOB,
+                    created_at INTEGER NOT NULL,
+                    FOREIGN KEY (photo_id) REFERENCES photos(id) ON DELETE CASCADE,
+                    FOREIGN KEY (person_id) REFERENCES people(id) ON DELETE SET NULL
+                );
+                CREATE INDEX IF NOT EXISTS idx_faces_photo ON faces(photo_id);
+                CREATE INDEX IF NOT EXISTS idx_faces_person ON faces(person_id);
+                CREATE TABLE IF NOT EXISTS face_scans (
+                    photo_id INTEGER PRIMARY KEY,
+                    model_version TEXT NOT NULL,
+                    scanned_at INTEGER NOT NULL,
+                    FOREIGN KEY (photo_id) REFERENCES photos(id) ON DELETE CASCADE
+                );",
+            )
+        },
+    },
+    Migration {
+        name: "face clusters",
+        // Suggested person for unnamed faces, from the last cluster_faces run
+        apply: |conn| {
+            add_column(conn, "faces", "cluster_id", "INTEGER")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_faces_cluster ON faces(cluster_id)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "quality scores",
+        // Sharpness and clipping from the quality job; see quality.rs.
+        // quality_checked_at is set even when the photo couldn't be read.
+        apply: |conn| {
+            add_column(conn, "photos", "quality_score", "REAL")?;
+            add_column(conn, "photos", "sharpness", "REAL")?;
+            add_column(conn, "photos", "clipped_fraction", "REAL")?;
+            add_column(conn, "photos", "quality_checked_at", "INTEGER")?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "placeholders",
+        // BlurHash and dominant color for the grid to paint before the
+        // thumbnail loads; see placeholders.rs
+        apply: |conn| {
+            add_column(conn, "photos", "blurhash", "TEXT")?;
+            add_column(conn, "photos", "dominant_color", "TEXT")?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "color labels",
+        // Lightroom-style labels; NULL is no label. See ColorLabel.
+        apply: |conn| {
+            add_column(
+                conn,
+                "photos",
+                "color_label",
+                "TEXT CHECK (color_label IN ('red', 'yellow', 'green', 'blue', 'purple'))",
+            )?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_color_label ON photos(color_label)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "cull flags",
+        // Pick or reject from culling; NULL is unflagged. See PhotoFlag.
+        apply: |conn| {
+            add_column(conn, "photos", "flag", "TEXT CHECK (flag IN ('pick', 'reject'))")?;
+            conn.execute("CREATE INDEX IF NOT EXISTS idx_flag ON photos(flag)", [])?;
+            Ok(())
+        },
+    },
+    Migration {
+        name: "stacks",
+        // Files of one shot shown as one grid item; see stacks.rs. The
+        // triggers keep every stack's primary a member: when it leaves,
+        // is deleted or is trashed another member takes over, and a stack
+        // left with one member is dissolved.
+        apply: |conn| {
+            conn.execute(
+                "CREATE TABLE IF NOT EXISTS stacks (
+                    id INTEGER PRIMARY KEY AUTOINCREMENT,
+                    primary_photo_id INTEGER NOT NULL,
+                    created_at INTEGER NOT NULL
+                )",
+                [],
+            )?;
+            add_column(conn, "photos", "stack_id", "INTEGER REFERENCES stacks(id) ON DELETE SET NULL")?;
+            // Set by unstack_photos so scans don't stack the photo again
+            add_column(conn, "photos", "unstacked", "INTEGER NOT NULL DEFAULT 0")?;
+            conn.execute_batch(&format!(
+                "CREATE INDEX IF NOT EXISTS idx_stack ON photos(stack_id);
+                 CREATE TRIGGER IF NOT EXISTS stacks_member_delete
+                 AFTER DELETE ON photos WHEN old.stack_id IS NOT NULL BEGIN
+                     {leave}
+                 END;
+                 CREATE TRIGGER IF NOT EXISTS stacks_member_move
+                 AFTER UPDATE OF stack_id ON photos
+                 WHEN old.stack_id IS NOT NULL AND new.stack_id IS NOT old.stack_id BEGIN
+                     {leave}
+                 END;
+                 CREATE TRIGGER IF NOT EXISTS stacks_member_trash
+                 AFTER UPDATE OF deleted_at ON photos WHEN new.stack_id IS NOT NULL BEGIN
+                     UPDATE stacks SET primary_photo_id =
+                         (SELECT id FROM photos WHERE stack_id = stacks.id AND deleted_at IS NULL ORDER BY id LIMIT 1)
+                     WHERE id = new.stack_id
+                       AND EXISTS (SELECT 1 FROM photos WHERE stack_id = stacks.id AND deleted_at IS NULL)
+                       AND (SELECT deleted_at FROM photos WHERE id = stacks.primary_photo_id) IS NOT NULL;
+                 END;",
+                leave = "UPDATE stacks SET primary_photo_id = COALESCE(
+                             (SELECT id FROM photos WHERE stack_id = old.stack_id ORDER BY deleted_at IS NOT NULL, id LIMIT 1),
+                             primary_photo_id)
+                         WHERE id = old.stack_id AND primary_photo_id = old.id;
+                         UPDATE photos SET stack_id = NULL
+                         WHERE stack_id = old.stack_id AND (SELECT COUNT(*) FROM photos WHERE stack_id = old.stack_id) < 2;
+                         DELETE FROM stacks WHERE id = old.stack_id
+                           AND NOT EXISTS (SELECT 1 FROM photos WHERE stack_id = old.stack_id);"
+            ))
+        },
+    },
+    Migration {
+        name: "retry unread heic",
+        // HEIC used to be unreadable: rows stuck at 0x0 get re-read on the
+        // next rescan and their thumbnails retried
+        apply: |conn| {
+            conn.execute(
+                "UPDATE photos SET file_mtime = NULL,
+                     thumb_status = CASE WHEN thumb_status = 'failed' THEN NULL ELSE thumb_status END
+                 WHERE width = 0 AND (lower(path) LIKE '%.heic' OR lower(path) LIKE '%.heif')",
+                [],
+            )
+            .map(drop)
+        },
+    },
+    Migration {
+        name: "remove orphaned references",
+        // Ghost memberships left while foreign keys were off; none can be
+        // made since, so one pass is enough
+        apply: remove_orphaned_references,
+    },
+    Migration {
+        name: "place regions",
+        // The state or province between city and country; photos already
+        // placed get it from the next geocode job
+        apply: |conn| add_column(conn, "photos", "place_region", "TEXT").map(drop),
+    },
+    Migration {
+        name: "user set fields",
+        // Bits of `USER_SET_*`; older edits can't be told from embedded
+        // values, so every row starts with none
+        apply: |conn| add_column(conn, "photos", "user_set", "INTEGER NOT NULL DEFAULT 0").map(drop),
+    },
+    Migration {
+        name: "enrichment marker",
+        // Set even when exiftool found nothing, so such photos aren't
+        // enriched again on every run. Rows with exiftool-only fields were
+        // enriched already.
+        apply: |conn| {
+            if add_column(conn, "photos", "enriched_at", "INTEGER")? {
+                conn.execute(
+                    "UPDATE photos SET enriched_at = ?1 WHERE iso IS NOT NULL OR codec IS NOT NULL",
+                    params![chrono::Utc::now().timestamp()],
+                )?;
+            }
+            Ok(())
+        },
+    },
+];
+
+/// Bits of photos.user_set: fields the user has set or cleared by hand,
+/// which a rescan then leaves alone even when they are empty.
+/// `INSERT_PHOTO_SQL` spells the values out.
+const USER_SET_DESCRIPTION: i64 = 1;
+const USER_SET_RATING: i64 = 2;
+const USER_SET_COLOR_LABEL: i64 = 4;
+
+/// Whether this platform's filesystems ignore case (macOS and Windows by
+/// default), making `IMG_001.JPG` and `img_001.jpg` one file. Linux
+/// compares paths exactly.
+const CASE_INSENSITIVE_PATHS: bool = cfg!(any(target_os = "macos", windows));
+
+/// How a path read from disk is matched against stored ones. NOCASE folds
+/// ASCII letters only, which covers camera and export names.
+const PATH_COLLATION: &str = if CASE_INSENSITIVE_PATHS { "NOCASE" } else { "BINARY" };
+
+/// Orders rows that are the same file so the one with the most metadata
+/// comes first, then a live one, then the oldest.
+const RICHEST_ROW_FIRST: &str = "(latitude IS NOT NULL) + (camera_make IS NOT NULL) + (description IS NOT NULL)
+         + (content_hash IS NOT NULL) + (dhash_64 IS NOT NULL) + (file_size IS NOT NULL)
+         + COALESCE(is_favorite, 0) + (COALESCE(rating, 0) > 0) + (color_label IS NOT NULL) + (flag IS NOT NULL)
+         + (SELECT COUNT(*) FROM album_photos WHERE photo_id = photos.id)
+         + (SELECT COUNT(*) FROM photo_tags WHERE photo_id = photos.id) DESC,
+         deleted_at IS NOT NULL, id";
+
+/// Fold `others`, rows for the same file as `kept`, into it: `kept` gains
+/// their album and tag memberships, edits, favorite flag, rating, color
+/// label, cull flag and caption, and takes over their album covers and Live
+/// Photo pairings. The others are deleted. Returns how many rows were removed.
+fn merge_photo_rows(conn: &Connection, (kept_id, kept_path): &(i64, String), others: &[(i64, String)]) -> SqlResult<usize> {
+    let mut removed = 0;
+    for (id, other_path) in others {
+        for table in ["album_photos", "photo_tags", "photo_edits"] {
+            conn.execute(
+                &format!("UPDATE OR IGNORE {} SET photo_id = ?1 WHERE photo_id = ?2", table),
+                params![kept_id, id],
+            )?;
+        }
+        conn.execute(
+            "UPDATE photos SET
+                 is_favorite = MAX(COALESCE(is_favorite, 0), (SELECT COALESCE(is_favorite, 0) FROM photos WHERE id = ?2)),
+                 rating = MAX(COALESCE(rating, 0), (SELECT COALESCE(rating, 0) FROM photos WHERE id = ?2)),
+                 color_label = COALESCE(color_label, (SELECT color_label FROM photos WHERE id = ?2)),
+                 flag = COALESCE(flag, (SELECT flag FROM photos WHERE id = ?2)),
+                 description = COALESCE(description, (SELECT description FROM photos WHERE id = ?2)),
+                 user_set = user_set | (SELECT user_set FROM photos WHERE id = ?2)
+             WHERE id = ?1",
+            params![kept_id, id],
+        )?;
+        conn.execute("UPDATE albums SET cover_photo_path = ?1 WHERE cover_photo_path = ?2", params![kept_path, other_path])?;
+        conn.execute("UPDATE photos SET paired_video_path = ?1 WHERE paired_video_path = ?2", params![kept_path, other_path])?;
+        removed += conn.execute("DELETE FROM photos WHERE id = ?1", params![id])?;
+    }
+    Ok(removed)
+}
 
-    // Create settings table
-    conn.execute(
-        "CREATE TABLE IF NOT EXISTS settings (
-            key TEXT PRIMARY KEY,
-            value TEXT NOT NULL
-        )",
-        [],
+/// Collapse rows whose paths differ only in case into the one with the most
+/// metadata (see `merge_photo_rows`). Returns how many rows were removed.
+fn merge_case_variants(conn: &Connection) -> SqlResult<usize> {
+    let groups: Vec<String> = conn
+        .prepare("SELECT path FROM photos GROUP BY path COLLATE NOCASE HAVING COUNT(*) > 1")?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    let mut removed = 0;
+    for path in groups {
+        let rows: Vec<(i64, String)> = conn
+            .prepare(&format!("SELECT id, path FROM photos WHERE path = ?1 COLLATE NOCASE ORDER BY {}", RICHEST_ROW_FIRST))?
+            .query_map(params![path], |row| Ok((row.get(0)?, row.get(1)?)))?
+            .collect::<SqlResult<_>>()?;
+        if let Some((kept, others)) = rows.split_first() {
+            removed += merge_photo_rows(conn, kept, others)?;
+        }
+    }
+    Ok(removed)
+}
+
+/// The spelling a row stores for `path` when compared under `collation`
+fn stored_spelling(conn: &Connection, path: &str, collation: &str) -> SqlResult<Option<String>> {
+    conn.prepare_cached(&format!("SELECT path FROM photos WHERE path = ?1 COLLATE {}", collation))?
+        .query_row(params![path], |row| row.get(0))
+        .optional()
+}
+
+/// Rewrite every photo path through `rewrite`, wherever it is referenced.
+/// A path whose new form already has a row is the same file imported
+/// twice: the two rows are merged as `merge_case_variants` does, and the
+/// one kept takes the new form.
+fn rewrite_stored_paths(conn: &Connection, rewrite: fn(&str) -> String) -> SqlResult<()> {
+    let paths: Vec<String> = conn
+        .prepare("SELECT path FROM photos")?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    for path in paths {
+        let new_path = rewrite(&path);
+        if new_path == path {
+            continue;
+        }
+        if let Some(existing) = stored_spelling(conn, &new_path, PATH_COLLATION)?.filter(|existing| *existing != path) {
+            let rows: Vec<(i64, String)> = conn
+                .prepare(&format!("SELECT id, path FROM photos WHERE path IN (?1, ?2) ORDER BY {}", RICHEST_ROW_FIRST))?
+                .query_map(params![path, existing], |row| Ok((row.get(0)?, row.get(1)?)))?
+                .collect::<SqlResult<_>>()?;
+            if let Some((kept, others)) = rows.split_first() {
+                merge_photo_rows(conn, kept, others)?;
+                repoint_photo_path(conn, &kept.1, &new_path)?;
+            }
+            continue;
+        }
+        repoint_photo_path(conn, &path, &new_path)?;
+    }
+    Ok(())
+}
+
+/// Rewrite the folder paths kept in settings: the library root, earlier
+/// roots and watch folders.
+fn rewrite_path_settings(conn: &Connection, rewrite: fn(&str) -> String) -> SqlResult<()> {
+    if let Some(raw) = get_setting(conn, "library_path") {
+        set_setting(conn, "library_path", &serde_json::json!(rewrite(&setting_text(&raw))).to_string())?;
+    }
+    for key in ["previous_library_paths", "watch_folders"] {
+        let Some(paths) = get_setting(conn, key).and_then(|raw| serde_json::from_str::<Vec<String>>(&raw).ok()) else {
+            continue;
+        };
+        // Both spellings of one folder become a single entry
+        let mut rewritten: Vec<String> = Vec::with_capacity(paths.len());
+        for path in paths.iter().map(|p| rewrite(p)) {
+            if !rewritten.contains(&path) {
+                rewritten.push(path);
+            }
+        }
+        set_setting(conn, key, &serde_json::json!(rewritten).to_string())?;
+    }
+    Ok(())
+}
+
+/// Add a column unless an older build already added it. True if it was added.
+fn add_column(conn: &Connection, table: &str, column: &str, definition: &str) -> SqlResult<bool> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2)",
+        params![table, column],
+        |row| row.get(0),
     )?;
+    if !exists {
+        conn.execute(&format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition), [])?;
+    }
+    Ok(!exists)
+}
+
+/// Version the database is at: the number of MIGRATIONS applied to it.
+pub fn schema_version(conn: &Connection) -> SqlResult<u32> {
+    conn.pragma_query_value(None, "user_version", |row| row.get(0))
+}
 
+/// Apply the migrations the database hasn't had yet, each in its own
+/// transaction together with the version bump, so a failure leaves the
+/// database at the last step that succeeded.
+fn apply_migrations(conn: &Connection, migrations: &[Migration]) -> Result<(), SchemaError> {
+    let current = schema_version(conn)?;
+    let supported = migrations.len() as u32;
+    if current > supported {
+        return Err(SchemaError::TooNew { found: current, supported });
+    }
+
+    for (index, migration) in migrations.iter().enumerate().skip(current as usize) {
+        let version = index as u32 + 1;
+        let failed = |source| SchemaError::Migration { version, name: migration.name, source };
+        let tx = conn.unchecked_transaction().map_err(failed)?;
+        (migration.apply)(&tx).map_err(failed)?;
+        tx.pragma_update(None, "user_version", version).map_err(failed)?;
+        tx.commit().map_err(failed)?;
+    }
     Ok(())
 }
 
-/// Initialize the database and create tables if they don't exist
-pub fn init_database() -> SqlResult<Connection> {
+/// Bring the schema up to date on an existing connection.
+/// Used by both init_database() and tests (with in-memory DBs).
+pub fn init_schema(conn: &Connection) -> Result<(), SchemaError> {
+    apply_migrations(conn, MIGRATIONS)
+}
+
+const ALBUM_PHOTOS_SCHEMA: &str = "CREATE TABLE album_photos (
+        album_id INTEGER NOT NULL,
+        photo_id INTEGER NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (album_id, photo_id),
+        FOREIGN KEY (album_id) REFERENCES albums(id) ON DELETE CASCADE,
+        FOREIGN KEY (photo_id) REFERENCES photos(id) ON DELETE CASCADE
+    )";
+
+const PHOTO_TAGS_SCHEMA: &str = "CREATE TABLE photo_tags (
+        tag_id INTEGER NOT NULL,
+        photo_id INTEGER NOT NULL,
+        added_at INTEGER NOT NULL,
+        PRIMARY KEY (tag_id, photo_id),
+        FOREIGN KEY (tag_id) REFERENCES tags(id) ON DELETE CASCADE,
+        FOREIGN KEY (photo_id) REFERENCES photos(id) ON DELETE CASCADE
+    )";
+
+/// Search index triggers that read photo_tags. They are dropped before
+/// photo_tags is rebuilt and recreated by create_search_triggers.
+const PHOTO_TAGS_TRIGGERS: [&str; 7] = [
+    "photos_fts_insert",
+    "photos_fts_update",
+    "photo_tags_fts_insert",
+    "photo_tags_fts_update",
+    "photo_tags_fts_delete",
+    "tags_fts_rename",
+    "tags_fts_delete",
+];
+
+/// Rebuild a junction table that still keys photos by `photo_path` so it
+/// keys them by `photos.id`, matching rows on path. Links to paths with no
+/// photo row, or to albums and tags that are gone, are dropped. Does nothing once the table has been migrated.
+fn migrate_to_photo_ids(conn: &Connection, table: &str, schema: &str) -> SqlResult<()> {
+    let by_path: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM pragma_table_info(?1) WHERE name = 'photo_path')",
+        params![table],
+        |row| row.get(0),
+    )?;
+    if !by_path {
+        return Ok(());
+    }
+
+    let (key, owner) = if table == "album_photos" { ("album_id", "albums") } else { ("tag_id", "tags") };
+    if table == "photo_tags" {
+        for trigger in PHOTO_TAGS_TRIGGERS {
+            conn.execute(&format!("DROP TRIGGER IF EXISTS {}", trigger), [])?;
+        }
+    }
+    conn.execute_batch(&format!(
+        "ALTER TABLE {table} RENAME TO {table}_by_path;
+         {schema};
+         INSERT OR IGNORE INTO {table} ({key}, photo_id, added_at)
+             SELECT prev.{key}, p.id, prev.added_at FROM {table}_by_path prev
+             JOIN photos p ON p.path = prev.photo_path
+             JOIN {owner} o ON o.id = prev.{key};
+         DROP TABLE {table}_by_path;",
+    ))
+}
+
+/// How long a statement waits on a locked database before failing with SQLITE_BUSY.
+const BUSY_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The app's single database connection, opened once at startup and shared
+/// by every command through Tauri's managed state.
+pub struct DbState(pub Mutex<Connection>);
+
+impl DbState {
+    pub fn new(conn: Connection) -> Self {
+        DbState(Mutex::new(conn))
+    }
+}
+
+/// How many times a write is retried after it fails with SQLITE_BUSY
+const BUSY_RETRIES: u32 = 3;
+
+/// Rows a long import writes per transaction. Between transactions the
+/// connection is free for other commands, so a favorite click doesn't wait
+/// behind a whole scan.
+pub const WRITE_CHUNK_ROWS: usize = 500;
+
+/// True for SQLITE_BUSY and SQLITE_LOCKED: another writer held the lock
+/// past the busy timeout.
+fn is_busy(err: &rusqlite::Error) -> bool {
+    matches!(
+        err.sqlite_error_code(),
+        Some(rusqlite::ErrorCode::DatabaseBusy | rusqlite::ErrorCode::DatabaseLocked)
+    )
+}
+
+/// Run a write, and again after 50, 100 and 200ms while it fails because
+/// the database is locked. `op` must be safe to repeat: a single statement
+/// or a whole transaction.
+pub fn retry_busy<T>(mut op: impl FnMut() -> SqlResult<T>) -> SqlResult<T> {
+    let mut delay = Duration::from_millis(50);
+    for _ in 0..BUSY_RETRIES {
+        match op() {
+            Err(e) if is_busy(&e) => {
+                log::warn!("Database busy, retrying in {:?}", delay);
+                std::thread::sleep(delay);
+                delay *= 2;
+            }
+            result => return result,
+        }
+    }
+    op()
+}
+
+/// Open the database file, apply connection pragmas, and run schema setup.
+/// Called once from `run()`; commands reach the connection through `DbState`.
+pub fn init_database() -> Result<Connection, SchemaError> {
     let db_path = get_db_path();
     let conn = Connection::open(db_path)?;
+    configure_connection(&conn)?;
     init_schema(&conn)?;
     Ok(conn)
 }
 
+/// WAL lets readers proceed while a scan is writing, and the busy timeout
+/// covers the remaining short write-write overlaps instead of erroring out.
+/// Foreign keys are switched on (SQLite leaves them off by default), so
+/// deleting a photo, album or tag cascades to its memberships.
+fn configure_connection(conn: &Connection) -> SqlResult<()> {
+    conn.busy_timeout(BUSY_TIMEOUT)?;
+    conn.pragma_update(None, "foreign_keys", true)?;
+    conn.pragma_update_and_check(None, "journal_mode", "WAL", |row| row.get::<_, String>(0))?;
+    conn.pragma_update(None, "synchronous", "NORMAL")?;
+    Ok(())
+}
+
+/// Drop rows left pointing at deleted albums, tags and photos while foreign
+/// keys were off, and album covers naming photos that are gone. Run once as a
+/// migration; with foreign keys on nothing new is left behind, so after that
+/// only `repair_library` calls it.
+pub fn remove_orphaned_references(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "UPDATE albums SET parent_id = NULL
+             WHERE parent_id IS NOT NULL AND parent_id NOT IN (SELECT id FROM albums);
+         DELETE FROM album_photos
+             WHERE album_id NOT IN (SELECT id FROM albums) OR photo_id NOT IN (SELECT id FROM photos);
+         DELETE FROM photo_tags
+             WHERE tag_id NOT IN (SELECT id FROM tags) OR photo_id NOT IN (SELECT id FROM photos);
+         DELETE FROM photo_edits WHERE photo_id NOT IN (SELECT id FROM photos);
+         DELETE FROM date_shift_photos
+             WHERE shift_id NOT IN (SELECT id FROM date_shifts) OR photo_id NOT IN (SELECT id FROM photos);
+         UPDATE albums SET cover_photo_path = NULL
+             WHERE cover_photo_path IS NOT NULL AND cover_photo_path NOT IN (SELECT path FROM photos);",
+    )
+}
+
 // ============================================================================
 // Settings Functions
 // ============================================================================
@@ -224,7 +1337,8 @@ pub fn get_setting(conn: &Connection, key: &str) -> Option<String> {
     ).ok()
 }
 
-/// Set a setting value
+/// Set a setting value. Callers store JSON text (`true`, `30`, `"/path"`,
+/// `["a","b"]`) so the frontend gets typed values back.
 pub fn set_setting(conn: &Connection, key: &str, value: &str) -> SqlResult<()> {
     conn.execute(
         "INSERT OR REPLACE INTO settings (key, value) VALUES (?1, ?2)",
@@ -233,25 +1347,205 @@ pub fn set_setting(conn: &Connection, key: &str, value: &str) -> SqlResult<()> {
     Ok(())
 }
 
+/// Every stored setting as (key, raw value), ordered by key
+pub fn get_all_settings(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare("SELECT key, value FROM settings ORDER BY key")?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Read a setting and parse it, falling back to `default` when the key is
+/// missing or doesn't parse. JSON string values are unquoted first.
+pub fn get_setting_or<T: std::str::FromStr>(conn: &Connection, key: &str, default: T) -> T {
+    get_setting(conn, key)
+        .and_then(|raw| setting_text(&raw).parse().ok())
+        .unwrap_or(default)
+}
+
+/// Decode a stored value as JSON. Values written before settings were JSON
+/// (e.g. a bare library path) come back as plain strings.
+pub fn decode_setting(raw: &str) -> serde_json::Value {
+    serde_json::from_str(raw).unwrap_or_else(|_| serde_json::Value::String(raw.to_string()))
+}
+
+/// The text of a stored value: the contents of a JSON string, otherwise the
+/// raw value unchanged.
+fn setting_text(raw: &str) -> String {
+    match serde_json::from_str::<String>(raw) {
+        Ok(text) => text,
+        Err(_) => raw.to_string(),
+    }
+}
+
+/// Upsert used by insert_photo and insert_photos_batch.
+/// On conflict only file-derived columns are refreshed; user state on an existing
+/// row (favorite, review, archive, tags) is preserved so rescans don't wipe it.
+/// An embedded description, rating or color label only fills a row that has none.
+/// A date or screenshot verdict the user set by hand is kept, and so are
+/// coordinates from elsewhere (a GPX track). So is a date from an export's
+/// JSON, which a rescan without that JSON would replace with a guess or a
+/// re-encoded file's own time. A trashed row whose file turns
+/// up at its path again is back in the library.
+const INSERT_PHOTO_SQL: &str =
+    "INSERT INTO photos (path, name, date_taken, width, height, source_type, created_at, is_favorite, content_hash, latitude, longitude, location_name, camera_make, camera_model, description, rating, duration_ms, file_format, filename_date_mismatch, tz_offset_minutes, date_source, date_confident, is_screenshot, file_size, mime_type, color_label)
+     VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12, ?13, ?14, ?15, ?16, ?17, ?18, ?19, ?20, ?21, ?22, ?23, ?24, ?25, ?26)
+     ON CONFLICT(path) DO UPDATE SET
+         name = excluded.name,
+         date_taken = CASE WHEN photos.date_source = 'manual' OR (photos.date_source = 'sidecar' AND excluded.date_source IS NOT 'sidecar') THEN photos.date_taken ELSE excluded.date_taken END,
+         width = excluded.width,
+         height = excluded.height,
+         content_hash = excluded.content_hash,
+         latitude = CASE WHEN photos.location_source IS NULL THEN excluded.latitude ELSE photos.latitude END,
+         longitude = CASE WHEN photos.location_source IS NULL THEN excluded.longitude ELSE photos.longitude END,
+         location_name = CASE WHEN photos.location_source IS NULL THEN excluded.location_name ELSE photos.location_name END,
+         place_city = CASE WHEN photos.location_source IS NOT NULL OR (photos.latitude IS excluded.latitude AND photos.longitude IS excluded.longitude) THEN photos.place_city END,
+         place_region = CASE WHEN photos.location_source IS NOT NULL OR (photos.latitude IS excluded.latitude AND photos.longitude IS excluded.longitude) THEN photos.place_region END,
+         place_country = CASE WHEN photos.location_source IS NOT NULL OR (photos.latitude IS excluded.latitude AND photos.longitude IS excluded.longitude) THEN photos.place_country END,
+         camera_make = excluded.camera_make,
+         camera_model = excluded.camera_model,
+         description = CASE WHEN photos.user_set & 1 THEN photos.description ELSE COALESCE(photos.description, excluded.description) END,
+         rating = CASE WHEN photos.user_set & 2 OR COALESCE(photos.rating, 0) != 0 THEN photos.rating ELSE excluded.rating END,
+         color_label = CASE WHEN photos.user_set & 4 THEN photos.color_label ELSE COALESCE(photos.color_label, excluded.color_label) END,
+         duration_ms = COALESCE(excluded.duration_ms, photos.duration_ms),
+         file_format = COALESCE(excluded.file_format, photos.file_format),
+         filename_date_mismatch = CASE WHEN photos.date_source = 'manual' THEN NULL
+             WHEN photos.date_source = 'sidecar' AND excluded.date_source IS NOT 'sidecar' THEN photos.filename_date_mismatch ELSE excluded.filename_date_mismatch END,
+         tz_offset_minutes = CASE WHEN photos.date_source = 'manual' OR (photos.date_source = 'sidecar' AND excluded.date_source IS NOT 'sidecar') THEN photos.tz_offset_minutes ELSE excluded.tz_offset_minutes END,
+         date_confident = CASE WHEN photos.date_source = 'manual' OR (photos.date_source = 'sidecar' AND excluded.date_source IS NOT 'sidecar') THEN photos.date_confident ELSE excluded.date_confident END,
+         date_source = CASE WHEN photos.date_source = 'manual' OR (photos.date_source = 'sidecar' AND excluded.date_source IS NOT 'sidecar') THEN photos.date_source ELSE excluded.date_source END,
+         is_screenshot = COALESCE(photos.screenshot_override, excluded.is_screenshot),
+         file_size = COALESCE(excluded.file_size, photos.file_size),
+         mime_type = COALESCE(excluded.mime_type, photos.mime_type),
+         quality_score = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.quality_score END,
+         sharpness = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.sharpness END,
+         clipped_fraction = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.clipped_fraction END,
+         quality_checked_at = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.quality_checked_at END,
+         enriched_at = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.enriched_at END,
+         blurhash = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.blurhash END,
+         dominant_color = CASE WHEN photos.content_hash IS excluded.content_hash THEN photos.dominant_color END,
+         is_missing = 0,
+         is_offline = 0,
+         deleted_at = NULL,
+         trash_path = NULL";
+
+/// Insert `photo` under `path`: its own, or the spelling a row already has
+/// for it where paths ignore case, so the row is updated instead.
+fn execute_insert_photo(
+    stmt: &mut rusqlite::Statement,
+    photo: &PhotoMetadata,
+    path: &str,
+    source_type: &str,
+    now: i64,
+) -> SqlResult<()> {
+    stmt.execute(params![
+        path,
+        photo.name,
+        photo.date_taken,
+        photo.width,
+        photo.height,
+        source_type,
+        now,
+        if photo.is_favorite { 1 } else { 0 },
+        photo.content_hash,
+        photo.latitude,
+        photo.longitude,
+        photo.location_name,
+        photo.camera_make,
+        photo.camera_model,
+        photo.description,
+        photo.rating,
+        photo.duration_ms,
+        photo.file_format,
+        photo.filename_date_mismatch,
+        photo.tz_offset_minutes,
+        photo.date_source,
+        photo.date_confident,
+        media::is_screenshot(photo),
+        photo.file_size,
+        photo.mime_type,
+        photo.color_label.and_then(ColorLabel::stored)
+    ])?;
+    Ok(())
+}
+
+/// Insert a photo, or refresh the file-derived columns of an existing row.
 pub fn insert_photo(conn: &Connection, photo: &PhotoMetadata, source_type: &str) -> SqlResult<()> {
-    conn.execute(
-        "INSERT OR REPLACE INTO photos (path, name, date_taken, width, height, source_type, created_at, is_favorite, content_hash, latitude, longitude, location_name)
-         VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9, ?10, ?11, ?12)",
-        params![
-            photo.path,
-            photo.name,
-            photo.date_taken,
-            photo.width,
-            photo.height,
-            source_type,
-            chrono::Utc::now().timestamp(),
-            if photo.is_favorite { 1 } else { 0 },
-            photo.content_hash,
-            photo.latitude,
-            photo.longitude,
-            photo.location_name
-        ],
+    let path = insert_path(conn, photo)?;
+    let mut stmt = conn.prepare_cached(INSERT_PHOTO_SQL)?;
+    execute_insert_photo(&mut stmt, photo, &path, source_type, chrono::Utc::now().timestamp())
+}
+
+/// The path to insert `photo` under; see execute_insert_photo.
+fn insert_path(conn: &Connection, photo: &PhotoMetadata) -> SqlResult<String> {
+    if !CASE_INSENSITIVE_PATHS {
+        return Ok(photo.path.clone());
+    }
+    Ok(stored_spelling(conn, &photo.path, PATH_COLLATION)?.unwrap_or_else(|| photo.path.clone()))
+}
+
+/// Insert many photos in a single transaction with one cached statement.
+/// A failing row doesn't abort the batch: its (path, error) is collected and returned.
+/// The outer error is reserved for failing to begin or commit the transaction.
+/// The write lock is taken at the start, so a locked database is retried
+/// there rather than failing rows halfway through.
+pub fn insert_photos_batch(conn: &Connection, photos: &[PhotoMetadata], source_type: &str) -> SqlResult<Vec<(String, String)>> {
+    let tx = retry_busy(|| rusqlite::Transaction::new_unchecked(conn, rusqlite::TransactionBehavior::Immediate))?;
+    let now = chrono::Utc::now().timestamp();
+    let mut failures = Vec::new();
+    {
+        let mut stmt = tx.prepare_cached(INSERT_PHOTO_SQL)?;
+        for photo in photos {
+            let inserted = insert_path(&tx, photo).and_then(|path| execute_insert_photo(&mut stmt, photo, &path, source_type, now));
+            if let Err(e) = inserted {
+                failures.push((photo.path.clone(), e.to_string()));
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(failures)
+}
+
+/// How long before its row was created an import-time stamp can fall:
+/// a scan reads every file before inserting the batch.
+const IMPORT_STAMP_WINDOW_SECS: i64 = 60 * 60;
+
+/// Rows from before date_source existed whose date_taken is just before
+/// their created_at may have been stamped with the import time. Those whose
+/// file still has a modified time had a real date (the stamp was only used
+/// without one) and are re-read on the next rescan to record its source;
+/// the rest are marked unknown.
+fn flag_import_stamped_dates(conn: &Connection) -> SqlResult<()> {
+    let suspects: Vec<String> = conn
+        .prepare("SELECT path FROM photos WHERE date_taken BETWEEN created_at - ?1 AND created_at")?
+        .query_map(params![IMPORT_STAMP_WINDOW_SECS], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+
+    let mut reread = conn.prepare_cached("UPDATE photos SET file_mtime = NULL WHERE path = ?1")?;
+    let mut undated = conn.prepare_cached(
+        "UPDATE photos SET date_taken = ?1, date_source = ?2, date_confident = 0 WHERE path = ?3",
     )?;
+    for path in &suspects {
+        if media::get_file_modified_time(std::path::Path::new(path)).is_some() {
+            reread.execute(params![path])?;
+        } else {
+            undated.execute(params![media::UNKNOWN_DATE, media::DATE_SOURCE_UNKNOWN, path])?;
+        }
+    }
+    Ok(())
+}
+
+/// Set file_format on rows imported before the column existed.
+fn backfill_file_formats(conn: &Connection) -> SqlResult<()> {
+    let paths: Vec<String> = conn
+        .prepare("SELECT path FROM photos WHERE file_format IS NULL")?
+        .query_map([], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    let mut stmt = conn.prepare_cached("UPDATE photos SET file_format = ?1 WHERE path = ?2")?;
+    for path in &paths {
+        if let Some(format) = media::file_format(std::path::Path::new(path)) {
+            stmt.execute(params![format, path])?;
+        }
+    }
     Ok(())
 }
 
@@ -261,10 +1555,63 @@ const PHOTO_COLUMNS: &str =
     "path, name, date_taken, width, height, is_favorite, content_hash, \
      latitude, longitude, location_name, \
      camera_make, camera_model, lens_model, iso, aperture, shutter_us, \
-     focal_length_mm, orientation, duration_ms, codec, thumb_status";
+     focal_length_mm, orientation, duration_ms, codec, thumb_status, is_missing, rating, \
+     file_format, paired_video_path, tz_offset_minutes, date_source, date_confident, id, is_offline, \
+     blurhash, dominant_color, color_label, flag, stack_id, \
+     (SELECT recipe FROM photo_edits WHERE photo_edits.photo_id = photos.id)";
+
+/// Index of the first column a query selects after PHOTO_COLUMNS.
+const FIRST_EXTRA_COLUMN: usize = 36;
+
+/// Excludes the video half of a Live Photo while its still is live. Stacks
+/// are narrowed to one photo by one_per_stack.
+const SHOWN_IN_GRID: &str = "NOT EXISTS (SELECT 1 FROM photos still \
+     WHERE still.paired_video_path = photos.path AND still.deleted_at IS NULL)";
+
+/// `visible`, a condition on photos, narrowed to one photo per stack: the
+/// primary when it matches, else the earliest member that does, so a hidden
+/// or rejected primary doesn't take its stack with it and a search finds
+/// other members. `visible` appears twice, and with it any `?` it binds.
+fn one_per_stack(visible: &str) -> String {
+    format!(
+        "{0} AND (photos.stack_id IS NULL OR photos.id IN (SELECT id FROM (
+             SELECT id, ROW_NUMBER() OVER (PARTITION BY stack_id
+                 ORDER BY id IS NOT (SELECT primary_photo_id FROM stacks WHERE stacks.id = photos.stack_id), id) AS stack_pick
+             FROM photos WHERE photos.stack_id IS NOT NULL AND {0}
+         ) WHERE stack_pick = 1))",
+        visible
+    )
+}
+
+/// Values bound by `one_per_stack` over a condition binding `values`
+fn bind_twice(mut values: Vec<rusqlite::types::Value>) -> Vec<rusqlite::types::Value> {
+    values.extend(values.clone());
+    values
+}
+
+/// Members of a photo's stack, selected after PHOTO_COLUMNS for stack_count.
+const STACK_COUNT: &str = "CASE WHEN photos.stack_id IS NULL THEN NULL \
+     ELSE (SELECT COUNT(*) FROM photos member WHERE member.stack_id = photos.stack_id AND member.deleted_at IS NULL) END";
+
+/// PHOTO_COLUMNS qualified with a table alias, for queries that join photos.
+fn photo_columns_as(alias: &str) -> String {
+    PHOTO_COLUMNS
+        .split(", ")
+        .map(|col| {
+            if col.starts_with('(') {
+                // The edits subquery names its tables already
+                col.replace("photos.id", &format!("{}.id", alias))
+            } else {
+                format!("{}.{}", alias, col)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
+}
 
 /// Map a row produced by PHOTO_COLUMNS into a PhotoMetadata.
 fn photo_from_row(row: &rusqlite::Row) -> rusqlite::Result<PhotoMetadata> {
+    let content_hash: Option<String> = row.get(6)?;
     Ok(PhotoMetadata {
         path: row.get(0)?,
         name: row.get(1)?,
@@ -272,7 +1619,7 @@ fn photo_from_row(row: &rusqlite::Row) -> rusqlite::Result<PhotoMetadata> {
         width: row.get(3)?,
         height: row.get(4)?,
         is_favorite: row.get::<_, i32>(5)? != 0,
-        content_hash: row.get(6)?,
+        content_hash: content_hash.clone(),
         latitude: row.get(7)?,
         longitude: row.get(8)?,
         location_name: row.get(9)?,
@@ -287,1084 +1634,7534 @@ fn photo_from_row(row: &rusqlite::Row) -> rusqlite::Result<PhotoMetadata> {
         duration_ms: row.get(18)?,
         codec: row.get(19)?,
         thumb_status: row.get(20)?,
+        is_missing: row.get::<_, Option<i32>>(21)?.unwrap_or(0) != 0,
+        rating: row.get::<_, Option<u8>>(22)?.unwrap_or(0),
+        tags: Vec::new(),
+        description: None,
+        file_format: row.get(23)?,
+        file_size: None,
+        mime_type: None,
+        paired_video_path: row.get(24)?,
+        filename_date_mismatch: None,
+        tz_offset_minutes: row.get(25)?,
+        date_source: row.get(26)?,
+        date_confident: row.get::<_, Option<i32>>(27)?.map(|v| v != 0),
+        id: row.get(28)?,
+        is_offline: row.get::<_, Option<i32>>(29)?.unwrap_or(0) != 0,
+        blurhash: row.get(30)?,
+        dominant_color: row.get(31)?,
+        color_label: row.get::<_, Option<String>>(32)?.as_deref().and_then(ColorLabel::parse),
+        flag: row.get::<_, Option<String>>(33)?.as_deref().and_then(PhotoFlag::parse),
+        stack_id: row.get(34)?,
+        edited_key: row
+            .get::<_, Option<String>>(35)?
+            .and_then(|recipe| serde_json::from_str::<PhotoEdits>(&recipe).ok())
+            .zip(content_hash)
+            .map(|(edits, hash)| crate::thumbnails::edited_key(&hash, &edits)),
+        stack_count: None,
     })
 }
 
-/// Get all photos from the database, sorted by date_taken descending
+/// Map a row of PHOTO_COLUMNS followed by STACK_COUNT.
+fn photo_with_stack_count(row: &rusqlite::Row) -> rusqlite::Result<PhotoMetadata> {
+    Ok(PhotoMetadata { stack_count: row.get(FIRST_EXTRA_COLUMN)?, ..photo_from_row(row)? })
+}
+
+/// Get all photos from the database, hidden and archived ones included, sorted by date_taken descending
 pub fn get_all_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
-    let query = format!("SELECT {} FROM photos ORDER BY date_taken DESC", PHOTO_COLUMNS);
+    let everything =
+        PhotoFilter { include_archived: true, include_hidden: true, include_rejected: true, ..Default::default() };
+    get_filtered_photos(conn, &everything)
+}
+
+/// get_all_photos narrowed by `filter`
+pub fn get_filtered_photos(conn: &Connection, filter: &PhotoFilter) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {}, {} FROM photos WHERE {}
+         ORDER BY {}",
+        PHOTO_COLUMNS,
+        STACK_COUNT,
+        one_per_stack(&format!("deleted_at IS NULL AND {} {}", SHOWN_IN_GRID, filter.sql())),
+        query_order()
+    );
     let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map([], photo_from_row)?;
+    let rows = stmt.query_map([], photo_with_stack_count)?;
     rows.collect()
 }
 
-/// Check if a photo already exists in the database
-pub fn photo_exists(conn: &Connection, path: &str) -> SqlResult<bool> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE path = ?1")?;
-    let count: i64 = stmt.query_row(params![path], |row| row.get(0))?;
-    Ok(count > 0)
+// ============================================================================
+// Pagination
+// ============================================================================
+
+/// Sort orders accepted by get_photos_page.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PhotoSort {
+    DateDesc,
+    DateAsc,
+    NameAsc,
+    NameDesc,
 }
 
-/// Delete a photo from the database
-pub fn delete_photo(conn: &Connection, path: &str) -> SqlResult<()> {
-    conn.execute("DELETE FROM photos WHERE path = ?1", params![path])?;
-    Ok(())
+impl std::str::FromStr for PhotoSort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "date_desc" | "date" => Ok(PhotoSort::DateDesc),
+            "date_asc" => Ok(PhotoSort::DateAsc),
+            "name_asc" | "name" => Ok(PhotoSort::NameAsc),
+            "name_desc" => Ok(PhotoSort::NameDesc),
+            other => Err(format!("Unknown sort order: {}", other)),
+        }
+    }
 }
 
-/// Get photo count by year
-pub fn get_photo_count_by_year(conn: &Connection) -> SqlResult<Vec<(String, i64)>> {
-    let mut stmt = conn.prepare(
-        "SELECT strftime('%Y', date_taken, 'unixepoch') as year, COUNT(*) as count
-         FROM photos
-         GROUP BY year
-         ORDER BY year DESC"
-    )?;
-    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
-    rows.collect()
-}
+impl PhotoSort {
+    /// `id` breaks ties so the order (and therefore the keyset) is total.
+    /// Oldest first still ends with the undated photos, as newest first does.
+    fn order_by(self) -> String {
+        match self {
+            PhotoSort::DateDesc => "date_taken DESC, id DESC".to_string(),
+            PhotoSort::DateAsc => format!("{}, date_taken ASC, id ASC", undated_sql()),
+            PhotoSort::NameAsc => "name COLLATE NOCASE ASC, id ASC".to_string(),
+            PhotoSort::NameDesc => "name COLLATE NOCASE DESC, id DESC".to_string(),
+        }
+    }
 
-/// Set photo favorite status
-pub fn set_photo_favorite(conn: &Connection, path: &str, is_favorite: bool) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET is_favorite = ?1 WHERE path = ?2",
-        params![if is_favorite { 1 } else { 0 }, path],
-    )?;
-    Ok(())
+    /// Rows strictly after the cursor in this order (?1 = date_taken, ?2 = name,
+    /// ?3 = id, ?6 = undated).
+    fn after_cursor(self) -> String {
+        match self {
+            PhotoSort::DateDesc => "(date_taken < ?1 OR (date_taken = ?1 AND id < ?3))".to_string(),
+            PhotoSort::DateAsc => format!(
+                "({undated} > ?6 OR ({undated} = ?6 AND (date_taken > ?1 OR (date_taken = ?1 AND id > ?3))))",
+                undated = undated_sql()
+            ),
+            PhotoSort::NameAsc => {
+                "(name COLLATE NOCASE > ?2 OR (name COLLATE NOCASE = ?2 AND id > ?3))".to_string()
+            }
+            PhotoSort::NameDesc => {
+                "(name COLLATE NOCASE < ?2 OR (name COLLATE NOCASE = ?2 AND id < ?3))".to_string()
+            }
+        }
+    }
 }
 
-/// Create a new album
-pub fn create_album(conn: &Connection, name: &str) -> SqlResult<i64> {
-    conn.execute(
-        "INSERT INTO albums (name, created_at) VALUES (?1, ?2)",
-        params![name, chrono::Utc::now().timestamp()],
-    )?;
-    Ok(conn.last_insert_rowid())
+/// Narrows what the main grid shows. The default is every photo
+/// get_photo_count counts, which leaves out hidden and archived ones.
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default)]
+#[serde(default)]
+pub struct PhotoFilter {
+    pub exclude_screenshots: bool,
+    pub include_archived: bool,
+    /// Not settable from the frontend, which goes through get_hidden_photos
+    #[serde(skip)]
+    pub include_hidden: bool,
+    /// Rejected photos leave the timeline once flagged. Not settable from
+    /// the frontend, which finds them through query_photos.
+    #[serde(skip)]
+    pub include_rejected: bool,
 }
 
-/// Delete an album
-pub fn delete_album(conn: &Connection, id: i64) -> SqlResult<()> {
-    conn.execute("DELETE FROM albums WHERE id = ?1", params![id])?;
-    Ok(())
+impl PhotoFilter {
+    /// Extra WHERE conditions, each starting with AND
+    fn sql(&self) -> String {
+        let mut sql = String::new();
+        if self.exclude_screenshots {
+            sql.push_str(" AND COALESCE(is_screenshot, 0) = 0");
+        }
+        if !self.include_archived {
+            sql.push_str(" AND COALESCE(is_archived, 0) = 0");
+        }
+        if !self.include_hidden {
+            sql.push_str(" AND COALESCE(is_hidden, 0) = 0");
+        }
+        if !self.include_rejected {
+            sql.push_str(" AND flag IS NOT 'reject'");
+        }
+        sql
+    }
 }
 
-/// Add a photo to an album
-pub fn add_photo_to_album(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
-    conn.execute(
-        "INSERT OR IGNORE INTO album_photos (album_id, photo_path, added_at) VALUES (?1, ?2, ?3)",
-        params![album_id, photo_path, chrono::Utc::now().timestamp()],
-    )?;
-    Ok(())
+/// The photos on the timeline under `filter`, one per stack: what
+/// get_photos_page, the buckets and get_photo_count show.
+fn timeline_sql(filter: &PhotoFilter) -> String {
+    one_per_stack(&format!("archived_at IS NULL AND deleted_at IS NULL AND {} {}", SHOWN_IN_GRID, filter.sql()))
 }
 
-/// Remove a photo from an album
-pub fn remove_photo_from_album(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
-    conn.execute(
-        "DELETE FROM album_photos WHERE album_id = ?1 AND photo_path = ?2",
-        params![album_id, photo_path],
-    )?;
-    Ok(())
+/// Sort key of the last row on a page. Passing it back fetches the next page
+/// with an index seek instead of skipping `offset` rows.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct PageCursor {
+    pub date_taken: i64,
+    pub name: String,
+    pub id: i64,
+    /// The row's date is unknown (see `PhotoSort::order_by`)
+    #[serde(default)]
+    pub undated: bool,
 }
 
 #[derive(serde::Serialize)]
-pub struct Album {
-    pub id: i64,
-    pub name: String,
-    pub cover_photo_path: Option<String>,
+pub struct PhotoPage {
+    pub photos: Vec<PhotoMetadata>,
+    /// None once the last page has been returned.
+    pub next_cursor: Option<PageCursor>,
+}
+
+/// Fetch one page of non-archived photos (the rows get_photo_count counts).
+/// With a cursor, rows start right after it and `offset` counts from there;
+/// without one this is plain LIMIT/OFFSET.
+pub fn get_photos_page(
+    conn: &Connection,
+    sort: PhotoSort,
+    offset: i64,
+    limit: i64,
+    cursor: Option<&PageCursor>,
+    filter: &PhotoFilter,
+) -> SqlResult<PhotoPage> {
+    let after = match cursor {
+        Some(_) => format!("AND {}", sort.after_cursor()),
+        None => String::new(),
+    };
+    let query = format!(
+        "SELECT {}, {}, id FROM photos WHERE {} {} ORDER BY {} LIMIT ?4 OFFSET ?5",
+        PHOTO_COLUMNS,
+        STACK_COUNT,
+        timeline_sql(filter),
+        after,
+        sort.order_by()
+    );
+
+    let (date_taken, name, id, undated) = match cursor {
+        Some(c) => (c.date_taken, c.name.as_str(), c.id, c.undated),
+        None => (0, "", 0, false),
+    };
+
+    let mut stmt = conn.prepare_cached(&query)?;
+    // Only some sorts use ?6, and SQLite wants exactly the parameters the query has
+    let values: [&dyn rusqlite::ToSql; 6] = [&date_taken, &name, &id, &limit, &offset, &undated];
+    let bound = &values[..stmt.parameter_count()];
+    let rows: Vec<(PhotoMetadata, i64)> = stmt
+        .query_map(bound, |row| {
+            Ok((photo_with_stack_count(row)?, row.get(FIRST_EXTRA_COLUMN + 1)?))
+        })?
+        .collect::<SqlResult<_>>()?;
+
+    let next_cursor = match rows.last() {
+        Some((photo, id)) if rows.len() as i64 == limit => Some(PageCursor {
+            date_taken: photo.date_taken,
+            name: photo.name.clone(),
+            id: *id,
+            undated: photo.date_source.as_deref() == Some(media::DATE_SOURCE_UNKNOWN),
+        }),
+        _ => None,
+    };
+
+    Ok(PhotoPage {
+        photos: rows.into_iter().map(|(photo, _)| photo).collect(),
+        next_cursor,
+    })
+}
+
+/// How finely get_timeline_buckets splits the timeline.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TimelineGranularity {
+    Year,
+    Month,
+    Day,
+}
+
+impl std::str::FromStr for TimelineGranularity {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "year" => Ok(TimelineGranularity::Year),
+            "month" => Ok(TimelineGranularity::Month),
+            "day" => Ok(TimelineGranularity::Day),
+            other => Err(format!("Unknown timeline granularity: {}", other)),
+        }
+    }
+}
+
+/// Period label of undated photos, which sort after every real period.
+pub const UNKNOWN_PERIOD: &str = "unknown";
+
+impl TimelineGranularity {
+    /// The period a row falls in ("2024", "2024-03" or "2024-03-09"), read on
+    /// the photo's own wall clock: its recorded offset, else `default_offset`,
+    /// else the machine's local time. The same rule exif_wall_clock uses.
+    fn period_sql(self, default_offset: Option<i32>) -> String {
+        let format = match self {
+            TimelineGranularity::Year => "%Y",
+            TimelineGranularity::Month => "%Y-%m",
+            TimelineGranularity::Day => "%Y-%m-%d",
+        };
+        let fallback = match default_offset {
+            Some(minutes) => format!("strftime('{}', date_taken + {} * 60, 'unixepoch')", format, minutes),
+            None => format!("strftime('{}', date_taken, 'unixepoch', 'localtime')", format),
+        };
+        format!(
+            "CASE WHEN date_source IS '{}' THEN '{}' \
+             WHEN tz_offset_minutes IS NOT NULL THEN strftime('{}', date_taken + tz_offset_minutes * 60, 'unixepoch') \
+             ELSE {} END",
+            media::DATE_SOURCE_UNKNOWN, UNKNOWN_PERIOD, format, fallback
+        )
+    }
+}
+
+/// A slice of the timeline for headers and the scrollbar scrubber.
+#[derive(serde::Serialize, Debug)]
+pub struct TimelineBucket {
+    pub period: String,
     pub count: i64,
+    /// Most recent favorite in the period, else its most recent photo
+    pub cover_photo_path: String,
 }
 
-/// Get all albums with photo counts
-pub fn get_albums(conn: &Connection) -> SqlResult<Vec<Album>> {
-    let mut stmt = conn.prepare(
-        "SELECT a.id, a.name, a.cover_photo_path, COUNT(ap.photo_path) as count
-         FROM albums a
-         LEFT JOIN album_photos ap ON a.id = ap.album_id
-         GROUP BY a.id
-         ORDER BY a.created_at DESC"
-    )?;
-    let rows = stmt.query_map([], |row| Ok(Album {
-        id: row.get(0)?,
-        name: row.get(1)?,
+/// Periods of the rows get_photos_page returns, newest first, undated last.
+pub fn get_timeline_buckets(
+    conn: &Connection,
+    granularity: TimelineGranularity,
+    default_offset: Option<i32>,
+    filter: &PhotoFilter,
+) -> SqlResult<Vec<TimelineBucket>> {
+    let query = format!(
+        "SELECT period, total, path FROM (
+             SELECT period, path, COUNT(*) OVER bucket AS total,
+                    ROW_NUMBER() OVER (bucket ORDER BY COALESCE(is_favorite, 0) DESC, date_taken DESC, id DESC) AS pick
+             FROM (
+                 SELECT {} AS period, path, is_favorite, date_taken, id FROM photos
+                 WHERE {}
+             )
+             WINDOW bucket AS (PARTITION BY period)
+         )
+         WHERE pick = 1
+         ORDER BY period = ?1, period DESC",
+        granularity.period_sql(default_offset),
+        timeline_sql(filter)
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![UNKNOWN_PERIOD], |row| Ok(TimelineBucket {
+        period: row.get(0)?,
+        count: row.get(1)?,
         cover_photo_path: row.get(2)?,
-        count: row.get(3)?,
     }))?;
     rows.collect()
 }
 
-/// Get all photos in an album
-pub fn get_album_photos(conn: &Connection, album_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
-    let mut stmt = conn.prepare(
-        "SELECT p.path, p.name, p.date_taken, p.width, p.height, p.is_favorite, p.content_hash, \
-         p.latitude, p.longitude, p.location_name, \
-         p.camera_make, p.camera_model, p.lens_model, p.iso, p.aperture, p.shutter_us, \
-         p.focal_length_mm, p.orientation, p.duration_ms, p.codec, p.thumb_status \
-         FROM photos p \
-         JOIN album_photos ap ON p.path = ap.photo_path \
-         WHERE ap.album_id = ?1 \
-         ORDER BY p.date_taken DESC"
-    )?;
-    let rows = stmt.query_map(params![album_id], photo_from_row)?;
+/// One page of a timeline bucket's photos, newest first.
+pub fn get_photos_in_bucket(
+    conn: &Connection,
+    period: &str,
+    granularity: TimelineGranularity,
+    default_offset: Option<i32>,
+    offset: i64,
+    limit: i64,
+    filter: &PhotoFilter,
+) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {}, {} FROM photos
+         WHERE {} AND {} = ?1
+         ORDER BY date_taken DESC, id DESC LIMIT ?2 OFFSET ?3",
+        PHOTO_COLUMNS,
+        STACK_COUNT,
+        timeline_sql(filter),
+        granularity.period_sql(default_offset)
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![period, limit, offset], photo_with_stack_count)?;
     rows.collect()
 }
 
-/// Set album cover photo
-pub fn set_album_cover(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE albums SET cover_photo_path = ?1 WHERE id = ?2",
-        params![photo_path, album_id],
-    )?;
-    Ok(())
+/// Media type query_photos can be limited to.
+#[derive(serde::Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaTypeFilter {
+    #[default]
+    All,
+    Photo,
+    Video,
 }
 
-/// Get all photos that have duplicates (same content_hash)
-pub fn get_duplicates(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
-    let query = format!(
-        "SELECT {} FROM photos \
-         WHERE content_hash IN ( \
-             SELECT content_hash FROM photos GROUP BY content_hash HAVING COUNT(*) > 1 \
-         ) \
-         ORDER BY content_hash, date_taken DESC",
-        PHOTO_COLUMNS
-    );
-    let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map([], photo_from_row)?;
-    rows.collect()
+/// Shape derived from width and height. Photos without dimensions have none.
+#[derive(serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Orientation {
+    Portrait,
+    Landscape,
+    Square,
 }
 
-/// Search photos by text (name or location)
-pub fn search_photos(conn: &Connection, query: &str) -> SqlResult<Vec<PhotoMetadata>> {
-    let search_term = format!("%{}%", query);
+/// What query_photos returns. Unset fields match everything; every set field
+/// must hold. The empty query is the grid get_all_photos returns.
+#[derive(serde::Deserialize, Debug, Clone, Default)]
+#[serde(default)]
+pub struct PhotoQuery {
+    pub media_type: MediaTypeFilter,
+    pub favorites_only: bool,
+    /// Inclusive capture-date bounds, unix seconds. Undated photos never match.
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    pub orientation: Option<Orientation>,
+    pub album_id: Option<i64>,
+    /// Photos must carry all of these tags
+    pub tag_ids: Vec<i64>,
+    /// Counts only tags the auto-tagger added, or only the user's
+    pub tag_source: Option<TagSource>,
+    pub min_rating: Option<u8>,
+    /// `none` matches photos without a label
+    pub color_label: Option<ColorLabel>,
+    /// `none` matches unflagged photos. Rejected photos are left out unless
+    /// asked for here.
+    pub flag: Option<PhotoFlag>,
+    /// Matched like search_photos
+    pub text: Option<String>,
+    pub exclude_screenshots: bool,
+}
+
+/// WHERE clause and bound values for `query`. As with smart_filter_sql the
+/// values are always bound and the clause only depends on which fields are set.
+pub fn build_photo_query(query: &PhotoQuery) -> (String, Vec<rusqlite::types::Value>) {
+    photo_query_sql(query, query.flag == Some(PhotoFlag::Reject))
+}
+
+/// build_photo_query, optionally keeping rejected photos whatever `query.flag` is
+fn photo_query_sql(query: &PhotoQuery, include_rejected: bool) -> (String, Vec<rusqlite::types::Value>) {
+    use rusqlite::types::Value;
+
+    let fts_query = query.text.as_deref().and_then(build_fts_query);
+    // Archived photos leave the timeline but not albums or search
+    let base = PhotoFilter {
+        exclude_screenshots: query.exclude_screenshots,
+        include_archived: query.album_id.is_some() || fts_query.is_some(),
+        include_rejected,
+        ..Default::default()
+    };
+    let mut clauses = vec![
+        format!("deleted_at IS NULL {}", base.sql()),
+        SHOWN_IN_GRID.to_string(),
+    ];
+    let mut values: Vec<Value> = Vec::new();
+
+    match query.media_type {
+        MediaTypeFilter::Video => clauses.push(is_video_sql()),
+        MediaTypeFilter::Photo => clauses.push(format!("NOT {}", is_video_sql())),
+        MediaTypeFilter::All => {}
+    }
+    if query.favorites_only {
+        clauses.push("is_favorite = 1".to_string());
+    }
+    if query.date_from.is_some() || query.date_to.is_some() {
+        clauses.push(format!("NOT {}", undated_sql()));
+    }
+    if let Some(from) = query.date_from {
+        clauses.push("date_taken >= ?".to_string());
+        values.push(Value::Integer(from));
+    }
+    if let Some(to) = query.date_to {
+        clauses.push("date_taken <= ?".to_string());
+        values.push(Value::Integer(to));
+    }
+    match query.orientation {
+        Some(Orientation::Portrait) => clauses.push("height > width AND width > 0".to_string()),
+        Some(Orientation::Landscape) => clauses.push("width > height AND height > 0".to_string()),
+        Some(Orientation::Square) => clauses.push("width = height AND width > 0".to_string()),
+        None => {}
+    }
+    if let Some(album_id) = query.album_id {
+        clauses.push("EXISTS (SELECT 1 FROM album_photos ap WHERE ap.photo_id = photos.id AND ap.album_id = ?)".to_string());
+        values.push(Value::Integer(album_id));
+    }
+    push_tag_clauses(&query.tag_ids, query.tag_source, &mut clauses, &mut values);
+    if let Some(min_rating) = query.min_rating {
+        clauses.push("COALESCE(rating, 0) >= ?".to_string());
+        values.push(Value::Integer(min_rating as i64));
+    }
+    push_color_label_clause(query.color_label, &mut clauses, &mut values);
+    match query.flag.map(PhotoFlag::stored) {
+        Some(Some(stored)) => {
+            clauses.push("flag = ?".to_string());
+            values.push(Value::Text(stored.to_string()));
+        }
+        Some(None) => clauses.push("flag IS NULL".to_string()),
+        None => {}
+    }
+    if let Some(fts_query) = fts_query {
+        clauses.push("id IN (SELECT rowid FROM photos_fts WHERE photos_fts MATCH ?)".to_string());
+        values.push(Value::Text(fts_query));
+    }
+
+    (one_per_stack(&clauses.join(" AND ")), bind_twice(values))
+}
+
+/// Grid order for query_photos: newest first, undated last, id breaking ties.
+fn query_order() -> String {
+    format!("{}, date_taken DESC, id DESC", undated_sql())
+}
+
+/// Photos matching `query` in grid order. `limit` None returns every match.
+pub fn query_photos(conn: &Connection, query: &PhotoQuery, offset: i64, limit: Option<i64>) -> SqlResult<Vec<PhotoMetadata>> {
+    let (where_clause, mut values) = build_photo_query(query);
     let sql = format!(
-        "SELECT {} FROM photos WHERE name LIKE ?1 OR location_name LIKE ?1 ORDER BY date_taken DESC",
-        PHOTO_COLUMNS
+        "SELECT {}, {} FROM photos WHERE {} ORDER BY {} LIMIT ? OFFSET ?",
+        PHOTO_COLUMNS, STACK_COUNT, where_clause, query_order()
     );
+    values.push(limit.unwrap_or(-1).into());
+    values.push(offset.into());
     let mut stmt = conn.prepare(&sql)?;
-    let rows = stmt.query_map(params![search_term], photo_from_row)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), photo_with_stack_count)?;
     rows.collect()
 }
 
-/// Get all unique locations with photo counts
-pub fn get_locations(conn: &Connection) -> SqlResult<Vec<(String, i64)>> {
-    let mut stmt = conn.prepare(
-        "SELECT location_name, COUNT(*) as count
-         FROM photos
-         WHERE location_name IS NOT NULL
-         GROUP BY location_name
-         ORDER BY count DESC"
-    )?;
-    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+/// Neighbours of a photo in query_photos order, for the lightbox.
+#[derive(serde::Serialize, Debug)]
+pub struct AdjacentPhotos {
+    /// In grid order, so the last one is the previous photo
+    pub before: Vec<PhotoMetadata>,
+    /// In grid order, so the first one is the next photo
+    pub after: Vec<PhotoMetadata>,
+}
+
+/// Up to `count` photos matching `query` on each side of `path`. The anchor
+/// only fixes a position, so it needn't match `query` itself (e.g. just
+/// unfavorited in a favorites view). None if `path` isn't in the library.
+pub fn get_adjacent_photos(conn: &Connection, path: &str, query: &PhotoQuery, count: i64) -> SqlResult<Option<AdjacentPhotos>> {
+    use rusqlite::types::Value;
+
+    let anchor: Option<(bool, i64, i64)> = conn
+        .query_row(
+            &format!("SELECT {}, date_taken, id FROM photos WHERE path = ?1", undated_sql()),
+            params![path],
+            |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+        )
+        .optional()?;
+    let Some((undated, date_taken, id)) = anchor else {
+        return Ok(None);
+    };
+
+    let (where_clause, values) = build_photo_query(query);
+    // Keyset on (undated, date_taken, id): "after" is later in query_order
+    let undated_expr = undated_sql();
+    let side = |after: bool| -> SqlResult<Vec<PhotoMetadata>> {
+        let (later, earlier, order) = if after {
+            (">", "<", query_order())
+        } else {
+            ("<", ">", format!("{} DESC, date_taken ASC, id ASC", undated_expr))
+        };
+        let sql = format!(
+            "SELECT {} FROM photos WHERE {} AND ({undated} {later} ? OR \
+             ({undated} = ? AND (date_taken {earlier} ? OR (date_taken = ? AND id {earlier} ?)))) \
+             ORDER BY {} LIMIT ?",
+            PHOTO_COLUMNS, where_clause, order, undated = undated_expr
+        );
+        let mut bound = values.clone();
+        bound.extend([
+            Value::Integer(undated as i64),
+            Value::Integer(undated as i64),
+            Value::Integer(date_taken),
+            Value::Integer(date_taken),
+            Value::Integer(id),
+            Value::Integer(count),
+        ]);
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(bound), photo_from_row)?;
+        rows.collect()
+    };
+
+    let mut before = side(false)?;
+    before.reverse();
+    Ok(Some(AdjacentPhotos { before, after: side(true)? }))
+}
+
+/// WHERE clause for slideshow picks: `query` minus anything archived, and
+/// photos only unless it asks for videos.
+fn slideshow_query(query: &PhotoQuery) -> (String, Vec<rusqlite::types::Value>) {
+    let mut query = query.clone();
+    if query.media_type == MediaTypeFilter::All {
+        query.media_type = MediaTypeFilter::Photo;
+    }
+    let (where_clause, values) = build_photo_query(&query);
+    (format!("{} AND archived_at IS NULL AND COALESCE(is_archived, 0) = 0", where_clause), values)
+}
+
+/// Up to `count` photos matching `query` for a slideshow, in random order.
+pub fn get_random_photos(conn: &Connection, query: &PhotoQuery, count: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    let (where_clause, mut values) = slideshow_query(query);
+    let sql = format!("SELECT {} FROM photos WHERE {} ORDER BY RANDOM() LIMIT ?", PHOTO_COLUMNS, where_clause);
+    values.push(count.into());
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), photo_from_row)?;
     rows.collect()
 }
 
-/// Check if a photo with the given hash exists
-pub fn hash_exists(conn: &Connection, hash: &str) -> SqlResult<bool> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE content_hash = ?1")?;
-    let count: i64 = stmt.query_row(params![hash], |row| row.get(0))?;
-    Ok(count > 0)
+/// SplitMix64 finalizer: spreads neighbouring ids across the whole range.
+fn mix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+    x ^ (x >> 31)
 }
 
-// ============================================================================
-// Duplicate Detection and Archive Functions
-// ============================================================================
+/// One page of the slideshow photos matching `query`, shuffled by `seed`.
+/// The same seed gives the same order, so paging through it neither repeats
+/// nor skips; a photo imported meanwhile just slots in somewhere.
+pub fn get_shuffled_page(conn: &Connection, query: &PhotoQuery, seed: u64, offset: usize, limit: usize) -> SqlResult<Vec<PhotoMetadata>> {
+    let (where_clause, values) = slideshow_query(query);
+    let mut ids: Vec<i64> = conn
+        .prepare(&format!("SELECT id FROM photos WHERE {}", where_clause))?
+        .query_map(rusqlite::params_from_iter(values), |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    let salt = mix64(seed);
+    ids.sort_by_key(|id| (mix64(*id as u64 ^ salt), *id));
+    let page: Vec<i64> = ids.into_iter().skip(offset).take(limit).collect();
+
+    let mut by_id: HashMap<i64, PhotoMetadata> = HashMap::new();
+    for chunk in page.chunks(MAX_IN_PARAMS) {
+        let placeholders = vec!["?"; chunk.len()].join(",");
+        let sql = format!("SELECT {} FROM photos WHERE id IN ({})", PHOTO_COLUMNS, placeholders);
+        let mut stmt = conn.prepare(&sql)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(chunk), photo_from_row)?;
+        for photo in rows {
+            let photo = photo?;
+            if let Some(id) = photo.id {
+                by_id.insert(id, photo);
+            }
+        }
+    }
+    Ok(page.iter().filter_map(|id| by_id.remove(id)).collect())
+}
 
-/// Get the path to the archive directory
-pub fn get_archive_path() -> std::path::PathBuf {
-    let mut path = get_library_path();
-    path.push("Archive");
-    std::fs::create_dir_all(&path).expect("Failed to create Archive directory");
-    path
+/// Check if a photo already exists in the database
+pub fn photo_exists(conn: &Connection, path: &str) -> SqlResult<bool> {
+    let mut stmt = conn.prepare(&format!("SELECT COUNT(*) FROM photos WHERE path = ?1 COLLATE {}", PATH_COLLATION))?;
+    let count: i64 = stmt.query_row(params![path], |row| row.get(0))?;
+    Ok(count > 0)
 }
 
-/// Get all photos that need dhash computation (dhash_64 is NULL and not archived)
-pub fn get_photos_without_dhash(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
-    let mut stmt = conn.prepare(
-        "SELECT path, name FROM photos WHERE dhash_64 IS NULL AND archived_at IS NULL"
-    )?;
-    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+/// Delete a photo from the database. Its album and tag memberships are
+/// removed by ON DELETE CASCADE.
+pub fn delete_photo(conn: &Connection, path: &str) -> SqlResult<()> {
+    conn.execute("UPDATE albums SET cover_photo_path = NULL WHERE cover_photo_path = ?1", params![path])?;
+    conn.execute("DELETE FROM photos WHERE path = ?1", params![path])?;
+    Ok(())
+}
+
+/// Get photo count by year
+pub fn get_photo_count_by_year(conn: &Connection) -> SqlResult<Vec<(String, i64)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT strftime('%Y', date_taken, 'unixepoch') as year, COUNT(*) as count
+         FROM photos
+         WHERE {}
+         GROUP BY year
+         ORDER BY year DESC",
+        one_per_stack(&format!("deleted_at IS NULL AND {}", SHOWN_IN_GRID))
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get::<_, String>(0)?, row.get::<_, i64>(1)?)))?;
     rows.collect()
 }
 
-/// Update the dhash for a photo
-pub fn update_photo_dhash(conn: &Connection, path: &str, dhash: i64) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET dhash_64 = ?1 WHERE path = ?2",
-        params![dhash, path],
-    )?;
+/// Most paths bound into one `IN (...)` list. SQLite's default limit is 999
+/// parameters per statement; this leaves room for a few others.
+const MAX_IN_PARAMS: usize = 900;
+
+/// Set photo favorite status
+pub fn set_photo_favorite(conn: &Connection, path: &str, is_favorite: bool) -> SqlResult<()> {
+    set_photos_favorite(conn, &[path.to_string()], is_favorite)?;
     Ok(())
 }
 
-/// Update the is_screenshot flag for a photo
-pub fn update_photo_screenshot_flag(conn: &Connection, path: &str, is_screenshot: bool) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET is_screenshot = ?1 WHERE path = ?2",
-        params![if is_screenshot { 1 } else { 0 }, path],
-    )?;
-    Ok(())
+/// Set favorite status on many photos in one transaction.
+/// Returns the number of rows matched, so callers can spot paths that are gone.
+pub fn set_photos_favorite(conn: &Connection, paths: &[String], is_favorite: bool) -> SqlResult<usize> {
+    set_photos_favorite_by_id(conn, &get_photo_ids(conn, paths)?, is_favorite)
 }
 
-/// Get all non-archived photos with their dhash values for duplicate detection
-pub fn get_all_photos_with_dhash(conn: &Connection) -> SqlResult<Vec<(String, Option<i64>, Option<String>)>> {
-    let mut stmt = conn.prepare(
-        "SELECT path, dhash_64, content_hash FROM photos WHERE archived_at IS NULL ORDER BY date_taken DESC"
-    )?;
-    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+/// set_photos_favorite keyed by photo id
+pub fn set_photos_favorite_by_id(conn: &Connection, ids: &[i64], is_favorite: bool) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    for chunk in ids.chunks(MAX_IN_PARAMS) {
+        let placeholders = vec!["?"; chunk.len()].join(",");
+        let sql = format!(
+            "UPDATE photos SET is_favorite = {} WHERE id IN ({})",
+            if is_favorite { 1 } else { 0 },
+            placeholders
+        );
+        updated += tx.execute(&sql, rusqlite::params_from_iter(chunk))?;
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// Set a 0/1 column on many photos in one transaction. Returns how many rows were updated.
+fn set_photos_flag(conn: &Connection, column: &str, paths: &[String], value: bool) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    for chunk in paths.chunks(MAX_IN_PARAMS) {
+        let placeholders = vec!["?"; chunk.len()].join(",");
+        let sql = format!(
+            "UPDATE photos SET {} = {} WHERE path IN ({})",
+            column,
+            if value { 1 } else { 0 },
+            placeholders
+        );
+        updated += tx.execute(&sql, rusqlite::params_from_iter(chunk))?;
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// Hide or unhide many photos in one transaction. Returns how many rows were updated.
+pub fn set_photos_hidden(conn: &Connection, paths: &[String], hidden: bool) -> SqlResult<usize> {
+    set_photos_flag(conn, "is_hidden", paths, hidden)
+}
+
+/// Hidden photos outside the trash, newest first
+pub fn get_hidden_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {}, {} FROM photos WHERE {}
+         ORDER BY date_taken DESC, id DESC",
+        PHOTO_COLUMNS,
+        STACK_COUNT,
+        one_per_stack(&format!("is_hidden = 1 AND deleted_at IS NULL AND {}", SHOWN_IN_GRID))
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_with_stack_count)?;
     rows.collect()
 }
 
-/// Get all photos marked as screenshots
-pub fn get_screenshots(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+/// Move photos off (or back onto) the main timeline. Albums and search are unaffected.
+/// Returns how many rows were updated.
+pub fn set_photos_archived(conn: &Connection, paths: &[String], archived: bool) -> SqlResult<usize> {
+    set_photos_flag(conn, "is_archived", paths, archived)
+}
+
+/// Photos archived off the timeline, newest first. Hidden ones stay out.
+pub fn get_archived_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
     let query = format!(
-        "SELECT {} FROM photos WHERE is_screenshot = 1 AND archived_at IS NULL ORDER BY date_taken DESC",
-        PHOTO_COLUMNS
+        "SELECT {}, {} FROM photos WHERE {}
+         ORDER BY date_taken DESC, id DESC",
+        PHOTO_COLUMNS,
+        STACK_COUNT,
+        one_per_stack(&format!(
+            "is_archived = 1 AND COALESCE(is_hidden, 0) = 0 AND deleted_at IS NULL AND {}",
+            SHOWN_IN_GRID
+        ))
     );
     let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map([], photo_from_row)?;
+    let rows = stmt.query_map([], photo_with_stack_count)?;
     rows.collect()
 }
 
-/// Archive a photo (set archived_at timestamp)
-pub fn archive_photo(conn: &Connection, path: &str) -> SqlResult<()> {
-    let now = chrono::Utc::now().timestamp();
-    conn.execute(
-        "UPDATE photos SET archived_at = ?1 WHERE path = ?2",
-        params![now, path],
+/// Set a photo's star rating (0-5; callers validate the range).
+/// Returns false if no photo has that path.
+pub fn set_photo_rating(conn: &Connection, path: &str, rating: u8) -> SqlResult<bool> {
+    let changed = conn.execute(
+        "UPDATE photos SET rating = ?1, user_set = user_set | ?3 WHERE path = ?2",
+        params![rating, path, USER_SET_RATING],
     )?;
-    Ok(())
+    Ok(changed > 0)
 }
 
-/// Restore a photo from archive (clear archived_at)
-pub fn restore_photo(conn: &Connection, path: &str) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET archived_at = NULL WHERE path = ?1",
-        params![path],
-    )?;
-    Ok(())
+/// Set the same rating on many photos (bulk operation). Returns rows updated.
+pub fn set_photos_rating(conn: &Connection, paths: &[String], rating: u8) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET rating = ?1, user_set = user_set | ?3 WHERE path = ?2")?;
+        for path in paths {
+            updated += stmt.execute(params![rating, path, USER_SET_RATING])?;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
 }
 
-/// Get all archived photos
-pub fn get_archived_photos(conn: &Connection) -> SqlResult<Vec<(PhotoMetadata, i64)>> {
+/// Non-archived photos rated at least `min_rating` stars, newest first
+pub fn get_photos_by_min_rating(conn: &Connection, min_rating: u8) -> SqlResult<Vec<PhotoMetadata>> {
     let query = format!(
-        "SELECT {}, archived_at FROM photos WHERE archived_at IS NOT NULL ORDER BY archived_at DESC",
+        "SELECT {} FROM photos
+         WHERE rating >= ?1 AND archived_at IS NULL AND deleted_at IS NULL
+         ORDER BY date_taken DESC",
         PHOTO_COLUMNS
     );
     let mut stmt = conn.prepare(&query)?;
-    // archived_at is at index 21 (after the 21 PHOTO_COLUMNS fields)
-    let rows = stmt.query_map([], |row| Ok((photo_from_row(row)?, row.get::<_, i64>(21)?)))?;
+    let rows = stmt.query_map(params![min_rating], photo_from_row)?;
     rows.collect()
 }
 
-/// Get photos archived more than N days ago (for cleanup)
-pub fn get_old_archived_photos(conn: &Connection, days: i64) -> SqlResult<Vec<String>> {
-    let cutoff = chrono::Utc::now().timestamp() - (days * 24 * 60 * 60);
-    let mut stmt = conn.prepare(
-        "SELECT path FROM photos WHERE archived_at IS NOT NULL AND archived_at < ?1"
-    )?;
-    let rows = stmt.query_map(params![cutoff], |row| row.get(0))?;
-    rows.collect()
+/// Lightroom-style color label. Stored lowercase in photos.color_label;
+/// `None` is no label, stored as NULL.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ColorLabel {
+    Red,
+    Yellow,
+    Green,
+    Blue,
+    Purple,
+    None,
 }
 
-/// Permanently delete a photo from database
-pub fn permanently_delete_photo(conn: &Connection, path: &str) -> SqlResult<()> {
-    conn.execute("DELETE FROM photos WHERE path = ?1", params![path])?;
-    Ok(())
+impl ColorLabel {
+    /// The labels a photo can carry
+    pub const COLORS: [ColorLabel; 5] =
+        [ColorLabel::Red, ColorLabel::Yellow, ColorLabel::Green, ColorLabel::Blue, ColorLabel::Purple];
+
+    /// Value of the color_label column; None for no label
+    pub fn stored(self) -> Option<&'static str> {
+        match self {
+            ColorLabel::Red => Some("red"),
+            ColorLabel::Yellow => Some("yellow"),
+            ColorLabel::Green => Some("green"),
+            ColorLabel::Blue => Some("blue"),
+            ColorLabel::Purple => Some("purple"),
+            ColorLabel::None => None,
+        }
+    }
+
+    pub fn parse(stored: &str) -> Option<ColorLabel> {
+        ColorLabel::COLORS.into_iter().find(|label| label.stored() == Some(stored))
+    }
 }
 
-/// Get total photo count (non-archived)
-pub fn get_photo_count(conn: &Connection) -> SqlResult<i64> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE archived_at IS NULL")?;
-    let count: i64 = stmt.query_row([], |row| row.get(0))?;
-    Ok(count)
+/// Set the same color label on many photos; ColorLabel::None clears it.
+/// Returns rows updated.
+pub fn set_photos_color_label(conn: &Connection, paths: &[String], label: ColorLabel) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET color_label = ?1, user_set = user_set | ?3 WHERE path = ?2")?;
+        for path in paths {
+            updated += stmt.execute(params![label.stored(), path, USER_SET_COLOR_LABEL])?;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
 }
 
-/// Get count of photos with dhash computed
-pub fn get_photos_with_dhash_count(conn: &Connection) -> SqlResult<i64> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE dhash_64 IS NOT NULL AND archived_at IS NULL")?;
-    let count: i64 = stmt.query_row([], |row| row.get(0))?;
-    Ok(count)
+/// Set or clear a photo's description. Blank text clears it.
+/// Returns false if no photo has that path.
+pub fn set_photo_description(conn: &Connection, path: &str, text: &str) -> SqlResult<bool> {
+    let text = text.trim();
+    let description = if text.is_empty() { None } else { Some(text) };
+    let changed = conn.execute(
+        "UPDATE photos SET description = ?1, user_set = user_set | ?3 WHERE path = ?2",
+        params![description, path, USER_SET_DESCRIPTION],
+    )?;
+    Ok(changed > 0)
 }
 
-// ============================================================================
-// TerraForm (Review Mode) Functions
-// ============================================================================
+/// Everything stored about one photo, for the info panel. The grid uses the
+/// slimmer PhotoMetadata.
+#[derive(serde::Serialize)]
+pub struct PhotoDetails {
+    #[serde(flatten)]
+    pub photo: PhotoMetadata,
+    pub description: Option<String>,
+    pub source_type: String,
+    /// When the photo was added to Terra
+    pub created_at: i64,
+    pub file_size: Option<i64>,
+    pub file_mtime: Option<i64>,
+    pub mime_type: Option<String>,
+    pub is_screenshot: bool,
+    pub reviewed_at: Option<i64>,
+    pub archived_at: Option<i64>,
+    pub deleted_at: Option<i64>,
+    /// Import source of an uploaded file, e.g. a folder on an SD card
+    pub original_path: Option<String>,
+    /// Crop and rotation applied on top of the original, if any
+    pub edits: Option<PhotoEdits>,
+}
 
-/// Get all unreviewed photos (reviewed_at is NULL and not archived)
-pub fn get_unreviewed_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+/// Full row for one photo, including archived and trashed ones
+pub fn get_photo_details(conn: &Connection, path: &str) -> SqlResult<Option<PhotoDetails>> {
     let query = format!(
-        "SELECT {} FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL ORDER BY date_taken DESC",
+        "SELECT {}, description, source_type, created_at, file_size, file_mtime,
+                is_screenshot, reviewed_at, archived_at, deleted_at, original_path,
+                (SELECT recipe FROM photo_edits WHERE photo_id = photos.id), mime_type
+         FROM photos WHERE path = ?1",
         PHOTO_COLUMNS
     );
-    let mut stmt = conn.prepare(&query)?;
-    let rows = stmt.query_map([], photo_from_row)?;
-    rows.collect()
+    conn.query_row(&query, params![path], |row| {
+        Ok(PhotoDetails {
+            photo: photo_from_row(row)?,
+            description: row.get(FIRST_EXTRA_COLUMN)?,
+            source_type: row.get(FIRST_EXTRA_COLUMN + 1)?,
+            created_at: row.get(FIRST_EXTRA_COLUMN + 2)?,
+            file_size: row.get(FIRST_EXTRA_COLUMN + 3)?,
+            file_mtime: row.get(FIRST_EXTRA_COLUMN + 4)?,
+            is_screenshot: row.get::<_, Option<i32>>(FIRST_EXTRA_COLUMN + 5)?.unwrap_or(0) != 0,
+            reviewed_at: row.get(FIRST_EXTRA_COLUMN + 6)?,
+            archived_at: row.get(FIRST_EXTRA_COLUMN + 7)?,
+            deleted_at: row.get(FIRST_EXTRA_COLUMN + 8)?,
+            original_path: row.get(FIRST_EXTRA_COLUMN + 9)?,
+            edits: row
+                .get::<_, Option<String>>(FIRST_EXTRA_COLUMN + 10)?
+                .and_then(|recipe| serde_json::from_str(&recipe).ok()),
+            mime_type: row.get(FIRST_EXTRA_COLUMN + 11)?,
+        })
+    })
+    .optional()
 }
 
-/// Mark a photo as reviewed
-pub fn mark_photo_reviewed(conn: &Connection, path: &str) -> SqlResult<()> {
-    let now = chrono::Utc::now().timestamp();
+/// Create a new album
+pub fn create_album(conn: &Connection, name: &str) -> SqlResult<i64> {
     conn.execute(
-        "UPDATE photos SET reviewed_at = ?1 WHERE path = ?2",
-        params![now, path],
+        "INSERT INTO albums (name, created_at) VALUES (?1, ?2)",
+        params![name, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// Id of the oldest album called `name`, if any
+pub fn find_album_by_name(conn: &Connection, name: &str) -> SqlResult<Option<i64>> {
+    conn.query_row(
+        "SELECT id FROM albums WHERE name = ?1 ORDER BY created_at, id LIMIT 1",
+        params![name],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+/// `id` and every album nested under it, at any depth.
+const ALBUM_SUBTREE_SQL: &str = "WITH RECURSIVE subtree(id) AS ( \
+         SELECT ?1 UNION SELECT a.id FROM albums a JOIN subtree ON a.parent_id = subtree.id) \
+     SELECT id FROM subtree";
+
+/// Delete an album. Albums nested in it are deleted too with
+/// `delete_children`, otherwise they move up to the top level.
+pub fn delete_album(conn: &Connection, id: i64, delete_children: bool) -> SqlResult<()> {
+    // Memberships go with the albums through ON DELETE CASCADE. The subtree
+    // is deleted in one statement so no child outlives its parent.
+    let tx = conn.unchecked_transaction()?;
+    if delete_children {
+        tx.execute(&format!("DELETE FROM albums WHERE id IN ({})", ALBUM_SUBTREE_SQL), params![id])?;
+    } else {
+        tx.execute("UPDATE albums SET parent_id = NULL WHERE parent_id = ?1", params![id])?;
+        tx.execute("DELETE FROM albums WHERE id = ?1", params![id])?;
+    }
+    tx.commit()
+}
+
+/// Whether putting `id` inside `parent_id` would nest an album in itself.
+pub fn album_move_creates_cycle(conn: &Connection, id: i64, parent_id: i64) -> SqlResult<bool> {
+    let nested: bool = conn.query_row(
+        &format!("SELECT ?2 IN ({})", ALBUM_SUBTREE_SQL),
+        params![id, parent_id],
+        |row| row.get(0),
     )?;
+    Ok(nested)
+}
+
+/// Move an album inside another, or to the top level with None. False if
+/// there is no such album. Callers check album_move_creates_cycle first.
+pub fn set_album_parent(conn: &Connection, id: i64, parent_id: Option<i64>) -> SqlResult<bool> {
+    let changed = conn.execute("UPDATE albums SET parent_id = ?1 WHERE id = ?2", params![parent_id, id])?;
+    Ok(changed > 0)
+}
+
+/// Rename an album. False if there is no such album.
+pub fn rename_album(conn: &Connection, id: i64, name: &str) -> SqlResult<bool> {
+    let changed = conn.execute("UPDATE albums SET name = ?1 WHERE id = ?2", params![name, id])?;
+    Ok(changed > 0)
+}
+
+/// Store the sidebar order: each album in `ids` gets its position in the
+/// list. Albums left out keep their old position.
+pub fn set_album_order(conn: &Connection, ids: &[i64]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE albums SET sort_order = ?1 WHERE id = ?2")?;
+        for (position, id) in ids.iter().enumerate() {
+            stmt.execute(params![position as i64, id])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Add a photo to an album
+pub fn add_photo_to_album(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
+    add_photos_to_album_by_id(conn, album_id, &get_photo_ids(conn, &[photo_path.to_string()])?)?;
     Ok(())
 }
 
-/// Get count of unreviewed photos
-pub fn get_unreviewed_count(conn: &Connection) -> SqlResult<i64> {
-    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL")?;
-    let count: i64 = stmt.query_row([], |row| row.get(0))?;
-    Ok(count)
+/// Add photos to an album by id in one transaction. Returns how many
+/// weren't in it already; ids with no photo are skipped.
+pub fn add_photos_to_album_by_id(conn: &Connection, album_id: i64, ids: &[i64]) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let now = chrono::Utc::now().timestamp();
+    let mut added = 0;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT OR IGNORE INTO album_photos (album_id, photo_id, added_at)
+             SELECT ?1, id, ?3 FROM photos WHERE id = ?2",
+        )?;
+        for id in ids {
+            added += stmt.execute(params![album_id, id, now])?;
+        }
+    }
+    tx.commit()?;
+    Ok(added)
 }
 
-/// Unmark a photo as reviewed (for undo)
-pub fn unmark_photo_reviewed(conn: &Connection, path: &str) -> SqlResult<()> {
+/// Remove a photo from an album
+pub fn remove_photo_from_album(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
     conn.execute(
-        "UPDATE photos SET reviewed_at = NULL WHERE path = ?1",
-        params![path],
+        "DELETE FROM album_photos WHERE album_id = ?1 AND photo_id = (SELECT id FROM photos WHERE path = ?2)",
+        params![album_id, photo_path],
     )?;
     Ok(())
 }
 
-// ============================================================================
-// Tag Functions
-// ============================================================================
+/// Regular albums hold the photos added to them; smart albums are a saved
+/// query. Ids are only unique within a kind.
+#[derive(serde::Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum AlbumKind {
+    Regular,
+    Smart,
+}
 
-#[derive(serde::Serialize, Clone)]
-pub struct Tag {
+#[derive(serde::Serialize)]
+pub struct Album {
     pub id: i64,
     pub name: String,
-    pub color: String,
+    pub cover_photo_path: Option<String>,
     pub count: i64,
+    /// Distinct photos in this album and every album nested in it
+    pub total_count: i64,
+    /// Bytes on disk of `count`'s photos, as far as sizes are known
+    pub size_bytes: i64,
+    /// Bytes on disk of `total_count`'s photos
+    pub total_size_bytes: i64,
+    pub parent_id: Option<i64>,
+    pub kind: AlbumKind,
 }
 
-/// Create a new tag
-pub fn create_tag(conn: &Connection, name: &str, color: &str) -> SqlResult<i64> {
-    conn.execute(
-        "INSERT INTO tags (name, color, created_at) VALUES (?1, ?2, ?3)",
-        params![name, color, chrono::Utc::now().timestamp()],
+/// Get all albums with photo counts, regular albums first, then smart ones.
+/// Regular albums follow the saved order; unordered ones come after, newest first.
+pub fn get_albums(conn: &Connection) -> SqlResult<Vec<Album>> {
+    let mut totals_stmt = conn.prepare(
+        "WITH RECURSIVE tree(root, id) AS (
+             SELECT id, id FROM albums
+             UNION SELECT tree.root, a.id FROM albums a JOIN tree ON a.parent_id = tree.id
+         )
+         SELECT root, COUNT(*), COALESCE(SUM(file_size), 0) FROM (
+             SELECT DISTINCT tree.root, p.id, p.file_size FROM tree
+             JOIN album_photos ap ON ap.album_id = tree.id
+             JOIN photos p ON p.id = ap.photo_id AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         )
+         GROUP BY root",
     )?;
-    Ok(conn.last_insert_rowid())
-}
+    let totals: HashMap<i64, (i64, i64)> = totals_stmt
+        .query_map([], |row| Ok((row.get(0)?, (row.get(1)?, row.get(2)?))))?
+        .collect::<SqlResult<_>>()?;
 
-/// Update a tag
-pub fn update_tag(conn: &Connection, id: i64, name: &str, color: &str) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3",
-        params![name, color, id],
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.name, a.cover_photo_path, COUNT(p.path) as count, a.parent_id,
+                COALESCE(SUM(p.file_size), 0)
+         FROM albums a
+         LEFT JOIN album_photos ap ON a.id = ap.album_id
+         LEFT JOIN photos p ON p.id = ap.photo_id AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         GROUP BY a.id
+         ORDER BY a.sort_order IS NULL, a.sort_order, a.created_at DESC, a.id DESC"
     )?;
-    Ok(())
+    let rows = stmt.query_map([], |row| {
+        let (total_count, total_size_bytes) = totals.get(&row.get::<_, i64>(0)?).copied().unwrap_or((0, 0));
+        Ok(Album {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            cover_photo_path: row.get(2)?,
+            count: row.get(3)?,
+            total_count,
+            size_bytes: row.get(5)?,
+            total_size_bytes,
+            parent_id: row.get(4)?,
+            kind: AlbumKind::Regular,
+        })
+    })?;
+    let mut albums = rows.collect::<SqlResult<Vec<_>>>()?;
+    albums.extend(get_smart_albums(conn)?.into_iter().map(|smart| Album {
+        id: smart.id,
+        name: smart.name,
+        cover_photo_path: smart.cover_photo_path,
+        count: smart.count,
+        total_count: smart.count,
+        size_bytes: smart.size_bytes,
+        total_size_bytes: smart.size_bytes,
+        parent_id: None,
+        kind: AlbumKind::Smart,
+    }));
+    Ok(albums)
 }
 
-/// Delete a tag
-pub fn delete_tag(conn: &Connection, id: i64) -> SqlResult<()> {
-    conn.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
-    Ok(())
+/// Get all photos in an album
+pub fn get_album_photos(conn: &Connection, album_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    album_photos(conn, album_id, false)
 }
 
-/// Get all tags with counts
-pub fn get_all_tags(conn: &Connection) -> SqlResult<Vec<Tag>> {
-    let mut stmt = conn.prepare(
-        "SELECT t.id, t.name, t.color, COUNT(pt.photo_path) as count
-         FROM tags t
-         LEFT JOIN photo_tags pt ON t.id = pt.tag_id
-         LEFT JOIN photos p ON pt.photo_path = p.path AND p.archived_at IS NULL
-         GROUP BY t.id
-         ORDER BY count DESC, t.name ASC"
-    )?;
-    let rows = stmt.query_map([], |row| Ok(Tag {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        color: row.get(2)?,
-        count: row.get(3)?,
-    }))?;
-    rows.collect()
+/// Every photo in an album, hidden ones included, for exports
+pub fn get_all_album_photos(conn: &Connection, album_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    album_photos(conn, album_id, true)
 }
 
-/// Get tags for a specific photo
-pub fn get_tags_for_photo(conn: &Connection, path: &str) -> SqlResult<Vec<Tag>> {
-    let mut stmt = conn.prepare(
-        "SELECT t.id, t.name, t.color, 0 as count
-         FROM tags t
-         JOIN photo_tags pt ON t.id = pt.tag_id
-         WHERE pt.photo_path = ?1
-         ORDER BY t.name ASC"
-    )?;
-    let rows = stmt.query_map(params![path], |row| Ok(Tag {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        color: row.get(2)?,
-        count: row.get(3)?,
-    }))?;
+fn album_photos(conn: &Connection, album_id: i64, include_hidden: bool) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos p \
+         JOIN album_photos ap ON p.id = ap.photo_id \
+         WHERE ap.album_id = ?1 AND p.deleted_at IS NULL {} \
+         ORDER BY p.date_taken DESC",
+        photo_columns_as("p"),
+        if include_hidden { "" } else { "AND COALESCE(p.is_hidden, 0) = 0" }
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![album_id], photo_from_row)?;
     rows.collect()
 }
 
-/// Add tags to photos (bulk operation)
-pub fn add_tags_to_photos(conn: &Connection, tag_ids: &[i64], photo_paths: &[String]) -> SqlResult<()> {
-    let now = chrono::Utc::now().timestamp();
-    for tag_id in tag_ids {
-        for path in photo_paths {
-            conn.execute(
-                "INSERT OR IGNORE INTO photo_tags (tag_id, photo_path, added_at) VALUES (?1, ?2, ?3)",
-                params![tag_id, path, now],
-            )?;
+/// Name of an album, or None if there is no such album
+pub fn get_album_name(conn: &Connection, album_id: i64) -> SqlResult<Option<String>> {
+    conn.query_row("SELECT name FROM albums WHERE id = ?1", params![album_id], |row| row.get(0))
+        .optional()
+}
+
+/// For each of `paths` in at least one album, the name of the oldest album
+/// it is in. Export by album files a photo there.
+pub fn get_first_album_names(conn: &Connection, paths: &[String]) -> SqlResult<HashMap<String, String>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT a.name FROM album_photos ap
+         JOIN albums a ON a.id = ap.album_id
+         JOIN photos p ON p.id = ap.photo_id
+         WHERE p.path = ?1
+         ORDER BY a.created_at, a.id
+         LIMIT 1",
+    )?;
+    let mut names = HashMap::new();
+    for path in paths {
+        if let Some(name) = stmt.query_row(params![path], |row| row.get(0)).optional()? {
+            names.insert(path.clone(), name);
         }
     }
-    Ok(())
+    Ok(names)
 }
 
-/// Remove a tag from a photo
-pub fn remove_tag_from_photo(conn: &Connection, tag_id: i64, photo_path: &str) -> SqlResult<()> {
+/// Set album cover photo
+pub fn set_album_cover(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
     conn.execute(
-        "DELETE FROM photo_tags WHERE tag_id = ?1 AND photo_path = ?2",
-        params![tag_id, photo_path],
+        "UPDATE albums SET cover_photo_path = ?1 WHERE id = ?2",
+        params![photo_path, album_id],
     )?;
     Ok(())
 }
 
-/// Get photos by tags (with AND/OR logic)
-pub fn get_photos_by_tags(conn: &Connection, tag_ids: &[i64], match_all: bool) -> SqlResult<Vec<PhotoMetadata>> {
-    if tag_ids.is_empty() {
-        return Ok(Vec::new());
-    }
+// ============================================================================
+// Smart Album Functions
+// ============================================================================
 
-    let placeholders: Vec<String> = tag_ids.iter().map(|_| "?".to_string()).collect();
-    let placeholder_str = placeholders.join(",");
+/// Media type a smart album can be limited to.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum MediaType {
+    Photo,
+    Video,
+}
 
-    let photo_cols = "p.path, p.name, p.date_taken, p.width, p.height, p.is_favorite, p.content_hash, \
-                      p.latitude, p.longitude, p.location_name, \
-                      p.camera_make, p.camera_model, p.lens_model, p.iso, p.aperture, p.shutter_us, \
-                      p.focal_length_mm, p.orientation, p.duration_ms, p.codec, p.thumb_status";
-    let query = if match_all {
-        // AND logic: photo must have ALL specified tags
-        format!(
-            "SELECT {} FROM photos p \
-             JOIN photo_tags pt ON p.path = pt.photo_path \
-             WHERE pt.tag_id IN ({}) AND p.archived_at IS NULL \
-             GROUP BY p.path \
-             HAVING COUNT(DISTINCT pt.tag_id) = ? \
-             ORDER BY p.date_taken DESC",
-            photo_cols, placeholder_str
-        )
-    } else {
-        // OR logic: photo must have ANY of the specified tags
-        format!(
-            "SELECT DISTINCT {} FROM photos p \
-             JOIN photo_tags pt ON p.path = pt.photo_path \
-             WHERE pt.tag_id IN ({}) AND p.archived_at IS NULL \
-             ORDER BY p.date_taken DESC",
-            photo_cols, placeholder_str
-        )
+/// The query behind a smart album. Unset fields match everything; every set
+/// field must hold.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct SmartAlbumFilter {
+    /// Inclusive capture-date bounds, unix seconds. Undated photos never match.
+    pub date_from: Option<i64>,
+    pub date_to: Option<i64>,
+    pub media_type: Option<MediaType>,
+    pub favorite: Option<bool>,
+    /// Photos must carry all of these tags
+    pub tag_ids: Vec<i64>,
+    /// Counts only tags the auto-tagger added, or only the user's
+    pub tag_source: Option<TagSource>,
+    /// Compared ignoring case, like the camera list groups them
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub min_rating: Option<u8>,
+    /// `none` matches photos without a label
+    pub color_label: Option<ColorLabel>,
+    pub has_location: Option<bool>,
+    pub screenshot: Option<bool>,
+}
+
+/// Who put a tag on a photo
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum TagSource {
+    Auto,
+    Manual,
+}
+
+/// photo_tags.source of the auto-tagger's memberships; the user's are NULL
+pub const TAG_SOURCE_AUTO: &str = "auto";
+
+/// Conditions requiring every tag in `tag_ids`. With a `source`, those
+/// memberships must be of that kind, and with no tags listed the photo
+/// needs at least one tag of it.
+fn push_tag_clauses(
+    tag_ids: &[i64],
+    source: Option<TagSource>,
+    clauses: &mut Vec<String>,
+    values: &mut Vec<rusqlite::types::Value>,
+) {
+    let source_sql = match source {
+        Some(TagSource::Auto) => " AND pt.source IS 'auto'",
+        Some(TagSource::Manual) => " AND pt.source IS NULL",
+        None => "",
     };
+    for tag_id in tag_ids {
+        clauses.push(format!(
+            "EXISTS (SELECT 1 FROM photo_tags pt WHERE pt.photo_id = photos.id AND pt.tag_id = ?{})",
+            source_sql
+        ));
+        values.push(rusqlite::types::Value::Integer(*tag_id));
+    }
+    if tag_ids.is_empty() && source.is_some() {
+        clauses.push(format!("EXISTS (SELECT 1 FROM photo_tags pt WHERE pt.photo_id = photos.id{})", source_sql));
+    }
+}
 
-    let mut stmt = conn.prepare(&query)?;
+/// Condition for photos carrying `label`, or no label for ColorLabel::None
+fn push_color_label_clause(
+    label: Option<ColorLabel>,
+    clauses: &mut Vec<String>,
+    values: &mut Vec<rusqlite::types::Value>,
+) {
+    match label.map(ColorLabel::stored) {
+        Some(Some(stored)) => {
+            clauses.push("color_label = ?".to_string());
+            values.push(rusqlite::types::Value::Text(stored.to_string()));
+        }
+        Some(None) => clauses.push("color_label IS NULL".to_string()),
+        None => {}
+    }
+}
 
-    // Build params dynamically
-    let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = tag_ids.iter()
-        .map(|id| Box::new(*id) as Box<dyn rusqlite::ToSql>)
-        .collect();
+/// SQL that is true for a photo no date was found for
+/// (`media::DATE_SOURCE_UNKNOWN`); never NULL, so it sorts and compares.
+fn undated_sql() -> String {
+    format!("(date_source IS '{}')", media::DATE_SOURCE_UNKNOWN)
+}
 
-    if match_all {
-        params_vec.push(Box::new(tag_ids.len() as i64));
+/// SQL that is true for a video's row, by `file_format` as media::is_video
+/// decides for files.
+fn is_video_sql() -> String {
+    let formats: Vec<String> = media::VIDEO_FORMATS.iter().map(|format| format!("'{}'", format)).collect();
+    format!("COALESCE(file_format, '') IN ({})", formats.join(", "))
+}
+
+/// WHERE clause and its bound values for `filter`. Values are always bound,
+/// never spliced into the SQL; the clause text only depends on which fields
+/// are set.
+fn smart_filter_sql(filter: &SmartAlbumFilter) -> (String, Vec<rusqlite::types::Value>) {
+    use rusqlite::types::Value;
+
+    let mut clauses = vec![
+        "archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0".to_string(),
+        SHOWN_IN_GRID.to_string(),
+    ];
+    let mut values: Vec<Value> = Vec::new();
+
+    if filter.date_from.is_some() || filter.date_to.is_some() {
+        clauses.push(format!("NOT {}", undated_sql()));
+    }
+    if let Some(from) = filter.date_from {
+        clauses.push("date_taken >= ?".to_string());
+        values.push(Value::Integer(from));
+    }
+    if let Some(to) = filter.date_to {
+        clauses.push("date_taken <= ?".to_string());
+        values.push(Value::Integer(to));
+    }
+    match filter.media_type {
+        Some(MediaType::Video) => clauses.push(is_video_sql()),
+        Some(MediaType::Photo) => clauses.push(format!("NOT {}", is_video_sql())),
+        None => {}
+    }
+    if let Some(favorite) = filter.favorite {
+        clauses.push("COALESCE(is_favorite, 0) = ?".to_string());
+        values.push(Value::Integer(favorite as i64));
+    }
+    push_tag_clauses(&filter.tag_ids, filter.tag_source, &mut clauses, &mut values);
+    if let Some(make) = &filter.camera_make {
+        clauses.push("camera_make = ? COLLATE NOCASE".to_string());
+        values.push(Value::Text(make.clone()));
+    }
+    if let Some(model) = &filter.camera_model {
+        clauses.push("camera_model = ? COLLATE NOCASE".to_string());
+        values.push(Value::Text(model.clone()));
+    }
+    if let Some(min_rating) = filter.min_rating {
+        clauses.push("COALESCE(rating, 0) >= ?".to_string());
+        values.push(Value::Integer(min_rating as i64));
+    }
+    push_color_label_clause(filter.color_label, &mut clauses, &mut values);
+    match filter.has_location {
+        Some(true) => clauses.push("latitude IS NOT NULL AND longitude IS NOT NULL".to_string()),
+        Some(false) => clauses.push("(latitude IS NULL OR longitude IS NULL)".to_string()),
+        None => {}
+    }
+    if let Some(screenshot) = filter.screenshot {
+        clauses.push("COALESCE(is_screenshot, 0) = ?".to_string());
+        values.push(Value::Integer(screenshot as i64));
     }
 
-    let rows = stmt.query_map(rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())), photo_from_row)?;
-    rows.collect()
+    (one_per_stack(&clauses.join(" AND ")), bind_twice(values))
 }
 
-/// Search tags by name (for autocomplete)
-pub fn search_tags(conn: &Connection, query: &str) -> SqlResult<Vec<Tag>> {
-    let search_term = format!("%{}%", query);
-    let mut stmt = conn.prepare(
-        "SELECT t.id, t.name, t.color, COUNT(pt.photo_path) as count
-         FROM tags t
-         LEFT JOIN photo_tags pt ON t.id = pt.tag_id
-         WHERE t.name LIKE ?1
-         GROUP BY t.id
-         ORDER BY count DESC, t.name ASC
-         LIMIT 10"
-    )?;
-    let rows = stmt.query_map(params![search_term], |row| Ok(Tag {
-        id: row.get(0)?,
-        name: row.get(1)?,
-        color: row.get(2)?,
-        count: row.get(3)?,
-    }))?;
-    rows.collect()
+fn filter_to_json(filter: &SmartAlbumFilter) -> SqlResult<String> {
+    serde_json::to_string(filter).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))
 }
 
-// ============================================================================
-// Smart Collections Functions
-// ============================================================================
+fn filter_from_json(raw: &str, column: usize) -> SqlResult<SmartAlbumFilter> {
+    serde_json::from_str(raw)
+        .map_err(|e| rusqlite::Error::FromSqlConversionFailure(column, rusqlite::types::Type::Text, Box::new(e)))
+}
 
 #[derive(serde::Serialize)]
-pub struct SmartCollection {
-    pub id: String,
+pub struct SmartAlbum {
+    pub id: i64,
     pub name: String,
-    pub icon: String,
+    pub filter: SmartAlbumFilter,
+    /// Newest matching photo
+    pub cover_photo_path: Option<String>,
     pub count: i64,
-    pub category: String,
+    /// Bytes on disk of the matching photos, as far as sizes are known
+    pub size_bytes: i64,
 }
 
-/// Get all smart collections with counts
-pub fn get_smart_collections(conn: &Connection) -> SqlResult<Vec<SmartCollection>> {
-    let mut collections = Vec::new();
+/// Create a smart album
+pub fn create_smart_album(conn: &Connection, name: &str, filter: &SmartAlbumFilter) -> SqlResult<i64> {
+    conn.execute(
+        "INSERT INTO smart_albums (name, filter, created_at) VALUES (?1, ?2, ?3)",
+        params![name, filter_to_json(filter)?, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
 
-    // Size-based collections
-    let large_count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE file_size > 5242880 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "size_large".to_string(),
-        name: "Large (>5MB)".to_string(),
-        icon: "hard-drive".to_string(),
-        count: large_count,
-        category: "size".to_string(),
-    });
+/// Rename a smart album and replace its filter. False if there is no such album.
+pub fn update_smart_album(conn: &Connection, id: i64, name: &str, filter: &SmartAlbumFilter) -> SqlResult<bool> {
+    let changed = conn.execute(
+        "UPDATE smart_albums SET name = ?1, filter = ?2 WHERE id = ?3",
+        params![name, filter_to_json(filter)?, id],
+    )?;
+    Ok(changed > 0)
+}
 
-    let medium_count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE file_size BETWEEN 1048576 AND 5242880 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "size_medium".to_string(),
-        name: "Medium (1-5MB)".to_string(),
-        icon: "hard-drive".to_string(),
-        count: medium_count,
-        category: "size".to_string(),
-    });
+/// Delete a smart album. Its photos are untouched.
+pub fn delete_smart_album(conn: &Connection, id: i64) -> SqlResult<()> {
+    conn.execute("DELETE FROM smart_albums WHERE id = ?1", params![id])?;
+    Ok(())
+}
 
-    let small_count: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE file_size < 1048576 AND file_size > 0 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "size_small".to_string(),
-        name: "Small (<1MB)".to_string(),
-        icon: "hard-drive".to_string(),
-        count: small_count,
-        category: "size".to_string(),
-    });
+/// Get all smart albums with their current counts, newest first
+pub fn get_smart_albums(conn: &Connection) -> SqlResult<Vec<SmartAlbum>> {
+    let mut stmt = conn.prepare("SELECT id, name, filter FROM smart_albums ORDER BY created_at DESC, id DESC")?;
+    let saved = stmt
+        .query_map([], |row| {
+            let raw: String = row.get(2)?;
+            Ok((row.get::<_, i64>(0)?, row.get::<_, String>(1)?, filter_from_json(&raw, 2)?))
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+
+    saved
+        .into_iter()
+        .map(|(id, name, filter)| {
+            let (clause, values) = smart_filter_sql(&filter);
+            let (count, size_bytes) = conn.query_row(
+                &format!("SELECT COUNT(*), COALESCE(SUM(file_size), 0) FROM photos WHERE {}", clause),
+                rusqlite::params_from_iter(values.iter()),
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            let cover_photo_path = conn
+                .query_row(
+                    &format!("SELECT path FROM photos WHERE {} ORDER BY date_taken DESC LIMIT 1", clause),
+                    rusqlite::params_from_iter(values.iter()),
+                    |row| row.get(0),
+                )
+                .optional()?;
+            Ok(SmartAlbum { id, name, filter, cover_photo_path, count, size_bytes })
+        })
+        .collect()
+}
 
-    // Dimension-based collections
-    let dim_4k: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE (width >= 3840 OR height >= 2160) AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "dim_4k".to_string(),
-        name: "4K+".to_string(),
-        icon: "monitor".to_string(),
-        count: dim_4k,
-        category: "dimension".to_string(),
-    });
+/// Filter of a smart album, or None if there is no such album
+pub fn get_smart_album_filter(conn: &Connection, id: i64) -> SqlResult<Option<SmartAlbumFilter>> {
+    let raw: Option<String> = conn
+        .query_row("SELECT filter FROM smart_albums WHERE id = ?1", params![id], |row| row.get(0))
+        .optional()?;
+    raw.map(|raw| filter_from_json(&raw, 0)).transpose()
+}
 
-    let dim_hd: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE (width >= 1920 OR height >= 1080) AND width < 3840 AND height < 2160 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "dim_hd".to_string(),
-        name: "HD".to_string(),
-        icon: "monitor".to_string(),
-        count: dim_hd,
-        category: "dimension".to_string(),
-    });
+/// Photos currently matching `filter`, newest first
+pub fn get_photos_matching(conn: &Connection, filter: &SmartAlbumFilter) -> SqlResult<Vec<PhotoMetadata>> {
+    let (clause, values) = smart_filter_sql(filter);
+    let query = format!("SELECT {} FROM photos WHERE {} ORDER BY date_taken DESC", PHOTO_COLUMNS, clause);
+    let mut stmt = conn.prepare(&query)?;
+    query_photos(&mut stmt, rusqlite::params_from_iter(values.iter()))
+}
 
-    let portrait: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE height > width AND width > 0 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "dim_portrait".to_string(),
-        name: "Portrait".to_string(),
-        icon: "smartphone".to_string(),
-        count: portrait,
-        category: "dimension".to_string(),
-    });
+/// Get all photos in a smart album; empty if there is no such album
+pub fn get_smart_album_photos(conn: &Connection, id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    match get_smart_album_filter(conn, id)? {
+        Some(filter) => get_photos_matching(conn, &filter),
+        None => Ok(Vec::new()),
+    }
+}
 
-    let landscape: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE width > height AND height > 0 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "dim_landscape".to_string(),
-        name: "Landscape".to_string(),
-        icon: "monitor".to_string(),
-        count: landscape,
-        category: "dimension".to_string(),
-    });
+/// Figures for an album's caption ("412 photos · 8.1 GB · Mar 3–Mar 18"),
+/// over the photos its view shows. Kept out of get_albums so the list
+/// stays one cheap query; fetch them per album as they're displayed.
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct AlbumStats {
+    pub count: i64,
+    /// As far as sizes are known
+    pub size_bytes: i64,
+    pub video_count: i64,
+    pub favorite_count: i64,
+    /// Range of the dated photos; None when none are dated
+    pub earliest_date: Option<i64>,
+    pub latest_date: Option<i64>,
+}
 
-    // Time-based collections
-    let now = chrono::Utc::now().timestamp();
-    let seven_days_ago = now - (7 * 24 * 60 * 60);
-    let thirty_days_ago = now - (30 * 24 * 60 * 60);
+/// AlbumStats over the photos that `condition` selects.
+fn album_stats_where(conn: &Connection, condition: &str, values: &[rusqlite::types::Value]) -> SqlResult<AlbumStats> {
+    let query = format!(
+        "SELECT COUNT(*),
+                COALESCE(SUM(file_size), 0),
+                COALESCE(SUM({}), 0),
+                COALESCE(SUM(is_favorite = 1), 0),
+                MIN(CASE WHEN date_source IS NOT '{unknown}' THEN date_taken END),
+                MAX(CASE WHEN date_source IS NOT '{unknown}' THEN date_taken END)
+         FROM photos WHERE {}",
+        is_video_sql(),
+        condition,
+        unknown = media::DATE_SOURCE_UNKNOWN
+    );
+    conn.query_row(&query, rusqlite::params_from_iter(values.iter()), |row| Ok(AlbumStats {
+        count: row.get(0)?,
+        size_bytes: row.get(1)?,
+        video_count: row.get(2)?,
+        favorite_count: row.get(3)?,
+        earliest_date: row.get(4)?,
+        latest_date: row.get(5)?,
+    }))
+}
 
-    let last_7_days: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE date_taken > ?1 AND archived_at IS NULL",
-        params![seven_days_ago],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "time_7days".to_string(),
-        name: "Last 7 Days".to_string(),
-        icon: "calendar".to_string(),
-        count: last_7_days,
-        category: "time".to_string(),
-    });
+/// Stats of the photos get_album_photos returns, or None if there is no such album
+pub fn get_album_stats(conn: &Connection, album_id: i64) -> SqlResult<Option<AlbumStats>> {
+    if get_album_name(conn, album_id)?.is_none() {
+        return Ok(None);
+    }
+    album_stats_where(
+        conn,
+        "id IN (SELECT photo_id FROM album_photos WHERE album_id = ?1)
+         AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0",
+        &[rusqlite::types::Value::Integer(album_id)],
+    )
+    .map(Some)
+}
 
-    let last_30_days: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE date_taken > ?1 AND archived_at IS NULL",
-        params![thirty_days_ago],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "time_30days".to_string(),
-        name: "Last 30 Days".to_string(),
-        icon: "calendar".to_string(),
-        count: last_30_days,
-        category: "time".to_string(),
-    });
+/// Stats of the photos get_smart_album_photos returns, or None if there is no such album
+pub fn get_smart_album_stats(conn: &Connection, id: i64) -> SqlResult<Option<AlbumStats>> {
+    let Some(filter) = get_smart_album_filter(conn, id)? else {
+        return Ok(None);
+    };
+    let (clause, values) = smart_filter_sql(&filter);
+    album_stats_where(conn, &clause, &values).map(Some)
+}
 
-    let current_year = chrono::Utc::now().format("%Y").to_string();
-    let this_year: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE strftime('%Y', date_taken, 'unixepoch') = ?1 AND archived_at IS NULL",
-        params![current_year],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "time_year".to_string(),
-        name: "This Year".to_string(),
-        icon: "calendar".to_string(),
-        count: this_year,
-        category: "time".to_string(),
-    });
+/// Get all photos that have duplicates (same content_hash)
+pub fn get_duplicates(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos \
+         WHERE deleted_at IS NULL AND content_hash IN ( \
+             SELECT content_hash FROM photos WHERE deleted_at IS NULL \
+             GROUP BY content_hash HAVING COUNT(*) > 1 \
+         ) \
+         ORDER BY content_hash, date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_from_row)?;
+    rows.collect()
+}
 
-    // Status-based collections
-    let unreviewed: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL",
+/// Search photos by text (name or location)
+pub fn search_photos(conn: &Connection, query: &str) -> SqlResult<Vec<PhotoMetadata>> {
+    let Some(fts_query) = build_fts_query(query) else {
+        return Ok(Vec::new());
+    };
+    let sql = format!(
+        "SELECT {} FROM photos_fts f
+         JOIN photos p ON p.id = f.rowid
+         WHERE photos_fts MATCH ?1 AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         ORDER BY p.date_taken DESC",
+        photo_columns_as("p")
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(params![fts_query], photo_from_row)?;
+    rows.collect()
+}
+
+// ============================================================================
+// Full-text Search Index
+// ============================================================================
+
+/// Builds one photos_fts row (rowid, name, description, location_name, tags)
+/// per photo; callers append a WHERE clause on `p`.
+const FTS_ROW_SELECT: &str =
+    "SELECT p.id, p.name, p.description, p.location_name,
+            (SELECT group_concat(t.name, ' ') FROM photo_tags pt
+             JOIN tags t ON t.id = pt.tag_id
+             WHERE pt.photo_id = p.id)
+     FROM photos p";
+
+/// Create the FTS5 index. It is filled, and kept in step by triggers, once
+/// photo_tags is keyed by photo id (see create_search_triggers).
+fn init_search_index(conn: &Connection) -> SqlResult<()> {
+    conn.execute(
+        "CREATE VIRTUAL TABLE IF NOT EXISTS photos_fts USING fts5(
+             name, description, location_name, tags,
+             tokenize = 'unicode61 remove_diacritics 2'
+         )",
         [],
-        |row| row.get(0),
-    ).unwrap_or(0);
-    collections.push(SmartCollection {
-        id: "status_unreviewed".to_string(),
-        name: "Unreviewed".to_string(),
-        icon: "eye-off".to_string(),
-        count: unreviewed,
-        category: "status".to_string(),
-    });
+    )?;
+    Ok(())
+}
 
-    Ok(collections)
+/// Create the triggers that keep the search index in step with photos,
+/// photo_tags and tags, and fill it from scratch; `rebuild_search_index` is
+/// there if it ever drifts.
+fn create_search_triggers(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(&format!(
+        "CREATE TRIGGER IF NOT EXISTS photos_fts_insert AFTER INSERT ON photos BEGIN
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id = new.id;
+         END;
+         CREATE TRIGGER IF NOT EXISTS photos_fts_update
+         AFTER UPDATE OF path, name, description, location_name ON photos BEGIN
+             DELETE FROM photos_fts WHERE rowid = old.id;
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id = new.id;
+         END;
+         CREATE TRIGGER IF NOT EXISTS photos_fts_delete AFTER DELETE ON photos BEGIN
+             DELETE FROM photos_fts WHERE rowid = old.id;
+         END;
+
+         CREATE TRIGGER IF NOT EXISTS photo_tags_fts_insert AFTER INSERT ON photo_tags BEGIN
+             DELETE FROM photos_fts WHERE rowid = new.photo_id;
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id = new.photo_id;
+         END;
+         CREATE TRIGGER IF NOT EXISTS photo_tags_fts_update AFTER UPDATE ON photo_tags BEGIN
+             DELETE FROM photos_fts WHERE rowid IN (old.photo_id, new.photo_id);
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id IN (old.photo_id, new.photo_id);
+         END;
+         CREATE TRIGGER IF NOT EXISTS photo_tags_fts_delete AFTER DELETE ON photo_tags BEGIN
+             DELETE FROM photos_fts WHERE rowid = old.photo_id;
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id = old.photo_id;
+         END;
+
+         CREATE TRIGGER IF NOT EXISTS tags_fts_rename AFTER UPDATE OF name ON tags BEGIN
+             DELETE FROM photos_fts WHERE rowid IN (SELECT photo_id FROM photo_tags WHERE tag_id = new.id);
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id IN (SELECT photo_id FROM photo_tags WHERE tag_id = new.id);
+         END;
+         CREATE TRIGGER IF NOT EXISTS tags_fts_delete AFTER DELETE ON tags BEGIN
+             DELETE FROM photos_fts WHERE rowid IN (SELECT photo_id FROM photo_tags WHERE tag_id = old.id);
+             INSERT INTO photos_fts (rowid, name, description, location_name, tags)
+             {select} WHERE p.id IN (SELECT photo_id FROM photo_tags WHERE tag_id = old.id);
+         END;",
+        select = FTS_ROW_SELECT
+    ))?;
+    fill_search_index(conn).map(drop)
 }
 
-/// Get photos for a specific smart collection
-pub fn get_smart_collection_photos(conn: &Connection, collection_id: &str) -> SqlResult<Vec<PhotoMetadata>> {
-    let now = chrono::Utc::now().timestamp();
-    let seven_days_ago = now - (7 * 24 * 60 * 60);
-    let thirty_days_ago = now - (30 * 24 * 60 * 60);
-    let current_year = chrono::Utc::now().format("%Y").to_string();
-    let cols = PHOTO_COLUMNS;
+fn fill_search_index(conn: &Connection) -> SqlResult<usize> {
+    conn.execute("DELETE FROM photos_fts", [])?;
+    conn.execute(
+        &format!("INSERT INTO photos_fts (rowid, name, description, location_name, tags) {}", FTS_ROW_SELECT),
+        [],
+    )
+}
 
-    // Time-based queries require bound parameters; handle before the static match.
-    if collection_id == "time_7days" {
-        let sql = format!("SELECT {} FROM photos WHERE date_taken > ?1 AND archived_at IS NULL ORDER BY date_taken DESC", cols);
-        let mut stmt = conn.prepare(&sql)?;
-        return query_photos(&mut stmt, params![seven_days_ago]);
-    } else if collection_id == "time_30days" {
-        let sql = format!("SELECT {} FROM photos WHERE date_taken > ?1 AND archived_at IS NULL ORDER BY date_taken DESC", cols);
-        let mut stmt = conn.prepare(&sql)?;
-        return query_photos(&mut stmt, params![thirty_days_ago]);
-    } else if collection_id == "time_year" {
-        let sql = format!("SELECT {} FROM photos WHERE strftime('%Y', date_taken, 'unixepoch') = ?1 AND archived_at IS NULL ORDER BY date_taken DESC", cols);
-        let mut stmt = conn.prepare(&sql)?;
-        return query_photos(&mut stmt, params![current_year]);
+/// Repopulate the search index from scratch. Returns the number of photos indexed.
+pub fn rebuild_search_index(conn: &Connection) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let indexed = fill_search_index(&tx)?;
+    tx.commit()?;
+    Ok(indexed)
+}
+
+/// Turn user input into an FTS5 query. Each whitespace-separated term must
+/// match (terms are ANDed); a trailing `*` makes a term a prefix match.
+/// Terms are quoted so punctuation in them is never parsed as FTS syntax.
+fn build_fts_query(input: &str) -> Option<String> {
+    let terms: Vec<String> = input
+        .split_whitespace()
+        .filter_map(|raw| {
+            let prefix = raw.ends_with('*');
+            let term: String = raw.trim_end_matches('*').chars().filter(|c| *c != '"').collect();
+            if term.is_empty() {
+                return None;
+            }
+            Some(if prefix { format!("\"{}\"*", term) } else { format!("\"{}\"", term) })
+        })
+        .collect();
+
+    if terms.is_empty() {
+        None
+    } else {
+        Some(terms.join(" "))
     }
+}
 
-    let where_clause = match collection_id {
-        "size_large"       => "file_size > 5242880 AND archived_at IS NULL ORDER BY file_size DESC",
-        "size_medium"      => "file_size BETWEEN 1048576 AND 5242880 AND archived_at IS NULL ORDER BY file_size DESC",
-        "size_small"       => "file_size < 1048576 AND file_size > 0 AND archived_at IS NULL ORDER BY file_size DESC",
-        "dim_4k"           => "(width >= 3840 OR height >= 2160) AND archived_at IS NULL ORDER BY date_taken DESC",
-        "dim_hd"           => "(width >= 1920 OR height >= 1080) AND width < 3840 AND height < 2160 AND archived_at IS NULL ORDER BY date_taken DESC",
-        "dim_portrait"     => "height > width AND width > 0 AND archived_at IS NULL ORDER BY date_taken DESC",
-        "dim_landscape"    => "width > height AND height > 0 AND archived_at IS NULL ORDER BY date_taken DESC",
-        "status_unreviewed" => "reviewed_at IS NULL AND archived_at IS NULL ORDER BY date_taken DESC",
-        _ => return Ok(Vec::new()),
-    };
+/// Get all unique locations with photo counts
+pub fn get_locations(conn: &Connection) -> SqlResult<Vec<(String, i64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT location_name, COUNT(*) as count
+         FROM photos
+         WHERE location_name IS NOT NULL AND deleted_at IS NULL
+         GROUP BY location_name
+         ORDER BY count DESC"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
 
-    let sql = format!("SELECT {} FROM photos WHERE {}", cols, where_clause);
-    let mut stmt = conn.prepare(&sql)?;
-    query_photos(&mut stmt, [])
+/// Get all non-archived photos that carry GPS coordinates (for the map view)
+pub fn get_photos_with_location(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos \
+         WHERE latitude IS NOT NULL AND longitude IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL \
+         ORDER BY date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_from_row)?;
+    rows.collect()
 }
 
-fn query_photos<P: rusqlite::Params>(stmt: &mut rusqlite::Statement, params: P) -> SqlResult<Vec<PhotoMetadata>> {
-    stmt.query_map(params, photo_from_row)?.collect()
+/// A map pin: photos in one grid cell, or a single photo at high zoom
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct MapCluster {
+    /// Mean position of the cell's photos, or the photo's own
+    pub latitude: f64,
+    pub longitude: f64,
+    pub count: i64,
+    /// The most recent photo in the cell, to show on the pin
+    pub photo_path: String,
 }
 
-// ============================================================================
-// Storage Analytics Functions
-// ============================================================================
+/// From this zoom on, get_map_clusters returns photos rather than cells
+pub const MAP_INDIVIDUAL_ZOOM: u8 = 17;
 
-#[derive(serde::Serialize)]
-pub struct StorageAnalytics {
-    pub total_size_bytes: i64,
-    pub total_photos: i64,
-    pub total_videos: i64,
-    pub total_screenshots: i64,
-    pub photos_size: i64,
-    pub videos_size: i64,
-    pub screenshots_size: i64,
-    pub duplicate_space_bytes: i64,
-    pub size_by_month: Vec<MonthSize>,
-    pub size_by_year: Vec<YearSize>,
-    pub top_largest_files: Vec<LargeFile>,
+/// Cells per 256px map tile side, i.e. a cell is about 64px on screen
+const MAP_CELLS_PER_TILE: f64 = 4.0;
+
+/// Size in degrees of a map grid cell at `zoom`: a web map tile spans
+/// 360 / 2^zoom degrees of longitude.
+pub fn map_cell_degrees(zoom: u8) -> f64 {
+    360.0 / f64::from(1u32 << zoom.min(MAP_INDIVIDUAL_ZOOM)) / MAP_CELLS_PER_TILE
 }
 
-#[derive(serde::Serialize)]
-pub struct MonthSize {
-    pub month: String,
-    pub size: i64,
-    pub count: i64,
+/// Pins for visible geotagged photos inside the box. `min_lon > max_lon`
+/// means the box crosses the antimeridian (from 170 east to 170 west, say).
+/// Photos are bucketed by integer cell indices computed in SQL; cells are
+/// anchored at (-90, -180) so they never straddle the antimeridian. At
+/// MAP_INDIVIDUAL_ZOOM and beyond each photo is its own pin, up to `limit`.
+pub fn get_map_clusters(
+    conn: &Connection,
+    (min_lat, max_lat): (f64, f64),
+    (min_lon, max_lon): (f64, f64),
+    zoom: u8,
+    limit: i64,
+) -> SqlResult<Vec<MapCluster>> {
+    let longitude_test = if min_lon <= max_lon {
+        "longitude BETWEEN ?3 AND ?4"
+    } else {
+        "(longitude >= ?3 OR longitude <= ?4)"
+    };
+    let within = format!(
+        "latitude IS NOT NULL AND longitude IS NOT NULL AND latitude BETWEEN ?1 AND ?2 AND {} \
+         AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0",
+        longitude_test
+    );
+    let row_to_cluster = |row: &rusqlite::Row| {
+        Ok(MapCluster { latitude: row.get(0)?, longitude: row.get(1)?, count: row.get(2)?, photo_path: row.get(3)? })
+    };
+    if zoom >= MAP_INDIVIDUAL_ZOOM {
+        let mut stmt = conn.prepare(&format!(
+            "SELECT latitude, longitude, 1, path FROM photos WHERE {} ORDER BY date_taken DESC, id DESC LIMIT ?5",
+            within
+        ))?;
+        let rows = stmt.query_map(params![min_lat, max_lat, min_lon, max_lon, limit], row_to_cluster)?;
+        return rows.collect();
+    }
+    // Offsets keep both operands non-negative, so CAST truncation is floor().
+    // With MAX(), SQLite takes the bare `path` from the row holding the maximum.
+    let mut stmt = conn.prepare(&format!(
+        "SELECT AVG(latitude), AVG(longitude), COUNT(*), path, MAX(date_taken)
+         FROM photos WHERE {}
+         GROUP BY CAST((latitude + 90.0) / ?5 AS INTEGER), CAST((longitude + 180.0) / ?5 AS INTEGER)
+         ORDER BY COUNT(*) DESC",
+        within
+    ))?;
+    let rows = stmt.query_map(params![min_lat, max_lat, min_lon, max_lon, map_cell_degrees(zoom)], row_to_cluster)?;
+    rows.collect()
 }
 
-#[derive(serde::Serialize)]
-pub struct YearSize {
-    pub year: String,
-    pub size: i64,
+/// A city photos were taken in, for browsing by place
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct PlaceSummary {
+    pub city: String,
+    pub region: String,
+    pub country: String,
     pub count: i64,
+    /// The place's most recent photo
+    pub cover_photo_path: String,
 }
 
-#[derive(serde::Serialize)]
-pub struct LargeFile {
-    pub path: String,
-    pub name: String,
-    pub size: i64,
+/// (id, latitude, longitude) of photos with coordinates but no place yet,
+/// or placed before regions were recorded
+pub fn get_photos_to_geocode(conn: &Connection) -> SqlResult<Vec<(i64, f64, f64)>> {
+    let mut stmt = conn.prepare(
+        "SELECT id, latitude, longitude FROM photos
+         WHERE latitude IS NOT NULL AND longitude IS NOT NULL AND (place_country IS NULL OR place_region IS NULL)
+           AND deleted_at IS NULL
+         ORDER BY id",
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// Store the places of many photos, by id, in one transaction
+pub fn set_photo_places_batch(conn: &Connection, places: &[(i64, Place)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt =
+            tx.prepare_cached("UPDATE photos SET place_city = ?2, place_region = ?3, place_country = ?4 WHERE id = ?1")?;
+        for (id, place) in places {
+            stmt.execute(params![id, place.city, place.region, place.country])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Set the coordinates of photos by path, with the location name geocoded
+/// for them, marking where they came from so rescans keep them. The place
+/// names are cleared for the geocode job to fill again. Returns how many
+/// photos were updated.
+pub fn set_photo_locations_batch(
+    conn: &Connection,
+    locations: &[(String, f64, f64, Option<String>)],
+    source: &str,
+) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt = tx.prepare_cached(&format!(
+            "UPDATE photos SET latitude = ?2, longitude = ?3, location_name = ?5, location_source = ?4,
+                 place_city = NULL, place_region = NULL, place_country = NULL
+             WHERE path = ?1 COLLATE {} AND deleted_at IS NULL",
+            PATH_COLLATION
+        ))?;
+        for (path, latitude, longitude, location_name) in locations {
+            updated += stmt.execute(params![path, latitude, longitude, source, location_name])?;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// Every city with visible photos, most photographed first
+pub fn get_places(conn: &Connection) -> SqlResult<Vec<PlaceSummary>> {
+    // With MAX(), SQLite takes the bare `path` from the row holding the maximum
+    let mut stmt = conn.prepare(
+        "SELECT place_city, COALESCE(place_region, ''), place_country, COUNT(*), path, MAX(date_taken)
+         FROM photos
+         WHERE place_country IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0
+         GROUP BY place_country, COALESCE(place_region, ''), place_city
+         ORDER BY COUNT(*) DESC, place_country, COALESCE(place_region, ''), place_city",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        Ok(PlaceSummary {
+            city: row.get(0)?,
+            region: row.get(1)?,
+            country: row.get(2)?,
+            count: row.get(3)?,
+            cover_photo_path: row.get(4)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Visible photos taken in `country`, or only in `region` or `city` there,
+/// newest first
+pub fn get_photos_by_place(
+    conn: &Connection,
+    country: &str,
+    region: Option<&str>,
+    city: Option<&str>,
+) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos \
+         WHERE place_country = ?1 AND (?2 IS NULL OR place_city = ?2) AND (?3 IS NULL OR place_region = ?3) \
+         AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0 \
+         ORDER BY date_taken DESC, id DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![country, city, region], photo_from_row)?;
+    rows.collect()
+}
+
+#[derive(serde::Serialize)]
+pub struct Camera {
+    pub make: Option<String>,
+    pub model: Option<String>,
+    pub count: i64,
+}
+
+/// Get distinct camera make/model pairs with photo counts
+pub fn get_cameras(conn: &Connection) -> SqlResult<Vec<Camera>> {
+    let mut stmt = conn.prepare(
+        "SELECT camera_make, camera_model, COUNT(*) as count
+         FROM photos
+         WHERE (camera_make IS NOT NULL OR camera_model IS NOT NULL) AND archived_at IS NULL AND deleted_at IS NULL
+         GROUP BY camera_make, camera_model
+         ORDER BY count DESC"
+    )?;
+    let rows = stmt.query_map([], |row| Ok(Camera {
+        make: row.get(0)?,
+        model: row.get(1)?,
+        count: row.get(2)?,
+    }))?;
+    rows.collect()
+}
+
+/// Get photos taken with a given camera. A None model matches every model from that make.
+pub fn get_photos_by_camera(conn: &Connection, make: &str, model: Option<&str>) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos \
+         WHERE camera_make = ?1 AND (?2 IS NULL OR camera_model = ?2) AND archived_at IS NULL AND deleted_at IS NULL \
+         ORDER BY date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![make, model], photo_from_row)?;
+    rows.collect()
+}
+
+/// Update camera make/model for a photo. Absent values keep whatever is stored.
+pub fn update_camera_info(conn: &Connection, path: &str, make: Option<&str>, model: Option<&str>) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET camera_make = COALESCE(?1, camera_make), camera_model = COALESCE(?2, camera_model) WHERE path = ?3",
+        params![make, model, path],
+    )?;
+    Ok(())
+}
+
+/// Store container metadata read from a video. A missing duration or
+/// creation time keeps whatever is stored.
+pub fn update_video_info(
+    conn: &Connection,
+    path: &str,
+    width: u32,
+    height: u32,
+    duration_ms: Option<i64>,
+    created_at: Option<i64>,
+) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET width = ?1, height = ?2,
+             duration_ms = COALESCE(?3, duration_ms), date_taken = COALESCE(?4, date_taken)
+         WHERE path = ?5",
+        params![width, height, duration_ms, created_at, path],
+    )?;
+    Ok(())
+}
+
+/// Paths of rows with no dimensions or duration yet: candidates for
+/// video metadata re-extraction. Callers filter to video files.
+pub fn get_paths_missing_dimensions_or_duration(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos WHERE (width = 0 OR duration_ms IS NULL) AND deleted_at IS NULL",
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Get paths of every non-archived photo (for metadata re-extraction)
+pub fn get_all_photo_paths(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT path FROM photos WHERE archived_at IS NULL AND deleted_at IS NULL")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// The library photo with this content hash, if any. Live photos win over
+/// archived ones, then the oldest import.
+pub fn find_photo_by_hash(conn: &Connection, hash: &str) -> SqlResult<Option<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos WHERE content_hash = ?1 AND deleted_at IS NULL
+         ORDER BY archived_at IS NOT NULL, id LIMIT 1",
+        PHOTO_COLUMNS
+    );
+    conn.query_row(&query, params![hash], photo_from_row).optional()
+}
+
+// ============================================================================
+// Incremental Rescan Functions
+// ============================================================================
+
+/// Get (file_size, file_mtime) for every photo stored under a directory prefix.
+/// Keys are stored paths; values are None for rows scanned before stats were recorded.
+pub fn get_file_stats_under(conn: &Connection, dir_prefix: &str) -> SqlResult<HashMap<String, (Option<i64>, Option<i64>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, file_size, file_mtime FROM photos WHERE substr(path, 1, length(?1)) = ?1 AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map(params![dir_prefix], |row| {
+        Ok((row.get::<_, String>(0)?, (row.get(1)?, row.get(2)?)))
+    })?;
+    rows.collect()
+}
+
+/// (file_size, file_mtime) for one stored path, or None if it isn't in the library
+pub fn get_file_stats(conn: &Connection, path: &str) -> SqlResult<Option<(Option<i64>, Option<i64>)>> {
+    conn.query_row(
+        &format!("SELECT file_size, file_mtime FROM photos WHERE path = ?1 COLLATE {} AND deleted_at IS NULL", PATH_COLLATION),
+        params![path],
+        |row| Ok((row.get(0)?, row.get(1)?)),
+    )
+    .optional()
+}
+
+/// Record file size and modified time so the next rescan can skip an unchanged file
+pub fn update_file_stats(conn: &Connection, path: &str, size: i64, mtime: i64) -> SqlResult<()> {
+    conn.execute(
+        &format!("UPDATE photos SET file_size = ?1, file_mtime = ?2 WHERE path = ?3 COLLATE {}", PATH_COLLATION),
+        params![size, mtime, path],
+    )?;
+    Ok(())
+}
+
+/// Record where each (library path, original path) pair was imported from
+pub fn set_original_paths(conn: &Connection, pairs: &[(String, String)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET original_path = ?1 WHERE path = ?2")?;
+        for (path, original) in pairs {
+            stmt.execute(params![original, path])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Photos imported with no date at all, by name.
+pub fn get_undated_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {} FROM photos WHERE date_source = ?1 AND deleted_at IS NULL ORDER BY name COLLATE NOCASE, id",
+        PHOTO_COLUMNS
+    ))?;
+    let rows = stmt.query_map(params![media::DATE_SOURCE_UNKNOWN], photo_from_row)?;
+    rows.collect()
+}
+
+/// A photo's date after a manual edit, for the file changes that follow it.
+#[derive(Debug, PartialEq)]
+pub struct PhotoDate {
+    pub path: String,
     pub date_taken: i64,
+    pub tz_offset_minutes: Option<i32>,
 }
 
-/// Get comprehensive storage analytics
-pub fn get_storage_analytics(conn: &Connection) -> SqlResult<StorageAnalytics> {
-    // Total size
-    let total_size_bytes: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(file_size), 0) FROM photos WHERE archived_at IS NULL",
-        [],
+/// Set a photo's date by hand. Returns its new date, or None if the photo
+/// isn't in the library.
+pub fn set_photo_date(conn: &Connection, path: &str, timestamp: i64) -> SqlResult<Option<PhotoDate>> {
+    conn.query_row(
+        "UPDATE photos SET date_taken = ?1, date_source = ?2, date_confident = 1, filename_date_mismatch = NULL
+         WHERE path = ?3 AND deleted_at IS NULL
+         RETURNING path, date_taken, tz_offset_minutes",
+        params![timestamp, media::DATE_SOURCE_MANUAL, path],
+        photo_date_from_row,
+    )
+    .optional()
+}
+
+/// Move the dates of several photos by `delta_seconds`, e.g. to correct a
+/// camera clock that was hours off. Returns the new dates of those found.
+pub fn shift_photo_dates(conn: &Connection, paths: &[String], delta_seconds: i64) -> SqlResult<Vec<PhotoDate>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut shifted = Vec::with_capacity(paths.len());
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET date_taken = date_taken + ?1, date_source = ?2, date_confident = 1,
+                 filename_date_mismatch = NULL
+             WHERE path = ?3 AND deleted_at IS NULL
+             RETURNING path, date_taken, tz_offset_minutes",
+        )?;
+        for path in paths {
+            if let Some(date) = stmt
+                .query_row(params![delta_seconds, media::DATE_SOURCE_MANUAL, path], photo_date_from_row)
+                .optional()?
+            {
+                shifted.push(date);
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(shifted)
+}
+
+/// The photos at `paths` outside the trash, in the order given. Paths with
+/// no photo are skipped.
+pub fn get_photos_by_paths(conn: &Connection, paths: &[String]) -> SqlResult<Vec<PhotoMetadata>> {
+    let mut stmt =
+        conn.prepare_cached(&format!("SELECT {} FROM photos WHERE path = ?1 AND deleted_at IS NULL", PHOTO_COLUMNS))?;
+    let mut photos = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(photo) = stmt.query_row(params![path], photo_from_row).optional()? {
+            photos.push(photo);
+        }
+    }
+    Ok(photos)
+}
+
+/// Current date of each live photo among `paths`, in the order given.
+pub fn get_photo_dates(conn: &Connection, paths: &[String]) -> SqlResult<Vec<(String, i64)>> {
+    let mut stmt = conn.prepare_cached("SELECT date_taken FROM photos WHERE path = ?1 AND deleted_at IS NULL")?;
+    let mut dates = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(date) = stmt.query_row(params![path], |row| row.get(0)).optional()? {
+            dates.push((path.clone(), date));
+        }
+    }
+    Ok(dates)
+}
+
+/// Shift the dates of `paths` as `shift_photo_dates` does, logging each
+/// photo's previous date fields so `undo_date_shift` can put them back.
+/// Returns the shift's id and the new dates, or None if no photo matched.
+pub fn record_date_shift(
+    conn: &Connection,
+    paths: &[String],
+    delta_seconds: i64,
+) -> SqlResult<Option<(i64, Vec<PhotoDate>)>> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute(
+        "INSERT INTO date_shifts (delta_seconds, shifted_at) VALUES (?1, ?2)",
+        params![delta_seconds, chrono::Utc::now().timestamp()],
+    )?;
+    let shift_id = tx.last_insert_rowid();
+
+    let mut shifted = Vec::with_capacity(paths.len());
+    {
+        let mut log = tx.prepare_cached(
+            "INSERT INTO date_shift_photos
+                 (shift_id, photo_id, old_date_taken, old_date_source, old_date_confident, old_filename_date_mismatch, old_path)
+             SELECT ?1, id, date_taken, date_source, date_confident, filename_date_mismatch, path
+             FROM photos WHERE path = ?2 AND deleted_at IS NULL",
+        )?;
+        let mut shift = tx.prepare_cached(
+            "UPDATE photos SET date_taken = date_taken + ?1, date_source = ?2, date_confident = 1,
+                 filename_date_mismatch = NULL
+             WHERE path = ?3 AND deleted_at IS NULL
+             RETURNING path, date_taken, tz_offset_minutes",
+        )?;
+        for path in paths {
+            if log.execute(params![shift_id, path])? == 0 {
+                continue;
+            }
+            shifted.push(shift.query_row(params![delta_seconds, media::DATE_SOURCE_MANUAL, path], photo_date_from_row)?);
+        }
+    }
+    if shifted.is_empty() {
+        return Ok(None);
+    }
+    tx.commit()?;
+    Ok(Some((shift_id, shifted)))
+}
+
+/// Note that a shifted photo's file was refiled from `old_path` to `new_path`.
+pub fn record_date_shift_move(conn: &Connection, shift_id: i64, old_path: &str, new_path: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE date_shift_photos SET new_path = ?3 WHERE shift_id = ?1 AND old_path = ?2",
+        params![shift_id, old_path, new_path],
+    )?;
+    Ok(())
+}
+
+/// A file a date shift refiled that is still where the shift put it.
+#[derive(Debug, PartialEq)]
+pub struct DateShiftMove {
+    pub current_path: String,
+    pub old_path: String,
+}
+
+/// Files `shift_id` refiled that haven't moved since and whose date
+/// `undo_date_shift` will restore, for putting back. None if there is no
+/// such shift.
+pub fn get_date_shift_moves(conn: &Connection, shift_id: i64) -> SqlResult<Option<Vec<DateShiftMove>>> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM date_shifts WHERE id = ?1)",
+        params![shift_id],
         |row| row.get(0),
-    ).unwrap_or(0);
+    )?;
+    if !exists {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(
+        "SELECT p.path, s.old_path FROM date_shift_photos s
+         JOIN photos p ON p.id = s.photo_id
+         JOIN date_shifts d ON d.id = s.shift_id
+         WHERE s.shift_id = ?1 AND s.new_path = p.path AND p.date_taken = s.old_date_taken + d.delta_seconds",
+    )?;
+    let rows = stmt.query_map(params![shift_id], |row| {
+        Ok(DateShiftMove { current_path: row.get(0)?, old_path: row.get(1)? })
+    })?;
+    rows.collect::<SqlResult<Vec<_>>>().map(Some)
+}
+
+/// Give the photos of `shift_id` back their previous date fields and forget
+/// the shift. Dates changed again since are left alone. Moving files back
+/// is up to the caller. Returns how many dates were restored, or None if
+/// there was no such shift.
+pub fn undo_date_shift(conn: &Connection, shift_id: i64) -> SqlResult<Option<usize>> {
+    let tx = conn.unchecked_transaction()?;
+    let Some(delta_seconds) = tx
+        .query_row("SELECT delta_seconds FROM date_shifts WHERE id = ?1", params![shift_id], |row| {
+            row.get::<_, i64>(0)
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    let restored = tx.execute(
+        "UPDATE photos SET
+             date_taken = s.old_date_taken,
+             date_source = s.old_date_source,
+             date_confident = s.old_date_confident,
+             filename_date_mismatch = s.old_filename_date_mismatch
+         FROM date_shift_photos s
+         WHERE s.shift_id = ?1 AND s.photo_id = photos.id AND photos.date_taken = s.old_date_taken + ?2",
+        params![shift_id, delta_seconds],
+    )?;
+    tx.execute("DELETE FROM date_shift_photos WHERE shift_id = ?1", params![shift_id])?;
+    tx.execute("DELETE FROM date_shifts WHERE id = ?1", params![shift_id])?;
+    tx.commit()?;
+    Ok(Some(restored))
+}
+
+fn photo_date_from_row(row: &rusqlite::Row) -> SqlResult<PhotoDate> {
+    Ok(PhotoDate {
+        path: row.get(0)?,
+        date_taken: row.get(1)?,
+        tz_offset_minutes: row.get(2)?,
+    })
+}
+
+/// A photo whose file name suggests a different date than its metadata.
+#[derive(serde::Serialize, Debug)]
+pub struct DateDiscrepancy {
+    pub photo: PhotoMetadata,
+    /// What the name says; `photo.date_taken` is the embedded date in use
+    pub filename_date: i64,
+}
+
+/// Photos whose filename date disagreed with their embedded date on import,
+/// largest disagreement first.
+pub fn get_date_discrepancies(conn: &Connection) -> SqlResult<Vec<DateDiscrepancy>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT {}, filename_date_mismatch FROM photos
+         WHERE filename_date_mismatch IS NOT NULL AND deleted_at IS NULL
+         ORDER BY abs(date_taken - filename_date_mismatch) DESC",
+        PHOTO_COLUMNS
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok(DateDiscrepancy {
+            photo: photo_from_row(row)?,
+            filename_date: row.get(FIRST_EXTRA_COLUMN)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Record Live Photo pairs as (still, video). Pairs whose video has no row
+/// yet are skipped; they are found again when it is imported.
+pub fn set_live_photo_pairs(conn: &Connection, pairs: &[(String, String)]) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut recorded = 0;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET paired_video_path = ?2
+             WHERE path = ?1 AND EXISTS (SELECT 1 FROM photos WHERE path = ?2)",
+        )?;
+        for (still, video) in pairs {
+            recorded += stmt.execute(params![still, video])?;
+        }
+    }
+    tx.commit()?;
+    Ok(recorded)
+}
+
+/// The motion video of a Live Photo still, if it is still in the library.
+pub fn get_live_photo_video(conn: &Connection, still: &str) -> SqlResult<Option<String>> {
+    conn.query_row(
+        "SELECT video.path FROM photos still
+         JOIN photos video ON video.path = still.paired_video_path
+         WHERE still.path = ?1 AND video.deleted_at IS NULL",
+        params![still],
+        |row| row.get(0),
+    )
+    .optional()
+}
+
+// ============================================================================
+// Stacks
+// ============================================================================
+
+/// Why a manual stack change was refused
+#[derive(Debug)]
+pub enum StackError {
+    /// Fewer than two of the given photos are in the library
+    TooFewPhotos,
+    /// The chosen primary isn't one of the stack's members
+    NotAMember(String),
+    Sqlite(rusqlite::Error),
+}
+
+impl std::fmt::Display for StackError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StackError::TooFewPhotos => write!(f, "A stack needs at least two photos in the library"),
+            StackError::NotAMember(path) => write!(f, "{} is not in this stack", path),
+            StackError::Sqlite(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl From<rusqlite::Error> for StackError {
+    fn from(e: rusqlite::Error) -> Self {
+        StackError::Sqlite(e)
+    }
+}
+
+impl From<StackError> for String {
+    fn from(e: StackError) -> Self {
+        e.to_string()
+    }
+}
+
+/// Record stacks found by `stacks::find_stacks`, each listed primary first.
+/// Members already stacked keep their stack, and others join it; photos
+/// taken out of a stack by hand are left alone. Returns how many photos
+/// were stacked.
+pub fn create_stacks(conn: &Connection, stacks: &[Vec<String>]) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let now = chrono::Utc::now().timestamp();
+    let mut stacked = 0;
+    {
+        let mut find = tx.prepare_cached(
+            "SELECT id, stack_id FROM photos WHERE path = ?1 AND deleted_at IS NULL AND unstacked = 0",
+        )?;
+        let mut join = tx.prepare_cached("UPDATE photos SET stack_id = ?1 WHERE id = ?2")?;
+        for paths in stacks {
+            let mut members: Vec<(i64, Option<i64>)> = Vec::new();
+            for path in paths {
+                if let Some(member) = find.query_row(params![path], |row| Ok((row.get(0)?, row.get(1)?))).optional()? {
+                    members.push(member);
+                }
+            }
+            if members.len() < 2 {
+                continue;
+            }
+            let stack_id = match members.iter().find_map(|(_, stack)| *stack) {
+                Some(existing) => existing,
+                None => {
+                    tx.execute(
+                        "INSERT INTO stacks (primary_photo_id, created_at) VALUES (?1, ?2)",
+                        params![members[0].0, now],
+                    )?;
+                    tx.last_insert_rowid()
+                }
+            };
+            for (id, _) in members.iter().filter(|(_, stack)| stack.is_none()) {
+                stacked += join.execute(params![stack_id, id])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(stacked)
+}
+
+/// Ids of the library photos at `paths`, in order, skipping unknown ones
+fn photo_ids_for_paths(conn: &Connection, paths: &[String]) -> SqlResult<Vec<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT id FROM photos WHERE path = ?1 AND deleted_at IS NULL")?;
+    let mut ids = Vec::new();
+    for path in paths {
+        if let Some(id) = stmt.query_row(params![path], |row| row.get(0)).optional()? {
+            if !ids.contains(&id) {
+                ids.push(id);
+            }
+        }
+    }
+    Ok(ids)
+}
+
+/// Stack `paths` by hand, taking them out of any stacks they were in. The
+/// primary is `primary`, else the first path. Returns the new stack's id.
+pub fn stack_photos(conn: &Connection, paths: &[String], primary: Option<&str>) -> Result<i64, StackError> {
+    let tx = conn.unchecked_transaction()?;
+    let ids = photo_ids_for_paths(&tx, paths)?;
+    if ids.len() < 2 {
+        return Err(StackError::TooFewPhotos);
+    }
+    let primary_id = match primary {
+        Some(path) => photo_ids_for_paths(&tx, &[path.to_string()])?
+            .into_iter()
+            .find(|id| ids.contains(id))
+            .ok_or_else(|| StackError::NotAMember(path.to_string()))?,
+        None => ids[0],
+    };
+    tx.execute(
+        "INSERT INTO stacks (primary_photo_id, created_at) VALUES (?1, ?2)",
+        params![primary_id, chrono::Utc::now().timestamp()],
+    )?;
+    let stack_id = tx.last_insert_rowid();
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET stack_id = ?1, unstacked = 0 WHERE id = ?2")?;
+        for id in &ids {
+            stmt.execute(params![stack_id, id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(stack_id)
+}
+
+/// Take photos out of their stacks; a stack left with one member is
+/// dissolved. Scans won't stack them again. Returns how many were unstacked.
+pub fn unstack_photos(conn: &Connection, paths: &[String]) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut unstacked = 0;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET stack_id = NULL, unstacked = 1 WHERE path = ?1 AND stack_id IS NOT NULL",
+        )?;
+        for path in paths {
+            unstacked += stmt.execute(params![path])?;
+        }
+    }
+    tx.commit()?;
+    Ok(unstacked)
+}
+
+/// Show `path` for its stack in the grid
+pub fn set_stack_primary(conn: &Connection, stack_id: i64, path: &str) -> Result<(), StackError> {
+    let changed = conn.execute(
+        "UPDATE stacks SET primary_photo_id = (SELECT id FROM photos WHERE path = ?2 AND stack_id = ?1 AND deleted_at IS NULL)
+         WHERE id = ?1 AND EXISTS (SELECT 1 FROM photos WHERE path = ?2 AND stack_id = ?1 AND deleted_at IS NULL)",
+        params![stack_id, path],
+    )?;
+    if changed == 0 {
+        return Err(StackError::NotAMember(path.to_string()));
+    }
+    Ok(())
+}
+
+/// Members of a stack outside the trash, primary first, then by name.
+/// Empty if there is no such stack.
+pub fn get_stack_members(conn: &Connection, stack_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {}, {} FROM photos WHERE stack_id = ?1 AND deleted_at IS NULL
+         ORDER BY id != (SELECT primary_photo_id FROM stacks WHERE id = ?1), name COLLATE NOCASE, id",
+        PHOTO_COLUMNS, STACK_COUNT
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![stack_id], photo_with_stack_count)?;
+    rows.collect()
+}
+
+/// Record (path, size, mtime) for many photos in one transaction
+pub fn update_file_stats_batch(conn: &Connection, stats: &[(String, i64, i64)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(&format!(
+            "UPDATE photos SET file_size = ?1, file_mtime = ?2 WHERE path = ?3 COLLATE {}",
+            PATH_COLLATION
+        ))?;
+        for (path, size, mtime) in stats {
+            stmt.execute(params![size, mtime, path])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Flag (or clear) photos whose files were not found on disk
+pub fn set_photo_missing(conn: &Connection, path: &str, missing: bool) -> SqlResult<()> {
+    conn.execute(
+        &format!("UPDATE photos SET is_missing = ?1 WHERE path = ?2 COLLATE {}", PATH_COLLATION),
+        params![if missing { 1 } else { 0 }, path],
+    )?;
+    Ok(())
+}
+
+/// Flag a photo missing. Returns false if it was already flagged, is in the
+/// trash (its file moved there) or isn't stored.
+pub fn flag_photo_missing(conn: &Connection, path: &str) -> SqlResult<bool> {
+    let changed = conn.execute(
+        &format!(
+            "UPDATE photos SET is_missing = 1 WHERE path = ?1 COLLATE {} AND is_missing = 0 AND deleted_at IS NULL",
+            PATH_COLLATION
+        ),
+        params![path],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Every stored path outside the trash, for grouping by volume
+pub fn get_all_paths(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT path FROM photos WHERE deleted_at IS NULL")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Flag (or clear) every photo under `root` as offline. Returns how many
+/// rows changed.
+pub fn set_offline_under(conn: &Connection, root: &str, offline: bool) -> SqlResult<usize> {
+    conn.execute(
+        "UPDATE photos SET is_offline = ?1
+         WHERE substr(path, 1, length(?2)) = ?2 AND is_offline != ?1",
+        params![offline as i32, root],
+    )
+}
+
+/// True if the photo's volume was unmounted at the last availability refresh
+pub fn is_photo_offline(conn: &Connection, path: &str) -> SqlResult<bool> {
+    conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM photos WHERE path = ?1 AND is_offline = 1)",
+        params![path],
+        |row| row.get(0),
+    )
+}
+
+/// Get all photos flagged as missing from disk
+pub fn get_missing_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos WHERE is_missing = 1 AND deleted_at IS NULL ORDER BY date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_from_row)?;
+    rows.collect()
+}
+
+// ============================================================================
+// Library health
+// ============================================================================
+
+/// What check_library_health looks at for one photo row.
+pub struct HealthRow {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub date_source: Option<String>,
+    /// On an unmounted drive: its file can't be checked
+    pub is_offline: bool,
+}
+
+/// Every photo row outside the trash, Live Photo videos included.
+pub fn get_health_rows(conn: &Connection) -> SqlResult<Vec<HealthRow>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, width, height, date_source, COALESCE(is_offline, 0) FROM photos WHERE deleted_at IS NULL ORDER BY path",
+    )?;
+    let rows = stmt.query_map([], |row| Ok(HealthRow {
+        path: row.get(0)?,
+        width: row.get(1)?,
+        height: row.get(2)?,
+        date_source: row.get(3)?,
+        is_offline: row.get(4)?,
+    }))?;
+    rows.collect()
+}
+
+/// An album's chosen cover. `in_library` is false when no photo outside the
+/// trash has that path.
+pub struct AlbumCover {
+    pub album_id: i64,
+    pub album_name: String,
+    pub cover_photo_path: String,
+    pub in_library: bool,
+}
+
+/// Every album with a cover set
+pub fn get_album_covers(conn: &Connection) -> SqlResult<Vec<AlbumCover>> {
+    let mut stmt = conn.prepare(
+        "SELECT a.id, a.name, a.cover_photo_path,
+                EXISTS(SELECT 1 FROM photos p WHERE p.path = a.cover_photo_path AND p.deleted_at IS NULL)
+         FROM albums a WHERE a.cover_photo_path IS NOT NULL ORDER BY a.id",
+    )?;
+    let rows = stmt.query_map([], |row| Ok(AlbumCover {
+        album_id: row.get(0)?,
+        album_name: row.get(1)?,
+        cover_photo_path: row.get(2)?,
+        in_library: row.get(3)?,
+    }))?;
+    rows.collect()
+}
+
+/// Unset an album's cover if it is still `photo_path`
+pub fn clear_album_cover(conn: &Connection, album_id: i64, photo_path: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE albums SET cover_photo_path = NULL WHERE id = ?1 AND cover_photo_path = ?2",
+        params![album_id, photo_path],
+    )?;
+    Ok(())
+}
+
+// ============================================================================
+// Library relocation
+// ============================================================================
+
+/// An unfinished move of the whole library from one root to another.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Relocation {
+    pub from: PathBuf,
+    pub to: PathBuf,
+}
+
+/// One file of a relocation.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelocationFile {
+    pub source: String,
+    pub dest: String,
+    pub size: u64,
+}
+
+/// The relocation left unfinished by an earlier run, if any
+pub fn get_relocation(conn: &Connection) -> Option<Relocation> {
+    get_setting(conn, "library_relocation").and_then(|raw| serde_json::from_str(&raw).ok())
+}
+
+/// Record the relocation and the files found under its source. Files already
+/// recorded (by an interrupted run) keep their progress.
+pub fn track_relocation(conn: &Connection, relocation: &Relocation, files: &[RelocationFile]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    set_setting(&tx, "library_relocation", &serde_json::json!(relocation).to_string())?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT OR IGNORE INTO relocation_files (source, dest, size) VALUES (?1, ?2, ?3)",
+        )?;
+        for file in files {
+            stmt.execute(params![file.source, file.dest, file.size as i64])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Files of the relocation not copied yet
+pub fn get_pending_relocation_files(conn: &Connection) -> SqlResult<Vec<RelocationFile>> {
+    let mut stmt = conn.prepare("SELECT source, dest, size FROM relocation_files WHERE copied = 0 ORDER BY source")?;
+    let rows = stmt.query_map([], |row| Ok(RelocationFile {
+        source: row.get(0)?,
+        dest: row.get(1)?,
+        size: row.get::<_, i64>(2)? as u64,
+    }))?;
+    rows.collect()
+}
+
+/// Sources whose copy has been verified, ready to delete once the library has switched over
+pub fn get_copied_relocation_sources(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT source FROM relocation_files WHERE copied = 1 ORDER BY source")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Mark a file's copy verified
+pub fn mark_relocation_copied(conn: &Connection, source: &str) -> SqlResult<()> {
+    conn.execute("UPDATE relocation_files SET copied = 1 WHERE source = ?1", params![source])?;
+    Ok(())
+}
+
+/// Drop a file that disappeared from the source before it was copied
+pub fn forget_relocation_file(conn: &Connection, source: &str) -> SqlResult<()> {
+    conn.execute("DELETE FROM relocation_files WHERE source = ?1", params![source])?;
+    Ok(())
+}
+
+/// Point every stored path under `from` at the same place under `to` and
+/// make `to` the library root, in one transaction. Thumbnails are keyed by
+/// content hash, so the cache needs no changes. Returns the photos moved.
+pub fn switch_library_root(conn: &Connection, relocation: &Relocation) -> SqlResult<usize> {
+    let with_separator = |p: &std::path::Path| {
+        let mut prefix = p.to_string_lossy().to_string();
+        if !prefix.ends_with(std::path::MAIN_SEPARATOR) {
+            prefix.push(std::path::MAIN_SEPARATOR);
+        }
+        prefix
+    };
+    let (from, to) = (with_separator(&relocation.from), with_separator(&relocation.to));
+
+    let tx = conn.unchecked_transaction()?;
+    let moved = tx.execute(
+        "UPDATE photos SET path = ?2 || substr(path, length(?1) + 1) WHERE substr(path, 1, length(?1)) = ?1",
+        params![from, to],
+    )?;
+    for (table, column) in [("photos", "trash_path"), ("photos", "paired_video_path"), ("albums", "cover_photo_path")] {
+        tx.execute(
+            &format!(
+                "UPDATE {table} SET {column} = ?2 || substr({column}, length(?1) + 1)
+                 WHERE substr({column}, 1, length(?1)) = ?1"
+            ),
+            params![from, to],
+        )?;
+    }
+
+    // The old root is emptied, so it isn't kept as a previous library
+    let previous: Vec<String> = get_previous_library_paths(&tx)
+        .iter()
+        .filter(|p| **p != relocation.from && **p != relocation.to)
+        .map(|p| p.to_string_lossy().to_string())
+        .collect();
+    write_library_path(&tx, &relocation.to, &previous)?;
+    tx.commit()?;
+    Ok(moved)
+}
+
+/// Forget a relocation once its sources are deleted
+pub fn finish_relocation(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "DELETE FROM relocation_files;
+         DELETE FROM settings WHERE key = 'library_relocation';",
+    )
+}
+
+// ============================================================================
+// Duplicate Detection and Archive Functions
+// ============================================================================
+
+/// Get the path to the archive directory
+pub fn get_archive_path(conn: &Connection) -> Result<PathBuf, LibraryPathError> {
+    let mut path = get_library_path(conn)?;
+    path.push("Archive");
+    std::fs::create_dir_all(&path).map_err(|e| LibraryPathError::Io(path.clone(), e))?;
+    Ok(path)
+}
+
+/// Get all photos that need dhash computation (dhash_64 is NULL and not archived)
+pub fn get_photos_without_dhash(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, name FROM photos WHERE dhash_64 IS NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Update the dhash for a photo
+pub fn update_photo_dhash(conn: &Connection, path: &str, dhash: i64) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET dhash_64 = ?1 WHERE path = ?2",
+        params![dhash, path],
+    )?;
+    Ok(())
+}
+
+/// Record the user's verdict on whether a photo is a screenshot. Detection
+/// leaves it alone from then on. False if there is no such photo.
+pub fn set_screenshot_override(conn: &Connection, path: &str, is_screenshot: bool) -> SqlResult<bool> {
+    let changed = conn.execute(
+        "UPDATE photos SET is_screenshot = ?1, screenshot_override = ?1 WHERE path = ?2",
+        params![is_screenshot as i64, path],
+    )?;
+    Ok(changed > 0)
+}
+
+/// Photos detection may reclassify (no user override), with their current flag
+pub fn get_screenshot_candidates(conn: &Connection) -> SqlResult<Vec<(PhotoMetadata, bool)>> {
+    let query = format!(
+        "SELECT {}, COALESCE(is_screenshot, 0) FROM photos WHERE screenshot_override IS NULL AND deleted_at IS NULL",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| Ok((photo_from_row(row)?, row.get::<_, i64>(FIRST_EXTRA_COLUMN)? != 0)))?;
+    rows.collect()
+}
+
+/// Set detected screenshot flags in one transaction. Rows the user has
+/// overridden since are skipped; returns how many changed.
+pub fn set_screenshot_flags(conn: &Connection, flags: &[(String, bool)]) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut changed = 0;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET is_screenshot = ?1 WHERE path = ?2 AND screenshot_override IS NULL",
+        )?;
+        for (path, is_screenshot) in flags {
+            changed += stmt.execute(params![*is_screenshot as i64, path])?;
+        }
+    }
+    tx.commit()?;
+    Ok(changed)
+}
+
+/// Get all non-archived photos with their dhash values for duplicate detection
+pub fn get_all_photos_with_dhash(conn: &Connection) -> SqlResult<Vec<(String, Option<i64>, Option<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, dhash_64, content_hash FROM photos WHERE archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// File size and album names for each of `paths`, for choosing which copy
+/// of a duplicate to keep. Sizes are None for rows imported before they
+/// were tracked.
+pub fn get_duplicate_member_details(
+    conn: &Connection,
+    paths: &[String],
+) -> SqlResult<HashMap<String, (Option<i64>, Vec<String>)>> {
+    let mut size_stmt = conn.prepare_cached("SELECT file_size FROM photos WHERE path = ?1")?;
+    let mut album_stmt = conn.prepare_cached(
+        "SELECT a.name FROM album_photos ap
+         JOIN albums a ON a.id = ap.album_id
+         JOIN photos p ON p.id = ap.photo_id
+         WHERE p.path = ?1
+         ORDER BY a.name COLLATE NOCASE",
+    )?;
+    let mut details = HashMap::new();
+    for path in paths {
+        let Some(size) = size_stmt.query_row(params![path], |row| row.get(0)).optional()? else {
+            continue;
+        };
+        let albums = album_stmt
+            .query_map(params![path], |row| row.get(0))?
+            .collect::<SqlResult<Vec<String>>>()?;
+        details.insert(path.clone(), (size, albums));
+    }
+    Ok(details)
+}
+
+/// Give `keep` the albums, tags, album covers and favorite flag of each of
+/// `discard`, and record what changed so `undo_duplicate_merge` can take it
+/// back. The discarded rows keep their own memberships for when they are
+/// restored. Paths that aren't live photos are skipped; returns the new
+/// resolution id, or None if `keep` isn't one or nothing was left to merge.
+/// Opens no transaction: the caller runs it in the one that trashes `discard`.
+pub fn merge_duplicates(conn: &Connection, keep: &str, discard: &[String]) -> SqlResult<Option<i64>> {
+    let Some((kept_id, kept_favorite)) = conn
+        .query_row(
+            "SELECT id, COALESCE(is_favorite, 0) FROM photos WHERE path = ?1 AND deleted_at IS NULL",
+            params![keep],
+            |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? != 0)),
+        )
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let mut discarded: Vec<(i64, bool)> = Vec::new();
+    {
+        let mut stmt = conn.prepare_cached(
+            "SELECT id, COALESCE(is_favorite, 0) FROM photos WHERE path = ?1 AND deleted_at IS NULL",
+        )?;
+        for path in discard.iter().filter(|p| p.as_str() != keep) {
+            if let Some(row) = stmt
+                .query_row(params![path], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)? != 0)))
+                .optional()?
+            {
+                discarded.push(row);
+            }
+        }
+    }
+    if discarded.is_empty() {
+        return Ok(None);
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "INSERT INTO duplicate_resolutions (kept_photo_id, kept_was_favorite, resolved_at) VALUES (?1, ?2, ?3)",
+        params![kept_id, kept_favorite, now],
+    )?;
+    let resolution_id = conn.last_insert_rowid();
+
+    {
+        let mut record = conn.prepare_cached(
+            "INSERT INTO duplicate_resolution_changes (resolution_id, kind, photo_id, target_id) VALUES (?1, ?2, ?3, ?4)",
+        )?;
+        let mut album_ids = conn.prepare_cached("SELECT album_id FROM album_photos WHERE photo_id = ?1")?;
+        let mut tag_ids = conn.prepare_cached("SELECT tag_id FROM photo_tags WHERE photo_id = ?1")?;
+        let mut add_album = conn.prepare_cached(
+            "INSERT OR IGNORE INTO album_photos (album_id, photo_id, added_at) VALUES (?1, ?2, ?3)",
+        )?;
+        let mut add_tag = conn.prepare_cached(
+            "INSERT OR IGNORE INTO photo_tags (tag_id, photo_id, added_at) VALUES (?1, ?2, ?3)",
+        )?;
+        let mut covers = conn.prepare_cached(
+            "SELECT a.id FROM albums a JOIN photos p ON p.path = a.cover_photo_path WHERE p.id = ?1",
+        )?;
+
+        for &(photo_id, _) in &discarded {
+            record.execute(params![resolution_id, "discarded", photo_id, None::<i64>])?;
+
+            let albums: Vec<i64> = album_ids.query_map(params![photo_id], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+            for album_id in albums {
+                // Only memberships the keeper didn't already have are undone later
+                if add_album.execute(params![album_id, kept_id, now])? > 0 {
+                    record.execute(params![resolution_id, "album", photo_id, album_id])?;
+                }
+            }
+            let tags: Vec<i64> = tag_ids.query_map(params![photo_id], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+            for tag_id in tags {
+                if add_tag.execute(params![tag_id, kept_id, now])? > 0 {
+                    record.execute(params![resolution_id, "tag", photo_id, tag_id])?;
+                }
+            }
+            let covered: Vec<i64> = covers.query_map(params![photo_id], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+            for album_id in covered {
+                conn.execute("UPDATE albums SET cover_photo_path = ?1 WHERE id = ?2", params![keep, album_id])?;
+                record.execute(params![resolution_id, "cover", photo_id, album_id])?;
+            }
+        }
+    }
+
+    let favorite_from = discarded.iter().find(|&&(_, favorite)| favorite).map(|&(id, _)| id);
+    if let Some(from_id) = favorite_from.filter(|_| !kept_favorite) {
+        conn.execute("UPDATE photos SET is_favorite = 1 WHERE id = ?1", params![kept_id])?;
+        conn.execute(
+            "INSERT INTO duplicate_resolution_changes (resolution_id, kind, photo_id, target_id) VALUES (?1, 'favorite', ?2, NULL)",
+            params![resolution_id, from_id],
+        )?;
+    }
+    Ok(Some(resolution_id))
+}
+
+/// Current paths of the copies a resolution discarded; None for a copy whose
+/// row is gone because the trash was emptied. None overall if there is no
+/// such resolution.
+pub fn get_discarded_duplicates(conn: &Connection, resolution_id: i64) -> SqlResult<Option<Vec<Option<String>>>> {
+    let exists: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM duplicate_resolutions WHERE id = ?1)",
+        params![resolution_id],
+        |row| row.get(0),
+    )?;
+    if !exists {
+        return Ok(None);
+    }
+    let mut stmt = conn.prepare(
+        "SELECT p.path FROM duplicate_resolution_changes c
+         LEFT JOIN photos p ON p.id = c.photo_id
+         WHERE c.resolution_id = ?1 AND c.kind = 'discarded'",
+    )?;
+    let rows = stmt.query_map(params![resolution_id], |row| row.get(0))?;
+    rows.collect::<SqlResult<Vec<_>>>().map(Some)
+}
+
+/// Take back what `merge_duplicates` gave the kept photo: the albums and tags
+/// it gained, the album covers it took over and its favorite flag. Restoring
+/// the discarded files is up to the caller. False if there was no such
+/// resolution.
+pub fn undo_duplicate_merge(conn: &Connection, resolution_id: i64) -> SqlResult<bool> {
+    let tx = conn.unchecked_transaction()?;
+    let Some(kept_id) = tx
+        .query_row(
+            "SELECT kept_photo_id FROM duplicate_resolutions WHERE id = ?1",
+            params![resolution_id],
+            |row| row.get::<_, i64>(0),
+        )
+        .optional()?
+    else {
+        return Ok(false);
+    };
+
+    tx.execute(
+        "DELETE FROM album_photos WHERE photo_id = ?1 AND album_id IN (
+             SELECT target_id FROM duplicate_resolution_changes WHERE resolution_id = ?2 AND kind = 'album'
+         )",
+        params![kept_id, resolution_id],
+    )?;
+    tx.execute(
+        "DELETE FROM photo_tags WHERE photo_id = ?1 AND tag_id IN (
+             SELECT target_id FROM duplicate_resolution_changes WHERE resolution_id = ?2 AND kind = 'tag'
+         )",
+        params![kept_id, resolution_id],
+    )?;
+    // Covers go back only if nobody picked a different one since
+    tx.execute(
+        "UPDATE albums SET cover_photo_path = (
+             SELECT p.path FROM duplicate_resolution_changes c JOIN photos p ON p.id = c.photo_id
+             WHERE c.resolution_id = ?2 AND c.kind = 'cover' AND c.target_id = albums.id
+         )
+         WHERE cover_photo_path = (SELECT path FROM photos WHERE id = ?1)
+           AND id IN (SELECT target_id FROM duplicate_resolution_changes WHERE resolution_id = ?2 AND kind = 'cover')",
+        params![kept_id, resolution_id],
+    )?;
+    // The favorite flag too, and only if the merge set it and it's still set
+    tx.execute(
+        "UPDATE photos SET is_favorite = 0
+         WHERE id = ?1 AND is_favorite = 1
+           AND EXISTS (SELECT 1 FROM duplicate_resolution_changes WHERE resolution_id = ?2 AND kind = 'favorite')",
+        params![kept_id, resolution_id],
+    )?;
+    tx.execute("DELETE FROM duplicate_resolution_changes WHERE resolution_id = ?1", params![resolution_id])?;
+    tx.execute("DELETE FROM duplicate_resolutions WHERE id = ?1", params![resolution_id])?;
+    tx.commit()?;
+    Ok(true)
+}
+
+/// Get all photos marked as screenshots
+pub fn get_screenshots(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos WHERE is_screenshot = 1 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_from_row)?;
+    rows.collect()
+}
+
+/// Archive a photo (set archived_at timestamp)
+pub fn archive_photo(conn: &Connection, path: &str) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE photos SET archived_at = ?1 WHERE path = ?2",
+        params![now, path],
+    )?;
+    Ok(())
+}
+
+/// Restore a photo from archive (clear archived_at)
+pub fn restore_photo(conn: &Connection, path: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET archived_at = NULL WHERE path = ?1",
+        params![path],
+    )?;
+    Ok(())
+}
+
+/// Photos archived for deletion (archived_at set), newest first. Not the
+/// timeline archive; see get_archived_photos.
+pub fn get_deletion_archive(conn: &Connection) -> SqlResult<Vec<(PhotoMetadata, i64)>> {
+    let query = format!(
+        "SELECT {}, archived_at FROM photos WHERE archived_at IS NOT NULL AND deleted_at IS NULL ORDER BY archived_at DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| Ok((photo_from_row(row)?, row.get::<_, i64>("archived_at")?)))?;
+    rows.collect()
+}
+
+/// Get photos archived more than N days ago (for cleanup)
+pub fn get_old_archived_photos(conn: &Connection, days: i64) -> SqlResult<Vec<String>> {
+    let cutoff = chrono::Utc::now().timestamp() - (days * 24 * 60 * 60);
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos WHERE archived_at IS NOT NULL AND archived_at < ?1 AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map(params![cutoff], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Permanently delete a photo from database, along with its album and tag memberships.
+/// Returns the photo's content hash if no remaining row shares it, so the
+/// caller can drop its cached thumbnails.
+pub fn permanently_delete_photo(conn: &Connection, path: &str) -> SqlResult<Option<String>> {
+    let tx = conn.unchecked_transaction()?;
+    let hash: Option<String> = tx
+        .query_row("SELECT content_hash FROM photos WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()?
+        .flatten();
+    tx.execute("DELETE FROM album_photos WHERE photo_id = (SELECT id FROM photos WHERE path = ?1)", params![path])?;
+    tx.execute("DELETE FROM photo_tags WHERE photo_id = (SELECT id FROM photos WHERE path = ?1)", params![path])?;
+    tx.execute("UPDATE albums SET cover_photo_path = NULL WHERE cover_photo_path = ?1", params![path])?;
+    tx.execute("UPDATE photos SET paired_video_path = NULL WHERE paired_video_path = ?1", params![path])?;
+    tx.execute("DELETE FROM photos WHERE path = ?1", params![path])?;
+    let orphaned = match hash {
+        Some(hash) => {
+            let shared: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM photos WHERE content_hash = ?1)",
+                params![hash],
+                |row| row.get(0),
+            )?;
+            (!shared).then_some(hash)
+        }
+        None => None,
+    };
+    tx.commit()?;
+    Ok(orphaned)
+}
+
+/// Content hash recorded for a photo, if any
+pub fn get_content_hash(conn: &Connection, path: &str) -> SqlResult<Option<String>> {
+    Ok(conn
+        .query_row("SELECT content_hash FROM photos WHERE path = ?1", params![path], |row| row.get(0))
+        .optional()?
+        .flatten())
+}
+
+/// Headline numbers for the sidebar. Trashed and hidden photos are left out
+/// of every count except their own; so are photos in the deletion archive.
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct LibrarySummary {
+    pub photo_count: i64,
+    pub video_count: i64,
+    pub favorite_count: i64,
+    pub album_count: i64,
+    pub earliest_date: Option<i64>,
+    pub latest_date: Option<i64>,
+    pub undated_count: i64,
+    pub total_size_bytes: i64,
+    /// Of `total_size_bytes`, the videos
+    pub video_size_bytes: i64,
+    pub trashed_count: i64,
+    /// Space emptying the trash would free
+    pub trashed_size_bytes: i64,
+    pub hidden_count: i64,
+    pub color_labels: ColorLabelCounts,
+}
+
+/// Shown photos carrying each color label
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct ColorLabelCounts {
+    pub red: i64,
+    pub yellow: i64,
+    pub green: i64,
+    pub blue: i64,
+    pub purple: i64,
+}
+
+
+/// Every LibrarySummary figure from one scan of photos. Live Photo videos
+/// count as part of their still, not as videos.
+pub fn get_library_summary(conn: &Connection) -> SqlResult<LibrarySummary> {
+    let video = is_video_sql();
+    let query = format!(
+        "SELECT
+             COALESCE(SUM(shown AND NOT video), 0),
+             COALESCE(SUM(shown AND video), 0),
+             COALESCE(SUM(shown AND is_favorite = 1), 0),
+             (SELECT COUNT(*) FROM albums),
+             MIN(CASE WHEN shown AND dated THEN date_taken END),
+             MAX(CASE WHEN shown AND dated THEN date_taken END),
+             COALESCE(SUM(shown AND NOT dated), 0),
+             COALESCE(SUM(CASE WHEN shown THEN file_size END), 0),
+             COALESCE(SUM(deleted_at IS NOT NULL), 0),
+             COALESCE(SUM(deleted_at IS NULL AND is_hidden = 1), 0),
+             COALESCE(SUM(CASE WHEN shown AND video THEN file_size END), 0),
+             COALESCE(SUM(CASE WHEN deleted_at IS NOT NULL THEN file_size END), 0),
+             COALESCE(SUM(shown AND color_label = 'red'), 0),
+             COALESCE(SUM(shown AND color_label = 'yellow'), 0),
+             COALESCE(SUM(shown AND color_label = 'green'), 0),
+             COALESCE(SUM(shown AND color_label = 'blue'), 0),
+             COALESCE(SUM(shown AND color_label = 'purple'), 0)
+         FROM (
+             SELECT date_taken, file_size, is_favorite, is_hidden, deleted_at, color_label,
+                    deleted_at IS NULL AND archived_at IS NULL AND COALESCE(is_hidden, 0) = 0 AS shown,
+                    ({}) AS video,
+                    date_source IS NOT '{}' AS dated
+             FROM photos
+             WHERE path NOT IN (SELECT paired_video_path FROM photos
+                                WHERE paired_video_path IS NOT NULL AND deleted_at IS NULL)
+         )",
+        video,
+        media::DATE_SOURCE_UNKNOWN
+    );
+    conn.query_row(&query, [], |row| Ok(LibrarySummary {
+        photo_count: row.get(0)?,
+        video_count: row.get(1)?,
+        favorite_count: row.get(2)?,
+        album_count: row.get(3)?,
+        earliest_date: row.get(4)?,
+        latest_date: row.get(5)?,
+        undated_count: row.get(6)?,
+        total_size_bytes: row.get(7)?,
+        trashed_count: row.get(8)?,
+        hidden_count: row.get(9)?,
+        video_size_bytes: row.get(10)?,
+        trashed_size_bytes: row.get(11)?,
+        color_labels: ColorLabelCounts {
+            red: row.get(12)?,
+            yellow: row.get(13)?,
+            green: row.get(14)?,
+            blue: row.get(15)?,
+            purple: row.get(16)?,
+        },
+    }))
+}
+
+/// Get total photo count (non-archived), of the rows get_photos_page returns
+pub fn get_photo_count(conn: &Connection, filter: &PhotoFilter) -> SqlResult<i64> {
+    let mut stmt = conn.prepare(&format!("SELECT COUNT(*) FROM photos WHERE {}", timeline_sql(filter)))?;
+    let count: i64 = stmt.query_row([], |row| row.get(0))?;
+    Ok(count)
+}
+
+/// Get count of photos with dhash computed
+pub fn get_photos_with_dhash_count(conn: &Connection) -> SqlResult<i64> {
+    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE dhash_64 IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL")?;
+    let count: i64 = stmt.query_row([], |row| row.get(0))?;
+    Ok(count)
+}
+
+// ============================================================================
+// TerraForm (Review Mode) Functions
+// ============================================================================
+
+/// Get all unreviewed photos (reviewed_at is NULL and not archived)
+pub fn get_unreviewed_photos(conn: &Connection) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], photo_from_row)?;
+    rows.collect()
+}
+
+/// Mark a photo as reviewed
+pub fn mark_photo_reviewed(conn: &Connection, path: &str) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE photos SET reviewed_at = ?1 WHERE path = ?2",
+        params![now, path],
+    )?;
+    Ok(())
+}
+
+/// Get count of unreviewed photos
+pub fn get_unreviewed_count(conn: &Connection) -> SqlResult<i64> {
+    let mut stmt = conn.prepare("SELECT COUNT(*) FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL AND deleted_at IS NULL")?;
+    let count: i64 = stmt.query_row([], |row| row.get(0))?;
+    Ok(count)
+}
+
+/// Unmark a photo as reviewed (for undo)
+pub fn unmark_photo_reviewed(conn: &Connection, path: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET reviewed_at = NULL WHERE path = ?1",
+        params![path],
+    )?;
+    Ok(())
+}
+
+// ============================================================================
+// Culling
+// ============================================================================
+
+/// Pick or reject from a culling pass, separate from favorites. `None` is
+/// unflagged, stored as NULL.
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum PhotoFlag {
+    Pick,
+    Reject,
+    None,
+}
+
+impl PhotoFlag {
+    /// Value of the flag column; None for unflagged
+    pub fn stored(self) -> Option<&'static str> {
+        match self {
+            PhotoFlag::Pick => Some("pick"),
+            PhotoFlag::Reject => Some("reject"),
+            PhotoFlag::None => None,
+        }
+    }
+
+    pub fn parse(stored: &str) -> Option<PhotoFlag> {
+        [PhotoFlag::Pick, PhotoFlag::Reject].into_iter().find(|flag| flag.stored() == Some(stored))
+    }
+}
+
+/// Set the same flag on many photos; PhotoFlag::None clears it. Returns
+/// rows updated.
+pub fn set_photos_flag(conn: &Connection, paths: &[String], flag: PhotoFlag) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let mut updated = 0;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET flag = ?1 WHERE path = ?2")?;
+        for path in paths {
+            updated += stmt.execute(params![flag.stored(), path])?;
+        }
+    }
+    tx.commit()?;
+    Ok(updated)
+}
+
+/// Unflagged photos matching `query` (whatever its `flag`), in capture
+/// order for stepping through a shoot: oldest first, undated last.
+pub fn get_cull_queue(conn: &Connection, query: &PhotoQuery) -> SqlResult<Vec<PhotoMetadata>> {
+    let (where_clause, values) = build_photo_query(&PhotoQuery { flag: Some(PhotoFlag::None), ..query.clone() });
+    let sql = format!(
+        "SELECT {} FROM photos WHERE {} ORDER BY {}, date_taken, name, id",
+        PHOTO_COLUMNS, where_clause, undated_sql()
+    );
+    let mut stmt = conn.prepare(&sql)?;
+    let rows = stmt.query_map(rusqlite::params_from_iter(values), photo_from_row)?;
+    rows.collect()
+}
+
+/// How far a culling pass has got
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct CullProgress {
+    pub picked: i64,
+    pub rejected: i64,
+    /// Still unflagged
+    pub remaining: i64,
+}
+
+/// Flag counts of the photos matching `query` (whatever its `flag`), in one
+/// pass over the matches
+pub fn get_cull_progress(conn: &Connection, query: &PhotoQuery) -> SqlResult<CullProgress> {
+    let (where_clause, values) = photo_query_sql(&PhotoQuery { flag: None, ..query.clone() }, true);
+    let sql = format!(
+        "SELECT COALESCE(SUM(flag = 'pick'), 0), COALESCE(SUM(flag = 'reject'), 0), COALESCE(SUM(flag IS NULL), 0)
+         FROM photos WHERE {}",
+        where_clause
+    );
+    conn.query_row(&sql, rusqlite::params_from_iter(values), |row| {
+        Ok(CullProgress { picked: row.get(0)?, rejected: row.get(1)?, remaining: row.get(2)? })
+    })
+}
+
+/// Paths of every rejected photo not already in the trash
+pub fn get_rejected_paths(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare("SELECT path FROM photos WHERE flag = 'reject' AND deleted_at IS NULL ORDER BY id")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+// ============================================================================
+// Tag Functions
+// ============================================================================
+
+#[derive(serde::Serialize, serde::Deserialize, Debug, Clone, PartialEq)]
+pub struct Tag {
+    pub id: i64,
+    pub name: String,
+    pub color: String,
+    pub count: i64,
+    /// Added by the auto-tagger: on a photo, this membership; in tag lists,
+    /// every membership of the tag
+    pub auto: bool,
+}
+
+/// Point photo_tags at the oldest of any tags whose names differ only in case,
+/// then drop the newer duplicates.
+fn merge_case_duplicate_tags(conn: &Connection) -> SqlResult<()> {
+    conn.execute_batch(
+        "UPDATE OR IGNORE photo_tags SET tag_id = (
+             SELECT MIN(t2.id) FROM tags t1
+             JOIN tags t2 ON t2.name = t1.name COLLATE NOCASE
+             WHERE t1.id = photo_tags.tag_id
+         );
+         DELETE FROM photo_tags WHERE tag_id NOT IN (SELECT MIN(id) FROM tags GROUP BY name COLLATE NOCASE);
+         DELETE FROM tags WHERE id NOT IN (SELECT MIN(id) FROM tags GROUP BY name COLLATE NOCASE);",
+    )
+}
+
+/// Create a tag, or return the id of the existing tag with the same name
+/// (compared case-insensitively). Creating a duplicate is a no-op.
+pub fn create_tag(conn: &Connection, name: &str, color: &str) -> SqlResult<i64> {
+    let name = name.trim();
+    conn.execute(
+        "INSERT INTO tags (name, color, created_at) VALUES (?1, ?2, ?3) ON CONFLICT DO NOTHING",
+        params![name, color, chrono::Utc::now().timestamp()],
+    )?;
+    conn.query_row(
+        "SELECT id FROM tags WHERE name = ?1 COLLATE NOCASE",
+        params![name],
+        |row| row.get(0),
+    )
+}
+
+/// Rename a tag. Returns false (and changes nothing) if another tag already
+/// has that name; changing only the case of a tag's own name is allowed.
+pub fn rename_tag(conn: &Connection, id: i64, name: &str) -> SqlResult<bool> {
+    let name = name.trim();
+    let taken: bool = conn.query_row(
+        "SELECT EXISTS(SELECT 1 FROM tags WHERE name = ?1 COLLATE NOCASE AND id != ?2)",
+        params![name, id],
+        |row| row.get(0),
+    )?;
+    if taken {
+        return Ok(false);
+    }
+    conn.execute("UPDATE tags SET name = ?1 WHERE id = ?2", params![name, id])?;
+    Ok(true)
+}
+
+/// Update a tag
+pub fn update_tag(conn: &Connection, id: i64, name: &str, color: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE tags SET name = ?1, color = ?2 WHERE id = ?3",
+        params![name, color, id],
+    )?;
+    Ok(())
+}
+
+/// Delete a tag
+pub fn delete_tag(conn: &Connection, id: i64) -> SqlResult<()> {
+    conn.execute("DELETE FROM tags WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Get all tags with counts
+pub fn get_all_tags(conn: &Connection) -> SqlResult<Vec<Tag>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.name, t.color, COUNT(p.path) as count, COALESCE(MIN(pt.source IS 'auto'), 0)
+         FROM tags t
+         LEFT JOIN photo_tags pt ON t.id = pt.tag_id
+         LEFT JOIN photos p ON pt.photo_id = p.id AND p.archived_at IS NULL AND p.deleted_at IS NULL
+         GROUP BY t.id
+         ORDER BY count DESC, t.name ASC"
+    )?;
+    let rows = stmt.query_map([], |row| Ok(Tag {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+        count: row.get(3)?,
+        auto: row.get(4)?,
+    }))?;
+    rows.collect()
+}
+
+/// Get tags for a specific photo
+pub fn get_tags_for_photo(conn: &Connection, path: &str) -> SqlResult<Vec<Tag>> {
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.name, t.color, 0 as count, pt.source IS 'auto'
+         FROM tags t
+         JOIN photo_tags pt ON t.id = pt.tag_id
+         JOIN photos p ON p.id = pt.photo_id
+         WHERE p.path = ?1
+         ORDER BY t.name ASC"
+    )?;
+    let rows = stmt.query_map(params![path], |row| Ok(Tag {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+        count: row.get(3)?,
+        auto: row.get(4)?,
+    }))?;
+    rows.collect()
+}
+
+/// Add tags to photos (bulk operation)
+pub fn add_tags_to_photos(conn: &Connection, tag_ids: &[i64], photo_paths: &[String]) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    for tag_id in tag_ids {
+        for path in photo_paths {
+            // Tagging by hand claims a tag the auto-tagger had added
+            conn.execute(
+                "INSERT INTO photo_tags (tag_id, photo_id, added_at)
+                 SELECT ?1, id, ?3 FROM photos WHERE path = ?2
+                 ON CONFLICT(tag_id, photo_id) DO UPDATE SET source = NULL, confidence = NULL",
+                params![tag_id, path, now],
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Remove tags from photos (bulk operation); pairs that aren't tagged are ignored
+pub fn remove_tags_from_photos(conn: &Connection, tag_ids: &[i64], photo_paths: &[String]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "DELETE FROM photo_tags WHERE tag_id = ?1 AND photo_id = (SELECT id FROM photos WHERE path = ?2)",
+        )?;
+        for tag_id in tag_ids {
+            for path in photo_paths {
+                stmt.execute(params![tag_id, path])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// Fill in `tags` on each photo with one query per chunk of photos rather
+/// than one per photo, so grid/lightbox payloads carry tags without N+1 lookups.
+pub fn attach_tags(conn: &Connection, photos: &mut [PhotoMetadata]) -> SqlResult<()> {
+    let mut by_path: HashMap<String, Vec<Tag>> = HashMap::new();
+    for chunk in photos.chunks(MAX_IN_PARAMS) {
+        let placeholders = vec!["?"; chunk.len()].join(",");
+        let query = format!(
+            "SELECT p.path, t.id, t.name, t.color, pt.source IS 'auto'
+             FROM photo_tags pt
+             JOIN tags t ON t.id = pt.tag_id
+             JOIN photos p ON p.id = pt.photo_id
+             WHERE p.path IN ({})
+             ORDER BY t.name COLLATE NOCASE",
+            placeholders
+        );
+        let mut stmt = conn.prepare(&query)?;
+        let rows = stmt.query_map(rusqlite::params_from_iter(chunk.iter().map(|p| &p.path)), |row| {
+            Ok((row.get::<_, String>(0)?, Tag {
+                id: row.get(1)?,
+                name: row.get(2)?,
+                color: row.get(3)?,
+                count: 0,
+                auto: row.get(4)?,
+            }))
+        })?;
+        for row in rows {
+            let (path, tag) = row?;
+            by_path.entry(path).or_default().push(tag);
+        }
+    }
+
+    for photo in photos.iter_mut() {
+        photo.tags = by_path.remove(&photo.path).unwrap_or_default();
+    }
+    Ok(())
+}
+
+/// Remove a tag from a photo
+pub fn remove_tag_from_photo(conn: &Connection, tag_id: i64, photo_path: &str) -> SqlResult<()> {
+    conn.execute(
+        "DELETE FROM photo_tags WHERE tag_id = ?1 AND photo_id = (SELECT id FROM photos WHERE path = ?2)",
+        params![tag_id, photo_path],
+    )?;
+    Ok(())
+}
+
+/// Get photos by tags (with AND/OR logic)
+pub fn get_photos_by_tags(conn: &Connection, tag_ids: &[i64], match_all: bool) -> SqlResult<Vec<PhotoMetadata>> {
+    if tag_ids.is_empty() {
+        return Ok(Vec::new());
+    }
+
+    let placeholders: Vec<String> = tag_ids.iter().map(|_| "?".to_string()).collect();
+    let placeholder_str = placeholders.join(",");
+
+    let photo_cols = photo_columns_as("p");
+    let query = if match_all {
+        // AND logic: photo must have ALL specified tags
+        format!(
+            "SELECT {} FROM photos p \
+             JOIN photo_tags pt ON p.id = pt.photo_id \
+             WHERE pt.tag_id IN ({}) AND p.archived_at IS NULL AND p.deleted_at IS NULL \
+             GROUP BY p.id \
+             HAVING COUNT(DISTINCT pt.tag_id) = ? \
+             ORDER BY p.date_taken DESC",
+            photo_cols, placeholder_str
+        )
+    } else {
+        // OR logic: photo must have ANY of the specified tags
+        format!(
+            "SELECT DISTINCT {} FROM photos p \
+             JOIN photo_tags pt ON p.id = pt.photo_id \
+             WHERE pt.tag_id IN ({}) AND p.archived_at IS NULL AND p.deleted_at IS NULL \
+             ORDER BY p.date_taken DESC",
+            photo_cols, placeholder_str
+        )
+    };
+
+    let mut stmt = conn.prepare(&query)?;
+
+    // Build params dynamically
+    let mut params_vec: Vec<Box<dyn rusqlite::ToSql>> = tag_ids.iter()
+        .map(|id| Box::new(*id) as Box<dyn rusqlite::ToSql>)
+        .collect();
+
+    if match_all {
+        params_vec.push(Box::new(tag_ids.len() as i64));
+    }
+
+    let rows = stmt.query_map(rusqlite::params_from_iter(params_vec.iter().map(|p| p.as_ref())), photo_from_row)?;
+    rows.collect()
+}
+
+/// Search tags by name (for autocomplete)
+pub fn search_tags(conn: &Connection, query: &str) -> SqlResult<Vec<Tag>> {
+    let search_term = format!("%{}%", query);
+    let mut stmt = conn.prepare(
+        "SELECT t.id, t.name, t.color, COUNT(pt.photo_id) as count, COALESCE(MIN(pt.source IS 'auto'), 0)
+         FROM tags t
+         LEFT JOIN photo_tags pt ON t.id = pt.tag_id
+         WHERE t.name LIKE ?1
+         GROUP BY t.id
+         ORDER BY count DESC, t.name ASC
+         LIMIT 10"
+    )?;
+    let rows = stmt.query_map(params![search_term], |row| Ok(Tag {
+        id: row.get(0)?,
+        name: row.get(1)?,
+        color: row.get(2)?,
+        count: row.get(3)?,
+        auto: row.get(4)?,
+    }))?;
+    rows.collect()
+}
+
+// ============================================================================
+// Smart Collections Functions
+// ============================================================================
+
+#[derive(serde::Serialize)]
+pub struct SmartCollection {
+    pub id: String,
+    pub name: String,
+    pub icon: String,
+    pub count: i64,
+    pub category: String,
+}
+
+/// Get all smart collections with counts
+pub fn get_smart_collections(conn: &Connection) -> SqlResult<Vec<SmartCollection>> {
+    let mut collections = Vec::new();
+
+    // Size-based collections
+    let large_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE file_size > 5242880 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "size_large".to_string(),
+        name: "Large (>5MB)".to_string(),
+        icon: "hard-drive".to_string(),
+        count: large_count,
+        category: "size".to_string(),
+    });
+
+    let medium_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE file_size BETWEEN 1048576 AND 5242880 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "size_medium".to_string(),
+        name: "Medium (1-5MB)".to_string(),
+        icon: "hard-drive".to_string(),
+        count: medium_count,
+        category: "size".to_string(),
+    });
+
+    let small_count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE file_size < 1048576 AND file_size > 0 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "size_small".to_string(),
+        name: "Small (<1MB)".to_string(),
+        icon: "hard-drive".to_string(),
+        count: small_count,
+        category: "size".to_string(),
+    });
+
+    // Dimension-based collections
+    let dim_4k: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE (width >= 3840 OR height >= 2160) AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "dim_4k".to_string(),
+        name: "4K+".to_string(),
+        icon: "monitor".to_string(),
+        count: dim_4k,
+        category: "dimension".to_string(),
+    });
+
+    let dim_hd: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE (width >= 1920 OR height >= 1080) AND width < 3840 AND height < 2160 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "dim_hd".to_string(),
+        name: "HD".to_string(),
+        icon: "monitor".to_string(),
+        count: dim_hd,
+        category: "dimension".to_string(),
+    });
+
+    let portrait: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE height > width AND width > 0 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "dim_portrait".to_string(),
+        name: "Portrait".to_string(),
+        icon: "smartphone".to_string(),
+        count: portrait,
+        category: "dimension".to_string(),
+    });
+
+    let landscape: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE width > height AND height > 0 AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "dim_landscape".to_string(),
+        name: "Landscape".to_string(),
+        icon: "monitor".to_string(),
+        count: landscape,
+        category: "dimension".to_string(),
+    });
+
+    // Time-based collections
+    let now = chrono::Utc::now().timestamp();
+    let seven_days_ago = now - (7 * 24 * 60 * 60);
+    let thirty_days_ago = now - (30 * 24 * 60 * 60);
+
+    let last_7_days: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE date_taken > ?1 AND archived_at IS NULL AND deleted_at IS NULL",
+        params![seven_days_ago],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "time_7days".to_string(),
+        name: "Last 7 Days".to_string(),
+        icon: "calendar".to_string(),
+        count: last_7_days,
+        category: "time".to_string(),
+    });
+
+    let last_30_days: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE date_taken > ?1 AND archived_at IS NULL AND deleted_at IS NULL",
+        params![thirty_days_ago],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "time_30days".to_string(),
+        name: "Last 30 Days".to_string(),
+        icon: "calendar".to_string(),
+        count: last_30_days,
+        category: "time".to_string(),
+    });
+
+    let current_year = chrono::Utc::now().format("%Y").to_string();
+    let this_year: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE strftime('%Y', date_taken, 'unixepoch') = ?1 AND archived_at IS NULL AND deleted_at IS NULL",
+        params![current_year],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "time_year".to_string(),
+        name: "This Year".to_string(),
+        icon: "calendar".to_string(),
+        count: this_year,
+        category: "time".to_string(),
+    });
+
+    // Status-based collections
+    let unreviewed: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM photos WHERE reviewed_at IS NULL AND archived_at IS NULL AND deleted_at IS NULL",
+        [],
+        |row| row.get(0),
+    ).unwrap_or(0);
+    collections.push(SmartCollection {
+        id: "status_unreviewed".to_string(),
+        name: "Unreviewed".to_string(),
+        icon: "eye-off".to_string(),
+        count: unreviewed,
+        category: "status".to_string(),
+    });
+
+    Ok(collections)
+}
+
+/// Get photos for a specific smart collection
+pub fn get_smart_collection_photos(conn: &Connection, collection_id: &str) -> SqlResult<Vec<PhotoMetadata>> {
+    let now = chrono::Utc::now().timestamp();
+    let seven_days_ago = now - (7 * 24 * 60 * 60);
+    let thirty_days_ago = now - (30 * 24 * 60 * 60);
+    let current_year = chrono::Utc::now().format("%Y").to_string();
+    let cols = PHOTO_COLUMNS;
+
+    // Time-based queries require bound parameters; handle before the static match.
+    if collection_id == "time_7days" {
+        let sql = format!("SELECT {} FROM photos WHERE date_taken > ?1 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC", cols);
+        let mut stmt = conn.prepare(&sql)?;
+        return query_photos(&mut stmt, params![seven_days_ago]);
+    } else if collection_id == "time_30days" {
+        let sql = format!("SELECT {} FROM photos WHERE date_taken > ?1 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC", cols);
+        let mut stmt = conn.prepare(&sql)?;
+        return query_photos(&mut stmt, params![thirty_days_ago]);
+    } else if collection_id == "time_year" {
+        let sql = format!("SELECT {} FROM photos WHERE strftime('%Y', date_taken, 'unixepoch') = ?1 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC", cols);
+        let mut stmt = conn.prepare(&sql)?;
+        return query_photos(&mut stmt, params![current_year]);
+    }
+
+    let where_clause = match collection_id {
+        "size_large"       => "file_size > 5242880 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY file_size DESC",
+        "size_medium"      => "file_size BETWEEN 1048576 AND 5242880 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY file_size DESC",
+        "size_small"       => "file_size < 1048576 AND file_size > 0 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY file_size DESC",
+        "dim_4k"           => "(width >= 3840 OR height >= 2160) AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        "dim_hd"           => "(width >= 1920 OR height >= 1080) AND width < 3840 AND height < 2160 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        "dim_portrait"     => "height > width AND width > 0 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        "dim_landscape"    => "width > height AND height > 0 AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        "status_unreviewed" => "reviewed_at IS NULL AND archived_at IS NULL AND deleted_at IS NULL ORDER BY date_taken DESC",
+        _ => return Ok(Vec::new()),
+    };
+
+    let sql = format!("SELECT {} FROM photos WHERE {}", cols, where_clause);
+    let mut stmt = conn.prepare(&sql)?;
+    query_photos(&mut stmt, [])
+}
+
+fn query_photos<P: rusqlite::Params>(stmt: &mut rusqlite::Statement, params: P) -> SqlResult<Vec<PhotoMetadata>> {
+    stmt.query_map(params, photo_from_row)?.collect()
+}
+
+// ============================================================================
+// Import Sessions
+// ============================================================================
+
+#[derive(serde::Serialize, Debug)]
+pub struct ImportSession {
+    pub id: i64,
+    pub started_at: i64,
+    /// "scan" or "upload"
+    pub source_type: String,
+    /// The scanned folder, or a description of the uploaded files
+    pub source: String,
+    /// Photos the session added (not ones it refreshed)
+    pub imported: i64,
+    pub failed: i64,
+    pub duration_ms: i64,
+    /// Photos from the session still in the library
+    pub photo_count: i64,
+}
+
+/// Record a finished import that started at `started_at_ms` (Unix millis) and
+/// stamp the rows it created among `paths`. Rows that already existed keep
+/// their session. Returns the new session id.
+pub fn record_import_session(
+    conn: &Connection,
+    source_type: &str,
+    source: &str,
+    started_at_ms: i64,
+    paths: &[String],
+    failed: usize,
+) -> SqlResult<i64> {
+    let tx = conn.unchecked_transaction()?;
+    let started_at = started_at_ms.div_euclid(1000);
+    let duration_ms = (chrono::Utc::now().timestamp_millis() - started_at_ms).max(0);
+    tx.execute(
+        "INSERT INTO import_sessions (started_at, source_type, source, failed, duration_ms) VALUES (?1, ?2, ?3, ?4, ?5)",
+        params![started_at, source_type, source, failed as i64, duration_ms],
+    )?;
+    let id = tx.last_insert_rowid();
+
+    let mut imported = 0;
+    for chunk in paths.chunks(MAX_IN_PARAMS - 2) {
+        let placeholders = vec!["?"; chunk.len()].join(",");
+        let sql = format!(
+            "UPDATE photos SET session_id = ? WHERE session_id IS NULL AND created_at >= ? AND path IN ({})",
+            placeholders
+        );
+        let mut values: Vec<rusqlite::types::Value> = vec![id.into(), started_at.into()];
+        values.extend(chunk.iter().map(|path| path.clone().into()));
+        imported += tx.execute(&sql, rusqlite::params_from_iter(values))?;
+    }
+    tx.execute("UPDATE import_sessions SET imported = ?1 WHERE id = ?2", params![imported as i64, id])?;
+    tx.commit()?;
+    Ok(id)
+}
+
+/// Every import session, newest first
+pub fn get_import_sessions(conn: &Connection) -> SqlResult<Vec<ImportSession>> {
+    let mut stmt = conn.prepare(
+        "SELECT s.id, s.started_at, s.source_type, s.source, s.imported, s.failed, s.duration_ms, COUNT(p.id)
+         FROM import_sessions s
+         LEFT JOIN photos p ON p.session_id = s.id AND p.deleted_at IS NULL
+         GROUP BY s.id
+         ORDER BY s.started_at DESC, s.id DESC",
+    )?;
+    let rows = stmt.query_map([], |row| Ok(ImportSession {
+        id: row.get(0)?,
+        started_at: row.get(1)?,
+        source_type: row.get(2)?,
+        source: row.get(3)?,
+        imported: row.get(4)?,
+        failed: row.get(5)?,
+        duration_ms: row.get(6)?,
+        photo_count: row.get(7)?,
+    }))?;
+    rows.collect()
+}
+
+/// Photos a session added that are still in the library, Live Photo videos included
+pub fn get_session_photos(conn: &Connection, session_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos WHERE session_id = ?1 AND deleted_at IS NULL ORDER BY date_taken DESC, id DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![session_id], photo_from_row)?;
+    rows.collect()
+}
+
+/// How many of a session's photos are in an album
+pub fn count_session_photos_in_albums(conn: &Connection, session_id: i64) -> SqlResult<i64> {
+    conn.query_row(
+        "SELECT COUNT(DISTINCT p.id) FROM photos p JOIN album_photos ap ON ap.photo_id = p.id
+         WHERE p.session_id = ?1 AND p.deleted_at IS NULL",
+        params![session_id],
+        |row| row.get(0),
+    )
+}
+
+/// Forget a session. Its photos stay, no longer attributed to it.
+/// Returns false if there was no such session.
+pub fn delete_import_session(conn: &Connection, session_id: i64) -> SqlResult<bool> {
+    Ok(conn.execute("DELETE FROM import_sessions WHERE id = ?1", params![session_id])? > 0)
+}
+
+// ============================================================================
+// Storage Analytics Functions
+// ============================================================================
+
+/// Where the library's bytes go. Trashed photos are left out of every figure
+/// except their own; so are photos in the deletion archive. Photos, videos
+/// and screenshots don't overlap, so their sizes add up to the total.
+#[derive(serde::Serialize, Debug)]
+pub struct StorageAnalytics {
+    pub total_size_bytes: i64,
+    /// Stills that aren't screenshots
+    pub total_photos: i64,
+    pub total_videos: i64,
+    pub total_screenshots: i64,
+    pub photos_size: i64,
+    pub videos_size: i64,
+    pub screenshots_size: i64,
+    /// Every copy past the first in each exact-duplicate group
+    pub duplicate_space_bytes: i64,
+    /// Off the timeline via set_archived
+    pub total_archived: i64,
+    pub total_hidden: i64,
+    pub trashed_count: i64,
+    /// Space emptying the trash would free
+    pub trashed_size_bytes: i64,
+    /// Newest first, with undated photos under UNKNOWN_PERIOD last
+    pub size_by_month: Vec<MonthSize>,
+    pub size_by_year: Vec<YearSize>,
+    /// Photos added per month by import sessions still in the library, last 12 months
+    pub imports_by_month: Vec<MonthSize>,
+    pub top_largest_files: Vec<LargeFile>,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct MonthSize {
+    pub month: String,
+    pub size: i64,
+    pub count: i64,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct YearSize {
+    pub year: String,
+    pub size: i64,
+    pub count: i64,
+}
+
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct LargeFile {
+    pub path: String,
+    pub name: String,
+    pub size: i64,
+    pub date_taken: i64,
+}
+
+/// (period, bytes, count) of library photos per period, newest first, read
+/// on each photo's own clock as the timeline does.
+fn size_by_period(
+    conn: &Connection,
+    granularity: TimelineGranularity,
+    default_offset: Option<i32>,
+) -> SqlResult<Vec<(String, i64, i64)>> {
+    let query = format!(
+        "SELECT {} AS period, COALESCE(SUM(file_size), 0), COUNT(*)
+         FROM photos
+         WHERE archived_at IS NULL AND deleted_at IS NULL
+         GROUP BY period
+         ORDER BY period = ?1, period DESC",
+        granularity.period_sql(default_offset)
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![UNKNOWN_PERIOD], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// Every StorageAnalytics figure, aggregated in SQL, with the `top_files`
+/// largest files.
+pub fn get_storage_analytics(
+    conn: &Connection,
+    default_offset: Option<i32>,
+    top_files: i64,
+) -> SqlResult<StorageAnalytics> {
+    let video = is_video_sql();
+    let query = format!(
+        "SELECT
+             COALESCE(SUM(CASE WHEN live THEN file_size END), 0),
+             COALESCE(SUM(live AND NOT video AND NOT screenshot), 0),
+             COALESCE(SUM(live AND video), 0),
+             COALESCE(SUM(live AND NOT video AND screenshot), 0),
+             COALESCE(SUM(CASE WHEN live AND NOT video AND NOT screenshot THEN file_size END), 0),
+             COALESCE(SUM(CASE WHEN live AND video THEN file_size END), 0),
+             COALESCE(SUM(CASE WHEN live AND NOT video AND screenshot THEN file_size END), 0),
+             COALESCE(SUM(live AND is_archived = 1), 0),
+             COALESCE(SUM(live AND is_hidden = 1), 0),
+             COALESCE(SUM(deleted_at IS NOT NULL), 0),
+             COALESCE(SUM(CASE WHEN deleted_at IS NOT NULL THEN file_size END), 0)
+         FROM (
+             SELECT file_size, is_archived, is_hidden, deleted_at,
+                    archived_at IS NULL AND deleted_at IS NULL AS live,
+                    ({}) AS video,
+                    COALESCE(is_screenshot, 0) = 1 AS screenshot
+             FROM photos
+         )",
+        video
+    );
+    let mut analytics = conn.query_row(&query, [], |row| Ok(StorageAnalytics {
+        total_size_bytes: row.get(0)?,
+        total_photos: row.get(1)?,
+        total_videos: row.get(2)?,
+        total_screenshots: row.get(3)?,
+        photos_size: row.get(4)?,
+        videos_size: row.get(5)?,
+        screenshots_size: row.get(6)?,
+        duplicate_space_bytes: 0,
+        total_archived: row.get(7)?,
+        total_hidden: row.get(8)?,
+        trashed_count: row.get(9)?,
+        trashed_size_bytes: row.get(10)?,
+        size_by_month: Vec::new(),
+        size_by_year: Vec::new(),
+        imports_by_month: Vec::new(),
+        top_largest_files: Vec::new(),
+    }))?;
+
+    // The groups find_exact_duplicates lists, each keeping one copy
+    analytics.duplicate_space_bytes = conn.query_row(
+        "SELECT COALESCE(SUM(size * (copies - 1)), 0) FROM (
+             SELECT MAX(file_size) AS size, COUNT(*) AS copies FROM photos
+             WHERE archived_at IS NULL AND deleted_at IS NULL AND content_hash IS NOT NULL
+             GROUP BY content_hash HAVING COUNT(*) > 1
+         )",
+        [],
+        |row| row.get(0),
+    )?;
+
+    analytics.size_by_month = size_by_period(conn, TimelineGranularity::Month, default_offset)?
+        .into_iter()
+        .map(|(month, size, count)| MonthSize { month, size, count })
+        .collect();
+    analytics.size_by_year = size_by_period(conn, TimelineGranularity::Year, default_offset)?
+        .into_iter()
+        .map(|(year, size, count)| YearSize { year, size, count })
+        .collect();
+
+    analytics.imports_by_month = conn.prepare(
+        "SELECT strftime('%Y-%m', s.started_at, 'unixepoch') as month,
+                COALESCE(SUM(p.file_size), 0) as size,
+                COUNT(p.id) as count
+         FROM import_sessions s
+         LEFT JOIN photos p ON p.session_id = s.id AND p.deleted_at IS NULL
+         WHERE s.started_at > strftime('%s', 'now', '-12 months')
+         GROUP BY month
+         ORDER BY month DESC"
+    )?.query_map([], |row| Ok(MonthSize {
+        month: row.get(0)?,
+        size: row.get(1)?,
+        count: row.get(2)?,
+    }))?.collect::<SqlResult<_>>()?;
+
+    analytics.top_largest_files = conn.prepare(
+        "SELECT path, name, file_size, date_taken
+         FROM photos
+         WHERE archived_at IS NULL AND deleted_at IS NULL AND file_size IS NOT NULL
+         ORDER BY file_size DESC, path
+         LIMIT ?1"
+    )?.query_map(params![top_files], |row| Ok(LargeFile {
+        path: row.get(0)?,
+        name: row.get(1)?,
+        size: row.get(2)?,
+        date_taken: row.get(3)?,
+    }))?.collect::<SqlResult<_>>()?;
+
+    Ok(analytics)
+}
+
+// ============================================================================
+// Enriched Metadata Functions
+// ============================================================================
+
+/// Write enriched camera/lens/video metadata for a single photo path and
+/// mark it enriched, even when exiftool found nothing.
+/// Only the enrichment columns are updated; all other columns are untouched.
+pub fn update_enriched_metadata(conn: &Connection, path: &str, meta: &crate::metadata_enrich::EnrichedMetadata) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET \
+         camera_make = ?1, camera_model = ?2, lens_model = ?3, \
+         iso = ?4, aperture = ?5, shutter_us = ?6, focal_length_mm = ?7, \
+         orientation = ?8, duration_ms = ?9, codec = ?10, enriched_at = ?12 \
+         WHERE path = ?11",
+        params![
+            meta.camera_make,
+            meta.camera_model,
+            meta.lens_model,
+            meta.iso,
+            meta.aperture,
+            meta.shutter_us,
+            meta.focal_length_mm,
+            meta.orientation,
+            meta.duration_ms,
+            meta.codec,
+            path,
+            chrono::Utc::now().timestamp(),
+        ],
+    )?;
+    Ok(())
+}
+
+/// A photo's stored shape after its file changed, the `photo_changed` payload.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct ChangedPhoto {
+    pub path: String,
+    pub width: u32,
+    pub height: u32,
+    pub orientation: Option<i32>,
+    pub content_hash: Option<String>,
+}
+
+/// Record a rotation written to a photo's file: its displayed dimensions
+/// (as `media::read_dimensions` reads them, when it could), the new
+/// orientation (None for re-encoded pixels, which keep theirs) and content
+/// hash replace the old, and the thumbnail is due again. Returns the row as it now is, and the old hash if no row shares it
+/// any more so the caller can drop its cached thumbnails. None if the photo
+/// isn't in the library.
+pub fn record_photo_rotation(
+    conn: &Connection,
+    path: &str,
+    dimensions: Option<(u32, u32)>,
+    orientation: Option<i32>,
+    content_hash: Option<&str>,
+) -> SqlResult<Option<(ChangedPhoto, Option<String>)>> {
+    record_file_change(conn, path, |tx| {
+        tx.execute(
+            "UPDATE photos SET
+                 width = COALESCE(?2, width),
+                 height = COALESCE(?3, height),
+                 orientation = COALESCE(?4, orientation),
+                 content_hash = COALESCE(?5, content_hash),
+                 thumb_status = NULL
+             WHERE path = ?1",
+            params![path, dimensions.map(|d| d.0), dimensions.map(|d| d.1), orientation, content_hash],
+        )
+    })
+}
+
+/// Record a photo's file as another app saved it: its dimensions (when they
+/// could be read) and content hash replace the old, and the thumbnail is
+/// due again. Returns as `record_photo_rotation` does.
+pub fn record_external_edit(
+    conn: &Connection,
+    path: &str,
+    dimensions: Option<(u32, u32)>,
+    content_hash: Option<&str>,
+) -> SqlResult<Option<(ChangedPhoto, Option<String>)>> {
+    record_file_change(conn, path, |tx| {
+        tx.execute(
+            "UPDATE photos SET
+                 width = COALESCE(?2, width),
+                 height = COALESCE(?3, height),
+                 content_hash = COALESCE(?4, content_hash),
+                 thumb_status = NULL
+             WHERE path = ?1",
+            params![path, dimensions.map(|d| d.0), dimensions.map(|d| d.1), content_hash],
+        )
+    })
+}
+
+/// Run `update` on a photo's row in a transaction, then read back its shape
+/// and check whether its old content hash is still used.
+fn record_file_change(
+    conn: &Connection,
+    path: &str,
+    update: impl FnOnce(&Connection) -> SqlResult<usize>,
+) -> SqlResult<Option<(ChangedPhoto, Option<String>)>> {
+    let tx = conn.unchecked_transaction()?;
+    let Some(old_hash) = tx
+        .query_row("SELECT content_hash FROM photos WHERE path = ?1", params![path], |row| row.get::<_, Option<String>>(0))
+        .optional()?
+    else {
+        return Ok(None);
+    };
+    update(&tx)?;
+    let changed = tx.query_row(
+        "SELECT width, height, orientation, content_hash FROM photos WHERE path = ?1",
+        params![path],
+        |row| {
+            Ok(ChangedPhoto {
+                path: path.to_string(),
+                width: row.get(0)?,
+                height: row.get(1)?,
+                orientation: row.get(2)?,
+                content_hash: row.get(3)?,
+            })
+        },
+    )?;
+    let orphaned = match old_hash {
+        Some(hash) => {
+            let shared: bool = tx.query_row(
+                "SELECT EXISTS(SELECT 1 FROM photos WHERE content_hash = ?1)",
+                params![hash],
+                |row| row.get(0),
+            )?;
+            (!shared).then_some(hash)
+        }
+        None => None,
+    };
+    tx.commit()?;
+    Ok(Some((changed, orphaned)))
+}
+
+/// Get paths of photos that have not yet been enriched. A photo whose file
+/// changes since is enriched again.
+pub fn get_photos_without_enrichment(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos WHERE enriched_at IS NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Get (path, content_hash) for every photo whose thumbnail is missing.
+/// Skips archived photos and photos without a content hash (rare; can't be addressed).
+pub fn get_photos_without_thumbnails(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, content_hash FROM photos \
+         WHERE thumb_status IS NULL AND content_hash IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Get (path, content_hash) for photos still showing the camera's embedded
+/// EXIF preview, which a full backfill upgrades to a proper thumbnail.
+pub fn get_photos_with_embedded_thumbnails(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, content_hash FROM photos \
+         WHERE thumb_status = 'embedded' AND content_hash IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Set the thumb_status for one photo. Caller passes 'ready', 'embedded' or 'failed'.
+pub fn set_thumb_status(conn: &Connection, path: &str, status: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET thumb_status = ?1 WHERE path = ?2",
+        params![status, path],
+    )?;
+    Ok(())
+}
+
+/// Set the thumbnail status of many photos by path in one transaction
+pub fn set_thumb_statuses_batch(conn: &Connection, statuses: &[(String, &str)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET thumb_status = ?2 WHERE path = ?1")?;
+        for (path, status) in statuses {
+            stmt.execute(params![path, status])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Store the grid placeholders of many photos by path in one transaction
+pub fn set_placeholders_batch(conn: &Connection, placeholders: &[(String, crate::placeholders::Placeholder)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE photos SET blurhash = ?2, dominant_color = ?3 WHERE path = ?1")?;
+        for (path, placeholder) in placeholders {
+            stmt.execute(params![path, placeholder.blurhash, placeholder.dominant_color])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Get (path, content_hash) for photos with a grid thumbnail (or video
+/// poster) cached but no placeholder, for the placeholders backfill
+pub fn get_photos_without_placeholders(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, content_hash FROM photos \
+         WHERE blurhash IS NULL AND thumb_status IN ('ready', 'embedded') AND content_hash IS NOT NULL \
+         AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Forget every thumbnail status after the cache was purged, so the next
+/// backfill regenerates them. Returns how many rows were reset.
+pub fn clear_thumb_statuses(conn: &Connection) -> SqlResult<usize> {
+    conn.execute("UPDATE photos SET thumb_status = NULL WHERE thumb_status IS NOT NULL", [])
+}
+
+/// Update file size for a photo
+pub fn update_photo_file_size(conn: &Connection, path: &str, size: i64) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET file_size = ?1 WHERE path = ?2",
+        params![size, path],
+    )?;
+    Ok(())
+}
+
+/// Get photos missing a file_size or mime_type
+pub fn get_photos_without_file_size(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos
+         WHERE (file_size IS NULL OR mime_type IS NULL) AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Fill in (path, file_size, mime_type) for many photos in one transaction.
+/// A None keeps what the row has.
+pub fn update_file_info_batch(conn: &Connection, info: &[(String, Option<i64>, Option<String>)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(&format!(
+            "UPDATE photos SET file_size = COALESCE(?1, file_size), mime_type = COALESCE(?2, mime_type) WHERE path = ?3 COLLATE {}",
+            PATH_COLLATION
+        ))?;
+        for (path, size, mime_type) in info {
+            stmt.execute(params![size, mime_type, path])?;
+        }
+    }
+    tx.commit()
+}
+
+// ============================================================================
+// Exact Duplicates (content hash)
+// ============================================================================
+
+/// Photos with byte-identical content. Every copy after the first is
+/// reclaimable, so `reclaimable_bytes` = file_size * (count - 1).
+#[derive(serde::Serialize)]
+pub struct ExactDuplicateGroup {
+    pub content_hash: String,
+    pub photos: Vec<PhotoMetadata>,
+    /// None until populate_file_sizes (or a rescan) has recorded it
+    pub file_size: Option<i64>,
+    pub reclaimable_bytes: i64,
+}
+
+/// Group non-archived photos by content_hash, dropping singletons.
+/// Sorted by reclaimable bytes, largest first.
+pub fn find_exact_duplicates(conn: &Connection) -> SqlResult<Vec<ExactDuplicateGroup>> {
+    let query = format!(
+        "SELECT {}, file_size FROM photos \
+         WHERE archived_at IS NULL AND deleted_at IS NULL AND content_hash IN ( \
+             SELECT content_hash FROM photos \
+             WHERE archived_at IS NULL AND deleted_at IS NULL AND content_hash IS NOT NULL \
+             GROUP BY content_hash HAVING COUNT(*) > 1 \
+         ) \
+         ORDER BY content_hash, date_taken ASC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| Ok((photo_from_row(row)?, row.get::<_, Option<i64>>(FIRST_EXTRA_COLUMN)?)))?;
+
+    let mut groups: Vec<ExactDuplicateGroup> = Vec::new();
+    for row in rows {
+        let (photo, size) = row?;
+        let hash = photo.content_hash.clone().unwrap_or_default();
+        match groups.last_mut() {
+            Some(group) if group.content_hash == hash => {
+                group.file_size = group.file_size.or(size);
+                group.photos.push(photo);
+            }
+            _ => groups.push(ExactDuplicateGroup {
+                content_hash: hash,
+                photos: vec![photo],
+                file_size: size,
+                reclaimable_bytes: 0,
+            }),
+        }
+    }
+
+    for group in &mut groups {
+        group.reclaimable_bytes = group.file_size.unwrap_or(0) * (group.photos.len() as i64 - 1);
+    }
+    groups.sort_by(|a, b| b.reclaimable_bytes.cmp(&a.reclaimable_bytes));
+    Ok(groups)
+}
+
+/// (path, content_hash) for every photo with a stored hash, archived ones
+/// included since their files still live in the library.
+pub fn get_hashed_photos(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, content_hash FROM photos WHERE content_hash IS NOT NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+/// Get paths of photos that were imported without a content hash
+pub fn get_photos_without_content_hash(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos WHERE content_hash IS NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Store computed content hashes in a single transaction. Thumbnails are
+/// cached by hash, so a photo whose hash changed needs them made again.
+pub fn update_content_hashes_batch(conn: &Connection, hashes: &[(String, String)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET content_hash = ?1,
+                 thumb_status = CASE WHEN content_hash IS ?1 THEN thumb_status END
+             WHERE path = ?2",
+        )?;
+        for (path, hash) in hashes {
+            stmt.execute(params![hash, path])?;
+        }
+    }
+    tx.commit()
+}
+
+// ============================================================================
+// Near Duplicates (perceptual hash)
+// ============================================================================
+
+/// Get paths of non-archived photos that have no perceptual hash yet
+pub fn get_photos_without_phash(conn: &Connection) -> SqlResult<Vec<String>> {
+    let mut stmt = conn.prepare(
+        "SELECT path FROM photos WHERE phash IS NULL AND archived_at IS NULL AND deleted_at IS NULL"
+    )?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+/// Store perceptual hashes ([0°, 90°, 180°, 270°]) in a single transaction
+pub fn update_phashes_batch(conn: &Connection, hashes: &[(String, [u64; 4])]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET phash = ?1, phash_r90 = ?2, phash_r180 = ?3, phash_r270 = ?4 WHERE path = ?5"
+        )?;
+        for (path, h) in hashes {
+            stmt.execute(params![h[0] as i64, h[1] as i64, h[2] as i64, h[3] as i64, path])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Get every non-archived photo that has perceptual hashes, with the hashes
+pub fn get_photos_with_phash(conn: &Connection) -> SqlResult<Vec<(PhotoMetadata, [u64; 4])>> {
+    let query = format!(
+        "SELECT {}, phash, phash_r90, phash_r180, phash_r270 FROM photos \
+         WHERE phash IS NOT NULL AND archived_at IS NULL AND deleted_at IS NULL",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| {
+        let hashes = [
+            row.get::<_, i64>(FIRST_EXTRA_COLUMN)? as u64,
+            row.get::<_, Option<i64>>(FIRST_EXTRA_COLUMN + 1)?.unwrap_or_default() as u64,
+            row.get::<_, Option<i64>>(FIRST_EXTRA_COLUMN + 2)?.unwrap_or_default() as u64,
+            row.get::<_, Option<i64>>(FIRST_EXTRA_COLUMN + 3)?.unwrap_or_default() as u64,
+        ];
+        Ok((photo_from_row(row)?, hashes))
+    })?;
+    rows.collect()
+}
+
+// ============================================================================
+// Photo Edits
+// ============================================================================
+
+/// Store the edit recipe for a photo, replacing any it had. A recipe that
+/// changes nothing clears the edits instead. False if the photo isn't in
+/// the library.
+pub fn set_photo_edits(conn: &Connection, path: &str, edits: &PhotoEdits) -> SqlResult<bool> {
+    if edits.is_identity() {
+        clear_photo_edits(conn, path)?;
+        return photo_exists(conn, path);
+    }
+    let recipe = serde_json::to_string(edits).map_err(|e| rusqlite::Error::ToSqlConversionFailure(Box::new(e)))?;
+    let changed = conn.execute(
+        "INSERT INTO photo_edits (photo_id, recipe, updated_at)
+         SELECT id, ?2, ?3 FROM photos WHERE path = ?1
+         ON CONFLICT(photo_id) DO UPDATE SET recipe = excluded.recipe, updated_at = excluded.updated_at",
+        params![path, recipe, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(changed > 0)
+}
+
+/// The edit recipe of a photo, or None if it has none (or isn't in the library).
+pub fn get_photo_edits(conn: &Connection, path: &str) -> SqlResult<Option<PhotoEdits>> {
+    let recipe: Option<String> = conn
+        .query_row(
+            "SELECT e.recipe FROM photo_edits e JOIN photos p ON p.id = e.photo_id WHERE p.path = ?1",
+            params![path],
+            |row| row.get(0),
+        )
+        .optional()?;
+    Ok(recipe.and_then(|r| serde_json::from_str(&r).ok()))
+}
+
+/// Drop a photo's edits. Returns whether it had any.
+pub fn clear_photo_edits(conn: &Connection, path: &str) -> SqlResult<bool> {
+    let removed = conn.execute(
+        "DELETE FROM photo_edits WHERE photo_id = (SELECT id FROM photos WHERE path = ?1)",
+        params![path],
+    )?;
+    Ok(removed > 0)
+}
+
+// ============================================================================
+// Trash
+// ============================================================================
+
+/// Get the path to the trash directory inside the library
+pub fn get_trash_path(conn: &Connection) -> Result<PathBuf, LibraryPathError> {
+    let mut path = get_library_path(conn)?;
+    path.push(".trash");
+    std::fs::create_dir_all(&path).map_err(|e| LibraryPathError::Io(path.clone(), e))?;
+    Ok(path)
+}
+
+/// Soft-delete a photo. `trash_path` is where its file now lives, if it was moved.
+pub fn trash_photo(conn: &Connection, path: &str, trash_path: Option<&str>) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE photos SET deleted_at = ?1, trash_path = ?2 WHERE path = ?3",
+        params![now, trash_path, path],
+    )?;
+    Ok(())
+}
+
+/// Clear the trash flag on a photo; its file is back, so it isn't missing either
+pub fn untrash_photo(conn: &Connection, path: &str) -> SqlResult<()> {
+    conn.execute(
+        "UPDATE photos SET deleted_at = NULL, trash_path = NULL, is_missing = 0 WHERE path = ?1",
+        params![path],
+    )?;
+    Ok(())
+}
+
+/// Get trashed photos with (trash_path, deleted_at), most recently deleted first
+pub fn get_trashed_photos(conn: &Connection) -> SqlResult<Vec<(PhotoMetadata, Option<String>, i64)>> {
+    let query = format!(
+        "SELECT {}, trash_path, deleted_at FROM photos WHERE deleted_at IS NOT NULL ORDER BY deleted_at DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map([], |row| {
+        Ok((photo_from_row(row)?, row.get("trash_path")?, row.get("deleted_at")?))
+    })?;
+    rows.collect()
+}
+
+/// Get (path, trash_path) for the given trashed photos
+pub fn get_trash_entries(conn: &Connection, paths: &[String]) -> SqlResult<Vec<(String, Option<String>)>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT path, trash_path FROM photos WHERE path = ?1 AND deleted_at IS NOT NULL"
+    )?;
+    let mut entries = Vec::new();
+    for path in paths {
+        if let Some(entry) = stmt
+            .query_row(params![path], |row| Ok((row.get(0)?, row.get(1)?)))
+            .optional()?
+        {
+            entries.push(entry);
+        }
+    }
+    Ok(entries)
+}
+
+/// Get (path, trash_path) for photos trashed before `cutoff` (unix seconds)
+pub fn get_trashed_before(conn: &Connection, cutoff: i64) -> SqlResult<Vec<(String, Option<String>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT path, trash_path FROM photos WHERE deleted_at IS NOT NULL AND deleted_at < ?1"
+    )?;
+    let rows = stmt.query_map(params![cutoff], |row| Ok((row.get(0)?, row.get(1)?)))?;
+    rows.collect()
+}
+
+// ============================================================================
+// Operations Log
+// ============================================================================
+
+/// A logged operation, as get_recent_operations lists it.
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct OperationSummary {
+    pub id: i64,
+    pub kind: String,
+    pub description: String,
+    pub photo_count: usize,
+    pub created_at: i64,
+}
+
+/// Log `operation` for undo; returns its id.
+pub fn record_operation(conn: &Connection, operation: &Operation) -> SqlResult<i64> {
+    let to_json = |e: serde_json::Error| rusqlite::Error::ToSqlConversionFailure(Box::new(e));
+    let before_state = serde_json::to_string(operation).map_err(to_json)?;
+    let targets = serde_json::to_string(&operation.photo_ids()).map_err(to_json)?;
+    conn.execute(
+        "INSERT INTO operations (kind, targets, before_state, created_at) VALUES (?1, ?2, ?3, ?4)",
+        params![operation.kind(), targets, before_state, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+/// An operations row (id, kind, before_state, created_at) as its summary
+/// and its record, which is None if it can't be read (e.g. it was written
+/// by a newer build).
+fn operation_from_row(row: &rusqlite::Row) -> SqlResult<(OperationSummary, Option<Operation>)> {
+    let kind: String = row.get(1)?;
+    let operation = serde_json::from_str::<Operation>(&row.get::<_, String>(2)?).ok();
+    let summary = OperationSummary {
+        id: row.get(0)?,
+        description: operation.as_ref().map_or_else(|| kind.clone(), Operation::describe),
+        photo_count: operation.as_ref().map_or(0, |op| op.photo_ids().len()),
+        kind,
+        created_at: row.get(3)?,
+    };
+    Ok((summary, operation))
+}
+
+/// Logged operations, newest first, at most `limit`.
+pub fn get_recent_operations(conn: &Connection, limit: i64) -> SqlResult<Vec<OperationSummary>> {
+    let mut stmt = conn.prepare("SELECT id, kind, before_state, created_at FROM operations ORDER BY id DESC LIMIT ?1")?;
+    let rows = stmt.query_map(params![limit], |row| operation_from_row(row).map(|(summary, _)| summary))?;
+    rows.collect()
+}
+
+/// The newest logged operation, with its record (see `operation_from_row`).
+pub fn get_last_operation(conn: &Connection) -> SqlResult<Option<(OperationSummary, Option<Operation>)>> {
+    conn.query_row("SELECT id, kind, before_state, created_at FROM operations ORDER BY id DESC LIMIT 1", [], operation_from_row)
+        .optional()
+}
+
+/// Drop an operation from the log
+pub fn delete_operation(conn: &Connection, id: i64) -> SqlResult<()> {
+    conn.execute("DELETE FROM operations WHERE id = ?1", params![id])?;
+    Ok(())
+}
+
+/// Forget operations logged before `cutoff` (unix seconds). Returns how many.
+pub fn prune_operations(conn: &Connection, cutoff: i64) -> SqlResult<usize> {
+    conn.execute("DELETE FROM operations WHERE created_at < ?1", params![cutoff])
+}
+
+/// Ids of the photos at `paths`; paths with no photo are skipped.
+pub fn get_photo_ids(conn: &Connection, paths: &[String]) -> SqlResult<Vec<i64>> {
+    let mut stmt = conn.prepare_cached("SELECT id FROM photos WHERE path = ?1")?;
+    let mut ids = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(id) = stmt.query_row(params![path], |row| row.get(0)).optional()? {
+            ids.push(id);
+        }
+    }
+    Ok(ids)
+}
+
+/// Each photo's current path and whether it is in the trash, in the order
+/// given; None for an id whose row is gone.
+pub fn get_photo_paths_by_id(conn: &Connection, ids: &[i64]) -> SqlResult<Vec<Option<(String, bool)>>> {
+    let mut stmt = conn.prepare_cached("SELECT path, deleted_at IS NOT NULL FROM photos WHERE id = ?1")?;
+    ids.iter()
+        .map(|id| stmt.query_row(params![id], |row| Ok((row.get(0)?, row.get(1)?))).optional())
+        .collect()
+}
+
+/// (photo id, added_at) of the photos at `paths` that are in `album_id`.
+pub fn get_album_members(conn: &Connection, album_id: i64, paths: &[String]) -> SqlResult<Vec<(i64, i64)>> {
+    let mut stmt = conn.prepare_cached(
+        "SELECT ap.photo_id, ap.added_at FROM album_photos ap JOIN photos p ON p.id = ap.photo_id
+         WHERE ap.album_id = ?1 AND p.path = ?2",
+    )?;
+    let mut members = Vec::new();
+    for path in paths {
+        if let Some(member) = stmt.query_row(params![album_id, path], |row| Ok((row.get(0)?, row.get(1)?))).optional()? {
+            members.push(member);
+        }
+    }
+    Ok(members)
+}
+
+/// Put album memberships back with their original added_at. False if the
+/// album is gone; photos that are gone are skipped.
+pub fn restore_album_members(conn: &Connection, album_id: i64, members: &[(i64, i64)]) -> SqlResult<bool> {
+    let tx = conn.unchecked_transaction()?;
+    let exists: bool = tx.query_row("SELECT EXISTS(SELECT 1 FROM albums WHERE id = ?1)", params![album_id], |row| row.get(0))?;
+    if !exists {
+        return Ok(false);
+    }
+    {
+        let mut stmt = tx.prepare_cached(
+            "INSERT OR IGNORE INTO album_photos (album_id, photo_id, added_at)
+             SELECT ?1, id, ?3 FROM photos WHERE id = ?2",
+        )?;
+        for &(photo_id, added_at) in members {
+            stmt.execute(params![album_id, photo_id, added_at])?;
+        }
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+/// (photo id, favorite flag) of the photos at `paths`.
+pub fn get_favorite_flags(conn: &Connection, paths: &[String]) -> SqlResult<Vec<(i64, bool)>> {
+    let mut stmt = conn.prepare_cached("SELECT id, COALESCE(is_favorite, 0) FROM photos WHERE path = ?1")?;
+    let mut flags = Vec::with_capacity(paths.len());
+    for path in paths {
+        if let Some(flag) = stmt.query_row(params![path], |row| Ok((row.get(0)?, row.get::<_, i64>(1)? != 0))).optional()? {
+            flags.push(flag);
+        }
+    }
+    Ok(flags)
+}
+
+/// Set each photo's favorite flag back by id, unless any of them is no
+/// longer `expected` (changed again since). True if they were restored.
+pub fn restore_favorite_flags(conn: &Connection, flags: &[(i64, bool)], expected: bool) -> SqlResult<bool> {
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut current = tx.prepare_cached("SELECT COALESCE(is_favorite, 0) != 0 FROM photos WHERE id = ?1")?;
+        for &(photo_id, _) in flags {
+            let flag: Option<bool> = current.query_row(params![photo_id], |row| row.get(0)).optional()?;
+            if flag.is_some_and(|flag| flag != expected) {
+                return Ok(false);
+            }
+        }
+        let mut stmt = tx.prepare_cached("UPDATE photos SET is_favorite = ?1 WHERE id = ?2")?;
+        for &(photo_id, is_favorite) in flags {
+            stmt.execute(params![is_favorite, photo_id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(true)
+}
+
+/// A live photo's stored date fields, for the undo log.
+pub fn get_previous_date(conn: &Connection, path: &str) -> SqlResult<Option<PreviousDate>> {
+    conn.query_row(
+        "SELECT id, date_taken, date_source, date_confident, filename_date_mismatch
+         FROM photos WHERE path = ?1 AND deleted_at IS NULL",
+        params![path],
+        |row| {
+            Ok(PreviousDate {
+                photo_id: row.get(0)?,
+                date_taken: row.get(1)?,
+                date_source: row.get(2)?,
+                date_confident: row.get(3)?,
+                filename_date_mismatch: row.get(4)?,
+            })
+        },
+    )
+    .optional()
+}
+
+/// Put a photo's date fields back, unless its date is no longer `expected`
+/// (changed again since). True if they were restored.
+pub fn restore_previous_date(conn: &Connection, previous: &PreviousDate, expected: i64) -> SqlResult<bool> {
+    let updated = conn.execute(
+        "UPDATE photos SET date_taken = ?1, date_source = ?2, date_confident = ?3, filename_date_mismatch = ?4
+         WHERE id = ?5 AND date_taken = ?6",
+        params![
+            previous.date_taken,
+            previous.date_source,
+            previous.date_confident,
+            previous.filename_date_mismatch,
+            previous.photo_id,
+            expected
+        ],
+    )?;
+    Ok(updated > 0)
+}
+
+/// Change a photo's path everywhere it is referenced (photos, album covers,
+/// Live Photo pairs). Album and tag memberships follow the photo's id.
+pub fn update_photo_path(conn: &Connection, old_path: &str, new_path: &str) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    repoint_photo_path(&tx, old_path, new_path)?;
+    tx.commit()
+}
+
+fn repoint_photo_path(conn: &Connection, old_path: &str, new_path: &str) -> SqlResult<()> {
+    conn.execute("UPDATE photos SET path = ?1 WHERE path = ?2", params![new_path, old_path])?;
+    conn.execute(
+        "UPDATE albums SET cover_photo_path = ?1 WHERE cover_photo_path = ?2",
+        params![new_path, old_path],
+    )?;
+    conn.execute(
+        "UPDATE photos SET paired_video_path = ?1 WHERE paired_video_path = ?2",
+        params![new_path, old_path],
+    )?;
+    Ok(())
+}
+
+fn rename_photo_row(conn: &Connection, old_path: &str, new_path: &str) -> SqlResult<()> {
+    let name = std::path::Path::new(new_path)
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    repoint_photo_path(conn, old_path, new_path)?;
+    conn.execute("UPDATE photos SET name = ?1 WHERE path = ?2", params![name, new_path])?;
+    Ok(())
+}
+
+/// rename_photo for many (old path, new path) pairs in one transaction. One
+/// may take the path another is leaving, so every row is parked on a
+/// placeholder (never an absolute path) first.
+pub fn rename_photos(conn: &Connection, renames: &[(String, String)]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    let parked = |i: usize| format!("terra-renaming:{}", i);
+    for (i, (old_path, _)) in renames.iter().enumerate() {
+        repoint_photo_path(&tx, old_path, &parked(i))?;
+    }
+    for (i, (_, new_path)) in renames.iter().enumerate() {
+        rename_photo_row(&tx, &parked(i), new_path)?;
+    }
+    tx.commit()
+}
+
+/// update_photo_path for a photo whose file was renamed: its name follows
+/// the new path too. Returns the updated photo, or None if there is no
+/// photo at `old_path`.
+pub fn rename_photo(conn: &Connection, old_path: &str, new_path: &str) -> SqlResult<Option<PhotoMetadata>> {
+    let tx = conn.unchecked_transaction()?;
+    rename_photo_row(&tx, old_path, new_path)?;
+    let photo = tx
+        .query_row(
+            &format!("SELECT {} FROM photos WHERE path = ?1", PHOTO_COLUMNS),
+            params![new_path],
+            photo_from_row,
+        )
+        .optional()?;
+    tx.commit()?;
+    Ok(photo)
+}
+
+// ============================================================================
+// Background Jobs
+// ============================================================================
+
+/// A background job as get_jobs lists it. `status` is queued, running,
+/// completed, failed or cancelled.
+#[derive(serde::Serialize, Debug, Clone, PartialEq)]
+pub struct JobRecord {
+    pub id: i64,
+    pub kind: String,
+    pub params: serde_json::Value,
+    pub status: String,
+    pub processed: i64,
+    pub total: i64,
+    pub error: Option<String>,
+    pub created_at: i64,
+    pub started_at: Option<i64>,
+    pub finished_at: Option<i64>,
+}
+
+const JOB_COLUMNS: &str = "id, kind, params, status, processed, total, error, created_at, started_at, finished_at";
+
+fn job_from_row(row: &rusqlite::Row) -> SqlResult<JobRecord> {
+    let params: String = row.get(2)?;
+    Ok(JobRecord {
+        id: row.get(0)?,
+        kind: row.get(1)?,
+        params: serde_json::from_str(&params).unwrap_or(serde_json::Value::Null),
+        status: row.get(3)?,
+        processed: row.get(4)?,
+        total: row.get(5)?,
+        error: row.get(6)?,
+        created_at: row.get(7)?,
+        started_at: row.get(8)?,
+        finished_at: row.get(9)?,
+    })
+}
+
+/// Record a queued job; returns its id.
+pub fn insert_job(conn: &Connection, kind: &str, params: &serde_json::Value) -> SqlResult<i64> {
+    conn.execute(
+        "INSERT INTO jobs (kind, params, status, created_at) VALUES (?1, ?2, 'queued', ?3)",
+        params![kind, params.to_string(), chrono::Utc::now().timestamp()],
+    )?;
+    Ok(conn.last_insert_rowid())
+}
+
+pub fn get_job(conn: &Connection, id: i64) -> SqlResult<Option<JobRecord>> {
+    conn.query_row(&format!("SELECT {} FROM jobs WHERE id = ?1", JOB_COLUMNS), params![id], job_from_row)
+        .optional()
+}
+
+/// The `limit` most recent jobs, newest first
+pub fn get_jobs(conn: &Connection, limit: i64) -> SqlResult<Vec<JobRecord>> {
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs ORDER BY id DESC LIMIT ?1", JOB_COLUMNS))?;
+    let rows = stmt.query_map(params![limit], job_from_row)?;
+    rows.collect()
+}
+
+/// Move a job to `status`, stamping when it started or finished.
+pub fn set_job_status(conn: &Connection, id: i64, status: &str, error: Option<&str>) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    conn.execute(
+        "UPDATE jobs SET status = ?2, error = ?3,
+             started_at = CASE WHEN ?2 = 'running' THEN ?4 ELSE started_at END,
+             finished_at = CASE WHEN ?2 IN ('completed', 'failed', 'cancelled') THEN ?4 ELSE NULL END
+         WHERE id = ?1",
+        params![id, status, error, now],
+    )?;
+    Ok(())
+}
+
+pub fn set_job_progress(conn: &Connection, id: i64, processed: i64, total: i64) -> SqlResult<()> {
+    conn.execute("UPDATE jobs SET processed = ?2, total = ?3 WHERE id = ?1", params![id, processed, total])?;
+    Ok(())
+}
+
+/// Jobs left queued or running when the app last quit, oldest first, all
+/// marked queued again. Their work is saved chunk by chunk, so rerunning
+/// one only does what's left.
+pub fn requeue_unfinished_jobs(conn: &Connection) -> SqlResult<Vec<JobRecord>> {
+    conn.execute("UPDATE jobs SET status = 'queued' WHERE status = 'running'", [])?;
+    let mut stmt = conn.prepare(&format!("SELECT {} FROM jobs WHERE status = 'queued' ORDER BY id", JOB_COLUMNS))?;
+    let rows = stmt.query_map([], job_from_row)?;
+    rows.collect()
+}
+
+// ============================================================================
+// Trip Suggestions
+// ============================================================================
+
+/// What trip detection needs of a photo
+#[derive(Debug, Clone)]
+pub struct TripPhoto {
+    pub path: String,
+    pub date_taken: i64,
+    pub tz_offset_minutes: Option<i32>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub place_city: Option<String>,
+    pub place_country: Option<String>,
+}
+
+/// Visible photos with a known date, oldest first
+pub fn get_trip_photos(conn: &Connection) -> SqlResult<Vec<TripPhoto>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT path, date_taken, tz_offset_minutes, latitude, longitude, place_city, place_country
+         FROM photos
+         WHERE NOT {} AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0
+         ORDER BY date_taken",
+        undated_sql()
+    ))?;
+    let rows = stmt.query_map([], |row| {
+        Ok(TripPhoto {
+            path: row.get(0)?,
+            date_taken: row.get(1)?,
+            tz_offset_minutes: row.get(2)?,
+            latitude: row.get(3)?,
+            longitude: row.get(4)?,
+            place_city: row.get(5)?,
+            place_country: row.get(6)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Ids of suggestions not to offer again
+pub fn get_dismissed_suggestions(conn: &Connection) -> SqlResult<std::collections::HashSet<String>> {
+    let mut stmt = conn.prepare("SELECT id FROM dismissed_suggestions")?;
+    let rows = stmt.query_map([], |row| row.get(0))?;
+    rows.collect()
+}
+
+pub fn dismiss_suggestion(conn: &Connection, id: &str) -> SqlResult<()> {
+    conn.execute(
+        "INSERT OR IGNORE INTO dismissed_suggestions (id, dismissed_at) VALUES (?1, ?2)",
+        params![id, chrono::Utc::now().timestamp()],
+    )?;
+    Ok(())
+}
+
+/// Add every visible dated photo taken from `start` to `end` (inclusive) to
+/// an album. Returns how many were added.
+pub fn add_photos_taken_between_to_album(conn: &Connection, album_id: i64, start: i64, end: i64) -> SqlResult<usize> {
+    conn.execute(
+        &format!(
+            "INSERT OR IGNORE INTO album_photos (album_id, photo_id, added_at)
+             SELECT ?1, id, ?4 FROM photos
+             WHERE date_taken BETWEEN ?2 AND ?3 AND NOT {}
+               AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0",
+            undated_sql()
+        ),
+        params![album_id, start, end, chrono::Utc::now().timestamp()],
+    )
+}
+
+// ============================================================================
+// Auto Tags
+// ============================================================================
+
+/// Stills (id, path, content hash) with no row in `done_table`, which
+/// records (photo_id, model_version), for the model `version`. Missing and
+/// offline files wait until they can be read.
+fn stills_not_done(conn: &Connection, done_table: &str, version: &str) -> SqlResult<Vec<(i64, String, Option<String>)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, path, content_hash FROM photos
+         WHERE deleted_at IS NULL AND COALESCE(is_missing, 0) = 0 AND COALESCE(is_offline, 0) = 0 AND NOT {}
+           AND NOT EXISTS (SELECT 1 FROM {} d WHERE d.photo_id = photos.id AND d.model_version = ?1)
+         ORDER BY id",
+        is_video_sql(), done_table
+    ))?;
+    let rows = stmt.query_map(params![version], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// Stills (id, path, content hash) the model `version` hasn't looked at
+pub fn get_photos_to_auto_tag(conn: &Connection, version: &str) -> SqlResult<Vec<(i64, String, Option<String>)>> {
+    stills_not_done(conn, "auto_tagged", version)
+}
+
+/// Store the predictions (label, confidence) of model `version` for each
+/// photo id, replacing that photo's earlier auto tags. Tags are created as
+/// needed with `color`; a tag the user already gave a photo stays theirs.
+pub fn save_auto_tags(
+    conn: &Connection,
+    version: &str,
+    color: &str,
+    predictions: &[(i64, Vec<(String, f32)>)],
+) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut clear = tx.prepare_cached("DELETE FROM photo_tags WHERE photo_id = ?1 AND source = 'auto'")?;
+        let mut add = tx.prepare_cached(
+            "INSERT INTO photo_tags (tag_id, photo_id, added_at, source, confidence) VALUES (?1, ?2, ?3, 'auto', ?4)
+             ON CONFLICT(tag_id, photo_id) DO NOTHING",
+        )?;
+        let mut done = tx.prepare_cached(
+            "INSERT OR REPLACE INTO auto_tagged (photo_id, model_version, tagged_at) VALUES (?1, ?2, ?3)",
+        )?;
+        let mut tag_ids: HashMap<&str, i64> = HashMap::new();
+        for (photo_id, labels) in predictions {
+            clear.execute(params![photo_id])?;
+            for (label, confidence) in labels {
+                let tag_id = match tag_ids.get(label.as_str()) {
+                    Some(&id) => id,
+                    None => {
+                        let id = create_tag(&tx, label, color)?;
+                        tag_ids.insert(label, id);
+                        id
+                    }
+                };
+                add.execute(params![tag_id, photo_id, now, f64::from(*confidence)])?;
+            }
+            done.execute(params![photo_id, version, now])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Remove the auto-tagger's tags, from one tag or all of them, and the tags
+/// left with no photos. Photos stay marked as looked at, so the tags don't
+/// come back until the model changes. Returns how many memberships were
+/// removed.
+pub fn remove_auto_tags(conn: &Connection, tag_id: Option<i64>) -> SqlResult<usize> {
+    let tx = conn.unchecked_transaction()?;
+    let touched: Vec<i64> = tx
+        .prepare("SELECT DISTINCT tag_id FROM photo_tags WHERE source = 'auto' AND (?1 IS NULL OR tag_id = ?1)")?
+        .query_map(params![tag_id], |row| row.get(0))?
+        .collect::<SqlResult<_>>()?;
+    let removed = tx.execute(
+        "DELETE FROM photo_tags WHERE source = 'auto' AND (?1 IS NULL OR tag_id = ?1)",
+        params![tag_id],
+    )?;
+    {
+        let mut unused =
+            tx.prepare_cached("DELETE FROM tags WHERE id = ?1 AND NOT EXISTS (SELECT 1 FROM photo_tags WHERE tag_id = ?1)")?;
+        for id in touched {
+            unused.execute(params![id])?;
+        }
+    }
+    tx.commit()?;
+    Ok(removed)
+}
+
+/// How many photos the model `version` has looked at
+pub fn count_auto_tagged(conn: &Connection, version: &str) -> SqlResult<i64> {
+    conn.query_row("SELECT COUNT(*) FROM auto_tagged WHERE model_version = ?1", params![version], |row| row.get(0))
+}
+
+// ============================================================================
+// Quality Scores
+// ============================================================================
+
+/// Stills (id, path, content hash) the quality job hasn't looked at
+pub fn get_photos_to_score_quality(conn: &Connection) -> SqlResult<Vec<(i64, String, Option<String>)>> {
+    let mut stmt = conn.prepare(&format!(
+        "SELECT id, path, content_hash FROM photos
+         WHERE quality_checked_at IS NULL AND deleted_at IS NULL AND COALESCE(is_missing, 0) = 0
+           AND NOT {}
+         ORDER BY id",
+        is_video_sql()
+    ))?;
+    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+    rows.collect()
+}
+
+/// Store (id, score) for many photos in one transaction. A photo without
+/// a score couldn't be read; it's marked checked so it isn't retried.
+pub fn save_quality_scores(conn: &Connection, scores: &[(i64, Option<crate::quality::QualityScore>)]) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut stmt = tx.prepare_cached(
+            "UPDATE photos SET quality_score = ?2, sharpness = ?3, clipped_fraction = ?4, quality_checked_at = ?5
+             WHERE id = ?1",
+        )?;
+        for (id, score) in scores {
+            stmt.execute(params![
+                id,
+                score.map(|s| s.score),
+                score.map(|s| s.sharpness),
+                score.map(|s| s.clipped_fraction),
+                now
+            ])?;
+        }
+    }
+    tx.commit()
+}
+
+/// Quality scores of those `paths` that have one
+pub fn get_quality_scores(conn: &Connection, paths: &[String]) -> SqlResult<HashMap<String, f64>> {
+    let mut stmt = conn.prepare_cached("SELECT quality_score FROM photos WHERE path = ?1 AND quality_score IS NOT NULL")?;
+    let mut scores = HashMap::new();
+    for path in paths {
+        if let Some(score) = stmt.query_row(params![path], |row| row.get(0)).optional()? {
+            scores.insert(path.clone(), score);
+        }
+    }
+    Ok(scores)
+}
+
+/// A photo and its quality; see quality.rs for the scale
+#[derive(serde::Serialize, Debug)]
+pub struct ScoredPhoto {
+    pub photo: PhotoMetadata,
+    pub quality_score: f64,
+    pub sharpness: f64,
+    pub clipped_fraction: f64,
+}
+
+/// Visible photos scoring below `threshold`, worst first
+pub fn get_low_quality_photos(conn: &Connection, threshold: f64, limit: i64) -> SqlResult<Vec<ScoredPhoto>> {
+    let query = format!(
+        "SELECT {}, quality_score, sharpness, clipped_fraction FROM photos \
+         WHERE quality_score < ?1 \
+         AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0 \
+         ORDER BY quality_score, id LIMIT ?2",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![threshold, limit], |row| {
+        Ok(ScoredPhoto {
+            photo: photo_from_row(row)?,
+            quality_score: row.get(FIRST_EXTRA_COLUMN)?,
+            sharpness: row.get(FIRST_EXTRA_COLUMN + 1)?,
+            clipped_fraction: row.get(FIRST_EXTRA_COLUMN + 2)?,
+        })
+    })?;
+    rows.collect()
+}
+
+// ============================================================================
+// Faces and People
+// ============================================================================
+
+/// Stills (id, path, content hash) the detector `version` hasn't scanned
+pub fn get_photos_to_scan_faces(conn: &Connection, version: &str) -> SqlResult<Vec<(i64, String, Option<String>)>> {
+    stills_not_done(conn, "face_scans", version)
+}
+
+/// Store each photo's detected faces and mark it scanned by `version`.
+/// Unnamed faces from an earlier scan are replaced; named ones stay, and a
+/// detection overlapping one is taken to be the same face, whose embedding
+/// it updates.
+pub fn save_faces(conn: &Connection, version: &str, detections: &[(i64, Vec<crate::faces::DetectedFace>)]) -> SqlResult<()> {
+    let now = chrono::Utc::now().timestamp();
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut named =
+            tx.prepare_cached("SELECT id, x, y, w, h FROM faces WHERE photo_id = ?1 AND person_id IS NOT NULL")?;
+        let mut clear = tx.prepare_cached("DELETE FROM faces WHERE photo_id = ?1 AND person_id IS NULL")?;
+        let mut add = tx.prepare_cached(
+            "INSERT INTO faces (photo_id, x, y, w, h, confidence, embedding, created_at)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut update = tx.prepare_cached("UPDATE faces SET embedding = ?2 WHERE id = ?1")?;
+        let mut done = tx.prepare_cached(
+            "INSERT OR REPLACE INTO face_scans (photo_id, model_version, scanned_at) VALUES (?1, ?2, ?3)",
+        )?;
+        for (photo_id, found) in detections {
+            let kept: Vec<(i64, (f64, f64, f64, f64))> = named
+                .query_map(params![photo_id], |row| {
+                    Ok((row.get(0)?, (row.get(1)?, row.get(2)?, row.get(3)?, row.get(4)?)))
+                })?
+                .collect::<SqlResult<_>>()?;
+            clear.execute(params![photo_id])?;
+            for (region, embedding) in found {
+                let blob = embedding.as_deref().map(crate::faces::embedding_to_blob);
+                let same = kept
+                    .iter()
+                    .find(|(_, rect)| crate::faces::iou(*rect, region.rect()) >= crate::faces::SAME_FACE_IOU);
+                match same {
+                    Some((face_id, _)) => {
+                        if blob.is_some() {
+                            update.execute(params![face_id, blob])?;
+                        }
+                    }
+                    None => {
+                        add.execute(params![photo_id, region.x, region.y, region.w, region.h, region.confidence, blob, now])?;
+                    }
+                }
+            }
+            done.execute(params![photo_id, version, now])?;
+        }
+    }
+    tx.commit()
+}
+
+/// A face region on a photo, in fractions of the upright image
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Face {
+    pub id: i64,
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub confidence: Option<f64>,
+    pub person_id: Option<i64>,
+    pub person_name: Option<String>,
+}
+
+/// Faces found on a photo, left to right
+pub fn get_faces(conn: &Connection, path: &str) -> SqlResult<Vec<Face>> {
+    let mut stmt = conn.prepare(
+        "SELECT f.id, f.x, f.y, f.w, f.h, f.confidence, f.person_id, pe.name
+         FROM faces f
+         JOIN photos p ON p.id = f.photo_id
+         LEFT JOIN people pe ON pe.id = f.person_id
+         WHERE p.path = ?1
+         ORDER BY f.x, f.id",
+    )?;
+    let rows = stmt.query_map(params![path], |row| {
+        Ok(Face {
+            id: row.get(0)?,
+            x: row.get(1)?,
+            y: row.get(2)?,
+            w: row.get(3)?,
+            h: row.get(4)?,
+            confidence: row.get(5)?,
+            person_id: row.get(6)?,
+            person_name: row.get(7)?,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Name a face as `person_id`, or clear its name with None. Returns false
+/// if there's no such face.
+pub fn assign_face(conn: &Connection, face_id: i64, person_id: Option<i64>) -> SqlResult<bool> {
+    let changed = conn.execute("UPDATE faces SET person_id = ?2 WHERE id = ?1", params![face_id, person_id])?;
+    Ok(changed > 0)
+}
+
+/// Id of the person called `name` (ignoring case), added if new
+pub fn create_person(conn: &Connection, name: &str) -> SqlResult<i64> {
+    let name = name.trim();
+    conn.execute(
+        "INSERT INTO people (name, created_at) VALUES (?1, ?2) ON CONFLICT DO NOTHING",
+        params![name, chrono::Utc::now().timestamp()],
+    )?;
+    conn.query_row("SELECT id FROM people WHERE name = ?1", params![name], |row| row.get(0))
+}
+
+pub fn person_exists(conn: &Connection, id: i64) -> SqlResult<bool> {
+    conn.query_row("SELECT EXISTS (SELECT 1 FROM people WHERE id = ?1)", params![id], |row| row.get(0))
+}
+
+/// Someone named on photos, with their most recent face as cover
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct Person {
+    pub id: i64,
+    pub name: String,
+    /// Visible photos they're named on
+    pub count: i64,
+    pub cover_photo_path: Option<String>,
+    /// The face on the cover photo as (x, y, w, h), to crop it to
+    pub cover_region: Option<[f64; 4]>,
+}
+
+/// Everyone, most photographed first
+pub fn get_people(conn: &Connection) -> SqlResult<Vec<Person>> {
+    // With MAX(), SQLite takes the bare columns from the row holding the maximum
+    let mut stmt = conn.prepare(
+        "SELECT pe.id, pe.name, COUNT(DISTINCT p.id), p.path, f.x, f.y, f.w, f.h, MAX(p.date_taken)
+         FROM people pe
+         LEFT JOIN faces f ON f.person_id = pe.id
+         LEFT JOIN photos p ON p.id = f.photo_id
+             AND p.archived_at IS NULL AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         GROUP BY pe.id
+         ORDER BY COUNT(DISTINCT p.id) DESC, pe.name",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let cover_photo_path: Option<String> = row.get(3)?;
+        let region: Option<[f64; 4]> = match (row.get(4)?, row.get(5)?, row.get(6)?, row.get(7)?) {
+            (Some(x), Some(y), Some(w), Some(h)) => Some([x, y, w, h]),
+            _ => None,
+        };
+        Ok(Person {
+            id: row.get(0)?,
+            name: row.get(1)?,
+            count: row.get(2)?,
+            cover_region: region.filter(|_| cover_photo_path.is_some()),
+            cover_photo_path,
+        })
+    })?;
+    rows.collect()
+}
+
+/// Unnamed faces with embeddings on visible photos: (face id, unit vector)
+pub fn get_unnamed_face_embeddings(conn: &Connection) -> SqlResult<Vec<(i64, Vec<f32>)>> {
+    let mut stmt = conn.prepare(
+        "SELECT f.id, f.embedding FROM faces f
+         JOIN photos p ON p.id = f.photo_id
+         WHERE f.person_id IS NULL AND f.embedding IS NOT NULL
+           AND p.archived_at IS NULL AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         ORDER BY f.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let blob: Vec<u8> = row.get(1)?;
+        Ok((row.get(0)?, crate::faces::embedding_from_blob(&blob)))
+    })?;
+    rows.collect()
+}
+
+/// Replace every unnamed face's cluster with `clusters`, lists of face ids
+/// numbered from 1 in order. Faces in none are left unclustered; named
+/// faces are untouched.
+pub fn set_face_clusters(conn: &Connection, clusters: &[Vec<i64>]) -> SqlResult<()> {
+    let tx = conn.unchecked_transaction()?;
+    tx.execute("UPDATE faces SET cluster_id = NULL WHERE cluster_id IS NOT NULL", [])?;
+    {
+        let mut stmt = tx.prepare_cached("UPDATE faces SET cluster_id = ?2 WHERE id = ?1 AND person_id IS NULL")?;
+        for (i, face_ids) in clusters.iter().enumerate() {
+            for face_id in face_ids {
+                stmt.execute(params![face_id, i as i64 + 1])?;
+            }
+        }
+    }
+    tx.commit()
+}
+
+/// A face to show as a crop of its photo
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FaceSample {
+    pub face_id: i64,
+    pub photo_path: String,
+    /// (x, y, w, h) in fractions of the upright photo
+    pub region: [f64; 4],
+}
+
+/// Unnamed faces that look like one person
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FaceCluster {
+    pub id: i64,
+    pub size: usize,
+    /// The most confident faces, up to the caller's limit
+    pub samples: Vec<FaceSample>,
+}
+
+/// Result of clustering: the clusters, largest first, and the faces too
+/// unlike any others to group
+#[derive(serde::Serialize, Debug, PartialEq)]
+pub struct FaceClusters {
+    pub clusters: Vec<FaceCluster>,
+    pub unclustered_count: usize,
+    pub unclustered_samples: Vec<FaceSample>,
+}
+
+/// Unnamed faces with embeddings on visible photos, by stored cluster
+pub fn get_face_clusters(conn: &Connection, samples: usize) -> SqlResult<FaceClusters> {
+    let mut stmt = conn.prepare(
+        "SELECT f.cluster_id, f.id, p.path, f.x, f.y, f.w, f.h FROM faces f
+         JOIN photos p ON p.id = f.photo_id
+         WHERE f.person_id IS NULL AND f.embedding IS NOT NULL
+           AND p.archived_at IS NULL AND p.deleted_at IS NULL AND COALESCE(p.is_hidden, 0) = 0
+         ORDER BY f.cluster_id, COALESCE(f.confidence, 0) DESC, f.id",
+    )?;
+    let rows = stmt.query_map([], |row| {
+        let cluster_id: Option<i64> = row.get(0)?;
+        let sample = FaceSample {
+            face_id: row.get(1)?,
+            photo_path: row.get(2)?,
+            region: [row.get(3)?, row.get(4)?, row.get(5)?, row.get(6)?],
+        };
+        Ok((cluster_id, sample))
+    })?;
+
+    let mut result = FaceClusters { clusters: Vec::new(), unclustered_count: 0, unclustered_samples: Vec::new() };
+    for row in rows {
+        let (cluster_id, sample) = row?;
+        let (size, kept) = match cluster_id {
+            None => (&mut result.unclustered_count, &mut result.unclustered_samples),
+            Some(id) => {
+                if result.clusters.last().map(|c| c.id) != Some(id) {
+                    result.clusters.push(FaceCluster { id, size: 0, samples: Vec::new() });
+                }
+                let cluster = result.clusters.last_mut().expect("just pushed");
+                (&mut cluster.size, &mut cluster.samples)
+            }
+        };
+        *size += 1;
+        if kept.len() < samples {
+            kept.push(sample);
+        }
+    }
+    // Faces named or hidden since clustering may have shrunk some below the minimum
+    let (clusters, small): (Vec<FaceCluster>, Vec<FaceCluster>) =
+        result.clusters.into_iter().partition(|c| c.size >= crate::faces::MIN_CLUSTER_SIZE);
+    for cluster in small {
+        result.unclustered_count += cluster.size;
+        for sample in cluster.samples {
+            if result.unclustered_samples.len() < samples {
+                result.unclustered_samples.push(sample);
+            }
+        }
+    }
+    result.clusters = clusters;
+    result.clusters.sort_by(|a, b| b.size.cmp(&a.size).then(a.id.cmp(&b.id)));
+    Ok(result)
+}
+
+/// Name every unnamed face in a cluster as `person_id`. Returns how many
+/// faces were named.
+pub fn assign_cluster_to_person(conn: &Connection, cluster_id: i64, person_id: i64) -> SqlResult<usize> {
+    conn.execute(
+        "UPDATE faces SET person_id = ?2, cluster_id = NULL WHERE cluster_id = ?1 AND person_id IS NULL",
+        params![cluster_id, person_id],
+    )
+}
+
+/// Visible photos a person is named on, newest first
+pub fn get_photos_by_person(conn: &Connection, person_id: i64) -> SqlResult<Vec<PhotoMetadata>> {
+    let query = format!(
+        "SELECT {} FROM photos \
+         WHERE id IN (SELECT photo_id FROM faces WHERE person_id = ?1) \
+         AND archived_at IS NULL AND deleted_at IS NULL AND COALESCE(is_hidden, 0) = 0 \
+         ORDER BY date_taken DESC, id DESC",
+        PHOTO_COLUMNS
+    );
+    let mut stmt = conn.prepare(&query)?;
+    let rows = stmt.query_map(params![person_id], photo_from_row)?;
+    rows.collect()
+}
+
+// ============================================================================
+// Metadata Export and Import
+// ============================================================================
+
+/// Up to `limit` photos outside the trash with ids above `after_id`, by
+/// id, with their album and tag names. Exports page through the library
+/// with this, so the connection is free for other commands between pages.
+pub fn get_photo_records(conn: &Connection, after_id: i64, limit: usize) -> SqlResult<Vec<PhotoRecord>> {
+    let tx = conn.unchecked_transaction()?;
+    let mut stmt = tx.prepare(
+        "SELECT id, path, name, date_taken, tz_offset_minutes, date_source, width, height, \
+         file_format, file_size, content_hash, is_favorite, rating, color_label, flag, \
+         is_hidden, is_archived, description, latitude, longitude, location_name, \
+         camera_make, camera_model, lens_model, iso, aperture, shutter_us, focal_length_mm, duration_ms \
+         FROM photos WHERE deleted_at IS NULL AND id > ?1 ORDER BY id LIMIT ?2",
+    )?;
+    let mut albums = tx.prepare(
+        "SELECT a.name FROM album_photos ap JOIN albums a ON a.id = ap.album_id \
+         WHERE ap.photo_id = ?1 ORDER BY a.name COLLATE NOCASE",
+    )?;
+    let mut tags = tx.prepare(
+        "SELECT t.name FROM photo_tags pt JOIN tags t ON t.id = pt.tag_id \
+         WHERE pt.photo_id = ?1 ORDER BY t.name COLLATE NOCASE",
+    )?;
+    let mut rows = stmt.query(params![after_id, limit as i64])?;
+    let mut records = Vec::new();
+    while let Some(row) = rows.next()? {
+        let id: i64 = row.get(0)?;
+        let date_taken: i64 = row.get(3)?;
+        let tz_offset_minutes: Option<i32> = row.get(4)?;
+        let date_source: Option<String> = row.get(5)?;
+        let record = PhotoRecord {
+            id,
+            path: row.get(1)?,
+            name: row.get(2)?,
+            date_taken,
+            date: metadata_export::record_date(date_taken, tz_offset_minutes, date_source.as_deref()),
+            tz_offset_minutes,
+            date_source,
+            width: row.get(6)?,
+            height: row.get(7)?,
+            file_format: row.get(8)?,
+            file_size: row.get(9)?,
+            content_hash: row.get(10)?,
+            is_favorite: row.get::<_, Option<i32>>(11)?.unwrap_or(0) != 0,
+            rating: row.get::<_, Option<u8>>(12)?.unwrap_or(0),
+            color_label: row.get::<_, Option<String>>(13)?.as_deref().and_then(ColorLabel::parse),
+            flag: row.get::<_, Option<String>>(14)?.as_deref().and_then(PhotoFlag::parse),
+            is_hidden: row.get::<_, Option<i32>>(15)?.unwrap_or(0) != 0,
+            is_archived: row.get::<_, Option<i32>>(16)?.unwrap_or(0) != 0,
+            description: row.get(17)?,
+            latitude: row.get(18)?,
+            longitude: row.get(19)?,
+            location_name: row.get(20)?,
+            camera_make: row.get(21)?,
+            camera_model: row.get(22)?,
+            lens_model: row.get(23)?,
+            iso: row.get(24)?,
+            aperture: row.get(25)?,
+            shutter_us: row.get(26)?,
+            focal_length_mm: row.get(27)?,
+            duration_ms: row.get(28)?,
+            albums: albums.query_map(params![id], |r| r.get(0))?.collect::<SqlResult<_>>()?,
+            tags: tags.query_map(params![id], |r| r.get(0))?.collect::<SqlResult<_>>()?,
+        };
+        records.push(record);
+    }
+    Ok(records)
+}
+
+/// Color given to tags an import creates; the tag editor's default.
+const IMPORTED_TAG_COLOR: &str = "#10b981";
+
+/// What `import_photo_records` changed, counted in photos (or memberships).
+#[derive(serde::Serialize, Debug, Default, PartialEq)]
+pub struct MetadataImportSummary {
+    pub matched_by_hash: usize,
+    pub matched_by_path: usize,
+    pub favorites: usize,
+    pub ratings: usize,
+    pub descriptions: usize,
+    pub dates: usize,
+    pub tags_added: usize,
+    pub tags_removed: usize,
+    pub album_photos_added: usize,
+    pub album_photos_removed: usize,
+    pub albums_created: usize,
+    /// Paths of records no photo here matched, by content hash or path
+    pub unmatched: Vec<String>,
+}
+
+/// Restore favorites, ratings, descriptions, hand-set dates, tags and album
+/// membership from exported records, in one transaction. A record matches
+/// the live photo with its content hash (preferring one at its path), else
+/// the photo at its path. Albums and tags are found by name and created
+/// when missing; auto tags are left alone. See `ImportMode` for how
+/// existing metadata is treated.
+pub fn import_photo_records(
+    conn: &Connection,
+    records: &[PhotoRecord],
+    mode: ImportMode,
+) -> SqlResult<MetadataImportSummary> {
+    let overwrite = mode == ImportMode::Overwrite;
+    let mut summary = MetadataImportSummary::default();
+    let mut album_ids: HashMap<String, i64> = HashMap::new();
+    let now = chrono::Utc::now().timestamp();
+    let tx = conn.unchecked_transaction()?;
+    {
+        let mut by_hash = tx.prepare_cached(
+            "SELECT id FROM photos WHERE content_hash = ?1 AND deleted_at IS NULL ORDER BY path = ?2 DESC, id LIMIT 1",
+        )?;
+        let mut by_path = tx.prepare_cached("SELECT id FROM photos WHERE path = ?1 AND deleted_at IS NULL")?;
+        // In merge mode each only touches a field that's empty
+        let mut favorite = tx.prepare_cached(
+            "UPDATE photos SET is_favorite = ?2 WHERE id = ?1 AND COALESCE(is_favorite, 0) != ?2
+             AND (?3 OR COALESCE(is_favorite, 0) = 0)",
+        )?;
+        let mut rating = tx.prepare_cached(
+            "UPDATE photos SET rating = ?2 WHERE id = ?1 AND COALESCE(rating, 0) != ?2 AND (?3 OR COALESCE(rating, 0) = 0)",
+        )?;
+        let mut description = tx.prepare_cached(
+            "UPDATE photos SET description = ?2 WHERE id = ?1 AND description IS NOT ?2
+             AND (?3 OR COALESCE(description, '') = '')",
+        )?;
+        // Only a date set by hand is restored; the rest came from the file.
+        // Merge mode fills in dates that were only guessed.
+        let mut date = tx.prepare_cached(
+            "UPDATE photos SET date_taken = ?2, tz_offset_minutes = ?3, date_source = ?4, date_confident = 1,
+             filename_date_mismatch = NULL
+             WHERE id = ?1 AND (date_taken IS NOT ?2 OR tz_offset_minutes IS NOT ?3 OR date_source IS NOT ?4)
+             AND (?5 OR date_confident = 0)",
+        )?;
+        // Another app's date that wasn't set by hand only dates a photo that
+        // has none, and stays as unsure as its source
+        let mut undated = tx.prepare_cached(
+            "UPDATE photos SET date_taken = ?2, tz_offset_minutes = ?3, date_source = ?4, filename_date_mismatch = NULL
+             WHERE id = ?1 AND (date_source IS ?5 OR COALESCE(date_taken, 0) = 0)",
+        )?;
+        let mut current_tags = tx.prepare_cached(
+            "SELECT t.name FROM photo_tags pt JOIN tags t ON t.id = pt.tag_id WHERE pt.photo_id = ?1 AND pt.source IS NULL",
+        )?;
+        let mut add_tag = tx.prepare_cached(
+            "INSERT INTO photo_tags (tag_id, photo_id, added_at) VALUES (?1, ?2, ?3)
+             ON CONFLICT(tag_id, photo_id) DO UPDATE SET source = NULL, confidence = NULL WHERE source IS NOT NULL",
+        )?;
+        let mut remove_tag = tx.prepare_cached(
+            "DELETE FROM photo_tags WHERE photo_id = ?1 AND source IS NULL
+             AND tag_id = (SELECT id FROM tags WHERE name = ?2 COLLATE NOCASE)",
+        )?;
+        let mut current_albums = tx.prepare_cached("SELECT album_id FROM album_photos WHERE photo_id = ?1")?;
+        let mut add_to_album =
+            tx.prepare_cached("INSERT OR IGNORE INTO album_photos (album_id, photo_id, added_at) VALUES (?1, ?2, ?3)")?;
+        let mut remove_from_album = tx.prepare_cached("DELETE FROM album_photos WHERE album_id = ?1 AND photo_id = ?2")?;
+
+        for record in records {
+            let hashed = match record.content_hash.as_deref().filter(|h| !h.is_empty()) {
+                Some(hash) => by_hash.query_row(params![hash, record.path], |row| row.get(0)).optional()?,
+                None => None,
+            };
+            let id: i64 = match hashed {
+                Some(id) => {
+                    summary.matched_by_hash += 1;
+                    id
+                }
+                None => match by_path.query_row(params![record.path], |row| row.get(0)).optional()? {
+                    Some(id) => {
+                        summary.matched_by_path += 1;
+                        id
+                    }
+                    None => {
+                        summary.unmatched.push(record.path.clone());
+                        continue;
+                    }
+                },
+            };
+
+            if overwrite || record.is_favorite {
+                summary.favorites += favorite.execute(params![id, record.is_favorite, overwrite])?;
+            }
+            if overwrite || record.rating > 0 {
+                summary.ratings += rating.execute(params![id, record.rating, overwrite])?;
+            }
+            let text = record.description.as_deref().map(str::trim).filter(|t| !t.is_empty());
+            if overwrite || text.is_some() {
+                summary.descriptions += description.execute(params![id, text, overwrite])?;
+            }
+            if record.date_source.as_deref() == Some(media::DATE_SOURCE_MANUAL) {
+                summary.dates += date.execute(params![
+                    id,
+                    record.date_taken,
+                    record.tz_offset_minutes,
+                    media::DATE_SOURCE_MANUAL,
+                    overwrite
+                ])?;
+            } else if let Some(source) = record.date_source.as_deref().filter(|s| *s != media::DATE_SOURCE_UNKNOWN) {
+                if record.date_taken > 0 {
+                    summary.dates += undated.execute(params![
+                        id,
+                        record.date_taken,
+                        record.tz_offset_minutes,
+                        source,
+                        media::DATE_SOURCE_UNKNOWN
+                    ])?;
+                }
+            }
+
+            let wanted: Vec<&str> = record.tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()).collect();
+            if overwrite {
+                let current: Vec<String> = current_tags.query_map(params![id], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+                for name in current.iter().filter(|c| !wanted.iter().any(|w| w.eq_ignore_ascii_case(c))) {
+                    summary.tags_removed += remove_tag.execute(params![id, name])?;
+                }
+            }
+            for name in wanted {
+                let tag_id = create_tag(&tx, name, IMPORTED_TAG_COLOR)?;
+                summary.tags_added += add_tag.execute(params![tag_id, id, now])?;
+            }
+
+            let mut wanted = Vec::new();
+            for name in record.albums.iter().map(|a| a.trim()).filter(|a| !a.is_empty()) {
+                let album_id = match album_ids.get(name) {
+                    Some(&album_id) => album_id,
+                    None => {
+                        let album_id = match find_album_by_name(&tx, name)? {
+                            Some(album_id) => album_id,
+                            None => {
+                                summary.albums_created += 1;
+                                create_album(&tx, name)?
+                            }
+                        };
+                        album_ids.insert(name.to_string(), album_id);
+                        album_id
+                    }
+                };
+                wanted.push(album_id);
+            }
+            if overwrite {
+                let current: Vec<i64> = current_albums.query_map(params![id], |row| row.get(0))?.collect::<SqlResult<_>>()?;
+                for album_id in current.into_iter().filter(|a| !wanted.contains(a)) {
+                    summary.album_photos_removed += remove_from_album.execute(params![album_id, id])?;
+                }
+            }
+            for album_id in wanted {
+                summary.album_photos_added += add_to_album.execute(params![album_id, id, now])?;
+            }
+        }
+    }
+    tx.commit()?;
+    Ok(summary)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Helper to create a PhotoMetadata for testing
+    fn test_photo(path: &str, name: &str) -> PhotoMetadata {
+        PhotoMetadata {
+            path: path.to_string(),
+            name: name.to_string(),
+            date_taken: 1700000000,
+            width: 1920,
+            height: 1080,
+            is_favorite: false,
+            content_hash: Some("abc123".to_string()),
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            camera_make: None,
+            camera_model: None,
+            lens_model: None,
+            iso: None,
+            aperture: None,
+            shutter_us: None,
+            focal_length_mm: None,
+            orientation: None,
+            duration_ms: None,
+            codec: None,
+            thumb_status: None,
+            is_missing: false,
+            is_offline: false,
+            blurhash: None,
+            dominant_color: None,
+            color_label: None,
+            flag: None,
+            stack_id: None,
+            edited_key: None,
+            stack_count: None,
+            rating: 0,
+            tags: Vec::new(),
+            description: None,
+            // As a scan records it
+            file_format: media::file_format(std::path::Path::new(path)),
+            file_size: None,
+            mime_type: None,
+            paired_video_path: None,
+            filename_date_mismatch: None,
+            tz_offset_minutes: None,
+            date_source: None,
+            date_confident: None,
+            id: None,
+        }
+    }
+
+    /// Helper to create an in-memory database with schema initialized
+    fn setup_db() -> Connection {
+        let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
+        configure_connection(&conn).expect("Failed to configure connection");
+        init_schema(&conn).expect("Failed to initialize schema");
+        conn
+    }
+
+    // ====================================================================
+    // Photos tests
+    // ====================================================================
+
+    #[test]
+    fn test_insert_and_get_all_photos() {
+        let conn = setup_db();
+        let photo = test_photo("/photos/test.jpg", "test.jpg");
+        insert_photo(&conn, &photo, "upload").unwrap();
+
+        let photos = get_all_photos(&conn).unwrap();
+        assert_eq!(photos.len(), 1);
+        assert_eq!(photos[0].path, "/photos/test.jpg");
+        assert_eq!(photos[0].name, "test.jpg");
+        assert_eq!(photos[0].date_taken, 1700000000);
+    }
+
+    #[test]
+    fn test_photo_exists_true_for_inserted() {
+        let conn = setup_db();
+        let photo = test_photo("/photos/exists.jpg", "exists.jpg");
+        insert_photo(&conn, &photo, "upload").unwrap();
+
+        assert!(photo_exists(&conn, "/photos/exists.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_photo_exists_false_for_missing() {
+        let conn = setup_db();
+        assert!(!photo_exists(&conn, "/photos/missing.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_delete_photo() {
+        let conn = setup_db();
+        let photo = test_photo("/photos/delete_me.jpg", "delete_me.jpg");
+        insert_photo(&conn, &photo, "upload").unwrap();
+        assert!(photo_exists(&conn, "/photos/delete_me.jpg").unwrap());
+
+        delete_photo(&conn, "/photos/delete_me.jpg").unwrap();
+        assert!(!photo_exists(&conn, "/photos/delete_me.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_search_photos_by_name() {
+        let conn = setup_db();
+        let photo = test_photo("/photos/sunset_beach.jpg", "sunset_beach.jpg");
+        insert_photo(&conn, &photo, "upload").unwrap();
+
+        let results = search_photos(&conn, "sunset").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "sunset_beach.jpg");
+    }
+
+    #[test]
+    fn test_search_photos_by_location() {
+        let conn = setup_db();
+        let mut photo = test_photo("/photos/trip.jpg", "trip.jpg");
+        photo.location_name = Some("San Francisco, California".to_string());
+        insert_photo(&conn, &photo, "upload").unwrap();
+
+        let results = search_photos(&conn, "San Francisco").unwrap();
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].location_name.as_deref(), Some("San Francisco, California"));
+    }
+
+    #[test]
+    fn test_get_photo_count() {
+        let conn = setup_db();
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 0);
+
+        insert_photo(&conn, &test_photo("/photos/a.jpg", "a.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo("/photos/b.jpg", "b.jpg"), "upload").unwrap();
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_get_photos_with_location_skips_photos_without_gps() {
+        let conn = setup_db();
+        let mut geotagged = test_photo("/photos/geo.jpg", "geo.jpg");
+        geotagged.latitude = Some(-33.8688);
+        geotagged.longitude = Some(151.2093);
+        insert_photo(&conn, &geotagged, "upload").unwrap();
+        insert_photo(&conn, &test_photo("/photos/plain.jpg", "plain.jpg"), "upload").unwrap();
+
+        let photos = get_photos_with_location(&conn).unwrap();
+        assert_eq!(photos.len(), 1);
+        assert_eq!(photos[0].path, "/photos/geo.jpg");
+        assert_eq!(photos[0].latitude, Some(-33.8688));
+    }
+
+    #[test]
+    fn test_insert_photo_persists_camera() {
+        let conn = setup_db();
+        let mut photo = test_photo("/photos/cam.jpg", "cam.jpg");
+        photo.camera_make = Some("Apple".to_string());
+        photo.camera_model = Some("iPhone 13 Pro".to_string());
+        insert_photo(&conn, &photo, "scan").unwrap();
+
+        let photos = get_all_photos(&conn).unwrap();
+        assert_eq!(photos[0].camera_make.as_deref(), Some("Apple"));
+        assert_eq!(photos[0].camera_model.as_deref(), Some("iPhone 13 Pro"));
+    }
+
+    #[test]
+    fn test_get_cameras_and_photos_by_camera() {
+        let conn = setup_db();
+        for (path, make, model) in [
+            ("/photos/a.jpg", "Apple", "iPhone 13 Pro"),
+            ("/photos/b.jpg", "Apple", "iPhone 13 Pro"),
+            ("/photos/c.jpg", "Apple", "iPhone 8"),
+            ("/photos/d.jpg", "SONY", "ILCE-7M3"),
+        ] {
+            let mut photo = test_photo(path, path);
+            photo.camera_make = Some(make.to_string());
+            photo.camera_model = Some(model.to_string());
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        insert_photo(&conn, &test_photo("/photos/none.jpg", "none.jpg"), "scan").unwrap();
+
+        let cameras = get_cameras(&conn).unwrap();
+        assert_eq!(cameras.len(), 3);
+        assert_eq!(cameras[0].model.as_deref(), Some("iPhone 13 Pro"));
+        assert_eq!(cameras[0].count, 2);
+
+        assert_eq!(get_photos_by_camera(&conn, "Apple", Some("iPhone 13 Pro")).unwrap().len(), 2);
+        assert_eq!(get_photos_by_camera(&conn, "Apple", None).unwrap().len(), 3);
+        assert_eq!(get_photos_by_camera(&conn, "Canon", None).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_photos_exiftool_found_nothing_in_are_not_enriched_again() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/screenshot.png", "screenshot.png"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        let nothing = crate::metadata_enrich::EnrichedMetadata {
+            camera_make: None,
+            camera_model: None,
+            lens_model: None,
+            iso: None,
+            aperture: None,
+            shutter_us: None,
+            focal_length_mm: None,
+            orientation: None,
+            duration_ms: None,
+            codec: None,
+        };
+        update_enriched_metadata(&conn, "/p/screenshot.png", &nothing).unwrap();
+        assert_eq!(get_photos_without_enrichment(&conn).unwrap(), vec!["/p/b.jpg".to_string()]);
+
+        // A rescan of the same file keeps the marker; a changed file loses it
+        insert_photo(&conn, &test_photo("/p/screenshot.png", "screenshot.png"), "scan").unwrap();
+        assert_eq!(get_photos_without_enrichment(&conn).unwrap().len(), 1);
+        let mut edited = test_photo("/p/screenshot.png", "screenshot.png");
+        edited.content_hash = Some("def456".to_string());
+        insert_photo(&conn, &edited, "scan").unwrap();
+        assert_eq!(get_photos_without_enrichment(&conn).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_update_camera_info_keeps_existing_on_none() {
+        let conn = setup_db();
+        let mut photo = test_photo("/photos/keep.jpg", "keep.jpg");
+        photo.camera_make = Some("FUJIFILM".to_string());
+        insert_photo(&conn, &photo, "scan").unwrap();
+
+        update_camera_info(&conn, "/photos/keep.jpg", None, Some("X-T4")).unwrap();
+        let photos = get_all_photos(&conn).unwrap();
+        assert_eq!(photos[0].camera_make.as_deref(), Some("FUJIFILM"));
+        assert_eq!(photos[0].camera_model.as_deref(), Some("X-T4"));
+    }
+
+    #[test]
+    fn test_insert_photo_upsert_preserves_favorite() {
+        let conn = setup_db();
+        let photo = test_photo("/photos/rescan.jpg", "rescan.jpg");
+        insert_photo(&conn, &photo, "scan").unwrap();
+        set_photo_favorite(&conn, "/photos/rescan.jpg", true).unwrap();
+
+        let mut changed = photo.clone();
+        changed.width = 4000;
+        insert_photo(&conn, &changed, "scan").unwrap();
+
+        let photos = get_all_photos(&conn).unwrap();
+        assert_eq!(photos.len(), 1);
+        assert_eq!(photos[0].width, 4000);
+        assert!(photos[0].is_favorite);
+    }
+
+    #[test]
+    fn test_file_stats_under_prefix() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/scan/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/scan/b.jpg", "b.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/other/c.jpg", "c.jpg"), "scan").unwrap();
+        update_file_stats(&conn, "/scan/a.jpg", 1234, 1700000000).unwrap();
+
+        let stats = get_file_stats_under(&conn, "/scan/").unwrap();
+        assert_eq!(stats.len(), 2);
+        assert_eq!(stats["/scan/a.jpg"], (Some(1234), Some(1700000000)));
+        assert_eq!(stats["/scan/b.jpg"], (None, None));
+    }
+
+    #[test]
+    fn test_set_photo_missing_and_reinsert_clears_it() {
+        let conn = setup_db();
+        let photo = test_photo("/scan/gone.jpg", "gone.jpg");
+        insert_photo(&conn, &photo, "scan").unwrap();
+
+        set_photo_missing(&conn, "/scan/gone.jpg", true).unwrap();
+        let missing = get_missing_photos(&conn).unwrap();
+        assert_eq!(missing.len(), 1);
+        assert!(missing[0].is_missing);
+
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert!(get_missing_photos(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_health_rows_and_album_covers() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+        let mut flat = test_photo("/lib/b.heic", "b.heic");
+        flat.width = 0;
+        flat.height = 0;
+        flat.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        insert_photo(&conn, &flat, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/c.jpg", "c.jpg"), "scan").unwrap();
+        trash_photo(&conn, "/lib/c.jpg", Some("/lib/.trash/c.jpg")).unwrap();
+
+        let rows = get_health_rows(&conn).unwrap();
+        let paths: Vec<&str> = rows.iter().map(|r| r.path.as_str()).collect();
+        assert_eq!(paths, vec!["/lib/a.jpg", "/lib/b.heic"]);
+        assert_eq!((rows[1].width, rows[1].height), (0, 0));
+        assert_eq!(rows[1].date_source.as_deref(), Some(media::DATE_SOURCE_UNKNOWN));
+        assert!(!rows[0].is_offline);
+        set_offline_under(&conn, "/lib/a.jpg", true).unwrap();
+        assert!(get_health_rows(&conn).unwrap()[0].is_offline);
+
+        let kept = create_album(&conn, "Kept").unwrap();
+        set_album_cover(&conn, kept, "/lib/a.jpg").unwrap();
+        let trashed = create_album(&conn, "Trashed").unwrap();
+        set_album_cover(&conn, trashed, "/lib/c.jpg").unwrap();
+        let covers = get_album_covers(&conn).unwrap();
+        assert_eq!(covers.len(), 2);
+        assert!(covers[0].in_library);
+        assert!(!covers[1].in_library);
+
+        // Only clears the cover it was asked about
+        clear_album_cover(&conn, kept, "/lib/b.heic").unwrap();
+        clear_album_cover(&conn, trashed, "/lib/c.jpg").unwrap();
+        let covers = get_album_covers(&conn).unwrap();
+        assert_eq!(covers.len(), 1);
+        assert_eq!(covers[0].album_id, kept);
+    }
+
+    #[test]
+    fn test_offline_flag_follows_the_volume() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/Volumes/SSD/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/Volumes/SSD2/b.jpg", "b.jpg"), "scan").unwrap();
+
+        // The prefix ends at a separator, so SSD2 is a different drive
+        assert_eq!(set_offline_under(&conn, "/Volumes/SSD/", true).unwrap(), 1);
+        assert_eq!(set_offline_under(&conn, "/Volumes/SSD/", true).unwrap(), 0);
+        assert!(is_photo_offline(&conn, "/Volumes/SSD/a.jpg").unwrap());
+        assert!(!is_photo_offline(&conn, "/Volumes/SSD2/b.jpg").unwrap());
+        let photos = get_all_photos(&conn).unwrap();
+        assert!(photos.iter().find(|p| p.path == "/Volumes/SSD/a.jpg").unwrap().is_offline);
+
+        assert_eq!(set_offline_under(&conn, "/Volumes/SSD/", false).unwrap(), 1);
+        assert!(!is_photo_offline(&conn, "/Volumes/SSD/a.jpg").unwrap());
+
+        // Re-importing the file means it is reachable again
+        set_offline_under(&conn, "/Volumes/SSD/", true).unwrap();
+        insert_photo(&conn, &test_photo("/Volumes/SSD/a.jpg", "a.jpg"), "scan").unwrap();
+        assert!(!is_photo_offline(&conn, "/Volumes/SSD/a.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_insert_photos_batch_10k_rows() {
+        let conn = setup_db();
+        let photos: Vec<PhotoMetadata> = (0..10_000)
+            .map(|i| test_photo(&format!("/batch/{}.jpg", i), &format!("{}.jpg", i)))
+            .collect();
+
+        let failures = insert_photos_batch(&conn, &photos, "scan").unwrap();
+        assert!(failures.is_empty());
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 10_000);
+        assert!(photo_exists(&conn, "/batch/9999.jpg").unwrap());
+    }
+
+    #[test]
+    fn test_insert_photos_batch_collects_row_errors() {
+        let conn = setup_db();
+        conn.execute_batch(
+            "CREATE TRIGGER reject_bad BEFORE INSERT ON photos WHEN NEW.name = 'bad.jpg'
+             BEGIN SELECT RAISE(ABORT, 'rejected'); END;"
+        ).unwrap();
+        let photos = vec![
+            test_photo("/batch/good1.jpg", "good1.jpg"),
+            test_photo("/batch/bad.jpg", "bad.jpg"),
+            test_photo("/batch/good2.jpg", "good2.jpg"),
+        ];
+
+        let failures = insert_photos_batch(&conn, &photos, "scan").unwrap();
+        assert_eq!(failures.len(), 1);
+        assert_eq!(failures[0].0, "/batch/bad.jpg");
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+    }
+
+    fn insert_dated(conn: &Connection, name: &str, date_taken: i64) {
+        let mut photo = test_photo(&format!("/photos/{}", name), name);
+        photo.date_taken = date_taken;
+        insert_photo(conn, &photo, "scan").unwrap();
+    }
+
+    #[test]
+    fn test_photo_sort_from_str() {
+        assert_eq!("date_desc".parse::<PhotoSort>().unwrap(), PhotoSort::DateDesc);
+        assert_eq!("date_asc".parse::<PhotoSort>().unwrap(), PhotoSort::DateAsc);
+        assert_eq!("name".parse::<PhotoSort>().unwrap(), PhotoSort::NameAsc);
+        assert_eq!("name_desc".parse::<PhotoSort>().unwrap(), PhotoSort::NameDesc);
+        assert!("size".parse::<PhotoSort>().is_err());
+    }
+
+    #[test]
+    fn test_timeline_buckets_use_each_photos_offset() {
+        let conn = setup_db();
+        let add = |name: &str, date_taken: i64, tz: Option<i32>, favorite: bool| {
+            let mut photo = test_photo(&format!("/t/{}", name), name);
+            photo.date_taken = date_taken;
+            photo.tz_offset_minutes = tz;
+            photo.is_favorite = favorite;
+            insert_photo(&conn, &photo, "scan").unwrap();
+        };
+        // 2024-01-01 02:00 UTC is still New Year's Eve in New York
+        add("nye.jpg", 1704074400, Some(-300), false);
+        add("jan.jpg", 1704153600, None, false);
+        add("jan_fav.jpg", 1704110400, None, true);
+        let mut undated = test_photo("/t/undated.jpg", "undated.jpg");
+        undated.date_taken = 0;
+        undated.date_source = Some("unknown".to_string());
+        insert_photo(&conn, &undated, "scan").unwrap();
+
+        let filter = PhotoFilter::default();
+        let buckets = get_timeline_buckets(&conn, TimelineGranularity::Month, Some(0), &filter).unwrap();
+        let periods: Vec<(&str, i64)> = buckets.iter().map(|b| (b.period.as_str(), b.count)).collect();
+        assert_eq!(periods, vec![("2024-01", 2), ("2023-12", 1), ("unknown", 1)]);
+        // The favorite wins the cover over a newer photo
+        assert_eq!(buckets[0].cover_photo_path, "/t/jan_fav.jpg");
+
+        let years = get_timeline_buckets(&conn, TimelineGranularity::Year, Some(0), &filter).unwrap();
+        assert_eq!(years[1].period, "2023");
+
+        let page = get_photos_in_bucket(&conn, "2024-01", TimelineGranularity::Month, Some(0), 0, 1, &filter).unwrap();
+        assert_eq!(page[0].path, "/t/jan.jpg");
+        let page = get_photos_in_bucket(&conn, "2024-01", TimelineGranularity::Month, Some(0), 1, 1, &filter).unwrap();
+        assert_eq!(page[0].path, "/t/jan_fav.jpg");
+        let days = get_photos_in_bucket(&conn, "2023-12-31", TimelineGranularity::Day, Some(0), 0, 10, &filter).unwrap();
+        assert_eq!(days.len(), 1);
+        assert_eq!("week".parse::<TimelineGranularity>().unwrap_err(), "Unknown timeline granularity: week");
+    }
+
+    #[test]
+    fn test_query_photos_each_filter_and_combinations() {
+        let conn = setup_db();
+        let add = |name: &str, date_taken: i64, width: u32, height: u32, format: &str| {
+            let mut photo = test_photo(&format!("/q/{}", name), name);
+            photo.date_taken = date_taken;
+            photo.width = width;
+            photo.height = height;
+            photo.file_format = Some(format.to_string());
+            insert_photo(&conn, &photo, "scan").unwrap();
+        };
+        add("beach_wide.jpg", 100, 4000, 3000, "jpg");
+        add("beach_tall.jpg", 200, 3000, 4000, "jpg");
+        add("square.png", 300, 1000, 1000, "png");
+        add("beach_clip.mov", 400, 1920, 1080, "mov");
+        set_photo_favorite(&conn, "/q/beach_tall.jpg", true).unwrap();
+        set_photo_favorite(&conn, "/q/beach_clip.mov", true).unwrap();
+        set_photo_rating(&conn, "/q/square.png", 4).unwrap();
+        set_photo_rating(&conn, "/q/beach_wide.jpg", 2).unwrap();
+        let album = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album, "/q/beach_wide.jpg").unwrap();
+        add_photo_to_album(&conn, album, "/q/square.png").unwrap();
+        let sunny = create_tag(&conn, "sunny", "#ffcc00").unwrap();
+        let family = create_tag(&conn, "family", "#00ccff").unwrap();
+        add_tags_to_photos(&conn, &[sunny], &["/q/beach_wide.jpg".to_string(), "/q/beach_tall.jpg".to_string()]).unwrap();
+        add_tags_to_photos(&conn, &[family], &["/q/beach_tall.jpg".to_string()]).unwrap();
+
+        let names = |query: PhotoQuery| -> Vec<String> {
+            query_photos(&conn, &query, 0, None).unwrap().into_iter().map(|p| p.name).collect()
+        };
+
+        // The empty query is the whole grid, in the same order
+        let all: Vec<String> = get_all_photos(&conn).unwrap().into_iter().map(|p| p.name).collect();
+        assert_eq!(names(PhotoQuery::default()), all);
+
+        assert_eq!(names(PhotoQuery { media_type: MediaTypeFilter::Video, ..Default::default() }), vec!["beach_clip.mov"]);
+        assert_eq!(names(PhotoQuery { media_type: MediaTypeFilter::Photo, ..Default::default() }).len(), 3);
+        assert_eq!(names(PhotoQuery { favorites_only: true, ..Default::default() }), vec!["beach_clip.mov", "beach_tall.jpg"]);
+        assert_eq!(names(PhotoQuery { date_from: Some(200), date_to: Some(300), ..Default::default() }), vec!["square.png", "beach_tall.jpg"]);
+        assert_eq!(names(PhotoQuery { orientation: Some(Orientation::Portrait), ..Default::default() }), vec!["beach_tall.jpg"]);
+        assert_eq!(names(PhotoQuery { orientation: Some(Orientation::Landscape), ..Default::default() }), vec!["beach_clip.mov", "beach_wide.jpg"]);
+        assert_eq!(names(PhotoQuery { orientation: Some(Orientation::Square), ..Default::default() }), vec!["square.png"]);
+        assert_eq!(names(PhotoQuery { album_id: Some(album), ..Default::default() }), vec!["square.png", "beach_wide.jpg"]);
+        assert_eq!(names(PhotoQuery { tag_ids: vec![sunny, family], ..Default::default() }), vec!["beach_tall.jpg"]);
+        assert_eq!(names(PhotoQuery { min_rating: Some(3), ..Default::default() }), vec!["square.png"]);
+        assert_eq!(names(PhotoQuery { text: Some("beach".to_string()), ..Default::default() }).len(), 3);
+        assert_eq!(names(PhotoQuery { text: Some("   ".to_string()), ..Default::default() }), all);
+
+        // Every set field must hold
+        let beach_photos = PhotoQuery { text: Some("beach".to_string()), media_type: MediaTypeFilter::Photo, ..Default::default() };
+        assert_eq!(names(beach_photos.clone()), vec!["beach_tall.jpg", "beach_wide.jpg"]);
+        assert_eq!(names(PhotoQuery { favorites_only: true, ..beach_photos.clone() }), vec!["beach_tall.jpg"]);
+        assert_eq!(names(PhotoQuery { album_id: Some(album), min_rating: Some(1), orientation: Some(Orientation::Landscape), ..Default::default() }), vec!["beach_wide.jpg"]);
+        assert!(names(PhotoQuery { tag_ids: vec![sunny], date_from: Some(300), ..Default::default() }).is_empty());
+
+        // Archived photos stay out of the timeline but not albums
+        set_photos_archived(&conn, &["/q/square.png".to_string()], true).unwrap();
+        assert!(!names(PhotoQuery::default()).contains(&"square.png".to_string()));
+        assert_eq!(names(PhotoQuery { album_id: Some(album), ..Default::default() }).len(), 2);
+
+        let page = query_photos(&conn, &PhotoQuery::default(), 1, Some(1)).unwrap();
+        assert_eq!(page[0].name, "beach_tall.jpg");
+    }
+
+    #[test]
+    fn test_adjacent_photos_walk_the_filtered_order() {
+        let conn = setup_db();
+        // c and d share a date so the id tiebreaker decides their order
+        for (name, date) in [("a.jpg", 400), ("b.jpg", 300), ("c.jpg", 200), ("d.jpg", 200), ("e.jpg", 100)] {
+            insert_dated(&conn, name, date);
+        }
+        let mut undated = test_photo("/photos/undated.jpg", "undated.jpg");
+        undated.date_source = Some("unknown".to_string());
+        undated.date_taken = 500;
+        insert_photo(&conn, &undated, "scan").unwrap();
+
+        let names = |photos: &[PhotoMetadata]| -> Vec<String> { photos.iter().map(|p| p.name.clone()).collect() };
+        let all = PhotoQuery::default();
+        let order = names(&query_photos(&conn, &all, 0, None).unwrap());
+        assert_eq!(order, vec!["a.jpg", "b.jpg", "d.jpg", "c.jpg", "e.jpg", "undated.jpg"]);
+
+        let around = get_adjacent_photos(&conn, "/photos/d.jpg", &all, 2).unwrap().unwrap();
+        assert_eq!(names(&around.before), vec!["a.jpg", "b.jpg"]);
+        assert_eq!(names(&around.after), vec!["c.jpg", "e.jpg"]);
+
+        // At the ends of the order
+        let first = get_adjacent_photos(&conn, "/photos/a.jpg", &all, 3).unwrap().unwrap();
+        assert!(first.before.is_empty());
+        assert_eq!(names(&first.after), vec!["b.jpg", "d.jpg", "c.jpg"]);
+        let last = get_adjacent_photos(&conn, "/photos/undated.jpg", &all, 1).unwrap().unwrap();
+        assert_eq!(names(&last.before), vec!["e.jpg"]);
+        assert!(last.after.is_empty());
+
+        // The anchor was just unfavorited: still a valid position in the favorites view
+        for name in ["a.jpg", "c.jpg", "e.jpg"] {
+            set_photo_favorite(&conn, &format!("/photos/{}", name), true).unwrap();
+        }
+        let favorites = PhotoQuery { favorites_only: true, ..Default::default() };
+        let around = get_adjacent_photos(&conn, "/photos/b.jpg", &favorites, 5).unwrap().unwrap();
+        assert_eq!(names(&around.before), vec!["a.jpg"]);
+        assert_eq!(names(&around.after), vec!["c.jpg", "e.jpg"]);
+
+        assert!(get_adjacent_photos(&conn, "/photos/missing.jpg", &all, 1).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_shuffled_pages_are_stable_and_skip_what_slideshows_hide() {
+        let conn = setup_db();
+        for i in 0..30 {
+            insert_dated(&conn, &format!("p{:02}.jpg", i), 1000 + i);
+        }
+        let mut clip = test_photo("/photos/clip.mov", "clip.mov");
+        clip.file_format = Some("mov".to_string());
+        insert_photo(&conn, &clip, "scan").unwrap();
+        let mut shot = test_photo("/photos/shot.png", "Screenshot 1.png");
+        shot.file_format = Some("png".to_string());
+        insert_photo(&conn, &shot, "scan").unwrap();
+        set_photos_hidden(&conn, &["/photos/p00.jpg".to_string()], true).unwrap();
+        set_photos_archived(&conn, &["/photos/p01.jpg".to_string()], true).unwrap();
+        conn.execute("UPDATE photos SET archived_at = 1 WHERE path = '/photos/p02.jpg'", []).unwrap();
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/photos/p03.jpg'", []).unwrap();
+
+        let query = PhotoQuery { exclude_screenshots: true, ..Default::default() };
+        let paths = |photos: Vec<PhotoMetadata>| -> Vec<String> { photos.into_iter().map(|p| p.path).collect() };
+        let whole = paths(get_shuffled_page(&conn, &query, 7, 0, 100).unwrap());
+        assert_eq!(whole.len(), 26);
+        for hidden in ["p00", "p01", "p02", "p03"] {
+            assert!(!whole.contains(&format!("/photos/{}.jpg", hidden)));
+        }
+        assert!(!whole.iter().any(|p| p.ends_with(".mov") || p.ends_with(".png")));
+
+        // Pages of the same seed tile the same permutation
+        let mut paged = paths(get_shuffled_page(&conn, &query, 7, 0, 10).unwrap());
+        paged.extend(paths(get_shuffled_page(&conn, &query, 7, 10, 10).unwrap()));
+        paged.extend(paths(get_shuffled_page(&conn, &query, 7, 20, 10).unwrap()));
+        assert_eq!(paged, whole);
+        let sorted: Vec<String> = { let mut s = whole.clone(); s.sort(); s };
+        assert_ne!(whole, sorted);
+        assert_ne!(paths(get_shuffled_page(&conn, &query, 8, 0, 100).unwrap()), whole);
+
+        let random = get_random_photos(&conn, &query, 5).unwrap();
+        assert_eq!(random.len(), 5);
+        assert!(random.iter().all(|p| whole.contains(&p.path)));
+        let videos = PhotoQuery { media_type: MediaTypeFilter::Video, ..Default::default() };
+        assert_eq!(paths(get_random_photos(&conn, &videos, 5).unwrap()), vec!["/photos/clip.mov"]);
+    }
+
+    #[test]
+    fn test_get_photos_page_cursor_walks_every_row_once() {
+        let conn = setup_db();
+        // Pairs of photos share a date so the id tiebreaker is exercised
+        for i in 0..25 {
+            insert_dated(&conn, &format!("p{:02}.jpg", i), 1_600_000_000 + (i / 2) as i64);
+        }
+
+        let mut seen = Vec::new();
+        let mut cursor = None;
+        let mut pages = 0;
+        loop {
+            let page = get_photos_page(&conn, PhotoSort::DateDesc, 0, 10, cursor.as_ref(), &PhotoFilter::default()).unwrap();
+            pages += 1;
+            seen.extend(page.photos.iter().map(|p| p.date_taken));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        assert_eq!(pages, 3);
+        assert_eq!(seen.len(), 25);
+        assert!(seen.windows(2).all(|w| w[0] >= w[1]));
+    }
+
+    #[test]
+    fn test_get_photos_page_oldest_first_ends_with_undated_photos() {
+        let conn = setup_db();
+        insert_dated(&conn, "new.jpg", 200);
+        for name in ["u1.jpg", "u2.jpg"] {
+            let mut photo = test_photo(&format!("/photos/{}", name), name);
+            photo.date_taken = 0;
+            photo.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        insert_dated(&conn, "old.jpg", 100);
+
+        // One per page, so the cursor crosses from dated to undated rows
+        let mut names = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = get_photos_page(&conn, PhotoSort::DateAsc, 0, 1, cursor.as_ref(), &PhotoFilter::default()).unwrap();
+            names.extend(page.photos.into_iter().map(|p| p.name));
+            match page.next_cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+        assert_eq!(names, vec!["old.jpg", "new.jpg", "u1.jpg", "u2.jpg"]);
+    }
+
+    #[test]
+    fn test_get_photos_page_offset_matches_cursor() {
+        let conn = setup_db();
+        for i in 0..12 {
+            insert_dated(&conn, &format!("p{:02}.jpg", i), 1_600_000_000 + i as i64);
+        }
+
+        let first = get_photos_page(&conn, PhotoSort::DateAsc, 0, 5, None, &PhotoFilter::default()).unwrap();
+        let by_cursor =
+            get_photos_page(&conn, PhotoSort::DateAsc, 0, 5, first.next_cursor.as_ref(), &PhotoFilter::default()).unwrap();
+        let by_offset = get_photos_page(&conn, PhotoSort::DateAsc, 5, 5, None, &PhotoFilter::default()).unwrap();
+
+        let paths = |page: &PhotoPage| page.photos.iter().map(|p| p.path.clone()).collect::<Vec<_>>();
+        assert_eq!(paths(&by_cursor), paths(&by_offset));
+        assert_eq!(by_cursor.photos[0].name, "p05.jpg");
+    }
+
+    #[test]
+    fn test_get_photos_page_name_sort_ignores_case() {
+        let conn = setup_db();
+        insert_dated(&conn, "b.jpg", 1);
+        insert_dated(&conn, "A.jpg", 2);
+        insert_dated(&conn, "c.jpg", 3);
+
+        let page = get_photos_page(&conn, PhotoSort::NameAsc, 0, 10, None, &PhotoFilter::default()).unwrap();
+        let names: Vec<&str> = page.photos.iter().map(|p| p.name.as_str()).collect();
+        assert_eq!(names, vec!["A.jpg", "b.jpg", "c.jpg"]);
+        assert!(page.next_cursor.is_none());
+
+        let page = get_photos_page(&conn, PhotoSort::NameDesc, 0, 2, None, &PhotoFilter::default()).unwrap();
+        let rest = get_photos_page(&conn, PhotoSort::NameDesc, 0, 2, page.next_cursor.as_ref(), &PhotoFilter::default()).unwrap();
+        assert_eq!(rest.photos.len(), 1);
+        assert_eq!(rest.photos[0].name, "A.jpg");
+    }
+
+    #[test]
+    fn test_get_photos_page_skips_archived_like_photo_count() {
+        let conn = setup_db();
+        insert_dated(&conn, "kept.jpg", 1);
+        insert_dated(&conn, "archived.jpg", 2);
+        archive_photo(&conn, "/photos/archived.jpg").unwrap();
+
+        let page = get_photos_page(&conn, PhotoSort::DateDesc, 0, 10, None, &PhotoFilter::default()).unwrap();
+        assert_eq!(page.photos.len(), 1);
+        assert_eq!(page.photos[0].name, "kept.jpg");
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 1);
+    }
+
+    fn insert_hashed(conn: &Connection, path: &str, hash: Option<&str>, size: Option<i64>) {
+        let mut photo = test_photo(path, path.rsplit('/').next().unwrap());
+        photo.content_hash = hash.map(String::from);
+        insert_photo(conn, &photo, "scan").unwrap();
+        if let Some(size) = size {
+            update_photo_file_size(conn, path, size).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_find_photo_by_hash_prefers_live_photos() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/lib/archived.jpg", Some("h1"), None);
+        insert_hashed(&conn, "/lib/live.jpg", Some("h1"), None);
+        archive_photo(&conn, "/lib/archived.jpg").unwrap();
+
+        assert_eq!(find_photo_by_hash(&conn, "h1").unwrap().unwrap().path, "/lib/live.jpg");
+        assert!(find_photo_by_hash(&conn, "nope").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_find_exact_duplicates_groups_and_sorts_by_wasted_space() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/a/small1.jpg", Some("small"), Some(100));
+        insert_hashed(&conn, "/b/small2.jpg", Some("small"), Some(100));
+        insert_hashed(&conn, "/a/big1.mov", Some("big"), Some(5_000));
+        insert_hashed(&conn, "/b/big2.mov", Some("big"), Some(5_000));
+        insert_hashed(&conn, "/c/big3.mov", Some("big"), Some(5_000));
+        insert_hashed(&conn, "/a/unique.jpg", Some("unique"), Some(10));
+        insert_hashed(&conn, "/a/unhashed.jpg", None, None);
+
+        let groups = find_exact_duplicates(&conn).unwrap();
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].content_hash, "big");
+        assert_eq!(groups[0].photos.len(), 3);
+        assert_eq!(groups[0].reclaimable_bytes, 10_000);
+        assert_eq!(groups[1].content_hash, "small");
+        assert_eq!(groups[1].reclaimable_bytes, 100);
+    }
+
+    #[test]
+    fn test_find_exact_duplicates_ignores_archived_copies() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/a/one.jpg", Some("same"), Some(100));
+        insert_hashed(&conn, "/b/two.jpg", Some("same"), Some(100));
+        archive_photo(&conn, "/b/two.jpg").unwrap();
+
+        assert!(find_exact_duplicates(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_content_hash_backfill_roundtrip() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/a/old.jpg", None, None);
+        insert_hashed(&conn, "/a/new.jpg", Some("known"), None);
+
+        assert_eq!(get_photos_without_content_hash(&conn).unwrap(), vec!["/a/old.jpg".to_string()]);
+        update_content_hashes_batch(&conn, &[("/a/old.jpg".to_string(), "deadbeef".to_string())]).unwrap();
+        assert!(get_photos_without_content_hash(&conn).unwrap().is_empty());
+
+        // A rewritten file's thumbnail is made again under its new hash
+        set_thumb_status(&conn, "/a/new.jpg", "ready").unwrap();
+        update_content_hashes_batch(&conn, &[("/a/new.jpg".to_string(), "known".to_string())]).unwrap();
+        assert_eq!(get_photo_details(&conn, "/a/new.jpg").unwrap().unwrap().photo.thumb_status.as_deref(), Some("ready"));
+        update_content_hashes_batch(&conn, &[("/a/new.jpg".to_string(), "rewritten".to_string())]).unwrap();
+        assert_eq!(get_photo_details(&conn, "/a/new.jpg").unwrap().unwrap().photo.thumb_status, None);
+    }
+
+    #[test]
+    fn test_phash_roundtrip_preserves_high_bit() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+
+        let hashes = [u64::MAX, 1, 0x8000_0000_0000_0000, 42];
+        update_phashes_batch(&conn, &[("/p/a.jpg".to_string(), hashes)]).unwrap();
+
+        assert_eq!(get_photos_without_phash(&conn).unwrap(), vec!["/p/b.jpg".to_string()]);
+        let with = get_photos_with_phash(&conn).unwrap();
+        assert_eq!(with.len(), 1);
+        assert_eq!(with[0].0.path, "/p/a.jpg");
+        assert_eq!(with[0].1, hashes);
+    }
+
+    #[test]
+    fn test_trashed_photo_hidden_but_keeps_album_membership() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo("/lib/b.jpg", "b.jpg"), "upload").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/a.jpg").unwrap();
+
+        trash_photo(&conn, "/lib/a.jpg", Some("/lib/.trash/a.jpg")).unwrap();
+
+        let all: Vec<String> = get_all_photos(&conn).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(all, vec!["/lib/b.jpg".to_string()]);
+        assert!(get_album_photos(&conn, album_id).unwrap().is_empty());
+        assert_eq!(get_albums(&conn).unwrap()[0].count, 0);
+
+        let trashed = get_trashed_photos(&conn).unwrap();
+        assert_eq!(trashed.len(), 1);
+        assert_eq!(trashed[0].0.path, "/lib/a.jpg");
+        assert_eq!(trashed[0].1.as_deref(), Some("/lib/.trash/a.jpg"));
+        // Its file is in the trash folder, not missing
+        assert!(!flag_photo_missing(&conn, "/lib/a.jpg").unwrap());
+        set_photo_missing(&conn, "/lib/a.jpg", true).unwrap();
+
+        untrash_photo(&conn, "/lib/a.jpg").unwrap();
+        assert_eq!(get_album_photos(&conn, album_id).unwrap().len(), 1);
+        assert!(get_missing_photos(&conn).unwrap().is_empty());
+
+        // A rescan finding the file at its path again brings it back
+        trash_photo(&conn, "/lib/b.jpg", None).unwrap();
+        insert_photo(&conn, &test_photo("/lib/b.jpg", "b.jpg"), "scan").unwrap();
+        assert!(get_trashed_photos(&conn).unwrap().is_empty());
+        assert!(get_trashed_photos(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_get_trashed_before_and_entries() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/old.jpg", "old.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo("/lib/live.jpg", "live.jpg"), "upload").unwrap();
+        trash_photo(&conn, "/lib/old.jpg", None).unwrap();
+        conn.execute("UPDATE photos SET deleted_at = 100 WHERE path = '/lib/old.jpg'", []).unwrap();
+
+        assert_eq!(get_trashed_before(&conn, 200).unwrap(), vec![("/lib/old.jpg".to_string(), None)]);
+        assert!(get_trashed_before(&conn, 50).unwrap().is_empty());
+
+        // Entries only include rows that are actually in the trash
+        let entries = get_trash_entries(&conn, &["/lib/old.jpg".to_string(), "/lib/live.jpg".to_string()]).unwrap();
+        assert_eq!(entries.len(), 1);
+    }
+
+    #[test]
+    fn test_update_photo_path_moves_memberships() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/old.jpg", "old.jpg"), "upload").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/old.jpg").unwrap();
+        set_album_cover(&conn, album_id, "/lib/old.jpg").unwrap();
+        let tag_id = create_tag(&conn, "beach", "#00f").unwrap();
+        add_tags_to_photos(&conn, &[tag_id], &["/lib/old.jpg".to_string()]).unwrap();
+
+        update_photo_path(&conn, "/lib/old.jpg", "/lib/new.jpg").unwrap();
+
+        assert_eq!(get_album_photos(&conn, album_id).unwrap()[0].path, "/lib/new.jpg");
+        assert_eq!(get_albums(&conn).unwrap()[0].cover_photo_path.as_deref(), Some("/lib/new.jpg"));
+        assert_eq!(get_tags_for_photo(&conn, "/lib/new.jpg").unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_rename_photo_updates_name_and_search() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/DSC_0042.jpg", "DSC_0042.jpg"), "upload").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/DSC_0042.jpg").unwrap();
+
+        let renamed = rename_photo(&conn, "/lib/DSC_0042.jpg", "/lib/Lake Tahoe.jpg").unwrap().unwrap();
+        assert_eq!(renamed.name, "Lake Tahoe.jpg");
+        assert_eq!(get_album_photos(&conn, album_id).unwrap()[0].path, "/lib/Lake Tahoe.jpg");
+        assert_eq!(search_photos(&conn, "tahoe").unwrap().len(), 1);
+        assert!(rename_photo(&conn, "/lib/gone.jpg", "/lib/new.jpg").unwrap().is_none());
+
+        // A batch may hand one photo's name to another
+        insert_photo(&conn, &test_photo("/lib/b.jpg", "b.jpg"), "upload").unwrap();
+        let swap = [("/lib/Lake Tahoe.jpg", "/lib/b.jpg"), ("/lib/b.jpg", "/lib/a.jpg")].map(|(a, b)| (a.to_string(), b.to_string()));
+        rename_photos(&conn, &swap).unwrap();
+        assert_eq!(get_album_photos(&conn, album_id).unwrap()[0].name, "b.jpg");
+        let mut names: Vec<String> = get_all_photos(&conn).unwrap().into_iter().map(|p| p.path).collect();
+        names.sort();
+        assert_eq!(names, vec!["/lib/a.jpg", "/lib/b.jpg"]);
+    }
+
+    #[test]
+    fn test_permanently_delete_photo_reports_orphaned_hash() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/lib/a.jpg", Some("samehash"), None);
+        insert_hashed(&conn, "/lib/b.jpg", Some("samehash"), None);
+        insert_hashed(&conn, "/lib/c.jpg", None, None);
+
+        assert_eq!(get_content_hash(&conn, "/lib/a.jpg").unwrap().as_deref(), Some("samehash"));
+        assert_eq!(permanently_delete_photo(&conn, "/lib/a.jpg").unwrap(), None);
+        assert_eq!(permanently_delete_photo(&conn, "/lib/b.jpg").unwrap().as_deref(), Some("samehash"));
+        assert_eq!(permanently_delete_photo(&conn, "/lib/c.jpg").unwrap(), None);
+        assert_eq!(permanently_delete_photo(&conn, "/lib/missing.jpg").unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_photo_rotation_takes_new_dimensions_and_reports_orphaned_hash() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/lib/a.jpg", Some("samehash"), None);
+        insert_hashed(&conn, "/lib/b.jpg", Some("samehash"), None);
+        insert_hashed(&conn, "/lib/c.png", Some("pnghash"), None);
+
+        // A duplicate still uses the old hash, so its thumbnails stay
+        let (rotated, orphaned) = record_photo_rotation(&conn, "/lib/a.jpg", Some((1080, 1920)), Some(6), Some("turned")).unwrap().unwrap();
+        assert_eq!((rotated.width, rotated.height), (1080, 1920));
+        assert_eq!(rotated.orientation, Some(6));
+        assert_eq!(rotated.content_hash.as_deref(), Some("turned"));
+        assert_eq!(orphaned, None);
+
+        // Re-encoded pixels keep the stored orientation; unreadable dimensions keep the old
+        let (rotated, orphaned) = record_photo_rotation(&conn, "/lib/a.jpg", None, None, Some("turned again")).unwrap().unwrap();
+        assert_eq!((rotated.width, rotated.height), (1080, 1920));
+        assert_eq!(rotated.orientation, Some(6));
+        assert_eq!(orphaned.as_deref(), Some("turned"));
+
+        let (rotated, orphaned) = record_photo_rotation(&conn, "/lib/c.png", Some((1080, 1920)), None, Some("png turned")).unwrap().unwrap();
+        assert_eq!((rotated.width, rotated.height), (1080, 1920));
+        assert_eq!(orphaned.as_deref(), Some("pnghash"));
+        assert_eq!(record_photo_rotation(&conn, "/lib/missing.jpg", None, None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_record_external_edit_takes_new_dimensions_and_hash() {
+        let conn = setup_db();
+        insert_hashed(&conn, "/lib/a.jpg", Some("before"), None);
+        set_thumb_status(&conn, "/lib/a.jpg", "ready").unwrap();
+
+        let (changed, orphaned) = record_external_edit(&conn, "/lib/a.jpg", Some((1200, 800)), Some("after")).unwrap().unwrap();
+        assert_eq!((changed.width, changed.height), (1200, 800));
+        assert_eq!(changed.content_hash.as_deref(), Some("after"));
+        assert_eq!(orphaned.as_deref(), Some("before"));
+        let status: Option<String> =
+            conn.query_row("SELECT thumb_status FROM photos WHERE path = '/lib/a.jpg'", [], |row| row.get(0)).unwrap();
+        assert_eq!(status, None);
+
+        // Unreadable dimensions keep the stored ones
+        let (changed, _) = record_external_edit(&conn, "/lib/a.jpg", None, Some("again")).unwrap().unwrap();
+        assert_eq!((changed.width, changed.height), (1200, 800));
+        assert_eq!(record_external_edit(&conn, "/lib/missing.jpg", None, None).unwrap(), None);
+    }
+
+    #[test]
+    fn test_permanently_delete_photo_drops_memberships() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/gone.jpg", "gone.jpg"), "upload").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/gone.jpg").unwrap();
+
+        permanently_delete_photo(&conn, "/lib/gone.jpg").unwrap();
+
+        let remaining: i64 = conn
+            .query_row("SELECT COUNT(*) FROM album_photos", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+
+    #[test]
+    fn test_photo_description_set_clear_and_details() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+
+        assert!(set_photo_description(&conn, "/p/a.jpg", "  Grandma's 90th, back porch ").unwrap());
+        let details = get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap();
+        assert_eq!(details.description.as_deref(), Some("Grandma's 90th, back porch"));
+        assert_eq!(details.source_type, "scan");
+        assert_eq!(details.photo.name, "a.jpg");
+
+        assert!(set_photo_description(&conn, "/p/a.jpg", "").unwrap());
+        let details = get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap();
+        assert!(details.description.is_none());
+
+        assert!(!set_photo_description(&conn, "/p/missing.jpg", "x").unwrap());
+        assert!(get_photo_details(&conn, "/p/missing.jpg").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_photo_edits_set_replace_clear_and_cascade() {
+        use crate::edits::CropRect;
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        let straighten = PhotoEdits { rotation: 2.5, ..Default::default() };
+        let cropped = PhotoEdits {
+            flip_horizontal: true,
+            crop: Some(CropRect { x: 0.1, y: 0.1, width: 0.5, height: 0.8 }),
+            ..Default::default()
+        };
+
+        assert!(set_photo_edits(&conn, "/p/a.jpg", &straighten).unwrap());
+        assert!(set_photo_edits(&conn, "/p/a.jpg", &cropped).unwrap());
+        assert_eq!(get_photo_edits(&conn, "/p/a.jpg").unwrap(), Some(cropped));
+        assert_eq!(get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap().edits, Some(cropped));
+
+        // The grid finds the edited thumbnail by key, through aliased queries too
+        let edited_key = crate::thumbnails::edited_key("abc123", &cropped);
+        let photos = get_photos_by_paths(&conn, &["/p/a.jpg".to_string()]).unwrap();
+        assert_eq!(photos[0].edited_key.as_deref(), Some(edited_key.as_str()));
+        let album = create_album(&conn, "Edited").unwrap();
+        add_photos_to_album_by_id(&conn, album, &[photos[0].id.unwrap()]).unwrap();
+        assert_eq!(get_album_photos(&conn, album).unwrap()[0].edited_key.as_deref(), Some(edited_key.as_str()));
+
+        // A recipe that changes nothing is the same as clearing
+        assert!(set_photo_edits(&conn, "/p/a.jpg", &PhotoEdits::default()).unwrap());
+        assert_eq!(get_photo_edits(&conn, "/p/a.jpg").unwrap(), None);
+        assert_eq!(get_photos_by_paths(&conn, &["/p/a.jpg".to_string()]).unwrap()[0].edited_key, None);
+        assert!(!clear_photo_edits(&conn, "/p/a.jpg").unwrap());
+        assert!(!set_photo_edits(&conn, "/p/missing.jpg", &straighten).unwrap());
+
+        set_photo_edits(&conn, "/p/a.jpg", &straighten).unwrap();
+        assert!(clear_photo_edits(&conn, "/p/a.jpg").unwrap());
+        set_photo_edits(&conn, "/p/a.jpg", &straighten).unwrap();
+        permanently_delete_photo(&conn, "/p/a.jpg").unwrap();
+        let left: i64 = conn.query_row("SELECT COUNT(*) FROM photo_edits", [], |row| row.get(0)).unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn test_original_path_is_recorded_for_uploads() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/2024/05/a.jpg", "a.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+
+        set_original_paths(&conn, &[("/lib/2024/05/a.jpg".to_string(), "/Volumes/SD/DCIM/a.jpg".to_string())]).unwrap();
+
+        let details = get_photo_details(&conn, "/lib/2024/05/a.jpg").unwrap().unwrap();
+        assert_eq!(details.original_path.as_deref(), Some("/Volumes/SD/DCIM/a.jpg"));
+        assert!(get_photo_details(&conn, "/p/b.jpg").unwrap().unwrap().original_path.is_none());
+    }
+
+    #[test]
+    fn test_date_discrepancies_are_recorded_and_cleared() {
+        let conn = setup_db();
+        let mut photo = test_photo("/p/IMG_20150704_120000.jpg", "IMG_20150704_120000.jpg");
+        photo.filename_date_mismatch = Some(1_436_011_200);
+        insert_photo(&conn, &photo, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+
+        let found = get_date_discrepancies(&conn).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].photo.path, "/p/IMG_20150704_120000.jpg");
+        assert_eq!(found[0].filename_date, 1_436_011_200);
+
+        // A rescan that no longer disagrees clears it
+        photo.filename_date_mismatch = None;
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert!(get_date_discrepancies(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_manual_dates_survive_rescans() {
+        let conn = setup_db();
+        let mut photo = test_photo("/p/a.jpg", "a.jpg");
+        photo.date_source = Some(media::DATE_SOURCE_MTIME.to_string());
+        photo.date_confident = Some(false);
+        insert_photo(&conn, &photo, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+
+        let set = set_photo_date(&conn, "/p/a.jpg", 946_684_800).unwrap().unwrap();
+        assert_eq!(set.date_taken, 946_684_800);
+        assert_eq!(set_photo_date(&conn, "/p/missing.jpg", 0).unwrap(), None);
+
+        // A camera three hours slow
+        let paths = vec!["/p/a.jpg".to_string(), "/p/b.jpg".to_string(), "/p/missing.jpg".to_string()];
+        let shifted = shift_photo_dates(&conn, &paths, 3 * 60 * 60).unwrap();
+        assert_eq!(shifted.len(), 2);
+        assert_eq!(shifted[1].date_taken, 1_700_000_000 + 3 * 60 * 60);
+
+        // The offset the manual date was written in stays with it too
+        photo.tz_offset_minutes = Some(-300);
+        insert_photo(&conn, &photo, "scan").unwrap();
+        let stored = get_all_photos(&conn).unwrap().into_iter().find(|p| p.path == "/p/a.jpg").unwrap();
+        assert_eq!(stored.date_taken, 946_684_800 + 3 * 60 * 60);
+        assert_eq!(stored.date_source.as_deref(), Some(media::DATE_SOURCE_MANUAL));
+        assert_eq!(stored.date_confident, Some(true));
+        assert_eq!(stored.tz_offset_minutes, None);
+    }
+
+    #[test]
+    fn test_apple_photos_dates_survive_rescans_of_the_copy() {
+        let conn = setup_db();
+        let asset = crate::apple_photos::AppleAsset {
+            id: 1,
+            original: "/Pictures/Photos Library.photoslibrary/originals/A/A1.heic".into(),
+            file_name: Some("IMG_0001.HEIC".to_string()),
+            date_taken: Some(1_559_399_405),
+            width: 4032,
+            height: 3024,
+            is_favorite: true,
+            gps: None,
+        };
+        // Nothing in the copied file dates it
+        let mut copied = test_photo("/library/2019/IMG_0001.HEIC", "A1.heic");
+        copied.date_taken = media::UNKNOWN_DATE;
+        copied.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        copied.date_confident = Some(false);
+        asset.overlay(&mut copied);
+        insert_photo(&conn, &copied, "apple_photos").unwrap();
+
+        // A rescan or the watcher reads the copied file again
+        let mut rescanned = test_photo("/library/2019/IMG_0001.HEIC", "IMG_0001.HEIC");
+        rescanned.date_source = Some(media::DATE_SOURCE_MTIME.to_string());
+        rescanned.date_confident = Some(false);
+        insert_photo(&conn, &rescanned, "scan").unwrap();
+        let stored = get_photos_by_paths(&conn, &[copied.path.clone()]).unwrap().remove(0);
+        assert_eq!(stored.date_taken, 1_559_399_405);
+        assert_eq!(stored.date_source.as_deref(), Some(media::DATE_SOURCE_SIDECAR));
+        assert_eq!(stored.date_confident, Some(true));
+        assert_eq!(stored.name, "IMG_0001.HEIC");
+        assert!(get_undated_photos(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_export_json_dates_survive_rescans_without_the_json() {
+        let conn = setup_db();
+        let mut imported = test_photo("/ig/media/posts/a.mp4", "a.mp4");
+        imported.date_taken = 1_559_399_405;
+        imported.date_source = Some(media::DATE_SOURCE_SIDECAR.to_string());
+        imported.date_confident = Some(true);
+        insert_photo(&conn, &imported, "instagram").unwrap();
+
+        // A rescan finds only the transcode time in the container
+        let mut rescanned = test_photo("/ig/media/posts/a.mp4", "a.mp4");
+        rescanned.date_source = Some(media::DATE_SOURCE_EXIF.to_string());
+        rescanned.tz_offset_minutes = Some(60);
+        insert_photo(&conn, &rescanned, "scan").unwrap();
+        let stored = get_photos_by_paths(&conn, &[imported.path.clone()]).unwrap().remove(0);
+        assert_eq!(stored.date_taken, 1_559_399_405);
+        assert_eq!(stored.date_source.as_deref(), Some(media::DATE_SOURCE_SIDECAR));
+        assert_eq!(stored.tz_offset_minutes, None);
+
+        // Importing the export again takes its date as it now reads
+        imported.date_taken = 1_559_399_000;
+        insert_photo(&conn, &imported, "instagram").unwrap();
+        let stored = get_photos_by_paths(&conn, &[imported.path.clone()]).unwrap().remove(0);
+        assert_eq!(stored.date_taken, 1_559_399_000);
+    }
+
+    #[test]
+    fn test_date_shift_is_logged_and_undone() {
+        let conn = setup_db();
+        let mut photo = test_photo("/lib/2015/06/a.jpg", "a.jpg");
+        photo.date_source = Some(media::DATE_SOURCE_EXIF.to_string());
+        insert_photo(&conn, &photo, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/2015/06/b.jpg", "b.jpg"), "scan").unwrap();
+
+        let paths = vec!["/lib/2015/06/a.jpg".to_string(), "/lib/2015/06/b.jpg".to_string(), "/p/missing.jpg".to_string()];
+        assert_eq!(get_photo_dates(&conn, &paths).unwrap().len(), 2);
+        let (shift_id, shifted) = record_date_shift(&conn, &paths, 86_400).unwrap().unwrap();
+        assert_eq!(shifted.len(), 2);
+        assert_eq!(shifted[0].date_taken, 1_700_000_000 + 86_400);
+        assert!(record_date_shift(&conn, &["/p/missing.jpg".to_string()], 60).unwrap().is_none());
+
+        update_photo_path(&conn, "/lib/2015/06/a.jpg", "/lib/2023/11/a.jpg").unwrap();
+        record_date_shift_move(&conn, shift_id, "/lib/2015/06/a.jpg", "/lib/2023/11/a.jpg").unwrap();
+        assert_eq!(
+            get_date_shift_moves(&conn, shift_id).unwrap().unwrap(),
+            vec![DateShiftMove { current_path: "/lib/2023/11/a.jpg".to_string(), old_path: "/lib/2015/06/a.jpg".to_string() }]
+        );
+
+        // b's date was set again by hand after the shift, so it keeps it, and
+        // its file stays in the folder of the date it has now
+        update_photo_path(&conn, "/lib/2015/06/b.jpg", "/lib/2023/11/b.jpg").unwrap();
+        record_date_shift_move(&conn, shift_id, "/lib/2015/06/b.jpg", "/lib/2023/11/b.jpg").unwrap();
+        set_photo_date(&conn, "/lib/2023/11/b.jpg", 5).unwrap();
+        assert_eq!(get_date_shift_moves(&conn, shift_id).unwrap().unwrap().len(), 1);
+        assert_eq!(undo_date_shift(&conn, shift_id).unwrap(), Some(1));
+        let photos = get_all_photos(&conn).unwrap();
+        let a = photos.iter().find(|p| p.path == "/lib/2023/11/a.jpg").unwrap();
+        assert_eq!(a.date_taken, 1_700_000_000);
+        assert_eq!(a.date_source.as_deref(), Some(media::DATE_SOURCE_EXIF));
+        assert_eq!(photos.iter().find(|p| p.path == "/lib/2023/11/b.jpg").unwrap().date_taken, 5);
+        assert_eq!(undo_date_shift(&conn, shift_id).unwrap(), None);
+        assert_eq!(get_date_shift_moves(&conn, shift_id).unwrap(), None);
+    }
+
+    #[test]
+    fn test_operations_log_and_undo_helpers() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        let paths = vec!["/p/a.jpg".to_string(), "/p/b.jpg".to_string(), "/p/missing.jpg".to_string()];
+        let ids = get_photo_ids(&conn, &paths).unwrap();
+        assert_eq!(ids.len(), 2);
+
+        let first = record_operation(&conn, &Operation::MoveToTrash { photo_ids: ids.clone() }).unwrap();
+        set_photo_favorite(&conn, "/p/a.jpg", true).unwrap();
+        let flags = get_favorite_flags(&conn, &paths).unwrap();
+        assert_eq!(flags, vec![(ids[0], true), (ids[1], false)]);
+        let second =
+            record_operation(&conn, &Operation::SetFavorites { previous: flags.clone(), is_favorite: true }).unwrap();
+
+        let recent = get_recent_operations(&conn, 10).unwrap();
+        assert_eq!(recent.iter().map(|op| op.id).collect::<Vec<_>>(), vec![second, first]);
+        assert_eq!(recent[0].kind, "set_favorites");
+        assert_eq!(recent[1].description, "Moved 2 photos to the trash");
+        let (last, _) = get_last_operation(&conn).unwrap().unwrap();
+        assert_eq!(last, recent.into_iter().next().unwrap());
+
+        set_photos_favorite(&conn, &paths, true).unwrap();
+        // A flag changed since blocks the undo
+        set_photo_favorite(&conn, "/p/b.jpg", false).unwrap();
+        assert!(!restore_favorite_flags(&conn, &flags, true).unwrap());
+        set_photo_favorite(&conn, "/p/b.jpg", true).unwrap();
+        assert!(restore_favorite_flags(&conn, &flags, true).unwrap());
+        assert_eq!(get_favorite_flags(&conn, &paths).unwrap(), flags);
+
+        delete_operation(&conn, second).unwrap();
+        let (last, operation) = get_last_operation(&conn).unwrap().unwrap();
+        assert_eq!((last.id, operation), (first, Some(Operation::MoveToTrash { photo_ids: ids.clone() })));
+        assert_eq!(prune_operations(&conn, i64::MAX).unwrap(), 1);
+        assert!(get_last_operation(&conn).unwrap().is_none());
+
+        // Album memberships come back with their original added_at
+        let album = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album, "/p/a.jpg").unwrap();
+        let members = get_album_members(&conn, album, &paths).unwrap();
+        assert_eq!(members.len(), 1);
+        remove_photo_from_album(&conn, album, "/p/a.jpg").unwrap();
+        assert!(restore_album_members(&conn, album, &members).unwrap());
+        assert_eq!(get_album_members(&conn, album, &paths).unwrap(), members);
+        assert!(!restore_album_members(&conn, album + 100, &members).unwrap());
+
+        // A date is only put back while it still holds the value that was set
+        let previous = get_previous_date(&conn, "/p/b.jpg").unwrap().unwrap();
+        set_photo_date(&conn, "/p/b.jpg", 5).unwrap();
+        assert!(!restore_previous_date(&conn, &previous, 6).unwrap());
+        assert!(restore_previous_date(&conn, &previous, 5).unwrap());
+        assert_eq!(get_previous_date(&conn, "/p/b.jpg").unwrap().unwrap(), previous);
+
+        delete_photo(&conn, "/p/b.jpg").unwrap();
+        let found = get_photo_paths_by_id(&conn, &ids).unwrap();
+        assert_eq!(found, vec![Some(("/p/a.jpg".to_string(), false)), None]);
+    }
+
+    #[test]
+    fn test_undated_photos_are_listed_last() {
+        let conn = setup_db();
+        let mut dated = test_photo("/p/dated.jpg", "dated.jpg");
+        dated.date_source = Some(media::DATE_SOURCE_EXIF.to_string());
+        dated.date_confident = Some(true);
+        insert_photo(&conn, &dated, "scan").unwrap();
+        let mut undated = test_photo("/p/undated.jpg", "undated.jpg");
+        undated.date_taken = media::UNKNOWN_DATE;
+        undated.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        undated.date_confident = Some(false);
+        insert_photo(&conn, &undated, "scan").unwrap();
+
+        let all = get_all_photos(&conn).unwrap();
+        assert_eq!(all.last().unwrap().path, "/p/undated.jpg");
+        let found = get_undated_photos(&conn).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].date_confident, Some(false));
+    }
+
+    #[test]
+    fn test_import_stamped_dates_are_flagged() {
+        let conn = setup_db();
+        // Stamped with the import time because the file had no date at all
+        let mut stamped = test_photo("/nonexistent/stamped.jpg", "stamped.jpg");
+        stamped.date_taken = chrono::Utc::now().timestamp() - 5;
+        insert_photo(&conn, &stamped, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/nonexistent/old.jpg", "old.jpg"), "scan").unwrap();
+
+        flag_import_stamped_dates(&conn).unwrap();
+        let undated = get_undated_photos(&conn).unwrap();
+        assert_eq!(undated.len(), 1);
+        assert_eq!(undated[0].path, "/nonexistent/stamped.jpg");
+        assert_eq!(undated[0].date_taken, media::UNKNOWN_DATE);
+    }
+
+    #[test]
+    fn test_live_photo_video_is_hidden_behind_its_still() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/IMG_0001.HEIC", "IMG_0001.HEIC"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/IMG_0001.MOV", "IMG_0001.MOV"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/IMG_0002.HEIC", "IMG_0002.HEIC"), "scan").unwrap();
+
+        let pairs = vec![
+            ("/p/IMG_0001.HEIC".to_string(), "/p/IMG_0001.MOV".to_string()),
+            // Video not imported yet
+            ("/p/IMG_0002.HEIC".to_string(), "/p/IMG_0002.MOV".to_string()),
+        ];
+        assert_eq!(set_live_photo_pairs(&conn, &pairs).unwrap(), 1);
+
+        let visible: Vec<String> = get_all_photos(&conn).unwrap().into_iter().map(|p| p.path).collect();
+        assert!(!visible.contains(&"/p/IMG_0001.MOV".to_string()));
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+        assert_eq!(get_photos_page(&conn, PhotoSort::DateDesc, 0, 10, None, &PhotoFilter::default()).unwrap().photos.len(), 2);
+        assert_eq!(get_live_photo_video(&conn, "/p/IMG_0001.HEIC").unwrap().as_deref(), Some("/p/IMG_0001.MOV"));
+        assert_eq!(get_live_photo_video(&conn, "/p/IMG_0002.HEIC").unwrap(), None);
+
+        // Deleting only the still leaves the video as a photo of its own
+        permanently_delete_photo(&conn, "/p/IMG_0001.HEIC").unwrap();
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_stacks_show_their_primary_and_promote_on_delete() {
+        let conn = setup_db();
+        for name in ["IMG_0042-edited.jpg", "IMG_0042.JPG", "IMG_0042.CR2", "IMG_0043.JPG"] {
+            insert_photo(&conn, &test_photo(&format!("/p/{}", name), name), "scan").unwrap();
+        }
+        let path = |name: &str| format!("/p/{}", name);
+        let names = |photos: Vec<PhotoMetadata>| -> Vec<String> { photos.into_iter().map(|p| p.name).collect() };
+        let found = vec![vec![path("IMG_0042-edited.jpg"), path("IMG_0042.JPG"), path("IMG_0042.CR2")]];
+        assert_eq!(create_stacks(&conn, &found).unwrap(), 3);
+        // Already stacked
+        assert_eq!(create_stacks(&conn, &found).unwrap(), 0);
+
+        let grid = get_all_photos(&conn).unwrap();
+        assert_eq!(grid.len(), 2);
+        let primary = grid.iter().find(|p| p.name == "IMG_0042-edited.jpg").unwrap();
+        assert_eq!(primary.stack_count, Some(3));
+        let stack_id = primary.stack_id.unwrap();
+        assert_eq!(query_photos(&conn, &PhotoQuery::default(), 0, None).unwrap().len(), 2);
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+        assert_eq!(
+            names(get_stack_members(&conn, stack_id).unwrap()),
+            vec!["IMG_0042-edited.jpg", "IMG_0042.CR2", "IMG_0042.JPG"]
+        );
+
+        set_stack_primary(&conn, stack_id, &path("IMG_0042.CR2")).unwrap();
+        assert!(matches!(set_stack_primary(&conn, stack_id, &path("IMG_0043.JPG")), Err(StackError::NotAMember(_))));
+        assert_eq!(names(get_stack_members(&conn, stack_id).unwrap())[0], "IMG_0042.CR2");
+
+        // A hidden primary leaves the next member on the timeline and shows
+        // in the hidden view; a search finds members that aren't primary
+        set_photos_hidden(&conn, &[path("IMG_0042.CR2")], true).unwrap();
+        let page = get_photos_page(&conn, PhotoSort::DateDesc, 0, 10, None, &PhotoFilter::default()).unwrap();
+        let shown = page.photos.iter().find(|p| p.stack_id == Some(stack_id)).unwrap();
+        assert_eq!((shown.name.as_str(), shown.stack_count), ("IMG_0042-edited.jpg", Some(3)));
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+        assert_eq!(names(get_hidden_photos(&conn).unwrap()), vec!["IMG_0042.CR2"]);
+        set_photos_hidden(&conn, &[path("IMG_0042.CR2")], false).unwrap();
+        let search = PhotoQuery { text: Some("edited".to_string()), ..Default::default() };
+        assert_eq!(names(query_photos(&conn, &search, 0, None).unwrap()), vec!["IMG_0042-edited.jpg"]);
+
+        // Deleting the primary promotes another member
+        permanently_delete_photo(&conn, &path("IMG_0042.CR2")).unwrap();
+        let members = get_stack_members(&conn, stack_id).unwrap();
+        assert_eq!(members.len(), 2);
+        assert_eq!(members[0].stack_count, Some(2));
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 2);
+
+        // Trashing the primary shows the other member until it is restored
+        let shown = members[0].path.clone();
+        trash_photo(&conn, &shown, None).unwrap();
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 2);
+        untrash_photo(&conn, &shown).unwrap();
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 2);
+
+        // Unstacking one of two dissolves the stack, and scans leave it be
+        assert_eq!(unstack_photos(&conn, &[path("IMG_0042.JPG")]).unwrap(), 1);
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 3);
+        assert!(get_stack_members(&conn, stack_id).unwrap().is_empty());
+        assert_eq!(create_stacks(&conn, &found).unwrap(), 0);
+
+        let manual = stack_photos(&conn, &[path("IMG_0042.JPG"), path("IMG_0043.JPG")], Some(&path("IMG_0043.JPG"))).unwrap();
+        assert_eq!(names(get_stack_members(&conn, manual).unwrap()), vec!["IMG_0043.JPG", "IMG_0042.JPG"]);
+        assert!(matches!(stack_photos(&conn, &[path("IMG_0043.JPG")], None), Err(StackError::TooFewPhotos)));
+    }
+
+    #[test]
+    fn test_raw_and_jpeg_pair_are_separate_photos() {
+        let conn = setup_db();
+        let mut raw = test_photo("/p/IMG_0001.CR2", "IMG_0001.CR2");
+        raw.file_format = Some("cr2".to_string());
+        raw.content_hash = Some("raw".to_string());
+        insert_photo(&conn, &raw, "scan").unwrap();
+        // Imported before file_format existed
+        insert_photo(&conn, &test_photo("/p/IMG_0001.JPG", "IMG_0001.JPG"), "scan").unwrap();
+        conn.execute("UPDATE photos SET file_format = NULL WHERE path = '/p/IMG_0001.JPG'", []).unwrap();
+
+        backfill_file_formats(&conn).unwrap();
+
+        let photos = get_all_photos(&conn).unwrap();
+        let format_of = |path: &str| photos.iter().find(|p| p.path == path).and_then(|p| p.file_format.clone());
+        assert_eq!(photos.len(), 2);
+        assert_eq!(format_of("/p/IMG_0001.CR2").as_deref(), Some("cr2"));
+        assert_eq!(format_of("/p/IMG_0001.JPG").as_deref(), Some("jpeg"));
+    }
 
-    // Total counts
-    let total_photos: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE archived_at IS NULL AND (
-            LOWER(name) LIKE '%.jpg' OR LOWER(name) LIKE '%.jpeg' OR LOWER(name) LIKE '%.png' OR
-            LOWER(name) LIKE '%.heic' OR LOWER(name) LIKE '%.webp' OR LOWER(name) LIKE '%.gif' OR LOWER(name) LIKE '%.bmp'
-        )",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+    #[test]
+    fn test_update_video_info_keeps_known_values() {
+        let conn = setup_db();
+        let mut video = test_photo("/v/clip.mp4", "clip.mp4");
+        video.width = 0;
+        video.height = 0;
+        video.date_taken = 500;
+        insert_photo(&conn, &video, "scan").unwrap();
+        assert_eq!(get_paths_missing_dimensions_or_duration(&conn).unwrap(), vec!["/v/clip.mp4"]);
+
+        update_video_info(&conn, "/v/clip.mp4", 1080, 1920, Some(12_500), Some(1_717_243_200)).unwrap();
+        let photo = &get_all_photos(&conn).unwrap()[0];
+        assert_eq!((photo.width, photo.height), (1080, 1920));
+        assert_eq!(photo.duration_ms, Some(12_500));
+        assert_eq!(photo.date_taken, 1_717_243_200);
+        assert!(get_paths_missing_dimensions_or_duration(&conn).unwrap().is_empty());
+
+        update_video_info(&conn, "/v/clip.mp4", 1080, 1920, None, None).unwrap();
+        let photo = &get_all_photos(&conn).unwrap()[0];
+        assert_eq!(photo.duration_ms, Some(12_500));
+        assert_eq!(photo.date_taken, 1_717_243_200);
+    }
 
-    let total_videos: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE archived_at IS NULL AND (
-            LOWER(name) LIKE '%.mp4' OR LOWER(name) LIKE '%.mov' OR LOWER(name) LIKE '%.avi' OR
-            LOWER(name) LIKE '%.webm' OR LOWER(name) LIKE '%.mkv'
-        )",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+    #[test]
+    fn test_photo_ratings() {
+        let conn = setup_db();
+        insert_dated(&conn, "a.jpg", 100);
+        insert_dated(&conn, "b.jpg", 200);
+        insert_dated(&conn, "c.jpg", 300);
+
+        assert!(set_photo_rating(&conn, "/photos/a.jpg", 5).unwrap());
+        assert!(!set_photo_rating(&conn, "/photos/missing.jpg", 5).unwrap());
+        let paths = vec!["/photos/b.jpg".to_string(), "/photos/c.jpg".to_string()];
+        assert_eq!(set_photos_rating(&conn, &paths, 3).unwrap(), 2);
+        set_photo_rating(&conn, "/photos/c.jpg", 2).unwrap();
+
+        let names = |min: u8| -> Vec<String> {
+            get_photos_by_min_rating(&conn, min).unwrap().into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(names(3), vec!["b.jpg", "a.jpg"]);
+        assert_eq!(names(5), vec!["a.jpg"]);
+        assert_eq!(names(0).len(), 3);
 
-    let total_screenshots: i64 = conn.query_row(
-        "SELECT COUNT(*) FROM photos WHERE is_screenshot = 1 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+        let photos = get_all_photos(&conn).unwrap();
+        assert_eq!(photos.iter().find(|p| p.name == "a.jpg").unwrap().rating, 5);
+    }
 
-    // Size by media type
-    let photos_size: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(file_size), 0) FROM photos WHERE archived_at IS NULL AND (
-            LOWER(name) LIKE '%.jpg' OR LOWER(name) LIKE '%.jpeg' OR LOWER(name) LIKE '%.png' OR
-            LOWER(name) LIKE '%.heic' OR LOWER(name) LIKE '%.webp' OR LOWER(name) LIKE '%.gif' OR LOWER(name) LIKE '%.bmp'
-        )",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+    #[test]
+    fn test_embedded_rating_only_fills_unrated_rows() {
+        let conn = setup_db();
+        let mut photo = test_photo("/p/a.jpg", "a.jpg");
+        photo.rating = 4;
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert_eq!(get_photos_by_min_rating(&conn, 4).unwrap().len(), 1);
+
+        set_photo_rating(&conn, "/p/a.jpg", 2).unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert_eq!(get_all_photos(&conn).unwrap()[0].rating, 2);
+
+        // A rating the user cleared stays cleared
+        set_photos_rating(&conn, &["/p/a.jpg".to_string()], 0).unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert_eq!(get_all_photos(&conn).unwrap()[0].rating, 0);
+    }
 
-    let videos_size: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(file_size), 0) FROM photos WHERE archived_at IS NULL AND (
-            LOWER(name) LIKE '%.mp4' OR LOWER(name) LIKE '%.mov' OR LOWER(name) LIKE '%.avi' OR
-            LOWER(name) LIKE '%.webm' OR LOWER(name) LIKE '%.mkv'
-        )",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+    #[test]
+    fn test_color_labels() {
+        let conn = setup_db();
+        let mut photo = test_photo("/p/a.jpg", "a.jpg");
+        photo.color_label = Some(ColorLabel::Green);
+        insert_photo(&conn, &photo, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/c.jpg", "c.jpg"), "scan").unwrap();
+
+        let paths = ["/p/b.jpg", "/p/c.jpg", "/p/missing.jpg"].map(String::from);
+        assert_eq!(set_photos_color_label(&conn, &paths, ColorLabel::Red).unwrap(), 2);
+        assert_eq!(set_photos_color_label(&conn, &paths[1..], ColorLabel::None).unwrap(), 1);
+        let stored: Option<String> =
+            conn.query_row("SELECT color_label FROM photos WHERE path = '/p/c.jpg'", [], |row| row.get(0)).unwrap();
+        assert_eq!(stored, None);
+
+        let names = |label| -> Vec<String> {
+            let query = PhotoQuery { color_label: Some(label), ..Default::default() };
+            query_photos(&conn, &query, 0, None).unwrap().into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(names(ColorLabel::Red), vec!["b.jpg"]);
+        assert_eq!(names(ColorLabel::None), vec!["c.jpg"]);
+        assert!(names(ColorLabel::Purple).is_empty());
+
+        // An embedded label only fills a photo without one
+        set_photos_color_label(&conn, &["/p/a.jpg".to_string()], ColorLabel::Blue).unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        assert_eq!(names(ColorLabel::Blue), vec!["a.jpg"]);
+
+        // ...and never refills one the user cleared
+        set_photos_color_label(&conn, &["/p/a.jpg".to_string()], ColorLabel::None).unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        let mut unlabeled = names(ColorLabel::None);
+        unlabeled.sort();
+        assert_eq!(unlabeled, vec!["a.jpg", "c.jpg"]);
+
+        assert!(conn.execute("UPDATE photos SET color_label = 'teal' WHERE path = '/p/a.jpg'", []).is_err());
+    }
 
-    let screenshots_size: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(file_size), 0) FROM photos WHERE is_screenshot = 1 AND archived_at IS NULL",
-        [],
-        |row| row.get(0),
-    ).unwrap_or(0);
+    #[test]
+    fn test_embedded_description_does_not_overwrite_user_text() {
+        let conn = setup_db();
+        let mut photo = test_photo("/p/a.jpg", "a.jpg");
+        photo.description = Some("From EXIF".to_string());
+        insert_photo(&conn, &photo, "scan").unwrap();
+        let details = get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap();
+        assert_eq!(details.description.as_deref(), Some("From EXIF"));
+
+        set_photo_description(&conn, "/p/a.jpg", "Back porch").unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        let details = get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap();
+        assert_eq!(details.description.as_deref(), Some("Back porch"));
+
+        set_photo_description(&conn, "/p/a.jpg", " ").unwrap();
+        insert_photo(&conn, &photo, "scan").unwrap();
+        let details = get_photo_details(&conn, "/p/a.jpg").unwrap().unwrap();
+        assert_eq!(details.description, None);
+    }
 
-    // Duplicate space (approximate: sum of all duplicate files minus one per group)
-    let duplicate_space_bytes: i64 = conn.query_row(
-        "SELECT COALESCE(SUM(file_size), 0) - (SELECT COUNT(DISTINCT content_hash) * AVG(file_size) FROM photos WHERE content_hash IS NOT NULL AND archived_at IS NULL)
-         FROM photos
-         WHERE content_hash IN (SELECT content_hash FROM photos WHERE archived_at IS NULL GROUP BY content_hash HAVING COUNT(*) > 1)
-         AND archived_at IS NULL",
-        [],
-        |row| row.get::<_, f64>(0).map(|v| v as i64),
-    ).unwrap_or(0);
+    fn search_names(conn: &Connection, query: &str) -> Vec<String> {
+        search_photos(conn, query).unwrap().into_iter().map(|p| p.name).collect()
+    }
 
-    // Size by month (last 12 months)
-    let size_by_month: Vec<MonthSize> = conn.prepare(
-        "SELECT strftime('%Y-%m', date_taken, 'unixepoch') as month,
-                COALESCE(SUM(file_size), 0) as size,
-                COUNT(*) as count
-         FROM photos
-         WHERE archived_at IS NULL AND date_taken > strftime('%s', 'now', '-12 months')
-         GROUP BY month
-         ORDER BY month DESC"
-    )?.query_map([], |row| Ok(MonthSize {
-        month: row.get(0)?,
-        size: row.get(1)?,
-        count: row.get(2)?,
-    }))?.collect::<SqlResult<_>>()?;
+    #[test]
+    fn test_build_fts_query() {
+        assert_eq!(build_fts_query("beac*").as_deref(), Some("\"beac\"*"));
+        assert_eq!(build_fts_query(" beach  dog ").as_deref(), Some("\"beach\" \"dog\""));
+        assert_eq!(build_fts_query("say \"hi\" OR").as_deref(), Some("\"say\" \"hi\" \"OR\""));
+        assert!(build_fts_query("  * ").is_none());
+    }
 
-    // Size by year
-    let size_by_year: Vec<YearSize> = conn.prepare(
-        "SELECT strftime('%Y', date_taken, 'unixepoch') as year,
-                COALESCE(SUM(file_size), 0) as size,
-                COUNT(*) as count
-         FROM photos
-         WHERE archived_at IS NULL
-         GROUP BY year
-         ORDER BY year DESC"
-    )?.query_map([], |row| Ok(YearSize {
-        year: row.get(0)?,
-        size: row.get(1)?,
-        count: row.get(2)?,
-    }))?.collect::<SqlResult<_>>()?;
+    #[test]
+    fn test_search_photos_matches_name_description_and_tags() {
+        let conn = setup_db();
+        insert_dated(&conn, "beach_day.jpg", 100);
+        insert_dated(&conn, "porch.jpg", 200);
+        insert_dated(&conn, "IMG_0001.jpg", 300);
+        set_photo_description(&conn, "/photos/porch.jpg", "Grandma's 90th, back porch").unwrap();
+        let dog = create_tag(&conn, "Dog", "#0f0").unwrap();
+        add_tags_to_photos(&conn, &[dog], &["/photos/IMG_0001.jpg".to_string(), "/photos/beach_day.jpg".to_string()]).unwrap();
+
+        assert_eq!(search_names(&conn, "beach"), vec!["beach_day.jpg"]);
+        assert_eq!(search_names(&conn, "beac*"), vec!["beach_day.jpg"]);
+        assert!(search_names(&conn, "beac").is_empty());
+        assert_eq!(search_names(&conn, "grandma"), vec!["porch.jpg"]);
+        // Newest first, and multiple terms are ANDed
+        assert_eq!(search_names(&conn, "dog"), vec!["IMG_0001.jpg", "beach_day.jpg"]);
+        assert_eq!(search_names(&conn, "dog beach"), vec!["beach_day.jpg"]);
+    }
 
-    // Top 10 largest files
-    let top_largest_files: Vec<LargeFile> = conn.prepare(
-        "SELECT path, name, file_size, date_taken
-         FROM photos
-         WHERE archived_at IS NULL AND file_size IS NOT NULL
-         ORDER BY file_size DESC
-         LIMIT 10"
-    )?.query_map([], |row| Ok(LargeFile {
-        path: row.get(0)?,
-        name: row.get(1)?,
-        size: row.get(2)?,
-        date_taken: row.get(3)?,
-    }))?.collect::<SqlResult<_>>()?;
+    #[test]
+    fn test_search_index_follows_tag_and_photo_changes() {
+        let conn = setup_db();
+        insert_dated(&conn, "a.jpg", 100);
+        let tag = create_tag(&conn, "Vacation", "#00f").unwrap();
+        add_tags_to_photos(&conn, &[tag], &["/photos/a.jpg".to_string()]).unwrap();
+        assert_eq!(search_names(&conn, "vacation"), vec!["a.jpg"]);
+
+        rename_tag(&conn, tag, "Holiday").unwrap();
+        assert!(search_names(&conn, "vacation").is_empty());
+        assert_eq!(search_names(&conn, "holiday"), vec!["a.jpg"]);
+
+        remove_tags_from_photos(&conn, &[tag], &["/photos/a.jpg".to_string()]).unwrap();
+        assert!(search_names(&conn, "holiday").is_empty());
+
+        add_tags_to_photos(&conn, &[tag], &["/photos/a.jpg".to_string()]).unwrap();
+        delete_tag(&conn, tag).unwrap();
+        assert!(search_names(&conn, "holiday").is_empty());
+
+        trash_photo(&conn, "/photos/a.jpg", None).unwrap();
+        assert!(search_names(&conn, "a").is_empty());
+        untrash_photo(&conn, "/photos/a.jpg").unwrap();
+        permanently_delete_photo(&conn, "/photos/a.jpg").unwrap();
+        let indexed: i64 = conn.query_row("SELECT COUNT(*) FROM photos_fts", [], |row| row.get(0)).unwrap();
+        assert_eq!(indexed, 0);
+    }
 
-    Ok(StorageAnalytics {
-        total_size_bytes,
-        total_photos,
-        total_videos,
-        total_screenshots,
-        photos_size,
-        videos_size,
-        screenshots_size,
-        duplicate_space_bytes,
-        size_by_month,
-        size_by_year,
-        top_largest_files,
-    })
-}
+    #[test]
+    fn test_rebuild_search_index_covers_existing_rows() {
+        let conn = setup_db();
+        insert_dated(&conn, "sunset.jpg", 100);
+        insert_dated(&conn, "sunrise.jpg", 200);
+        conn.execute("DELETE FROM photos_fts", []).unwrap();
+        assert!(search_names(&conn, "sunset").is_empty());
 
-// ============================================================================
-// Enriched Metadata Functions
-// ============================================================================
+        assert_eq!(rebuild_search_index(&conn).unwrap(), 2);
+        assert_eq!(search_names(&conn, "sun*"), vec!["sunrise.jpg", "sunset.jpg"]);
+    }
 
-/// Write enriched camera/lens/video metadata for a single photo path.
-/// Only the enrichment columns are updated; all other columns are untouched.
-pub fn update_enriched_metadata(conn: &Connection, path: &str, meta: &crate::metadata_enrich::EnrichedMetadata) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET \
-         camera_make = ?1, camera_model = ?2, lens_model = ?3, \
-         iso = ?4, aperture = ?5, shutter_us = ?6, focal_length_mm = ?7, \
-         orientation = ?8, duration_ms = ?9, codec = ?10 \
-         WHERE path = ?11",
-        params![
-            meta.camera_make,
-            meta.camera_model,
-            meta.lens_model,
-            meta.iso,
-            meta.aperture,
-            meta.shutter_us,
-            meta.focal_length_mm,
-            meta.orientation,
-            meta.duration_ms,
-            meta.codec,
-            path,
-        ],
-    )?;
-    Ok(())
-}
+    #[test]
+    fn test_create_tag_is_case_insensitive_noop() {
+        let conn = setup_db();
+        let id = create_tag(&conn, "Beach", "#00f").unwrap();
+        assert_eq!(create_tag(&conn, "beach", "#f00").unwrap(), id);
+        assert_eq!(create_tag(&conn, "  BEACH ", "#f00").unwrap(), id);
 
-/// Get paths of photos that have not yet been enriched (camera_make IS NULL).
-pub fn get_photos_without_enrichment(conn: &Connection) -> SqlResult<Vec<String>> {
-    let mut stmt = conn.prepare(
-        "SELECT path FROM photos WHERE camera_make IS NULL AND archived_at IS NULL"
-    )?;
-    let rows = stmt.query_map([], |row| row.get(0))?;
-    rows.collect()
-}
+        let tags = get_all_tags(&conn).unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].name, "Beach");
+        assert_eq!(tags[0].color, "#00f");
+    }
 
-/// Get (path, content_hash) for every photo whose thumbnail is missing.
-/// Skips archived photos and photos without a content hash (rare; can't be addressed).
-pub fn get_photos_without_thumbnails(conn: &Connection) -> SqlResult<Vec<(String, String)>> {
-    let mut stmt = conn.prepare(
-        "SELECT path, content_hash FROM photos \
-         WHERE thumb_status IS NULL AND content_hash IS NOT NULL AND archived_at IS NULL"
-    )?;
-    let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?;
-    rows.collect()
-}
+    #[test]
+    fn test_rename_tag_rejects_taken_names() {
+        let conn = setup_db();
+        let beach = create_tag(&conn, "beach", "#00f").unwrap();
+        create_tag(&conn, "Sunset", "#f80").unwrap();
 
-/// Set the thumb_status for one photo. Caller passes 'ready', 'failed', or 'unsupported'.
-pub fn set_thumb_status(conn: &Connection, path: &str, status: &str) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET thumb_status = ?1 WHERE path = ?2",
-        params![status, path],
-    )?;
-    Ok(())
-}
+        assert!(!rename_tag(&conn, beach, "sunset").unwrap());
+        assert!(rename_tag(&conn, beach, "Beach").unwrap());
+        assert!(rename_tag(&conn, beach, "Coast").unwrap());
 
-/// Update file size for a photo
-pub fn update_photo_file_size(conn: &Connection, path: &str, size: i64) -> SqlResult<()> {
-    conn.execute(
-        "UPDATE photos SET file_size = ?1 WHERE path = ?2",
-        params![size, path],
-    )?;
-    Ok(())
-}
+        let names: Vec<String> = get_all_tags(&conn).unwrap().into_iter().map(|t| t.name).collect();
+        assert!(names.contains(&"Coast".to_string()));
+        assert!(names.contains(&"Sunset".to_string()));
+    }
 
-/// Get photos without file_size populated
-pub fn get_photos_without_file_size(conn: &Connection) -> SqlResult<Vec<String>> {
-    let mut stmt = conn.prepare(
-        "SELECT path FROM photos WHERE file_size IS NULL AND archived_at IS NULL"
-    )?;
-    let rows = stmt.query_map([], |row| row.get(0))?;
-    rows.collect()
-}
+    #[test]
+    fn test_case_duplicate_tags_merged_on_init() {
+        let conn = Connection::open_in_memory().unwrap();
+        // Pre-migration schema allowed names differing only in case
+        conn.execute_batch(
+            "CREATE TABLE photos (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE,
+                                  name TEXT NOT NULL, date_taken INTEGER NOT NULL, width INTEGER NOT NULL,
+                                  height INTEGER NOT NULL, source_type TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO photos (path, name, date_taken, width, height, source_type, created_at)
+                 VALUES ('/a.jpg', 'a.jpg', 0, 0, 0, 'scan', 0), ('/b.jpg', 'b.jpg', 0, 0, 0, 'scan', 0);
+             CREATE TABLE tags (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE,
+                                color TEXT NOT NULL, created_at INTEGER NOT NULL);
+             CREATE TABLE photo_tags (tag_id INTEGER NOT NULL, photo_path TEXT NOT NULL,
+                                      added_at INTEGER NOT NULL, PRIMARY KEY (tag_id, photo_path));
+             INSERT INTO tags VALUES (1, 'Beach', '#00f', 0), (2, 'beach', '#f00', 0);
+             INSERT INTO photo_tags VALUES (1, '/a.jpg', 0), (2, '/a.jpg', 0), (2, '/b.jpg', 0);",
+        )
+        .unwrap();
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+        init_schema(&conn).unwrap();
 
-    /// Helper to create a PhotoMetadata for testing
-    fn test_photo(path: &str, name: &str) -> PhotoMetadata {
-        PhotoMetadata {
-            path: path.to_string(),
-            name: name.to_string(),
-            date_taken: 1700000000,
-            width: 1920,
-            height: 1080,
-            is_favorite: false,
-            content_hash: Some("abc123".to_string()),
-            latitude: None,
-            longitude: None,
-            location_name: None,
-            camera_make: None,
-            camera_model: None,
-            lens_model: None,
-            iso: None,
-            aperture: None,
-            shutter_us: None,
-            focal_length_mm: None,
-            orientation: None,
-            duration_ms: None,
-            codec: None,
-            thumb_status: None,
-        }
+        let tags = get_all_tags(&conn).unwrap();
+        assert_eq!(tags.len(), 1);
+        assert_eq!(tags[0].id, 1);
+        let links: i64 = conn
+            .query_row("SELECT COUNT(*) FROM photo_tags WHERE tag_id = 1", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(links, 2);
     }
 
-    /// Helper to create an in-memory database with schema initialized
-    fn setup_db() -> Connection {
-        let conn = Connection::open_in_memory().expect("Failed to open in-memory database");
-        init_schema(&conn).expect("Failed to initialize schema");
-        conn
+    #[test]
+    fn test_path_keyed_memberships_migrate_to_photo_ids() {
+        let conn = Connection::open_in_memory().unwrap();
+        configure_connection(&conn).unwrap();
+        // Schema from before memberships referenced photos.id, search triggers included
+        conn.execute_batch(
+            "CREATE TABLE photos (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE,
+                                  name TEXT NOT NULL, date_taken INTEGER NOT NULL, width INTEGER NOT NULL,
+                                  height INTEGER NOT NULL, source_type TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO photos (path, name, date_taken, width, height, source_type, created_at)
+                 VALUES ('/lib/a.jpg', 'a.jpg', 2, 0, 0, 'scan', 0), ('/lib/b.jpg', 'b.jpg', 1, 0, 0, 'scan', 0);
+             CREATE TABLE albums (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL,
+                                  cover_photo_path TEXT, created_at INTEGER NOT NULL);
+             INSERT INTO albums (name, created_at) VALUES ('Trip', 0);
+             CREATE TABLE album_photos (album_id INTEGER NOT NULL, photo_path TEXT NOT NULL,
+                                        added_at INTEGER NOT NULL, PRIMARY KEY (album_id, photo_path));
+             INSERT INTO album_photos VALUES (1, '/lib/a.jpg', 0), (1, '/lib/b.jpg', 0), (1, '/lib/gone.jpg', 0),
+                                             (2, '/lib/a.jpg', 0);
+             CREATE TABLE tags (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL UNIQUE,
+                                color TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO tags VALUES (1, 'beach', '#00f', 0);
+             CREATE TABLE photo_tags (tag_id INTEGER NOT NULL, photo_path TEXT NOT NULL,
+                                      added_at INTEGER NOT NULL, PRIMARY KEY (tag_id, photo_path));
+             INSERT INTO photo_tags VALUES (1, '/lib/b.jpg', 0);
+             CREATE VIRTUAL TABLE photos_fts USING fts5(name, description, location_name, tags);
+             CREATE TRIGGER photos_fts_insert AFTER INSERT ON photos BEGIN
+                 INSERT INTO photos_fts (rowid, name, tags)
+                 SELECT p.id, p.name, (SELECT group_concat(t.name, ' ') FROM photo_tags pt
+                     JOIN tags t ON t.id = pt.tag_id WHERE pt.photo_path = p.path)
+                 FROM photos p WHERE p.id = new.id;
+             END;
+             CREATE TRIGGER tags_fts_rename AFTER UPDATE OF name ON tags BEGIN
+                 DELETE FROM photos_fts WHERE rowid IN
+                     (SELECT p.id FROM photos p JOIN photo_tags pt ON pt.photo_path = p.path WHERE pt.tag_id = new.id);
+             END;",
+        )
+        .unwrap();
+
+        init_schema(&conn).unwrap();
+        // Running it again leaves the migrated tables alone
+        init_schema(&conn).unwrap();
+
+        let paths: Vec<String> = get_album_photos(&conn, 1).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(paths, vec!["/lib/a.jpg", "/lib/b.jpg"]);
+        assert_eq!(get_tags_for_photo(&conn, "/lib/b.jpg").unwrap()[0].name, "beach");
+
+        // Memberships now follow the photo when its file moves
+        update_photo_path(&conn, "/lib/b.jpg", "/lib/2021/b.jpg").unwrap();
+        assert_eq!(get_album_photos(&conn, 1).unwrap()[1].path, "/lib/2021/b.jpg");
+        assert_eq!(get_tags_for_photo(&conn, "/lib/2021/b.jpg").unwrap().len(), 1);
+
+        // The search triggers were rebuilt against the new columns
+        rename_tag(&conn, 1, "coast").unwrap();
+        let found: Vec<String> = search_photos(&conn, "coast").unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(found, vec!["/lib/2021/b.jpg"]);
+        insert_photo(&conn, &test_photo("/lib/harbour.jpg", "harbour.jpg"), "scan").unwrap();
+        assert_eq!(search_photos(&conn, "harbour").unwrap().len(), 1);
     }
 
-    // ====================================================================
-    // Photos tests
-    // ====================================================================
+    #[test]
+    fn test_photos_carry_their_row_id() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+        let id: i64 = conn.query_row("SELECT id FROM photos WHERE path = '/lib/a.jpg'", [], |row| row.get(0)).unwrap();
+        assert_eq!(get_all_photos(&conn).unwrap()[0].id, Some(id));
+    }
 
     #[test]
-    fn test_insert_and_get_all_photos() {
+    fn test_fresh_database_is_at_the_latest_version() {
         let conn = setup_db();
-        let photo = test_photo("/photos/test.jpg", "test.jpg");
-        insert_photo(&conn, &photo, "upload").unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len() as u32);
+        // Already up to date: nothing left to apply
+        init_schema(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len() as u32);
+    }
+
+    #[test]
+    fn test_unread_heic_rows_are_retried_once() {
+        let conn = Connection::open_in_memory().unwrap();
+        configure_connection(&conn).unwrap();
+        let before = MIGRATIONS.iter().position(|m| m.name == "retry unread heic").unwrap();
+        apply_migrations(&conn, &MIGRATIONS[..before]).unwrap();
+        insert_photo(&conn, &test_photo("/lib/a.HEIC", "a.HEIC"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/b.jpg", "b.jpg"), "scan").unwrap();
+        conn.execute("UPDATE photos SET width = 0, height = 0, file_mtime = 5, thumb_status = 'failed'", []).unwrap();
+
+        init_schema(&conn).unwrap();
+        let state = |path: &str| -> (Option<i64>, Option<String>) {
+            conn.query_row("SELECT file_mtime, thumb_status FROM photos WHERE path = ?1", [path], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap()
+        };
+        assert_eq!(state("/lib/a.HEIC"), (None, None));
+        assert_eq!(state("/lib/b.jpg"), (Some(5), Some("failed".to_string())));
+
+        // Later launches leave a HEIC that still can't be read alone
+        conn.execute("UPDATE photos SET file_mtime = 5, thumb_status = 'failed'", []).unwrap();
+        init_schema(&conn).unwrap();
+        assert_eq!(state("/lib/a.HEIC"), (Some(5), Some("failed".to_string())));
+    }
+
+    #[test]
+    fn test_database_from_before_favorites_upgrades() {
+        let conn = Connection::open_in_memory().unwrap();
+        configure_connection(&conn).unwrap();
+        // The first release's schema: no favorites, hashes, places, albums or tags
+        conn.execute_batch(
+            "CREATE TABLE photos (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE,
+                                  name TEXT NOT NULL, date_taken INTEGER NOT NULL, width INTEGER NOT NULL,
+                                  height INTEGER NOT NULL, source_type TEXT NOT NULL, created_at INTEGER NOT NULL);
+             INSERT INTO photos (path, name, date_taken, width, height, source_type, created_at)
+                 VALUES ('/lib/lighthouse.jpg', 'lighthouse.jpg', 1600000000, 4000, 3000, 'scan', 1700000000);",
+        )
+        .unwrap();
+
+        init_schema(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len() as u32);
 
         let photos = get_all_photos(&conn).unwrap();
         assert_eq!(photos.len(), 1);
-        assert_eq!(photos[0].path, "/photos/test.jpg");
-        assert_eq!(photos[0].name, "test.jpg");
-        assert_eq!(photos[0].date_taken, 1700000000);
+        assert_eq!(photos[0].date_taken, 1600000000);
+        assert_eq!((photos[0].width, photos[0].height), (4000, 3000));
+        assert!(!photos[0].is_favorite);
+        assert_eq!(photos[0].file_format.as_deref(), Some("jpeg"));
+
+        // Tables from later releases work, and old rows are searchable
+        let album = create_album(&conn, "Coast").unwrap();
+        add_photo_to_album(&conn, album, "/lib/lighthouse.jpg").unwrap();
+        assert_eq!(get_album_photos(&conn, album).unwrap().len(), 1);
+        assert_eq!(search_photos(&conn, "lighthouse").unwrap().len(), 1);
     }
 
     #[test]
-    fn test_photo_exists_true_for_inserted() {
-        let conn = setup_db();
-        let photo = test_photo("/photos/exists.jpg", "exists.jpg");
-        insert_photo(&conn, &photo, "upload").unwrap();
+    fn test_database_from_after_favorites_upgrades() {
+        let conn = Connection::open_in_memory().unwrap();
+        configure_connection(&conn).unwrap();
+        // Favorites, hashes, places and path-keyed albums, before everything since
+        conn.execute_batch(
+            "CREATE TABLE photos (id INTEGER PRIMARY KEY AUTOINCREMENT, path TEXT NOT NULL UNIQUE,
+                                  name TEXT NOT NULL, date_taken INTEGER NOT NULL, width INTEGER NOT NULL,
+                                  height INTEGER NOT NULL, source_type TEXT NOT NULL, created_at INTEGER NOT NULL,
+                                  is_favorite INTEGER DEFAULT 0, content_hash TEXT,
+                                  latitude REAL, longitude REAL, location_name TEXT);
+             INSERT INTO photos (path, name, date_taken, width, height, source_type, created_at,
+                                 is_favorite, content_hash, latitude, longitude, location_name)
+                 VALUES ('/lib/a.jpg', 'a.jpg', 2, 0, 0, 'scan', 0, 1, 'abc', 43.7, -79.4, 'Toronto'),
+                        ('/lib/b.jpg', 'b.jpg', 1, 0, 0, 'scan', 0, 0, NULL, NULL, NULL, NULL);
+             CREATE TABLE albums (id INTEGER PRIMARY KEY AUTOINCREMENT, name TEXT NOT NULL,
+                                  cover_photo_path TEXT, created_at INTEGER NOT NULL);
+             INSERT INTO albums (name, cover_photo_path, created_at) VALUES ('Trip', '/lib/a.jpg', 0);
+             CREATE TABLE album_photos (album_id INTEGER NOT NULL, photo_path TEXT NOT NULL,
+                                        added_at INTEGER NOT NULL, PRIMARY KEY (album_id, photo_path));
+             INSERT INTO album_photos VALUES (1, '/lib/a.jpg', 0), (1, '/lib/b.jpg', 0);",
+        )
+        .unwrap();
 
-        assert!(photo_exists(&conn, "/photos/exists.jpg").unwrap());
+        init_schema(&conn).unwrap();
+        assert_eq!(schema_version(&conn).unwrap(), MIGRATIONS.len() as u32);
+
+        let photos = get_all_photos(&conn).unwrap();
+        let a = photos.iter().find(|p| p.path == "/lib/a.jpg").unwrap();
+        assert!(a.is_favorite);
+        assert_eq!(a.content_hash.as_deref(), Some("abc"));
+        assert_eq!(a.location_name.as_deref(), Some("Toronto"));
+
+        let paths: Vec<String> = get_album_photos(&conn, 1).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(paths, vec!["/lib/a.jpg", "/lib/b.jpg"]);
+        assert_eq!(get_albums(&conn).unwrap()[0].cover_photo_path.as_deref(), Some("/lib/a.jpg"));
+        assert_eq!(search_photos(&conn, "toronto").unwrap().len(), 1);
     }
 
     #[test]
-    fn test_photo_exists_false_for_missing() {
-        let conn = setup_db();
-        assert!(!photo_exists(&conn, "/photos/missing.jpg").unwrap());
+    fn test_failed_migration_is_rolled_back_and_named() {
+        let conn = Connection::open_in_memory().unwrap();
+        let migrations = [
+            Migration {
+                name: "first",
+                apply: |conn| conn.execute("CREATE TABLE first (id INTEGER)", []).map(drop),
+            },
+            Migration {
+                name: "broken",
+                apply: |conn| {
+                    conn.execute("CREATE TABLE second (id INTEGER)", [])?;
+                    conn.execute("INSERT INTO missing VALUES (1)", []).map(drop)
+                },
+            },
+        ];
+
+        let err = apply_migrations(&conn, &migrations).unwrap_err();
+        assert!(matches!(err, SchemaError::Migration { version: 2, name: "broken", .. }));
+        assert!(err.to_string().contains("version 2 (broken)"));
+        assert!(err.to_string().contains("left at version 1"));
+
+        // The first step stuck; none of the broken one did
+        assert_eq!(schema_version(&conn).unwrap(), 1);
+        let tables: Vec<String> = conn
+            .prepare("SELECT name FROM sqlite_master WHERE type = 'table' ORDER BY name")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        assert_eq!(tables, vec!["first"]);
     }
 
     #[test]
-    fn test_delete_photo() {
+    fn test_newer_database_is_refused() {
         let conn = setup_db();
-        let photo = test_photo("/photos/delete_me.jpg", "delete_me.jpg");
-        insert_photo(&conn, &photo, "upload").unwrap();
-        assert!(photo_exists(&conn, "/photos/delete_me.jpg").unwrap());
-
-        delete_photo(&conn, "/photos/delete_me.jpg").unwrap();
-        assert!(!photo_exists(&conn, "/photos/delete_me.jpg").unwrap());
+        conn.pragma_update(None, "user_version", MIGRATIONS.len() as u32 + 1).unwrap();
+        assert!(matches!(init_schema(&conn), Err(SchemaError::TooNew { .. })));
     }
 
     #[test]
-    fn test_search_photos_by_name() {
+    fn test_remove_tags_from_photos_and_attach_tags() {
         let conn = setup_db();
-        let photo = test_photo("/photos/sunset_beach.jpg", "sunset_beach.jpg");
-        insert_photo(&conn, &photo, "upload").unwrap();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        let beach = create_tag(&conn, "beach", "#00f").unwrap();
+        let dog = create_tag(&conn, "dog", "#0f0").unwrap();
+        let paths = vec!["/p/a.jpg".to_string(), "/p/b.jpg".to_string()];
+        add_tags_to_photos(&conn, &[beach, dog], &paths).unwrap();
+
+        remove_tags_from_photos(&conn, &[dog], &paths[1..]).unwrap();
+
+        let mut photos = get_all_photos(&conn).unwrap();
+        attach_tags(&conn, &mut photos).unwrap();
+        let tags_of = |path: &str| -> Vec<String> {
+            photos.iter().find(|p| p.path == path).unwrap().tags.iter().map(|t| t.name.clone()).collect()
+        };
+        assert_eq!(tags_of("/p/a.jpg"), vec!["beach", "dog"]);
+        assert_eq!(tags_of("/p/b.jpg"), vec!["beach"]);
+    }
 
-        let results = search_photos(&conn, "sunset").unwrap();
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].name, "sunset_beach.jpg");
+    #[test]
+    fn test_configure_connection_enables_wal_and_busy_timeout() {
+        let dir = std::env::temp_dir().join(format!("terra-wal-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let conn = Connection::open(dir.join("wal.db")).unwrap();
+        configure_connection(&conn).unwrap();
+
+        let mode: String = conn.query_row("PRAGMA journal_mode", [], |row| row.get(0)).unwrap();
+        assert_eq!(mode.to_lowercase(), "wal");
+        let timeout: i64 = conn.query_row("PRAGMA busy_timeout", [], |row| row.get(0)).unwrap();
+        assert_eq!(timeout, BUSY_TIMEOUT.as_millis() as i64);
+
+        drop(conn);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     #[test]
-    fn test_search_photos_by_location() {
-        let conn = setup_db();
-        let mut photo = test_photo("/photos/trip.jpg", "trip.jpg");
-        photo.location_name = Some("San Francisco, California".to_string());
-        insert_photo(&conn, &photo, "upload").unwrap();
+    fn test_concurrent_writers_and_readers_never_see_locked_errors() {
+        let dir = std::env::temp_dir().join(format!("terra-busy-test-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("busy.db");
+        let open = || {
+            let conn = Connection::open(&db_path).unwrap();
+            configure_connection(&conn).unwrap();
+            conn
+        };
+        init_schema(&open()).unwrap();
+        insert_photo(&open(), &test_photo("/busy/star.jpg", "star.jpg"), "scan").unwrap();
+
+        std::thread::scope(|scope| {
+            let mut handles = Vec::new();
+            for writer in 0..4 {
+                let conn = open();
+                handles.push(scope.spawn(move || -> SqlResult<()> {
+                    for batch in 0..10 {
+                        let photos: Vec<PhotoMetadata> = (0..WRITE_CHUNK_ROWS / 10)
+                            .map(|i| {
+                                let name = format!("{}-{}-{}.jpg", writer, batch, i);
+                                test_photo(&format!("/busy/{}", name), &name)
+                            })
+                            .collect();
+                        assert!(insert_photos_batch(&conn, &photos, "scan")?.is_empty());
+                        retry_busy(|| set_photo_favorite(&conn, "/busy/star.jpg", batch % 2 == 0))?;
+                    }
+                    Ok(())
+                }));
+            }
+            for _ in 0..2 {
+                let conn = open();
+                handles.push(scope.spawn(move || -> SqlResult<()> {
+                    for _ in 0..20 {
+                        get_all_photos(&conn)?;
+                    }
+                    Ok(())
+                }));
+            }
+            for handle in handles {
+                if let Err(e) = handle.join().unwrap() {
+                    panic!("concurrent access failed: {}", e);
+                }
+            }
+        });
 
-        let results = search_photos(&conn, "San Francisco").unwrap();
-        assert_eq!(results.len(), 1);
-        assert_eq!(results[0].location_name.as_deref(), Some("San Francisco, California"));
+        let count: i64 = open().query_row("SELECT COUNT(*) FROM photos", [], |row| row.get(0)).unwrap();
+        assert_eq!(count, 1 + 4 * 10 * (WRITE_CHUNK_ROWS / 10) as i64);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
+    /// Benchmark: per-row autocommit vs one batch transaction on an on-disk DB.
+    /// Run with `cargo test --release bench_insert_photos -- --ignored --nocapture`.
     #[test]
-    fn test_get_photo_count() {
-        let conn = setup_db();
-        assert_eq!(get_photo_count(&conn).unwrap(), 0);
+    #[ignore]
+    fn bench_insert_photos_batch_vs_per_row() {
+        let dir = std::env::temp_dir().join(format!("terra-batch-bench-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let photos: Vec<PhotoMetadata> = (0..2_000)
+            .map(|i| test_photo(&format!("/bench/{}.jpg", i), &format!("{}.jpg", i)))
+            .collect();
+
+        let per_row_conn = Connection::open(dir.join("per_row.db")).unwrap();
+        init_schema(&per_row_conn).unwrap();
+        let start = std::time::Instant::now();
+        for photo in &photos {
+            insert_photo(&per_row_conn, photo, "scan").unwrap();
+        }
+        let per_row = start.elapsed();
 
-        insert_photo(&conn, &test_photo("/photos/a.jpg", "a.jpg"), "upload").unwrap();
-        insert_photo(&conn, &test_photo("/photos/b.jpg", "b.jpg"), "upload").unwrap();
-        assert_eq!(get_photo_count(&conn).unwrap(), 2);
+        let batch_conn = Connection::open(dir.join("batch.db")).unwrap();
+        init_schema(&batch_conn).unwrap();
+        let start = std::time::Instant::now();
+        insert_photos_batch(&batch_conn, &photos, "scan").unwrap();
+        let batch = start.elapsed();
+
+        println!("per-row: {:?}, batch: {:?} ({} rows)", per_row, batch, photos.len());
+        assert!(batch < per_row);
+        let _ = std::fs::remove_dir_all(&dir);
     }
 
     // ====================================================================
@@ -1388,10 +9185,76 @@ mod tests {
         let photo = test_photo("/photos/fav2.jpg", "fav2.jpg");
         insert_photo(&conn, &photo, "upload").unwrap();
 
-        set_photo_favorite(&conn, "/photos/fav2.jpg", true).unwrap();
-        set_photo_favorite(&conn, "/photos/fav2.jpg", false).unwrap();
-        let photos = get_all_photos(&conn).unwrap();
-        assert!(!photos[0].is_favorite);
+        set_photo_favorite(&conn, "/photos/fav2.jpg", true).unwrap();
+        set_photo_favorite(&conn, "/photos/fav2.jpg", false).unwrap();
+        let photos = get_all_photos(&conn).unwrap();
+        assert!(!photos[0].is_favorite);
+    }
+
+    #[test]
+    fn test_set_photos_favorite_counts_existing_rows_across_chunks() {
+        let conn = setup_db();
+        let mut paths = Vec::new();
+        for i in 0..(MAX_IN_PARAMS + 50) {
+            let path = format!("/photos/{}.jpg", i);
+            insert_photo(&conn, &test_photo(&path, &format!("{}.jpg", i)), "scan").unwrap();
+            paths.push(path);
+        }
+        paths.push("/photos/gone.jpg".to_string());
+
+        assert_eq!(set_photos_favorite(&conn, &paths, true).unwrap(), MAX_IN_PARAMS + 50);
+        assert!(get_all_photos(&conn).unwrap().iter().all(|p| p.is_favorite));
+
+        assert_eq!(set_photos_favorite(&conn, &paths[..2], false).unwrap(), 2);
+        let favorites = get_all_photos(&conn).unwrap().iter().filter(|p| p.is_favorite).count();
+        assert_eq!(favorites, MAX_IN_PARAMS + 48);
+    }
+
+    #[test]
+    fn test_path_and_id_variants_change_the_same_rows() {
+        // (favorites, album members, photos left) after the same edits keyed both ways
+        let run = |by_id: bool| -> (Vec<String>, Vec<String>, Vec<String>) {
+            let conn = setup_db();
+            for name in ["a.jpg", "b.jpg", "c.jpg"] {
+                insert_photo(&conn, &test_photo(&format!("/photos/{}", name), name), "scan").unwrap();
+            }
+            let album = create_album(&conn, "Trip").unwrap();
+            let paths: Vec<String> = ["a.jpg", "c.jpg", "gone.jpg"].iter().map(|n| format!("/photos/{}", n)).collect();
+            let doomed: Vec<String> = ["b.jpg", "gone.jpg"].iter().map(|n| format!("/photos/{}", n)).collect();
+            let doomed_ids = if by_id {
+                let ids = get_photo_ids(&conn, &paths).unwrap();
+                assert_eq!(ids.len(), 2);
+                assert_eq!(set_photos_favorite_by_id(&conn, &[ids[0], ids[1], 999], true).unwrap(), 2);
+                assert_eq!(add_photos_to_album_by_id(&conn, album, &[ids[0], 999]).unwrap(), 1);
+                assert_eq!(add_photos_to_album_by_id(&conn, album, &ids).unwrap(), 1);
+                let b = get_photo_ids(&conn, &doomed[..1]).unwrap();
+                vec![b[0], 999]
+            } else {
+                assert_eq!(set_photos_favorite(&conn, &paths, true).unwrap(), 2);
+                for path in &paths {
+                    add_photo_to_album(&conn, album, path).unwrap();
+                }
+                // delete_photos resolves paths to ids, silently skipping
+                // paths with no row as delete_photos_by_id skips unknown ids
+                get_photo_ids(&conn, &doomed).unwrap()
+            };
+            assert_eq!(doomed_ids.len(), if by_id { 2 } else { 1 });
+            // What remove_photos then does with the rows it finds
+            for (path, _) in get_photo_paths_by_id(&conn, &doomed_ids).unwrap().into_iter().flatten() {
+                delete_photo(&conn, &path).unwrap();
+            }
+            let mut favorites: Vec<String> =
+                get_all_photos(&conn).unwrap().into_iter().filter(|p| p.is_favorite).map(|p| p.name).collect();
+            let mut members: Vec<String> = get_album_photos(&conn, album).unwrap().into_iter().map(|p| p.name).collect();
+            let mut left: Vec<String> = get_all_photos(&conn).unwrap().into_iter().map(|p| p.name).collect();
+            favorites.sort();
+            members.sort();
+            left.sort();
+            (favorites, members, left)
+        };
+        assert_eq!(run(false), run(true));
+        assert_eq!(run(true).0, vec!["a.jpg", "c.jpg"]);
+        assert_eq!(run(true).2, vec!["a.jpg", "c.jpg"]);
     }
 
     // ====================================================================
@@ -1410,6 +9273,43 @@ mod tests {
         assert_eq!(albums[0].count, 0);
     }
 
+    #[test]
+    fn test_rename_album() {
+        let conn = setup_db();
+        let first = create_album(&conn, "Trip").unwrap();
+        let second = create_album(&conn, "Beach").unwrap();
+
+        assert!(rename_album(&conn, second, "Trip").unwrap());
+        assert!(!rename_album(&conn, second + 1, "Nope").unwrap());
+        assert_eq!(get_album_name(&conn, first).unwrap().as_deref(), Some("Trip"));
+        assert_eq!(get_album_name(&conn, second).unwrap().as_deref(), Some("Trip"));
+    }
+
+    #[test]
+    fn test_album_order() {
+        let conn = setup_db();
+        conn.execute("INSERT INTO albums (name, created_at) VALUES ('a', 1), ('b', 2), ('c', 3), ('d', 4)", []).unwrap();
+        let names = |conn: &Connection| get_albums(conn).unwrap().into_iter().map(|a| a.name).collect::<Vec<_>>();
+        assert_eq!(names(&conn), vec!["d", "c", "b", "a"]);
+
+        let id = |name: &str| find_album_by_name(&conn, name).unwrap().unwrap();
+        set_album_order(&conn, &[id("b"), id("a")]).unwrap();
+        assert_eq!(names(&conn), vec!["b", "a", "d", "c"]);
+
+        set_album_order(&conn, &[id("c"), id("d"), id("a"), id("b")]).unwrap();
+        assert_eq!(names(&conn), vec!["c", "d", "a", "b"]);
+    }
+
+    #[test]
+    fn test_find_album_by_name() {
+        let conn = setup_db();
+        let first = create_album(&conn, "Lake trip").unwrap();
+        create_album(&conn, "Lake trip").unwrap();
+
+        assert_eq!(find_album_by_name(&conn, "Lake trip").unwrap(), Some(first));
+        assert_eq!(find_album_by_name(&conn, "lake trip").unwrap(), None);
+    }
+
     #[test]
     fn test_add_photo_to_album_and_get_album_photos() {
         let conn = setup_db();
@@ -1424,6 +9324,23 @@ mod tests {
         assert_eq!(photos[0].path, "/photos/album_pic.jpg");
     }
 
+    #[test]
+    fn test_first_album_names_prefer_the_oldest_album() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        let trip = create_album(&conn, "Trip").unwrap();
+        let best = create_album(&conn, "Best of").unwrap();
+        add_photo_to_album(&conn, best, "/p/a.jpg").unwrap();
+        add_photo_to_album(&conn, trip, "/p/a.jpg").unwrap();
+
+        let names = get_first_album_names(&conn, &["/p/a.jpg".to_string(), "/p/b.jpg".to_string()]).unwrap();
+        assert_eq!(names.get("/p/a.jpg").map(String::as_str), Some("Trip"));
+        assert!(!names.contains_key("/p/b.jpg"));
+        assert_eq!(get_album_name(&conn, best).unwrap().as_deref(), Some("Best of"));
+        assert_eq!(get_album_name(&conn, 9999).unwrap(), None);
+    }
+
     #[test]
     fn test_remove_photo_from_album() {
         let conn = setup_db();
@@ -1438,6 +9355,77 @@ mod tests {
         assert_eq!(get_album_photos(&conn, album_id).unwrap().len(), 0);
     }
 
+    #[test]
+    fn test_foreign_keys_are_enforced() {
+        let conn = setup_db();
+        let on: bool = conn.query_row("PRAGMA foreign_keys", [], |row| row.get(0)).unwrap();
+        assert!(on);
+    }
+
+    #[test]
+    fn test_delete_photo_leaves_no_orphans() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/a.jpg").unwrap();
+        set_album_cover(&conn, album_id, "/lib/a.jpg").unwrap();
+        let tag_id = create_tag(&conn, "beach", "#00f").unwrap();
+        add_tags_to_photos(&conn, &[tag_id], &["/lib/a.jpg".to_string()]).unwrap();
+
+        delete_photo(&conn, "/lib/a.jpg").unwrap();
+
+        let album = &get_albums(&conn).unwrap()[0];
+        assert_eq!((album.count, album.cover_photo_path.as_deref()), (0, None));
+        let memberships: i64 = conn
+            .query_row("SELECT (SELECT COUNT(*) FROM album_photos) + (SELECT COUNT(*) FROM photo_tags)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(memberships, 0);
+    }
+
+    #[test]
+    fn test_delete_album_leaves_no_orphans() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/a.jpg").unwrap();
+
+        delete_album(&conn, album_id, false).unwrap();
+
+        let memberships: i64 = conn.query_row("SELECT COUNT(*) FROM album_photos", [], |row| row.get(0)).unwrap();
+        assert_eq!(memberships, 0);
+        // A new album never inherits the old one's photos
+        let next = create_album(&conn, "Next").unwrap();
+        assert!(get_album_photos(&conn, next).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_orphaned_references_are_cleaned_up() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+        let album_id = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album_id, "/lib/a.jpg").unwrap();
+        // Ghost rows as deletes used to leave them, before foreign keys were on
+        conn.pragma_update(None, "foreign_keys", false).unwrap();
+        conn.execute_batch(
+            "INSERT INTO album_photos (album_id, photo_id, added_at) VALUES (999, 1, 0), (1, 999, 0);
+             INSERT INTO photo_tags (tag_id, photo_id, added_at) VALUES (999, 1, 0);
+             UPDATE albums SET cover_photo_path = '/lib/gone.jpg', parent_id = 999;",
+        )
+        .unwrap();
+        conn.pragma_update(None, "foreign_keys", true).unwrap();
+
+        remove_orphaned_references(&conn).unwrap();
+
+        let album = &get_albums(&conn).unwrap()[0];
+        assert_eq!((album.count, album.cover_photo_path.as_deref(), album.parent_id), (1, None, None));
+        let rows: i64 = conn
+            .query_row("SELECT (SELECT COUNT(*) FROM album_photos) + (SELECT COUNT(*) FROM photo_tags)", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(rows, 1);
+        let mut check = conn.prepare("PRAGMA foreign_key_check").unwrap();
+        assert!(check.query([]).unwrap().next().unwrap().is_none());
+    }
+
     #[test]
     fn test_delete_album_cascade() {
         let conn = setup_db();
@@ -1447,11 +9435,70 @@ mod tests {
         let album_id = create_album(&conn, "ToDelete").unwrap();
         add_photo_to_album(&conn, album_id, "/photos/cascade.jpg").unwrap();
 
-        delete_album(&conn, album_id).unwrap();
+        delete_album(&conn, album_id, false).unwrap();
         let albums = get_albums(&conn).unwrap();
         assert_eq!(albums.len(), 0);
     }
 
+    /// Trips > 2023 > Japan, with a.jpg in Japan and in Trips itself
+    fn setup_album_tree(conn: &Connection) -> (i64, i64, i64) {
+        insert_photo(conn, &test_photo("/lib/a.jpg", "a.jpg"), "upload").unwrap();
+        insert_photo(conn, &test_photo("/lib/b.jpg", "b.jpg"), "upload").unwrap();
+        let trips = create_album(conn, "Trips").unwrap();
+        let year = create_album(conn, "2023").unwrap();
+        let japan = create_album(conn, "Japan").unwrap();
+        set_album_parent(conn, year, Some(trips)).unwrap();
+        set_album_parent(conn, japan, Some(year)).unwrap();
+        add_photo_to_album(conn, trips, "/lib/a.jpg").unwrap();
+        add_photo_to_album(conn, japan, "/lib/a.jpg").unwrap();
+        add_photo_to_album(conn, japan, "/lib/b.jpg").unwrap();
+        (trips, year, japan)
+    }
+
+    #[test]
+    fn test_nested_album_counts_roll_up() {
+        let conn = setup_db();
+        let (trips, year, japan) = setup_album_tree(&conn);
+
+        let albums = get_albums(&conn).unwrap();
+        let album = |id: i64| albums.iter().find(|a| a.id == id).unwrap();
+        assert_eq!((album(trips).count, album(trips).total_count, album(trips).parent_id), (1, 2, None));
+        assert_eq!((album(year).count, album(year).total_count, album(year).parent_id), (0, 2, Some(trips)));
+        assert_eq!((album(japan).count, album(japan).total_count, album(japan).parent_id), (2, 2, Some(year)));
+    }
+
+    #[test]
+    fn test_album_moves_cannot_create_cycles() {
+        let conn = setup_db();
+        let (trips, year, japan) = setup_album_tree(&conn);
+        let other = create_album(&conn, "Other").unwrap();
+
+        assert!(album_move_creates_cycle(&conn, trips, trips).unwrap());
+        assert!(album_move_creates_cycle(&conn, trips, japan).unwrap());
+        assert!(album_move_creates_cycle(&conn, year, japan).unwrap());
+        assert!(!album_move_creates_cycle(&conn, japan, trips).unwrap());
+        assert!(!album_move_creates_cycle(&conn, trips, other).unwrap());
+
+        assert!(set_album_parent(&conn, japan, None).unwrap());
+        assert!(!set_album_parent(&conn, other + 1, None).unwrap());
+    }
+
+    #[test]
+    fn test_deleting_a_folder_orphans_or_cascades() {
+        let conn = setup_db();
+        let (trips, year, japan) = setup_album_tree(&conn);
+        delete_album(&conn, trips, false).unwrap();
+        let albums = get_albums(&conn).unwrap();
+        assert_eq!(albums.len(), 2);
+        assert_eq!(albums.iter().find(|a| a.id == year).unwrap().parent_id, None);
+        assert_eq!(albums.iter().find(|a| a.id == japan).unwrap().parent_id, Some(year));
+
+        delete_album(&conn, year, true).unwrap();
+        assert!(get_albums(&conn).unwrap().is_empty());
+        let memberships: i64 = conn.query_row("SELECT COUNT(*) FROM album_photos", [], |row| row.get(0)).unwrap();
+        assert_eq!(memberships, 0);
+    }
+
     #[test]
     fn test_set_album_cover() {
         let conn = setup_db();
@@ -1465,6 +9512,157 @@ mod tests {
         assert_eq!(albums[0].cover_photo_path.as_deref(), Some("/photos/cover.jpg"));
     }
 
+    /// a: 2021 favorite JPEG from a Canon with GPS, rated 4, tagged beach
+    /// b: 2021 video
+    /// c: 2023 PNG screenshot
+    /// d: 2019 iPhone JPEG, rated 2, tagged beach and family
+    /// e: undated JPEG
+    fn setup_smart_album_photos() -> (Connection, i64, i64) {
+        let conn = setup_db();
+        let mut a = test_photo("/lib/a.jpg", "a.jpg");
+        a.date_taken = 1625000000;
+        a.file_format = Some("jpeg".to_string());
+        a.camera_make = Some("Canon".to_string());
+        a.camera_model = Some("EOS R5".to_string());
+        a.latitude = Some(48.85);
+        a.longitude = Some(2.35);
+        let mut b = test_photo("/lib/b.mp4", "b.mp4");
+        b.date_taken = 1630000000;
+        b.file_format = Some("mp4".to_string());
+        let mut c = test_photo("/lib/c.png", "c.png");
+        c.date_taken = 1690000000;
+        c.file_format = Some("png".to_string());
+        let mut d = test_photo("/lib/d.jpg", "d.jpg");
+        d.date_taken = 1560000000;
+        d.file_format = Some("jpeg".to_string());
+        d.camera_make = Some("Apple".to_string());
+        d.camera_model = Some("iPhone 12".to_string());
+        let mut e = test_photo("/lib/e.jpg", "e.jpg");
+        e.date_taken = media::UNKNOWN_DATE;
+        e.file_format = Some("jpeg".to_string());
+        e.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        for photo in [&a, &b, &c, &d, &e] {
+            insert_photo(&conn, photo, "upload").unwrap();
+        }
+        set_photo_favorite(&conn, "/lib/a.jpg", true).unwrap();
+        set_photo_rating(&conn, "/lib/a.jpg", 4).unwrap();
+        set_photo_rating(&conn, "/lib/d.jpg", 2).unwrap();
+        set_screenshot_flags(&conn, &[("/lib/c.png".to_string(), true)]).unwrap();
+        let beach = create_tag(&conn, "beach", "#00f").unwrap();
+        let family = create_tag(&conn, "family", "#f00").unwrap();
+        add_tags_to_photos(&conn, &[beach], &["/lib/a.jpg".to_string(), "/lib/d.jpg".to_string()]).unwrap();
+        add_tags_to_photos(&conn, &[family], &["/lib/d.jpg".to_string()]).unwrap();
+        (conn, beach, family)
+    }
+
+    fn matching(conn: &Connection, filter: SmartAlbumFilter) -> Vec<String> {
+        let mut paths: Vec<String> = get_photos_matching(conn, &filter).unwrap().into_iter().map(|p| p.path).collect();
+        paths.sort();
+        paths
+    }
+
+    #[test]
+    fn test_smart_filter_fields_alone() {
+        let (conn, beach, family) = setup_smart_album_photos();
+
+        assert_eq!(matching(&conn, SmartAlbumFilter::default()).len(), 5);
+        // 2021 only
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { date_from: Some(1609459200), date_to: Some(1640995199), ..Default::default() }),
+            vec!["/lib/a.jpg", "/lib/b.mp4"]
+        );
+        // An open start still leaves out undated photos
+        assert_eq!(matching(&conn, SmartAlbumFilter { date_to: Some(1600000000), ..Default::default() }), vec!["/lib/d.jpg"]);
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { media_type: Some(MediaType::Video), ..Default::default() }),
+            vec!["/lib/b.mp4"]
+        );
+        assert_eq!(matching(&conn, SmartAlbumFilter { media_type: Some(MediaType::Photo), ..Default::default() }).len(), 4);
+        assert_eq!(matching(&conn, SmartAlbumFilter { favorite: Some(true), ..Default::default() }), vec!["/lib/a.jpg"]);
+        assert_eq!(matching(&conn, SmartAlbumFilter { favorite: Some(false), ..Default::default() }).len(), 4);
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { tag_ids: vec![beach], ..Default::default() }),
+            vec!["/lib/a.jpg", "/lib/d.jpg"]
+        );
+        assert_eq!(matching(&conn, SmartAlbumFilter { tag_ids: vec![beach, family], ..Default::default() }), vec!["/lib/d.jpg"]);
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { camera_make: Some("canon".to_string()), ..Default::default() }),
+            vec!["/lib/a.jpg"]
+        );
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { camera_model: Some("iPhone 12".to_string()), ..Default::default() }),
+            vec!["/lib/d.jpg"]
+        );
+        assert_eq!(
+            matching(&conn, SmartAlbumFilter { min_rating: Some(2), ..Default::default() }),
+            vec!["/lib/a.jpg", "/lib/d.jpg"]
+        );
+        assert_eq!(matching(&conn, SmartAlbumFilter { has_location: Some(true), ..Default::default() }), vec!["/lib/a.jpg"]);
+        assert_eq!(matching(&conn, SmartAlbumFilter { has_location: Some(false), ..Default::default() }).len(), 4);
+        assert_eq!(matching(&conn, SmartAlbumFilter { screenshot: Some(true), ..Default::default() }), vec!["/lib/c.png"]);
+    }
+
+    #[test]
+    fn test_smart_filter_fields_combined() {
+        let (conn, beach, _) = setup_smart_album_photos();
+
+        let favorites_with_gps = SmartAlbumFilter { favorite: Some(true), has_location: Some(true), ..Default::default() };
+        assert_eq!(matching(&conn, favorites_with_gps), vec!["/lib/a.jpg"]);
+
+        let rated_beach_photos = SmartAlbumFilter {
+            media_type: Some(MediaType::Photo),
+            tag_ids: vec![beach],
+            min_rating: Some(3),
+            ..Default::default()
+        };
+        assert_eq!(matching(&conn, rated_beach_photos), vec!["/lib/a.jpg"]);
+
+        let videos_from_2019 = SmartAlbumFilter {
+            date_from: Some(1546300800),
+            date_to: Some(1577836799),
+            media_type: Some(MediaType::Video),
+            ..Default::default()
+        };
+        assert!(matching(&conn, videos_from_2019).is_empty());
+    }
+
+    #[test]
+    fn test_smart_filter_values_are_bound() {
+        let (conn, _, _) = setup_smart_album_photos();
+        let injection = SmartAlbumFilter { camera_make: Some("x' OR '1'='1".to_string()), ..Default::default() };
+        assert!(matching(&conn, injection).is_empty());
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 5);
+    }
+
+    #[test]
+    fn test_smart_albums_stay_current() {
+        let (conn, _, _) = setup_smart_album_photos();
+        let regular = create_album(&conn, "Trip").unwrap();
+        let id = create_smart_album(&conn, "Favorites", &SmartAlbumFilter { favorite: Some(true), ..Default::default() }).unwrap();
+        assert_eq!(get_smart_album_photos(&conn, id).unwrap().len(), 1);
+
+        set_photo_favorite(&conn, "/lib/d.jpg", true).unwrap();
+        let paths: Vec<String> = get_smart_album_photos(&conn, id).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(paths, vec!["/lib/a.jpg", "/lib/d.jpg"]);
+
+        let albums = get_albums(&conn).unwrap();
+        assert_eq!(albums.len(), 2);
+        assert_eq!((albums[0].id, albums[0].kind), (regular, AlbumKind::Regular));
+        assert_eq!((albums[1].id, albums[1].kind), (id, AlbumKind::Smart));
+        assert_eq!(albums[1].count, 2);
+        assert_eq!(albums[1].cover_photo_path.as_deref(), Some("/lib/a.jpg"));
+
+        let screenshots = SmartAlbumFilter { screenshot: Some(true), ..Default::default() };
+        assert!(update_smart_album(&conn, id, "Screenshots", &screenshots).unwrap());
+        let saved = &get_smart_albums(&conn).unwrap()[0];
+        assert_eq!((saved.name.as_str(), &saved.filter, saved.count), ("Screenshots", &screenshots, 1));
+        assert!(!update_smart_album(&conn, id + 1, "Nope", &screenshots).unwrap());
+
+        delete_smart_album(&conn, id).unwrap();
+        assert!(get_smart_albums(&conn).unwrap().is_empty());
+        assert!(get_smart_album_photos(&conn, id).unwrap().is_empty());
+    }
+
     // ====================================================================
     // Tags tests
     // ====================================================================
@@ -1544,7 +9742,7 @@ mod tests {
 
         archive_photo(&conn, "/photos/archive_me.jpg").unwrap();
 
-        let archived = get_archived_photos(&conn).unwrap();
+        let archived = get_deletion_archive(&conn).unwrap();
         assert_eq!(archived.len(), 1);
         assert_eq!(archived[0].0.path, "/photos/archive_me.jpg");
     }
@@ -1556,10 +9754,10 @@ mod tests {
         insert_photo(&conn, &photo, "upload").unwrap();
 
         archive_photo(&conn, "/photos/restore_me.jpg").unwrap();
-        assert_eq!(get_archived_photos(&conn).unwrap().len(), 1);
+        assert_eq!(get_deletion_archive(&conn).unwrap().len(), 1);
 
         restore_photo(&conn, "/photos/restore_me.jpg").unwrap();
-        assert_eq!(get_archived_photos(&conn).unwrap().len(), 0);
+        assert_eq!(get_deletion_archive(&conn).unwrap().len(), 0);
     }
 
     // ====================================================================
@@ -1593,6 +9791,43 @@ mod tests {
         assert_eq!(get_unreviewed_count(&conn).unwrap(), 1);
     }
 
+    #[test]
+    fn test_culling_flags_queue_and_progress() {
+        let conn = setup_db();
+        for (name, date) in [("c.jpg", 300), ("a.jpg", 100), ("b.jpg", 200), ("d.jpg", 400)] {
+            insert_dated(&conn, name, date);
+        }
+        let path = |name: &str| format!("/photos/{}", name);
+        let names = |photos: Vec<PhotoMetadata>| -> Vec<String> { photos.into_iter().map(|p| p.name).collect() };
+        let everything = PhotoQuery::default();
+
+        assert_eq!(names(get_cull_queue(&conn, &everything).unwrap()), vec!["a.jpg", "b.jpg", "c.jpg", "d.jpg"]);
+        assert_eq!(set_photos_flag(&conn, &[path("a.jpg")], PhotoFlag::Pick).unwrap(), 1);
+        assert_eq!(set_photos_flag(&conn, &[path("b.jpg"), path("d.jpg")], PhotoFlag::Reject).unwrap(), 2);
+        assert_eq!(names(get_cull_queue(&conn, &everything).unwrap()), vec!["c.jpg"]);
+        assert_eq!(
+            get_cull_progress(&conn, &everything).unwrap(),
+            CullProgress { picked: 1, rejected: 2, remaining: 1 }
+        );
+
+        // Rejects leave the timeline but can still be asked for
+        assert_eq!(names(get_filtered_photos(&conn, &PhotoFilter::default()).unwrap()), vec!["c.jpg", "a.jpg"]);
+        assert_eq!(get_photo_count(&conn, &PhotoFilter::default()).unwrap(), 2);
+        assert_eq!(names(query_photos(&conn, &everything, 0, None).unwrap()), vec!["c.jpg", "a.jpg"]);
+        let rejects = PhotoQuery { flag: Some(PhotoFlag::Reject), ..Default::default() };
+        assert_eq!(names(query_photos(&conn, &rejects, 0, None).unwrap()), vec!["d.jpg", "b.jpg"]);
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 4);
+
+        // Clearing a reject puts it back in the queue
+        set_photos_flag(&conn, &[path("d.jpg")], PhotoFlag::None).unwrap();
+        assert_eq!(names(get_cull_queue(&conn, &everything).unwrap()), vec!["c.jpg", "d.jpg"]);
+        assert_eq!(get_all_photos(&conn).unwrap().iter().find(|p| p.name == "a.jpg").unwrap().flag, Some(PhotoFlag::Pick));
+
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/photos/b.jpg'", []).unwrap();
+        set_photos_flag(&conn, &[path("c.jpg")], PhotoFlag::Reject).unwrap();
+        assert_eq!(get_rejected_paths(&conn).unwrap(), vec![path("c.jpg")]);
+    }
+
     // ====================================================================
     // Settings tests
     // ====================================================================
@@ -1611,4 +9846,1109 @@ mod tests {
         let value = get_setting(&conn, "theme");
         assert_eq!(value.as_deref(), Some("dark"));
     }
+
+    #[test]
+    fn test_unmounted_library_path_is_an_error() {
+        let conn = setup_db();
+        let gone = std::env::temp_dir().join(format!("terra-unmounted-{}", std::process::id()));
+        set_setting(&conn, "library_path", &serde_json::json!(gone.to_string_lossy()).to_string()).unwrap();
+
+        match get_library_path(&conn) {
+            Err(LibraryPathError::Unavailable(path)) => assert_eq!(path, gone),
+            other => panic!("expected Unavailable, got {:?}", other),
+        }
+        assert!(!gone.exists(), "a missing library root must not be created");
+        assert!(get_archive_path(&conn).is_err());
+        assert!(get_trash_path(&conn).is_err());
+    }
+
+    #[test]
+    fn test_set_library_path_keeps_previous_roots_managed() {
+        let conn = setup_db();
+        let base = std::env::temp_dir().join(format!("terra-roots-{}", std::process::id()));
+        let old_root = base.join("old");
+        let new_root = base.join("new");
+        std::fs::create_dir_all(&old_root).unwrap();
+        std::fs::create_dir_all(&new_root).unwrap();
+
+        set_library_path(&conn, &old_root).unwrap();
+        set_library_path(&conn, &new_root).unwrap();
+
+        assert_eq!(get_library_path(&conn).unwrap(), new_root);
+        let roots = get_managed_roots(&conn);
+        assert_eq!(roots[0], new_root.join("Archive"));
+        assert_eq!(roots[1], new_root);
+        assert!(roots.contains(&old_root));
+        assert!(roots.contains(&old_root.join("Archive")));
+
+        // Switching back doesn't list the current root as a previous one
+        set_library_path(&conn, &old_root).unwrap();
+        assert!(!get_previous_library_paths(&conn).contains(&old_root));
+        assert!(get_previous_library_paths(&conn).contains(&new_root));
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_screenshot_detection_override_and_filter() {
+        let conn = setup_db();
+        let mut shot = test_photo("/lib/Screenshot 2024-01-01.png", "Screenshot 2024-01-01.png");
+        shot.file_format = Some("png".to_string());
+        insert_photo(&conn, &shot, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/a.jpg", "a.jpg"), "scan").unwrap();
+
+        // Flagged on import; the filter leaves it out of the grid
+        let hidden = PhotoFilter { exclude_screenshots: true, ..Default::default() };
+        assert_eq!(get_screenshots(&conn).unwrap().len(), 1);
+        assert_eq!(get_photo_count(&conn, &hidden).unwrap(), 1);
+        let page = get_photos_page(&conn, PhotoSort::DateDesc, 0, 10, None, &hidden).unwrap();
+        assert_eq!(page.photos[0].path, "/lib/a.jpg");
+        assert_eq!(get_filtered_photos(&conn, &hidden).unwrap().len(), 1);
+
+        // The user's verdict survives rescans and backfills
+        assert!(set_screenshot_override(&conn, &shot.path, false).unwrap());
+        insert_photo(&conn, &shot, "scan").unwrap();
+        assert_eq!(set_screenshot_flags(&conn, &[(shot.path.clone(), true)]).unwrap(), 0);
+        assert!(get_screenshots(&conn).unwrap().is_empty());
+        let candidates = get_screenshot_candidates(&conn).unwrap();
+        assert_eq!(candidates.len(), 1);
+        assert_eq!((candidates[0].0.path.as_str(), candidates[0].1), ("/lib/a.jpg", false));
+
+        assert_eq!(set_screenshot_flags(&conn, &[("/lib/a.jpg".to_string(), true)]).unwrap(), 1);
+        assert_eq!(get_photo_count(&conn, &hidden).unwrap(), 1);
+        assert!(!set_screenshot_override(&conn, "/lib/nope.png", true).unwrap());
+    }
+
+    #[test]
+    fn test_hidden_photos_stay_out_of_grid_albums_and_search() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/secret_beach.jpg", "secret_beach.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/open_beach.jpg", "open_beach.jpg"), "scan").unwrap();
+        let album = create_album(&conn, "Beach").unwrap();
+        add_photo_to_album(&conn, album, "/lib/secret_beach.jpg").unwrap();
+        add_photo_to_album(&conn, album, "/lib/open_beach.jpg").unwrap();
+
+        let paths = vec!["/lib/secret_beach.jpg".to_string(), "/lib/missing.jpg".to_string()];
+        assert_eq!(set_photos_hidden(&conn, &paths, true).unwrap(), 1);
+
+        let filter = PhotoFilter::default();
+        assert_eq!(get_photo_count(&conn, &filter).unwrap(), 1);
+        assert_eq!(get_filtered_photos(&conn, &filter).unwrap().len(), 1);
+        assert_eq!(search_photos(&conn, "beach").unwrap().len(), 1);
+        assert_eq!(get_album_photos(&conn, album).unwrap().len(), 1);
+        let beach = get_albums(&conn).unwrap().into_iter().find(|a| a.id == album).unwrap();
+        assert_eq!(beach.count, 1);
+
+        // Exports and the hidden view still see it
+        assert_eq!(get_all_album_photos(&conn, album).unwrap().len(), 2);
+        assert_eq!(get_all_photos(&conn).unwrap().len(), 2);
+        let hidden = get_hidden_photos(&conn).unwrap();
+        assert_eq!(hidden.len(), 1);
+        assert_eq!(hidden[0].path, "/lib/secret_beach.jpg");
+
+        // Deleting the album leaves the hidden photo in the library
+        delete_album(&conn, album, false).unwrap();
+        assert_eq!(get_hidden_photos(&conn).unwrap().len(), 1);
+
+        assert_eq!(set_photos_hidden(&conn, &paths[..1], false).unwrap(), 1);
+        assert!(get_hidden_photos(&conn).unwrap().is_empty());
+        assert_eq!(get_photo_count(&conn, &filter).unwrap(), 2);
+    }
+
+    #[test]
+    fn test_timeline_archive_keeps_albums_and_search() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/lib/old_beach.jpg", "old_beach.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/lib/new_beach.jpg", "new_beach.jpg"), "scan").unwrap();
+        let album = create_album(&conn, "Beach").unwrap();
+        add_photo_to_album(&conn, album, "/lib/old_beach.jpg").unwrap();
+
+        assert_eq!(set_photos_archived(&conn, &["/lib/old_beach.jpg".to_string()], true).unwrap(), 1);
+
+        let timeline = PhotoFilter::default();
+        assert_eq!(get_photo_count(&conn, &timeline).unwrap(), 1);
+        assert_eq!(get_filtered_photos(&conn, &timeline).unwrap()[0].path, "/lib/new_beach.jpg");
+        let with_archived = PhotoFilter { include_archived: true, ..Default::default() };
+        assert_eq!(get_filtered_photos(&conn, &with_archived).unwrap().len(), 2);
+
+        // Unlike hidden, archived photos still show in albums and search
+        assert_eq!(get_album_photos(&conn, album).unwrap().len(), 1);
+        assert_eq!(search_photos(&conn, "beach").unwrap().len(), 2);
+
+        let archived = get_archived_photos(&conn).unwrap();
+        assert_eq!(archived.len(), 1);
+        assert_eq!(archived[0].path, "/lib/old_beach.jpg");
+        // The deletion archive is a separate thing
+        assert!(get_deletion_archive(&conn).unwrap().is_empty());
+
+        set_photos_hidden(&conn, &["/lib/new_beach.jpg".to_string()], true).unwrap();
+        let stats = get_storage_analytics(&conn).unwrap();
+        assert_eq!((stats.total_archived, stats.total_hidden), (1, 1));
+
+        set_photos_archived(&conn, &["/lib/old_beach.jpg".to_string()], false).unwrap();
+        assert!(get_archived_photos(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_import_session_stamps_only_new_rows() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/card/old.jpg", "old.jpg"), "scan").unwrap();
+        let earlier = record_import_session(&conn, "scan", "/card", 0, &["/card/old.jpg".to_string()], 0).unwrap();
+
+        let started = chrono::Utc::now().timestamp_millis();
+        let photos = vec![test_photo("/card/a.jpg", "a.jpg"), test_photo("/card/b.jpg", "b.jpg")];
+        insert_photos_batch(&conn, &photos, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/card/old.jpg", "old.jpg"), "scan").unwrap();
+        let paths: Vec<String> = ["/card/a.jpg", "/card/b.jpg", "/card/old.jpg"].iter().map(|p| p.to_string()).collect();
+        let id = record_import_session(&conn, "scan", "/card", started, &paths, 1).unwrap();
+
+        // The rescanned photo stays with the session that first added it
+        let sessions = get_import_sessions(&conn).unwrap();
+        let session = sessions.iter().find(|s| s.id == id).unwrap();
+        assert_eq!((session.imported, session.failed, session.photo_count), (2, 1, 2));
+        assert_eq!(sessions.iter().find(|s| s.id == earlier).unwrap().photo_count, 1);
+        let mut in_session: Vec<String> = get_session_photos(&conn, id).unwrap().into_iter().map(|p| p.path).collect();
+        in_session.sort();
+        assert_eq!(in_session, vec!["/card/a.jpg", "/card/b.jpg"]);
+
+        let album = create_album(&conn, "Keep").unwrap();
+        assert_eq!(count_session_photos_in_albums(&conn, id).unwrap(), 0);
+        add_photo_to_album(&conn, album, "/card/a.jpg").unwrap();
+        assert_eq!(count_session_photos_in_albums(&conn, id).unwrap(), 1);
+
+        assert!(delete_import_session(&conn, id).unwrap());
+        assert!(get_session_photos(&conn, id).unwrap().is_empty());
+        assert!(photo_exists(&conn, "/card/a.jpg").unwrap());
+        assert!(!delete_import_session(&conn, id).unwrap());
+    }
+
+    #[test]
+    fn test_file_info_backfill_and_album_byte_totals() {
+        let conn = setup_db();
+        let mut imported = test_photo("/s/a.jpg", "a.jpg");
+        imported.file_size = Some(1000);
+        imported.mime_type = Some("image/jpeg".to_string());
+        insert_photo(&conn, &imported, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/s/old.mov", "old.mov"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/s/gone.jpg", "gone.jpg"), "scan").unwrap();
+        // A rescan without a size keeps the recorded one
+        insert_photo(&conn, &test_photo("/s/a.jpg", "a.jpg"), "scan").unwrap();
+
+        let mut missing = get_photos_without_file_size(&conn).unwrap();
+        missing.sort();
+        assert_eq!(missing, vec!["/s/gone.jpg".to_string(), "/s/old.mov".to_string()]);
+        update_file_info_batch(&conn, &[
+            ("/s/old.mov".to_string(), Some(4000), Some("video/quicktime".to_string())),
+            ("/s/gone.jpg".to_string(), None, Some("image/jpeg".to_string())),
+        ])
+        .unwrap();
+        let details = get_photo_details(&conn, "/s/old.mov").unwrap().unwrap();
+        assert_eq!((details.file_size, details.mime_type.as_deref()), (Some(4000), Some("video/quicktime")));
+        let details = get_photo_details(&conn, "/s/a.jpg").unwrap().unwrap();
+        assert_eq!((details.file_size, details.mime_type.as_deref()), (Some(1000), Some("image/jpeg")));
+        // The missing file stays without a size, so it's tried again next time
+        assert_eq!(get_photos_without_file_size(&conn).unwrap(), vec!["/s/gone.jpg".to_string()]);
+
+        let trip = create_album(&conn, "Trip").unwrap();
+        let day = create_album(&conn, "Day one").unwrap();
+        set_album_parent(&conn, day, Some(trip)).unwrap();
+        add_photo_to_album(&conn, trip, "/s/a.jpg").unwrap();
+        add_photo_to_album(&conn, day, "/s/a.jpg").unwrap();
+        add_photo_to_album(&conn, day, "/s/old.mov").unwrap();
+        let albums = get_albums(&conn).unwrap();
+        let trip = albums.iter().find(|a| a.id == trip).unwrap();
+        assert_eq!((trip.size_bytes, trip.total_size_bytes), (1000, 5000));
+        let day = albums.iter().find(|a| a.id == day).unwrap();
+        assert_eq!((day.size_bytes, day.total_size_bytes), (5000, 5000));
+    }
+
+    #[test]
+    fn test_album_stats_for_regular_and_smart_albums() {
+        let conn = setup_db();
+        for (name, date, size) in [("a.jpg", 100, 1000), ("b.jpg", 300, 2000), ("clip.mov", 200, 5000), ("gone.jpg", 50, 400)] {
+            let mut photo = test_photo(&format!("/s/{}", name), name);
+            photo.date_taken = date;
+            photo.file_size = Some(size);
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let mut undated = test_photo("/s/undated.jpg", "undated.jpg");
+        undated.date_taken = 10;
+        undated.date_source = Some("unknown".to_string());
+        insert_photo(&conn, &undated, "scan").unwrap();
+        set_photo_favorite(&conn, "/s/b.jpg", true).unwrap();
+        set_photo_favorite(&conn, "/s/clip.mov", true).unwrap();
+
+        let trip = create_album(&conn, "Trip").unwrap();
+        assert_eq!(get_album_stats(&conn, trip).unwrap(), Some(AlbumStats::default()));
+        for name in ["a.jpg", "b.jpg", "clip.mov", "gone.jpg", "undated.jpg"] {
+            add_photo_to_album(&conn, trip, &format!("/s/{}", name)).unwrap();
+        }
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/s/gone.jpg'", []).unwrap();
+        assert_eq!(get_album_stats(&conn, trip).unwrap(), Some(AlbumStats {
+            count: 4,
+            size_bytes: 8000,
+            video_count: 1,
+            favorite_count: 2,
+            earliest_date: Some(100),
+            latest_date: Some(300),
+        }));
+        assert_eq!(get_album_stats(&conn, trip + 1).unwrap(), None);
+
+        let favorites = create_smart_album(&conn, "Favorites", &SmartAlbumFilter { favorite: Some(true), ..Default::default() }).unwrap();
+        assert_eq!(get_smart_album_stats(&conn, favorites).unwrap(), Some(AlbumStats {
+            count: 2,
+            size_bytes: 7000,
+            video_count: 1,
+            favorite_count: 2,
+            earliest_date: Some(200),
+            latest_date: Some(300),
+        }));
+        assert_eq!(get_smart_album_stats(&conn, favorites + 1).unwrap(), None);
+    }
+
+    #[test]
+    fn test_rewrite_stored_paths_merges_unicode_spellings() {
+        let conn = setup_db();
+        let decomposed = "/s/cafe\u{301}.jpg";
+        let composed = "/s/caf\u{e9}.jpg";
+        insert_photo(&conn, &test_photo(decomposed, "cafe\u{301}.jpg"), "scan").unwrap();
+        let mut still = test_photo("/s/re\u{301}sume\u{301}.jpg", "re\u{301}sume\u{301}.jpg");
+        still.paired_video_path = Some("/s/clip.mov".to_string());
+        insert_photo(&conn, &still, "scan").unwrap();
+        let album = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album, decomposed).unwrap();
+        set_album_cover(&conn, album, decomposed).unwrap();
+
+        rewrite_stored_paths(&conn, crate::library::compose_unicode).unwrap();
+        assert!(!photo_exists(&conn, decomposed).unwrap());
+        assert!(photo_exists(&conn, composed).unwrap());
+        assert!(photo_exists(&conn, "/s/r\u{e9}sum\u{e9}.jpg").unwrap());
+        let cover = get_albums(&conn).unwrap().into_iter().find(|a| a.id == album).unwrap().cover_photo_path;
+        assert_eq!(cover.as_deref(), Some(composed));
+        assert_eq!(get_album_photos(&conn, album).unwrap()[0].path, composed);
+
+        // Both spellings imported: one row remains, under the composed
+        // path, with the decomposed row's favorite and tag
+        insert_photo(&conn, &test_photo(decomposed, "cafe\u{301}.jpg"), "scan").unwrap();
+        set_photo_favorite(&conn, decomposed, true).unwrap();
+        let tag = create_tag(&conn, "Paris", "#00aaff").unwrap();
+        add_tags_to_photos(&conn, &[tag], &[decomposed.to_string()]).unwrap();
+        rewrite_stored_paths(&conn, crate::library::compose_unicode).unwrap();
+        assert!(!photo_exists(&conn, decomposed).unwrap());
+        let (count, favorite): (i64, bool) = conn
+            .query_row("SELECT COUNT(*), MAX(is_favorite) FROM photos WHERE name LIKE 'caf%'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((count, favorite), (1, true));
+        assert_eq!(get_tags_for_photo(&conn, composed).unwrap().len(), 1);
+        assert_eq!(get_album_photos(&conn, album).unwrap()[0].path, composed);
+    }
+
+    #[test]
+    fn test_rewrite_strips_windows_prefixes_from_photos_and_settings() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo(r"\\?\C:\Terra\a.jpg", "a.jpg"), "scan").unwrap();
+        set_setting(&conn, "library_path", &serde_json::json!(r"\\?\C:\Terra").to_string()).unwrap();
+        set_setting(&conn, "watch_folders", &serde_json::json!([r"\\?\UNC\nas\photos"]).to_string()).unwrap();
+
+        rewrite_stored_paths(&conn, crate::library::windows_form).unwrap();
+        rewrite_path_settings(&conn, crate::library::windows_form).unwrap();
+        assert!(photo_exists(&conn, r"C:\Terra\a.jpg").unwrap());
+        assert_eq!(get_setting(&conn, "library_path").map(|raw| setting_text(&raw)).as_deref(), Some(r"C:\Terra"));
+        assert_eq!(get_watch_folders(&conn), vec![PathBuf::from(r"\\nas\photos")]);
+
+        // The prefixed and plain spellings of one file merge into one row
+        let prefixed = r"\\?\C:\Terra\b.jpg";
+        insert_photo(&conn, &test_photo(prefixed, "b.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo(r"C:\Terra\b.jpg", "b.jpg"), "scan").unwrap();
+        set_photo_rating(&conn, prefixed, 3).unwrap();
+        set_setting(&conn, "watch_folders", &serde_json::json!([r"\\?\D:\Pics", r"D:\Pics"]).to_string()).unwrap();
+        rewrite_stored_paths(&conn, crate::library::windows_form).unwrap();
+        rewrite_path_settings(&conn, crate::library::windows_form).unwrap();
+        let rows: Vec<(String, i32)> = conn
+            .prepare("SELECT path, rating FROM photos WHERE name = 'b.jpg'")
+            .unwrap()
+            .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        assert_eq!(rows, vec![(r"C:\Terra\b.jpg".to_string(), 3)]);
+        assert_eq!(get_watch_folders(&conn), vec![PathBuf::from(r"D:\Pics")]);
+    }
+
+    // Where paths ignore case the second spelling can't be inserted at all
+    #[cfg(not(any(target_os = "macos", windows)))]
+    #[test]
+    fn test_merge_case_variants_keeps_richer_row_with_memberships() {
+        let conn = setup_db();
+        let mut rich = test_photo("/s/IMG_0001.JPG", "IMG_0001.JPG");
+        rich.camera_make = Some("Canon".to_string());
+        rich.latitude = Some(35.0);
+        rich.description = Some("Harbour".to_string());
+        rich.rating = 4;
+        insert_photo(&conn, &rich, "scan").unwrap();
+        insert_photo(&conn, &test_photo("/s/img_0001.jpg", "img_0001.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/s/other.jpg", "other.jpg"), "scan").unwrap();
+        set_photo_favorite(&conn, "/s/img_0001.jpg", true).unwrap();
+        let album = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album, "/s/img_0001.jpg").unwrap();
+        set_album_cover(&conn, album, "/s/img_0001.jpg").unwrap();
+        let tag = create_tag(&conn, "Beach", "#00aaff").unwrap();
+        add_tags_to_photos(&conn, &[tag], &["/s/img_0001.jpg".to_string()]).unwrap();
+
+        // The exact spelling still finds only itself; NOCASE finds the stored one
+        assert_eq!(stored_spelling(&conn, "/s/Other.JPG", "BINARY").unwrap(), None);
+        assert_eq!(stored_spelling(&conn, "/s/Other.JPG", "NOCASE").unwrap().as_deref(), Some("/s/other.jpg"));
+
+        assert_eq!(merge_case_variants(&conn).unwrap(), 1);
+        assert!(photo_exists(&conn, "/s/IMG_0001.JPG").unwrap());
+        let kept: (bool, Option<String>) = conn
+            .query_row("SELECT is_favorite, camera_make FROM photos WHERE path = '/s/IMG_0001.JPG'", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!(kept, (true, Some("Canon".to_string())));
+        let photos: Vec<String> = get_album_photos(&conn, album).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(photos, vec!["/s/IMG_0001.JPG"]);
+        let cover: String = conn
+            .query_row("SELECT cover_photo_path FROM albums WHERE id = ?1", params![album], |row| row.get(0))
+            .unwrap();
+        assert_eq!(cover, "/s/IMG_0001.JPG");
+        assert_eq!(get_tags_for_photo(&conn, "/s/IMG_0001.JPG").unwrap().len(), 1);
+        assert_eq!(merge_case_variants(&conn).unwrap(), 0);
+    }
+
+    #[test]
+    fn test_jobs_track_status_and_requeue_after_restart() {
+        let conn = setup_db();
+        let hashes = insert_job(&conn, "hash_backfill", &serde_json::json!({})).unwrap();
+        let thumbs = insert_job(&conn, "thumbnails", &serde_json::json!({ "prewarm": true })).unwrap();
+        let sizes = insert_job(&conn, "file_sizes", &serde_json::json!({})).unwrap();
+
+        set_job_status(&conn, hashes, "running", None).unwrap();
+        set_job_progress(&conn, hashes, 200, 1000).unwrap();
+        set_job_status(&conn, sizes, "failed", Some("disk gone")).unwrap();
+
+        let job = get_job(&conn, hashes).unwrap().unwrap();
+        assert_eq!((job.status.as_str(), job.processed, job.total), ("running", 200, 1000));
+        assert!(job.started_at.is_some() && job.finished_at.is_none());
+        let failed = get_job(&conn, sizes).unwrap().unwrap();
+        assert_eq!(failed.error.as_deref(), Some("disk gone"));
+        assert!(failed.finished_at.is_some());
+
+        // After a restart the interrupted job and the queued one run again, in order
+        let resumed = requeue_unfinished_jobs(&conn).unwrap();
+        assert_eq!(resumed.iter().map(|j| j.id).collect::<Vec<_>>(), vec![hashes, thumbs]);
+        assert!(resumed.iter().all(|j| j.status == "queued"));
+        assert_eq!(resumed[1].params, serde_json::json!({ "prewarm": true }));
+        assert_eq!(resumed[0].processed, 200);
+
+        let listed: Vec<i64> = get_jobs(&conn, 2).unwrap().iter().map(|j| j.id).collect();
+        assert_eq!(listed, vec![sizes, thumbs]);
+    }
+
+    #[test]
+    fn test_places_group_by_city_and_reset_when_coordinates_change() {
+        let conn = setup_db();
+        for (name, date, lat) in [("a.jpg", 100, 48.85), ("b.jpg", 300, 48.86), ("c.jpg", 200, 35.68), ("none.jpg", 50, 0.0)] {
+            let mut photo = test_photo(&format!("/p/{}", name), name);
+            photo.date_taken = date;
+            if name != "none.jpg" {
+                photo.latitude = Some(lat);
+                photo.longitude = Some(if lat > 40.0 { 2.35 } else { 139.69 });
+            }
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let pending = get_photos_to_geocode(&conn).unwrap();
+        assert_eq!(pending.len(), 3);
+        let places: Vec<(i64, Place)> = pending
+            .iter()
+            .map(|&(id, lat, _)| {
+                let (city, region, country) =
+                    if lat > 40.0 { ("Paris", "Île-de-France", "France") } else { ("Tokyo", "Tokyo", "Japan") };
+                (id, Place { city: city.to_string(), region: region.to_string(), country: country.to_string() })
+            })
+            .collect();
+        set_photo_places_batch(&conn, &places).unwrap();
+        assert!(get_photos_to_geocode(&conn).unwrap().is_empty());
+
+        let summary = get_places(&conn).unwrap();
+        assert_eq!(summary[0], PlaceSummary {
+            city: "Paris".to_string(),
+            region: "Île-de-France".to_string(),
+            country: "France".to_string(),
+            count: 2,
+            cover_photo_path: "/p/b.jpg".to_string(),
+        });
+        assert_eq!(summary[1].city, "Tokyo");
+        let paris: Vec<String> =
+            get_photos_by_place(&conn, "France", None, Some("Paris")).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(paris, vec!["/p/b.jpg", "/p/a.jpg"]);
+        assert_eq!(get_photos_by_place(&conn, "France", Some("Île-de-France"), None).unwrap().len(), 2);
+        assert!(get_photos_by_place(&conn, "France", Some("Normandy"), Some("Paris")).unwrap().is_empty());
+        assert_eq!(get_photos_by_place(&conn, "Japan", None, None).unwrap().len(), 1);
+
+        // Placed before regions were recorded: geocoded again
+        conn.execute("UPDATE photos SET place_region = NULL WHERE id = ?1", [places[2].0]).unwrap();
+        assert_eq!(get_photos_to_geocode(&conn).unwrap().len(), 1);
+        set_photo_places_batch(&conn, &places[2..]).unwrap();
+
+        // A rescan with the same coordinates keeps the place; moved ones are geocoded again
+        let mut same = test_photo("/p/a.jpg", "a.jpg");
+        same.latitude = Some(48.85);
+        same.longitude = Some(2.35);
+        insert_photo(&conn, &same, "scan").unwrap();
+        let mut moved = test_photo("/p/c.jpg", "c.jpg");
+        moved.latitude = Some(51.5);
+        moved.longitude = Some(-0.12);
+        insert_photo(&conn, &moved, "scan").unwrap();
+        let pending: Vec<i64> = get_photos_to_geocode(&conn).unwrap().iter().map(|p| p.0).collect();
+        assert_eq!(pending, vec![places[2].0]);
+    }
+
+    #[test]
+    fn test_face_clusters_are_recomputed_around_names() {
+        use crate::faces::FaceRegion;
+
+        let conn = setup_db();
+        for i in 0..6 {
+            insert_photo(&conn, &test_photo(&format!("/c/{}.jpg", i), "c.jpg"), "scan").unwrap();
+        }
+        let ids: Vec<i64> = get_photos_to_scan_faces(&conn, "v1").unwrap().into_iter().map(|(id, _, _)| id).collect();
+        let face = |confidence: f64, embedding: Vec<f32>| {
+            (FaceRegion { x: 0.1, y: 0.1, w: 0.2, h: 0.2, confidence }, Some(embedding))
+        };
+        let detections: Vec<(i64, Vec<_>)> = ids
+            .iter()
+            .enumerate()
+            .map(|(i, &id)| {
+                let embedding = if i < 4 { vec![1.0, 0.0] } else { vec![0.0, 1.0] };
+                (id, vec![face(0.8 + i as f64 / 100.0, embedding)])
+            })
+            .collect();
+        save_faces(&conn, "v1", &detections).unwrap();
+
+        let embeddings = get_unnamed_face_embeddings(&conn).unwrap();
+        assert_eq!(embeddings.len(), 6);
+        let vectors: Vec<Vec<f32>> = embeddings.iter().map(|(_, v)| v.clone()).collect();
+        let clusters: Vec<Vec<i64>> = crate::faces::cluster(&vectors)
+            .into_iter()
+            .map(|members| members.into_iter().map(|i| embeddings[i].0).collect())
+            .collect();
+        set_face_clusters(&conn, &clusters).unwrap();
+
+        let found = get_face_clusters(&conn, 2).unwrap();
+        assert_eq!(found.clusters.len(), 1);
+        assert_eq!(found.clusters[0].size, 4);
+        // Most confident first
+        assert_eq!(found.clusters[0].samples[0].photo_path, "/c/3.jpg");
+        assert_eq!(found.clusters[0].samples.len(), 2);
+        assert_eq!(found.unclustered_count, 2);
+
+        let grandma = create_person(&conn, "Grandma").unwrap();
+        assert_eq!(assign_cluster_to_person(&conn, found.clusters[0].id, grandma).unwrap(), 4);
+        assert_eq!(get_photos_by_person(&conn, grandma).unwrap().len(), 4);
+
+        // Reclustering sees only the unnamed faces and keeps the names
+        set_face_clusters(&conn, &[]).unwrap();
+        assert_eq!(get_unnamed_face_embeddings(&conn).unwrap().len(), 2);
+        assert_eq!(get_people(&conn).unwrap()[0].count, 4);
+        let found = get_face_clusters(&conn, 2).unwrap();
+        assert!(found.clusters.is_empty());
+        assert_eq!(found.unclustered_count, 2);
+    }
+
+    #[test]
+    fn test_quality_scores_are_saved_and_cleared_when_content_changes() {
+        use crate::quality::QualityScore;
+
+        let conn = setup_db();
+        for name in ["sharp.jpg", "blurry.jpg", "broken.jpg"] {
+            insert_photo(&conn, &test_photo(&format!("/q/{}", name), name), "scan").unwrap();
+        }
+        let pending = get_photos_to_score_quality(&conn).unwrap();
+        assert_eq!(pending.len(), 3);
+        let score = |score: f64| Some(QualityScore { sharpness: 10f64.powf(score) - 1.0, clipped_fraction: 0.0, score });
+        save_quality_scores(&conn, &[(pending[0].0, score(3.0)), (pending[1].0, score(1.5)), (pending[2].0, None)]).unwrap();
+        assert!(get_photos_to_score_quality(&conn).unwrap().is_empty());
+
+        let low = get_low_quality_photos(&conn, 2.0, 10).unwrap();
+        assert_eq!(low.len(), 1);
+        assert_eq!((low[0].photo.path.as_str(), low[0].quality_score), ("/q/blurry.jpg", 1.5));
+        let paths = vec!["/q/sharp.jpg".to_string(), "/q/broken.jpg".to_string()];
+        let scores = get_quality_scores(&conn, &paths).unwrap();
+        assert_eq!(scores.len(), 1);
+        assert_eq!(scores["/q/sharp.jpg"], 3.0);
+
+        // Rescanning the same file keeps its score; new content is scored again
+        insert_photo(&conn, &test_photo("/q/sharp.jpg", "sharp.jpg"), "scan").unwrap();
+        assert!(get_photos_to_score_quality(&conn).unwrap().is_empty());
+        let mut edited = test_photo("/q/blurry.jpg", "blurry.jpg");
+        edited.content_hash = Some("def456".to_string());
+        insert_photo(&conn, &edited, "scan").unwrap();
+        assert_eq!(get_photos_to_score_quality(&conn).unwrap()[0].1, "/q/blurry.jpg");
+        assert!(get_low_quality_photos(&conn, 2.0, 10).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_faces_named_by_hand_survive_rescans() {
+        use crate::faces::FaceRegion;
+
+        let conn = setup_db();
+        for (name, date) in [("old.jpg", 100), ("new.jpg", 200)] {
+            let mut photo = test_photo(&format!("/f/{}", name), name);
+            photo.date_taken = date;
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let pending = get_photos_to_scan_faces(&conn, "v1").unwrap();
+        assert_eq!(pending.len(), 2);
+        let (old_id, new_id) = (pending[0].0, pending[1].0);
+        let region = |x: f64| (FaceRegion { x, y: 0.2, w: 0.2, h: 0.3, confidence: 0.9 }, None);
+        save_faces(&conn, "v1", &[(old_id, vec![region(0.5), region(0.1)]), (new_id, vec![region(0.3)])]).unwrap();
+        assert!(get_photos_to_scan_faces(&conn, "v1").unwrap().is_empty());
+
+        let faces = get_faces(&conn, "/f/old.jpg").unwrap();
+        assert_eq!(faces.iter().map(|f| f.x).collect::<Vec<_>>(), vec![0.1, 0.5]);
+        let ada = create_person(&conn, "Ada").unwrap();
+        assert_eq!(create_person(&conn, " ada ").unwrap(), ada);
+        assert!(assign_face(&conn, faces[0].id, Some(ada)).unwrap());
+        assert!(!assign_face(&conn, 9999, Some(ada)).unwrap());
+        let new_face = get_faces(&conn, "/f/new.jpg").unwrap()[0].id;
+        assert!(assign_face(&conn, new_face, Some(ada)).unwrap());
+
+        let people = get_people(&conn).unwrap();
+        assert_eq!(people, vec![Person {
+            id: ada,
+            name: "Ada".to_string(),
+            count: 2,
+            cover_photo_path: Some("/f/new.jpg".to_string()),
+            cover_region: Some([0.3, 0.2, 0.2, 0.3]),
+        }]);
+        let photos: Vec<String> = get_photos_by_person(&conn, ada).unwrap().into_iter().map(|p| p.path).collect();
+        assert_eq!(photos, vec!["/f/new.jpg", "/f/old.jpg"]);
+
+        // A new model finds the named face slightly moved and loses the other
+        save_faces(&conn, "v2", &[(old_id, vec![region(0.11)])]).unwrap();
+        let faces = get_faces(&conn, "/f/old.jpg").unwrap();
+        assert_eq!(faces.len(), 1);
+        assert_eq!((faces[0].x, faces[0].person_name.as_deref()), (0.1, Some("Ada")));
+    }
+
+    #[test]
+    fn test_auto_tags_are_marked_filterable_and_removable() {
+        let conn = setup_db();
+        for name in ["dog.jpg", "beach.jpg", "clip.mov"] {
+            let mut photo = test_photo(&format!("/a/{}", name), name);
+            photo.file_format = name.rsplit('.').next().map(|ext| ext.replace("jpg", "jpeg"));
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        // On a drive that's unplugged: left until it's back
+        insert_photo(&conn, &test_photo("/a/away.jpg", "away.jpg"), "scan").unwrap();
+        conn.execute("UPDATE photos SET is_offline = 1 WHERE path = '/a/away.jpg'", []).unwrap();
+        let pending = get_photos_to_auto_tag(&conn, "v1").unwrap();
+        assert_eq!(pending.iter().map(|p| p.1.as_str()).collect::<Vec<_>>(), vec!["/a/dog.jpg", "/a/beach.jpg"]);
+        let (dog_id, beach_id) = (pending[0].0, pending[1].0);
+
+        // The user tagged the beach photo "beach" already
+        let beach = create_tag(&conn, "beach", "#00f").unwrap();
+        add_tags_to_photos(&conn, &[beach], &["/a/beach.jpg".to_string()]).unwrap();
+        save_auto_tags(&conn, "v1", "#888", &[
+            (dog_id, vec![("dog".to_string(), 0.9)]),
+            (beach_id, vec![("beach".to_string(), 0.8), ("sand".to_string(), 0.4)]),
+        ])
+        .unwrap();
+        assert!(get_photos_to_auto_tag(&conn, "v1").unwrap().is_empty());
+        assert_eq!(get_photos_to_auto_tag(&conn, "v2").unwrap().len(), 2);
+        assert_eq!(count_auto_tagged(&conn, "v1").unwrap(), 2);
+
+        let tags: Vec<(String, bool)> =
+            get_tags_for_photo(&conn, "/a/beach.jpg").unwrap().into_iter().map(|t| (t.name, t.auto)).collect();
+        assert_eq!(tags, vec![("beach".to_string(), false), ("sand".to_string(), true)]);
+
+        let names = |query: PhotoQuery| -> Vec<String> {
+            query_photos(&conn, &query, 0, None).unwrap().into_iter().map(|p| p.name).collect()
+        };
+        assert_eq!(names(PhotoQuery { tag_source: Some(TagSource::Manual), ..Default::default() }), vec!["beach.jpg"]);
+        assert_eq!(names(PhotoQuery { tag_ids: vec![beach], tag_source: Some(TagSource::Auto), ..Default::default() }).len(), 0);
+        assert_eq!(names(PhotoQuery { tag_source: Some(TagSource::Auto), ..Default::default() }).len(), 2);
+
+        // Tagging by hand takes over an auto tag
+        let dog = create_tag(&conn, "dog", "#0f0").unwrap();
+        add_tags_to_photos(&conn, &[dog], &["/a/dog.jpg".to_string()]).unwrap();
+        assert_eq!(remove_auto_tags(&conn, None).unwrap(), 1);
+        assert_eq!(get_tags_for_photo(&conn, "/a/dog.jpg").unwrap().len(), 1);
+        assert_eq!(get_tags_for_photo(&conn, "/a/beach.jpg").unwrap().len(), 1);
+        // "sand" only ever had the tagger's photo; tags the user has on photos stay
+        let mut left: Vec<String> = get_all_tags(&conn).unwrap().into_iter().map(|t| t.name).collect();
+        left.sort();
+        assert_eq!(left, vec!["beach", "dog"]);
+    }
+
+    #[test]
+    fn test_trip_albums_and_dismissed_suggestions() {
+        let conn = setup_db();
+        for (name, date) in [("before.jpg", 50), ("first.jpg", 100), ("last.jpg", 200), ("after.jpg", 300)] {
+            let mut photo = test_photo(&format!("/t/{}", name), name);
+            photo.date_taken = date;
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        assert_eq!(get_trip_photos(&conn).unwrap().len(), 4);
+
+        let album_id = create_album(&conn, "Trip").unwrap();
+        assert_eq!(add_photos_taken_between_to_album(&conn, album_id, 100, 200).unwrap(), 2);
+        assert_eq!(get_album_photos(&conn, album_id).unwrap().len(), 2);
+
+        assert!(get_dismissed_suggestions(&conn).unwrap().is_empty());
+        dismiss_suggestion(&conn, "trip-1-2").unwrap();
+        dismiss_suggestion(&conn, "trip-1-2").unwrap();
+        assert!(get_dismissed_suggestions(&conn).unwrap().contains("trip-1-2"));
+    }
+
+    #[test]
+    fn test_gpx_locations_survive_rescans() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/g/walk.jpg", "walk.jpg"), "scan").unwrap();
+        let locations = vec![
+            ("/g/walk.jpg".to_string(), 46.5, 7.9, Some("Grindelwald, Bern".to_string())),
+            ("/g/gone.jpg".to_string(), 1.0, 1.0, None),
+        ];
+        assert_eq!(set_photo_locations_batch(&conn, &locations, "gpx").unwrap(), 1);
+        let pending = get_photos_to_geocode(&conn).unwrap();
+        assert_eq!(pending.len(), 1);
+        let place = Place {
+            city: "Grindelwald".to_string(),
+            region: "Bern".to_string(),
+            country: "Switzerland".to_string(),
+        };
+        set_photo_places_batch(&conn, &[(pending[0].0, place)]).unwrap();
+
+        // The file still has no GPS
+        insert_photo(&conn, &test_photo("/g/walk.jpg", "walk.jpg"), "scan").unwrap();
+        let photo = get_photo_details(&conn, "/g/walk.jpg").unwrap().unwrap().photo;
+        assert_eq!((photo.latitude, photo.longitude), (Some(46.5), Some(7.9)));
+        assert_eq!(photo.location_name.as_deref(), Some("Grindelwald, Bern"));
+        assert!(get_photos_to_geocode(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_map_clusters_bucket_by_cell_and_cross_the_antimeridian() {
+        let conn = setup_db();
+        for (name, date, lat, lon) in [
+            ("paris1.jpg", 100, 48.85, 2.35),
+            ("paris2.jpg", 300, 48.86, 2.34),
+            ("london.jpg", 200, 51.50, -0.12),
+            ("fiji.jpg", 400, -17.7, 178.0),
+            ("samoa.jpg", 500, -13.8, -171.7),
+        ] {
+            let mut photo = test_photo(&format!("/m/{}", name), name);
+            photo.date_taken = date;
+            photo.latitude = Some(lat);
+            photo.longitude = Some(lon);
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let europe = ((40.0, 60.0), (-10.0, 10.0));
+
+        // Zoom 4: 5.6° cells hold Paris together, London apart
+        let clusters = get_map_clusters(&conn, europe.0, europe.1, 4, 100).unwrap();
+        assert_eq!(clusters.len(), 2);
+        assert_eq!((clusters[0].count, clusters[0].photo_path.as_str()), (2, "/m/paris2.jpg"));
+        assert!((clusters[0].latitude - 48.855).abs() < 1e-9);
+        // Coarser cells never lose photos
+        let world = get_map_clusters(&conn, europe.0, europe.1, 0, 100).unwrap();
+        assert_eq!(world.iter().map(|c| c.count).sum::<i64>(), 3);
+
+        let close = get_map_clusters(&conn, europe.0, europe.1, MAP_INDIVIDUAL_ZOOM, 100).unwrap();
+        assert_eq!(close.len(), 3);
+        assert!(close.iter().all(|c| c.count == 1));
+        assert_eq!(close[0].photo_path, "/m/paris2.jpg");
+
+        // From 170°E across to 170°W
+        let pacific = get_map_clusters(&conn, (-30.0, 0.0), (170.0, -170.0), 2, 100).unwrap();
+        let mut paths: Vec<&str> = pacific.iter().map(|c| c.photo_path.as_str()).collect();
+        paths.sort();
+        assert_eq!(paths, vec!["/m/fiji.jpg", "/m/samoa.jpg"]);
+    }
+
+    #[test]
+    fn test_library_summary_reports_trashed_and_hidden_apart() {
+        let conn = setup_db();
+        assert_eq!(get_library_summary(&conn).unwrap(), LibrarySummary::default());
+
+        for (name, date) in [("a.jpg", 100), ("b.jpg", 300), ("clip.MOV", 200), ("gone.jpg", 50), ("secret.jpg", 10)] {
+            let mut photo = test_photo(&format!("/s/{}", name), name);
+            photo.date_taken = date;
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let mut undated = test_photo("/s/undated.jpg", "undated.jpg");
+        undated.date_source = Some("unknown".to_string());
+        insert_photo(&conn, &undated, "scan").unwrap();
+        update_photo_file_size(&conn, "/s/a.jpg", 1000).unwrap();
+        update_photo_file_size(&conn, "/s/clip.MOV", 3000).unwrap();
+        update_photo_file_size(&conn, "/s/gone.jpg", 200).unwrap();
+        update_photo_file_size(&conn, "/s/secret.jpg", 5000).unwrap();
+        set_photo_favorite(&conn, "/s/b.jpg", true).unwrap();
+        set_photos_hidden(&conn, &["/s/secret.jpg".to_string()], true).unwrap();
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/s/gone.jpg'", []).unwrap();
+        create_album(&conn, "Trip").unwrap();
+        let labelled = ["/s/a.jpg", "/s/b.jpg", "/s/secret.jpg"].map(String::from);
+        set_photos_color_label(&conn, &labelled, ColorLabel::Red).unwrap();
+
+        let summary = get_library_summary(&conn).unwrap();
+        assert_eq!(summary, LibrarySummary {
+            photo_count: 3,
+            video_count: 1,
+            favorite_count: 1,
+            album_count: 1,
+            earliest_date: Some(100),
+            latest_date: Some(300),
+            undated_count: 1,
+            total_size_bytes: 4000,
+            video_size_bytes: 3000,
+            trashed_count: 1,
+            trashed_size_bytes: 200,
+            hidden_count: 1,
+            color_labels: ColorLabelCounts { red: 2, ..Default::default() },
+        });
+    }
+
+    #[test]
+    fn test_storage_analytics_splits_media_periods_and_trash() {
+        let conn = setup_db();
+        // 2024-03-10, 2024-03-11, 2024-03-12 and 2023-07-01 UTC
+        for (name, date, size) in [
+            ("a.jpg", 1710028800, 1000),
+            ("copy.jpg", 1710115200, 1000),
+            ("clip.MOV", 1710201600, 3000),
+            ("shot.png", 1688169600, 300),
+            ("gone.jpg", 1710028800, 200),
+        ] {
+            let mut photo = test_photo(&format!("/s/{}", name), name);
+            photo.date_taken = date;
+            photo.file_size = Some(size);
+            photo.content_hash = Some(if matches!(name, "a.jpg" | "copy.jpg") { "same" } else { name }.to_string());
+            insert_photo(&conn, &photo, "scan").unwrap();
+        }
+        let mut undated = test_photo("/s/undated.jpg", "undated.jpg");
+        undated.date_source = Some("unknown".to_string());
+        undated.file_size = Some(50);
+        undated.content_hash = None;
+        insert_photo(&conn, &undated, "scan").unwrap();
+        conn.execute("UPDATE photos SET is_screenshot = 1 WHERE path = '/s/shot.png'", []).unwrap();
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/s/gone.jpg'", []).unwrap();
+
+        let analytics = get_storage_analytics(&conn, Some(0), 2).unwrap();
+        assert_eq!(analytics.total_size_bytes, 5350);
+        assert_eq!((analytics.total_photos, analytics.photos_size), (3, 2050));
+        assert_eq!((analytics.total_videos, analytics.videos_size), (1, 3000));
+        assert_eq!((analytics.total_screenshots, analytics.screenshots_size), (1, 300));
+        assert_eq!(analytics.duplicate_space_bytes, 1000);
+        assert_eq!((analytics.trashed_count, analytics.trashed_size_bytes), (1, 200));
+        assert_eq!(analytics.size_by_month, vec![
+            MonthSize { month: "2024-03".to_string(), size: 5000, count: 3 },
+            MonthSize { month: "2023-07".to_string(), size: 300, count: 1 },
+            MonthSize { month: UNKNOWN_PERIOD.to_string(), size: 50, count: 1 },
+        ]);
+        let years: Vec<_> = analytics.size_by_year.iter().map(|y| (y.year.as_str(), y.size)).collect();
+        assert_eq!(years, vec![("2024", 5000), ("2023", 300), (UNKNOWN_PERIOD, 50)]);
+        let largest: Vec<_> = analytics.top_largest_files.iter().map(|f| f.name.as_str()).collect();
+        assert_eq!(largest, vec!["clip.MOV", "a.jpg"]);
+    }
+
+    #[test]
+    fn test_merge_duplicates_and_undo() {
+        let conn = setup_db();
+        for name in ["keep.jpg", "copy.jpg", "other.jpg"] {
+            insert_photo(&conn, &test_photo(&format!("/lib/{}", name), name), "scan").unwrap();
+        }
+        let shared = create_album(&conn, "Shared").unwrap();
+        let trip = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, shared, "/lib/keep.jpg").unwrap();
+        add_photo_to_album(&conn, shared, "/lib/copy.jpg").unwrap();
+        add_photo_to_album(&conn, trip, "/lib/copy.jpg").unwrap();
+        set_album_cover(&conn, trip, "/lib/copy.jpg").unwrap();
+        let tag = create_tag(&conn, "beach", "#00ff00").unwrap();
+        add_tags_to_photos(&conn, &[tag], &["/lib/copy.jpg".to_string()]).unwrap();
+        set_photo_favorite(&conn, "/lib/copy.jpg", true).unwrap();
+
+        // The keeper listed among the discards is never merged into itself
+        let group = vec!["/lib/keep.jpg".to_string(), "/lib/copy.jpg".to_string()];
+        let id = merge_duplicates(&conn, "/lib/keep.jpg", &group).unwrap().unwrap();
+
+        let details = get_duplicate_member_details(&conn, &group).unwrap();
+        assert_eq!(details["/lib/keep.jpg"].1, vec!["Shared", "Trip"]);
+        assert_eq!(get_tags_for_photo(&conn, "/lib/keep.jpg").unwrap()[0].id, tag);
+        let photo = |path: &str| get_all_photos(&conn).unwrap().into_iter().find(|p| p.path == path).unwrap();
+        assert!(photo("/lib/keep.jpg").is_favorite);
+        let cover = |album: i64| get_albums(&conn).unwrap().into_iter().find(|a| a.id == album).unwrap().cover_photo_path;
+        assert_eq!(cover(trip).as_deref(), Some("/lib/keep.jpg"));
+        // The discarded copy keeps its own memberships for a restore
+        assert_eq!(details["/lib/copy.jpg"].1, vec!["Shared", "Trip"]);
+
+        trash_photo(&conn, "/lib/copy.jpg", Some("/lib/.trash/copy.jpg")).unwrap();
+        assert_eq!(get_discarded_duplicates(&conn, id).unwrap(), Some(vec![Some("/lib/copy.jpg".to_string())]));
+
+        assert!(undo_duplicate_merge(&conn, id).unwrap());
+        let details = get_duplicate_member_details(&conn, &group).unwrap();
+        // Shared was the keeper's own membership and stays
+        assert_eq!(details["/lib/keep.jpg"].1, vec!["Shared"]);
+        assert!(get_tags_for_photo(&conn, "/lib/keep.jpg").unwrap().is_empty());
+        assert!(!photo("/lib/keep.jpg").is_favorite);
+        assert_eq!(cover(trip).as_deref(), Some("/lib/copy.jpg"));
+        assert_eq!(get_discarded_duplicates(&conn, id).unwrap(), None);
+        assert!(!undo_duplicate_merge(&conn, id).unwrap());
+
+        // A favorite the merge didn't set survives the undo
+        let id = merge_duplicates(&conn, "/lib/keep.jpg", &["/lib/other.jpg".to_string()]).unwrap().unwrap();
+        set_photo_favorite(&conn, "/lib/keep.jpg", true).unwrap();
+        assert!(undo_duplicate_merge(&conn, id).unwrap());
+        assert!(photo("/lib/keep.jpg").is_favorite);
+
+        // Trashed or unknown copies leave nothing to merge
+        assert_eq!(merge_duplicates(&conn, "/lib/keep.jpg", &["/lib/copy.jpg".to_string()]).unwrap(), None);
+        assert_eq!(merge_duplicates(&conn, "/lib/nope.jpg", &["/lib/other.jpg".to_string()]).unwrap(), None);
+    }
+
+    #[test]
+    fn test_relocation_progress_survives_a_rerun() {
+        let conn = setup_db();
+        assert!(get_relocation(&conn).is_none());
+        let relocation = Relocation { from: PathBuf::from("/old"), to: PathBuf::from("/new") };
+        let file = |name: &str| RelocationFile {
+            source: format!("/old/2023/05/{}", name),
+            dest: format!("/new/2023/05/{}", name),
+            size: 10,
+        };
+
+        track_relocation(&conn, &relocation, &[file("a.jpg"), file("b.jpg")]).unwrap();
+        mark_relocation_copied(&conn, "/old/2023/05/a.jpg").unwrap();
+        // A rerun walks the source again; a.jpg stays copied, c.jpg is new
+        track_relocation(&conn, &relocation, &[file("a.jpg"), file("b.jpg"), file("c.jpg")]).unwrap();
+
+        assert_eq!(get_relocation(&conn), Some(relocation));
+        let pending: Vec<String> = get_pending_relocation_files(&conn).unwrap().into_iter().map(|f| f.source).collect();
+        assert_eq!(pending, vec!["/old/2023/05/b.jpg", "/old/2023/05/c.jpg"]);
+        assert_eq!(get_copied_relocation_sources(&conn).unwrap(), vec!["/old/2023/05/a.jpg"]);
+
+        forget_relocation_file(&conn, "/old/2023/05/c.jpg").unwrap();
+        assert_eq!(get_pending_relocation_files(&conn).unwrap().len(), 1);
+
+        finish_relocation(&conn).unwrap();
+        assert!(get_relocation(&conn).is_none());
+        assert!(get_pending_relocation_files(&conn).unwrap().is_empty());
+        assert!(get_copied_relocation_sources(&conn).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_switch_library_root_rewrites_stored_paths() {
+        let conn = setup_db();
+        let base = std::env::temp_dir().join(format!("terra-relocate-{}", std::process::id()));
+        let old_root = base.join("old");
+        let new_root = base.join("new");
+        std::fs::create_dir_all(&old_root).unwrap();
+        std::fs::create_dir_all(&new_root).unwrap();
+        set_library_path(&conn, &old_root).unwrap();
+
+        let in_old = |rel: &str| old_root.join(rel).to_string_lossy().to_string();
+        let in_new = |rel: &str| new_root.join(rel).to_string_lossy().to_string();
+        insert_photo(&conn, &test_photo(&in_old("2023/05/a.jpg"), "a.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo(&in_old("2023/05/b.jpg"), "b.jpg"), "upload").unwrap();
+        insert_photo(&conn, &test_photo("/elsewhere/c.jpg", "c.jpg"), "scan").unwrap();
+        trash_photo(&conn, &in_old("2023/05/b.jpg"), Some(&in_old(".trash/2023/05/b.jpg"))).unwrap();
+        let album = create_album(&conn, "Trip").unwrap();
+        add_photo_to_album(&conn, album, &in_old("2023/05/a.jpg")).unwrap();
+        set_album_cover(&conn, album, &in_old("2023/05/a.jpg")).unwrap();
+
+        let relocation = Relocation { from: old_root.clone(), to: new_root.clone() };
+        assert_eq!(switch_library_root(&conn, &relocation).unwrap(), 2);
+
+        assert_eq!(get_library_path(&conn).unwrap(), new_root);
+        assert!(!get_previous_library_paths(&conn).contains(&old_root));
+        let mut paths: Vec<String> = conn
+            .prepare("SELECT path FROM photos ORDER BY path")
+            .unwrap()
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<SqlResult<_>>()
+            .unwrap();
+        paths.sort();
+        let mut expected = vec![in_new("2023/05/a.jpg"), in_new("2023/05/b.jpg"), "/elsewhere/c.jpg".to_string()];
+        expected.sort();
+        assert_eq!(paths, expected);
+        assert_eq!(get_trashed_photos(&conn).unwrap()[0].1.as_deref(), Some(in_new(".trash/2023/05/b.jpg").as_str()));
+        // Membership is by id and the cover follows the path
+        assert_eq!(get_album_photos(&conn, album).unwrap()[0].path, in_new("2023/05/a.jpg"));
+        assert_eq!(get_albums(&conn).unwrap()[0].cover_photo_path, Some(in_new("2023/05/a.jpg")));
+        let _ = std::fs::remove_dir_all(&base);
+    }
+
+    #[test]
+    fn test_watch_folders_add_and_remove() {
+        let conn = setup_db();
+        assert!(get_watch_folders(&conn).is_empty());
+
+        let a = std::path::Path::new("/Volumes/Card/DCIM");
+        let b = std::path::Path::new("/Users/x/Downloads");
+        assert!(add_watch_folder(&conn, a).unwrap());
+        assert!(add_watch_folder(&conn, b).unwrap());
+        assert!(!add_watch_folder(&conn, a).unwrap());
+        assert_eq!(get_watch_folders(&conn), vec![a.to_path_buf(), b.to_path_buf()]);
+
+        assert!(remove_watch_folder(&conn, a).unwrap());
+        assert!(!remove_watch_folder(&conn, a).unwrap());
+        assert_eq!(get_watch_folders(&conn), vec![b.to_path_buf()]);
+    }
+
+    #[test]
+    fn test_get_setting_or_parses_json_values() {
+        let conn = setup_db();
+        set_setting(&conn, "trash_retention_days", "14").unwrap();
+        set_setting(&conn, "watch_enabled", "true").unwrap();
+        set_setting(&conn, "theme", "\"dark\"").unwrap();
+        set_setting(&conn, "broken", "not a number").unwrap();
+
+        assert_eq!(get_setting_or(&conn, "trash_retention_days", 30i64), 14);
+        assert!(get_setting_or(&conn, "watch_enabled", false));
+        assert_eq!(get_setting_or(&conn, "theme", String::new()), "dark");
+        assert_eq!(get_setting_or(&conn, "broken", 7u32), 7);
+        assert_eq!(get_setting_or(&conn, "missing", 30i64), 30);
+    }
+
+    #[test]
+    fn test_decode_setting_handles_json_and_legacy_values() {
+        assert_eq!(decode_setting("true"), serde_json::json!(true));
+        assert_eq!(decode_setting("[\"a\",\"b\"]"), serde_json::json!(["a", "b"]));
+        assert_eq!(decode_setting("\"/photos\""), serde_json::json!("/photos"));
+        // Library paths used to be stored unquoted
+        assert_eq!(decode_setting("/Users/me/Pictures"), serde_json::json!("/Users/me/Pictures"));
+        assert_eq!(setting_text("/Users/me/Pictures"), "/Users/me/Pictures");
+        assert_eq!(setting_text("\"/Users/me/Pictures\""), "/Users/me/Pictures");
+    }
+
+    #[test]
+    fn test_get_all_settings_sorted_by_key() {
+        let conn = setup_db();
+        set_setting(&conn, "b", "2").unwrap();
+        set_setting(&conn, "a", "1").unwrap();
+        set_setting(&conn, "a", "3").unwrap();
+
+        assert_eq!(
+            get_all_settings(&conn).unwrap(),
+            vec![("a".to_string(), "3".to_string()), ("b".to_string(), "2".to_string())]
+        );
+    }
+
+    #[test]
+    fn test_photo_records_carry_album_and_tag_names() {
+        let conn = setup_db();
+        insert_photo(&conn, &test_photo("/p/a.jpg", "a.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/b.jpg", "b.jpg"), "scan").unwrap();
+        insert_photo(&conn, &test_photo("/p/gone.jpg", "gone.jpg"), "scan").unwrap();
+        let ids = get_photo_ids(&conn, &["/p/a.jpg".to_string()]).unwrap();
+        let trips = create_album(&conn, "Trips").unwrap();
+        let family = create_album(&conn, "family").unwrap();
+        add_photos_to_album_by_id(&conn, trips, &ids).unwrap();
+        add_photos_to_album_by_id(&conn, family, &ids).unwrap();
+        let beach = create_tag(&conn, "beach", "#00f").unwrap();
+        add_tags_to_photos(&conn, &[beach], &["/p/a.jpg".to_string()]).unwrap();
+        set_photos_favorite(&conn, &["/p/b.jpg".to_string()], true).unwrap();
+        conn.execute("UPDATE photos SET deleted_at = 1 WHERE path = '/p/gone.jpg'", []).unwrap();
+
+        let records = get_photo_records(&conn, 0, 10).unwrap();
+        assert_eq!(records.len(), 2);
+        assert_eq!(records[0].path, "/p/a.jpg");
+        assert_eq!(records[0].albums, vec!["family", "Trips"]);
+        assert_eq!(records[0].tags, vec!["beach"]);
+        assert!(!records[0].is_favorite);
+        assert!(records[1].is_favorite && records[1].albums.is_empty());
+
+        // Paging on from the last id read
+        let first = get_photo_records(&conn, 0, 1).unwrap();
+        assert_eq!(first.len(), 1);
+        let rest = get_photo_records(&conn, first[0].id, 1).unwrap();
+        assert_eq!(rest[0].path, "/p/b.jpg");
+        assert!(get_photo_records(&conn, rest[0].id, 1).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_import_matches_by_hash_then_path_and_respects_mode() {
+        let conn = setup_db();
+        let mut moved = test_photo("/new/a.jpg", "a.jpg");
+        moved.content_hash = Some("hash-a".to_string());
+        moved.date_confident = Some(true);
+        insert_photo(&conn, &moved, "scan").unwrap();
+        let mut rated = test_photo("/p/b.jpg", "b.jpg");
+        rated.content_hash = None;
+        insert_photo(&conn, &rated, "scan").unwrap();
+        set_photos_rating(&conn, &["/p/b.jpg".to_string()], 3).unwrap();
+        let trips = create_album(&conn, "Trips").unwrap();
+        let ids = get_photo_ids(&conn, &["/p/b.jpg".to_string()]).unwrap();
+        add_photos_to_album_by_id(&conn, trips, &ids).unwrap();
+
+        let records = vec![
+            PhotoRecord {
+                path: "/old/a.jpg".to_string(),
+                content_hash: Some("hash-a".to_string()),
+                is_favorite: true,
+                date_taken: 1_000,
+                date_source: Some(media::DATE_SOURCE_MANUAL.to_string()),
+                tags: vec!["beach".to_string()],
+                albums: vec!["Family".to_string()],
+                ..Default::default()
+            },
+            PhotoRecord { path: "/p/b.jpg".to_string(), rating: 5, albums: vec!["Family".to_string()], ..Default::default() },
+            PhotoRecord { path: "/p/missing.jpg".to_string(), ..Default::default() },
+        ];
+
+        let photo = |path: &str| get_photos_by_paths(&conn, &[path.to_string()]).unwrap().remove(0);
+        let merged = import_photo_records(&conn, &records, ImportMode::Merge).unwrap();
+        assert_eq!((merged.matched_by_hash, merged.matched_by_path), (1, 1));
+        assert_eq!(merged.unmatched, vec!["/p/missing.jpg"]);
+        assert_eq!((merged.favorites, merged.ratings, merged.tags_added), (1, 0, 1));
+        assert_eq!((merged.albums_created, merged.album_photos_added, merged.album_photos_removed), (1, 2, 0));
+        let a = photo("/new/a.jpg");
+        assert!(a.is_favorite);
+        // The scanned date was confident, so merging leaves it
+        assert_eq!(a.date_taken, moved.date_taken);
+        assert_eq!(photo("/p/b.jpg").rating, 3);
+
+        let overwritten = import_photo_records(&conn, &records, ImportMode::Overwrite).unwrap();
+        assert_eq!((overwritten.ratings, overwritten.dates, overwritten.album_photos_removed), (1, 1, 1));
+        assert_eq!(photo("/p/b.jpg").rating, 5);
+        assert_eq!(photo("/new/a.jpg").date_taken, 1_000);
+        assert_eq!(get_album_photos(&conn, trips).unwrap().len(), 0);
+    }
+
+    #[test]
+    fn test_import_only_dates_undated_photos_from_dates_not_set_by_hand() {
+        let conn = setup_db();
+        let mut guessed = test_photo("/p/guessed.jpg", "guessed.jpg");
+        guessed.date_source = Some(media::DATE_SOURCE_MTIME.to_string());
+        guessed.date_confident = Some(false);
+        insert_photo(&conn, &guessed, "scan").unwrap();
+        let mut undated = test_photo("/p/undated.jpg", "undated.jpg");
+        undated.date_taken = 0;
+        undated.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        undated.date_confident = Some(false);
+        insert_photo(&conn, &undated, "scan").unwrap();
+
+        // As digiKam's creation dates arrive: maybe EXIF, maybe only the file's mtime
+        let records: Vec<PhotoRecord> = ["/p/guessed.jpg", "/p/undated.jpg"]
+            .iter()
+            .map(|path| PhotoRecord {
+                path: path.to_string(),
+                content_hash: None,
+                date_taken: 5_000,
+                date_source: Some(media::DATE_SOURCE_SIDECAR.to_string()),
+                ..Default::default()
+            })
+            .collect();
+        let summary = import_photo_records(&conn, &records, ImportMode::Overwrite).unwrap();
+        assert_eq!(summary.dates, 1);
+
+        let photos = get_photos_by_paths(&conn, &["/p/guessed.jpg".to_string(), "/p/undated.jpg".to_string()]).unwrap();
+        assert_eq!(photos[0].date_taken, guessed.date_taken);
+        assert_eq!(photos[1].date_taken, 5_000);
+        assert_eq!(photos[1].date_source.as_deref(), Some(media::DATE_SOURCE_SIDECAR));
+        assert_eq!(photos[1].date_confident, Some(false));
+    }
 }
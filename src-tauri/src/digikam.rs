@@ -0,0 +1,291 @@
+//! Reading a digiKam database (`digikam4.db`): where each image lives, its
+//! rating, creation date and tags. Hierarchical tags are flattened to
+//! "Parent/Child" names.
+//!
+//! No access to Terra's database; `import_digikam` in `lib.rs` matches the
+//! images to library photos and imports their metadata. digiKam stores
+//! folders as album roots (a volume plus a path on it), resolved here to
+//! real paths where the volume can be found.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use chrono::NaiveDateTime;
+use rusqlite::{Connection, Result as SqlResult};
+
+use crate::sqlite_snapshot::Snapshot;
+
+/// Root of digiKam's own bookkeeping tags (color labels, pick labels, ...)
+const INTERNAL_TAGS_ROOT: &str = "_Digikam_Internal_Tags_";
+/// Images.status of an image that is neither hidden nor in digiKam's trash
+const VISIBLE_STATUS: i64 = 1;
+
+/// One image in the digiKam database.
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct DigikamImage {
+    /// The file on this machine; None when its album root's volume isn't
+    /// mounted or can't be found
+    pub path: Option<PathBuf>,
+    /// Album path and file name as digiKam shows them, for reporting
+    pub display_path: String,
+    /// 1 to 5 stars; None when unrated
+    pub rating: Option<u8>,
+    /// Capture time on the camera's clock
+    pub created: Option<NaiveDateTime>,
+    pub tags: Vec<String>,
+}
+
+/// Read a digiKam database. It's copied aside first: digiKam may have it
+/// open, and this must never write to it.
+pub(crate) fn read_library(database: &Path) -> Result<Vec<DigikamImage>, String> {
+    if !database.is_file() {
+        return Err(format!("{} is not a digiKam database", database.display()));
+    }
+    let snapshot = Snapshot::take(database).map_err(|e| format!("Failed to copy the digiKam database: {}", e))?;
+    read_database(snapshot.conn(), find_root).map_err(|e| format!("Failed to read the digiKam database: {}", e))
+}
+
+/// Read visible images from an open digiKam database. `locate_root` turns
+/// an album root's identifier and specific path into a folder.
+pub(crate) fn read_database(
+    conn: &Connection,
+    locate_root: impl Fn(&str, &str) -> Option<PathBuf>,
+) -> SqlResult<Vec<DigikamImage>> {
+    let mut roots: HashMap<i64, Option<PathBuf>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT id, identifier, specificPath FROM AlbumRoots")?;
+        let rows = stmt.query_map([], |row| {
+            let identifier: Option<String> = row.get(1)?;
+            let specific_path: Option<String> = row.get(2)?;
+            Ok((row.get::<_, i64>(0)?, identifier.unwrap_or_default(), specific_path.unwrap_or_default()))
+        })?;
+        for row in rows {
+            let (id, identifier, specific_path) = row?;
+            roots.insert(id, locate_root(&identifier, &specific_path));
+        }
+    }
+
+    let tags = read_tag_names(conn)?;
+    let mut image_tags: HashMap<i64, Vec<String>> = HashMap::new();
+    {
+        let mut stmt = conn.prepare("SELECT imageid, tagid FROM ImageTags ORDER BY imageid, tagid")?;
+        let rows = stmt.query_map([], |row| Ok((row.get::<_, i64>(0)?, row.get::<_, i64>(1)?)))?;
+        for row in rows {
+            let (image, tag) = row?;
+            if let Some(name) = tags.get(&tag) {
+                image_tags.entry(image).or_default().push(name.clone());
+            }
+        }
+    }
+
+    let mut stmt = conn.prepare(
+        "SELECT i.id, i.name, a.albumRoot, a.relativePath, info.rating, info.creationDate
+         FROM Images i
+         JOIN Albums a ON a.id = i.album
+         LEFT JOIN ImageInformation info ON info.imageid = i.id
+         WHERE i.status = ?1
+         ORDER BY i.id",
+    )?;
+    let images = stmt
+        .query_map([VISIBLE_STATUS], |row| {
+            let id: i64 = row.get(0)?;
+            let name: String = row.get(1)?;
+            let root: i64 = row.get(2)?;
+            let relative: String = row.get::<_, Option<String>>(3)?.unwrap_or_default();
+            let relative = relative.trim_matches('/');
+            let rating: Option<i64> = row.get(4)?;
+            let created: Option<String> = row.get(5)?;
+            let path = roots.get(&root).cloned().flatten().map(|root| {
+                let folder = relative.split('/').filter(|part| !part.is_empty()).fold(root, |dir, part| dir.join(part));
+                folder.join(&name)
+            });
+            Ok(DigikamImage {
+                path,
+                display_path: if relative.is_empty() { name.clone() } else { format!("{}/{}", relative, name) },
+                rating: rating.filter(|r| (1..=5).contains(r)).map(|r| r as u8),
+                created: created.as_deref().and_then(parse_date),
+                tags: image_tags.remove(&id).unwrap_or_default(),
+            })
+        })?
+        .collect::<SqlResult<Vec<_>>>()?;
+    Ok(images)
+}
+
+/// Full "Parent/Child" name of every tag by id, leaving out digiKam's
+/// internal tags.
+fn read_tag_names(conn: &Connection) -> SqlResult<HashMap<i64, String>> {
+    let mut parents: HashMap<i64, (i64, String)> = HashMap::new();
+    let mut stmt = conn.prepare("SELECT id, pid, name FROM Tags")?;
+    let rows = stmt.query_map([], |row| {
+        Ok((row.get::<_, i64>(0)?, row.get::<_, Option<i64>>(1)?.unwrap_or(0), row.get::<_, String>(2)?))
+    })?;
+    for row in rows {
+        let (id, pid, name) = row?;
+        parents.insert(id, (pid, name));
+    }
+
+    let mut names = HashMap::new();
+    for &id in parents.keys() {
+        let mut parts = Vec::new();
+        let mut current = id;
+        // Bounded by the tag count, in case the tree has a loop
+        while let Some((pid, name)) = parents.get(&current).filter(|_| parts.len() <= parents.len()) {
+            parts.push(name.trim());
+            current = *pid;
+        }
+        parts.reverse();
+        if parts.first() == Some(&INTERNAL_TAGS_ROOT) || parts.iter().any(|p| p.is_empty()) {
+            continue;
+        }
+        names.insert(id, parts.join("/"));
+    }
+    Ok(names)
+}
+
+/// digiKam's ISO creation dates, with or without fractional seconds.
+fn parse_date(text: &str) -> Option<NaiveDateTime> {
+    let text = text.trim().replace(' ', "T");
+    NaiveDateTime::parse_from_str(&text, "%Y-%m-%dT%H:%M:%S%.f").ok()
+}
+
+/// Decode the %XX escapes in an album root identifier's query.
+fn percent_decode(text: &str) -> String {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        let hex = bytes.get(i + 1..i + 3).and_then(|h| std::str::from_utf8(h).ok());
+        match (bytes[i], hex.and_then(|h| u8::from_str_radix(h, 16).ok())) {
+            (b'%', Some(byte)) => {
+                out.push(byte);
+                i += 3;
+            }
+            (byte, _) => {
+                out.push(byte);
+                i += 1;
+            }
+        }
+    }
+    String::from_utf8_lossy(&out).to_string()
+}
+
+/// The value of `key` in an identifier such as "volumeid:?uuid=1234" or
+/// "volumeid:?path=%2Fhome%2Fme%2FPictures".
+fn identifier_value(identifier: &str, key: &str) -> Option<String> {
+    let (_, query) = identifier.split_once('?')?;
+    query.split('&').find_map(|pair| {
+        let (k, v) = pair.split_once('=')?;
+        (k == key).then(|| percent_decode(v))
+    })
+}
+
+/// `specific_path` under `mount`.
+fn under(mount: &Path, specific_path: &str) -> PathBuf {
+    specific_path.split('/').filter(|part| !part.is_empty()).fold(mount.to_path_buf(), |dir, part| dir.join(part))
+}
+
+/// Where a drive could be mounted, for album roots digiKam knows by volume
+/// UUID: the system disk, then removable and network mounts.
+fn mount_candidates() -> Vec<PathBuf> {
+    let mut candidates = vec![PathBuf::from("/")];
+    let children = |dir: &Path| -> Vec<PathBuf> {
+        fs::read_dir(dir)
+            .map(|entries| entries.flatten().map(|e| e.path()).filter(|p| p.is_dir()).collect())
+            .unwrap_or_default()
+    };
+    for parent in ["/Volumes", "/mnt"] {
+        candidates.extend(children(Path::new(parent)));
+    }
+    // /media/<user>/<drive> and /run/media/<user>/<drive>
+    for parent in ["/media", "/run/media"] {
+        for user in children(Path::new(parent)) {
+            candidates.push(user.clone());
+            candidates.extend(children(&user));
+        }
+    }
+    if cfg!(windows) {
+        candidates.extend((b'C'..=b'Z').map(|drive| PathBuf::from(format!("{}:\\", drive as char))));
+    }
+    candidates
+}
+
+/// The folder an album root points at on this machine, if it can be found.
+fn find_root(identifier: &str, specific_path: &str) -> Option<PathBuf> {
+    if let Some(path) = identifier_value(identifier, "path").or_else(|| identifier_value(identifier, "mountpath")) {
+        return Some(under(Path::new(&path), specific_path));
+    }
+    mount_candidates().into_iter().map(|mount| under(&mount, specific_path)).find(|dir| dir.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The parts of a digiKam 7 digikam4.db that Terra reads
+    fn digikam_db() -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE AlbumRoots (id INTEGER PRIMARY KEY, label TEXT, status INTEGER, type INTEGER,
+                 identifier TEXT, specificPath TEXT);
+             CREATE TABLE Albums (id INTEGER PRIMARY KEY, albumRoot INTEGER, relativePath TEXT);
+             CREATE TABLE Images (id INTEGER PRIMARY KEY, album INTEGER, name TEXT, status INTEGER);
+             CREATE TABLE ImageInformation (imageid INTEGER PRIMARY KEY, rating INTEGER, creationDate DATETIME);
+             CREATE TABLE Tags (id INTEGER PRIMARY KEY, pid INTEGER, name TEXT);
+             CREATE TABLE ImageTags (imageid INTEGER, tagid INTEGER);
+
+             INSERT INTO AlbumRoots VALUES (1, 'Pictures', 0, 1, 'volumeid:?path=%2Fhome%2Fme%2FPictures', '/');
+             INSERT INTO AlbumRoots VALUES (2, 'Backup', 0, 2, 'volumeid:?uuid=ABCD', '/Backup');
+             INSERT INTO Albums VALUES (10, 1, '/');
+             INSERT INTO Albums VALUES (11, 1, '/2019/Hawaii');
+             INSERT INTO Albums VALUES (12, 2, '/');
+             INSERT INTO Images VALUES (100, 11, 'IMG_0001.JPG', 1);
+             INSERT INTO Images VALUES (101, 10, 'scan.png', 1);
+             INSERT INTO Images VALUES (102, 11, 'trashed.jpg', 3);
+             INSERT INTO Images VALUES (103, 12, 'offline.jpg', 1);
+             INSERT INTO ImageInformation VALUES (100, 4, '2019-06-01T14:30:05.000');
+             INSERT INTO ImageInformation VALUES (101, -1, NULL);
+
+             INSERT INTO Tags VALUES (1, 0, 'Places');
+             INSERT INTO Tags VALUES (2, 1, 'Hawaii');
+             INSERT INTO Tags VALUES (3, 0, '_Digikam_Internal_Tags_');
+             INSERT INTO Tags VALUES (4, 3, 'Color Label Red');
+             INSERT INTO Tags VALUES (5, 0, 'Beach');
+             INSERT INTO ImageTags VALUES (100, 2);
+             INSERT INTO ImageTags VALUES (100, 4);
+             INSERT INTO ImageTags VALUES (100, 5);",
+        )
+        .unwrap();
+        conn
+    }
+
+    #[test]
+    fn reads_visible_images_with_flattened_tags() {
+        let images = read_database(&digikam_db(), |identifier, specific| {
+            identifier_value(identifier, "path").map(|path| under(Path::new(&path), specific))
+        })
+        .unwrap();
+        assert_eq!(images.len(), 3);
+
+        let hawaii = &images[0];
+        assert_eq!(hawaii.path, Some(PathBuf::from("/home/me/Pictures/2019/Hawaii/IMG_0001.JPG")));
+        assert_eq!(hawaii.display_path, "2019/Hawaii/IMG_0001.JPG");
+        assert_eq!(hawaii.rating, Some(4));
+        assert_eq!(hawaii.created, parse_date("2019-06-01 14:30:05"));
+        assert_eq!(hawaii.tags, vec!["Places/Hawaii", "Beach"]);
+
+        assert_eq!(images[1].path, Some(PathBuf::from("/home/me/Pictures/scan.png")));
+        assert_eq!((images[1].rating, images[1].created), (None, None));
+        // A volume that isn't mounted
+        assert_eq!(images[2].path, None);
+        assert_eq!(images[2].display_path, "offline.jpg");
+    }
+
+    #[test]
+    fn decodes_album_root_identifiers() {
+        assert_eq!(identifier_value("volumeid:?path=%2Fmedia%2Fa%20b", "path").as_deref(), Some("/media/a b"));
+        assert_eq!(identifier_value("volumeid:?uuid=1234&fileuuid=5", "uuid").as_deref(), Some("1234"));
+        assert_eq!(identifier_value("volumeid:?uuid=1234", "path"), None);
+        assert_eq!(percent_decode("100%"), "100%");
+    }
+}
@@ -0,0 +1,207 @@
+//! Non-destructive edits: a recipe of rotation, flips and crop kept in the
+//! database and applied whenever an edited image is rendered. Originals on
+//! disk are only ever read.
+
+use image::imageops;
+use image::{DynamicImage, Rgba, RgbaImage};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// Slack for crop edges computed in floating point by the frontend.
+const CROP_EPSILON: f32 = 1e-4;
+/// Straightening smaller than this is left out rather than resampled.
+const MIN_ANGLE: f32 = 0.01;
+
+/// What to do to a photo, applied in order: rotate, flip, crop.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq)]
+pub struct PhotoEdits {
+    /// Degrees clockwise. Quarter turns are exact; any angle between
+    /// straightens the photo, zoomed in so no empty corners show
+    #[serde(default)]
+    pub rotation: f32,
+    #[serde(default)]
+    pub flip_horizontal: bool,
+    #[serde(default)]
+    pub flip_vertical: bool,
+    /// Part of the rotated and flipped image to keep
+    #[serde(default)]
+    pub crop: Option<CropRect>,
+}
+
+/// A rectangle as fractions (0-1) of an image's width and height.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq)]
+pub struct CropRect {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl PhotoEdits {
+    /// Why the recipe can't be applied, if it can't.
+    pub fn validate(&self) -> Result<(), String> {
+        if !self.rotation.is_finite() {
+            return Err("Rotation must be a number of degrees".to_string());
+        }
+        if let Some(c) = self.crop {
+            let inside = [c.x, c.y, c.width, c.height].iter().all(|v| v.is_finite())
+                && c.x >= 0.0
+                && c.y >= 0.0
+                && c.width > 0.0
+                && c.height > 0.0
+                && c.x + c.width <= 1.0 + CROP_EPSILON
+                && c.y + c.height <= 1.0 + CROP_EPSILON;
+            if !inside {
+                return Err("The crop must lie within the photo".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// True if applying the recipe would change nothing.
+    pub fn is_identity(&self) -> bool {
+        let full = CropRect { x: 0.0, y: 0.0, width: 1.0, height: 1.0 };
+        self.split_rotation() == (0, 0.0)
+            && !self.flip_horizontal
+            && !self.flip_vertical
+            && self.crop.map_or(true, |c| c == full)
+    }
+
+    /// Short fingerprint of the recipe, for naming cached renders.
+    pub fn fingerprint(&self) -> String {
+        let json = serde_json::to_string(self).unwrap_or_default();
+        hex::encode(&Sha256::digest(json.as_bytes())[..8])
+    }
+
+    /// The smaller side of the crop as a fraction of the image: how far a
+    /// render may shrink the image before editing and still fill its size.
+    pub fn crop_scale(&self) -> f32 {
+        self.crop.map_or(1.0, |c| c.width.min(c.height)).clamp(0.01, 1.0)
+    }
+
+    /// Quarter turns clockwise (0-3) and the straightening left over
+    /// (-45 to 45 degrees).
+    fn split_rotation(&self) -> (u8, f32) {
+        let turns = (self.rotation / 90.0).round();
+        let mut angle = self.rotation - turns * 90.0;
+        if angle.abs() < MIN_ANGLE {
+            angle = 0.0;
+        }
+        (turns.rem_euclid(4.0) as u8, angle)
+    }
+
+    /// `img`, upright as it's displayed, with the recipe applied.
+    pub fn apply(&self, img: DynamicImage) -> DynamicImage {
+        let (turns, angle) = self.split_rotation();
+        let mut img = match turns {
+            1 => img.rotate90(),
+            2 => img.rotate180(),
+            3 => img.rotate270(),
+            _ => img,
+        };
+        if angle != 0.0 {
+            img = straighten(&img, angle);
+        }
+        if self.flip_horizontal {
+            img = img.fliph();
+        }
+        if self.flip_vertical {
+            img = img.flipv();
+        }
+        if let Some(c) = self.crop {
+            let (w, h) = (img.width() as f32, img.height() as f32);
+            let x = ((c.x * w).round() as u32).min(img.width() - 1);
+            let y = ((c.y * h).round() as u32).min(img.height() - 1);
+            let width = ((c.width * w).round() as u32).clamp(1, img.width() - x);
+            let height = ((c.height * h).round() as u32).clamp(1, img.height() - y);
+            img = img.crop_imm(x, y, width, height);
+        }
+        img
+    }
+}
+
+/// `img` turned `angle` degrees clockwise about its centre, zoomed just
+/// enough that the result keeps its size with no empty corners.
+fn straighten(img: &DynamicImage, angle: f32) -> DynamicImage {
+    let src = img.to_rgba8();
+    let (w, h) = src.dimensions();
+    let (wf, hf) = (w as f32, h as f32);
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let zoom = cos.abs() + (wf / hf).max(hf / wf) * sin.abs();
+    let (cx, cy) = (wf / 2.0, hf / 2.0);
+    let out = RgbaImage::from_fn(w, h, |x, y| {
+        // Each output pixel's centre, turned back into the source
+        let dx = (x as f32 + 0.5 - cx) / zoom;
+        let dy = (y as f32 + 0.5 - cy) / zoom;
+        let sx = (cx + dx * cos + dy * sin - 0.5).clamp(0.0, wf - 1.0);
+        let sy = (cy - dx * sin + dy * cos - 0.5).clamp(0.0, hf - 1.0);
+        imageops::interpolate_bilinear(&src, sx, sy).unwrap_or(Rgba([0, 0, 0, 255]))
+    });
+    DynamicImage::ImageRgba8(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgb;
+
+    /// 40x20 black with a red top-left pixel.
+    fn marked() -> DynamicImage {
+        let mut img = image::RgbImage::from_pixel(40, 20, Rgb([0, 0, 0]));
+        img.put_pixel(0, 0, Rgb([255, 0, 0]));
+        DynamicImage::ImageRgb8(img)
+    }
+
+    fn is_red(img: &DynamicImage, x: u32, y: u32) -> bool {
+        img.to_rgb8().get_pixel(x, y) == &Rgb([255, 0, 0])
+    }
+
+    #[test]
+    fn applies_turns_flips_and_crop_in_order() {
+        let turned = PhotoEdits { rotation: 90.0, ..Default::default() }.apply(marked());
+        assert_eq!((turned.width(), turned.height()), (20, 40));
+        assert!(is_red(&turned, 19, 0));
+
+        let flipped = PhotoEdits { rotation: -90.0, flip_horizontal: true, ..Default::default() }.apply(marked());
+        assert_eq!((flipped.width(), flipped.height()), (20, 40));
+        assert!(is_red(&flipped, 19, 39));
+
+        let cropped = PhotoEdits {
+            crop: Some(CropRect { x: 0.0, y: 0.0, width: 0.5, height: 0.5 }),
+            ..Default::default()
+        }
+        .apply(marked());
+        assert_eq!((cropped.width(), cropped.height()), (20, 10));
+        assert!(is_red(&cropped, 0, 0));
+    }
+
+    #[test]
+    fn straightening_keeps_the_size_and_fills_the_corners() {
+        let white = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(60, 40, Rgb([255, 255, 255])));
+        let straightened = PhotoEdits { rotation: 5.0, ..Default::default() }.apply(white);
+        assert_eq!((straightened.width(), straightened.height()), (60, 40));
+        let rgba = straightened.to_rgba8();
+        for (x, y) in [(0, 0), (59, 0), (0, 39), (59, 39)] {
+            assert_eq!(rgba.get_pixel(x, y), &Rgba([255, 255, 255, 255]));
+        }
+    }
+
+    #[test]
+    fn validates_and_fingerprints_recipes() {
+        assert!(PhotoEdits::default().validate().is_ok());
+        assert!(PhotoEdits::default().is_identity());
+        assert!(PhotoEdits { rotation: 360.0, ..Default::default() }.is_identity());
+        assert!(PhotoEdits { rotation: f32::NAN, ..Default::default() }.validate().is_err());
+        let outside = PhotoEdits {
+            crop: Some(CropRect { x: 0.6, y: 0.0, width: 0.5, height: 1.0 }),
+            ..Default::default()
+        };
+        assert!(outside.validate().is_err());
+
+        let straighten = PhotoEdits { rotation: 2.5, ..Default::default() };
+        assert!(!straighten.is_identity());
+        assert_eq!(straighten.fingerprint(), straighten.fingerprint());
+        assert_ne!(straighten.fingerprint(), PhotoEdits::default().fingerprint());
+        assert_eq!(straighten.fingerprint().len(), 16);
+    }
+}
@@ -0,0 +1,312 @@
+//! What folder scans leave alone: hidden folders, caches and app bundles
+//! that hold previews rather than photos, the user's own glob patterns, and
+//! files too small to be anything but icons.
+//!
+//! Excluded folders are pruned in WalkDir's `filter_entry`, so their
+//! contents are never read.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use globset::{GlobBuilder, GlobSet, GlobSetBuilder};
+use log::warn;
+use serde::{Deserialize, Serialize};
+use walkdir::WalkDir;
+
+/// Setting holding the user's ScanExclusions as JSON
+pub const SCAN_EXCLUSIONS_SETTING: &str = "scan_exclusions";
+
+/// Folder names never scanned: Terra's own trash, version control and
+/// package caches, NAS thumbnail and recycle folders, and OS metadata.
+pub const BUILTIN_EXCLUDED_DIRS: &[&str] = &[
+    ".trash",
+    "node_modules",
+    ".git",
+    "@eaDir",
+    "#recycle",
+    "#snapshot",
+    "$RECYCLE.BIN",
+    "System Volume Information",
+    "__MACOSX",
+    ".Trashes",
+    ".Spotlight-V100",
+    ".fseventsd",
+];
+
+/// Endings of bundle folders whose contents belong to another app:
+/// Lightroom previews and Photos/Aperture libraries.
+pub const BUILTIN_EXCLUDED_SUFFIXES: &[&str] = &[".lrdata", ".photoslibrary", ".aplibrary", ".photolibrary"];
+
+/// Files smaller than this are skipped unless the setting says otherwise
+pub const DEFAULT_MIN_FILE_SIZE: u64 = 10 * 1024;
+
+/// The user's exclusion settings. Built-in folders are skipped regardless.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(default)]
+pub struct ScanExclusions {
+    /// Skip folders and files whose names start with '.'
+    pub skip_hidden: bool,
+    /// Globs like `*.tmp` or `Exports/**`. One without a '/' is matched
+    /// against each name; one with a '/' against the path under the
+    /// scanned folder.
+    pub patterns: Vec<String>,
+    /// In bytes; 0 keeps every file
+    pub min_file_size: u64,
+    /// Descend into symlinked folders and import symlinked files. Off, links
+    /// are skipped, so a file is only found at its real path.
+    pub follow_symlinks: bool,
+}
+
+impl Default for ScanExclusions {
+    fn default() -> Self {
+        ScanExclusions {
+            skip_hidden: true,
+            patterns: Vec::new(),
+            min_file_size: DEFAULT_MIN_FILE_SIZE,
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl ScanExclusions {
+    /// Compile the patterns, or say which one isn't a valid glob.
+    pub fn compile(&self) -> Result<ScanRules, String> {
+        let mut names = GlobSetBuilder::new();
+        let mut paths = GlobSetBuilder::new();
+        for pattern in self.patterns.iter().map(|p| p.trim()).filter(|p| !p.is_empty()) {
+            let glob = GlobBuilder::new(pattern.trim_end_matches('/'))
+                .literal_separator(true)
+                .build()
+                .map_err(|e| format!("Invalid exclusion pattern '{}': {}", pattern, e))?;
+            if pattern.contains('/') {
+                paths.add(glob);
+            } else {
+                names.add(glob);
+            }
+        }
+        let build = |set: GlobSetBuilder| set.build().map_err(|e| format!("Invalid exclusion patterns: {}", e));
+        Ok(ScanRules {
+            skip_hidden: self.skip_hidden,
+            names: build(names)?,
+            paths: build(paths)?,
+            min_file_size: self.min_file_size,
+            follow_symlinks: self.follow_symlinks,
+        })
+    }
+}
+
+/// ScanExclusions ready for matching.
+pub struct ScanRules {
+    skip_hidden: bool,
+    names: GlobSet,
+    paths: GlobSet,
+    min_file_size: u64,
+    follow_symlinks: bool,
+}
+
+impl Default for ScanRules {
+    fn default() -> Self {
+        ScanRules {
+            skip_hidden: true,
+            names: GlobSet::empty(),
+            paths: GlobSet::empty(),
+            min_file_size: DEFAULT_MIN_FILE_SIZE,
+            follow_symlinks: false,
+        }
+    }
+}
+
+impl ScanRules {
+    /// True if the entry at `relative` (its path under the scanned folder)
+    /// is skipped, and with a folder everything in it.
+    pub fn excludes(&self, relative: &Path, is_dir: bool) -> bool {
+        let Some(name) = relative.file_name().map(|n| n.to_string_lossy()) else {
+            return false;
+        };
+        if self.skip_hidden && name.starts_with('.') {
+            return true;
+        }
+        if is_dir {
+            let lower = name.to_lowercase();
+            if BUILTIN_EXCLUDED_DIRS.contains(&&*name)
+                || BUILTIN_EXCLUDED_SUFFIXES.iter().any(|suffix| lower.ends_with(suffix))
+            {
+                return true;
+            }
+        }
+        self.names.is_match(&*name) || self.paths.is_match(relative)
+    }
+
+    /// True if the file at `relative`, or any folder between the scanned
+    /// folder and it, is excluded. For single files, e.g. from the watcher.
+    pub fn excludes_within(&self, relative: &Path) -> bool {
+        relative
+            .ancestors()
+            .filter(|a| !a.as_os_str().is_empty())
+            .enumerate()
+            .any(|(i, a)| self.excludes(a, i > 0))
+    }
+
+    pub fn follows_symlinks(&self) -> bool {
+        self.follow_symlinks
+    }
+
+    /// True if a file of `len` bytes is below the size threshold
+    pub fn too_small(&self, len: u64) -> bool {
+        len < self.min_file_size
+    }
+}
+
+/// Every file under `root` that `rules` doesn't exclude, by size or
+/// otherwise. Symlinks are skipped unless rules follow them; then a link
+/// back into a folder being walked is logged and skipped rather than looped
+/// through, and a file reachable by several paths is listed once.
+pub fn walk_files(root: &Path, rules: &ScanRules) -> Vec<walkdir::DirEntry> {
+    walk_files_within(root, root, rules)
+}
+
+/// `walk_files` for `dir`, a folder somewhere under the scanned folder
+/// `root`: path patterns are matched against paths under `root`, as a scan
+/// of `root` would match them.
+pub fn walk_files_within(root: &Path, dir: &Path, rules: &ScanRules) -> Vec<walkdir::DirEntry> {
+    let mut seen = HashSet::new();
+    WalkDir::new(dir)
+        .follow_links(rules.follow_symlinks)
+        .into_iter()
+        .filter_entry(|e| {
+            e.depth() == 0 || !rules.excludes(e.path().strip_prefix(root).unwrap_or(e.path()), e.file_type().is_dir())
+        })
+        .filter_map(|e| match e {
+            Ok(entry) => Some(entry),
+            Err(err) => {
+                if let Some(ancestor) = err.loop_ancestor() {
+                    warn!("Skipping {}: it links back to {}", err.path().unwrap_or(dir).display(), ancestor.display());
+                }
+                None
+            }
+        })
+        // Without follow_links a symlink's type is the link's, so it's not a file
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.metadata().map_or(false, |m| !rules.too_small(m.len())))
+        .filter(|e| !rules.follow_symlinks || e.path().canonicalize().map_or(false, |real| seen.insert(real)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::path::PathBuf;
+
+    /// Fresh directory under the system temp dir.
+    fn fixture(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("terra-exclusions-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn walk_skips_symlinks_or_follows_them_without_looping() {
+        use std::os::unix::fs::symlink;
+
+        let root = fixture("walk");
+        let elsewhere = fixture("walk-target");
+        let photos = root.join("photos");
+        fs::create_dir_all(&photos).unwrap();
+        let photo = vec![0u8; DEFAULT_MIN_FILE_SIZE as usize];
+        fs::write(photos.join("café.jpg"), &photo).unwrap();
+        fs::write(photos.join("icon.png"), b"tiny").unwrap();
+        fs::write(elsewhere.join("linked.jpg"), &photo).unwrap();
+        // A link back to its own parent, a second path to café.jpg, and a
+        // folder outside the root
+        symlink(&photos, photos.join("loop")).unwrap();
+        symlink(photos.join("café.jpg"), root.join("alias.jpg")).unwrap();
+        symlink(&elsewhere, root.join("linked")).unwrap();
+
+        let found = |rules: ScanRules| {
+            let mut real: Vec<PathBuf> = walk_files(&root, &rules)
+                .iter()
+                .map(|e| e.path().canonicalize().unwrap())
+                .collect();
+            real.sort();
+            real
+        };
+        let cafe = photos.join("café.jpg").canonicalize().unwrap();
+        assert_eq!(found(ScanRules::default()), vec![cafe.clone()]);
+
+        let following = ScanExclusions { follow_symlinks: true, ..Default::default() }.compile().unwrap();
+        let mut expected = vec![cafe, elsewhere.join("linked.jpg").canonicalize().unwrap()];
+        expected.sort();
+        assert_eq!(found(following), expected);
+
+        let _ = fs::remove_dir_all(&root);
+        let _ = fs::remove_dir_all(&elsewhere);
+    }
+
+    #[test]
+    fn skips_hidden_builtin_and_bundle_folders() {
+        let rules = ScanRules::default();
+        assert!(rules.excludes(Path::new(".cache"), true));
+        assert!(rules.excludes(Path::new("web/node_modules"), true));
+        assert!(rules.excludes(Path::new("@eaDir"), true));
+        assert!(rules.excludes(Path::new(".trash"), true));
+        assert!(rules.excludes(Path::new("Lightroom/Catalog Previews.lrdata"), true));
+        assert!(rules.excludes(Path::new("Pictures/Photos Library.photoslibrary"), true));
+        assert!(!rules.excludes(Path::new("2023/Japan"), true));
+        assert!(!rules.excludes(Path::new("2023/Japan/IMG_0001.jpg"), false));
+        // A file that merely shares a folder's name is kept
+        assert!(!rules.excludes(Path::new("node_modules"), false));
+
+        let shown = ScanExclusions { skip_hidden: false, ..Default::default() }.compile().unwrap();
+        assert!(!shown.excludes(Path::new(".cache"), true));
+        assert!(shown.excludes(Path::new(".git"), true));
+        assert!(shown.excludes(Path::new(".trash"), true));
+    }
+
+    #[test]
+    fn user_patterns_match_names_or_paths() {
+        let rules = ScanExclusions {
+            patterns: vec!["*.tmp.jpg".to_string(), "Exports/*".to_string(), " ".to_string()],
+            ..Default::default()
+        }
+        .compile()
+        .unwrap();
+        assert!(rules.excludes(Path::new("a/b/photo.tmp.jpg"), false));
+        assert!(rules.excludes(Path::new("Exports/2023"), true));
+        assert!(!rules.excludes(Path::new("Trip/Exports/2023"), true));
+        assert!(rules.excludes_within(Path::new("Exports/2023/IMG_0001.jpg")));
+        assert!(rules.excludes_within(Path::new("web/node_modules/pkg/logo.png")));
+        assert!(!rules.excludes_within(Path::new("Trip/IMG_0001.jpg")));
+
+        let invalid = ScanExclusions { patterns: vec!["[oops".to_string()], ..Default::default() }.compile();
+        assert!(invalid.err().unwrap().contains("[oops"));
+    }
+
+    #[test]
+    fn folder_walks_match_paths_from_the_scanned_root() {
+        let root = fixture("within");
+        let moved = root.join("Exports");
+        fs::create_dir_all(moved.join("2023")).unwrap();
+        fs::create_dir_all(root.join("Trip")).unwrap();
+        let photo = vec![0u8; DEFAULT_MIN_FILE_SIZE as usize];
+        fs::write(moved.join("2023").join("a.jpg"), &photo).unwrap();
+        fs::write(root.join("Trip").join("b.jpg"), &photo).unwrap();
+        let rules = ScanExclusions { patterns: vec!["Exports/*".to_string()], ..Default::default() }.compile().unwrap();
+
+        // Relative to Exports itself, "2023" wouldn't match Exports/*
+        assert!(walk_files_within(&root, &moved, &rules).is_empty());
+        assert_eq!(walk_files_within(&root, &root.join("Trip"), &rules).len(), 1);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn size_threshold() {
+        let rules = ScanRules::default();
+        assert!(rules.too_small(2 * 1024));
+        assert!(!rules.too_small(DEFAULT_MIN_FILE_SIZE));
+        let keep_all = ScanExclusions { min_file_size: 0, ..Default::default() }.compile().unwrap();
+        assert!(!keep_all.too_small(0));
+    }
+}
@@ -0,0 +1,957 @@
+//! Writing metadata into JPEGs: a corrected capture date or a rotation back
+//! into the original, and on export Terra's date, caption, rating, color
+//! label and location (or nothing at all, for sharing).
+//!
+//! Existing EXIF date fields have a fixed length (`YYYY:MM:DD HH:MM:SS` and
+//! `+HH:MM`, each NUL-terminated), so they are overwritten in place; a file
+//! with none gets them added to its Exif IFD. Other values go into an XMP
+//! packet, which any length fits. Pixels are never re-encoded.
+
+use std::fs;
+use std::path::Path;
+
+use chrono::NaiveDateTime;
+
+use crate::db::ColorLabel;
+
+/// DateTime, DateTimeOriginal, DateTimeDigitized
+const DATE_TAGS: [u16; 3] = [0x0132, 0x9003, 0x9004];
+/// OffsetTime, OffsetTimeOriginal, OffsetTimeDigitized
+const OFFSET_TAGS: [u16; 3] = [0x9010, 0x9011, 0x9012];
+const TAG_IMAGE_DESCRIPTION: u16 = 0x010E;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_RATING: u16 = 0x4746;
+const TAG_DATE_TIME_ORIGINAL: u16 = 0x9003;
+const TAG_OFFSET_TIME_ORIGINAL: u16 = 0x9011;
+const EXIF_IFD_POINTER: u16 = 0x8769;
+const GPS_IFD_POINTER: u16 = 0x8825;
+const BYTE: u16 = 1;
+const ASCII: u16 = 2;
+const SHORT: u16 = 3;
+const LONG: u16 = 4;
+const RATIONAL: u16 = 5;
+const DATE_LEN: usize = 20;
+const OFFSET_LEN: usize = 7;
+
+const SOI: [u8; 2] = [0xFF, 0xD8];
+const APP0: u8 = 0xE0;
+const APP1: u8 = 0xE1;
+const EXIF_HEADER: &[u8] = b"Exif\0\0";
+const XMP_HEADER: &[u8] = b"http://ns.adobe.com/xap/1.0/\0";
+/// A segment's length field counts itself, and tops out at 0xFFFF.
+const MAX_SEGMENT_DATA: usize = 0xFFFF - 2;
+
+/// Set the date fields of a JPEG to `wall_clock` at `offset_minutes` east
+/// of UTC. Existing fields are overwritten; a file without any gets
+/// DateTimeOriginal and OffsetTimeOriginal. Returns how many fields were written.
+pub(crate) fn write_capture_date(path: &Path, wall_clock: NaiveDateTime, offset_minutes: i32) -> Result<usize, String> {
+    let mut bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let segments = segments(&bytes).ok_or_else(|| format!("{} isn't a readable JPEG", path.display()))?;
+    let Some(exif) = segments.iter().find(|s| s.is_exif(&bytes)) else {
+        let meta = EmbeddedMetadata { date: Some((wall_clock, offset_minutes)), ..Default::default() };
+        let mut added = Vec::new();
+        push_segment(&mut added, APP1, &[EXIF_HEADER, &minimal_exif(None, Some(&meta))].concat())
+            .ok_or_else(|| format!("No room for a date in {}", path.display()))?;
+        let kept = segments.iter().map(|s| (s.marker, bytes[s.start..s.end].to_vec())).collect();
+        replace_file(path, &assemble(&bytes, &segments, kept, &added))?;
+        return Ok(2);
+    };
+
+    let tiff_start = exif.start + 4 + EXIF_HEADER.len();
+    let written = patch_dates(&mut bytes[tiff_start..exif.end], wall_clock, offset_minutes);
+    if written > 0 {
+        replace_file(path, &bytes)?;
+        return Ok(written);
+    }
+    let no_room = || format!("No room for a date in {}", path.display());
+    let (tiff, added) = with_capture_date(&bytes[tiff_start..exif.end], wall_clock, offset_minutes).ok_or_else(no_room)?;
+    let mut segment = Vec::new();
+    push_segment(&mut segment, APP1, &[EXIF_HEADER, &tiff].concat()).ok_or_else(no_room)?;
+    let kept = segments
+        .iter()
+        .map(|s| (s.marker, if s.start == exif.start { segment.clone() } else { bytes[s.start..s.end].to_vec() }))
+        .collect();
+    replace_file(path, &assemble(&bytes, &segments, kept, &[]))?;
+    Ok(added)
+}
+
+/// Turn a JPEG a further `degrees` (90, 180 or 270) clockwise by rewriting
+/// its EXIF orientation. Returns the orientation written.
+pub(crate) fn rotate_jpeg(path: &Path, degrees: u16) -> Result<u16, String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let (rotated, orientation) = rotate_orientation(&bytes, degrees)
+        .ok_or_else(|| format!("{} has no room for an orientation", path.display()))?;
+    replace_file(path, &rotated)?;
+    Ok(orientation)
+}
+
+/// Embed `meta` into a JPEG on disk, as `embed_metadata` does for exports.
+pub(crate) fn embed_into_file(path: &Path, meta: &EmbeddedMetadata) -> Result<(), String> {
+    let bytes = fs::read(path).map_err(|e| format!("Failed to read {}: {}", path.display(), e))?;
+    let embedded = embed_metadata(&bytes, meta).ok_or_else(|| format!("{} isn't a readable JPEG", path.display()))?;
+    replace_file(path, &embedded)
+}
+
+/// Written beside the original and renamed over it, so a failure never
+/// leaves a half-written photo.
+fn replace_file(path: &Path, bytes: &[u8]) -> Result<(), String> {
+    let tmp = path.with_extension("terra-tmp");
+    fs::write(&tmp, bytes)
+        .and_then(|_| fs::rename(&tmp, path))
+        .map_err(|e| {
+            let _ = fs::remove_file(&tmp);
+            format!("Failed to write {}: {}", path.display(), e)
+        })
+}
+
+/// What export writes into a JPEG.
+#[derive(Debug, Default)]
+pub(crate) struct EmbeddedMetadata {
+    /// Capture time as the camera's clock showed it, and its UTC offset
+    pub date: Option<(NaiveDateTime, i32)>,
+    pub description: Option<String>,
+    /// 0-5; 0 writes nothing
+    pub rating: u8,
+    pub color_label: Option<ColorLabel>,
+    /// (latitude, longitude)
+    pub gps: Option<(f64, f64)>,
+}
+
+/// `jpeg` with `meta` embedded: existing EXIF dates are corrected in place,
+/// a file without EXIF gets a minimal block, and everything goes into a new
+/// XMP packet that replaces any already there. None if `jpeg` isn't a JPEG.
+pub(crate) fn embed_metadata(jpeg: &[u8], meta: &EmbeddedMetadata) -> Option<Vec<u8>> {
+    let segments = segments(jpeg)?;
+    let has_exif = segments.iter().any(|s| s.is_exif(jpeg));
+    let mut xmp = Vec::new();
+    push_segment(&mut xmp, APP1, &[XMP_HEADER, xmp_packet(meta).as_bytes()].concat())?;
+    // EXIF first, XMP straight after it
+    let mut added = Vec::new();
+    if !has_exif {
+        push_segment(&mut added, APP1, &[EXIF_HEADER, &minimal_exif(None, Some(meta))].concat())?;
+        added.extend_from_slice(&xmp);
+    }
+
+    let mut kept = Vec::new();
+    for segment in &segments {
+        if segment.is_xmp(jpeg) {
+            continue;
+        }
+        let mut bytes = jpeg[segment.start..segment.end].to_vec();
+        if segment.is_exif(jpeg) {
+            if let Some((wall_clock, offset)) = meta.date {
+                patch_dates(&mut bytes[4 + EXIF_HEADER.len()..], wall_clock, offset);
+            }
+            bytes.extend_from_slice(&xmp);
+        }
+        kept.push((segment.marker, bytes));
+    }
+    Some(assemble(jpeg, &segments, kept, &added))
+}
+
+/// `jpeg` without EXIF (GPS included), XMP, IPTC or comments. The colour
+/// profile is kept, and so is the orientation so the photo isn't shown
+/// sideways. None if `jpeg` isn't a JPEG.
+pub(crate) fn strip_metadata(jpeg: &[u8]) -> Option<Vec<u8>> {
+    let segments = segments(jpeg)?;
+    let orientation = segments
+        .iter()
+        .find(|s| s.is_exif(jpeg))
+        .and_then(|s| Tiff::new(&jpeg[s.start + 4 + EXIF_HEADER.len()..s.end]))
+        .and_then(|tiff| tiff.ifd0_short(TAG_ORIENTATION))
+        .filter(|&o| o != 1);
+    let mut added = Vec::new();
+    if orientation.is_some() {
+        push_segment(&mut added, APP1, &[EXIF_HEADER, &minimal_exif(orientation, None)].concat())?;
+    }
+
+    // JFIF, ICC profile (APP2), Adobe colour transform (APP14) and
+    // everything that isn't an application segment stay
+    let kept = segments
+        .iter()
+        .filter(|s| !matches!(s.marker, 0xE1 | 0xE3..=0xED | 0xEF | 0xFE))
+        .map(|s| (s.marker, jpeg[s.start..s.end].to_vec()))
+        .collect();
+    Some(assemble(jpeg, &segments, kept, &added))
+}
+
+/// `jpeg` turned a further `degrees` (90, 180 or 270) clockwise through its
+/// EXIF orientation, and the orientation written. An existing tag is
+/// changed in place; otherwise IFD0 is copied to the end of the EXIF block
+/// with the tag added, or a JPEG without EXIF gets a minimal block. None if
+/// `jpeg` isn't a JPEG or the tag doesn't fit.
+pub(crate) fn rotate_orientation(jpeg: &[u8], degrees: u16) -> Option<(Vec<u8>, u16)> {
+    let segments = segments(jpeg)?;
+    let Some(exif) = segments.iter().find(|s| s.is_exif(jpeg)) else {
+        let orientation = rotated_orientation(1, degrees)?;
+        let mut added = Vec::new();
+        push_segment(&mut added, APP1, &[EXIF_HEADER, &minimal_exif(Some(orientation), None)].concat())?;
+        let kept = segments.iter().map(|s| (s.marker, jpeg[s.start..s.end].to_vec())).collect();
+        return Some((assemble(jpeg, &segments, kept, &added), orientation));
+    };
+
+    let tiff_start = exif.start + 4 + EXIF_HEADER.len();
+    let reader = Tiff::new(&jpeg[tiff_start..exif.end])?;
+    let ifd0 = reader.u32_at(4)?;
+    if let Some(entry) = reader.entries(ifd0).iter().find(|e| e.tag == TAG_ORIENTATION && e.kind == SHORT) {
+        let orientation = rotated_orientation(reader.u16_at(entry.value_at)?, degrees)?;
+        let at = tiff_start + entry.value_at;
+        let mut out = jpeg.to_vec();
+        out[at..at + 2].copy_from_slice(&reader.u16_bytes(orientation));
+        return Some((out, orientation));
+    }
+
+    // Entries keep their value offsets, so the old IFD0 is just left unused
+    let orientation = rotated_orientation(1, degrees)?;
+    let count = usize::from(reader.u16_at(ifd0)?);
+    let entries_end = ifd0 + 2 + count * 12;
+    let mut tiff = jpeg.get(tiff_start..exif.end)?.to_vec();
+    let next_ifd = tiff.get(entries_end..entries_end + 4)?.to_vec();
+    let mut added_entry = reader.u16_bytes(TAG_ORIENTATION).to_vec();
+    added_entry.extend_from_slice(&reader.u16_bytes(SHORT));
+    added_entry.extend_from_slice(&reader.u32_bytes(1));
+    added_entry.extend_from_slice(&reader.u16_bytes(orientation));
+    added_entry.extend_from_slice(&[0, 0]);
+    // Entries stay sorted by tag
+    let insert_at = (0..count)
+        .find(|i| reader.u16_at(ifd0 + 2 + i * 12).is_some_and(|tag| tag > TAG_ORIENTATION))
+        .unwrap_or(count);
+
+    let mut new_ifd = reader.u16_bytes(u16::try_from(count + 1).ok()?).to_vec();
+    new_ifd.extend_from_slice(&tiff[ifd0 + 2..ifd0 + 2 + insert_at * 12]);
+    new_ifd.extend(added_entry);
+    new_ifd.extend_from_slice(&tiff[ifd0 + 2 + insert_at * 12..entries_end]);
+    new_ifd.extend_from_slice(&next_ifd);
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let new_at = reader.u32_bytes(tiff.len() as u32);
+    tiff.extend(new_ifd);
+    tiff[4..8].copy_from_slice(&new_at);
+
+    let mut segment = Vec::new();
+    push_segment(&mut segment, APP1, &[EXIF_HEADER, &tiff].concat())?;
+    let kept = segments
+        .iter()
+        .map(|s| (s.marker, if s.start == exif.start { segment.clone() } else { jpeg[s.start..s.end].to_vec() }))
+        .collect();
+    Some((assemble(jpeg, &segments, kept, &[]), orientation))
+}
+
+/// The EXIF orientation of a photo shown with `orientation` and then turned
+/// a further `degrees` clockwise. None unless `degrees` is a quarter turn.
+fn rotated_orientation(orientation: u16, degrees: u16) -> Option<u16> {
+    // Each orientation is an optional mirror followed by 0, 90, 180 or 270
+    // degrees clockwise; turning it further moves along its row
+    const UPRIGHT: [u16; 4] = [1, 6, 3, 8];
+    const MIRRORED: [u16; 4] = [2, 7, 4, 5];
+    if degrees % 90 != 0 {
+        return None;
+    }
+    let (row, at) = [UPRIGHT, MIRRORED]
+        .into_iter()
+        .find_map(|row| row.iter().position(|&o| o == orientation).map(|at| (row, at)))
+        .unwrap_or((UPRIGHT, 0));
+    Some(row[(at + usize::from(degrees / 90)) % 4])
+}
+
+/// SOI, the kept segments with `added` after any leading JFIF segment (which
+/// must stay first), then the image data of `jpeg` unchanged.
+fn assemble(jpeg: &[u8], segments: &[Segment], kept: Vec<(u8, Vec<u8>)>, added: &[u8]) -> Vec<u8> {
+    let insert_at = kept.iter().position(|(marker, _)| *marker != APP0).unwrap_or(kept.len());
+    let mut out = SOI.to_vec();
+    for (i, (_, bytes)) in kept.iter().enumerate() {
+        if i == insert_at {
+            out.extend_from_slice(added);
+        }
+        out.extend_from_slice(bytes);
+    }
+    if insert_at == kept.len() {
+        out.extend_from_slice(added);
+    }
+    out.extend_from_slice(&jpeg[segments.last().map_or(2, |s| s.end)..]);
+    out
+}
+
+/// A marker segment before the image data: `start..end` covers the marker,
+/// length and payload.
+struct Segment {
+    marker: u8,
+    start: usize,
+    end: usize,
+}
+
+impl Segment {
+    fn payload<'a>(&self, jpeg: &'a [u8]) -> &'a [u8] {
+        &jpeg[self.start + 4..self.end]
+    }
+
+    fn is_exif(&self, jpeg: &[u8]) -> bool {
+        self.marker == APP1 && self.payload(jpeg).starts_with(EXIF_HEADER)
+    }
+
+    fn is_xmp(&self, jpeg: &[u8]) -> bool {
+        self.marker == APP1 && self.payload(jpeg).starts_with(XMP_HEADER)
+    }
+}
+
+/// The segments between SOI and the start of scan. None unless `jpeg` is a
+/// well-formed JPEG up to there.
+fn segments(jpeg: &[u8]) -> Option<Vec<Segment>> {
+    if jpeg.get(0..2)? != SOI {
+        return None;
+    }
+    let mut segments = Vec::new();
+    let mut at = 2;
+    loop {
+        let marker = jpeg.get(at..at + 2)?;
+        if marker[0] != 0xFF {
+            return None;
+        }
+        // Start of scan or end of image: the rest is copied as it is
+        if marker[1] == 0xDA || marker[1] == 0xD9 {
+            return Some(segments);
+        }
+        let len = usize::from(u16::from_be_bytes(jpeg.get(at + 2..at + 4)?.try_into().ok()?));
+        let end = at + 2 + len;
+        if len < 2 || end > jpeg.len() {
+            return None;
+        }
+        segments.push(Segment { marker: marker[1], start: at, end });
+        at = end;
+    }
+}
+
+fn push_segment(out: &mut Vec<u8>, marker: u8, payload: &[u8]) -> Option<()> {
+    if payload.len() > MAX_SEGMENT_DATA {
+        return None;
+    }
+    out.extend_from_slice(&[0xFF, marker]);
+    out.extend_from_slice(&((payload.len() + 2) as u16).to_be_bytes());
+    out.extend_from_slice(payload);
+    Some(())
+}
+
+/// Overwrite every date and offset field in IFD0 and the Exif IFD of `tiff`.
+/// Returns how many were written.
+fn patch_dates(tiff: &mut [u8], wall_clock: NaiveDateTime, offset_minutes: i32) -> usize {
+    let date = format!("{}\0", wall_clock.format("%Y:%m:%d %H:%M:%S"));
+    let offset = format!("{}\0", format_offset(offset_minutes));
+    let fields = match Tiff::new(tiff) {
+        Some(reader) => reader.date_fields(),
+        None => return 0,
+    };
+    for &(at, len) in &fields {
+        let value = if len == DATE_LEN { &date } else { &offset };
+        tiff[at..at + len].copy_from_slice(value.as_bytes());
+    }
+    fields.len()
+}
+
+/// `tiff`, whose IFDs have no date fields, with DateTimeOriginal and
+/// OffsetTimeOriginal added to its Exif IFD (made if there is none), and
+/// how many were added. None if the structure can't be followed.
+fn with_capture_date(tiff: &[u8], wall_clock: NaiveDateTime, offset_minutes: i32) -> Option<(Vec<u8>, usize)> {
+    let reader = Tiff::new(tiff)?;
+    let ifd0 = reader.u32_at(4)?;
+    let pointer = reader.entries(ifd0).into_iter().find(|e| e.tag == EXIF_IFD_POINTER && e.kind == LONG);
+    let present: Vec<u16> = pointer.as_ref().map(|p| reader.entries(p.value).iter().map(|e| e.tag).collect()).unwrap_or_default();
+    let added: Vec<NewEntry> = [
+        NewEntry::ascii(TAG_DATE_TIME_ORIGINAL, &wall_clock.format("%Y:%m:%d %H:%M:%S").to_string()),
+        NewEntry::ascii(TAG_OFFSET_TIME_ORIGINAL, &format_offset(offset_minutes)),
+    ]
+    .into_iter()
+    .filter(|e| !present.contains(&e.tag))
+    .collect();
+    if added.is_empty() {
+        return None;
+    }
+    let count = added.len();
+
+    let mut out = tiff.to_vec();
+    let exif_at = extend_ifd(&mut out, pointer.as_ref().map(|p| p.value), added)?;
+    let exif_at = reader.u32_bytes(u32::try_from(exif_at).ok()?);
+    match pointer {
+        Some(pointer) => out[pointer.value_at..pointer.value_at + 4].copy_from_slice(&exif_at),
+        None => {
+            let entry = NewEntry { tag: EXIF_IFD_POINTER, kind: LONG, count: 1, data: exif_at.to_vec() };
+            let ifd0_at = extend_ifd(&mut out, Some(ifd0), vec![entry])?;
+            out[4..8].copy_from_slice(&reader.u32_bytes(u32::try_from(ifd0_at).ok()?));
+        }
+    }
+    Some((out, count))
+}
+
+/// Append a copy of the IFD at `ifd` (or an empty one) to `tiff` with
+/// `added` among its entries, in tag order, and their values after it.
+/// Entries keep their value offsets, so the old IFD is just left unused.
+/// `added` data must already be in the TIFF's byte order. Returns where the
+/// new IFD starts.
+fn extend_ifd(tiff: &mut Vec<u8>, ifd: Option<usize>, added: Vec<NewEntry>) -> Option<usize> {
+    let reader = Tiff::new(tiff)?;
+    let (old, next_ifd) = match ifd {
+        Some(ifd) => {
+            let entries_end = ifd + 2 + usize::from(reader.u16_at(ifd)?) * 12;
+            (tiff.get(ifd + 2..entries_end)?.to_vec(), tiff.get(entries_end..entries_end + 4)?.to_vec())
+        }
+        None => (Vec::new(), vec![0; 4]),
+    };
+    let little_endian = reader.little_endian;
+    let u16_bytes = |v: u16| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+    let u32_bytes = |v: u32| if little_endian { v.to_le_bytes() } else { v.to_be_bytes() };
+    let tag_of = |entry: &[u8]| {
+        let bytes = [entry[0], entry[1]];
+        if little_endian { u16::from_le_bytes(bytes) } else { u16::from_be_bytes(bytes) }
+    };
+    let mut entries: Vec<(u16, Vec<u8>)> = old.chunks(12).map(|entry| (tag_of(entry), entry.to_vec())).collect();
+
+    if tiff.len() % 2 == 1 {
+        tiff.push(0);
+    }
+    let start = tiff.len();
+    let count = entries.len() + added.len();
+    let mut value_at = start + 2 + count * 12 + 4;
+    let mut values = Vec::new();
+    for entry in added {
+        let mut bytes = u16_bytes(entry.tag).to_vec();
+        bytes.extend_from_slice(&u16_bytes(entry.kind));
+        bytes.extend_from_slice(&u32_bytes(entry.count));
+        if entry.data.len() <= 4 {
+            let mut inline = entry.data.clone();
+            inline.resize(4, 0);
+            bytes.extend(inline);
+        } else {
+            bytes.extend_from_slice(&u32_bytes(u32::try_from(value_at).ok()?));
+            values.extend_from_slice(&entry.data);
+            if entry.data.len() % 2 == 1 {
+                values.push(0);
+            }
+            value_at += (entry.data.len() + 1) & !1;
+        }
+        entries.push((entry.tag, bytes));
+    }
+    entries.sort_by_key(|(tag, _)| *tag);
+
+    tiff.extend_from_slice(&u16_bytes(u16::try_from(count).ok()?));
+    for (_, bytes) in entries {
+        tiff.extend(bytes);
+    }
+    tiff.extend(next_ifd);
+    tiff.extend(values);
+    Some(start)
+}
+
+/// `+09:00` / `-05:30`
+fn format_offset(minutes: i32) -> String {
+    let sign = if minutes < 0 { '-' } else { '+' };
+    format!("{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60)
+}
+
+/// Read-only view of a TIFF structure (the body of an EXIF segment).
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+/// One IFD entry: tag, type, count and the value field (an offset when the
+/// value doesn't fit in four bytes).
+struct IfdEntry {
+    tag: u16,
+    kind: u16,
+    count: usize,
+    value: usize,
+    value_at: usize,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        Some(Tiff { data, little_endian })
+    }
+
+    fn u16_at(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32_at(&self, at: usize) -> Option<usize> {
+        let b: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) } as usize)
+    }
+
+    fn u16_bytes(&self, value: u16) -> [u8; 2] {
+        if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() }
+    }
+
+    fn u32_bytes(&self, value: u32) -> [u8; 4] {
+        if self.little_endian { value.to_le_bytes() } else { value.to_be_bytes() }
+    }
+
+    fn entries(&self, ifd: usize) -> Vec<IfdEntry> {
+        let count = self.u16_at(ifd).unwrap_or(0);
+        (0..usize::from(count))
+            .map(|i| ifd + 2 + i * 12)
+            .map_while(|at| {
+                Some(IfdEntry {
+                    tag: self.u16_at(at)?,
+                    kind: self.u16_at(at + 2)?,
+                    count: self.u32_at(at + 4)?,
+                    value: self.u32_at(at + 8)?,
+                    value_at: at + 8,
+                })
+            })
+            .collect()
+    }
+
+    fn ifd0_short(&self, tag: u16) -> Option<u16> {
+        let entry = self.entries(self.u32_at(4)?).into_iter().find(|e| e.tag == tag && e.kind == SHORT)?;
+        self.u16_at(entry.value_at)
+    }
+
+    /// (offset, length) of every date and offset value in IFD0 and the Exif IFD.
+    fn date_fields(&self) -> Vec<(usize, usize)> {
+        let Some(ifd0) = self.u32_at(4) else {
+            return Vec::new();
+        };
+        let ifd0 = self.entries(ifd0);
+        let exif = ifd0
+            .iter()
+            .find(|e| e.tag == EXIF_IFD_POINTER)
+            .map(|e| self.entries(e.value))
+            .unwrap_or_default();
+        ifd0.iter()
+            .chain(&exif)
+            .filter(|e| {
+                e.kind == ASCII
+                    && ((DATE_TAGS.contains(&e.tag) && e.count == DATE_LEN)
+                        || (OFFSET_TAGS.contains(&e.tag) && e.count == OFFSET_LEN))
+            })
+            .filter(|e| e.value + e.count <= self.data.len())
+            .map(|e| (e.value, e.count))
+            .collect()
+    }
+}
+
+/// An entry for `build_ifds`; values longer than four bytes are stored
+/// after the IFD.
+struct NewEntry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    data: Vec<u8>,
+}
+
+impl NewEntry {
+    fn ascii(tag: u16, text: &str) -> Self {
+        let mut data = text.as_bytes().to_vec();
+        data.push(0);
+        NewEntry { tag, kind: ASCII, count: data.len() as u32, data }
+    }
+
+    fn short(tag: u16, value: u16) -> Self {
+        NewEntry { tag, kind: SHORT, count: 1, data: value.to_be_bytes().to_vec() }
+    }
+
+    fn long(tag: u16, value: u32) -> Self {
+        NewEntry { tag, kind: LONG, count: 1, data: value.to_be_bytes().to_vec() }
+    }
+
+    fn rationals(tag: u16, values: &[(u32, u32)]) -> Self {
+        let data = values.iter().flat_map(|(n, d)| n.to_be_bytes().into_iter().chain(d.to_be_bytes())).collect();
+        NewEntry { tag, kind: RATIONAL, count: values.len() as u32, data }
+    }
+}
+
+/// Bytes an IFD and its out-of-line values take, kept to even offsets.
+fn ifd_len(entries: &[NewEntry]) -> usize {
+    let values: usize = entries.iter().filter(|e| e.data.len() > 4).map(|e| (e.data.len() + 1) & !1).sum();
+    2 + entries.len() * 12 + 4 + values
+}
+
+/// Append a big-endian IFD at `out.len()` (a TIFF offset), followed by its
+/// out-of-line values.
+fn write_ifd(out: &mut Vec<u8>, entries: &[NewEntry]) {
+    let mut value_at = out.len() + 2 + entries.len() * 12 + 4;
+    let mut values = Vec::new();
+    out.extend_from_slice(&(entries.len() as u16).to_be_bytes());
+    for entry in entries {
+        out.extend_from_slice(&entry.tag.to_be_bytes());
+        out.extend_from_slice(&entry.kind.to_be_bytes());
+        out.extend_from_slice(&entry.count.to_be_bytes());
+        if entry.data.len() <= 4 {
+            let mut inline = entry.data.clone();
+            inline.resize(4, 0);
+            out.extend(inline);
+        } else {
+            out.extend_from_slice(&(value_at as u32).to_be_bytes());
+            values.extend_from_slice(&entry.data);
+            if entry.data.len() % 2 == 1 {
+                values.push(0);
+            }
+            value_at += (entry.data.len() + 1) & !1;
+        }
+    }
+    out.extend_from_slice(&0u32.to_be_bytes());
+    out.extend(values);
+}
+
+/// A small big-endian TIFF for a JPEG that has no EXIF: orientation and,
+/// when given, the date, caption, rating and location.
+fn minimal_exif(orientation: Option<u16>, meta: Option<&EmbeddedMetadata>) -> Vec<u8> {
+    let mut ifd0 = Vec::new();
+    let mut exif = Vec::new();
+    let mut gps = Vec::new();
+    if let Some(description) = meta.and_then(|m| m.description.as_deref()) {
+        ifd0.push(NewEntry::ascii(TAG_IMAGE_DESCRIPTION, description));
+    }
+    if let Some(orientation) = orientation {
+        ifd0.push(NewEntry::short(TAG_ORIENTATION, orientation));
+    }
+    if let Some(rating) = meta.map(|m| m.rating).filter(|&r| r > 0) {
+        ifd0.push(NewEntry::short(TAG_RATING, u16::from(rating)));
+    }
+    if let Some((wall_clock, offset)) = meta.and_then(|m| m.date) {
+        exif.push(NewEntry::ascii(TAG_DATE_TIME_ORIGINAL, &wall_clock.format("%Y:%m:%d %H:%M:%S").to_string()));
+        exif.push(NewEntry::ascii(TAG_OFFSET_TIME_ORIGINAL, &format_offset(offset)));
+    }
+    if let Some((lat, lon)) = meta.and_then(|m| m.gps) {
+        gps.push(NewEntry { tag: 0x0000, kind: BYTE, count: 4, data: vec![2, 3, 0, 0] });
+        gps.push(NewEntry::ascii(0x0001, if lat < 0.0 { "S" } else { "N" }));
+        gps.push(NewEntry::rationals(0x0002, &dms(lat)));
+        gps.push(NewEntry::ascii(0x0003, if lon < 0.0 { "W" } else { "E" }));
+        gps.push(NewEntry::rationals(0x0004, &dms(lon)));
+    }
+
+    // Pointers are inline, so IFD0's size doesn't depend on their values
+    let placeholder = |tag| NewEntry::long(tag, 0);
+    if !exif.is_empty() {
+        ifd0.push(placeholder(EXIF_IFD_POINTER));
+    }
+    if !gps.is_empty() {
+        ifd0.push(placeholder(GPS_IFD_POINTER));
+    }
+    let exif_at = 8 + ifd_len(&ifd0);
+    let gps_at = exif_at + if exif.is_empty() { 0 } else { ifd_len(&exif) };
+    for entry in &mut ifd0 {
+        match entry.tag {
+            EXIF_IFD_POINTER => entry.data = (exif_at as u32).to_be_bytes().to_vec(),
+            GPS_IFD_POINTER => entry.data = (gps_at as u32).to_be_bytes().to_vec(),
+            _ => {}
+        }
+    }
+
+    let mut tiff = b"MM\0*".to_vec();
+    tiff.extend_from_slice(&8u32.to_be_bytes());
+    write_ifd(&mut tiff, &ifd0);
+    if !exif.is_empty() {
+        write_ifd(&mut tiff, &exif);
+    }
+    if !gps.is_empty() {
+        write_ifd(&mut tiff, &gps);
+    }
+    tiff
+}
+
+/// Degrees, minutes and seconds of a coordinate, as EXIF GPS rationals.
+fn dms(coordinate: f64) -> [(u32, u32); 3] {
+    let coordinate = coordinate.abs();
+    let degrees = coordinate.trunc();
+    let minutes = ((coordinate - degrees) * 60.0).trunc();
+    let seconds = ((coordinate - degrees) * 60.0 - minutes) * 60.0;
+    [(degrees as u32, 1), (minutes as u32, 1), ((seconds * 10_000.0).round() as u32, 10_000)]
+}
+
+/// XMP coordinate: `DDD,MM.mmmmmmK`
+fn xmp_coordinate(coordinate: f64, positive: char, negative: char) -> String {
+    let abs = coordinate.abs();
+    let degrees = abs.trunc();
+    let minutes = (abs - degrees) * 60.0;
+    let direction = if coordinate < 0.0 { negative } else { positive };
+    format!("{},{:.6}{}", degrees as u32, minutes, direction)
+}
+
+/// xmp:Label as Lightroom writes it
+fn lightroom_label(label: ColorLabel) -> Option<&'static str> {
+    match label {
+        ColorLabel::Red => Some("Red"),
+        ColorLabel::Yellow => Some("Yellow"),
+        ColorLabel::Green => Some("Green"),
+        ColorLabel::Blue => Some("Blue"),
+        ColorLabel::Purple => Some("Purple"),
+        ColorLabel::None => None,
+    }
+}
+
+fn escape_xml(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// An XMP packet holding everything in `meta`, in the properties Lightroom,
+/// Photos and exiftool read (and `media::parse_xmp_*` reads back).
+fn xmp_packet(meta: &EmbeddedMetadata) -> String {
+    let mut attributes = String::new();
+    if meta.rating > 0 {
+        attributes.push_str(&format!("\n    xmp:Rating=\"{}\"", meta.rating));
+    }
+    if let Some(label) = meta.color_label.and_then(lightroom_label) {
+        attributes.push_str(&format!("\n    xmp:Label=\"{}\"", label));
+    }
+    if let Some((wall_clock, offset)) = meta.date {
+        let date = format!("{}{}", wall_clock.format("%Y-%m-%dT%H:%M:%S"), format_offset(offset));
+        attributes.push_str(&format!("\n    exif:DateTimeOriginal=\"{0}\"\n    photoshop:DateCreated=\"{0}\"", date));
+    }
+    if let Some((lat, lon)) = meta.gps {
+        attributes.push_str(&format!(
+            "\n    exif:GPSVersionID=\"2.3.0.0\"\n    exif:GPSLatitude=\"{}\"\n    exif:GPSLongitude=\"{}\"",
+            xmp_coordinate(lat, 'N', 'S'),
+            xmp_coordinate(lon, 'E', 'W')
+        ));
+    }
+    let description = meta
+        .description
+        .as_deref()
+        .map(|d| {
+            format!(
+                "\n   <dc:description><rdf:Alt><rdf:li xml:lang=\"x-default\">{}</rdf:li></rdf:Alt></dc:description>",
+                escape_xml(d)
+            )
+        })
+        .unwrap_or_default();
+
+    format!(
+        "<?xpacket begin=\"\u{feff}\" id=\"W5M0MpCehiHzreSzNTczkc9d\"?>\n\
+         <x:xmpmeta xmlns:x=\"adobe:ns:meta/\">\n \
+         <rdf:RDF xmlns:rdf=\"http://www.w3.org/1999/02/22-rdf-syntax-ns#\">\n  \
+         <rdf:Description rdf:about=\"\"\n    \
+         xmlns:xmp=\"http://ns.adobe.com/xap/1.0/\"\n    \
+         xmlns:dc=\"http://purl.org/dc/elements/1.1/\"\n    \
+         xmlns:exif=\"http://ns.adobe.com/exif/1.0/\"\n    \
+         xmlns:photoshop=\"http://ns.adobe.com/photoshop/1.0/\"{}>{}\n  \
+         </rdf:Description>\n \
+         </rdf:RDF>\n\
+         </x:xmpmeta>\n\
+         <?xpacket end=\"w\"?>",
+        attributes, description
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::media;
+
+    /// Byte range of the TIFF structure in a JPEG's EXIF APP1 segment.
+    fn find_exif_tiff(jpeg: &[u8]) -> Option<(usize, usize)> {
+        let exif = segments(jpeg)?.into_iter().find(|s| s.is_exif(jpeg))?;
+        Some((exif.start + 4 + EXIF_HEADER.len(), exif.end))
+    }
+
+    /// A little-endian JPEG with DateTime and Orientation (6, rotated) in
+    /// IFD0 and DateTimeOriginal and OffsetTimeOriginal in the Exif IFD.
+    fn jpeg_with_dates() -> Vec<u8> {
+        let entry = |tag: u16, kind: u16, count: u32, value: u32| {
+            let mut out = tag.to_le_bytes().to_vec();
+            out.extend_from_slice(&kind.to_le_bytes());
+            out.extend_from_slice(&count.to_le_bytes());
+            out.extend_from_slice(&value.to_le_bytes());
+            out
+        };
+        // IFD0 at 8 (3 entries, 42 bytes), Exif IFD at 50 (2 entries), values from 80
+        let mut tiff = b"II*\0".to_vec();
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend(entry(0x0132, ASCII, 20, 80));
+        tiff.extend(entry(TAG_ORIENTATION, SHORT, 1, 6));
+        tiff.extend(entry(EXIF_IFD_POINTER, LONG, 1, 50));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        tiff.extend(entry(0x9003, ASCII, 20, 100));
+        tiff.extend(entry(0x9011, ASCII, 7, 120));
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        tiff.extend_from_slice(b"2001:01:01 00:00:00\0");
+        tiff.extend_from_slice(b"2001:01:01 00:00:00\0");
+        tiff.extend_from_slice(b"+00:00\0");
+
+        let mut jpeg = SOI.to_vec();
+        push_segment(&mut jpeg, APP0, b"JFIF\0\x01\x01\0\0\x01\0\x01\0\0").unwrap();
+        push_segment(&mut jpeg, APP1, &[EXIF_HEADER, &tiff].concat()).unwrap();
+        push_segment(&mut jpeg, 0xFE, b"a comment").unwrap();
+        jpeg.extend(scan());
+        jpeg
+    }
+
+    /// Start of scan, some entropy-coded bytes and end of image.
+    fn scan() -> Vec<u8> {
+        vec![0xFF, 0xDA, 0x00, 0x02, 0x12, 0x34, 0x56, 0xFF, 0xD9]
+    }
+
+    fn wall_clock() -> NaiveDateTime {
+        NaiveDateTime::parse_from_str("2019-07-17 10:30:00", "%Y-%m-%d %H:%M:%S").unwrap()
+    }
+
+    fn date_time_original(jpeg: &[u8]) -> Option<String> {
+        let exif = rexif::parse_buffer(jpeg).ok()?;
+        exif.entries.iter().find_map(|e| match (&e.tag, &e.value) {
+            (rexif::ExifTag::DateTimeOriginal, rexif::TagValue::Ascii(s)) => Some(s.trim_end_matches('\0').to_string()),
+            _ => None,
+        })
+    }
+
+    #[test]
+    fn rewrites_every_date_field_in_place() {
+        let tmp = std::env::temp_dir().join(format!("terra-exif-write-{}.jpg", std::process::id()));
+        let original = jpeg_with_dates();
+        fs::write(&tmp, &original).unwrap();
+
+        assert_eq!(write_capture_date(&tmp, wall_clock(), -300), Ok(3));
+
+        let written = fs::read(&tmp).unwrap();
+        assert_eq!(written.len(), original.len());
+        assert_eq!(date_time_original(&written).as_deref(), Some("2019:07:17 10:30:00"));
+        assert!(written.windows(6).any(|w| w == b"-05:00"));
+        let _ = fs::remove_file(&tmp);
+    }
+
+    #[test]
+    fn files_without_date_fields_get_them() {
+        let tmp = std::env::temp_dir().join(format!("terra-exif-write-none-{}.jpg", std::process::id()));
+        let bare = [SOI.to_vec(), scan()].concat();
+        fs::write(&tmp, &bare).unwrap();
+        assert_eq!(write_capture_date(&tmp, wall_clock(), 0), Ok(2));
+        let written = fs::read(&tmp).unwrap();
+        assert_eq!(date_time_original(&written).as_deref(), Some("2019:07:17 10:30:00"));
+        assert!(written.ends_with(&scan()));
+
+        // EXIF with only an orientation: the date goes into a new Exif IFD
+        // and the orientation stays
+        let oriented = strip_metadata(&jpeg_with_dates()).unwrap();
+        assert_eq!(date_time_original(&oriented), None);
+        fs::write(&tmp, &oriented).unwrap();
+        assert_eq!(write_capture_date(&tmp, wall_clock(), 60), Ok(2));
+        let written = fs::read(&tmp).unwrap();
+        assert_eq!(date_time_original(&written).as_deref(), Some("2019:07:17 10:30:00"));
+        assert_eq!(orientation(&written), Some(6));
+        assert!(written.windows(6).any(|w| w == b"+01:00"));
+        let _ = fs::remove_file(&tmp);
+    }
+
+    fn metadata() -> EmbeddedMetadata {
+        EmbeddedMetadata {
+            date: Some((wall_clock(), 540)),
+            description: Some("Fish & chips <Brighton>".to_string()),
+            rating: 4,
+            color_label: Some(ColorLabel::Green),
+            gps: Some((50.8225, -0.1372)),
+        }
+    }
+
+    #[test]
+    fn embeds_metadata_alongside_existing_exif() {
+        let embedded = embed_metadata(&jpeg_with_dates(), &metadata()).unwrap();
+        assert_eq!(date_time_original(&embedded).as_deref(), Some("2019:07:17 10:30:00"));
+        assert!(embedded.ends_with(&scan()));
+
+        let text = String::from_utf8_lossy(&embedded);
+        assert_eq!(media::parse_xmp_rating(&text), Some(4));
+        assert_eq!(media::parse_xmp_label(&text), Some(ColorLabel::Green));
+        assert_eq!(media::parse_xmp_description(&text).as_deref(), Some("Fish & chips <Brighton>"));
+        assert!(text.contains("exif:GPSLatitude=\"50,49.350000N\""));
+        assert!(text.contains("exif:GPSLongitude=\"0,8.232000W\""));
+
+        // Embedding again replaces the packet instead of adding a second
+        let again = embed_metadata(&embedded, &metadata()).unwrap();
+        assert_eq!(again.len(), embedded.len());
+    }
+
+    #[test]
+    fn embeds_a_minimal_exif_block_when_there_is_none() {
+        let bare = [SOI.to_vec(), scan()].concat();
+        let embedded = embed_metadata(&bare, &metadata()).unwrap();
+        let exif = rexif::parse_buffer(&embedded).unwrap();
+        assert_eq!(date_time_original(&embedded).as_deref(), Some("2019:07:17 10:30:00"));
+        let latitude_ref = exif.entries.iter().find(|e| e.tag == rexif::ExifTag::GPSLatitudeRef).unwrap();
+        assert!(matches!(&latitude_ref.value, rexif::TagValue::Ascii(s) if s.starts_with('N')));
+        assert!(embedded.ends_with(&scan()));
+    }
+
+    #[test]
+    fn strips_everything_but_orientation() {
+        let with_xmp = embed_metadata(&jpeg_with_dates(), &metadata()).unwrap();
+        let stripped = strip_metadata(&with_xmp).unwrap();
+
+        assert_eq!(date_time_original(&stripped), None);
+        let text = String::from_utf8_lossy(&stripped);
+        assert!(!text.contains("xmpmeta"));
+        assert!(!text.contains("a comment"));
+        assert!(text.contains("JFIF"));
+        let (start, end) = find_exif_tiff(&stripped).unwrap();
+        assert_eq!(Tiff::new(&stripped[start..end]).unwrap().ifd0_short(TAG_ORIENTATION), Some(6));
+        assert!(stripped.ends_with(&scan()));
+    }
+
+    fn orientation(jpeg: &[u8]) -> Option<u16> {
+        let (start, end) = find_exif_tiff(jpeg)?;
+        Tiff::new(&jpeg[start..end])?.ifd0_short(TAG_ORIENTATION)
+    }
+
+    #[test]
+    fn rotates_an_existing_orientation_in_place() {
+        let original = jpeg_with_dates();
+        let (rotated, written) = rotate_orientation(&original, 90).unwrap();
+        assert_eq!(written, 3);
+        assert_eq!(rotated.len(), original.len());
+        assert_eq!(orientation(&rotated), Some(3));
+
+        // Turning back is the undo, and restores the file exactly
+        let (back, _) = rotate_orientation(&rotated, 270).unwrap();
+        assert_eq!(back, original);
+    }
+
+    #[test]
+    fn adds_an_orientation_to_exif_without_one() {
+        let bare = [SOI.to_vec(), scan()].concat();
+        let dated = embed_metadata(&bare, &EmbeddedMetadata { date: Some((wall_clock(), 0)), ..Default::default() }).unwrap();
+        assert_eq!(orientation(&dated), None);
+
+        let (rotated, written) = rotate_orientation(&dated, 90).unwrap();
+        assert_eq!(written, 6);
+        assert_eq!(orientation(&rotated), Some(6));
+        assert_eq!(date_time_original(&rotated).as_deref(), Some("2019:07:17 10:30:00"));
+        assert!(rotated.ends_with(&scan()));
+    }
+
+    #[test]
+    fn gives_a_jpeg_without_exif_a_minimal_block_to_rotate() {
+        let bare = [SOI.to_vec(), scan()].concat();
+        let (rotated, written) = rotate_orientation(&bare, 270).unwrap();
+        assert_eq!(written, 8);
+        assert_eq!(orientation(&rotated), Some(8));
+        assert!(rotated.ends_with(&scan()));
+    }
+
+    #[test]
+    fn composes_turns_with_mirrored_orientations() {
+        assert_eq!(rotated_orientation(1, 180), Some(3));
+        assert_eq!(rotated_orientation(8, 90), Some(1));
+        assert_eq!(rotated_orientation(2, 90), Some(7));
+        assert_eq!(rotated_orientation(5, 90), Some(2));
+        assert_eq!(rotated_orientation(4, 180), Some(2));
+        // A missing or nonsense value counts as upright
+        assert_eq!(rotated_orientation(0, 90), Some(6));
+        assert_eq!(rotated_orientation(1, 45), None);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_jpegs() {
+        assert!(embed_metadata(b"\x89PNG\r\n", &metadata()).is_none());
+        assert!(strip_metadata(b"\x89PNG\r\n").is_none());
+        assert!(rotate_orientation(b"\x89PNG\r\n", 90).is_none());
+    }
+}
@@ -0,0 +1,329 @@
+//! Handing photos to other apps: showing them in the file manager, opening
+//! them in the system's default app, and picking up the changes an external
+//! editor saves.
+//!
+//! Programs are started with their arguments passed separately, never
+//! through a shell, so spaces and non-ASCII names need no quoting. The two
+//! exceptions are spelled out: Explorer parses its own command line, and a
+//! DBus call takes a file URI.
+
+use std::collections::HashMap;
+#[cfg(any(windows, test))]
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use tauri::{AppHandle, Emitter, Manager};
+#[cfg(target_os = "linux")]
+use tauri_plugin_shell::ShellExt;
+
+use crate::db::{self, DbState};
+use crate::media;
+use crate::thumbnails;
+
+/// Editors save in bursts (temp file, rename, metadata); a change counts
+/// once events have been quiet this long.
+const SETTLE: Duration = Duration::from_secs(1);
+/// A file stays watched this long after it was opened or last saved.
+const WATCH_FOR: Duration = Duration::from_secs(60 * 60);
+
+/// Select `path` in Finder, Explorer or the desktop's file manager.
+pub async fn reveal(app: &AppHandle, path: &Path) -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    {
+        let _ = app;
+        spawn(std::process::Command::new("open").arg("-R").arg(path))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = app;
+        spawn(std::process::Command::new("explorer").raw_arg(explorer_select_arg(path)))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        // FileManager1 is what Nautilus, Dolphin, Nemo and Thunar answer to
+        let item = format!("array:string:{}", file_uri(path));
+        let shown = app
+            .shell()
+            .command("dbus-send")
+            .args([
+                "--session",
+                "--print-reply",
+                "--dest=org.freedesktop.FileManager1",
+                "--type=method_call",
+                "/org/freedesktop/FileManager1",
+                "org.freedesktop.FileManager1.ShowItems",
+                item.as_str(),
+                "string:",
+            ])
+            .status()
+            .await
+            .is_ok_and(|status| status.success());
+        if shown {
+            return Ok(());
+        }
+        // No file manager on the bus: open the folder instead
+        debug!("FileManager1 unavailable, opening the folder of {}", path.display());
+        xdg_open(app, path.parent().unwrap_or(path)).await
+    }
+}
+
+/// Open `path` in the app the system uses for its type.
+pub async fn open_with_default_app(app: &AppHandle, path: &Path) -> Result<(), String> {
+    #[cfg(target_os = "macos")]
+    {
+        let _ = app;
+        spawn(std::process::Command::new("open").arg(path))
+    }
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt;
+        let _ = app;
+        let mut arg = OsString::from("\"");
+        arg.push(strip_verbatim(path));
+        arg.push("\"");
+        spawn(std::process::Command::new("explorer").raw_arg(arg))
+    }
+    #[cfg(target_os = "linux")]
+    {
+        xdg_open(app, path).await
+    }
+}
+
+#[cfg(any(target_os = "macos", windows))]
+fn spawn(command: &mut std::process::Command) -> Result<(), String> {
+    command.spawn().map(drop).map_err(|e| format!("Failed to start {:?}: {}", command.get_program(), e))
+}
+
+#[cfg(target_os = "linux")]
+async fn xdg_open(app: &AppHandle, path: &Path) -> Result<(), String> {
+    let status = app
+        .shell()
+        .command("xdg-open")
+        .arg(path)
+        .status()
+        .await
+        .map_err(|e| format!("Failed to run xdg-open: {}", e))?;
+    if !status.success() {
+        return Err(format!("xdg-open couldn't open {}", path.display()));
+    }
+    Ok(())
+}
+
+/// Explorer reads `/select,` and the path as one argument it splits itself,
+/// so the path is quoted by hand (Windows paths can't contain quotes).
+#[cfg(any(windows, test))]
+fn explorer_select_arg(path: &Path) -> OsString {
+    let mut arg = OsString::from("/select,\"");
+    arg.push(strip_verbatim(path));
+    arg.push("\"");
+    arg
+}
+
+/// `path` without the `\\?\` prefix canonicalize adds on Windows, which
+/// Explorer doesn't understand.
+#[cfg(any(windows, test))]
+fn strip_verbatim(path: &Path) -> PathBuf {
+    let text = path.to_string_lossy();
+    match text.strip_prefix(r"\\?\") {
+        Some(rest) if !rest.starts_with("UNC\\") => PathBuf::from(rest),
+        _ => path.to_path_buf(),
+    }
+}
+
+/// `file://` URI of an absolute path, percent-encoded byte by byte. Commas
+/// are encoded too, since dbus-send splits arrays on them.
+#[cfg(any(target_os = "linux", test))]
+fn file_uri(path: &Path) -> String {
+    let mut uri = String::from("file://");
+    for &byte in path.to_string_lossy().as_bytes() {
+        if byte.is_ascii_alphanumeric() || b"/-._~".contains(&byte) {
+            uri.push(byte as char);
+        } else {
+            uri.push_str(&format!("%{:02X}", byte));
+        }
+    }
+    uri
+}
+
+// ============================================================================
+// Watching external edits
+// ============================================================================
+
+/// Files open in another app, watched so their saves are picked up.
+#[derive(Default)]
+pub struct EditWatchState {
+    inner: Mutex<Option<EditWatcher>>,
+}
+
+struct EditWatcher {
+    watcher: RecommendedWatcher,
+    /// Watched file and when to stop watching it
+    files: HashMap<PathBuf, Instant>,
+}
+
+/// Watch `path` for the next hour (longer if it keeps being saved). Each
+/// save re-reads its dimensions and hash, drops stale thumbnails and emits
+/// `photo_changed`.
+pub fn watch_for_edit(app: &AppHandle, path: &Path) -> Result<(), String> {
+    let dir = path.parent().ok_or_else(|| format!("No folder for {}", path.display()))?;
+    let state = app.state::<EditWatchState>();
+    let mut inner = state.inner.lock().map_err(|_| "Edit watcher is unavailable".to_string())?;
+    if inner.is_none() {
+        let (tx, rx) = channel();
+        let watcher = notify::recommended_watcher(tx).map_err(|e| format!("Failed to start watching: {}", e))?;
+        *inner = Some(EditWatcher { watcher, files: HashMap::new() });
+        let app = app.clone();
+        std::thread::spawn(move || run_worker(&app, rx));
+    }
+    let Some(active) = inner.as_mut() else {
+        return Err("Edit watcher is unavailable".to_string());
+    };
+
+    // Editors often save by replacing the file, which ends a watch on the
+    // file itself, so its folder is watched instead
+    if !active.files.keys().any(|f| f.parent() == Some(dir)) {
+        active
+            .watcher
+            .watch(dir, RecursiveMode::NonRecursive)
+            .map_err(|e| format!("Failed to watch {}: {}", dir.display(), e))?;
+    }
+    active.files.insert(path.to_path_buf(), Instant::now() + WATCH_FOR);
+    debug!("Watching {} for external edits", path.display());
+    Ok(())
+}
+
+fn run_worker(app: &AppHandle, rx: Receiver<notify::Result<Event>>) {
+    // Watched files with events not yet acted on, and when the last came
+    let mut pending: HashMap<PathBuf, Instant> = HashMap::new();
+    loop {
+        match rx.recv_timeout(SETTLE) {
+            Ok(Ok(event)) if !matches!(event.kind, EventKind::Access(_)) => {
+                for path in event.paths {
+                    if is_watched(app, &path) {
+                        pending.insert(path, Instant::now());
+                    }
+                }
+            }
+            Ok(Ok(_)) | Err(RecvTimeoutError::Timeout) => {}
+            Ok(Err(e)) => warn!("Edit watcher error: {}", e),
+            Err(RecvTimeoutError::Disconnected) => break,
+        }
+
+        let settled: Vec<PathBuf> = pending
+            .iter()
+            .filter(|(_, last)| last.elapsed() >= SETTLE)
+            .map(|(path, _)| path.clone())
+            .collect();
+        for path in settled {
+            pending.remove(&path);
+            // Mid-replace the file can be briefly gone; the rename that
+            // brings it back is another event
+            if path.is_file() {
+                apply_edit(app, &path);
+            }
+        }
+        expire(app);
+    }
+}
+
+fn is_watched(app: &AppHandle, path: &Path) -> bool {
+    let state = app.state::<EditWatchState>();
+    let inner = state.inner.lock();
+    inner.is_ok_and(|inner| inner.as_ref().is_some_and(|active| active.files.contains_key(path)))
+}
+
+/// Stop watching files whose time is up, and folders left with none.
+fn expire(app: &AppHandle) {
+    let state = app.state::<EditWatchState>();
+    let Ok(mut inner) = state.inner.lock() else {
+        return;
+    };
+    let Some(active) = inner.as_mut() else {
+        return;
+    };
+    let now = Instant::now();
+    let expired: Vec<PathBuf> = active.files.iter().filter(|(_, until)| **until <= now).map(|(p, _)| p.clone()).collect();
+    for path in expired {
+        active.files.remove(&path);
+        let Some(dir) = path.parent() else {
+            continue;
+        };
+        if !active.files.keys().any(|f| f.parent() == Some(dir)) {
+            let _ = active.watcher.unwatch(dir);
+        }
+        debug!("Stopped watching {} for external edits", path.display());
+    }
+}
+
+/// Take in a save: new dimensions, hash and stats, and fresh thumbnails.
+fn apply_edit(app: &AppHandle, path: &Path) {
+    let path_str = path.to_string_lossy().to_string();
+    let hash = media::calculate_hash(path);
+    let db_state = app.state::<DbState>();
+    let Ok(conn) = db_state.0.lock() else {
+        return;
+    };
+    // Opening and closing without saving, or Terra's own rewrite
+    if hash.is_some() && db::get_content_hash(&conn, &path_str).ok().flatten() == hash {
+        return;
+    }
+
+    if let Some((size, mtime)) = media::file_stats(path) {
+        let _ = db::update_file_stats(&conn, &path_str, size, mtime);
+    }
+    let dimensions = media::read_dimensions(path);
+    let recorded = db::record_external_edit(&conn, &path_str, dimensions, hash.as_deref());
+    drop(conn);
+    match recorded {
+        Ok(Some((changed, orphaned))) => {
+            if let Some(hash) = orphaned {
+                thumbnails::remove_thumbnails(&hash);
+            }
+            info!("Picked up an external edit of {}", path_str);
+            let _ = app.emit("photo_changed", &changed);
+        }
+        Ok(None) => debug!("{} was edited but is no longer in the library", path_str),
+        Err(e) => error!("Failed to record the edit of {}: {}", path_str, e),
+    }
+
+    // Keep watching while the user keeps saving
+    if let Ok(mut inner) = app.state::<EditWatchState>().inner.lock() {
+        if let Some(until) = inner.as_mut().and_then(|active| active.files.get_mut(path)) {
+            *until = Instant::now() + WATCH_FOR;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_uris_encode_spaces_unicode_and_commas() {
+        assert_eq!(file_uri(Path::new("/home/ana/Pictures/a.jpg")), "file:///home/ana/Pictures/a.jpg");
+        assert_eq!(
+            file_uri(Path::new("/home/ana/Día de playa, 2024/IMG 1.jpg")),
+            "file:///home/ana/D%C3%ADa%20de%20playa%2C%202024/IMG%201.jpg"
+        );
+    }
+
+    #[test]
+    fn explorer_gets_a_quoted_select_argument_without_the_verbatim_prefix() {
+        assert_eq!(
+            explorer_select_arg(Path::new(r"C:\Users\Ana\Fotos de playa\día 1.jpg")),
+            OsString::from(r#"/select,"C:\Users\Ana\Fotos de playa\día 1.jpg""#)
+        );
+        assert_eq!(
+            explorer_select_arg(Path::new(r"\\?\C:\Photos\a b.jpg")),
+            OsString::from(r#"/select,"C:\Photos\a b.jpg""#)
+        );
+        // Network shares keep their long form
+        assert_eq!(strip_verbatim(Path::new(r"\\?\UNC\nas\photos")), PathBuf::from(r"\\?\UNC\nas\photos"));
+    }
+}
@@ -0,0 +1,379 @@
+//! Face detection for the People view. An ONNX detector (an Ultra-Light
+//! "version-RFB-320" model, or another with the same outputs) finds face
+//! regions; the user names them. There is no recognition: which faces
+//! belong to whom is only ever what the user assigned. Running the model
+//! needs the `faces` cargo feature; see onnx.rs.
+//!
+//! The model file goes in `models/` beside the database. Regions are
+//! stored as fractions of the upright photo, so they outlive thumbnail
+//! sizes. Each photo is scanned once per model version.
+//!
+//! An optional second model (an ArcFace-style embedder taking a 112px face)
+//! gives each face a vector; faces of the same person point the same way,
+//! so unnamed faces can be clustered into suggested people. Clusters are
+//! suggestions only: nothing is named until the user assigns a cluster.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::onnx;
+use crate::thumbnails;
+
+const MODEL_FILE: &str = "face-detector.onnx";
+const EMBEDDER_FILE: &str = "face-embedder.onnx";
+
+/// The detector's input, width by height
+const INPUT_WIDTH: u32 = 320;
+const INPUT_HEIGHT: u32 = 240;
+/// Maps pixels to (value - 127) / 128, as the detector was trained
+const MEAN: [f32; 3] = [127.0 / 255.0; 3];
+const STD: [f32; 3] = [128.0 / 255.0; 3];
+
+/// Side of the square face crop the embedder takes
+const EMBED_SIZE: u32 = 112;
+/// Maps pixels to (value - 127.5) / 127.5
+const EMBED_MEAN: [f32; 3] = [0.5; 3];
+const EMBED_STD: [f32; 3] = [0.5; 3];
+/// Crop margin around the detected box, as a fraction of its longer side
+const EMBED_MARGIN: f64 = 0.15;
+/// Photo size read when embedding, so small faces still give a usable crop
+const EMBED_SOURCE_SIZE: u32 = 1024;
+
+/// Cosine similarity at which two faces are linked into one cluster
+pub const SAME_PERSON_SIMILARITY: f32 = 0.5;
+/// Smaller clusters go to the unclustered bucket
+pub const MIN_CLUSTER_SIZE: usize = 3;
+/// Faces shown per cluster
+pub const CLUSTER_SAMPLES: usize = 6;
+
+/// Lowest score kept as a face
+const MIN_CONFIDENCE: f32 = 0.7;
+/// Overlap above which the weaker of two detections is dropped
+const NMS_IOU: f64 = 0.3;
+/// Overlap at which a new detection is taken to be a named face again
+pub const SAME_FACE_IOU: f64 = 0.5;
+/// Smaller regions, as a fraction of the photo's width or height, are
+/// too small to name
+const MIN_FACE_SIDE: f64 = 0.02;
+
+/// A detected face, in fractions of the upright photo's width and height
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct FaceRegion {
+    pub x: f64,
+    pub y: f64,
+    pub w: f64,
+    pub h: f64,
+    pub confidence: f64,
+}
+
+/// Intersection over union of two (x, y, w, h) boxes
+pub fn iou(a: (f64, f64, f64, f64), b: (f64, f64, f64, f64)) -> f64 {
+    let width = (a.0 + a.2).min(b.0 + b.2) - a.0.max(b.0);
+    let height = (a.1 + a.3).min(b.1 + b.3) - a.1.max(b.1);
+    if width <= 0.0 || height <= 0.0 {
+        return 0.0;
+    }
+    let intersection = width * height;
+    intersection / (a.2 * a.3 + b.2 * b.3 - intersection)
+}
+
+impl FaceRegion {
+    pub fn rect(&self) -> (f64, f64, f64, f64) {
+        (self.x, self.y, self.w, self.h)
+    }
+}
+
+/// Faces from the detector's outputs: `scores` as (background, face)
+/// pairs and `boxes` as (x1, y1, x2, y2) fractions, one per anchor.
+/// Overlapping detections are reduced to the strongest.
+fn decode(scores: &[f32], boxes: &[f32]) -> Vec<FaceRegion> {
+    let mut found: Vec<FaceRegion> = scores
+        .chunks_exact(2)
+        .zip(boxes.chunks_exact(4))
+        .filter(|(score, _)| score[1] >= MIN_CONFIDENCE)
+        .filter_map(|(score, corners)| {
+            let [x1, y1, x2, y2] = [0, 1, 2, 3].map(|i| f64::from(corners[i]).clamp(0.0, 1.0));
+            let region = FaceRegion { x: x1, y: y1, w: x2 - x1, h: y2 - y1, confidence: f64::from(score[1]) };
+            (region.w >= MIN_FACE_SIDE && region.h >= MIN_FACE_SIDE).then_some(region)
+        })
+        .collect();
+    found.sort_by(|a, b| b.confidence.total_cmp(&a.confidence));
+
+    let mut kept: Vec<FaceRegion> = Vec::new();
+    for region in found {
+        if kept.iter().all(|k| iou(k.rect(), region.rect()) <= NMS_IOU) {
+            kept.push(region);
+        }
+    }
+    kept
+}
+
+/// A detected face and, when the embedder is installed, its unit vector
+pub type DetectedFace = (FaceRegion, Option<Vec<f32>>);
+
+/// A loaded face detector, and embedder if there is one
+pub struct Detector {
+    model: onnx::Model,
+    embedder: Option<onnx::Model>,
+    /// Covers both models, so adding the embedder has photos scanned again
+    pub version: String,
+}
+
+impl Detector {
+    pub fn load() -> Result<Detector, String> {
+        let dir = onnx::models_dir();
+        let (path, embedder_path) = (dir.join(MODEL_FILE), dir.join(EMBEDDER_FILE));
+        if !path.exists() {
+            return Err(format!("Face detection needs a detector model at {}", path.display()));
+        }
+        let model = onnx::load(&path, [1, 3, INPUT_HEIGHT as usize, INPUT_WIDTH as usize])?;
+        if !embedder_path.exists() {
+            return Ok(Detector { model, embedder: None, version: onnx::files_version(&[&path])? });
+        }
+        let side = EMBED_SIZE as usize;
+        Ok(Detector {
+            model,
+            embedder: Some(onnx::load(&embedder_path, [1, 3, side, side])?),
+            version: onnx::files_version(&[&path, &embedder_path])?,
+        })
+    }
+
+    /// Faces in one photo, strongest first. Reads the upright cached
+    /// thumbnail when the photo has one, as auto-tagging does.
+    pub fn detect(&self, path: &Path, content_hash: Option<&str>) -> Result<Vec<DetectedFace>, String> {
+        let wanted = if self.embedder.is_some() { EMBED_SOURCE_SIZE } else { INPUT_WIDTH };
+        let source = match content_hash {
+            Some(hash) => thumbnails::generate_thumbnail(path, hash, thumbnails::bucket_size(wanted))?,
+            None => path.to_path_buf(),
+        };
+        let img = image::open(&source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+        // Stretched to fit: fractions of the input are fractions of the photo
+        let rgb = img.resize_exact(INPUT_WIDTH, INPUT_HEIGHT, image::imageops::FilterType::Triangle).to_rgb8();
+        let shape = [1, 3, INPUT_HEIGHT as usize, INPUT_WIDTH as usize];
+        let outputs = onnx::run(&self.model, &onnx::planar_rgb(&rgb, MEAN, STD), shape)?;
+        let regions = match outputs.as_slice() {
+            [scores, boxes, ..] => decode(scores, boxes),
+            _ => return Err("The face detector should give scores and boxes".to_string()),
+        };
+        let Some(embedder) = &self.embedder else {
+            return Ok(regions.into_iter().map(|region| (region, None)).collect());
+        };
+        regions
+            .into_iter()
+            .map(|region| Ok((region, Some(embed(embedder, &img, &region)?))))
+            .collect()
+    }
+}
+
+/// The face's embedding, scaled to unit length so a dot product is the
+/// cosine similarity
+fn embed(embedder: &onnx::Model, img: &image::DynamicImage, region: &FaceRegion) -> Result<Vec<f32>, String> {
+    let (width, height) = (f64::from(img.width()), f64::from(img.height()));
+    // A square around the face, padded a little, kept inside the photo
+    let side = (region.w * width).max(region.h * height) * (1.0 + 2.0 * EMBED_MARGIN);
+    let side = side.min(width).min(height).max(1.0);
+    let center_x = (region.x + region.w / 2.0) * width;
+    let center_y = (region.y + region.h / 2.0) * height;
+    let left = (center_x - side / 2.0).clamp(0.0, width - side);
+    let top = (center_y - side / 2.0).clamp(0.0, height - side);
+    let crop = img
+        .crop_imm(left as u32, top as u32, side as u32, side as u32)
+        .resize_exact(EMBED_SIZE, EMBED_SIZE, image::imageops::FilterType::Triangle)
+        .to_rgb8();
+    let side = EMBED_SIZE as usize;
+    let outputs = onnx::run(embedder, &onnx::planar_rgb(&crop, EMBED_MEAN, EMBED_STD), [1, 3, side, side])?;
+    let mut vector = outputs.into_iter().next().ok_or("The face embedder gave no output")?;
+    let norm = vector.iter().map(|v| v * v).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|v| *v /= norm);
+    }
+    Ok(vector)
+}
+
+/// Embeddings are stored as little-endian f32s
+pub fn embedding_to_blob(vector: &[f32]) -> Vec<u8> {
+    vector.iter().flat_map(|v| v.to_le_bytes()).collect()
+}
+
+pub fn embedding_from_blob(blob: &[u8]) -> Vec<f32> {
+    blob.chunks_exact(4).map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]])).collect()
+}
+
+/// Dot product with eight running sums, which the compiler turns into
+/// vector instructions; a plain fold can't be reordered that way
+fn dot(a: &[f32], b: &[f32]) -> f32 {
+    let mut sums = [0.0f32; 8];
+    let (a_chunks, b_chunks) = (a.chunks_exact(8), b.chunks_exact(8));
+    let tail: f32 = a_chunks.remainder().iter().zip(b_chunks.remainder()).map(|(x, y)| x * y).sum();
+    for (x, y) in a_chunks.zip(b_chunks) {
+        for ((sum, x), y) in sums.iter_mut().zip(x).zip(y) {
+            *sum += x * y;
+        }
+    }
+    sums.iter().sum::<f32>() + tail
+}
+
+/// Group unit `embeddings` into clusters: faces at least
+/// SAME_PERSON_SIMILARITY alike are linked, and linked faces share a cluster.
+/// Returns index groups of MIN_CLUSTER_SIZE or more, largest first; the
+/// rest are left out as unclustered.
+///
+/// Every pair is compared, spread over threads: for 20k 512-dimension
+/// faces that's 200M dot products, some seconds on a laptop. Links are
+/// joined as they're found, each thread in its own forest, so memory stays
+/// linear however alike the faces are.
+pub fn cluster(embeddings: &[Vec<f32>]) -> Vec<Vec<usize>> {
+    use rayon::prelude::*;
+
+    let n = embeddings.len();
+    let mut forest = (0..n)
+        .into_par_iter()
+        .fold(
+            || Forest::new(n),
+            |mut forest, i| {
+                for j in i + 1..n {
+                    // Already joined through other faces; no need to compare
+                    if forest.find(i) == forest.find(j) {
+                        continue;
+                    }
+                    if dot(&embeddings[i], &embeddings[j]) >= SAME_PERSON_SIMILARITY {
+                        forest.union(i, j);
+                    }
+                }
+                forest
+            },
+        )
+        .reduce(
+            || Forest::new(n),
+            |mut joined, mut other| {
+                for x in 0..n {
+                    let root = other.find(x);
+                    joined.union(x, root);
+                }
+                joined
+            },
+        );
+
+    let mut groups: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+    for i in 0..n {
+        let root = forest.find(i);
+        groups.entry(root).or_default().push(i);
+    }
+    let mut clusters: Vec<Vec<usize>> = groups.into_values().filter(|g| g.len() >= MIN_CLUSTER_SIZE).collect();
+    clusters.sort_by(|a, b| b.len().cmp(&a.len()).then(a[0].cmp(&b[0])));
+    clusters
+}
+
+/// Union-find over face indices.
+struct Forest {
+    parent: Vec<usize>,
+}
+
+impl Forest {
+    fn new(n: usize) -> Forest {
+        Forest { parent: (0..n).collect() }
+    }
+
+    fn find(&mut self, mut x: usize) -> usize {
+        while self.parent[x] != x {
+            self.parent[x] = self.parent[self.parent[x]];
+            x = self.parent[x];
+        }
+        x
+    }
+
+    fn union(&mut self, a: usize, b: usize) {
+        let (ra, rb) = (self.find(a), self.find(b));
+        if ra != rb {
+            self.parent[ra] = rb;
+        }
+    }
+}
+
+/// Where face detection stands, for settings
+#[derive(Serialize, Debug)]
+pub struct FaceDetectionStatus {
+    /// Whether this build can run the detector; as with auto-tagging
+    pub available: bool,
+    pub model_path: String,
+    pub model_version: Option<String>,
+    /// Where the optional embedder goes, which clustering needs
+    pub embedder_path: String,
+    pub embedder_installed: bool,
+}
+
+pub fn status() -> FaceDetectionStatus {
+    let dir = onnx::models_dir();
+    let (path, embedder_path) = (dir.join(MODEL_FILE), dir.join(EMBEDDER_FILE));
+    FaceDetectionStatus {
+        available: onnx::runtime_available(),
+        model_version: onnx::files_version(&[&path]).ok(),
+        model_path: path.to_string_lossy().to_string(),
+        embedder_installed: embedder_path.exists(),
+        embedder_path: embedder_path.to_string_lossy().to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overlap_of_boxes() {
+        assert_eq!(iou((0.0, 0.0, 0.2, 0.2), (0.5, 0.5, 0.2, 0.2)), 0.0);
+        assert!((iou((0.0, 0.0, 0.2, 0.2), (0.1, 0.0, 0.2, 0.2)) - 1.0 / 3.0).abs() < 1e-9);
+        assert!((iou((0.1, 0.1, 0.3, 0.3), (0.1, 0.1, 0.3, 0.3)) - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn keeps_the_strongest_of_overlapping_detections() {
+        let scores = [
+            0.1, 0.9, // a face
+            0.2, 0.8, // the same face, shifted a little
+            0.05, 0.95, // another face
+            0.6, 0.4, // background
+            0.1, 0.9, // too small to keep
+        ];
+        let boxes = [
+            0.10, 0.10, 0.30, 0.40, //
+            0.11, 0.10, 0.31, 0.41, //
+            0.60, 0.20, 0.80, 0.50, //
+            0.00, 0.00, 1.00, 1.00, //
+            0.50, 0.50, 0.51, 0.51,
+        ];
+        let faces = decode(&scores, &boxes);
+        assert_eq!(faces.len(), 2);
+        assert!((faces[0].x - 0.6).abs() < 1e-6 && (faces[0].confidence - 0.95).abs() < 1e-6);
+        assert!((faces[1].w - 0.2).abs() < 1e-6 && (faces[1].h - 0.3).abs() < 1e-6);
+    }
+
+    fn unit(v: &[f32]) -> Vec<f32> {
+        let norm = v.iter().map(|x| x * x).sum::<f32>().sqrt();
+        v.iter().map(|x| x / norm).collect()
+    }
+
+    #[test]
+    fn clusters_alike_faces_and_leaves_out_small_groups() {
+        let embeddings = vec![
+            unit(&[1.0, 0.1, 0.0]),
+            unit(&[0.0, 1.0, 0.0]),
+            unit(&[1.0, 0.0, 0.1]),
+            unit(&[0.0, 1.0, 0.2]),
+            unit(&[0.9, 0.2, 0.1]),
+            unit(&[1.0, 0.0, 0.0]),
+            unit(&[0.0, 0.0, 1.0]),
+        ];
+        // Two faces like [0, 1, 0] aren't enough for a cluster, and the last is alone
+        assert_eq!(cluster(&embeddings), vec![vec![0, 2, 4, 5]]);
+        assert!(cluster(&[]).is_empty());
+    }
+
+    #[test]
+    fn dot_product_covers_the_tail() {
+        let a: Vec<f32> = (1..=11).map(|x| x as f32).collect();
+        assert_eq!(dot(&a, &vec![1.0; 11]), 66.0);
+        let blob = embedding_to_blob(&[0.5, -2.0]);
+        assert_eq!(embedding_from_blob(&blob), vec![0.5, -2.0]);
+    }
+}
@@ -0,0 +1,259 @@
+//! Geotagging photos from a GPX track, for cameras without GPS carried
+//! alongside a phone that logged the walk.
+//!
+//! Each photo's capture time, moved by the caller's offset, is placed on
+//! the track: between two timed points the position is interpolated
+//! linearly, and the gap to the nearer point says how far the guess is from
+//! a real fix. Photos whose gap exceeds the tolerance (taken before the
+//! track starts, after it ends, or while the phone had no signal) are
+//! skipped. A wrong camera clock or time zone is the usual reason nothing
+//! matches, which is what the offset is for.
+
+use std::fs::File;
+use std::io::{BufRead, BufReader};
+use std::path::Path;
+
+use serde::Serialize;
+
+/// Furthest a photo may be from the nearest track point, unless the caller
+/// says otherwise
+pub const DEFAULT_TOLERANCE_SECONDS: i64 = 5 * 60;
+
+/// Value of `location_source` for coordinates taken from a GPX track
+pub const LOCATION_SOURCE_GPX: &str = "gpx";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct TrackPoint {
+    time: i64,
+    latitude: f64,
+    longitude: f64,
+}
+
+/// The timed points of every track and segment in a GPX file, in time order
+#[derive(Debug)]
+pub struct Track {
+    points: Vec<TrackPoint>,
+}
+
+/// A position on the track at some moment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Fix {
+    pub latitude: f64,
+    pub longitude: f64,
+    /// Seconds to the nearest recorded point
+    pub gap_seconds: i64,
+}
+
+impl Track {
+    pub fn open(path: &Path) -> Result<Track, String> {
+        let file = File::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+        Track::read(BufReader::new(file)).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+
+    pub fn read<R: BufRead>(reader: R) -> Result<Track, String> {
+        let gpx = gpx::read(reader).map_err(|e| format!("Not a readable GPX file: {}", e))?;
+        let mut points: Vec<TrackPoint> = gpx
+            .tracks
+            .iter()
+            .flat_map(|track| &track.segments)
+            .flat_map(|segment| &segment.points)
+            .filter_map(|waypoint| {
+                let time = waypoint.time.as_ref()?.format().ok()?;
+                let time = chrono::DateTime::parse_from_rfc3339(&time).ok()?.timestamp();
+                let point = waypoint.point();
+                Some(TrackPoint { time, latitude: point.y(), longitude: point.x() })
+            })
+            .collect();
+        if points.is_empty() {
+            return Err("The GPX file has no track points with times".to_string());
+        }
+        points.sort_by_key(|p| p.time);
+        points.dedup_by_key(|p| p.time);
+        Ok(Track { points })
+    }
+
+    /// First and last recorded times
+    pub fn span(&self) -> (i64, i64) {
+        (self.points[0].time, self.points[self.points.len() - 1].time)
+    }
+
+    /// Where the track was at `time`. Outside the track this is the nearer
+    /// end, with the gap to it.
+    pub fn locate(&self, time: i64) -> Fix {
+        let after = self.points.partition_point(|p| p.time < time);
+        let fix = |p: &TrackPoint| Fix { latitude: p.latitude, longitude: p.longitude, gap_seconds: (p.time - time).abs() };
+        if after == 0 {
+            return fix(&self.points[0]);
+        }
+        if after == self.points.len() {
+            return fix(&self.points[after - 1]);
+        }
+        let (a, b) = (&self.points[after - 1], &self.points[after]);
+        let t = (time - a.time) as f64 / (b.time - a.time) as f64;
+        // The short way round when the segment crosses the antimeridian
+        let mut delta_lon = b.longitude - a.longitude;
+        if delta_lon > 180.0 {
+            delta_lon -= 360.0;
+        } else if delta_lon < -180.0 {
+            delta_lon += 360.0;
+        }
+        let mut longitude = a.longitude + delta_lon * t;
+        if longitude > 180.0 {
+            longitude -= 360.0;
+        } else if longitude < -180.0 {
+            longitude += 360.0;
+        }
+        Fix {
+            latitude: a.latitude + (b.latitude - a.latitude) * t,
+            longitude,
+            gap_seconds: (time - a.time).min(b.time - time),
+        }
+    }
+}
+
+/// A photo placed on the track
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GeotagMatch {
+    pub path: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    pub gap_seconds: i64,
+    /// Whether the position was also written into the file's EXIF
+    pub exif_written: bool,
+}
+
+/// A photo left as it was, and why
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GeotagSkip {
+    pub path: String,
+    pub reason: String,
+    /// Seconds to the nearest track point, when the photo has a date
+    pub gap_seconds: Option<i64>,
+}
+
+/// Result of geotag_from_gpx
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct GeotagReport {
+    pub matched: Vec<GeotagMatch>,
+    pub skipped: Vec<GeotagSkip>,
+    /// False for a preview, which changes nothing
+    pub applied: bool,
+    /// The track's first and last times, to compare with the photos'
+    pub track_start: i64,
+    pub track_end: i64,
+}
+
+/// Place each photo (path and date_taken) on `track` after adding
+/// `offset_seconds` to its time. Photos further than `tolerance_seconds`
+/// from a recorded point, or without a date, are skipped.
+pub fn correlate(
+    track: &Track,
+    photos: &[(String, i64)],
+    offset_seconds: i64,
+    tolerance_seconds: i64,
+) -> (Vec<GeotagMatch>, Vec<GeotagSkip>) {
+    let mut matched = Vec::new();
+    let mut skipped = Vec::new();
+    for (path, date_taken) in photos {
+        if *date_taken == crate::media::UNKNOWN_DATE {
+            skipped.push(GeotagSkip { path: path.clone(), reason: "The photo has no capture date".to_string(), gap_seconds: None });
+            continue;
+        }
+        let fix = track.locate(date_taken + offset_seconds);
+        if fix.gap_seconds > tolerance_seconds {
+            skipped.push(GeotagSkip {
+                path: path.clone(),
+                reason: format!("No track point within {} seconds", tolerance_seconds),
+                gap_seconds: Some(fix.gap_seconds),
+            });
+            continue;
+        }
+        matched.push(GeotagMatch {
+            path: path.clone(),
+            latitude: fix.latitude,
+            longitude: fix.longitude,
+            gap_seconds: fix.gap_seconds,
+            exif_written: false,
+        });
+    }
+    (matched, skipped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gpx(points: &[(&str, f64, f64)]) -> Track {
+        let trkpts: String = points
+            .iter()
+            .map(|(time, lat, lon)| format!("<trkpt lat=\"{}\" lon=\"{}\"><time>{}</time></trkpt>", lat, lon, time))
+            .collect();
+        let xml = format!(
+            "<?xml version=\"1.0\"?><gpx version=\"1.1\" creator=\"test\" xmlns=\"http://www.topografix.com/GPX/1/1\">\
+             <trk><trkseg>{}</trkseg></trk></gpx>",
+            trkpts
+        );
+        Track::read(xml.as_bytes()).unwrap()
+    }
+
+    #[test]
+    fn interpolates_between_points_and_clamps_to_the_ends() {
+        // Listed out of order: points are sorted by time
+        let track = gpx(&[
+            ("2024-06-01T10:10:00Z", 46.2, 7.2),
+            ("2024-06-01T10:00:00Z", 46.0, 7.0),
+        ]);
+        let start = 1_717_236_000;
+        assert_eq!(track.span(), (start, start + 600));
+
+        let quarter = track.locate(start + 150);
+        assert!((quarter.latitude - 46.05).abs() < 1e-9);
+        assert!((quarter.longitude - 7.05).abs() < 1e-9);
+        assert_eq!(quarter.gap_seconds, 150);
+
+        let before = track.locate(start - 90);
+        assert_eq!((before.latitude, before.gap_seconds), (46.0, 90));
+        assert_eq!(track.locate(start + 700).gap_seconds, 100);
+    }
+
+    #[test]
+    fn crosses_the_antimeridian_the_short_way() {
+        let track = gpx(&[("2024-06-01T10:00:00Z", -17.0, 179.0), ("2024-06-01T10:00:10Z", -17.0, -179.0)]);
+        let middle = track.locate(1_717_236_005);
+        assert!((middle.longitude.abs() - 180.0).abs() < 1e-9);
+        let later = track.locate(1_717_236_008);
+        assert!((later.longitude - -179.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn skips_photos_outside_the_tolerance_or_undated() {
+        let track = gpx(&[("2024-06-01T10:00:00Z", 46.0, 7.0), ("2024-06-01T11:00:00Z", 46.5, 7.5)]);
+        let start = 1_717_236_000;
+        // A camera an hour behind the phone
+        let photos = vec![
+            ("/a.jpg".to_string(), start - 3_600 + 1_800),
+            ("/b.jpg".to_string(), start - 3_600 + 10),
+            ("/c.jpg".to_string(), start + 3 * 3_600),
+            ("/d.jpg".to_string(), crate::media::UNKNOWN_DATE),
+        ];
+        let (matched, skipped) = correlate(&track, &photos, 3_600, 60);
+        assert_eq!(matched.iter().map(|m| m.path.as_str()).collect::<Vec<_>>(), vec!["/b.jpg"]);
+        assert_eq!(matched[0].gap_seconds, 10);
+        assert_eq!(skipped.len(), 3);
+        assert_eq!(skipped[0].gap_seconds, Some(1_800));
+        assert_eq!(skipped[2].gap_seconds, None);
+
+        // With a wider tolerance the early photo takes the first point
+        let (matched, _) = correlate(&track, &photos, 3_600, 1_800);
+        assert_eq!(matched.len(), 2);
+        assert_eq!((matched[0].latitude, matched[0].gap_seconds), (46.0, 1_800));
+    }
+
+    #[test]
+    fn rejects_files_without_timed_points() {
+        let xml = "<?xml version=\"1.0\"?><gpx version=\"1.1\" creator=\"test\"><trk><trkseg>\
+                   <trkpt lat=\"1\" lon=\"2\"></trkpt></trkseg></trk></gpx>";
+        assert!(Track::read(xml.as_bytes()).unwrap_err().contains("no track points"));
+        assert!(Track::read("not xml".as_bytes()).is_err());
+    }
+}
@@ -0,0 +1,470 @@
+//! HEIC/HEIF container parsing. The image crate can't read these, so the
+//! primary image's size (`ispe`, rotated by `irot`) and the Exif item are
+//! read straight from the ISOBMFF boxes. Decoding pixels needs libheif and
+//! is behind the `heif` cargo feature.
+//!
+//! Layout: `ftyp`, then a `meta` box describing items (`pitm` primary item,
+//! `iinf` item types, `iloc` byte ranges, `iprp` properties), then `mdat`.
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// A larger `meta` box isn't a photo from any camera or phone.
+const MAX_META_BYTES: u64 = 4 * 1024 * 1024;
+/// Sanity cap on the Exif item.
+const MAX_EXIF_BYTES: u64 = 1024 * 1024;
+
+/// True for the extensions HEIF images use.
+pub(crate) fn is_heif(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| matches!(ext.to_lowercase().as_str(), "heic" | "heif" | "hif"))
+}
+
+/// Displayed width and height of the primary image: its `ispe`, swapped
+/// when `irot` turns it by 90 or 270 degrees.
+pub(crate) fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut file = File::open(path).ok()?;
+    let body = read_meta(&mut file)?;
+    parse_meta(&body)?.primary_dimensions()
+}
+
+/// The TIFF data (what rexif parses) of the Exif item.
+pub(crate) fn read_exif(path: &Path) -> Option<Vec<u8>> {
+    let mut file = File::open(path).ok()?;
+    let body = read_meta(&mut file)?;
+    let meta = parse_meta(&body)?;
+    let item = meta.items.iter().find(|(_, kind)| kind == b"Exif")?.0;
+    let payload = meta.item_data(item, &mut file)?;
+
+    // The item starts with the offset of the TIFF header past this field
+    // (usually 6, skipping "Exif\0\0")
+    let skip = u32::from_be_bytes(payload.get(0..4)?.try_into().ok()?) as usize;
+    let tiff = payload.get(4usize.checked_add(skip)?..)?;
+    if !(tiff.starts_with(b"II*\0") || tiff.starts_with(b"MM\0*")) {
+        return None;
+    }
+    Some(tiff.to_vec())
+}
+
+/// Decode the primary image with libheif, transformations applied.
+#[cfg(feature = "heif")]
+pub(crate) fn decode(path: &Path) -> Result<image::DynamicImage, String> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let path_str = path.to_str().ok_or_else(|| format!("unsupported path {}", path.display()))?;
+    let ctx = HeifContext::read_from_file(path_str).map_err(|e| format!("failed to read {}: {}", path.display(), e))?;
+    let handle = ctx
+        .primary_image_handle()
+        .map_err(|e| format!("no primary image in {}: {}", path.display(), e))?;
+    let decoded = LibHeif::new()
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|e| format!("failed to decode {}: {}", path.display(), e))?;
+    let plane = decoded
+        .planes()
+        .interleaved
+        .ok_or_else(|| format!("no RGB plane decoding {}", path.display()))?;
+
+    // Rows may be padded past width * 3
+    let row_len = plane.width as usize * 3;
+    let mut pixels = Vec::with_capacity(row_len * plane.height as usize);
+    for row in plane.data.chunks(plane.stride).take(plane.height as usize) {
+        pixels.extend_from_slice(row.get(..row_len).ok_or("short HEIF row")?);
+    }
+    image::RgbImage::from_raw(plane.width, plane.height, pixels)
+        .map(image::DynamicImage::ImageRgb8)
+        .ok_or_else(|| format!("bad HEIF plane size in {}", path.display()))
+}
+
+/// Body of the top-level `meta` box, found by hopping box headers so the
+/// (large) `mdat` is never read.
+fn read_meta(file: &mut File) -> Option<Vec<u8>> {
+    let len = file.metadata().ok()?.len();
+    let mut offset = 0u64;
+    while offset + 8 <= len {
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 8];
+        file.read_exact(&mut header).ok()?;
+        let kind: [u8; 4] = header[4..8].try_into().ok()?;
+        let (header_len, size) = match u32::from_be_bytes(header[0..4].try_into().ok()?) {
+            1 => {
+                let mut large = [0u8; 8];
+                file.read_exact(&mut large).ok()?;
+                (16, u64::from_be_bytes(large))
+            }
+            0 => (8, len - offset),
+            n => (8, u64::from(n)),
+        };
+        if size < header_len || (offset == 0 && &kind != b"ftyp") {
+            return None;
+        }
+        if &kind == b"meta" {
+            let body_len = size - header_len;
+            if body_len > MAX_META_BYTES {
+                return None;
+            }
+            let mut body = vec![0u8; body_len as usize];
+            file.read_exact(&mut body).ok()?;
+            return Some(body);
+        }
+        offset = offset.checked_add(size)?;
+    }
+    None
+}
+
+/// Big-endian reader over a box body.
+struct Reader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Reader { data, pos: 0 }
+    }
+
+    fn bytes(&mut self, n: usize) -> Option<&'a [u8]> {
+        let slice = self.data.get(self.pos..self.pos.checked_add(n)?)?;
+        self.pos += n;
+        Some(slice)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        Some(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Option<u16> {
+        Some(u16::from_be_bytes(self.bytes(2)?.try_into().ok()?))
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_be_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    /// An unsigned field of 0, 4 or 8 bytes, as `iloc` uses
+    fn sized(&mut self, size: u8) -> Option<u64> {
+        match size {
+            0 => Some(0),
+            4 => self.u32().map(u64::from),
+            8 => Some(u64::from_be_bytes(self.bytes(8)?.try_into().ok()?)),
+            _ => None,
+        }
+    }
+
+    /// Version and flags of a full box
+    fn full_box(&mut self) -> Option<(u8, u32)> {
+        let version = self.u8()?;
+        let flags = self.bytes(3)?;
+        Some((version, u32::from_be_bytes([0, flags[0], flags[1], flags[2]])))
+    }
+
+    fn rest(&mut self) -> &'a [u8] {
+        let rest = self.data.get(self.pos..).unwrap_or_default();
+        self.pos = self.data.len();
+        rest
+    }
+}
+
+/// Child boxes of a container body, as (type, body).
+fn boxes(mut data: &[u8]) -> impl Iterator<Item = ([u8; 4], &[u8])> {
+    std::iter::from_fn(move || {
+        let mut r = Reader::new(data);
+        let size = r.u32()? as usize;
+        let kind: [u8; 4] = r.bytes(4)?.try_into().ok()?;
+        let (header_len, size) = match size {
+            1 => (16, usize::try_from(u64::from_be_bytes(r.bytes(8)?.try_into().ok()?)).ok()?),
+            0 => (8, data.len()),
+            n => (8, n),
+        };
+        if size < header_len || size > data.len() {
+            return None;
+        }
+        let body = &data[header_len..size];
+        data = &data[size..];
+        Some((kind, body))
+    })
+}
+
+struct Location {
+    /// 0 = file offsets, 1 = inside `idat`
+    method: u8,
+    base_offset: u64,
+    extents: Vec<(u64, u64)>,
+}
+
+#[derive(Default)]
+struct Meta<'a> {
+    primary: Option<u32>,
+    items: Vec<(u32, [u8; 4])>,
+    locations: HashMap<u32, Location>,
+    /// Property boxes in `ipco`, referenced 1-based by `ipma`
+    properties: Vec<([u8; 4], &'a [u8])>,
+    associations: HashMap<u32, Vec<u16>>,
+    idat: &'a [u8],
+}
+
+fn parse_meta(body: &[u8]) -> Option<Meta<'_>> {
+    let mut r = Reader::new(body);
+    r.full_box()?;
+    let mut meta = Meta::default();
+    for (kind, child) in boxes(r.rest()) {
+        match &kind {
+            b"pitm" => {
+                let mut r = Reader::new(child);
+                let (version, _) = r.full_box()?;
+                meta.primary = Some(if version == 0 { u32::from(r.u16()?) } else { r.u32()? });
+            }
+            b"iinf" => meta.items = parse_iinf(child)?,
+            b"iloc" => meta.locations = parse_iloc(child)?,
+            b"iprp" => {
+                for (kind, child) in boxes(child) {
+                    match &kind {
+                        b"ipco" => meta.properties = boxes(child).collect(),
+                        b"ipma" => meta.associations = parse_ipma(child)?,
+                        _ => {}
+                    }
+                }
+            }
+            b"idat" => meta.idat = child,
+            _ => {}
+        }
+    }
+    Some(meta)
+}
+
+fn parse_iinf(body: &[u8]) -> Option<Vec<(u32, [u8; 4])>> {
+    let mut r = Reader::new(body);
+    let (version, _) = r.full_box()?;
+    // Entry count; the infe boxes follow
+    let _ = if version == 0 { u32::from(r.u16()?) } else { r.u32()? };
+    let mut items = Vec::new();
+    for (kind, infe) in boxes(r.rest()) {
+        if &kind != b"infe" {
+            continue;
+        }
+        let mut r = Reader::new(infe);
+        let (version, _) = r.full_box()?;
+        // Versions 0 and 1 predate item types; HEIF requires 2 or 3
+        if version < 2 {
+            continue;
+        }
+        let id = if version == 2 { u32::from(r.u16()?) } else { r.u32()? };
+        r.u16()?; // protection index
+        items.push((id, r.bytes(4)?.try_into().ok()?));
+    }
+    Some(items)
+}
+
+fn parse_iloc(body: &[u8]) -> Option<HashMap<u32, Location>> {
+    let mut r = Reader::new(body);
+    let (version, _) = r.full_box()?;
+    let sizes = r.u8()?;
+    let (offset_size, length_size) = (sizes >> 4, sizes & 0x0F);
+    let sizes = r.u8()?;
+    let base_offset_size = sizes >> 4;
+    let index_size = if version == 1 || version == 2 { sizes & 0x0F } else { 0 };
+    let count = if version < 2 { u32::from(r.u16()?) } else { r.u32()? };
+
+    let mut locations = HashMap::new();
+    for _ in 0..count {
+        let id = if version < 2 { u32::from(r.u16()?) } else { r.u32()? };
+        let method = if version == 1 || version == 2 { (r.u16()? & 0x0F) as u8 } else { 0 };
+        r.u16()?; // data reference index
+        let base_offset = r.sized(base_offset_size)?;
+        let extent_count = r.u16()?;
+        let mut extents = Vec::with_capacity(usize::from(extent_count));
+        for _ in 0..extent_count {
+            r.sized(index_size)?;
+            extents.push((r.sized(offset_size)?, r.sized(length_size)?));
+        }
+        locations.insert(id, Location { method, base_offset, extents });
+    }
+    Some(locations)
+}
+
+fn parse_ipma(body: &[u8]) -> Option<HashMap<u32, Vec<u16>>> {
+    let mut r = Reader::new(body);
+    let (version, flags) = r.full_box()?;
+    let count = r.u32()?;
+    let mut associations = HashMap::new();
+    for _ in 0..count {
+        let id = if version < 1 { u32::from(r.u16()?) } else { r.u32()? };
+        let n = r.u8()?;
+        let mut indices = Vec::with_capacity(usize::from(n));
+        for _ in 0..n {
+            // High bit is "essential"; the rest is a 1-based ipco index
+            let index = if flags & 1 == 1 { r.u16()? & 0x7FFF } else { u16::from(r.u8()? & 0x7F) };
+            indices.push(index);
+        }
+        associations.insert(id, indices);
+    }
+    Some(associations)
+}
+
+impl Meta<'_> {
+    fn item_properties(&self, item: u32) -> impl Iterator<Item = &([u8; 4], &[u8])> {
+        self.associations
+            .get(&item)
+            .into_iter()
+            .flatten()
+            .filter_map(|&index| self.properties.get(usize::from(index).checked_sub(1)?))
+    }
+
+    fn primary_dimensions(&self) -> Option<(u32, u32)> {
+        let primary = self.primary?;
+        let mut size = None;
+        let mut quarter_turns = 0;
+        for (kind, body) in self.item_properties(primary) {
+            let mut r = Reader::new(body);
+            match kind {
+                b"ispe" => {
+                    r.full_box()?;
+                    size = Some((r.u32()?, r.u32()?));
+                }
+                b"irot" => quarter_turns = r.u8()? & 0x03,
+                _ => {}
+            }
+        }
+        let (width, height) = size.filter(|&(w, h)| w > 0 && h > 0)?;
+        Some(if quarter_turns % 2 == 1 { (height, width) } else { (width, height) })
+    }
+
+    /// Concatenated extents of an item, from the file or `idat`.
+    fn item_data(&self, item: u32, file: &mut File) -> Option<Vec<u8>> {
+        let location = self.locations.get(&item)?;
+        let total: u64 = location.extents.iter().map(|&(_, len)| len).sum();
+        if total == 0 || total > MAX_EXIF_BYTES {
+            return None;
+        }
+        let mut data = Vec::with_capacity(total as usize);
+        for &(offset, len) in &location.extents {
+            let start = location.base_offset.checked_add(offset)?;
+            match location.method {
+                0 => {
+                    file.seek(SeekFrom::Start(start)).ok()?;
+                    let mut chunk = vec![0u8; len as usize];
+                    file.read_exact(&mut chunk).ok()?;
+                    data.extend_from_slice(&chunk);
+                }
+                1 => {
+                    let start = usize::try_from(start).ok()?;
+                    data.extend_from_slice(self.idat.get(start..start.checked_add(len as usize)?)?);
+                }
+                _ => return None,
+            }
+        }
+        Some(data)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    fn bx(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn full(kind: &[u8; 4], version: u8, flags: u32, body: &[u8]) -> Vec<u8> {
+        let mut content = vec![version];
+        content.extend_from_slice(&flags.to_be_bytes()[1..]);
+        content.extend_from_slice(body);
+        bx(kind, &content)
+    }
+
+    /// TIFF block with DateTime 2019:07:17 10:30:00 in IFD0
+    pub(crate) fn exif_tiff() -> Vec<u8> {
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&0x0132u16.to_be_bytes()); // DateTime
+        tiff.extend_from_slice(&2u16.to_be_bytes()); // ASCII
+        tiff.extend_from_slice(&20u32.to_be_bytes());
+        tiff.extend_from_slice(&26u32.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes()); // no next IFD
+        tiff.extend_from_slice(b"2019:07:17 10:30:00\0");
+        tiff
+    }
+
+    /// A structurally complete HEIC: a primary `hvc1` item with `ispe` and
+    /// `irot`, and an Exif item in `mdat`. The coded image itself is absent,
+    /// which is all the parsing here needs.
+    pub(crate) fn heic_fixture(width: u32, height: u32, quarter_turns: u8, tiff: &[u8]) -> Vec<u8> {
+        let mut exif = 6u32.to_be_bytes().to_vec();
+        exif.extend_from_slice(b"Exif\0\0");
+        exif.extend_from_slice(tiff);
+
+        let ftyp = bx(b"ftyp", b"heic\0\0\0\0mif1heic");
+        let meta_for = |exif_offset: u32| {
+            let pitm = full(b"pitm", 0, 0, &1u16.to_be_bytes());
+            let mut entries = 2u16.to_be_bytes().to_vec();
+            entries.extend(full(b"infe", 2, 0, &[&1u16.to_be_bytes()[..], &[0, 0], b"hvc1"].concat()));
+            entries.extend(full(b"infe", 2, 0, &[&2u16.to_be_bytes()[..], &[0, 0], b"Exif"].concat()));
+            let iinf = full(b"iinf", 0, 0, &entries);
+
+            let mut iloc = vec![0x44, 0x00];
+            iloc.extend_from_slice(&1u16.to_be_bytes()); // item count
+            iloc.extend_from_slice(&2u16.to_be_bytes()); // item id
+            iloc.extend_from_slice(&0u16.to_be_bytes()); // data reference
+            iloc.extend_from_slice(&1u16.to_be_bytes()); // extent count
+            iloc.extend_from_slice(&exif_offset.to_be_bytes());
+            iloc.extend_from_slice(&(exif.len() as u32).to_be_bytes());
+            let iloc = full(b"iloc", 0, 0, &iloc);
+
+            let ispe = full(b"ispe", 0, 0, &[width.to_be_bytes(), height.to_be_bytes()].concat());
+            let ipco = bx(b"ipco", &[ispe, bx(b"irot", &[quarter_turns])].concat());
+            let mut ipma = 1u32.to_be_bytes().to_vec();
+            ipma.extend_from_slice(&1u16.to_be_bytes());
+            ipma.extend_from_slice(&[2, 0x01, 0x82]);
+            let iprp = bx(b"iprp", &[ipco, full(b"ipma", 0, 0, &ipma)].concat());
+
+            full(b"meta", 0, 0, &[pitm, iinf, iloc, iprp].concat())
+        };
+        // The meta box's size doesn't depend on the offset it records
+        let exif_offset = (ftyp.len() + meta_for(0).len() + 8) as u32;
+        [ftyp, meta_for(exif_offset), bx(b"mdat", &exif)].concat()
+    }
+
+    pub(crate) fn write_fixture(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("terra-heif-{}-{}.heic", name, std::process::id()));
+        std::fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    #[test]
+    fn reads_primary_dimensions() {
+        let path = write_fixture("dims", &heic_fixture(4032, 3024, 0, &exif_tiff()));
+        assert_eq!(dimensions(&path), Some((4032, 3024)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rotation_swaps_dimensions() {
+        let path = write_fixture("rotated", &heic_fixture(4032, 3024, 3, &exif_tiff()));
+        assert_eq!(dimensions(&path), Some((3024, 4032)));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn extracts_the_exif_item() {
+        let path = write_fixture("exif", &heic_fixture(64, 48, 0, &exif_tiff()));
+        assert_eq!(read_exif(&path), Some(exif_tiff()));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn rejects_files_that_are_not_heif() {
+        let path = write_fixture("jpeg", &[0xFF, 0xD8, 0xFF, 0xE0, 0, 16, b'J', b'F', b'I', b'F']);
+        assert_eq!(dimensions(&path), None);
+        assert_eq!(read_exif(&path), None);
+        let _ = std::fs::remove_file(&path);
+
+        assert!(is_heif(Path::new("/p/IMG_0001.HEIC")));
+        assert!(!is_heif(Path::new("/p/IMG_0001.jpg")));
+    }
+}
@@ -0,0 +1,506 @@
+//! Background jobs: hash backfill, file sizes, perceptual hashes,
+//! thumbnails, place names and auto tags, run off the command threads with
+//! their state in the `jobs` table.
+//!
+//! Each job saves its results a chunk at a time and selects only the photos
+//! still lacking them, so one interrupted by quitting is queued again at the
+//! next launch and carries on where it stopped. At most MAX_IO_JOBS jobs
+//! that mostly read files run at once; CPU-bound ones overlap up to
+//! MAX_CPU_JOBS. Progress is sent as `job_progress` events carrying the
+//! job's db::JobRecord.
+
+use std::collections::{HashMap, VecDeque};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use lazy_static::lazy_static;
+use log::{debug, error, info, warn};
+use rayon::prelude::*;
+use reverse_geocoder::ReverseGeocoder;
+use serde::{Deserialize, Serialize};
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::auto_tag;
+use crate::db::{self, DbState};
+use crate::faces;
+use crate::media::{self, GEOCODER_LOCATIONS};
+use crate::placeholders;
+use crate::places;
+use crate::quality;
+
+/// IO-heavy jobs running at once; more would only make the disk seek
+const MAX_IO_JOBS: usize = 1;
+
+/// CPU-bound jobs running at once, sharing JOB_POOL
+const MAX_CPU_JOBS: usize = 2;
+
+/// Photos handled between saves and cancellation checks
+const JOB_CHUNK: usize = 200;
+
+lazy_static! {
+    /// Threads for the jobs' parallel work, one short of the machine so
+    /// commands and the UI stay responsive.
+    static ref JOB_POOL: rayon::ThreadPool = rayon::ThreadPoolBuilder::new()
+        .num_threads(std::thread::available_parallelism().map_or(2, |n| n.get().saturating_sub(1).max(1)))
+        .thread_name(|i| format!("terra-job-worker-{}", i))
+        .build()
+        .expect("Failed to build the job thread pool");
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum JobKind {
+    /// Content hashes for photos imported without one
+    HashBackfill,
+    /// File sizes and MIME types, as populate_file_sizes
+    FileSizes,
+    /// Perceptual hashes for similar-photo search
+    PerceptualHashes,
+    /// Missing grid thumbnails. With `{"prewarm": true}` embedded previews
+    /// go first, then full thumbnails replace them.
+    Thumbnails,
+    /// City and country for photos with coordinates; see places.rs
+    Geocode,
+    /// Tags from the on-device classifier; see auto_tag.rs. Takes
+    /// `{"threshold": 0.3}`, the lowest probability kept.
+    AutoTag,
+    /// Face regions from the on-device detector; see faces.rs
+    DetectFaces,
+    /// Sharpness and exposure scores for culling; see quality.rs
+    Quality,
+    /// Grid placeholders for photos thumbnailed before they were made,
+    /// read from the cached thumbnails; see placeholders.rs
+    Placeholders,
+}
+
+impl JobKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            JobKind::HashBackfill => "hash_backfill",
+            JobKind::FileSizes => "file_sizes",
+            JobKind::PerceptualHashes => "perceptual_hashes",
+            JobKind::Thumbnails => "thumbnails",
+            JobKind::Geocode => "geocode",
+            JobKind::AutoTag => "auto_tag",
+            JobKind::DetectFaces => "detect_faces",
+            JobKind::Quality => "quality",
+            JobKind::Placeholders => "placeholders",
+        }
+    }
+
+    fn parse(name: &str) -> Option<JobKind> {
+        ALL_KINDS.into_iter().find(|kind| kind.as_str() == name)
+    }
+
+    /// Whether the job's time goes to reading files rather than computing
+    fn io_heavy(self) -> bool {
+        matches!(self, JobKind::HashBackfill | JobKind::FileSizes)
+    }
+}
+
+const ALL_KINDS: [JobKind; 9] = [
+    JobKind::HashBackfill,
+    JobKind::FileSizes,
+    JobKind::PerceptualHashes,
+    JobKind::Thumbnails,
+    JobKind::Geocode,
+    JobKind::AutoTag,
+    JobKind::DetectFaces,
+    JobKind::Quality,
+    JobKind::Placeholders,
+];
+
+struct PendingJob {
+    id: i64,
+    kind: JobKind,
+    params: serde_json::Value,
+}
+
+#[derive(Default)]
+struct QueueState {
+    pending: VecDeque<PendingJob>,
+    /// Running jobs' kinds and cancel flags, by id
+    running: HashMap<i64, (JobKind, Arc<AtomicBool>)>,
+}
+
+/// The job scheduler, held in Tauri managed state.
+#[derive(Default)]
+pub struct JobQueue(Mutex<QueueState>);
+
+/// Index of the first pending job that fits beside the running ones
+fn next_runnable(pending: &VecDeque<PendingJob>, running: impl Iterator<Item = JobKind>) -> Option<usize> {
+    let (io, cpu) = running.fold((0, 0), |(io, cpu), kind| if kind.io_heavy() { (io + 1, cpu) } else { (io, cpu + 1) });
+    pending
+        .iter()
+        .position(|job| if job.kind.io_heavy() { io < MAX_IO_JOBS } else { cpu < MAX_CPU_JOBS })
+}
+
+impl JobQueue {
+    /// Queue a job and start it if there's room. A job of the same kind and
+    /// params still waiting is reused rather than queued twice.
+    pub fn enqueue(&self, app: &AppHandle, kind: JobKind, params: serde_json::Value) -> Result<i64, String> {
+        {
+            let state = self.0.lock().map_err(|e| format!("Job queue error: {}", e))?;
+            if let Some(waiting) = state.pending.iter().find(|job| job.kind == kind && job.params == params) {
+                return Ok(waiting.id);
+            }
+        }
+        let id = crate::with_db_write(&app.state::<DbState>(), "Failed to queue job", |c| {
+            db::insert_job(c, kind.as_str(), &params)
+        })?;
+        self.0
+            .lock()
+            .map_err(|e| format!("Job queue error: {}", e))?
+            .pending
+            .push_back(PendingJob { id, kind, params });
+        publish(app, id);
+        self.schedule(app);
+        Ok(id)
+    }
+
+    /// Cancel a queued job, or ask a running one to stop after its current
+    /// chunk. False if the job isn't queued or running.
+    pub fn cancel(&self, app: &AppHandle, id: i64) -> Result<bool, String> {
+        let mut state = self.0.lock().map_err(|e| format!("Job queue error: {}", e))?;
+        if let Some((_, cancelled)) = state.running.get(&id) {
+            cancelled.store(true, Ordering::SeqCst);
+            return Ok(true);
+        }
+        let Some(index) = state.pending.iter().position(|job| job.id == id) else {
+            return Ok(false);
+        };
+        state.pending.remove(index);
+        drop(state);
+        crate::with_db_write(&app.state::<DbState>(), "Failed to cancel job", |c| {
+            db::set_job_status(c, id, "cancelled", None)
+        })?;
+        publish(app, id);
+        Ok(true)
+    }
+
+    /// Queue again the jobs the last session didn't finish. Called once at startup.
+    pub fn resume(&self, app: &AppHandle) {
+        let jobs = match crate::with_db(&app.state::<DbState>(), "Failed to load jobs", db::requeue_unfinished_jobs) {
+            Ok(jobs) => jobs,
+            Err(e) => {
+                warn!("{}", e);
+                return;
+            }
+        };
+        let Ok(mut state) = self.0.lock() else {
+            return;
+        };
+        for job in jobs {
+            match JobKind::parse(&job.kind) {
+                Some(kind) => {
+                    info!("Resuming {} job {}", job.kind, job.id);
+                    state.pending.push_back(PendingJob { id: job.id, kind, params: job.params });
+                }
+                None => warn!("Dropping job {} of unknown kind {}", job.id, job.kind),
+            }
+        }
+        drop(state);
+        self.schedule(app);
+    }
+
+    /// Start every pending job there's room for, each on its own thread.
+    fn schedule(&self, app: &AppHandle) {
+        let Ok(mut state) = self.0.lock() else {
+            return;
+        };
+        while let Some(index) = next_runnable(&state.pending, state.running.values().map(|(kind, _)| *kind)) {
+            let job = state.pending.remove(index).expect("index from next_runnable");
+            let id = job.id;
+            let cancelled = Arc::new(AtomicBool::new(false));
+            state.running.insert(id, (job.kind, cancelled.clone()));
+            let app = app.clone();
+            let spawned = std::thread::Builder::new()
+                .name(format!("terra-job-{}", id))
+                .spawn(move || run_job(&app, job, cancelled));
+            if let Err(e) = spawned {
+                // Left queued in the table, so the next launch tries again
+                error!("Failed to start job {}: {}", id, e);
+                state.running.remove(&id);
+                break;
+            }
+        }
+    }
+
+    fn finished(&self, app: &AppHandle, id: i64) {
+        if let Ok(mut state) = self.0.lock() {
+            state.running.remove(&id);
+        }
+        self.schedule(app);
+    }
+}
+
+/// Send a job's current row as a `job_progress` event
+fn publish(app: &AppHandle, id: i64) {
+    let db_state = app.state::<DbState>();
+    if let Ok(Some(job)) = crate::with_db(&db_state, "Failed to read job", |c| db::get_job(c, id)) {
+        let _ = app.emit("job_progress", job);
+    }
+}
+
+/// What a running job sees of the queue
+struct JobContext<'a> {
+    app: &'a AppHandle,
+    id: i64,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl JobContext<'_> {
+    fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn progress(&self, processed: usize, total: usize) {
+        let db_state = self.app.state::<DbState>();
+        let _ = crate::with_db(&db_state, "Failed to record job progress", |c| {
+            db::set_job_progress(c, self.id, processed as i64, total as i64)
+        });
+        publish(self.app, self.id);
+    }
+
+    fn db(&self) -> tauri::State<'_, DbState> {
+        self.app.state::<DbState>()
+    }
+}
+
+fn run_job(app: &AppHandle, job: PendingJob, cancelled: Arc<AtomicBool>) {
+    let ctx = JobContext { app, id: job.id, cancelled };
+    let _ = crate::with_db_write(&ctx.db(), "Failed to start job", |c| db::set_job_status(c, job.id, "running", None));
+    publish(app, job.id);
+
+    let result = match job.kind {
+        JobKind::HashBackfill => backfill_hashes(&ctx),
+        JobKind::FileSizes => populate_file_sizes(&ctx),
+        JobKind::PerceptualHashes => compute_perceptual_hashes(&ctx),
+        JobKind::Thumbnails => generate_thumbnails(&ctx, &job.params),
+        JobKind::Geocode => geocode_photos(&ctx),
+        JobKind::AutoTag => auto_tag_photos(&ctx, &job.params),
+        JobKind::DetectFaces => detect_faces(&ctx),
+        JobKind::Quality => analyze_quality(&ctx),
+        JobKind::Placeholders => backfill_placeholders(&ctx),
+    };
+    let (status, error) = match result {
+        Ok(()) if ctx.is_cancelled() => ("cancelled", None),
+        Ok(()) => ("completed", None),
+        Err(e) => {
+            warn!("{} job {} failed: {}", job.kind.as_str(), job.id, e);
+            ("failed", Some(e))
+        }
+    };
+    let _ = crate::with_db_write(&ctx.db(), "Failed to finish job", |c| {
+        db::set_job_status(c, job.id, status, error.as_deref())
+    });
+    publish(app, job.id);
+    app.state::<JobQueue>().finished(app, job.id);
+}
+
+/// Work through `items` a chunk at a time: `compute` runs on JOB_POOL,
+/// `save` stores each chunk's results before the next begins.
+fn in_chunks<I: Sync, T: Send>(
+    ctx: &JobContext,
+    items: &[I],
+    compute: impl Fn(&I) -> Option<T> + Sync,
+    save: impl Fn(&rusqlite::Connection, &[T]) -> rusqlite::Result<()>,
+) -> Result<(), String> {
+    let total = items.len();
+    ctx.progress(0, total);
+    let mut processed = 0;
+    for chunk in items.chunks(JOB_CHUNK) {
+        if ctx.is_cancelled() {
+            break;
+        }
+        let results: Vec<T> = JOB_POOL.install(|| chunk.par_iter().filter_map(&compute).collect());
+        crate::with_db_write(&ctx.db(), "Failed to save job results", |c| save(c, &results))?;
+        processed += chunk.len();
+        ctx.progress(processed, total);
+    }
+    Ok(())
+}
+
+fn backfill_hashes(ctx: &JobContext) -> Result<(), String> {
+    let paths = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_without_content_hash)?;
+    in_chunks(
+        ctx,
+        &paths,
+        |path| media::calculate_hash(Path::new(path)).map(|hash| (path.clone(), hash)),
+        db::update_content_hashes_batch,
+    )
+}
+
+/// A missing file keeps a NULL size, as with populate_file_sizes
+fn populate_file_sizes(ctx: &JobContext) -> Result<(), String> {
+    let paths = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_without_file_size)?;
+    in_chunks(
+        ctx,
+        &paths,
+        |path| Some(crate::file_info(path)),
+        db::update_file_info_batch,
+    )
+}
+
+fn compute_perceptual_hashes(ctx: &JobContext) -> Result<(), String> {
+    let paths: Vec<String> = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_without_phash)?
+        .into_iter()
+        .filter(|p| !media::is_video(Path::new(p)))
+        .collect();
+    in_chunks(
+        ctx,
+        &paths,
+        |path| media::compute_phash_rotations(Path::new(path)).map(|hashes| (path.clone(), hashes)),
+        db::update_phashes_batch,
+    )
+}
+
+/// Photos whose coordinates match no place (out of range, or 0,0) stay
+/// without one and are looked at again by the next run.
+fn geocode_photos(ctx: &JobContext) -> Result<(), String> {
+    let photos = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_to_geocode)?;
+    if photos.is_empty() {
+        return Ok(());
+    }
+    let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+    in_chunks(
+        ctx,
+        &photos,
+        |&(id, lat, lon)| places::resolve(lat, lon, &geocoder).map(|place| (id, place)),
+        db::set_photo_places_batch,
+    )
+}
+
+/// A photo the model can't read isn't recorded as looked at, so the next
+/// run tries it again.
+fn auto_tag_photos(ctx: &JobContext, params: &serde_json::Value) -> Result<(), String> {
+    let threshold = params
+        .get("threshold")
+        .and_then(|v| v.as_f64())
+        .map_or(auto_tag::DEFAULT_THRESHOLD, |t| t as f32);
+    let classifier = auto_tag::Classifier::load()?;
+    let photos = crate::with_db(&ctx.db(), "Failed to get photos", |c| {
+        db::get_photos_to_auto_tag(c, &classifier.version)
+    })?;
+    in_chunks(
+        ctx,
+        &photos,
+        |(id, path, content_hash)| {
+            let labels = classifier
+                .classify(Path::new(path), content_hash.as_deref(), threshold)
+                .map_err(|e| debug!("Couldn't auto-tag {}: {}", path, e))
+                .ok()?;
+            Some((*id, labels))
+        },
+        |c, predictions| db::save_auto_tags(c, &classifier.version, auto_tag::AUTO_TAG_COLOR, predictions),
+    )
+}
+
+/// Like auto-tagging, an unreadable photo is left for the next run.
+fn detect_faces(ctx: &JobContext) -> Result<(), String> {
+    let detector = faces::Detector::load()?;
+    let photos = crate::with_db(&ctx.db(), "Failed to get photos", |c| {
+        db::get_photos_to_scan_faces(c, &detector.version)
+    })?;
+    in_chunks(
+        ctx,
+        &photos,
+        |(id, path, content_hash)| {
+            let regions = detector
+                .detect(Path::new(path), content_hash.as_deref())
+                .map_err(|e| debug!("Couldn't detect faces in {}: {}", path, e))
+                .ok()?;
+            Some((*id, regions))
+        },
+        |c, detections| db::save_faces(c, &detector.version, detections),
+    )
+}
+
+/// An unreadable photo is marked checked without a score
+fn analyze_quality(ctx: &JobContext) -> Result<(), String> {
+    let photos = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_to_score_quality)?;
+    in_chunks(
+        ctx,
+        &photos,
+        |(id, path, content_hash)| {
+            let score = quality::analyze(Path::new(path), content_hash.as_deref())
+                .map_err(|e| debug!("Couldn't score {}: {}", path, e))
+                .ok();
+            Some((*id, score))
+        },
+        db::save_quality_scores,
+    )
+}
+
+/// Photos whose cached thumbnail has gone (a purged cache) are left for
+/// the thumbnails job, which makes their placeholder as it goes.
+fn backfill_placeholders(ctx: &JobContext) -> Result<(), String> {
+    let photos = crate::with_db(&ctx.db(), "Failed to get photos", db::get_photos_without_placeholders)?;
+    in_chunks(
+        ctx,
+        &photos,
+        |(path, hash)| {
+            let thumb = crate::thumbnails::thumb_path(hash, crate::thumbnails::THUMB_SIZE);
+            placeholders::from_file(&thumb).ok().map(|placeholder| (path.clone(), placeholder))
+        },
+        db::set_placeholders_batch,
+    )
+}
+
+/// Thumbnails are recorded per photo as they're made, so this needs no
+/// chunking of its own to resume.
+fn generate_thumbnails(ctx: &JobContext, params: &serde_json::Value) -> Result<(), String> {
+    let prewarm = params.get("prewarm").and_then(|v| v.as_bool()).unwrap_or(false);
+    let passes: &[bool] = if prewarm { &[true, false] } else { &[false] };
+    let progress = |processed: usize, total: usize| ctx.progress(processed, total);
+    for &use_embedded in passes {
+        if ctx.is_cancelled() {
+            break;
+        }
+        let count = JOB_POOL.install(|| {
+            tauri::async_runtime::block_on(crate::backfill_thumbnails(
+                ctx.app,
+                &ctx.db(),
+                use_embedded,
+                &progress,
+                &ctx.cancelled,
+            ))
+        })?;
+        info!("Generated {} thumbnails (embedded: {})", count, use_embedded);
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pending(kinds: &[JobKind]) -> VecDeque<PendingJob> {
+        kinds
+            .iter()
+            .enumerate()
+            .map(|(i, &kind)| PendingJob { id: i as i64, kind, params: serde_json::Value::Null })
+            .collect()
+    }
+
+    #[test]
+    fn one_io_job_at_a_time_while_cpu_jobs_overlap() {
+        let queue = pending(&[JobKind::HashBackfill, JobKind::FileSizes, JobKind::Thumbnails]);
+        assert_eq!(next_runnable(&queue, [].into_iter()), Some(0));
+        // With a hash backfill reading files, the file-size job waits its turn
+        assert_eq!(next_runnable(&queue, [JobKind::HashBackfill].into_iter()), Some(2));
+        let cpu_only = pending(&[JobKind::PerceptualHashes]);
+        assert_eq!(next_runnable(&cpu_only, [JobKind::Thumbnails].into_iter()), Some(0));
+        assert_eq!(next_runnable(&cpu_only, [JobKind::Thumbnails, JobKind::Thumbnails].into_iter()), None);
+    }
+
+    #[test]
+    fn kinds_round_trip_through_their_stored_names() {
+        for kind in ALL_KINDS {
+            assert_eq!(JobKind::parse(kind.as_str()), Some(kind));
+            assert_eq!(serde_json::to_value(kind).unwrap(), serde_json::json!(kind.as_str()));
+        }
+        assert_eq!(JobKind::parse("defrag"), None);
+    }
+}
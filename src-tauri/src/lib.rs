@@ -1,58 +1,140 @@
 use std::collections::HashMap;
 use std::fs;
-use std::path::Path;
-use std::sync::atomic::{AtomicU32, Ordering};
-use std::sync::Arc;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, MutexGuard};
 
 use rayon::prelude::*;
 use reverse_geocoder::ReverseGeocoder;
 use serde::{Deserialize, Serialize};
-use tauri::Emitter;
+use tauri::{Emitter, Manager};
 use walkdir::WalkDir;
 use log::{debug, error, info, warn};
 
+// Reading a Photos library is plain SQLite, so it builds (and is tested)
+// everywhere; only import_apple_photos is macOS-only
+#[cfg_attr(not(target_os = "macos"), allow(dead_code))]
+mod apple_photos;
+mod auto_scan;
+mod auto_tag;
 mod db;
+mod digikam;
+mod edits;
+mod exclusions;
+mod exif_write;
+mod external;
+mod faces;
+mod geotag;
+mod heif;
+mod jobs;
+mod library;
+mod live_photos;
+mod logging;
 mod media;
+mod meta_export;
 mod metadata_enrich;
+mod metadata_export;
+mod onnx;
+mod operations;
+mod placeholders;
+mod places;
+mod quality;
+mod raw;
+mod rename_template;
+mod sqlite_snapshot;
+mod stacks;
+mod takeout;
 mod thumbnails;
+mod trips;
+mod video;
+mod watcher;
+mod zip_export;
 
-use media::{compute_dhash, detect_screenshot, hamming_distance, process_image, GEOCODER_LOCATIONS};
+use db::DbState;
+use media::{compute_dhash, hamming_distance, process_image, GEOCODER_LOCATIONS};
 use metadata_enrich::enrich_path;
+use operations::Operation;
 
 /// Application configuration constants
 pub mod config {
     /// Number of days before archived photos are permanently deleted
     pub const ARCHIVE_DELETION_DAYS: i64 = 14;
 
+    /// Number of days trashed photos are kept before the startup purge removes them
+    pub const TRASH_RETENTION_DAYS: i64 = 30;
+
+    /// Days an operation stays in the undo log, unless the
+    /// operation_retention_days setting says otherwise
+    pub const OPERATION_RETENTION_DAYS: i64 = 30;
+
     /// Hamming distance threshold for perceptual hash duplicate detection.
     /// Lower values = stricter matching (fewer false positives).
     /// Range: 0-64 where 0 is exact match, 10 is default for "similar" photos.
     pub const DUPLICATE_HAMMING_THRESHOLD: u32 = 10;
 
-    /// Minimum valid year for date parsing (Unix epoch start)
-    pub const MIN_VALID_YEAR: i32 = 1970;
+    /// Upper bound for find_similar_photos. Past this, unrelated photos start
+    /// matching and the block index stops narrowing candidates.
+    pub const MAX_SIMILAR_DISTANCE: u32 = 24;
+
+    /// Earliest year a filename date may claim; lower "years" are counters.
+    /// The upper bound is a day past now.
+    pub const MIN_VALID_YEAR: i32 = 1990;
+
+    /// Largest page get_photos_page will return in one call
+    pub const MAX_PAGE_SIZE: i64 = 1000;
 
-    /// Maximum valid year for date parsing (reasonable future bound)
-    pub const MAX_VALID_YEAR: i32 = 2100;
+    /// Largest files get_storage_analytics lists when not given a limit
+    pub const LARGEST_FILES_SHOWN: i64 = 10;
+
+    /// Photos export_metadata reads per page, holding the database lock
+    pub const EXPORT_PAGE_ROWS: usize = 500;
+
+    /// Space an upload must leave free on the library's drive, so a full
+    /// disk doesn't also break the database and thumbnail cache
+    pub const UPLOAD_SPACE_MARGIN_BYTES: u64 = 100 * 1024 * 1024;
 }
 
-/// Open a database connection with a consistent error format.
+/// Lock the shared database connection with a consistent error format.
 /// Use directly when a command needs the connection across multiple steps.
-fn db_conn() -> Result<rusqlite::Connection, String> {
-    db::init_database().map_err(|e| format!("Database error: {}", e))
+/// Long-running commands should drop the guard before heavy file work so
+/// other commands aren't blocked behind them.
+fn db_conn(db_state: &DbState) -> Result<MutexGuard<'_, rusqlite::Connection>, String> {
+    db_state.0.lock().map_err(|e| format!("Database error: {}", e))
 }
 
-/// Open a connection, run one db operation, and format any error.
+/// Lock the connection, run one db operation, and format any error.
 /// Use for one-shot commands; multi-step commands should call `db_conn()`.
-fn with_db<T, F>(op: &str, f: F) -> Result<T, String>
+fn with_db<T, F>(db_state: &DbState, op: &str, f: F) -> Result<T, String>
 where
     F: FnOnce(&rusqlite::Connection) -> rusqlite::Result<T>,
 {
-    let conn = db_conn()?;
+    let conn = db_conn(db_state)?;
     f(&conn).map_err(|e| format!("{}: {}", op, e))
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone)]
+/// with_db for writes, retried while another connection holds the write
+/// lock. Every one-shot write goes through here; `f` must be safe to repeat
+/// (see db::retry_busy).
+fn with_db_write<T, F>(db_state: &DbState, op: &str, mut f: F) -> Result<T, String>
+where
+    F: FnMut(&rusqlite::Connection) -> rusqlite::Result<T>,
+{
+    let conn = db_conn(db_state)?;
+    db::retry_busy(|| f(&conn)).map_err(|e| format!("{}: {}", op, e))
+}
+
+/// Insert photos WRITE_CHUNK_ROWS at a time, releasing the connection
+/// between chunks so other commands aren't starved during a long scan.
+fn insert_photos_chunked(db_state: &DbState, photos: &[PhotoMetadata], source_type: &str) -> Result<Vec<(String, String)>, String> {
+    let mut failures = Vec::new();
+    for chunk in photos.chunks(db::WRITE_CHUNK_ROWS) {
+        let conn = db_conn(db_state)?;
+        failures.extend(db::insert_photos_batch(&conn, chunk, source_type).map_err(|e| e.to_string())?);
+    }
+    Ok(failures)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
 pub struct PhotoMetadata {
     pub path: String,
     pub name: String,
@@ -88,841 +170,5923 @@ pub struct PhotoMetadata {
     #[serde(skip_serializing_if = "Option::is_none")]
     pub codec: Option<String>,
     /// 'ready' = on-disk thumb at the canonical content-addressed path;
-    /// 'failed' = decoder rejected (e.g. unsupported HEIC); 'unsupported' = video.
+    /// 'embedded' = same path, but holding the low-res EXIF preview until upgraded;
+    /// 'failed' = decoder or ffmpeg rejected it (e.g. unsupported HEIC).
     /// None means we haven't tried yet.
     #[serde(skip_serializing_if = "Option::is_none")]
     pub thumb_status: Option<String>,
+    /// Set when a rescan found the row's file gone from disk.
+    #[serde(default)]
+    pub is_missing: bool,
+    /// Set while the drive or folder the file lives on isn't mounted; the
+    /// grid greys these out and deletes leave their files alone.
+    #[serde(default)]
+    pub is_offline: bool,
+    /// BlurHash of the grid thumbnail, painted until it loads; see placeholders.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blurhash: Option<String>,
+    /// "#rrggbb", the cell's background before anything else arrives
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dominant_color: Option<String>,
+    /// 0-5 stars; 0 means unrated
+    #[serde(default)]
+    pub rating: u8,
+    /// Read from XMP xmp:Label on import, then set by hand. Never ColorLabel::None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub color_label: Option<db::ColorLabel>,
+    /// Pick or reject from culling. Never PhotoFlag::None.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub flag: Option<db::PhotoFlag>,
+    /// Stack this photo belongs to, e.g. a RAW+JPEG pair; see stacks.rs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_id: Option<i64>,
+    /// Cache name of the grid thumbnail with the photo's crop and rotation
+    /// applied (`thumbnails::edited_key`); None when it has no edits.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub edited_key: Option<String>,
+    /// Members of the stack, this one included. Set on the stack primaries
+    /// `get_all_photos` and `query_photos` return; None elsewhere.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub stack_count: Option<i64>,
+    /// Filled by commands that call `db::attach_tags`; empty elsewhere.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<db::Tag>,
+    /// Caption read from the file during import, stored on insert. Not sent
+    /// to the grid; `get_photo_details` returns the stored description.
+    #[serde(skip)]
+    pub description: Option<String>,
+    /// Lowercase format from the extension ("jpeg", "cr2"); the grid badges RAW files
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file_format: Option<String>,
+    /// Bytes on disk, read during import and stored on insert. Not sent to
+    /// the grid; `get_photo_details` returns the stored size.
+    #[serde(skip)]
+    pub file_size: Option<i64>,
+    /// MIME type from the file's format, stored on insert like `file_size`
+    #[serde(skip)]
+    pub mime_type: Option<String>,
+    /// Motion video of a Live Photo still; the video itself is hidden from the grid
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub paired_video_path: Option<String>,
+    /// Date in the file name when it disagrees with the embedded date by over
+    /// a year. Set during import and stored on insert; `get_date_discrepancies`
+    /// reads the stored value.
+    #[serde(skip)]
+    pub filename_date_mismatch: Option<i64>,
+    /// UTC offset of the capture time in minutes, from EXIF OffsetTime or the
+    /// default used when the camera recorded none; the viewer shows local time
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tz_offset_minutes: Option<i32>,
+    /// Where date_taken came from: "exif", "sidecar", "filename", "mtime" or
+    /// "unknown" (date_taken is then 0). None for rows imported before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_source: Option<String>,
+    /// False when date_taken is only the file's modified time or unknown
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub date_confident: Option<bool>,
+    /// Row id in the photos table; None for a file not yet imported
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<i64>,
+}
+
+/// COMMAND: Get all photos from the database.
+/// Deprecated for the main grid: serializing every row stalls large libraries.
+/// Use `get_photos_page` + `get_photo_count` instead.
+/// Archived photos are left out unless `filter.include_archived` is set.
+#[tauri::command]
+fn get_all_photos(
+    db_state: tauri::State<'_, DbState>,
+    filter: Option<db::PhotoFilter>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get photos", |c| {
+        let mut photos = db::get_filtered_photos(c, &filter)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// COMMAND: Get one page of photos.
+/// `sort` is one of date_desc, date_asc, name_asc, name_desc. Pass the previous
+/// page's `next_cursor` to keep deep pages fast; `offset` works without one.
+/// Pass the same `filter` to `get_photo_count`.
+#[tauri::command]
+fn get_photos_page(
+    db_state: tauri::State<'_, DbState>,
+    offset: i64,
+    limit: i64,
+    sort: String,
+    cursor: Option<db::PageCursor>,
+    filter: Option<db::PhotoFilter>,
+) -> Result<db::PhotoPage, String> {
+    let sort: db::PhotoSort = sort.parse()?;
+    let limit = limit.clamp(1, config::MAX_PAGE_SIZE);
+    let offset = offset.max(0);
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get photos", |c| {
+        let mut page = db::get_photos_page(c, sort, offset, limit, cursor.as_ref(), &filter)?;
+        db::attach_tags(c, &mut page.photos)?;
+        Ok(page)
+    })
+}
+
+/// COMMAND: Count non-archived photos (cheap; sizes the grid before pages load)
+#[tauri::command]
+fn get_photo_count(db_state: tauri::State<'_, DbState>, filter: Option<db::PhotoFilter>) -> Result<i64, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to count photos", |c| db::get_photo_count(c, &filter))
+}
+
+/// COMMAND: Photos matching every field set in `filter` (media type, favorites,
+/// date range, orientation, album, tags, rating, color label, flag, text),
+/// newest first. Rejected photos are left out unless `flag` asks for them.
+/// `limit` pages the results; without it every match is returned.
+#[tauri::command]
+fn query_photos(
+    db_state: tauri::State<'_, DbState>,
+    filter: Option<db::PhotoQuery>,
+    offset: Option<i64>,
+    limit: Option<i64>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let filter = filter.unwrap_or_default();
+    let offset = offset.unwrap_or(0).max(0);
+    let limit = limit.map(|l| l.clamp(1, config::MAX_PAGE_SIZE));
+    with_db(&db_state, "Failed to query photos", |c| {
+        let mut photos = db::query_photos(c, &filter, offset, limit)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// COMMAND: Up to `count` photos on each side of `path` in `query_photos`
+/// order under the same `filter`, so the lightbox can step past the loaded
+/// page. `path` needn't match the filter. Errors if it isn't in the library.
+#[tauri::command]
+fn get_adjacent_photos(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    filter: Option<db::PhotoQuery>,
+    count: i64,
+) -> Result<db::AdjacentPhotos, String> {
+    let filter = filter.unwrap_or_default();
+    let count = count.clamp(1, config::MAX_PAGE_SIZE);
+    with_db(&db_state, "Failed to get adjacent photos", |c| {
+        let adjacent = db::get_adjacent_photos(c, &path, &filter, count)?;
+        let Some(mut adjacent) = adjacent else { return Ok(None) };
+        db::attach_tags(c, &mut adjacent.before)?;
+        db::attach_tags(c, &mut adjacent.after)?;
+        Ok(Some(adjacent))
+    })?
+    .ok_or_else(|| format!("Photo not found: {}", path))
+}
+
+/// COMMAND: Up to `count` random photos matching `filter`, for a slideshow.
+/// Hidden, archived and trashed photos never show; videos only when
+/// `filter.media_type` is "video".
+#[tauri::command]
+fn get_random_photos(
+    db_state: tauri::State<'_, DbState>,
+    count: i64,
+    filter: Option<db::PhotoQuery>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let filter = filter.unwrap_or_default();
+    let count = count.clamp(1, config::MAX_PAGE_SIZE);
+    with_db(&db_state, "Failed to get random photos", |c| {
+        let mut photos = db::get_random_photos(c, &filter, count)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// COMMAND: One page of a slideshow shuffle. The same `seed` always gives
+/// the same order, so keep it for the session and page with `offset`.
+/// Picks photos like `get_random_photos`.
+#[tauri::command]
+fn get_shuffled_page(
+    db_state: tauri::State<'_, DbState>,
+    seed: u64,
+    offset: i64,
+    limit: i64,
+    filter: Option<db::PhotoQuery>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let filter = filter.unwrap_or_default();
+    let limit = limit.clamp(1, config::MAX_PAGE_SIZE) as usize;
+    let offset = offset.max(0) as usize;
+    with_db(&db_state, "Failed to get slideshow page", |c| {
+        let mut photos = db::get_shuffled_page(c, &filter, seed, offset, limit)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// COMMAND: Split the grid's photos into periods for headers and the scrubber.
+/// `granularity` is year, month or day; periods read "2024", "2024-03" or
+/// "2024-03-09" on each photo's own clock (its recorded offset, else the
+/// default_utc_offset_minutes setting), newest first, with "unknown" last.
+#[tauri::command]
+fn get_timeline_buckets(
+    db_state: tauri::State<'_, DbState>,
+    granularity: String,
+    filter: Option<db::PhotoFilter>,
+) -> Result<Vec<db::TimelineBucket>, String> {
+    let granularity: db::TimelineGranularity = granularity.parse()?;
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get timeline", |c| {
+        db::get_timeline_buckets(c, granularity, media::default_utc_offset(), &filter)
+    })
 }
 
-/// COMMAND: Get all photos from the database
+/// COMMAND: One page of the photos in a period from `get_timeline_buckets`.
+/// Pass the same `granularity` and `filter`.
 #[tauri::command]
-fn get_all_photos() -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get photos", |c| db::get_all_photos(c))
+fn get_photos_in_bucket(
+    db_state: tauri::State<'_, DbState>,
+    period: String,
+    granularity: String,
+    offset: i64,
+    limit: i64,
+    filter: Option<db::PhotoFilter>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let granularity: db::TimelineGranularity = granularity.parse()?;
+    let limit = limit.clamp(1, config::MAX_PAGE_SIZE);
+    let offset = offset.max(0);
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get photos", |c| {
+        let mut photos = db::get_photos_in_bucket(c, &period, granularity, media::default_utc_offset(), offset, limit, &filter)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// Walk a directory and return every file with a supported photo/video
+/// extension, leaving out what `rules` excludes (see exclusions::walk_files).
+fn collect_media_files(dir_path: &str, rules: &exclusions::ScanRules) -> Vec<walkdir::DirEntry> {
+    exclusions::walk_files(Path::new(dir_path), rules)
+        .into_iter()
+        .filter(|e| is_media_file(e.path()))
+        .collect()
+}
+
+/// The scan_exclusions setting, or the defaults when it isn't set.
+fn scan_exclusions(conn: &rusqlite::Connection) -> exclusions::ScanExclusions {
+    db::get_setting(conn, exclusions::SCAN_EXCLUSIONS_SETTING)
+        .and_then(|raw| serde_json::from_str(&raw).ok())
+        .unwrap_or_default()
+}
+
+/// scan_exclusions compiled for collect_media_files. set_scan_exclusions
+/// only saves valid patterns, so the fallback is for hand-edited databases.
+fn scan_rules(conn: &rusqlite::Connection) -> exclusions::ScanRules {
+    scan_exclusions(conn).compile().unwrap_or_else(|e| {
+        warn!("{}; scanning with the default exclusions", e);
+        exclusions::ScanRules::default()
+    })
+}
+
+/// True if the extension is one Terra imports.
+fn is_media_file(path: &Path) -> bool {
+    let ext = path.extension()
+        .and_then(|s| s.to_str())
+        .unwrap_or("")
+        .to_lowercase();
+    matches!(ext.as_str(), "jpg" | "jpeg" | "png" | "heic" | "webp" | "gif" | "bmp")
+        || media::is_video(path)
+        || raw::RAW_EXTENSIONS.contains(&ext.as_str())
+}
+
+/// Save Live Photo pairs found by `live_photos::find_pairs` once both
+/// halves have rows.
+fn record_live_photo_pairs(conn: &rusqlite::Connection, pairs: &[(String, String)]) {
+    match db::set_live_photo_pairs(conn, pairs) {
+        Ok(0) => {}
+        Ok(n) => info!("Paired {} Live Photos", n),
+        Err(e) => warn!("Failed to record Live Photo pairs: {}", e),
+    }
+}
+
+/// Save stacks found by `stacks::find_stacks` once their members have rows.
+fn record_stacks(conn: &rusqlite::Connection, stacks: &[Vec<String>]) {
+    match db::create_stacks(conn, stacks) {
+        Ok(0) => {}
+        Ok(n) => info!("Stacked {} photos", n),
+        Err(e) => warn!("Failed to record stacks: {}", e),
+    }
+}
+
+/// Cancellation flag for the in-flight directory scan.
+/// `cancel_scan` sets it; `scan_directory` clears it when a new scan starts.
+/// `active` counts scans and imports running, so scheduled scans can stay
+/// out of their way.
+#[derive(Default)]
+pub struct ScanState {
+    cancelled: AtomicBool,
+    active: AtomicU32,
+}
+
+impl ScanState {
+    /// Count a scan or import as running until the guard drops. The cancel
+    /// flag is cleared only when nothing else is running, so a cancel made
+    /// on a scan that's still going isn't lost.
+    fn begin(&self) -> ActiveScan<'_> {
+        if self.active.fetch_add(1, Ordering::SeqCst) == 0 {
+            self.cancelled.store(false, Ordering::SeqCst);
+        }
+        ActiveScan(self)
+    }
+
+    /// Like `begin`, unless a scan or import is already running
+    fn try_begin(&self) -> Option<ActiveScan<'_>> {
+        self.active.compare_exchange(0, 1, Ordering::SeqCst, Ordering::SeqCst).ok()?;
+        self.cancelled.store(false, Ordering::SeqCst);
+        Some(ActiveScan(self))
+    }
+}
+
+/// See ScanState::begin
+struct ActiveScan<'a>(&'a ScanState);
+
+impl Drop for ActiveScan<'_> {
+    fn drop(&mut self) {
+        self.0.active.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// Payload of `scan_progress` events emitted by scan_directory.
+/// Superset of ScanProgress so duplicate-scan listeners keep working.
+#[derive(Serialize, Clone)]
+pub struct DirectoryScanProgress {
+    pub total: u32,
+    pub processed: u32,
+    pub phase: String,
+    pub current_file: String,
+}
+
+/// Payload of the `scan_complete` event.
+#[derive(Serialize, Clone)]
+pub struct ScanComplete {
+    pub total: u32,
+    pub processed: u32,
+    pub found: u32,
+    pub cancelled: bool,
 }
 
+/// COMMAND: Scan a directory for photos and videos.
+/// Emits `scan_progress` every 25 files and `scan_complete` at the end.
+/// A scan stopped with `cancel_scan` still returns (and saves) what it processed.
 #[tauri::command]
-fn scan_directory(dir_path: String, save_to_db: bool) -> Result<Vec<PhotoMetadata>, String> {
+async fn scan_directory(
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    dir_path: String,
+    save_to_db: bool,
+) -> Result<Vec<PhotoMetadata>, String> {
     info!("Scanning directory: {}", dir_path);
+    let _active = scan_state.begin();
+    let started_at_ms = chrono::Utc::now().timestamp_millis();
+    let cancelled = &scan_state.cancelled;
 
     // Use cached geocoder locations for better performance
     let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
 
     // 1. Collect all image paths efficiently
-    let entries: Vec<_> = WalkDir::new(&dir_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-        .filter(|e| {
-            let path = e.path();
-            if !path.is_file() {
-                return false;
-            }
-            let ext = path.extension()
-                .and_then(|s| s.to_str())
-                .unwrap_or("")
-                .to_lowercase();
-            matches!(ext.as_str(), "jpg" | "jpeg" | "png" | "heic" | "webp" | "gif" | "bmp" | "mp4" | "mov" | "avi" | "webm" | "mkv")
-        })
-        .collect();
+    let rules = scan_rules(&*db_conn(&db_state)?);
+    let entries = collect_media_files(&dir_path, &rules);
 
-    info!("Found {} image files", entries.len());
+    let total = entries.len() as u32;
+    info!("Found {} image files", total);
+    let processed = AtomicU32::new(0);
 
     // 2. Process metadata in parallel using Rayon
     let photos: Vec<PhotoMetadata> = entries
         .par_iter()
-        .filter_map(|entry| process_image(entry.path(), Some(&geocoder)))
+        .filter_map(|entry| {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
+            }
+
+            let photo = process_image(entry.path(), Some(&geocoder));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = window.emit("scan_progress", DirectoryScanProgress {
+                    total,
+                    processed: current,
+                    phase: "processing".to_string(),
+                    current_file: entry.path().to_string_lossy().to_string(),
+                });
+            }
+
+            photo
+        })
         .collect();
 
+    let was_cancelled = cancelled.load(Ordering::SeqCst);
+    if was_cancelled {
+        info!("Scan cancelled after {} of {} files", processed.load(Ordering::SeqCst), total);
+    }
     info!("Successfully processed {} photos", photos.len());
 
     // 3. Optionally save to database
     if save_to_db {
-        let conn = db_conn()?;
-        for photo in &photos {
-            db::insert_photo(&conn, photo, "scan")
-                .map_err(|e| format!("Failed to insert photo: {}", e))?;
+        let stats: Vec<(String, i64, i64)> = photos
+            .iter()
+            .filter_map(|p| media::file_stats(Path::new(&p.path)).map(|(size, mtime)| (p.path.clone(), size, mtime)))
+            .collect();
+        let live_pairs = live_photos::find_pairs(photos.iter().map(|p| p.path.as_str()));
+        let stacks = stacks::find_stacks(photos.iter().map(|p| p.path.as_str()));
+        let failures = insert_photos_chunked(&db_state, &photos, "scan")
+            .map_err(|e| format!("Failed to save scanned photos: {}", e))?;
+        let conn = db_conn(&db_state)?;
+        for (path, err) in &failures {
+            error!("Failed to insert {}: {}", path, err);
+        }
+        let _ = db::update_file_stats_batch(&conn, &stats);
+        record_live_photo_pairs(&conn, &live_pairs);
+        record_stacks(&conn, &stacks);
+        let saved: Vec<String> = photos.iter().map(|p| p.path.clone()).collect();
+        let unreadable = processed.load(Ordering::SeqCst) as usize - photos.len();
+        if let Err(e) = db::record_import_session(&conn, "scan", &dir_path, started_at_ms, &saved, unreadable + failures.len()) {
+            warn!("Failed to record import session: {}", e);
+        }
+        info!("Saved {} photos to database", photos.len() - failures.len());
+        if !photos.is_empty() {
+            spawn_post_import_jobs(window.app_handle());
         }
-        info!("Saved {} photos to database", photos.len());
     }
 
+    let _ = window.emit("scan_complete", ScanComplete {
+        total,
+        processed: processed.load(Ordering::SeqCst),
+        found: photos.len() as u32,
+        cancelled: was_cancelled,
+    });
+
     Ok(photos)
 }
 
-/// COMMAND: Upload Photos
-/// Copies photos to the Terra managed library and saves metadata to database
-#[tauri::command]
-fn upload_photos(file_paths: Vec<String>) -> Result<Vec<PhotoMetadata>, String> {
-    info!("Uploading {} photos", file_paths.len());
-
-    let library_path = db::get_library_path();
-    let conn = db_conn()?;
+/// What import_matched read and saved.
+struct ImportedFiles {
+    /// Files matched for reading
+    total: u32,
+    /// Files read, readable or not
+    processed: u32,
+    /// Photos saved to the library
+    saved: u32,
+    /// Photos that failed to save, with why
+    failures: Vec<(String, String)>,
+}
 
-    // Use cached geocoder locations for better performance
+/// Read `matched` files with their sidecars in parallel, as scan_directory
+/// does, emitting `scan_progress`. `keep` gets each readable photo with the
+/// index of its entry in `matched` and returns those to save; they're saved
+/// as `source_type` with their file stats, Live Photo pairs and stacks.
+fn import_matched(
+    window: &tauri::Window,
+    db_state: &DbState,
+    cancelled: &AtomicBool,
+    matched: &[(&Path, Option<takeout::Sidecar>)],
+    source_type: &str,
+    keep: impl FnOnce(Vec<(usize, PhotoMetadata)>) -> Vec<PhotoMetadata>,
+) -> Result<ImportedFiles, String> {
     let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
-
-    let uploaded_photos: Vec<PhotoMetadata> = file_paths
-        .iter()
-        .filter_map(|file_path| {
-            let source_path = Path::new(file_path);
-            if !source_path.exists() {
-                warn!("File not found: {}", file_path);
+    let total = matched.len() as u32;
+    let processed = AtomicU32::new(0);
+    let photos: Vec<(usize, PhotoMetadata)> = matched
+        .par_iter()
+        .enumerate()
+        .filter_map(|(i, (path, sidecar))| {
+            if cancelled.load(Ordering::Relaxed) {
                 return None;
             }
+            let photo = media::process_image_with(path, Some(&geocoder), sidecar.as_ref());
 
-            // Process the image to get metadata (especially date_taken)
-            let mut photo = process_image(source_path, Some(&geocoder))?;
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = window.emit("scan_progress", DirectoryScanProgress {
+                    total,
+                    processed: current,
+                    phase: "processing".to_string(),
+                    current_file: path.to_string_lossy().to_string(),
+                });
+            }
+            Some((i, photo?))
+        })
+        .collect();
+    let to_save = keep(photos);
 
-            // Check for duplicates
-            if let Some(hash) = &photo.content_hash {
-                match db::hash_exists(&conn, hash) {
-                    Ok(exists) => {
-                        if exists {
-                            debug!("Skipping duplicate photo: {} (hash: {})", photo.name, hash);
-                            return None;
-                        }
-                    },
-                    Err(e) => warn!("Failed to check hash existence: {}", e),
+    let stats: Vec<(String, i64, i64)> = to_save
+        .iter()
+        .filter_map(|p| media::file_stats(Path::new(&p.path)).map(|(size, mtime)| (p.path.clone(), size, mtime)))
+        .collect();
+    let live_pairs = live_photos::find_pairs(to_save.iter().map(|p| p.path.as_str()));
+    let stacks = stacks::find_stacks(to_save.iter().map(|p| p.path.as_str()));
+    let failures = insert_photos_chunked(db_state, &to_save, source_type)
+        .map_err(|e| format!("Failed to save {} photos: {}", source_type, e))?;
+    let conn = db_conn(db_state)?;
+    for (path, err) in &failures {
+        error!("Failed to insert {}: {}", path, err);
+    }
+    let _ = db::update_file_stats_batch(&conn, &stats);
+    record_live_photo_pairs(&conn, &live_pairs);
+    record_stacks(&conn, &stacks);
+    Ok(ImportedFiles {
+        total,
+        processed: processed.load(Ordering::SeqCst),
+        saved: (to_save.len() - failures.len()) as u32,
+        failures,
+    })
+}
+
+/// Queue thumbnails for what an import saved and tell the frontend it's done.
+fn finish_import(window: &tauri::Window, imported: &ImportedFiles, cancelled: bool) {
+    if imported.saved > 0 {
+        spawn_post_import_jobs(window.app_handle());
+    }
+    let _ = window.emit("scan_complete", ScanComplete {
+        total: imported.total,
+        processed: imported.processed,
+        found: imported.saved,
+        cancelled,
+    });
+}
+
+/// Result of import_takeout.
+#[derive(Serialize, Default)]
+pub struct TakeoutImportSummary {
+    pub imported: u32,
+    /// Photos whose date, caption or location could come from a JSON sidecar
+    pub with_sidecar: u32,
+    /// Media files no sidecar could be matched to
+    pub unmatched: Vec<String>,
+    /// Album-folder copies of photos already imported from a year folder
+    pub album_copies_skipped: u32,
+    /// Albums created or added to
+    pub albums: u32,
+    pub cancelled: bool,
+}
+
+/// COMMAND: Import a Google Photos Takeout export in place.
+/// Takeout strips EXIF from many files and keeps the capture time, caption
+/// and location in a JSON sidecar per file, which is used instead.
+/// Album folders repeat photos from the year folders; those copies are
+/// skipped, and with `create_albums` each album folder becomes a Terra album
+/// (reusing one of the same name) holding the matching photos.
+/// Emits `scan_progress` events and honours `cancel_scan`.
+#[tauri::command]
+async fn import_takeout(
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    dir_path: String,
+    create_albums: Option<bool>,
+) -> Result<TakeoutImportSummary, String> {
+    info!("Importing Takeout export: {}", dir_path);
+    let _active = scan_state.begin();
+    let cancelled = &scan_state.cancelled;
+
+    let rules = scan_rules(&*db_conn(&db_state)?);
+    let entries = collect_media_files(&dir_path, &rules);
+    let mut summary = TakeoutImportSummary::default();
+
+    // 1. Match sidecars by name, falling back to the titles of each
+    // folder's sidecars when Takeout truncated the media name itself
+    let mut indexes: HashMap<PathBuf, HashMap<String, takeout::Sidecar>> = HashMap::new();
+    let mut album_titles: HashMap<PathBuf, Option<String>> = HashMap::new();
+    let mut matched: Vec<(&Path, Option<takeout::Sidecar>)> = Vec::with_capacity(entries.len());
+    for entry in &entries {
+        let path = entry.path();
+        let Some(dir) = path.parent() else {
+            continue;
+        };
+        let sidecar = takeout::find_sidecar(path).or_else(|| {
+            let index = indexes.entry(dir.to_path_buf()).or_insert_with(|| takeout::title_index(dir));
+            takeout::match_title(index, &entry.file_name().to_string_lossy()).cloned()
+        });
+        if sidecar.is_none() {
+            summary.unmatched.push(path.to_string_lossy().to_string());
+        }
+        album_titles.entry(dir.to_path_buf()).or_insert_with(|| takeout::album_title(dir));
+        matched.push((path, sidecar));
+    }
+    drop(indexes);
+
+    // 2. Read metadata, skipping album copies of photos that are also in a
+    // year folder, and save the rest
+    let mut memberships: Vec<(&str, String)> = Vec::new();
+    let imported = import_matched(&window, &db_state, cancelled, &matched, "takeout", |photos| {
+        let album_of = |i: usize| matched[i].0.parent().and_then(|dir| album_titles.get(dir)?.as_deref());
+        let outside_albums: HashMap<String, String> = photos
+            .iter()
+            .filter(|(i, _)| album_of(*i).is_none())
+            .filter_map(|(_, photo)| Some((photo.content_hash.clone()?, photo.path.clone())))
+            .collect();
+        let mut to_save = Vec::with_capacity(photos.len());
+        for (i, photo) in photos {
+            if let Some(album) = album_of(i) {
+                let original = photo.content_hash.as_ref().and_then(|h| outside_albums.get(h));
+                if let Some(original) = original {
+                    summary.album_copies_skipped += 1;
+                    memberships.push((album, original.clone()));
+                    continue;
+                }
+                memberships.push((album, photo.path.clone()));
+            }
+            if matched[i].1.is_some() {
+                summary.with_sidecar += 1;
+            }
+            to_save.push(photo);
+        }
+        to_save
+    })?;
+    summary.cancelled = cancelled.load(Ordering::SeqCst);
+    summary.imported = imported.saved;
+
+    // 3. Recreate albums
+    if create_albums.unwrap_or(false) {
+        let conn = db_conn(&db_state)?;
+        let failed: std::collections::HashSet<&str> = imported.failures.iter().map(|(p, _)| p.as_str()).collect();
+        let mut album_ids: HashMap<&str, i64> = HashMap::new();
+        for (album, path) in memberships.iter().filter(|(_, p)| !failed.contains(p.as_str())) {
+            let id = match album_ids.get(album) {
+                Some(&id) => id,
+                None => {
+                    let existing = db::find_album_by_name(&conn, album)
+                        .map_err(|e| format!("Failed to look up album {}: {}", album, e))?;
+                    let id = match existing {
+                        Some(id) => id,
+                        None => db::create_album(&conn, album)
+                            .map_err(|e| format!("Failed to create album {}: {}", album, e))?,
+                    };
+                    album_ids.insert(*album, id);
+                    id
                 }
+            };
+            if let Err(e) = db::add_photo_to_album(&conn, id, path) {
+                warn!("Failed to add {} to album {}: {}", path, album, e);
             }
+        }
+        summary.albums = album_ids.len() as u32;
+    }
 
-            // Create year/month subdirectories based on date_taken
-            let date = chrono::DateTime::from_timestamp(photo.date_taken, 0)?;
-            let year = date.format("%Y").to_string();
-            let month = date.format("%m").to_string();
+    info!(
+        "Takeout import: {} photos ({} with sidecars, {} unmatched), {} album copies skipped, {} albums",
+        summary.imported,
+        summary.with_sidecar,
+        summary.unmatched.len(),
+        summary.album_copies_skipped,
+        summary.albums
+    );
+    finish_import(&window, &imported, summary.cancelled);
+    Ok(summary)
+}
 
-            let mut dest_dir = library_path.clone();
-            dest_dir.push(&year);
-            dest_dir.push(&month);
+/// Result of import_meta_export.
+#[derive(Serialize, Default)]
+pub struct MetaExportImportSummary {
+    pub imported: u32,
+    /// Media whose date or caption came from the export's JSON
+    pub with_metadata: u32,
+    /// Media files no JSON referred to
+    pub unmatched: Vec<String>,
+    /// References to media another post or message had already named
+    pub repeated_references: u32,
+    /// Set when the export's layout wasn't recognized and it was scanned
+    /// as a plain folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub warning: Option<String>,
+    pub cancelled: bool,
+}
 
-            // Create directories if they don't exist
-            fs::create_dir_all(&dest_dir).ok()?;
+/// COMMAND: Import an Instagram or Facebook data export ("Download your
+/// information", JSON format) in place. Meta strips EXIF, so capture times
+/// and captions are read from the export's JSON (posts, stories, albums and
+/// messages) instead; captions become descriptions. Media several posts
+/// share is imported once. An export whose JSON names no media is scanned
+/// like any folder, with a warning. Emits `scan_progress` events and
+/// honours `cancel_scan`.
+#[tauri::command]
+async fn import_meta_export(
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    dir_path: String,
+    service: String,
+) -> Result<MetaExportImportSummary, String> {
+    let service = meta_export::Service::parse(&service)?;
+    info!("Importing {} export: {}", service.source_type(), dir_path);
+    let _active = scan_state.begin();
+    let cancelled = &scan_state.cancelled;
+
+    let rules = scan_rules(&*db_conn(&db_state)?);
+    let entries = collect_media_files(&dir_path, &rules);
+    let mut summary = MetaExportImportSummary::default();
+
+    let mut index = meta_export::read_export(Path::new(&dir_path), service);
+    summary.repeated_references = index.repeated;
+    if index.media.is_empty() {
+        let warning = format!(
+            "{} doesn't look like a {} export (no JSON names its media); imported it as a plain folder",
+            dir_path,
+            service.source_type()
+        );
+        warn!("{}", warning);
+        summary.warning = Some(warning);
+    }
+    let matched: Vec<(&Path, Option<takeout::Sidecar>)> = entries
+        .iter()
+        .map(|entry| {
+            let sidecar = index.media.remove(entry.path());
+            if sidecar.is_none() && summary.warning.is_none() {
+                summary.unmatched.push(entry.path().to_string_lossy().to_string());
+            }
+            (entry.path(), sidecar)
+        })
+        .collect();
 
-            // Copy file to managed location
-            let file_name = source_path.file_name()?.to_string_lossy().to_string();
-            let mut dest_path = dest_dir;
-            dest_path.push(&file_name);
+    let imported = import_matched(&window, &db_state, cancelled, &matched, service.source_type(), |photos| {
+        summary.with_metadata = photos.iter().filter(|(i, _)| matched[*i].1.is_some()).count() as u32;
+        photos.into_iter().map(|(_, photo)| photo).collect()
+    })?;
+    summary.cancelled = cancelled.load(Ordering::SeqCst);
+    summary.imported = imported.saved;
+
+    info!(
+        "{} export import: {} photos ({} with JSON metadata, {} unmatched), {} repeated references",
+        service.source_type(),
+        summary.imported,
+        summary.with_metadata,
+        summary.unmatched.len(),
+        summary.repeated_references
+    );
+    finish_import(&window, &imported, summary.cancelled);
+    Ok(summary)
+}
 
-            // Handle duplicate filenames by appending a number
-            let mut final_dest_path = dest_path.clone();
-            let mut counter = 1;
-            while final_dest_path.exists() {
-                let stem = source_path.file_stem()?.to_string_lossy();
-                let ext = source_path.extension()?.to_string_lossy();
-                final_dest_path = dest_path.with_file_name(format!("{}_{}.{}", stem, counter, ext));
-                counter += 1;
-            }
+#[derive(Serialize, Clone, Default)]
+pub struct RescanSummary {
+    pub added: u32,
+    pub updated: u32,
+    pub skipped: u32,
+    pub missing: u32,
+    pub cancelled: bool,
+}
 
-            // Copy the file
-            match fs::copy(source_path, &final_dest_path) {
-                Ok(_) => debug!("Copied {} to {}", file_path, final_dest_path.display()),
-                Err(e) => {
-                    error!("Failed to copy {}: {}", file_path, e);
-                    return None;
-                }
+enum RescanOutcome {
+    Added(PhotoMetadata),
+    Updated(PhotoMetadata),
+    Skipped(String),
+}
+
+/// COMMAND: Incrementally rescan a directory that was scanned before.
+/// Files whose size and mtime match the stored row are skipped without reading
+/// EXIF; new and changed files are processed as in scan_directory; rows under
+/// the directory whose files are gone are flagged `is_missing`.
+#[tauri::command]
+async fn rescan_directory(
+    app: tauri::AppHandle,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    dir_path: String,
+) -> Result<RescanSummary, String> {
+    rescan_folder(&app, &scan_state, &db_state, &dir_path, None)
+}
+
+/// rescan_directory's work. With `session_source`, the photos it adds are
+/// recorded as an import session of that source type.
+fn rescan_folder(
+    app: &tauri::AppHandle,
+    scan_state: &ScanState,
+    db_state: &DbState,
+    dir_path: &str,
+    session_source: Option<&str>,
+) -> Result<RescanSummary, String> {
+    info!("Rescanning directory: {}", dir_path);
+    let _active = scan_state.begin();
+    let started_at_ms = chrono::Utc::now().timestamp_millis();
+    let cancelled = &scan_state.cancelled;
+
+    let mut prefix = library::canonical_path(Path::new(&dir_path))
+        .map_err(|e| format!("Cannot access {}: {}", dir_path, e))?;
+    if !prefix.ends_with(std::path::MAIN_SEPARATOR) {
+        prefix.push(std::path::MAIN_SEPARATOR);
+    }
+
+    let (known, rules) = {
+        let conn = db_conn(&db_state)?;
+        let known = db::get_file_stats_under(&conn, &prefix).map_err(|e| format!("Failed to load known files: {}", e))?;
+        (known, scan_rules(&conn))
+    };
+
+    let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+    let entries = collect_media_files(&dir_path, &rules);
+    let total = entries.len() as u32;
+    let processed = AtomicU32::new(0);
+
+    let outcomes: Vec<RescanOutcome> = entries
+        .par_iter()
+        .filter_map(|entry| {
+            if cancelled.load(Ordering::Relaxed) {
+                return None;
             }
 
-            // Canonicalize the destination path for Tauri file access
-            let canonical_dest = match final_dest_path.canonicalize() {
-                Ok(p) => {
-                    debug!("Canonicalized destination: {}", p.display());
-                    p.to_string_lossy().to_string()
-                },
-                Err(e) => {
-                    warn!("Could not canonicalize destination {:?}: {}", final_dest_path, e);
-                    final_dest_path.to_string_lossy().to_string()
+            let path = entry.path();
+            let canonical = library::canonical_path(path).ok()?;
+            let stats = media::file_stats(path);
+
+            let outcome = match known.get(&canonical) {
+                Some(&(Some(size), Some(mtime))) if stats == Some((size, mtime)) => {
+                    Some(RescanOutcome::Skipped(canonical))
                 }
+                Some(_) => process_image(path, Some(&geocoder)).map(RescanOutcome::Updated),
+                None => process_image(path, Some(&geocoder)).map(RescanOutcome::Added),
             };
 
-            // Update photo path to the new canonicalized location
-            photo.path = canonical_dest.clone();
-            photo.name = final_dest_path.file_name()?.to_string_lossy().to_string();
-
-            // Save to database
-            match db::insert_photo(&conn, &photo, "upload") {
-                Ok(_) => debug!("Saved to database: {}", photo.name),
-                Err(e) => {
-                    error!("Failed to save {} to database: {}", photo.name, e);
-                    return None;
-                }
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = app.emit("scan_progress", DirectoryScanProgress {
+                    total,
+                    processed: current,
+                    phase: "rescanning".to_string(),
+                    current_file: path.to_string_lossy().to_string(),
+                });
             }
 
-            // Compute perceptual hash for duplicate detection
-            if let Some(dhash) = compute_dhash(&final_dest_path) {
-                let _ = db::update_photo_dhash(&conn, &photo.path, dhash as i64);
-                debug!("Computed dhash for {}: {}", photo.name, dhash);
-            }
+            outcome
+        })
+        .collect();
 
-            // Detect if this is a screenshot
-            let is_screenshot = detect_screenshot(&photo.name, photo.width, photo.height);
-            if is_screenshot {
-                let _ = db::update_photo_screenshot_flag(&conn, &photo.path, true);
-                debug!("Detected screenshot: {}", photo.name);
+    let mut summary = RescanSummary {
+        cancelled: cancelled.load(Ordering::SeqCst),
+        ..Default::default()
+    };
+    let mut skipped: Vec<String> = Vec::new();
+    let mut to_save: Vec<PhotoMetadata> = Vec::new();
+
+    for outcome in outcomes {
+        match outcome {
+            RescanOutcome::Skipped(path) => {
+                skipped.push(path);
+                summary.skipped += 1;
+            }
+            RescanOutcome::Added(photo) => {
+                summary.added += 1;
+                to_save.push(photo);
             }
+            RescanOutcome::Updated(photo) => {
+                summary.updated += 1;
+                to_save.push(photo);
+            }
+        }
+    }
 
-            debug!("Successfully uploaded: {} -> {}", file_path, photo.path);
-            Some(photo)
-        })
+    let stats: Vec<(String, i64, i64)> = to_save
+        .iter()
+        .filter_map(|p| media::file_stats(Path::new(&p.path)).map(|(size, mtime)| (p.path.clone(), size, mtime)))
         .collect();
 
-    info!("Successfully uploaded {} photos", uploaded_photos.len());
-    Ok(uploaded_photos)
-}
+    // Only a complete walk can tell which files disappeared
+    let gone: Vec<&String> = if summary.cancelled {
+        Vec::new()
+    } else {
+        let seen: std::collections::HashSet<&str> = skipped
+            .iter()
+            .map(|p| p.as_str())
+            .chain(to_save.iter().map(|p| p.path.as_str()))
+            .collect();
+        known
+            .keys()
+            .filter(|p| !seen.contains(p.as_str()) && !Path::new(p).exists())
+            .collect()
+    };
 
-#[tauri::command]
-fn toggle_favorite(path: String, is_favorite: bool) -> Result<(), String> {
-    with_db("Failed to set favorite", |c| db::set_photo_favorite(c, &path, is_favorite))
-}
+    let live_pairs = live_photos::find_pairs(to_save.iter().map(|p| p.path.as_str()));
+    let stacks = stacks::find_stacks(to_save.iter().map(|p| p.path.as_str()));
+    let failures = insert_photos_chunked(&db_state, &to_save, "scan")
+        .map_err(|e| format!("Failed to save rescanned photos: {}", e))?;
+    let conn = db_conn(&db_state)?;
+    for (path, err) in &failures {
+        error!("Failed to insert {}: {}", path, err);
+    }
+    let _ = db::update_file_stats_batch(&conn, &stats);
+    record_live_photo_pairs(&conn, &live_pairs);
+    record_stacks(&conn, &stacks);
+    for path in &skipped {
+        let _ = db::set_photo_missing(&conn, path, false);
+    }
+    for path in &gone {
+        let _ = db::set_photo_missing(&conn, path, true);
+    }
+    summary.missing = gone.len() as u32;
+    if let Some(source_type) = session_source {
+        let saved: Vec<String> = to_save
+            .iter()
+            .filter(|p| !failures.iter().any(|(path, _)| path == &p.path))
+            .map(|p| p.path.clone())
+            .collect();
+        if let Err(e) = db::record_import_session(&conn, source_type, dir_path, started_at_ms, &saved, failures.len()) {
+            warn!("Failed to record import session: {}", e);
+        }
+    }
+    drop(conn);
+    if !to_save.is_empty() {
+        spawn_post_import_jobs(app);
+    }
 
-#[tauri::command]
-fn create_album(name: String) -> Result<i64, String> {
-    with_db("Failed to create album", |c| db::create_album(c, &name))
+    info!(
+        "Rescan of {}: {} added, {} updated, {} skipped, {} missing",
+        dir_path, summary.added, summary.updated, summary.skipped, summary.missing
+    );
+
+    let _ = app.emit("scan_complete", ScanComplete {
+        total,
+        processed: processed.load(Ordering::SeqCst),
+        found: summary.added + summary.updated,
+        cancelled: summary.cancelled,
+    });
+
+    Ok(summary)
 }
 
+/// COMMAND: Get photos whose files were not found during the last rescan
 #[tauri::command]
-fn delete_album(id: i64) -> Result<(), String> {
-    with_db("Failed to delete album", |c| db::delete_album(c, id))
+fn get_missing_photos(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get missing photos", |c| db::get_missing_photos(c))
 }
 
-#[tauri::command]
-fn get_albums() -> Result<Vec<db::Album>, String> {
-    with_db("Failed to get albums", |c| db::get_albums(c))
+/// Result of refresh_availability.
+#[derive(Serialize, Default)]
+pub struct AvailabilitySummary {
+    /// Drives photos live on that aren't mounted right now
+    pub offline_roots: Vec<String>,
+    /// Photos newly flagged offline
+    pub went_offline: u32,
+    /// Photos whose drive came back
+    pub came_back: u32,
 }
 
-#[tauri::command]
-fn add_to_album(album_id: i64, photo_paths: Vec<String>) -> Result<(), String> {
-    let conn = db_conn()?;
-    for path in photo_paths {
-        db::add_photo_to_album(&conn, album_id, &path).map_err(|e| format!("Failed to add to album: {}", e))?;
+/// Check each drive photos live on (see `library::volume_root`) and flag the
+/// photos on unmounted ones offline, clearing the flag on the ones that are
+/// back. One existence check per drive, so it is cheap enough for startup.
+fn refresh_photo_availability(conn: &rusqlite::Connection) -> Result<AvailabilitySummary, String> {
+    let paths = db::get_all_paths(conn).map_err(|e| format!("Failed to get photos: {}", e))?;
+    let roots: std::collections::BTreeSet<PathBuf> =
+        paths.iter().filter_map(|p| library::volume_root(Path::new(p))).collect();
+
+    let mut summary = AvailabilitySummary::default();
+    for root in roots {
+        let mut prefix = root.to_string_lossy().to_string();
+        if !prefix.ends_with(std::path::MAIN_SEPARATOR) {
+            prefix.push(std::path::MAIN_SEPARATOR);
+        }
+        let online = root.is_dir();
+        let changed = db::set_offline_under(conn, &prefix, !online)
+            .map_err(|e| format!("Failed to update availability: {}", e))? as u32;
+        if online {
+            summary.came_back += changed;
+        } else {
+            summary.went_offline += changed;
+            summary.offline_roots.push(root.to_string_lossy().to_string());
+        }
     }
-    Ok(())
+    Ok(summary)
 }
 
+/// COMMAND: Re-check which drives are mounted. Photos on a missing drive are
+/// flagged offline (not removed); plugging it back in and refreshing clears it.
 #[tauri::command]
-fn remove_from_album(album_id: i64, photo_paths: Vec<String>) -> Result<(), String> {
-    let conn = db_conn()?;
-    for path in photo_paths {
-        db::remove_photo_from_album(&conn, album_id, &path).map_err(|e| format!("Failed to remove from album: {}", e))?;
+fn refresh_availability(db_state: tauri::State<'_, DbState>) -> Result<AvailabilitySummary, String> {
+    let summary = refresh_photo_availability(&*db_conn(&db_state)?)?;
+    if summary.went_offline > 0 || summary.came_back > 0 {
+        info!(
+            "Availability: {} photos offline, {} back online",
+            summary.went_offline, summary.came_back
+        );
     }
-    Ok(())
+    Ok(summary)
 }
 
+/// COMMAND: Abort the running directory scan
 #[tauri::command]
-fn get_album_photos(album_id: i64) -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get album photos", |c| db::get_album_photos(c, album_id))
+fn cancel_scan(scan_state: tauri::State<'_, ScanState>) {
+    scan_state.cancelled.store(true, Ordering::SeqCst);
 }
 
-#[tauri::command]
-fn set_album_cover(album_id: i64, photo_path: String) -> Result<(), String> {
-    with_db("Failed to set album cover", |c| db::set_album_cover(c, album_id, &photo_path))
+/// Why one file in an upload wasn't imported.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum UploadErrorKind {
+    SourceMissing,
+    UnsupportedFormat,
+    CopyFailed,
+    /// The copy didn't match the original, even after a retry
+    VerifyFailed,
+    DbInsertFailed,
 }
 
-/// Check if a path is within the Terra managed library or archive directories.
-/// This is a security check to prevent deletion of files outside the managed library.
-fn is_path_in_managed_library(path: &Path) -> bool {
-    let library_path = db::get_library_path();
-    let archive_path = db::get_archive_path();
+/// Why upload_photos didn't run at all. Serialized with a `kind` tag so the
+/// frontend can tell a full drive apart from other errors.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum UploadError {
+    /// The files to copy, plus a safety margin, don't fit on the library's drive
+    InsufficientSpace { needed: u64, available: u64 },
+    Failed { message: String },
+}
 
-    // Canonicalize paths for reliable comparison
-    let canonical_path = match path.canonicalize() {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
+impl From<String> for UploadError {
+    fn from(message: String) -> Self {
+        UploadError::Failed { message }
+    }
+}
 
-    let canonical_library = match library_path.canonicalize() {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
+impl From<db::LibraryPathError> for UploadError {
+    fn from(e: db::LibraryPathError) -> Self {
+        UploadError::Failed { message: e.to_string() }
+    }
+}
 
-    let canonical_archive = match archive_path.canonicalize() {
-        Ok(p) => p,
-        Err(_) => return false,
-    };
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadFailure {
+    pub path: String,
+    pub kind: UploadErrorKind,
+    pub error: String,
+}
 
-    canonical_path.starts_with(&canonical_library) || canonical_path.starts_with(&canonical_archive)
+/// How fast an upload ran, for reporting on big imports.
+#[derive(Serialize, Default)]
+pub struct UploadThroughput {
+    pub files: u32,
+    pub bytes: u64,
+    pub elapsed_ms: u64,
+    pub files_per_sec: f64,
+    pub mb_per_sec: f64,
 }
 
-#[tauri::command]
-fn delete_photos(paths: Vec<String>) -> Result<(), String> {
-    let conn = db_conn()?;
-    for path_str in paths {
-        // 1. Delete from database
-        db::delete_photo(&conn, &path_str).map_err(|e| format!("Failed to delete from DB: {}", e))?;
+/// An upload skipped because identical content is already in the library
+/// (or earlier in the same upload).
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadDuplicate {
+    /// The selected source file
+    pub path: String,
+    /// Library path of the existing copy
+    pub duplicate_of: String,
+    /// The existing photo, when it made it into the library
+    pub photo: Option<PhotoMetadata>,
+}
 
-        // 2. Delete from filesystem ONLY if it's in the managed library
-        let path = Path::new(&path_str);
-        if path.exists() {
-            // Safety check: only delete files within Terra's managed directories
-            if is_path_in_managed_library(path) {
-                fs::remove_file(path).map_err(|e| format!("Failed to delete file: {}", e))?;
-            } else {
-                warn!("Skipping filesystem deletion for path outside managed library: {}", path_str);
-            }
-        }
-    }
-    Ok(())
+/// Where one imported file ended up and how it got there.
+#[derive(Serialize, Clone, Debug)]
+pub struct UploadTransfer {
+    pub source: String,
+    pub dest: String,
+    pub transfer: library::Transfer,
 }
 
-#[tauri::command]
-fn get_duplicates() -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get duplicates", |c| db::get_duplicates(c))
+/// Whether upload_photos leaves the originals in place.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum UploadMode {
+    #[default]
+    Copy,
+    /// Rename into the library, or copy + verify + delete across drives
+    Move,
 }
 
-#[tauri::command]
-fn search_photos(query: String) -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to search photos", |c| db::search_photos(c, &query))
+/// Result of upload_photos. Files already in the library are neither
+/// imported nor failures; they're listed in `duplicates`.
+#[derive(Serialize, Default)]
+pub struct UploadResult {
+    pub succeeded: Vec<PhotoMetadata>,
+    pub failed: Vec<UploadFailure>,
+    pub duplicates: Vec<UploadDuplicate>,
+    /// One entry per file in `succeeded`
+    pub transfers: Vec<UploadTransfer>,
+    pub throughput: UploadThroughput,
 }
 
-#[tauri::command]
-fn get_locations() -> Result<Vec<(String, i64)>, String> {
-    with_db("Failed to get locations", |c| db::get_locations(c))
+/// Payload of `upload_progress` events, one per file.
+/// `status` is a transfer ("copied", "moved", "copied_then_deleted",
+/// "existing" for an identical file already at the destination, imported in
+/// place), "duplicate" or "failed".
+#[derive(Serialize, Clone)]
+pub struct UploadProgress {
+    pub current: u32,
+    pub total: u32,
+    pub file: String,
+    pub status: &'static str,
 }
 
-// ============================================================================
-// Duplicate Detection and Screenshot Detection
-// ============================================================================
+/// A file copied into the library, with the derived data that's saved next to it.
+struct CopiedUpload {
+    source: String,
+    photo: PhotoMetadata,
+    bytes: u64,
+    dhash: Option<u64>,
+    transfer: library::Transfer,
+}
 
-#[derive(Serialize, Clone)]
-pub struct DuplicateGroup {
-    pub group_id: u32,
-    pub group_type: String,        // "exact" or "similar"
-    pub photos: Vec<PhotoMetadata>,
-    pub similarity_score: f32,     // 0.0-1.0 (1.0 = identical)
-}
-
-#[derive(Serialize, Clone)]
-pub struct ScanProgress {
-    pub total: u32,
-    pub processed: u32,
-    pub phase: String,
+/// (path, size, mtime) of the library files of `saved` uploads whose rows
+/// were written, for update_file_stats_batch
+fn saved_file_stats<'a>(
+    saved: impl IntoIterator<Item = &'a CopiedUpload>,
+    failures: &HashMap<String, String>,
+) -> Vec<(String, i64, i64)> {
+    saved
+        .into_iter()
+        .filter(|u| !failures.contains_key(&u.photo.path))
+        .filter_map(|u| media::file_stats(Path::new(&u.photo.path)).map(|(size, mtime)| (u.photo.path.clone(), size, mtime)))
+        .collect()
 }
 
-#[derive(Serialize)]
-pub struct ArchivedPhoto {
-    pub photo: PhotoMetadata,
-    pub archived_at: i64,
-    pub days_until_deletion: i64,
+/// Name an upload for its import session: the folder the files came from,
+/// or how many there were when they came from several.
+fn describe_upload_source(file_paths: &[String]) -> String {
+    let mut parents = file_paths.iter().map(|p| Path::new(p).parent());
+    match parents.next() {
+        Some(Some(first)) if parents.all(|p| p == Some(first)) => first.to_string_lossy().to_string(),
+        _ => format!("{} files", file_paths.len()),
+    }
 }
 
-/// COMMAND: Scan library for duplicates and compute missing hashes
+/// COMMAND: Upload Photos
+/// Copies photos to the Terra managed library and saves metadata to database.
+/// Metadata extraction and copies run in parallel; duplicate checks and the
+/// database insert happen on one thread. A file that fails is reported in
+/// `failed` and the rest still import. Emits `upload_progress` after each file.
+/// Content already in the library is skipped unless `allow_duplicates`.
+/// `mode` "move" removes each original once it is safely in the library;
+/// the default "copy" never touches originals. Copies are checked against the
+/// source hash unless the `verify_uploads` setting is false (length only).
+/// Nothing is copied if the files won't fit on the library's drive; the error
+/// is then `{ kind: "insufficient_space", needed, available }`. Moves from
+/// the same drive are renames and don't count.
 #[tauri::command]
-async fn scan_for_duplicates(window: tauri::Window) -> Result<ScanProgress, String> {
-    let conn = db_conn()?;
-
-    // Get photos that need hash computation
-    let photos_without_hash = db::get_photos_without_dhash(&conn)
-        .map_err(|e| format!("Failed to get photos: {}", e))?;
+async fn upload_photos(
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    file_paths: Vec<String>,
+    allow_duplicates: Option<bool>,
+    mode: Option<UploadMode>,
+) -> Result<UploadResult, UploadError> {
+    let _active = scan_state.begin();
+    let mode = mode.unwrap_or_default();
+    info!("Uploading {} photos ({:?})", file_paths.len(), mode);
+    let allow_duplicates = allow_duplicates.unwrap_or(false);
+    let started = std::time::Instant::now();
+    let started_at_ms = chrono::Utc::now().timestamp_millis();
+
+    let (library_path, verify) = {
+        let conn = db_conn(&db_state)?;
+        (db::get_library_path(&conn)?, db::get_setting_or(&conn, "verify_uploads", true))
+    };
 
-    let total = photos_without_hash.len() as u32;
-    let processed = Arc::new(AtomicU32::new(0));
+    // Use cached geocoder locations for better performance
+    let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
 
-    // Emit initial progress
-    let _ = window.emit("scan_progress", ScanProgress {
-        total,
-        processed: 0,
-        phase: "hashing".to_string(),
-    });
+    let total = file_paths.len() as u32;
+    let done = AtomicU32::new(0);
+    let report = |file: &str, status: &'static str| {
+        let current = done.fetch_add(1, Ordering::SeqCst) + 1;
+        let _ = window.emit("upload_progress", UploadProgress {
+            current,
+            total,
+            file: file.to_string(),
+            status,
+        });
+    };
+    let mut result = UploadResult::default();
 
-    // Process photos in parallel and collect results
-    let results: Vec<(String, Option<u64>)> = photos_without_hash
+    // 1. Read metadata and content hashes in parallel
+    let read: Vec<Result<PhotoMetadata, UploadFailure>> = file_paths
         .par_iter()
-        .map(|(path, _name)| {
-            let hash = compute_dhash(Path::new(path));
+        .map(|file_path| read_upload_source(file_path, &geocoder))
+        .collect();
 
-            // Update progress
-            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
-            if current % 10 == 0 || current == total {
-                let _ = window.emit("scan_progress", ScanProgress {
-                    total,
-                    processed: current,
-                    phase: "hashing".to_string(),
-                });
+    // 2. Drop duplicates in selection order: the same file picked twice, or
+    // content already in the library
+    let mut to_copy: Vec<(String, PhotoMetadata)> = Vec::new();
+    // (source, first source with the same content, hash) for repeats within this upload
+    let mut repeats: Vec<(String, String, String)> = Vec::new();
+    {
+        let conn = db_conn(&db_state)?;
+        let mut batch_hashes: HashMap<String, String> = HashMap::new();
+        for (file_path, outcome) in file_paths.iter().zip(read) {
+            let photo = match outcome {
+                Ok(photo) => photo,
+                Err(failure) => {
+                    warn!("Upload of {} failed: {}", file_path, failure.error);
+                    report(file_path, "failed");
+                    result.failed.push(failure);
+                    continue;
+                }
+            };
+            let hash = match &photo.content_hash {
+                Some(hash) if !allow_duplicates => hash.clone(),
+                _ => {
+                    to_copy.push((file_path.clone(), photo));
+                    continue;
+                }
+            };
+            if let Some(first) = batch_hashes.get(&hash) {
+                debug!("Skipping duplicate within upload: {} (same as {})", file_path, first);
+                report(file_path, "duplicate");
+                repeats.push((file_path.clone(), first.clone(), hash));
+                continue;
+            }
+            batch_hashes.insert(hash.clone(), file_path.clone());
+            match db::find_photo_by_hash(&conn, &hash) {
+                Ok(Some(existing)) => {
+                    debug!("Skipping duplicate photo: {} (already at {})", file_path, existing.path);
+                    report(file_path, "duplicate");
+                    result.duplicates.push(UploadDuplicate {
+                        path: file_path.clone(),
+                        duplicate_of: existing.path.clone(),
+                        photo: Some(existing),
+                    });
+                }
+                Ok(None) => to_copy.push((file_path.clone(), photo)),
+                Err(e) => {
+                    warn!("Failed to check hash existence: {}", e);
+                    to_copy.push((file_path.clone(), photo));
+                }
             }
+        }
+    }
 
-            (path.clone(), hash)
+    // Refuse up front rather than fail halfway with a full disk
+    let sources = to_copy.iter().map(|(source, _)| Path::new(source.as_str()));
+    let incoming = library::space_needed(sources, &library_path, mode == UploadMode::Move);
+    if incoming > 0 {
+        let available = fs2::available_space(&library_path)
+            .map_err(|e| format!("Cannot read free space on {}: {}", library_path.display(), e))?;
+        let needed = incoming + config::UPLOAD_SPACE_MARGIN_BYTES;
+        if needed > available {
+            warn!("Upload needs {} bytes but only {} are free on {}", needed, available, library_path.display());
+            return Err(UploadError::InsufficientSpace { needed, available });
+        }
+    }
+
+    // 3. Copy in parallel; names are claimed with create_new, so two files
+    // called IMG_0001.jpg landing in the same month can't collide
+    let copied: Vec<Result<CopiedUpload, UploadFailure>> = to_copy
+        .into_par_iter()
+        .map(|(source, photo)| {
+            let outcome = copy_into_library(&library_path, &source, photo, allow_duplicates, mode, verify);
+            let status = match &outcome {
+                Ok(upload) => upload.transfer.as_str(),
+                Err(_) => "failed",
+            };
+            report(&source, status);
+            outcome
         })
         .collect();
+    let mut saved: Vec<CopiedUpload> = Vec::new();
+    for outcome in copied {
+        match outcome {
+            Ok(upload) => saved.push(upload),
+            Err(failure) => {
+                warn!("Upload of {} failed: {}", failure.path, failure.error);
+                result.failed.push(failure);
+            }
+        }
+    }
 
-    // Emit comparing phase
-    let _ = window.emit("scan_progress", ScanProgress {
-        total,
-        processed: total,
-        phase: "saving".to_string(),
-    });
+    // 4. Save everything in one transaction
+    let live_pairs = live_photos::find_pairs(saved.iter().map(|u| u.photo.path.as_str()));
+    let stacks = stacks::find_stacks(saved.iter().map(|u| u.photo.path.as_str()));
+    let conn = db_conn(&db_state)?;
+    let photos: Vec<PhotoMetadata> = saved.iter().map(|u| u.photo.clone()).collect();
+    let failures: HashMap<String, String> = db::insert_photos_batch(&conn, &photos, "upload")
+        .map_err(|e| format!("Failed to save uploaded photos: {}", e))?
+        .into_iter()
+        .collect();
+    // So rescans and the watcher see the new library files as unchanged
+    let _ = db::update_file_stats_batch(&conn, &saved_file_stats(&saved, &failures));
+    record_live_photo_pairs(&conn, &live_pairs);
+    record_stacks(&conn, &stacks);
 
-    // Save hashes to database (must be done sequentially)
-    for (path, hash) in results {
-        if let Some(h) = hash {
-            let _ = db::update_photo_dhash(&conn, &path, h as i64);
+    // Trace each new library file back to where it came from
+    let originals: Vec<(String, String)> = saved
+        .iter()
+        .filter(|u| u.transfer != library::Transfer::Existing && !failures.contains_key(&u.photo.path))
+        .map(|u| (u.photo.path.clone(), u.source.clone()))
+        .collect();
+    if let Err(e) = db::set_original_paths(&conn, &originals) {
+        warn!("Failed to record original paths: {}", e);
+    }
+
+    for upload in saved {
+        let photo = upload.photo;
+        if let Some(err) = failures.get(&photo.path) {
+            error!("Failed to save {} to database: {}", photo.path, err);
+            // Don't leave an untracked copy behind in the library. A moved
+            // file is the only one left, so it stays for a rescan to pick up.
+            match upload.transfer {
+                library::Transfer::Copied => {
+                    let _ = library::delete_file_permanently(Path::new(&photo.path));
+                }
+                library::Transfer::Existing => {}
+                library::Transfer::Moved | library::Transfer::CopiedThenDeleted => {
+                    warn!("{} was moved into the library but isn't in the database", photo.path);
+                }
+            }
+            result.failed.push(UploadFailure {
+                path: upload.source,
+                kind: UploadErrorKind::DbInsertFailed,
+                error: err.clone(),
+            });
+            continue;
         }
+        if let Some(dhash) = upload.dhash {
+            let _ = db::update_photo_dhash(&conn, &photo.path, dhash as i64);
+        }
+        result.throughput.bytes += upload.bytes;
+        result.transfers.push(UploadTransfer {
+            source: upload.source,
+            dest: photo.path.clone(),
+            transfer: upload.transfer,
+        });
+        result.succeeded.push(photo);
+    }
+    let session_paths: Vec<String> = result.succeeded.iter().map(|p| p.path.clone()).collect();
+    let session_source = describe_upload_source(&file_paths);
+    if let Err(e) = db::record_import_session(&conn, "upload", &session_source, started_at_ms, &session_paths, result.failed.len()) {
+        warn!("Failed to record import session: {}", e);
     }
+    drop(conn);
 
-    // Emit completion
-    let _ = window.emit("scan_progress", ScanProgress {
-        total,
-        processed: total,
-        phase: "complete".to_string(),
-    });
+    // Point repeats at wherever their first occurrence ended up
+    for (source, first, hash) in repeats {
+        let imported = result
+            .succeeded
+            .iter()
+            .chain(result.duplicates.iter().filter_map(|d| d.photo.as_ref()))
+            .find(|p| p.content_hash.as_deref() == Some(hash.as_str()))
+            .cloned();
+        result.duplicates.push(UploadDuplicate {
+            path: source,
+            duplicate_of: imported.as_ref().map_or(first, |p| p.path.clone()),
+            photo: imported,
+        });
+    }
 
-    Ok(ScanProgress {
-        total,
-        processed: total,
-        phase: "complete".to_string(),
-    })
+    let elapsed = started.elapsed();
+    let secs = elapsed.as_secs_f64().max(0.001);
+    result.throughput.files = result.succeeded.len() as u32;
+    result.throughput.elapsed_ms = elapsed.as_millis() as u64;
+    result.throughput.files_per_sec = result.throughput.files as f64 / secs;
+    result.throughput.mb_per_sec = result.throughput.bytes as f64 / (1024.0 * 1024.0) / secs;
+
+    info!(
+        "Uploaded {} photos ({} failed, {} duplicates) in {} ms, {:.1} MB/s",
+        result.succeeded.len(),
+        result.failed.len(),
+        result.duplicates.len(),
+        result.throughput.elapsed_ms,
+        result.throughput.mb_per_sec
+    );
+    Ok(result)
 }
 
-/// COMMAND: Get duplicate groups based on hash similarity
+/// Result of import_apple_photos.
+#[derive(Serialize, Default)]
+pub struct ApplePhotosImport {
+    pub imported: u32,
+    /// Photos Terra already had (same content); they still get favorites and albums
+    pub already_imported: u32,
+    /// Originals kept only in iCloud ("Optimize Mac Storage"), by name
+    pub skipped_in_icloud: Vec<String>,
+    pub failed: Vec<UploadFailure>,
+    /// Albums created or added to
+    pub albums: u32,
+}
+
+/// COMMAND: Copy the originals out of a macOS Photos library into the Terra
+/// library, keeping Photos' capture dates, favorites, locations and albums.
+/// Originals that are only in iCloud are reported in `skipped_in_icloud`
+/// rather than failing the import, and content Terra already has isn't
+/// copied again, so the import can be re-run after downloading them.
+/// Copies are verified as in upload_photos. Emits `import_progress` events.
+/// macOS only.
 #[tauri::command]
-fn get_duplicate_groups(threshold: u32) -> Result<Vec<DuplicateGroup>, String> {
-    let conn = db_conn()?;
+async fn import_apple_photos(
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    library_path: String,
+) -> Result<ApplePhotosImport, String> {
+    #[cfg(not(target_os = "macos"))]
+    {
+        let _ = (window, scan_state, db_state, library_path);
+        Err("Importing from Apple Photos is only available on macOS".to_string())
+    }
+    #[cfg(target_os = "macos")]
+    {
+        info!("Importing Photos library: {}", library_path);
+        let _active = scan_state.begin();
+        let photos_library = apple_photos::read_library(Path::new(&library_path))?;
+        let (terra_library, verify) = {
+            let conn = db_conn(&db_state)?;
+            (db::get_library_path(&conn)?, db::get_setting_or(&conn, "verify_uploads", true))
+        };
+        let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+        let mut summary = ApplePhotosImport::default();
+
+        let (local, offloaded): (Vec<&apple_photos::AppleAsset>, Vec<&apple_photos::AppleAsset>) =
+            photos_library.assets.iter().partition(|a| a.original.is_file());
+        summary.skipped_in_icloud = offloaded.iter().map(|a| a.display_name()).collect();
+
+        let total = local.len() as u32;
+        let report = |counter: &AtomicU32, phase: &str| {
+            let processed = counter.fetch_add(1, Ordering::SeqCst) + 1;
+            if processed % 25 == 0 || processed == total {
+                let _ = window.emit("import_progress", ScanProgress {
+                    total,
+                    processed,
+                    phase: phase.to_string(),
+                });
+            }
+        };
+
+        // 1. Read each original, then lay Photos' own metadata over it
+        let read_count = AtomicU32::new(0);
+        let read: Vec<(&apple_photos::AppleAsset, Result<PhotoMetadata, UploadFailure>)> = local
+            .par_iter()
+            .map(|&asset| {
+                let source = asset.original.to_string_lossy();
+                let outcome = read_upload_source(&source, &geocoder).map(|mut photo| {
+                    asset.overlay(&mut photo);
+                    if let (None, Some((lat, lon))) = (photo.latitude, asset.gps) {
+                        photo.latitude = Some(lat);
+                        photo.longitude = Some(lon);
+                        photo.location_name = media::get_location_name(lat, lon, &geocoder);
+                    }
+                    photo
+                });
+                report(&read_count, "reading");
+                (asset, outcome)
+            })
+            .collect();
+
+        // 2. Skip content Terra already has. Photos asset id -> Terra path,
+        // for album membership
+        let mut terra_paths: HashMap<i64, String> = HashMap::new();
+        // (asset, asset with the same content) for duplicates inside Photos
+        let mut repeats: Vec<(i64, i64)> = Vec::new();
+        let mut to_copy: Vec<(&apple_photos::AppleAsset, PhotoMetadata)> = Vec::new();
+        {
+            let conn = db_conn(&db_state)?;
+            let mut seen: HashMap<String, i64> = HashMap::new();
+            for (asset, outcome) in read {
+                let photo = match outcome {
+                    Ok(photo) => photo,
+                    Err(failure) => {
+                        warn!("Import of {} failed: {}", failure.path, failure.error);
+                        summary.failed.push(failure);
+                        continue;
+                    }
+                };
+                if let Some(hash) = photo.content_hash.clone() {
+                    if let Some(&first) = seen.get(&hash) {
+                        repeats.push((asset.id, first));
+                        continue;
+                    }
+                    seen.insert(hash.clone(), asset.id);
+                    if let Ok(Some(existing)) = db::find_photo_by_hash(&conn, &hash) {
+                        if asset.is_favorite {
+                            let _ = db::set_photo_favorite(&conn, &existing.path, true);
+                        }
+                        summary.already_imported += 1;
+                        terra_paths.insert(asset.id, existing.path);
+                        continue;
+                    }
+                }
+                to_copy.push((asset, photo));
+            }
+        }
 
-    // Get all photos with their hashes
-    let photos_with_hash = db::get_all_photos_with_dhash(&conn)
-        .map_err(|e| format!("Failed to get photos: {}", e))?;
+        // 3. Copy in parallel
+        let copy_count = AtomicU32::new(0);
+        let copied: Vec<(i64, Result<CopiedUpload, UploadFailure>)> = to_copy
+            .into_par_iter()
+            .map(|(asset, photo)| {
+                let source = asset.original.to_string_lossy();
+                let outcome = copy_into_library(&terra_library, &source, photo, false, UploadMode::Copy, verify);
+                report(&copy_count, "copying");
+                (asset.id, outcome)
+            })
+            .collect();
+
+        // 4. Save, then recreate albums
+        let mut saved: Vec<(i64, CopiedUpload)> = Vec::new();
+        for (id, outcome) in copied {
+            match outcome {
+                Ok(upload) => saved.push((id, upload)),
+                Err(failure) => {
+                    warn!("Import of {} failed: {}", failure.path, failure.error);
+                    summary.failed.push(failure);
+                }
+            }
+        }
+        let conn = db_conn(&db_state)?;
+        let photos: Vec<PhotoMetadata> = saved.iter().map(|(_, u)| u.photo.clone()).collect();
+        let failures: HashMap<String, String> = db::insert_photos_batch(&conn, &photos, "apple_photos")
+            .map_err(|e| format!("Failed to save imported photos: {}", e))?
+            .into_iter()
+            .collect();
+        let _ = db::update_file_stats_batch(&conn, &saved_file_stats(saved.iter().map(|(_, u)| u), &failures));
+
+        let mut originals: Vec<(String, String)> = Vec::new();
+        for (id, upload) in saved {
+            let path = upload.photo.path;
+            if let Some(err) = failures.get(&path) {
+                error!("Failed to save {} to database: {}", path, err);
+                if upload.transfer == library::Transfer::Copied {
+                    let _ = library::delete_file_permanently(Path::new(&path));
+                }
+                summary.failed.push(UploadFailure {
+                    path: upload.source,
+                    kind: UploadErrorKind::DbInsertFailed,
+                    error: err.clone(),
+                });
+                continue;
+            }
+            if let Some(dhash) = upload.dhash {
+                let _ = db::update_photo_dhash(&conn, &path, dhash as i64);
+            }
+            originals.push((path.clone(), upload.source));
+            terra_paths.insert(id, path);
+            summary.imported += 1;
+        }
+        if let Err(e) = db::set_original_paths(&conn, &originals) {
+            warn!("Failed to record original paths: {}", e);
+        }
 
-    // Also get full photo metadata for later
-    let all_photos = db::get_all_photos(&conn)
-        .map_err(|e| format!("Failed to get photo metadata: {}", e))?;
+        for (id, first) in repeats {
+            if let Some(path) = terra_paths.get(&first).cloned() {
+                terra_paths.insert(id, path);
+            }
+        }
+        for album in &photos_library.albums {
+            let paths: Vec<&String> = album.assets.iter().filter_map(|id| terra_paths.get(id)).collect();
+            if paths.is_empty() {
+                continue;
+            }
+            let album_id = match db::find_album_by_name(&conn, &album.title)
+                .map_err(|e| format!("Failed to look up album {}: {}", album.title, e))?
+            {
+                Some(id) => id,
+                None => db::create_album(&conn, &album.title)
+                    .map_err(|e| format!("Failed to create album {}: {}", album.title, e))?,
+            };
+            for path in paths {
+                if let Err(e) = db::add_photo_to_album(&conn, album_id, path) {
+                    warn!("Failed to add {} to album {}: {}", path, album.title, e);
+                }
+            }
+            summary.albums += 1;
+        }
+        drop(conn);
+
+        info!(
+            "Photos import: {} imported, {} already in Terra, {} only in iCloud, {} failed, {} albums",
+            summary.imported,
+            summary.already_imported,
+            summary.skipped_in_icloud.len(),
+            summary.failed.len(),
+            summary.albums
+        );
+        if summary.imported > 0 {
+            spawn_post_import_jobs(window.app_handle());
+        }
+        Ok(summary)
+    }
+}
 
-    // Create a map for quick lookup
-    let photo_map: HashMap<String, PhotoMetadata> = all_photos
-        .into_iter()
-        .map(|p| (p.path.clone(), p))
-        .collect();
+/// Check an upload source and read its metadata (especially date_taken and
+/// content hash).
+fn read_upload_source(file_path: &str, geocoder: &ReverseGeocoder) -> Result<PhotoMetadata, UploadFailure> {
+    let fail = |kind, error| UploadFailure { path: file_path.to_string(), kind, error };
+    let source_path = Path::new(file_path);
+    if !source_path.is_file() {
+        return Err(fail(UploadErrorKind::SourceMissing, format!("File not found: {}", file_path)));
+    }
+    if !is_media_file(source_path) {
+        return Err(fail(UploadErrorKind::UnsupportedFormat, format!("Not a supported photo or video: {}", file_path)));
+    }
+    process_image(source_path, Some(geocoder))
+        .ok_or_else(|| fail(UploadErrorKind::UnsupportedFormat, format!("Could not read {}", file_path)))
+}
 
-    let mut groups: Vec<DuplicateGroup> = Vec::new();
-    let mut processed_paths: std::collections::HashSet<String> = std::collections::HashSet::new();
-    let mut group_id: u32 = 0;
+/// Copy (or move) one file into the library's year/month folders and point
+/// its metadata at the result. Also derives the perceptual hash and
+/// screenshot flag while the file is hot in cache. Unless `allow_duplicates`,
+/// an identical file already sitting at the destination is used instead of a
+/// second copy, and the original is left alone even when moving. `verify`
+/// checks a copy's hash against the source's; moves across drives always do.
+fn copy_into_library(
+    library_path: &Path,
+    source: &str,
+    mut photo: PhotoMetadata,
+    allow_duplicates: bool,
+    mode: UploadMode,
+    verify: bool,
+) -> Result<CopiedUpload, UploadFailure> {
+    let copy_failed = |error: String| UploadFailure {
+        path: source.to_string(),
+        kind: UploadErrorKind::CopyFailed,
+        error,
+    };
+    let source_path = Path::new(source);
+
+    // Create year/month subdirectories based on date_taken
+    let dest_dir = library::month_folder(library_path, photo.date_taken)
+        .ok_or_else(|| copy_failed(format!("Invalid date for {}", source)))?;
+    fs::create_dir_all(&dest_dir)
+        .map_err(|e| copy_failed(format!("Failed to create {}: {}", dest_dir.display(), e)))?;
+
+    // Named after photo.name, which is the source's name unless the caller
+    // knows a better one (Photos stores originals under UUIDs)
+    let file_name = Path::new(&photo.name)
+        .file_name()
+        .ok_or_else(|| copy_failed(format!("No file name in {}", source)))?;
+    let dest = dest_dir.join(file_name);
+    let reused_existing = !allow_duplicates
+        && photo.content_hash.is_some()
+        && dest.is_file()
+        && media::calculate_hash(&dest) == photo.content_hash;
+
+    let (final_dest_path, transfer, bytes) = if reused_existing {
+        debug!("{} already exists with identical content", dest.display());
+        (dest, library::Transfer::Existing, 0)
+    } else {
+        let (final_dest_path, placeholder) = library::create_unique(&dest)
+            .map_err(|e| copy_failed(format!("Failed to create file in {}: {}", dest_dir.display(), e)))?;
+        drop(placeholder);
+        let written = match mode {
+            UploadMode::Copy => {
+                let expected_hash = photo.content_hash.as_deref().filter(|_| verify);
+                library::copy_verified(source_path, &final_dest_path, expected_hash)
+                    .map(|bytes| (library::Transfer::Copied, bytes))
+            }
+            UploadMode::Move => library::move_file(source_path, &final_dest_path),
+        };
+        let (transfer, bytes) = written.map_err(|e| {
+            let _ = fs::remove_file(&final_dest_path);
+            let action = if mode == UploadMode::Move { "move" } else { "copy" };
+            UploadFailure {
+                path: source.to_string(),
+                kind: if e.kind() == std::io::ErrorKind::InvalidData {
+                    UploadErrorKind::VerifyFailed
+                } else {
+                    UploadErrorKind::CopyFailed
+                },
+                error: format!("Failed to {} {}: {}", action, source, e),
+            }
+        })?;
+        (final_dest_path, transfer, bytes)
+    };
 
-    // First, find exact duplicates (same content_hash)
-    let mut hash_groups: HashMap<String, Vec<String>> = HashMap::new();
-    for (path, _, content_hash) in &photos_with_hash {
-        if let Some(hash) = content_hash {
-            hash_groups.entry(hash.clone()).or_default().push(path.clone());
+    // Canonicalize the destination path for Tauri file access
+    let canonical_dest = match library::canonical_path(&final_dest_path) {
+        Ok(p) => p,
+        Err(e) => {
+            warn!("Could not canonicalize destination {:?}: {}", final_dest_path, e);
+            library::stored_path(&final_dest_path)
         }
+    };
+
+    // Update photo path to the new canonicalized location
+    photo.path = canonical_dest;
+    if let Some(name) = final_dest_path.file_name() {
+        photo.name = name.to_string_lossy().to_string();
     }
+    debug!("{}: {} -> {}", transfer.as_str(), source, photo.path);
+
+    let dhash = compute_dhash(Path::new(&photo.path));
+    Ok(CopiedUpload {
+        source: source.to_string(),
+        photo,
+        bytes,
+        dhash,
+        transfer,
+    })
+}
 
-    for (_, paths) in hash_groups {
-        if paths.len() > 1 {
-            let photos: Vec<PhotoMetadata> = paths.iter()
-                .filter_map(|p| photo_map.get(p).cloned())
-                .collect();
+/// COMMAND: toggle_favorite_by_id for the photo at `path`
+#[tauri::command]
+fn toggle_favorite(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    is_favorite: bool,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to set favorite", |c| db::set_photo_favorite(c, &path, is_favorite))
+}
 
-            if photos.len() > 1 {
-                for path in &paths {
-                    processed_paths.insert(path.clone());
-                }
+/// COMMAND: Set a photo's favorite status by its id. Unknown ids are ignored.
+#[tauri::command]
+fn toggle_favorite_by_id(db_state: tauri::State<'_, DbState>, id: i64, is_favorite: bool) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to set favorite", |c| db::set_photos_favorite_by_id(c, &[id], is_favorite).map(drop))
+}
 
-                groups.push(DuplicateGroup {
-                    group_id,
-                    group_type: "exact".to_string(),
-                    photos,
-                    similarity_score: 1.0,
-                });
-                group_id += 1;
-            }
-        }
+/// COMMAND: Set favorite status on many photos at once.
+/// Returns the number of photos updated; fewer than `paths.len()` means some no longer exist.
+#[tauri::command]
+fn set_favorites(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    is_favorite: bool,
+) -> Result<usize, String> {
+    let conn = db_conn(&db_state)?;
+    let previous = db::get_favorite_flags(&conn, &paths).map_err(|e| format!("Failed to set favorites: {}", e))?;
+    let updated = db::set_photos_favorite(&conn, &paths, is_favorite).map_err(|e| format!("Failed to set favorites: {}", e))?;
+    if !previous.is_empty() {
+        log_operation(&conn, &Operation::SetFavorites { previous, is_favorite });
     }
+    Ok(updated)
+}
 
-    // Then, find similar photos (perceptual hash within threshold)
-    // Use provided threshold or fall back to configured default
-    let effective_threshold = if threshold == 0 { config::DUPLICATE_HAMMING_THRESHOLD } else { threshold };
+/// COMMAND: Hide or unhide photos. Hidden photos stay in the library and its
+/// exports but drop out of the grid, albums and search.
+/// Returns the number of photos updated.
+#[tauri::command]
+fn set_hidden(db_state: tauri::State<'_, DbState>, paths: Vec<String>, hidden: bool) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to set hidden", |c| db::set_photos_hidden(c, &paths, hidden))
+}
 
-    for i in 0..photos_with_hash.len() {
-        let (path_i, hash_i, _) = &photos_with_hash[i];
+/// COMMAND: List hidden photos. Kept apart from get_all_photos so the
+/// frontend can put the hidden view behind a confirmation.
+#[tauri::command]
+fn get_hidden_photos(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get hidden photos", |c| {
+        let mut photos = db::get_hidden_photos(c)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-        if processed_paths.contains(path_i) {
-            continue;
-        }
+/// COMMAND: Archive photos off the main timeline, or bring them back.
+/// They stay in albums and search; see archive_photos for the deletion archive.
+/// Returns the number of photos updated.
+#[tauri::command]
+fn set_archived(db_state: tauri::State<'_, DbState>, paths: Vec<String>, archived: bool) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to set archived", |c| db::set_photos_archived(c, &paths, archived))
+}
 
-        if let Some(h_i) = hash_i {
-            let mut similar_paths: Vec<String> = vec![path_i.clone()];
+/// COMMAND: List photos archived off the timeline with set_archived
+#[tauri::command]
+fn get_archived_photos(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get archived photos", |c| {
+        let mut photos = db::get_archived_photos(c)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-            for j in (i + 1)..photos_with_hash.len() {
-                let (path_j, hash_j, _) = &photos_with_hash[j];
+/// COMMAND: List import sessions (one per scan or upload), newest first
+#[tauri::command]
+fn get_import_sessions(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::ImportSession>, String> {
+    with_db(&db_state, "Failed to get import sessions", |c| db::get_import_sessions(c))
+}
 
-                if processed_paths.contains(path_j) {
-                    continue;
-                }
+/// COMMAND: Photos an import session added that are still in the library
+#[tauri::command]
+fn get_session_photos(db_state: tauri::State<'_, DbState>, session_id: i64) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get session photos", |c| {
+        let mut photos = db::get_session_photos(c, session_id)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-                if let Some(h_j) = hash_j {
-                    let distance = hamming_distance(*h_i as u64, *h_j as u64);
-                    if distance <= effective_threshold && distance > 0 {
-                        similar_paths.push(path_j.clone());
-                    }
-                }
-            }
+/// COMMAND: Undo an import: move every photo it added to the trash and forget
+/// the session. Refused once any of them is in an album.
+/// Returns the number of photos trashed.
+#[tauri::command]
+fn undo_import_session(db_state: tauri::State<'_, DbState>, session_id: i64) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+    let in_albums = db::count_session_photos_in_albums(&conn, session_id)
+        .map_err(|e| format!("Failed to read import session: {}", e))?;
+    if in_albums > 0 {
+        return Err(format!("{} photos from this import are in albums; remove them first", in_albums));
+    }
+    let paths: Vec<String> = db::get_session_photos(&conn, session_id)
+        .map_err(|e| format!("Failed to read import session: {}", e))?
+        .into_iter()
+        .map(|p| p.path)
+        .collect();
+    let trashed = trash_photos(&conn, &paths)?;
+    if trashed.len() < paths.len() {
+        // Keep the session so the rest can be retried
+        return Err(format!("Moved {} of {} photos to trash", trashed.len(), paths.len()));
+    }
+    db::delete_import_session(&conn, session_id).map_err(|e| format!("Failed to remove import session: {}", e))?;
+    info!("Undid import session {}: {} photos to trash", session_id, trashed.len());
+    Ok(trashed.len() as u32)
+}
+
+/// COMMAND: Set a photo's star rating (0-5; 0 clears it)
+#[tauri::command]
+fn set_photo_rating(db_state: tauri::State<'_, DbState>, path: String, rating: u8) -> Result<(), String> {
+    validate_rating(rating)?;
+    if with_db_write(&db_state, "Failed to set rating", |c| db::set_photo_rating(c, &path, rating))? {
+        Ok(())
+    } else {
+        Err(format!("Photo not found: {}", path))
+    }
+}
+
+/// COMMAND: Set the same star rating on several photos. Returns how many were updated.
+#[tauri::command]
+fn set_photos_rating(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    rating: u8,
+) -> Result<usize, String> {
+    validate_rating(rating)?;
+    with_db_write(&db_state, "Failed to set ratings", |c| db::set_photos_rating(c, &paths, rating))
+}
+
+/// COMMAND: Get photos rated at least `min_rating` stars
+#[tauri::command]
+fn get_photos_by_min_rating(
+    db_state: tauri::State<'_, DbState>,
+    min_rating: u8,
+) -> Result<Vec<PhotoMetadata>, String> {
+    validate_rating(min_rating)?;
+    with_db(&db_state, "Failed to get rated photos", |c| db::get_photos_by_min_rating(c, min_rating))
+}
+
+/// COMMAND: Set the same color label on several photos; `none` clears it.
+/// Returns how many were updated.
+#[tauri::command]
+fn set_color_label(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    label: db::ColorLabel,
+) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to set color labels", |c| db::set_photos_color_label(c, &paths, label))
+}
+
+fn validate_rating(rating: u8) -> Result<(), String> {
+    if rating > 5 {
+        return Err(format!("Rating must be between 0 and 5, got {}", rating));
+    }
+    Ok(())
+}
+
+/// COMMAND: Set a photo's caption. An empty string clears it.
+#[tauri::command]
+fn set_photo_description(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    text: String,
+) -> Result<(), String> {
+    if with_db_write(&db_state, "Failed to set description", |c| db::set_photo_description(c, &path, &text))? {
+        Ok(())
+    } else {
+        Err(format!("Photo not found: {}", path))
+    }
+}
+
+/// COMMAND: Photos whose file name and embedded metadata disagree on the
+/// date by more than a year. The embedded date is the one in use.
+#[tauri::command]
+fn get_date_discrepancies(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::DateDiscrepancy>, String> {
+    with_db(&db_state, "Failed to get date discrepancies", db::get_date_discrepancies)
+}
+
+/// COMMAND: Photos for which no date could be found on import, so the user
+/// can set one.
+#[tauri::command]
+fn get_undated_photos(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get undated photos", db::get_undated_photos)
+}
+
+/// A photo after its date was edited.
+#[derive(Serialize, Debug)]
+pub struct DateChange {
+    /// Where the photo is now
+    pub path: String,
+    /// Where it was, if it moved to a different month folder
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub previous_path: Option<String>,
+    pub date_taken: i64,
+    /// Whether the new date was written into the file's EXIF
+    pub exif_written: bool,
+}
+
+/// COMMAND: Set one photo's capture date by hand (scanned film, undated
+/// imports). `move_file` refiles a library photo into the new month's
+/// folder; `write_exif` writes the date into a JPEG so exports carry it.
+#[tauri::command]
+fn set_photo_date(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    timestamp: i64,
+    move_file: Option<bool>,
+    write_exif: Option<bool>,
+) -> Result<DateChange, String> {
+    let conn = db_conn(&db_state)?;
+    let previous = db::get_previous_date(&conn, &path).map_err(|e| format!("Failed to set date: {}", e))?;
+    let date = db::set_photo_date(&conn, &path, timestamp)
+        .map_err(|e| format!("Failed to set date: {}", e))?
+        .ok_or_else(|| format!("{} is not in the library", path))?;
+    let change = apply_date_files(&conn, vec![date], move_file.unwrap_or(false), write_exif.unwrap_or(false)).remove(0);
+    if let Some(previous) = previous {
+        let moved = change.previous_path.clone().map(|from| operations::FileMove { from, to: change.path.clone() });
+        log_operation(&conn, &Operation::SetPhotoDate { previous, new_date_taken: timestamp, moved });
+    }
+    drop(conn);
+    if change.exif_written {
+        queue_thumbnails(&app);
+    }
+    Ok(change)
+}
+
+/// COMMAND: Move the capture dates of several photos by the same amount,
+/// e.g. +3 hours for a camera whose clock was set wrong. Options as for
+/// `set_photo_date`.
+#[tauri::command]
+fn shift_photo_dates(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    delta_seconds: i64,
+    move_file: Option<bool>,
+    write_exif: Option<bool>,
+) -> Result<Vec<DateChange>, String> {
+    let conn = db_conn(&db_state)?;
+    let dates = db::shift_photo_dates(&conn, &paths, delta_seconds)
+        .map_err(|e| format!("Failed to shift dates: {}", e))?;
+    info!("Shifted {} photo dates by {}s", dates.len(), delta_seconds);
+    let changes = apply_date_files(&conn, dates, move_file.unwrap_or(false), write_exif.unwrap_or(false));
+    drop(conn);
+    if changes.iter().any(|c| c.exif_written) {
+        queue_thumbnails(&app);
+    }
+    Ok(changes)
+}
+
+/// Carry edited dates over to the files: write them into EXIF and refile
+/// library photos by month, as asked. The database already holds the new
+/// dates, so a file that can't be changed is logged and left as it was.
+/// Callers queue thumbnails for photos whose EXIF was written.
+fn apply_date_files(
+    conn: &rusqlite::Connection,
+    dates: Vec<db::PhotoDate>,
+    move_file: bool,
+    write_exif: bool,
+) -> Vec<DateChange> {
+    let library_root = if move_file { db::get_library_path(conn).ok() } else { None };
+    dates
+        .into_iter()
+        .map(|date| {
+            let mut change = DateChange {
+                path: date.path.clone(),
+                previous_path: None,
+                date_taken: date.date_taken,
+                exif_written: false,
+            };
+            let path = Path::new(&date.path);
+
+            if write_exif && media::file_format(path).as_deref() == Some("jpeg") {
+                let written = media::exif_wall_clock(date.date_taken, date.tz_offset_minutes)
+                    .ok_or_else(|| format!("{} is out of range", date.date_taken))
+                    .and_then(|(wall_clock, offset)| exif_write::write_capture_date(path, wall_clock, offset));
+                match written {
+                    Ok(_) => {
+                        change.exif_written = true;
+                        record_rewritten_file(conn, &date.path);
+                    }
+                    Err(e) => warn!("Couldn't write the date into {}: {}", date.path, e),
+                }
+            }
+
+            if let Some(dest) = library_root
+                .as_deref()
+                .and_then(|root| library::refiled_destination(path, root, date.date_taken))
+            {
+                match refile_photo(conn, &date.path, &library::unique_destination(dest)) {
+                    Ok(new_path) => {
+                        change.previous_path = Some(date.path.clone());
+                        change.path = new_path;
+                    }
+                    Err(e) => warn!("{}", e),
+                }
+            }
+            change
+        })
+        .collect()
+}
+
+/// Keep a photo's row current after Terra rewrote the file's metadata: its
+/// content hash and stats. True if the hash changed, which leaves the photo
+/// needing thumbnails under the new one (see `queue_thumbnails`).
+fn record_rewritten_file(conn: &rusqlite::Connection, path: &str) -> bool {
+    let file = Path::new(path);
+    if let Some((size, mtime)) = media::file_stats(file) {
+        let _ = db::update_file_stats(conn, path, size, mtime);
+    }
+    let Some(hash) = media::calculate_hash(file) else {
+        return false;
+    };
+    let changed = db::get_content_hash(conn, path).ok().flatten().as_deref() != Some(hash.as_str());
+    changed && db::update_content_hashes_batch(conn, &[(path.to_string(), hash)]).is_ok()
+}
+
+/// Queue thumbnails for photos without them, e.g. after record_rewritten_file
+/// changed their hashes. Takes the database lock, so call it with none held.
+fn queue_thumbnails(app: &tauri::AppHandle) {
+    if let Err(e) = app.state::<jobs::JobQueue>().enqueue(app, jobs::JobKind::Thumbnails, serde_json::json!({})) {
+        warn!("Couldn't queue thumbnails: {}", e);
+    }
+}
+
+/// Move a photo's file to `dest` and point its row (and album covers, Live
+/// Photo pairs) there. If the row can't be updated the file is put back so
+/// the two never disagree. Returns the new path.
+fn refile_photo(conn: &rusqlite::Connection, path: &str, dest: &Path) -> Result<String, String> {
+    let source = Path::new(path);
+    dest.parent()
+        .map_or(Ok(()), fs::create_dir_all)
+        .and_then(|_| library::move_file(source, dest))
+        .map_err(|e| format!("Couldn't move {} to {}: {}", path, dest.display(), e))?;
+    let new_path = dest.to_string_lossy().to_string();
+    if let Err(e) = db::update_photo_path(conn, path, &new_path) {
+        let _ = library::move_file(dest, source);
+        return Err(format!("Failed to record the new path of {}: {}", path, e));
+    }
+    Ok(new_path)
+}
+
+/// COMMAND: Rename a photo's file within its folder and update its row,
+/// album covers and Live Photo pairing to match; album, tag and face rows
+/// follow its id, and thumbnails are keyed by content. The extension is
+/// kept unless `change_extension` is set. A name already taken gets a
+/// `_1`-style suffix, as uploads do. Returns the renamed photo.
+#[tauri::command]
+fn rename_photo(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    new_name: String,
+    change_extension: Option<bool>,
+) -> Result<PhotoMetadata, String> {
+    let conn = db_conn(&db_state)?;
+    let source = Path::new(&path);
+    let name = library::renamed_file_name(source, &new_name, change_extension.unwrap_or(false))?;
+    let known = db::get_photo_ids(&conn, std::slice::from_ref(&path)).map_err(|e| format!("Failed to look up {}: {}", path, e))?;
+    if known.is_empty() {
+        return Err(format!("{} is not in the library", path));
+    }
+    if db::is_photo_offline(&conn, &path).unwrap_or(false) {
+        return Err(format!("{} is on an offline drive", path));
+    }
+
+    let case_insensitive = cfg!(any(target_os = "macos", windows));
+    let dest = library::rename_destination(source, source.with_file_name(&name), case_insensitive, |p| {
+        p.exists() || db::photo_exists(&conn, &p.to_string_lossy()).unwrap_or(false)
+    });
+    // Already called that: nothing to rename
+    let Some(dest) = dest else {
+        return db::get_photos_by_paths(&conn, std::slice::from_ref(&path))
+            .map_err(|e| format!("Failed to read {}: {}", path, e))?
+            .pop()
+            .ok_or_else(|| format!("{} is not in the library", path));
+    };
+    fs::rename(source, &dest).map_err(|e| format!("Couldn't rename {} to {}: {}", path, dest.display(), e))?;
+    let new_path = dest.to_string_lossy().to_string();
+    match db::rename_photo(&conn, &path, &new_path) {
+        Ok(Some(photo)) => {
+            info!("Renamed {} to {}", path, new_path);
+            Ok(photo)
+        }
+        result => {
+            let _ = fs::rename(&dest, source);
+            let reason = result.err().map_or("the photo is gone".to_string(), |e| e.to_string());
+            Err(format!("Failed to record the new name of {}: {}", path, reason))
+        }
+    }
+}
+
+/// `rename_template::plan` for `paths`, against the files on disk and the
+/// library's rows. Photos not in the library (or trashed) are left out.
+fn plan_batch_rename(
+    conn: &rusqlite::Connection,
+    paths: &[String],
+    template: &str,
+) -> Result<Vec<rename_template::BatchRenamePlan>, String> {
+    let template = rename_template::Template::parse(template)?;
+    let photos = db::get_photos_by_paths(conn, paths).map_err(|e| format!("Failed to read photos: {}", e))?;
+    rename_template::plan(&template, photos, cfg!(any(target_os = "macos", windows)), |p| {
+        p.exists() || db::photo_exists(conn, &p.to_string_lossy()).unwrap_or(false)
+    })
+}
+
+/// Rename each (path, new path) file, then update all their rows in one
+/// transaction. Every file is moved aside under a temporary name first, so
+/// one can take the name another is leaving. If a file can't be renamed or
+/// the rows can't be updated, the files are put back and nothing changes.
+fn rename_files_and_rows(conn: &rusqlite::Connection, renames: &[(String, String)]) -> Result<(), String> {
+    let parked: Vec<PathBuf> = renames
+        .iter()
+        .enumerate()
+        .map(|(i, (path, _))| library::unique_destination(Path::new(path).with_file_name(format!(".terra-rename-{}", i))))
+        .collect();
+    // (from, to) of every file rename done so far
+    let mut done: Vec<(PathBuf, PathBuf)> = Vec::new();
+    let put_back = |done: &[(PathBuf, PathBuf)]| {
+        for (from, to) in done.iter().rev() {
+            if let Err(e) = fs::rename(to, from) {
+                error!("Couldn't put {} back at {}: {}", to.display(), from.display(), e);
+            }
+        }
+    };
+    let aside = renames.iter().zip(&parked).map(|((path, new_path), temp)| (path, new_path, PathBuf::from(path), temp.clone()));
+    let into_place =
+        renames.iter().zip(&parked).map(|((path, new_path), temp)| (path, new_path, temp.clone(), PathBuf::from(new_path)));
+    for (path, new_path, from, to) in aside.chain(into_place) {
+        // Checked again: something may have landed there since the plan
+        let renamed = if to.exists() {
+            Err(format!("{} already exists", to.display()))
+        } else {
+            fs::rename(&from, &to).map_err(|e| e.to_string())
+        };
+        if let Err(e) = renamed {
+            put_back(&done);
+            return Err(format!("Couldn't rename {} to {}: {}", path, new_path, e));
+        }
+        done.push((from, to));
+    }
+    if let Err(e) = db::rename_photos(conn, renames) {
+        put_back(&done);
+        return Err(format!("Failed to record the new names: {}", e));
+    }
+    Ok(())
+}
+
+/// COMMAND: Show what `batch_rename` would do with the same arguments:
+/// each photo's new name in capture order, and whether it collided or was
+/// cut short. Changes nothing.
+#[tauri::command]
+fn preview_batch_rename(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    template: String,
+) -> Result<Vec<rename_template::BatchRenamePlan>, String> {
+    let conn = db_conn(&db_state)?;
+    plan_batch_rename(&conn, &paths, &template)
+}
+
+/// COMMAND: Rename `paths` from a template such as
+/// "{date:YYYY-MM-DD}_{seq:3}"; see `rename_template` for the tokens.
+/// Sequence numbers follow capture order and taken names get a `_1`-style
+/// suffix. All the photos are renamed or none are, and the rename can be
+/// reverted with `undo_last_operation`. Returns the plan as carried out.
+#[tauri::command]
+fn batch_rename(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    template: String,
+) -> Result<Vec<rename_template::BatchRenamePlan>, String> {
+    let conn = db_conn(&db_state)?;
+    let plan = plan_batch_rename(&conn, &paths, &template)?;
+    if let Some(offline) = plan.iter().find(|p| db::is_photo_offline(&conn, &p.path).unwrap_or(false)) {
+        return Err(format!("{} is on an offline drive", offline.path));
+    }
+    let changed: Vec<&rename_template::BatchRenamePlan> = plan.iter().filter(|p| p.new_path != p.path).collect();
+    if changed.is_empty() {
+        return Ok(plan);
+    }
+    let renames: Vec<(String, String)> = changed.iter().map(|p| (p.path.clone(), p.new_path.clone())).collect();
+    rename_files_and_rows(&conn, &renames)?;
+    info!("Renamed {} photos from template {:?}", renames.len(), template);
+    let renames = changed
+        .iter()
+        .map(|p| (p.id, operations::FileMove { from: p.path.clone(), to: p.new_path.clone() }))
+        .collect();
+    log_operation(&conn, &Operation::BatchRename { renames });
+    Ok(plan)
+}
+
+/// One photo's part in a date shift, as previewed and as applied.
+#[derive(Serialize, Clone, Debug)]
+pub struct DateShiftPlan {
+    pub path: String,
+    pub date_taken: i64,
+    pub new_date_taken: i64,
+    /// The month folder the new date files it in, for library photos filed
+    /// by month whose month changes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_folder: Option<String>,
+    /// Where its file goes when files are moved. Gets a `_1`-style suffix
+    /// if the name is taken on disk or by another photo in the batch.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub new_path: Option<String>,
+}
+
+/// Work out each photo's new date and folder for a shift of `delta_seconds`.
+/// Photos not in the library (or trashed) are left out.
+fn plan_date_shift(
+    conn: &rusqlite::Connection,
+    paths: &[String],
+    delta_seconds: i64,
+) -> Result<Vec<DateShiftPlan>, String> {
+    let dates = db::get_photo_dates(conn, paths).map_err(|e| format!("Failed to read dates: {}", e))?;
+    let library_root = db::get_library_path(conn).ok();
+    let mut claimed: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
+    dates
+        .into_iter()
+        .map(|(path, date_taken)| {
+            let new_date_taken = date_taken
+                .checked_add(delta_seconds)
+                .filter(|date| chrono::DateTime::from_timestamp(*date, 0).is_some())
+                .ok_or_else(|| format!("Shifting {} by {}s leaves the calendar", path, delta_seconds))?;
+            let dest = library_root
+                .as_deref()
+                .and_then(|root| library::refiled_destination(Path::new(&path), root, new_date_taken))
+                .map(|dest| library::unique_destination_avoiding(dest, |p| p.exists() || claimed.contains(p)));
+            if let Some(dest) = &dest {
+                claimed.insert(dest.clone());
+            }
+            Ok(DateShiftPlan {
+                new_folder: dest.as_deref().and_then(Path::parent).map(|dir| dir.to_string_lossy().to_string()),
+                new_path: dest.map(|dest| dest.to_string_lossy().to_string()),
+                path,
+                date_taken,
+                new_date_taken,
+            })
+        })
+        .collect()
+}
+
+/// COMMAND: Show what `apply_date_shift` would do with the same arguments:
+/// each photo's current and new date and, for library photos filed by
+/// month, the folder and path its file would move to. Changes nothing.
+#[tauri::command]
+fn preview_date_shift(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    delta_seconds: i64,
+) -> Result<Vec<DateShiftPlan>, String> {
+    let conn = db_conn(&db_state)?;
+    plan_date_shift(&conn, &paths, delta_seconds)
+}
+
+#[derive(Serialize, Default)]
+pub struct DateShiftSummary {
+    /// Pass to `undo_date_shift`; None if no photo was shifted
+    pub shift_id: Option<i64>,
+    pub shifted: u32,
+    /// The plan as carried out; `new_path` is only set for files that moved
+    pub photos: Vec<DateShiftPlan>,
+    /// Files that couldn't be moved; their dates still changed
+    pub failed: Vec<DateShiftFailure>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DateShiftFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// COMMAND: Shift the capture dates of `paths` by `delta_seconds`, e.g. a
+/// camera whose clock was years off for a whole trip. With `move_files`,
+/// library photos filed by month move to the new month's folder, with
+/// albums, tags and covers following them. The shift is logged and can be
+/// reverted with `undo_date_shift`. Check it with `preview_date_shift` first.
+#[tauri::command]
+fn apply_date_shift(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    delta_seconds: i64,
+    move_files: bool,
+) -> Result<DateShiftSummary, String> {
+    let conn = db_conn(&db_state)?;
+    let mut plan = plan_date_shift(&conn, &paths, delta_seconds)?;
+    let planned: Vec<String> = plan.iter().map(|p| p.path.clone()).collect();
+    let Some((shift_id, shifted)) = db::record_date_shift(&conn, &planned, delta_seconds)
+        .map_err(|e| format!("Failed to shift dates: {}", e))?
+    else {
+        return Ok(DateShiftSummary::default());
+    };
+    info!("Shifted {} photo dates by {}s (shift {})", shifted.len(), delta_seconds, shift_id);
+    let photo_ids = db::get_photo_ids(&conn, &planned).unwrap_or_default();
+    log_operation(&conn, &Operation::DateShift { shift_id, photo_ids });
+
+    let mut summary = DateShiftSummary { shift_id: Some(shift_id), shifted: shifted.len() as u32, ..Default::default() };
+    for photo in &mut plan {
+        let Some(dest) = photo.new_path.take().filter(|_| move_files) else {
+            continue;
+        };
+        // Checked again: something may have landed there since the plan
+        let dest = library::unique_destination(PathBuf::from(dest));
+        match refile_photo(&conn, &photo.path, &dest) {
+            Ok(new_path) => {
+                if let Err(e) = db::record_date_shift_move(&conn, shift_id, &photo.path, &new_path) {
+                    warn!("Failed to log the move of {}: {}", photo.path, e);
+                }
+                photo.new_path = Some(new_path);
+            }
+            Err(error) => {
+                warn!("{}", error);
+                summary.failed.push(DateShiftFailure { path: photo.path.clone(), error });
+            }
+        }
+    }
+    summary.photos = plan;
+    Ok(summary)
+}
+
+/// COMMAND: Undo an `apply_date_shift`: photos get their previous dates
+/// back (unless changed again since) and those refiled return to their old
+/// folders. Returns how many dates were restored.
+#[tauri::command]
+fn undo_date_shift(db_state: tauri::State<'_, DbState>, shift_id: i64) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+    revert_date_shift(&conn, shift_id)
+}
+
+/// Undo a date shift as `undo_date_shift` describes; returns how many dates were restored.
+fn revert_date_shift(conn: &rusqlite::Connection, shift_id: i64) -> Result<u32, String> {
+    let moves = db::get_date_shift_moves(conn, shift_id)
+        .map_err(|e| format!("Failed to read date shift: {}", e))?
+        .ok_or_else(|| format!("No date shift {}", shift_id))?;
+    for moved in moves {
+        let dest = library::unique_destination(PathBuf::from(&moved.old_path));
+        if let Err(e) = refile_photo(conn, &moved.current_path, &dest) {
+            warn!("{}", e);
+        }
+    }
+    let restored = db::undo_date_shift(conn, shift_id)
+        .map_err(|e| format!("Failed to undo date shift: {}", e))?
+        .unwrap_or(0);
+    info!("Undid date shift {}: {} dates restored", shift_id, restored);
+    Ok(restored as u32)
+}
+
+/// COMMAND: Turn a photo `degrees` (90, 180 or 270) clockwise. A JPEG only
+/// gets a new EXIF orientation, so nothing is re-encoded; other formats have
+/// their pixels turned and saved over the original. Rotating by 360 minus
+/// `degrees` undoes it. Emits `photo_changed` with the photo's new shape.
+#[tauri::command]
+fn rotate_photo(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    degrees: u16,
+) -> Result<db::ChangedPhoto, String> {
+    let rotated = rotate_file(&db_conn(&db_state)?, &path, degrees)?;
+    let _ = window.emit("photo_changed", &rotated);
+    Ok(rotated)
+}
+
+/// COMMAND: Turn several photos as `rotate_photo` does, for multi-select.
+/// A photo that can't be rotated is logged and skipped; returns the rest.
+#[tauri::command]
+fn rotate_photos(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    degrees: u16,
+) -> Result<Vec<db::ChangedPhoto>, String> {
+    let conn = db_conn(&db_state)?;
+    let mut rotated = Vec::new();
+    for path in &paths {
+        match rotate_file(&conn, path, degrees) {
+            Ok(photo) => {
+                let _ = window.emit("photo_changed", &photo);
+                rotated.push(photo);
+            }
+            Err(e) => warn!("Couldn't rotate {}: {}", path, e),
+        }
+    }
+    info!("Rotated {} of {} photos by {} degrees", rotated.len(), paths.len(), degrees);
+    Ok(rotated)
+}
+
+/// Rotate one library photo's file and record its new dimensions, hash and
+/// stats, read back from the file as a rescan would.
+/// The old hash's thumbnails go once no other photo uses them.
+fn rotate_file(conn: &rusqlite::Connection, path: &str, degrees: u16) -> Result<db::ChangedPhoto, String> {
+    if !matches!(degrees, 90 | 180 | 270) {
+        return Err(format!("Can't rotate by {} degrees", degrees));
+    }
+    // Checked first so a photo outside the library (or in the trash) isn't touched
+    if db::get_file_stats(conn, path).map_err(|e| format!("Failed to look up photo: {}", e))?.is_none() {
+        return Err(format!("{} is not in the library", path));
+    }
+
+    let file = Path::new(path);
+    let orientation = if media::file_format(file).as_deref() == Some("jpeg") {
+        Some(i32::from(exif_write::rotate_jpeg(file, degrees)?))
+    } else {
+        thumbnails::rotate_pixels(file, degrees)?;
+        None
+    };
+    if let Some((size, mtime)) = media::file_stats(file) {
+        let _ = db::update_file_stats(conn, path, size, mtime);
+    }
+    let hash = media::calculate_hash(file);
+    let dimensions = media::read_dimensions(file);
+    let (rotated, orphaned) = db::record_photo_rotation(conn, path, dimensions, orientation, hash.as_deref())
+        .map_err(|e| format!("Failed to record rotation: {}", e))?
+        .ok_or_else(|| format!("{} is not in the library", path))?;
+    if let Some(hash) = orphaned {
+        thumbnails::remove_thumbnails(&hash);
+    }
+    Ok(rotated)
+}
+
+/// How export_photos treats the files it writes.
+#[derive(Deserialize, Clone, Copy, Debug, Default)]
+pub struct ExportOptions {
+    /// Write Terra's date, caption, rating and location into the copies
+    #[serde(default)]
+    pub embed_metadata: bool,
+    /// Remove EXIF (GPS included), XMP and comments from the copies
+    #[serde(default)]
+    pub strip_metadata: bool,
+    /// Shrink images to this long edge and save them as JPEG ("export for web")
+    #[serde(default)]
+    pub max_edge: Option<u32>,
+    /// JPEG quality for resized or edited images, 1-100
+    #[serde(default)]
+    pub jpeg_quality: Option<u8>,
+    /// Export photos as they are on disk, or with their crop and rotation
+    #[serde(default)]
+    pub variant: ExportVariant,
+}
+
+/// Which version of an edited photo an export writes.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportVariant {
+    /// The file as it is on disk
+    #[default]
+    Original,
+    /// Crop and rotation applied, saved as JPEG. Photos without edits are
+    /// exported as they are
+    Edited,
+}
+
+/// Quality of resized exports when none is given.
+const EXPORT_JPEG_QUALITY: u8 = 85;
+
+/// Folder layout of an export.
+#[derive(Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum ExportStructure {
+    /// Everything straight into the destination
+    #[default]
+    Flat,
+    /// YYYY/MM folders, like the library
+    Date,
+    /// A folder per album; photos in no album go straight into the destination
+    Album,
+}
+
+/// One exported file.
+#[derive(Serialize, Debug)]
+pub struct ExportedFile {
+    pub source: String,
+    pub dest: String,
+    /// Why an option wasn't applied, e.g. metadata for a format Terra
+    /// can't write into
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub note: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ExportFailure {
+    pub path: String,
+    pub error: String,
+}
+
+#[derive(Serialize, Default, Debug)]
+pub struct ExportResult {
+    pub exported: Vec<ExportedFile>,
+    pub failed: Vec<ExportFailure>,
+    pub bytes: u64,
+}
+
+/// Payload of `export_progress` events, one per file. `status` is
+/// "exported" or "failed".
+#[derive(Serialize, Clone)]
+pub struct ExportProgress {
+    pub current: u32,
+    pub total: u32,
+    pub file: String,
+    pub status: &'static str,
+}
+
+/// COMMAND: Copy photos out of Terra into `dest_dir`, laid out by
+/// `structure` (default flat). Metadata can be embedded (corrected dates,
+/// captions, ratings, GPS) or stripped for sharing, and images shrunk for
+/// the web; see ExportOptions. Names already taken in a folder get a numeric
+/// suffix, as on upload. Emits `export_progress` after each file.
+#[tauri::command]
+async fn export_photos(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    dest_dir: String,
+    structure: Option<ExportStructure>,
+    options: Option<ExportOptions>,
+) -> Result<ExportResult, String> {
+    let structure = structure.unwrap_or_default();
+    let photos = {
+        let conn = db_conn(&db_state)?;
+        let albums = if structure == ExportStructure::Album {
+            db::get_first_album_names(&conn, &paths).map_err(|e| format!("Failed to read albums: {}", e))?
+        } else {
+            HashMap::new()
+        };
+        let mut photos = Vec::with_capacity(paths.len());
+        for path in paths {
+            let details = db::get_photo_details(&conn, &path).map_err(|e| format!("Failed to read photos: {}", e))?;
+            let folder = match (structure, &details) {
+                (ExportStructure::Date, Some(d)) => library::month_folder(Path::new(""), d.photo.date_taken),
+                (ExportStructure::Album, _) => albums.get(&path).map(|name| PathBuf::from(library::folder_name(name))),
+                _ => None,
+            };
+            photos.push((path, details, folder));
+        }
+        photos
+    };
+    export_all(&window, photos, Path::new(&dest_dir), options.unwrap_or_default())
+}
+
+/// COMMAND: Export an album into a folder named after it inside `dest_dir`.
+/// Options and events as for export_photos.
+#[tauri::command]
+async fn export_album(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    album_id: i64,
+    dest_dir: String,
+    options: Option<ExportOptions>,
+) -> Result<ExportResult, String> {
+    let (name, photos) = {
+        let conn = db_conn(&db_state)?;
+        let name = db::get_album_name(&conn, album_id)
+            .map_err(|e| format!("Failed to read album: {}", e))?
+            .ok_or_else(|| format!("Album {} does not exist", album_id))?;
+        let photos = db::get_all_album_photos(&conn, album_id)
+            .map_err(|e| format!("Failed to read album photos: {}", e))?
+            .into_iter()
+            .map(|photo| db::get_photo_details(&conn, &photo.path).map(|d| (photo.path, d, None)))
+            .collect::<rusqlite::Result<Vec<_>>>()
+            .map_err(|e| format!("Failed to read photos: {}", e))?;
+        (name, photos)
+    };
+    let dest = Path::new(&dest_dir).join(library::folder_name(&name));
+    fs::create_dir_all(&dest).map_err(|e| format!("Failed to create {}: {}", dest.display(), e))?;
+    export_all(&window, photos, &dest, options.unwrap_or_default())
+}
+
+/// Cancellation flag for the in-flight ZIP export.
+/// `cancel_export` sets it; `export_album_zip` clears it when it starts.
+#[derive(Default)]
+pub struct ExportState {
+    cancelled: AtomicBool,
+}
+
+/// COMMAND: Abort the running ZIP export
+#[tauri::command]
+fn cancel_export(export_state: tauri::State<'_, ExportState>) {
+    export_state.cancelled.store(true, Ordering::SeqCst);
+}
+
+/// Result of export_album_zip.
+#[derive(Serialize, Debug, Default)]
+pub struct ZipExportResult {
+    /// The archive written; empty when cancelled
+    pub path: String,
+    pub added: u32,
+    /// Photos left out because their file is gone
+    pub skipped: Vec<ExportFailure>,
+    /// Bytes of photos and videos stored in the archive
+    pub bytes: u64,
+    pub cancelled: bool,
+}
+
+/// COMMAND: Write an album to one ZIP archive for sharing. Members sit in a
+/// folder named after the album, prefixed with their capture date so they
+/// sort in order. Files are streamed in, never held in memory. `dest_path`
+/// may be a folder, in which case the archive is `<album name>.zip` inside
+/// it. Videos are left out when `photos_only`. Emits `export_progress`;
+/// `cancel_export` stops it and removes the partial archive.
+#[tauri::command]
+async fn export_album_zip(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    export_state: tauri::State<'_, ExportState>,
+    album_id: i64,
+    dest_path: String,
+    photos_only: Option<bool>,
+) -> Result<ZipExportResult, String> {
+    export_state.cancelled.store(false, Ordering::SeqCst);
+    let (name, photos) = {
+        let conn = db_conn(&db_state)?;
+        let name = db::get_album_name(&conn, album_id)
+            .map_err(|e| format!("Failed to read album: {}", e))?
+            .ok_or_else(|| format!("Album {} does not exist", album_id))?;
+        let photos = db::get_all_album_photos(&conn, album_id).map_err(|e| format!("Failed to read album photos: {}", e))?;
+        (name, photos)
+    };
+    let folder = library::folder_name(&name);
+    let mut dest = PathBuf::from(&dest_path);
+    if dest.is_dir() {
+        dest = library::unique_destination(dest.join(format!("{}.zip", folder)));
+    }
+
+    let mut photos: Vec<PhotoMetadata> = photos
+        .into_iter()
+        .filter(|p| !(photos_only.unwrap_or(false) && media::is_video(Path::new(&p.path))))
+        .collect();
+    // Oldest first, as the date prefixes will list them
+    photos.sort_by(|a, b| a.date_taken.cmp(&b.date_taken).then_with(|| a.name.cmp(&b.name)));
+
+    let result = zip_export::write_album_zip(&photos, &folder, &dest, &export_state.cancelled, |current, file, status| {
+        let _ = window.emit("export_progress", ExportProgress {
+            current,
+            total: photos.len() as u32,
+            file: file.to_string(),
+            status,
+        });
+    })
+    .map_err(|e| format!("Failed to write {}: {}", dest.display(), e))?;
+    info!(
+        "Album {} zipped to {}: {} added, {} skipped{}",
+        album_id,
+        dest.display(),
+        result.added,
+        result.skipped.len(),
+        if result.cancelled { ", cancelled" } else { "" }
+    );
+    Ok(result)
+}
+
+/// Result of export_metadata.
+#[derive(Serialize, Debug)]
+pub struct MetadataExportResult {
+    pub path: String,
+    pub photos: usize,
+}
+
+/// COMMAND: Write everything Terra knows about every photo outside the
+/// trash (dates, favorites, ratings, labels, albums, tags, location,
+/// camera) to `dest_path` as `format` "json" or "csv"; see
+/// `metadata_export` for the layouts. Rows are read a page at a time and
+/// streamed to disk, so large libraries aren't buffered and other commands
+/// get the database between pages; edits made meanwhile may or may not
+/// make it into the file. `dest_path` may be a folder, in which case
+/// the file is `terra-metadata-<date>.<format>` inside it. Like ZIP
+/// exports, the file only appears once complete.
+#[tauri::command]
+async fn export_metadata(
+    db_state: tauri::State<'_, DbState>,
+    dest_path: String,
+    format: String,
+) -> Result<MetadataExportResult, String> {
+    let format = metadata_export::ExportFormat::parse(&format)?;
+    let extension = match format {
+        metadata_export::ExportFormat::Json => "json",
+        metadata_export::ExportFormat::Csv => "csv",
+    };
+    let now = chrono::Utc::now();
+    let mut dest = PathBuf::from(&dest_path);
+    if dest.is_dir() {
+        dest = library::unique_destination(dest.join(format!("terra-metadata-{}.{}", now.format("%Y-%m-%d"), extension)));
+    }
+
+    let partial = dest.with_extension(format!("{}.partial", extension));
+    let written = (|| -> Result<usize, Box<dyn std::error::Error + Send + Sync>> {
+        let file = std::io::BufWriter::new(fs::File::create(&partial)?);
+        let mut writer = metadata_export::MetadataWriter::new(file, format, &now.to_rfc3339())?;
+        let mut after_id = 0;
+        loop {
+            // The lock is only held while a page is read, not while it's written
+            let records = db::get_photo_records(&*db_conn(&db_state)?, after_id, config::EXPORT_PAGE_ROWS)?;
+            let Some(last) = records.last() else {
+                break;
+            };
+            after_id = last.id;
+            for record in &records {
+                writer.write(record)?;
+            }
+        }
+        let photos = writer.finish()?;
+        fs::rename(&partial, &dest)?;
+        Ok(photos)
+    })();
+    match written {
+        Ok(photos) => {
+            info!("Exported metadata of {} photos to {}", photos, dest.display());
+            Ok(MetadataExportResult { path: dest.to_string_lossy().to_string(), photos })
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(format!("Failed to write {}: {}", dest.display(), e))
+        }
+    }
+}
+
+/// COMMAND: Restore favorites, ratings, descriptions, hand-set dates, tags
+/// and album membership from a JSON file `export_metadata` wrote, e.g. to
+/// move a library's organization to a fresh database or another machine.
+/// Records match photos by content hash, then by path. `mode` "merge" only
+/// fills in what's empty; "overwrite" replaces it. All of it is applied in
+/// one transaction. Returns what changed and the paths of records that
+/// matched no photo.
+#[tauri::command]
+async fn import_metadata(
+    db_state: tauri::State<'_, DbState>,
+    src_path: String,
+    mode: String,
+) -> Result<db::MetadataImportSummary, String> {
+    let mode = metadata_export::ImportMode::parse(&mode)?;
+    let file = fs::File::open(&src_path).map_err(|e| format!("Couldn't open {}: {}", src_path, e))?;
+    let records = metadata_export::read_export(file)?;
+    let conn = db_conn(&db_state)?;
+    let summary = db::import_photo_records(&conn, &records, mode).map_err(|e| format!("Failed to import metadata: {}", e))?;
+    info!(
+        "Imported metadata from {}: {} matched by hash, {} by path, {} unmatched",
+        src_path,
+        summary.matched_by_hash,
+        summary.matched_by_path,
+        summary.unmatched.len()
+    );
+    Ok(summary)
+}
+
+/// COMMAND: Bring ratings, tags and creation dates over from digiKam's
+/// database (`digikam4.db`), which is read from a copy and never written.
+/// Images match library photos at the same path, or by content for copies
+/// Terra keeps elsewhere. Hierarchical tags become "Parent/Child" tags.
+/// Like `import_metadata` in merge mode, only empty ratings are filled in;
+/// creation dates only date photos Terra found no date for. Returns what changed and the images that matched
+/// no photo, including those on drives that aren't connected.
+#[tauri::command]
+async fn import_digikam(
+    db_state: tauri::State<'_, DbState>,
+    db_path: String,
+) -> Result<db::MetadataImportSummary, String> {
+    let images = digikam::read_library(Path::new(&db_path))?;
+    let elsewhere: Vec<usize> = {
+        let conn = db_conn(&db_state)?;
+        images
+            .iter()
+            .enumerate()
+            .filter(|(_, image)| {
+                image.path.as_ref().is_some_and(|p| !db::photo_exists(&conn, &p.to_string_lossy()).unwrap_or(false))
+            })
+            .map(|(i, _)| i)
+            .collect()
+    };
+    // Hashed without holding the database
+    let mut hashes: HashMap<usize, String> = elsewhere
+        .into_par_iter()
+        .filter_map(|i| images[i].path.as_deref().and_then(media::calculate_hash).map(|hash| (i, hash)))
+        .collect();
+
+    let records: Vec<metadata_export::PhotoRecord> = images
+        .iter()
+        .enumerate()
+        .map(|(i, image)| {
+            let date = image.created.and_then(|created| media::exif_timestamp(created, None));
+            metadata_export::PhotoRecord {
+                path: image.path.as_ref().map_or_else(|| image.display_path.clone(), |p| p.to_string_lossy().to_string()),
+                content_hash: hashes.remove(&i),
+                rating: image.rating.unwrap_or(0),
+                date_taken: date.map_or(0, |(timestamp, _)| timestamp),
+                tz_offset_minutes: date.map(|(_, offset)| offset),
+                // digiKam fills creationDate from EXIF, or the file's mtime
+                // without it, so it only dates photos Terra found no date for
+                date_source: date.map(|_| media::DATE_SOURCE_SIDECAR.to_string()),
+                tags: image.tags.clone(),
+                ..Default::default()
+            }
+        })
+        .collect();
+
+    let conn = db_conn(&db_state)?;
+    let summary = db::import_photo_records(&conn, &records, metadata_export::ImportMode::Merge)
+        .map_err(|e| format!("Failed to import from digiKam: {}", e))?;
+    info!(
+        "Imported {} digiKam images from {}: {} matched by path, {} by content, {} unmatched",
+        images.len(),
+        db_path,
+        summary.matched_by_path,
+        summary.matched_by_hash,
+        summary.unmatched.len()
+    );
+    Ok(summary)
+}
+
+/// Export each (path, row, subfolder) into `dest_dir`, reporting progress.
+/// One file failing doesn't stop the rest.
+fn export_all(
+    window: &tauri::Window,
+    photos: Vec<(String, Option<db::PhotoDetails>, Option<PathBuf>)>,
+    dest_dir: &Path,
+    options: ExportOptions,
+) -> Result<ExportResult, String> {
+    if options.embed_metadata && options.strip_metadata {
+        return Err("Choose either embedding or stripping metadata, not both".to_string());
+    }
+    if !dest_dir.is_dir() {
+        return Err(format!("{} is not an existing folder", dest_dir.display()));
+    }
+
+    let total = photos.len() as u32;
+    let mut result = ExportResult::default();
+    for (i, (path, details, folder)) in photos.into_iter().enumerate() {
+        let exported = match details {
+            Some(details) => {
+                let dir = folder.map_or_else(|| dest_dir.to_path_buf(), |f| dest_dir.join(f));
+                fs::create_dir_all(&dir)
+                    .map_err(|e| format!("Failed to create {}: {}", dir.display(), e))
+                    .and_then(|_| export_photo(&details, &dir, options))
+            }
+            None => Err("Not in the library".to_string()),
+        };
+        let status = match exported {
+            Ok(file) => {
+                result.bytes += fs::metadata(&file.dest).map(|m| m.len()).unwrap_or(0);
+                result.exported.push(file);
+                "exported"
+            }
+            Err(error) => {
+                warn!("Export of {} failed: {}", path, error);
+                result.failed.push(ExportFailure { path: path.clone(), error });
+                "failed"
+            }
+        };
+        let _ = window.emit("export_progress", ExportProgress {
+            current: i as u32 + 1,
+            total,
+            file: path,
+            status,
+        });
+    }
+    info!("Exported {} photos to {} ({} failed)", result.exported.len(), dest_dir.display(), result.failed.len());
+    Ok(result)
+}
+
+/// Export one photo into `dir`. Resized and edited images are re-encoded as
+/// JPEG; JPEGs get their metadata rewritten as asked; anything else (or a
+/// JPEG too damaged to parse) is copied as it is.
+fn export_photo(details: &db::PhotoDetails, dir: &Path, options: ExportOptions) -> Result<ExportedFile, String> {
+    let photo = &details.photo;
+    let source = Path::new(&photo.path);
+    let resize = options.max_edge.filter(|_| !media::is_video(source));
+    let edits = details.edits.filter(|_| options.variant == ExportVariant::Edited && !media::is_video(source));
+    let rendered = resize.is_some() || edits.is_some();
+    let file_name = source.file_name().ok_or_else(|| format!("No file name in {}", photo.path))?;
+    let target = if rendered { dir.join(file_name).with_extension("jpg") } else { dir.join(file_name) };
+    let (dest, placeholder) = library::create_unique(&target)
+        .map_err(|e| format!("Failed to create file in {}: {}", dir.display(), e))?;
+    drop(placeholder);
+    let fail = |error: String| {
+        let _ = fs::remove_file(&dest);
+        error
+    };
+
+    let rewrite = options.embed_metadata || options.strip_metadata;
+    let mut notes = Vec::new();
+    if options.max_edge.is_some() && resize.is_none() {
+        notes.push("Videos are exported at full size".to_string());
+    }
+
+    // Either the edited or resized JPEG already at `dest`, or the original
+    let quality = options.jpeg_quality.unwrap_or(EXPORT_JPEG_QUALITY);
+    let (input, is_jpeg) = match (edits, resize) {
+        (Some(edits), _) => {
+            let max_edge = resize.unwrap_or(u32::MAX);
+            thumbnails::render_edited(source, &dest, &edits, max_edge, quality).map_err(&fail)?;
+            (dest.clone(), true)
+        }
+        (None, Some(max_edge)) => {
+            thumbnails::render_resized(source, &dest, max_edge, quality).map_err(&fail)?;
+            (dest.clone(), true)
+        }
+        (None, None) => (source.to_path_buf(), media::file_format(source).as_deref() == Some("jpeg")),
+    };
+
+    let rewritten = if rewrite && is_jpeg {
+        let bytes = fs::read(&input).map_err(|e| fail(format!("Failed to read {}: {}", input.display(), e)))?;
+        if options.strip_metadata {
+            exif_write::strip_metadata(&bytes)
+        } else {
+            exif_write::embed_metadata(&bytes, &embedded_metadata(details))
+        }
+    } else {
+        None
+    };
+    if rewrite && rewritten.is_none() {
+        notes.push(if is_jpeg {
+            "Couldn't read the JPEG's metadata; copied unchanged".to_string()
+        } else {
+            format!(
+                "Metadata can't be written to {} files; copied unchanged",
+                media::file_format(source).unwrap_or_default().to_uppercase()
+            )
+        });
+    }
+
+    let written = match rewritten {
+        Some(bytes) => fs::write(&dest, bytes),
+        None if rendered => Ok(()),
+        None => library::copy_verified(source, &dest, None).map(|_| ()),
+    };
+    written.map_err(|e| fail(format!("Failed to write {}: {}", dest.display(), e)))?;
+
+    Ok(ExportedFile {
+        source: photo.path.clone(),
+        dest: dest.to_string_lossy().to_string(),
+        note: (!notes.is_empty()).then(|| notes.join("; ")),
+    })
+}
+
+/// What export embeds for a photo: the date as Terra has it (unless
+/// unknown), the stored caption, rating and color label, and the location.
+fn embedded_metadata(details: &db::PhotoDetails) -> exif_write::EmbeddedMetadata {
+    let photo = &details.photo;
+    let date = match photo.date_source.as_deref() {
+        Some(media::DATE_SOURCE_UNKNOWN) => None,
+        _ => media::exif_wall_clock(photo.date_taken, photo.tz_offset_minutes),
+    };
+    exif_write::EmbeddedMetadata {
+        date,
+        description: details.description.clone(),
+        rating: photo.rating,
+        color_label: photo.color_label,
+        gps: photo.latitude.zip(photo.longitude),
+    }
+}
+
+/// COMMAND: The motion video of a Live Photo, for the lightbox to play.
+/// None for photos that aren't Live Photos.
+#[tauri::command]
+fn get_live_photo_video(db_state: tauri::State<'_, DbState>, path: String) -> Result<Option<String>, String> {
+    with_db(&db_state, "Failed to get Live Photo video", |c| db::get_live_photo_video(c, &path))
+}
+
+/// COMMAND: Get the full stored row for one photo, with its tags
+#[tauri::command]
+fn get_photo_details(db_state: tauri::State<'_, DbState>, path: String) -> Result<db::PhotoDetails, String> {
+    with_db(&db_state, "Failed to get photo details", |c| {
+        let mut details = db::get_photo_details(c, &path)?;
+        if let Some(details) = details.as_mut() {
+            db::attach_tags(c, std::slice::from_mut(&mut details.photo))?;
+        }
+        Ok(details)
+    })?
+    .ok_or_else(|| format!("Photo not found: {}", path))
+}
+
+#[tauri::command]
+fn create_album(db_state: tauri::State<'_, DbState>, name: String) -> Result<i64, String> {
+    with_db_write(&db_state, "Failed to create album", |c| db::create_album(c, &name))
+}
+
+/// COMMAND: Delete an album. Albums nested in it move to the top level,
+/// or are deleted with it when `delete_children` is set.
+#[tauri::command]
+fn delete_album(db_state: tauri::State<'_, DbState>, id: i64, delete_children: Option<bool>) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to delete album", |c| db::delete_album(c, id, delete_children.unwrap_or(false)))
+}
+
+/// COMMAND: Nest an album inside another, or move it to the top level when
+/// `parent_id` is None. An album can't end up inside itself.
+#[tauri::command]
+fn set_album_parent(db_state: tauri::State<'_, DbState>, id: i64, parent_id: Option<i64>) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    if let Some(parent_id) = parent_id {
+        let exists = db::get_album_name(&conn, parent_id).map_err(|e| format!("Failed to move album: {}", e))?;
+        if exists.is_none() {
+            return Err(format!("Album {} does not exist", parent_id));
+        }
+        if db::album_move_creates_cycle(&conn, id, parent_id).map_err(|e| format!("Failed to move album: {}", e))? {
+            return Err("An album can't be moved inside itself".to_string());
+        }
+    }
+    if !db::set_album_parent(&conn, id, parent_id).map_err(|e| format!("Failed to move album: {}", e))? {
+        return Err(format!("Album {} does not exist", id));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn rename_album(db_state: tauri::State<'_, DbState>, id: i64, new_name: String) -> Result<(), String> {
+    let name = new_name.trim();
+    if name.is_empty() {
+        return Err("Album name cannot be empty".to_string());
+    }
+    let renamed = with_db_write(&db_state, "Failed to rename album", |c| db::rename_album(c, id, name))?;
+    if !renamed {
+        return Err(format!("Album {} does not exist", id));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn set_album_order(db_state: tauri::State<'_, DbState>, ids: Vec<i64>) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to reorder albums", |c| db::set_album_order(c, &ids))
+}
+
+#[tauri::command]
+fn get_albums(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::Album>, String> {
+    with_db(&db_state, "Failed to get albums", |c| db::get_albums(c))
+}
+
+/// COMMAND: add_to_album_by_id for the photos at `photo_paths`
+#[tauri::command]
+fn add_to_album(
+    db_state: tauri::State<'_, DbState>,
+    album_id: i64,
+    photo_paths: Vec<String>,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to add to album", |c| {
+        db::add_photos_to_album_by_id(c, album_id, &db::get_photo_ids(c, &photo_paths)?).map(drop)
+    })
+}
+
+/// COMMAND: Add photos to an album by id. Ids with no photo are skipped.
+#[tauri::command]
+fn add_to_album_by_id(db_state: tauri::State<'_, DbState>, album_id: i64, photo_ids: Vec<i64>) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to add to album", |c| db::add_photos_to_album_by_id(c, album_id, &photo_ids).map(drop))
+}
+
+#[tauri::command]
+fn remove_from_album(
+    db_state: tauri::State<'_, DbState>,
+    album_id: i64,
+    photo_paths: Vec<String>,
+) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    let members = db::get_album_members(&conn, album_id, &photo_paths)
+        .map_err(|e| format!("Failed to remove from album: {}", e))?;
+    for path in photo_paths {
+        db::remove_photo_from_album(&conn, album_id, &path).map_err(|e| format!("Failed to remove from album: {}", e))?;
+    }
+    if !members.is_empty() {
+        log_operation(&conn, &Operation::RemoveFromAlbum { album_id, members });
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn get_album_photos(
+    db_state: tauri::State<'_, DbState>,
+    album_id: i64,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get album photos", |c| {
+        let mut photos = db::get_album_photos(c, album_id)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+/// COMMAND: Suggested albums for trips found in the timeline, newest first,
+/// leaving out dismissed ones. See trips.rs for how trips are found.
+#[tauri::command]
+fn analyze_trips(db_state: tauri::State<'_, DbState>) -> Result<Vec<trips::TripSuggestion>, String> {
+    let (photos, dismissed) = with_db(&db_state, "Failed to analyze trips", |c| {
+        Ok((db::get_trip_photos(c)?, db::get_dismissed_suggestions(c)?))
+    })?;
+    let mut suggestions: Vec<trips::TripSuggestion> =
+        trips::suggest_trips(&photos).into_iter().filter(|s| !dismissed.contains(&s.id)).collect();
+    suggestions.reverse();
+    Ok(suggestions)
+}
+
+/// COMMAND: Make a suggested trip into an album of the photos taken during
+/// it, named after the suggestion, and stop suggesting it. Returns the
+/// album's id.
+#[tauri::command]
+fn create_album_from_suggestion(
+    db_state: tauri::State<'_, DbState>,
+    suggestion: trips::TripSuggestion,
+) -> Result<i64, String> {
+    with_db_write(&db_state, "Failed to create album", |c| {
+        let tx = c.unchecked_transaction()?;
+        let album_id = db::create_album(&tx, &suggestion.title)?;
+        db::add_photos_taken_between_to_album(&tx, album_id, suggestion.start, suggestion.end)?;
+        db::dismiss_suggestion(&tx, &suggestion.id)?;
+        tx.commit()?;
+        Ok(album_id)
+    })
+}
+
+/// COMMAND: Stop offering a suggestion
+#[tauri::command]
+fn dismiss_suggestion(db_state: tauri::State<'_, DbState>, id: String) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to dismiss suggestion", |c| db::dismiss_suggestion(c, &id))
+}
+
+/// COMMAND: Size, date range, video and favorite counts of an album's photos,
+/// for its caption. Computed per call; get_albums only carries counts and sizes.
+#[tauri::command]
+fn get_album_stats(db_state: tauri::State<'_, DbState>, album_id: i64) -> Result<db::AlbumStats, String> {
+    with_db(&db_state, "Failed to get album stats", |c| db::get_album_stats(c, album_id))?
+        .ok_or_else(|| format!("No album with id {}", album_id))
+}
+
+/// COMMAND: get_album_stats for a smart album's current matches.
+#[tauri::command]
+fn get_smart_album_stats(db_state: tauri::State<'_, DbState>, id: i64) -> Result<db::AlbumStats, String> {
+    with_db(&db_state, "Failed to get album stats", |c| db::get_smart_album_stats(c, id))?
+        .ok_or_else(|| format!("No smart album with id {}", id))
+}
+
+/// Reject filters that can never match anything sensible.
+fn validate_smart_filter(filter: &db::SmartAlbumFilter) -> Result<(), String> {
+    if let (Some(from), Some(to)) = (filter.date_from, filter.date_to) {
+        if from > to {
+            return Err("The date range ends before it starts".to_string());
+        }
+    }
+    if filter.min_rating.is_some_and(|rating| rating > 5) {
+        return Err("Ratings go from 0 to 5".to_string());
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn create_smart_album(db_state: tauri::State<'_, DbState>, name: String, filter: db::SmartAlbumFilter) -> Result<i64, String> {
+    validate_smart_filter(&filter)?;
+    with_db_write(&db_state, "Failed to create smart album", |c| db::create_smart_album(c, &name, &filter))
+}
+
+#[tauri::command]
+fn update_smart_album(
+    db_state: tauri::State<'_, DbState>,
+    id: i64,
+    name: String,
+    filter: db::SmartAlbumFilter,
+) -> Result<(), String> {
+    validate_smart_filter(&filter)?;
+    let updated = with_db_write(&db_state, "Failed to update smart album", |c| db::update_smart_album(c, id, &name, &filter))?;
+    if !updated {
+        return Err(format!("Smart album {} does not exist", id));
+    }
+    Ok(())
+}
+
+#[tauri::command]
+fn delete_smart_album(db_state: tauri::State<'_, DbState>, id: i64) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to delete smart album", |c| db::delete_smart_album(c, id))
+}
+
+#[tauri::command]
+fn get_smart_albums(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::SmartAlbum>, String> {
+    with_db(&db_state, "Failed to get smart albums", |c| db::get_smart_albums(c))
+}
+
+#[tauri::command]
+fn get_smart_album_photos(db_state: tauri::State<'_, DbState>, id: i64) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get smart album photos", |c| {
+        let mut photos = db::get_smart_album_photos(c, id)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
+
+#[tauri::command]
+fn set_album_cover(
+    db_state: tauri::State<'_, DbState>,
+    album_id: i64,
+    photo_path: String,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to set album cover", |c| db::set_album_cover(c, album_id, &photo_path))
+}
+
+/// Check if a path is within the Terra managed library or archive directories,
+/// including library roots used before the location was changed.
+/// This is a security check to prevent deletion of files outside the managed library.
+fn is_path_in_managed_library(conn: &rusqlite::Connection, path: &Path) -> bool {
+    let roots = db::get_managed_roots(conn);
+    let roots: Vec<&Path> = roots.iter().map(PathBuf::as_path).collect();
+    library::is_within_roots(path, &roots)
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct DeleteSummary {
+    /// Rows removed from the database
+    pub removed: u32,
+    /// Files deleted from disk, or moved to the system trash
+    pub files_deleted: u32,
+    /// Of `files_deleted`, those in the system Trash / Recycle Bin
+    pub files_system_trashed: u32,
+    /// Photos moved into Terra's trash instead (`terra_trash` mode)
+    pub moved_to_trash: u32,
+    /// Photos scanned in place whose original file was left untouched
+    pub originals_kept: Vec<String>,
+    /// Photos on an offline drive; only their rows were removed
+    pub offline_files_kept: Vec<String>,
+    /// Files that couldn't be disposed of; they and their rows were kept
+    pub failed: Vec<DeleteFailure>,
+}
+
+#[derive(Serialize, Clone, Debug)]
+pub struct DeleteFailure {
+    pub path: String,
+    pub error: String,
+}
+
+/// The `deletion_mode` setting; files are deleted outright if it's unset.
+fn deletion_mode(conn: &rusqlite::Connection) -> library::DeletionMode {
+    db::get_setting_or(conn, library::DELETION_MODE_SETTING, library::DeletionMode::default())
+}
+
+/// COMMAND: Remove photos from Terra, as the `deletion_mode` setting says:
+/// into Terra's trash (`terra_trash`), into the system trash
+/// (`system_trash`), or deleted outright (`permanent`, the default).
+/// Library files are disposed of. Originals scanned in place from elsewhere
+/// only lose their row unless `also_delete_file` is set, and photos on an
+/// offline drive only ever lose their row. A file the system trash refuses
+/// is kept, with its row, and listed in `failed`. Paths with no photo are skipped.
+#[tauri::command]
+fn delete_photos(
+    db_state: tauri::State<'_, DbState>,
+    paths: Vec<String>,
+    also_delete_file: Option<bool>,
+) -> Result<DeleteSummary, String> {
+    let conn = db_conn(&db_state)?;
+    let ids = db::get_photo_ids(&conn, &paths).map_err(|e| format!("Failed to look up photos: {}", e))?;
+    remove_photos(&conn, &ids, also_delete_file.unwrap_or(false))
+}
+
+/// COMMAND: delete_photos keyed by photo id. Ids with no photo are skipped.
+#[tauri::command]
+fn delete_photos_by_id(
+    db_state: tauri::State<'_, DbState>,
+    ids: Vec<i64>,
+    also_delete_file: Option<bool>,
+) -> Result<DeleteSummary, String> {
+    let conn = db_conn(&db_state)?;
+    remove_photos(&conn, &ids, also_delete_file.unwrap_or(false))
+}
+
+/// Remove photos as `delete_photos` describes
+fn remove_photos(conn: &rusqlite::Connection, ids: &[i64], also_delete_file: bool) -> Result<DeleteSummary, String> {
+    let paths: Vec<String> = db::get_photo_paths_by_id(conn, ids)
+        .map_err(|e| format!("Failed to look up photos: {}", e))?
+        .into_iter()
+        .flatten()
+        .map(|(path, _)| path)
+        .collect();
+    let mode = deletion_mode(conn);
+    let mut summary = DeleteSummary::default();
+    if mode == library::DeletionMode::TerraTrash {
+        let trashed = trash_photos(conn, &paths)?;
+        log_trashing(conn, &trashed);
+        summary.moved_to_trash = trashed.len() as u32;
+        return Ok(summary);
+    }
+
+    let managed_roots = db::get_managed_roots(conn);
+    let roots: Vec<&Path> = managed_roots.iter().map(PathBuf::as_path).collect();
+    for path_str in paths {
+        let path = Path::new(&path_str);
+        if db::is_photo_offline(conn, &path_str).unwrap_or(false) {
+            warn!("{} is on an offline drive; removing its row but not the file", path_str);
+            summary.offline_files_kept.push(path_str.clone());
+        } else if fs::symlink_metadata(path).is_ok() {
+            match library::file_action_on_delete(path, &roots, also_delete_file) {
+                library::FileAction::Delete => match library::dispose_file(path, mode, library::send_to_system_trash) {
+                    library::Disposal::Deleted => summary.files_deleted += 1,
+                    library::Disposal::SystemTrashed => {
+                        summary.files_deleted += 1;
+                        summary.files_system_trashed += 1;
+                    }
+                    library::Disposal::Kept(error) => {
+                        warn!("Kept {}: {}", path_str, error);
+                        summary.failed.push(DeleteFailure { path: path_str, error });
+                        continue;
+                    }
+                },
+                library::FileAction::Keep => {
+                    debug!("Keeping original outside managed library: {}", path_str);
+                    summary.originals_kept.push(path_str.clone());
+                }
+            }
+        }
+
+        let orphaned_hash = db::permanently_delete_photo(conn, &path_str)
+            .map_err(|e| format!("Failed to delete from DB: {}", e))?;
+        if let Some(hash) = orphaned_hash {
+            thumbnails::remove_thumbnails(&hash);
+        }
+        summary.removed += 1;
+    }
+    Ok(summary)
+}
+
+#[tauri::command]
+fn get_duplicates(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get duplicates", |c| db::get_duplicates(c))
+}
+
+/// COMMAND: Full-text search over names, descriptions, locations and tags.
+/// All terms must match; end a term with `*` to match it as a prefix.
+#[tauri::command]
+fn search_photos(
+    db_state: tauri::State<'_, DbState>,
+    query: String,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to search photos", |c| db::search_photos(c, &query))
+}
+
+/// COMMAND: Rebuild the full-text search index from the photos table.
+/// Returns the number of photos indexed.
+#[tauri::command]
+fn rebuild_search_index(db_state: tauri::State<'_, DbState>) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to rebuild search index", |c| db::rebuild_search_index(c))
+}
+
+#[tauri::command]
+fn get_locations(db_state: tauri::State<'_, DbState>) -> Result<Vec<(String, i64)>, String> {
+    with_db(&db_state, "Failed to get locations", |c| db::get_locations(c))
+}
+
+/// COMMAND: Queue a job naming the city and country of every photo with
+/// coordinates and no place yet, offline. Returns the job id.
+#[tauri::command]
+fn geocode_photos(app: tauri::AppHandle, job_queue: tauri::State<'_, jobs::JobQueue>) -> Result<i64, String> {
+    job_queue.enqueue(&app, jobs::JobKind::Geocode, serde_json::json!({}))
+}
+
+/// COMMAND: Cities photos were taken in, with counts and the most recent
+/// photo as cover, most photographed first. Run `geocode_photos` first.
+#[tauri::command]
+fn get_places(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::PlaceSummary>, String> {
+    with_db(&db_state, "Failed to get places", |c| db::get_places(c))
+}
+
+/// COMMAND: Photos taken in `country`, narrowed to `region` and `city` if
+/// given, newest first
+#[tauri::command]
+fn get_photos_by_place(
+    db_state: tauri::State<'_, DbState>,
+    country: String,
+    region: Option<String>,
+    city: Option<String>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get photos", |c| {
+        db::get_photos_by_place(c, &country, region.as_deref(), city.as_deref())
+    })
+}
+
+/// COMMAND: Geotag photos from a GPX track recorded while they were taken.
+/// `time_offset_seconds` is added to each photo's time first, to correct a
+/// camera clock or time zone that was off. Photos further than
+/// `tolerance_seconds` (default 5 minutes) from a track point are skipped.
+/// `preview` only reports the proposed positions; otherwise they're saved,
+/// written into JPEGs too with `write_exif`, and the places geocoded again.
+#[tauri::command]
+#[allow(clippy::too_many_arguments)]
+fn geotag_from_gpx(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    job_queue: tauri::State<'_, jobs::JobQueue>,
+    gpx_path: String,
+    photo_paths: Vec<String>,
+    time_offset_seconds: i64,
+    tolerance_seconds: Option<i64>,
+    preview: Option<bool>,
+    write_exif: Option<bool>,
+) -> Result<geotag::GeotagReport, String> {
+    let tolerance = tolerance_seconds.unwrap_or(geotag::DEFAULT_TOLERANCE_SECONDS);
+    if tolerance < 0 {
+        return Err("Tolerance can't be negative".to_string());
+    }
+    let track = geotag::Track::open(Path::new(&gpx_path))?;
+    let (track_start, track_end) = track.span();
+
+    let conn = db_conn(&db_state)?;
+    let dates = db::get_photo_dates(&conn, &photo_paths).map_err(|e| format!("Failed to get photo dates: {}", e))?;
+    let (mut matched, mut skipped) = geotag::correlate(&track, &dates, time_offset_seconds, tolerance);
+    let found: std::collections::HashSet<&str> = dates.iter().map(|(path, _)| path.as_str()).collect();
+    skipped.extend(photo_paths.iter().filter(|path| !found.contains(path.as_str())).map(|path| geotag::GeotagSkip {
+        path: path.clone(),
+        reason: "Not in the library".to_string(),
+        gap_seconds: None,
+    }));
+
+    let preview = preview.unwrap_or(false);
+    if !preview && !matched.is_empty() {
+        let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+        let locations: Vec<(String, f64, f64, Option<String>)> = matched
+            .iter()
+            .map(|m| (m.path.clone(), m.latitude, m.longitude, media::get_location_name(m.latitude, m.longitude, &geocoder)))
+            .collect();
+        db::set_photo_locations_batch(&conn, &locations, geotag::LOCATION_SOURCE_GPX)
+            .map_err(|e| format!("Failed to save locations: {}", e))?;
+        info!("Geotagged {} photos from {}", matched.len(), gpx_path);
+
+        let mut rethumbnail = false;
+        if write_exif.unwrap_or(false) {
+            for photo in matched.iter_mut() {
+                let path = Path::new(&photo.path);
+                if media::file_format(path).as_deref() != Some("jpeg") {
+                    continue;
+                }
+                let written = match db::get_photo_details(&conn, &photo.path) {
+                    Ok(Some(details)) => exif_write::embed_into_file(path, &embedded_metadata(&details)),
+                    Ok(None) => continue,
+                    Err(e) => Err(e.to_string()),
+                };
+                match written {
+                    Ok(()) => {
+                        photo.exif_written = true;
+                        rethumbnail |= record_rewritten_file(&conn, &photo.path);
+                    }
+                    Err(e) => warn!("Couldn't write the location into {}: {}", photo.path, e),
+                }
+            }
+        }
+        drop(conn);
+        if let Err(e) = job_queue.enqueue(&app, jobs::JobKind::Geocode, serde_json::json!({})) {
+            warn!("Couldn't queue geocoding: {}", e);
+        }
+        if rethumbnail {
+            queue_thumbnails(&app);
+        }
+    }
+
+    Ok(geotag::GeotagReport { matched, skipped, applied: !preview, track_start, track_end })
+}
+
+/// COMMAND: Get distinct cameras with photo counts
+#[tauri::command]
+fn get_cameras(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::Camera>, String> {
+    with_db(&db_state, "Failed to get cameras", |c| db::get_cameras(c))
+}
+
+/// COMMAND: Get photos taken with a camera; omit model to match every model of a make
+#[tauri::command]
+fn get_photos_by_camera(
+    db_state: tauri::State<'_, DbState>,
+    make: String,
+    model: Option<String>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get photos by camera", |c| db::get_photos_by_camera(c, &make, model.as_deref()))
+}
+
+/// COMMAND: Get all geotagged photos for the map view
+#[tauri::command]
+fn get_photos_with_location(
+    db_state: tauri::State<'_, DbState>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get geotagged photos", |c| db::get_photos_with_location(c))
+}
+
+/// COMMAND: Map pins for the visible area at a zoom level: photos grouped
+/// into grid cells, or single photos once zoomed in far enough. A box with
+/// `min_lon > max_lon` crosses the antimeridian.
+#[tauri::command]
+fn get_map_clusters(
+    db_state: tauri::State<'_, DbState>,
+    min_lat: f64,
+    max_lat: f64,
+    min_lon: f64,
+    max_lon: f64,
+    zoom: u8,
+) -> Result<Vec<db::MapCluster>, String> {
+    if !(-90.0..=90.0).contains(&min_lat) || !(-90.0..=90.0).contains(&max_lat) || min_lat > max_lat {
+        return Err("Latitude bounds must be within -90 to 90, south first".to_string());
+    }
+    if !(-180.0..=180.0).contains(&min_lon) || !(-180.0..=180.0).contains(&max_lon) {
+        return Err("Longitude bounds must be within -180 to 180".to_string());
+    }
+    if zoom > 22 {
+        return Err("Zoom must be between 0 and 22".to_string());
+    }
+    with_db(&db_state, "Failed to get map clusters", |c| {
+        db::get_map_clusters(c, (min_lat, max_lat), (min_lon, max_lon), zoom, config::MAX_PAGE_SIZE)
+    })
+}
+
+// ============================================================================
+// Duplicate Detection and Screenshot Detection
+// ============================================================================
+
+#[derive(Serialize, Clone)]
+pub struct DuplicateGroup {
+    pub group_id: u32,
+    pub group_type: String,        // "exact" or "similar"
+    pub photos: Vec<PhotoMetadata>,
+    pub similarity_score: f32,     // 0.0-1.0 (1.0 = identical)
+    /// Same order as `photos`
+    pub members: Vec<DuplicateMember>,
+    /// Path of the copy `resolve_duplicates` should keep if the user doesn't choose
+    pub suggested_keeper: String,
+    /// Best scoring member of a similar group, once every member is scored
+    pub sharpest: Option<String>,
+}
+
+/// What sets one copy of a duplicate apart from the others
+#[derive(Serialize, Clone)]
+pub struct DuplicateMember {
+    pub path: String,
+    /// None if the file can't be read and no size was recorded
+    pub file_size: Option<i64>,
+    pub width: u32,
+    pub height: u32,
+    pub albums: Vec<String>,
+    /// How many of date, location, camera, caption and rating are known
+    pub metadata_fields: u32,
+    /// From the quality job; see quality.rs
+    pub quality_score: Option<f64>,
+}
+
+/// Number of descriptive fields a copy carries, for picking the keeper
+fn metadata_fields(photo: &PhotoMetadata) -> u32 {
+    [
+        photo.date_confident.unwrap_or(photo.date_taken != 0),
+        photo.latitude.is_some() || photo.location_name.is_some(),
+        photo.camera_make.is_some() || photo.camera_model.is_some(),
+        photo.description.is_some(),
+        photo.rating > 0,
+    ]
+    .into_iter()
+    .filter(|&known| known)
+    .count() as u32
+}
+
+/// Fill in `members` and `suggested_keeper`. Similar groups also get their
+/// sharpest member, which is the keeper once every member is scored;
+/// otherwise, and for exact copies, it's the largest file, then the one
+/// with the most metadata, then the most pixels, then the earliest.
+fn describe_duplicate_group(
+    group: &mut DuplicateGroup,
+    details: &HashMap<String, (Option<i64>, Vec<String>)>,
+    quality: &HashMap<String, f64>,
+) {
+    group.members = group
+        .photos
+        .iter()
+        .map(|photo| {
+            let (size, albums) = details.get(&photo.path).cloned().unwrap_or_default();
+            DuplicateMember {
+                path: photo.path.clone(),
+                file_size: size.or_else(|| fs::metadata(&photo.path).ok().map(|m| m.len() as i64)),
+                width: photo.width,
+                height: photo.height,
+                albums,
+                metadata_fields: metadata_fields(photo),
+                quality_score: quality.get(&photo.path).copied(),
+            }
+        })
+        .collect();
+    if group.group_type != "exact" {
+        group.sharpest = sharpest(group.members.iter().map(|m| (&m.path, m.quality_score)));
+    }
+    if let Some(sharpest) = &group.sharpest {
+        group.suggested_keeper = sharpest.clone();
+        return;
+    }
+    group.suggested_keeper = group
+        .photos
+        .iter()
+        .zip(&group.members)
+        .max_by(|(pa, a), (pb, b)| {
+            a.file_size
+                .cmp(&b.file_size)
+                .then(a.metadata_fields.cmp(&b.metadata_fields))
+                .then((a.width as u64 * a.height as u64).cmp(&(b.width as u64 * b.height as u64)))
+                .then(pb.date_taken.cmp(&pa.date_taken))
+        })
+        .map(|(photo, _)| photo.path.clone())
+        .unwrap_or_default();
+}
+
+/// Path of the best scoring photo, or None unless every one has a score.
+/// Ties go to the first.
+fn sharpest<'a>(scores: impl Iterator<Item = (&'a String, Option<f64>)>) -> Option<String> {
+    let mut best: Option<(&String, f64)> = None;
+    for (path, score) in scores {
+        let score = score?;
+        if best.is_some_and(|(_, top)| score <= top) {
+            continue;
+        }
+        best = Some((path, score));
+    }
+    best.map(|(path, _)| path.clone())
+}
+
+#[derive(Serialize, Clone)]
+pub struct SimilarPhoto {
+    pub photo: PhotoMetadata,
+    /// Hamming distance to the suggested keeper at the closest rotation
+    pub distance: u32,
+    pub is_suggested_keeper: bool,
+    /// From the quality job; see quality.rs
+    pub quality_score: Option<f64>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct SimilarGroup {
+    /// Suggested keeper first, then by distance
+    pub photos: Vec<SimilarPhoto>,
+    /// Largest member distance from the keeper; groups are sorted by this
+    pub max_distance: u32,
+    /// Best scoring photo, once every member is scored
+    pub sharpest: Option<String>,
+}
+
+#[derive(Serialize, Clone)]
+pub struct ScanProgress {
+    pub total: u32,
+    pub processed: u32,
+    pub phase: String,
+}
+
+#[derive(Serialize)]
+pub struct ArchivedPhoto {
+    pub photo: PhotoMetadata,
+    pub archived_at: i64,
+    pub days_until_deletion: i64,
+}
+
+/// COMMAND: Scan library for duplicates and compute missing hashes
+#[tauri::command]
+async fn scan_for_duplicates(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<ScanProgress, String> {
+    // Get photos that need hash computation
+    let photos_without_hash = db::get_photos_without_dhash(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    let total = photos_without_hash.len() as u32;
+    let processed = Arc::new(AtomicU32::new(0));
+
+    // Emit initial progress
+    let _ = window.emit("scan_progress", ScanProgress {
+        total,
+        processed: 0,
+        phase: "hashing".to_string(),
+    });
+
+    // Process photos in parallel and collect results
+    let results: Vec<(String, Option<u64>)> = photos_without_hash
+        .par_iter()
+        .map(|(path, _name)| {
+            let hash = compute_dhash(Path::new(path));
+
+            // Update progress
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 10 == 0 || current == total {
+                let _ = window.emit("scan_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "hashing".to_string(),
+                });
+            }
+
+            (path.clone(), hash)
+        })
+        .collect();
+
+    // Emit comparing phase
+    let _ = window.emit("scan_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "saving".to_string(),
+    });
+
+    // Save hashes to database (must be done sequentially)
+    let conn = db_conn(&db_state)?;
+    for (path, hash) in results {
+        if let Some(h) = hash {
+            let _ = db::update_photo_dhash(&conn, &path, h as i64);
+        }
+    }
+
+    // Emit completion
+    let _ = window.emit("scan_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    Ok(ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    })
+}
+
+/// COMMAND: Group byte-identical photos by content hash.
+/// Groups of one are excluded; sorted by reclaimable bytes, largest first.
+#[tauri::command]
+fn find_duplicates(
+    db_state: tauri::State<'_, DbState>,
+) -> Result<Vec<db::ExactDuplicateGroup>, String> {
+    let mut groups = with_db(&db_state, "Failed to find duplicates", |c| db::find_exact_duplicates(c))?;
+
+    // Rows imported before file sizes were tracked: stat one copy instead
+    let mut resized = false;
+    for group in groups.iter_mut().filter(|g| g.file_size.is_none()) {
+        let size = group
+            .photos
+            .iter()
+            .find_map(|p| fs::metadata(&p.path).ok())
+            .map(|m| m.len() as i64);
+        if let Some(size) = size {
+            group.file_size = Some(size);
+            group.reclaimable_bytes = size * (group.photos.len() as i64 - 1);
+            resized = true;
+        }
+    }
+    if resized {
+        groups.sort_by(|a, b| b.reclaimable_bytes.cmp(&a.reclaimable_bytes));
+    }
+
+    Ok(groups)
+}
+
+/// COMMAND: Compute content hashes for photos imported without one.
+/// Emits `hash_progress` events every 25 files; returns how many were hashed.
+#[tauri::command]
+async fn backfill_hashes(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<u32, String> {
+    let paths = db::get_photos_without_content_hash(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    let total = paths.len() as u32;
+    let processed = AtomicU32::new(0);
+
+    let _ = window.emit("hash_progress", ScanProgress {
+        total,
+        processed: 0,
+        phase: "hashing".to_string(),
+    });
+
+    let hashes: Vec<(String, String)> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let hash = media::calculate_hash(Path::new(path));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = window.emit("hash_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "hashing".to_string(),
+                });
+            }
+
+            hash.map(|h| (path.clone(), h))
+        })
+        .collect();
+
+    db::update_content_hashes_batch(&*db_conn(&db_state)?, &hashes)
+        .map_err(|e| format!("Failed to save hashes: {}", e))?;
+
+    let _ = window.emit("hash_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    Ok(hashes.len() as u32)
+}
+
+/// A library file whose contents no longer match the hash stored at import.
+#[derive(Serialize, Clone, Debug)]
+pub struct CorruptedFile {
+    pub path: String,
+    pub expected_hash: String,
+    pub actual_hash: String,
+}
+
+/// Result of verify_library.
+#[derive(Serialize, Default)]
+pub struct LibraryVerification {
+    pub checked: u32,
+    pub missing: Vec<String>,
+    pub corrupted: Vec<CorruptedFile>,
+}
+
+/// COMMAND: Re-hash every file with a stored hash and report the ones that
+/// are gone or no longer match (bit rot, truncated copies, or edits made
+/// outside Terra). Read-only: nothing is changed.
+/// Emits `verify_progress` events every 25 files.
+#[tauri::command]
+async fn verify_library(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<LibraryVerification, String> {
+    let photos = db::get_hashed_photos(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    let total = photos.len() as u32;
+    let processed = AtomicU32::new(0);
+
+    // Some(None) = missing, Some(Some(actual)) = mismatch
+    let problems: Vec<(String, String, Option<String>)> = photos
+        .par_iter()
+        .filter_map(|(path, expected)| {
+            let actual = media::calculate_hash(Path::new(path));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = window.emit("verify_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "verifying".to_string(),
+                });
+            }
+
+            match actual {
+                Some(actual) if actual == *expected => None,
+                actual => Some((path.clone(), expected.clone(), actual)),
+            }
+        })
+        .collect();
+
+    let mut report = LibraryVerification { checked: total, ..Default::default() };
+    for (path, expected_hash, actual) in problems {
+        match actual {
+            Some(actual_hash) => report.corrupted.push(CorruptedFile { path, expected_hash, actual_hash }),
+            None => report.missing.push(path),
+        }
+    }
+    info!(
+        "Verified {} files: {} missing, {} corrupted",
+        total, report.missing.len(), report.corrupted.len()
+    );
+    Ok(report)
+}
+
+/// An album cover naming a photo that is gone, trashed, or whose file is missing.
+#[derive(Serialize, Clone, Debug)]
+pub struct DanglingAlbumReference {
+    pub album_id: i64,
+    pub album_name: String,
+    pub cover_photo_path: String,
+}
+
+/// Result of check_library_health. Each list holds photo paths.
+#[derive(Serialize, Default)]
+pub struct LibraryHealthReport {
+    pub checked: u32,
+    /// Rows whose file is no longer on disk
+    pub missing_files: Vec<String>,
+    /// Media files in the library folder with no row
+    pub untracked_files: Vec<String>,
+    pub dangling_album_references: Vec<DanglingAlbumReference>,
+    /// Rows stored as 0x0 (the decoder couldn't read them)
+    pub zero_dimension: Vec<String>,
+    /// Rows with no capture date from metadata, filename or file time
+    pub unknown_date: Vec<String>,
+}
+
+/// What repair_library does with rows whose file is missing.
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum MissingFileRepair {
+    /// Delete the row, its memberships and cached thumbnails
+    Remove,
+    /// Keep the row but flag it `is_missing`, e.g. for a drive that comes back
+    MarkOffline,
+}
+
+/// Repairs to apply; each is off unless asked for.
+#[derive(Deserialize, Default)]
+#[serde(default)]
+pub struct RepairActions {
+    pub missing_files: Option<MissingFileRepair>,
+    pub import_untracked: bool,
+    pub fix_dangling_references: bool,
+}
+
+#[derive(Serialize, Default)]
+pub struct RepairSummary {
+    pub removed: u32,
+    pub marked_offline: u32,
+    pub imported: u32,
+    pub references_fixed: u32,
+}
+
+/// Check every row against the disk and walk the library folder for files
+/// Terra doesn't know about. The trash folder, whose files belong to trashed
+/// rows, and rows on offline drives, whose files can't be seen, are skipped.
+/// Emits `health_progress` every 25 files in each phase.
+fn run_health_check(window: &tauri::Window, db_state: &DbState) -> Result<LibraryHealthReport, String> {
+    let (rows, covers, root) = {
+        let conn = db_conn(db_state)?;
+        let rows = db::get_health_rows(&conn).map_err(|e| format!("Failed to get photos: {}", e))?;
+        let covers = db::get_album_covers(&conn).map_err(|e| format!("Failed to get album covers: {}", e))?;
+        (rows, covers, db::get_library_path(&conn)?)
+    };
+    let emit_progress = |total: u32, processed: u32, phase: &str| {
+        if processed % 25 == 0 || processed == total {
+            let _ = window.emit("health_progress", ScanProgress {
+                total,
+                processed,
+                phase: phase.to_string(),
+            });
+        }
+    };
+
+    let total = rows.len() as u32;
+    let processed = AtomicU32::new(0);
+    // A file on an unmounted drive isn't missing, just out of reach
+    let missing_files: Vec<String> = rows
+        .par_iter()
+        .filter_map(|row| {
+            let gone = !row.is_offline && !Path::new(&row.path).exists();
+            emit_progress(total, processed.fetch_add(1, Ordering::SeqCst) + 1, "checking_files");
+            gone.then(|| row.path.clone())
+        })
+        .collect();
+
+    let trash = root.join(".trash");
+    let files: Vec<walkdir::DirEntry> = WalkDir::new(&root)
+        .into_iter()
+        .filter_entry(|e| e.path() != trash)
+        .filter_map(|e| e.ok())
+        .filter(|e| e.path().is_file() && is_media_file(e.path()))
+        .collect();
+    let known: std::collections::HashSet<&str> = rows.iter().map(|r| r.path.as_str()).collect();
+    let total = files.len() as u32;
+    let processed = AtomicU32::new(0);
+    let untracked_files: Vec<String> = files
+        .par_iter()
+        .filter_map(|entry| {
+            let canonical = library::canonical_path(entry.path()).ok()?;
+            emit_progress(total, processed.fetch_add(1, Ordering::SeqCst) + 1, "finding_untracked");
+            (!known.contains(canonical.as_str())).then_some(canonical)
+        })
+        .collect();
+
+    let missing: std::collections::HashSet<&str> = missing_files.iter().map(String::as_str).collect();
+    let dangling_album_references = covers
+        .into_iter()
+        .filter(|c| !c.in_library || missing.contains(c.cover_photo_path.as_str()))
+        .map(|c| DanglingAlbumReference {
+            album_id: c.album_id,
+            album_name: c.album_name,
+            cover_photo_path: c.cover_photo_path,
+        })
+        .collect();
+
+    let report = LibraryHealthReport {
+        checked: rows.len() as u32,
+        zero_dimension: rows.iter().filter(|r| r.width == 0 || r.height == 0).map(|r| r.path.clone()).collect(),
+        unknown_date: rows
+            .iter()
+            .filter(|r| r.date_source.as_deref() == Some(media::DATE_SOURCE_UNKNOWN))
+            .map(|r| r.path.clone())
+            .collect(),
+        missing_files,
+        untracked_files,
+        dangling_album_references,
+    };
+    info!(
+        "Library health: {} checked, {} missing, {} untracked, {} dangling covers, {} 0x0, {} undated",
+        report.checked,
+        report.missing_files.len(),
+        report.untracked_files.len(),
+        report.dangling_album_references.len(),
+        report.zero_dimension.len(),
+        report.unknown_date.len()
+    );
+    Ok(report)
+}
+
+/// COMMAND: Report problems in the library: rows whose file is gone, files
+/// in the library folder that were never imported, album covers pointing at
+/// missing photos, 0x0 photos and photos with no known date. Read-only.
+/// Emits `health_progress` events while it walks the library.
+#[tauri::command]
+async fn check_library_health(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<LibraryHealthReport, String> {
+    run_health_check(&window, &db_state)
+}
+
+/// COMMAND: Fix what check_library_health finds, only for the actions asked
+/// for. The check is run again first so nothing stale is acted on.
+/// Emits `health_progress` events, with an `importing` phase for untracked files.
+#[tauri::command]
+async fn repair_library(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+    actions: RepairActions,
+) -> Result<RepairSummary, String> {
+    let report = run_health_check(&window, &db_state)?;
+    let mut summary = RepairSummary::default();
+
+    if actions.import_untracked && !report.untracked_files.is_empty() {
+        let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+        let total = report.untracked_files.len() as u32;
+        let processed = AtomicU32::new(0);
+        let photos: Vec<PhotoMetadata> = report
+            .untracked_files
+            .par_iter()
+            .filter_map(|path| {
+                let photo = process_image(Path::new(path), Some(&geocoder));
+                let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+                if current % 25 == 0 || current == total {
+                    let _ = window.emit("health_progress", ScanProgress {
+                        total,
+                        processed: current,
+                        phase: "importing".to_string(),
+                    });
+                }
+                photo
+            })
+            .collect();
+
+        let stats: Vec<(String, i64, i64)> = photos
+            .iter()
+            .filter_map(|p| media::file_stats(Path::new(&p.path)).map(|(size, mtime)| (p.path.clone(), size, mtime)))
+            .collect();
+        let live_pairs = live_photos::find_pairs(photos.iter().map(|p| p.path.as_str()));
+        let stacks = stacks::find_stacks(photos.iter().map(|p| p.path.as_str()));
+        let failures = insert_photos_chunked(&db_state, &photos, "scan")
+            .map_err(|e| format!("Failed to save untracked photos: {}", e))?;
+        let conn = db_conn(&db_state)?;
+        for (path, err) in &failures {
+            error!("Failed to insert {}: {}", path, err);
+        }
+        let _ = db::update_file_stats_batch(&conn, &stats);
+        record_live_photo_pairs(&conn, &live_pairs);
+        record_stacks(&conn, &stacks);
+        summary.imported = (photos.len() - failures.len()) as u32;
+        drop(conn);
+        if summary.imported > 0 {
+            spawn_post_import_jobs(window.app_handle());
+        }
+    }
+
+    let conn = db_conn(&db_state)?;
+    match actions.missing_files {
+        Some(MissingFileRepair::Remove) => {
+            for path in &report.missing_files {
+                // Its drive may have gone offline since the check, as delete_photos guards
+                if db::is_photo_offline(&conn, path).unwrap_or(false) {
+                    continue;
+                }
+                let orphaned_hash = db::permanently_delete_photo(&conn, path)
+                    .map_err(|e| format!("Failed to delete from DB: {}", e))?;
+                if let Some(hash) = orphaned_hash {
+                    thumbnails::remove_thumbnails(&hash);
+                }
+                summary.removed += 1;
+            }
+        }
+        Some(MissingFileRepair::MarkOffline) => {
+            for path in &report.missing_files {
+                db::set_photo_missing(&conn, path, true)
+                    .map_err(|e| format!("Failed to mark photo offline: {}", e))?;
+                summary.marked_offline += 1;
+            }
+        }
+        None => {}
+    }
+
+    if actions.fix_dangling_references {
+        db::remove_orphaned_references(&conn)
+            .map_err(|e| format!("Failed to remove orphaned references: {}", e))?;
+        for reference in &report.dangling_album_references {
+            db::clear_album_cover(&conn, reference.album_id, &reference.cover_photo_path)
+                .map_err(|e| format!("Failed to clear album cover: {}", e))?;
+            summary.references_fixed += 1;
+        }
+    }
+
+    info!(
+        "Library repair: {} removed, {} marked offline, {} imported, {} references fixed",
+        summary.removed, summary.marked_offline, summary.imported, summary.references_fixed
+    );
+    Ok(summary)
+}
+
+/// COMMAND: Compute perceptual hashes for photos that don't have one yet.
+/// Emits `phash_progress` events every 25 photos; returns how many were hashed.
+#[tauri::command]
+async fn compute_perceptual_hashes(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<u32, String> {
+    let paths: Vec<String> = db::get_photos_without_phash(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?
+        .into_iter()
+        .filter(|p| !media::is_video(Path::new(p)))
+        .collect();
+
+    let total = paths.len() as u32;
+    let processed = AtomicU32::new(0);
+
+    let hashes: Vec<(String, [u64; 4])> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let hashes = media::compute_phash_rotations(Path::new(path));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 25 == 0 || current == total {
+                let _ = window.emit("phash_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "hashing".to_string(),
+                });
+            }
+
+            hashes.map(|h| (path.clone(), h))
+        })
+        .collect();
+
+    db::update_phashes_batch(&*db_conn(&db_state)?, &hashes)
+        .map_err(|e| format!("Failed to save perceptual hashes: {}", e))?;
+
+    let _ = window.emit("phash_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    Ok(hashes.len() as u32)
+}
+
+/// COMMAND: Group visually similar photos (recompressed, resized or rotated
+/// copies) by perceptual hash. Run `compute_perceptual_hashes` first.
+/// The sharpest member of each group is flagged as the keeper once every
+/// member has a quality score, the highest-resolution one until then.
+#[tauri::command]
+async fn find_similar_photos(
+    db_state: tauri::State<'_, DbState>,
+    max_distance: u32,
+) -> Result<Vec<SimilarGroup>, String> {
+    let max_distance = max_distance.min(config::MAX_SIMILAR_DISTANCE);
+    let rows = with_db(&db_state, "Failed to get photos", |c| db::get_photos_with_phash(c))?;
+    let hashes: Vec<[u64; 4]> = rows.iter().map(|(_, h)| *h).collect();
+    let members_of = media::group_similar_hashes(&hashes, max_distance);
+    let grouped: Vec<String> = members_of.iter().flatten().map(|&i| rows[i].0.path.clone()).collect();
+    let quality = with_db(&db_state, "Failed to get quality scores", |c| db::get_quality_scores(c, &grouped))?;
+
+    let mut groups: Vec<SimilarGroup> = members_of
+        .into_iter()
+        .map(|members| {
+            let path = |i: usize| &rows[i].0.path;
+            let sharpest = sharpest(members.iter().map(|&i| (path(i), quality.get(path(i)).copied())));
+            let keeper = members
+                .iter()
+                .copied()
+                .find(|&i| sharpest.as_ref() == Some(path(i)))
+                .or_else(|| {
+                    members.iter().copied().max_by(|&a, &b| {
+                        let (pa, pb) = (&rows[a].0, &rows[b].0);
+                        (pa.width as u64 * pa.height as u64)
+                            .cmp(&(pb.width as u64 * pb.height as u64))
+                            // Prefer the earlier photo when resolutions tie
+                            .then(pb.date_taken.cmp(&pa.date_taken))
+                    })
+                })
+                .expect("groups have at least two members");
+
+            let mut photos: Vec<SimilarPhoto> = members
+                .iter()
+                .map(|&i| SimilarPhoto {
+                    photo: rows[i].0.clone(),
+                    distance: media::rotation_distance(hashes[i][0], &hashes[keeper]),
+                    is_suggested_keeper: i == keeper,
+                    quality_score: quality.get(&rows[i].0.path).copied(),
+                })
+                .collect();
+            photos.sort_by_key(|p| (!p.is_suggested_keeper, p.distance));
+
+            SimilarGroup {
+                max_distance: photos.iter().map(|p| p.distance).max().unwrap_or(0),
+                sharpest,
+                photos,
+            }
+        })
+        .collect();
+
+    groups.sort_by(|a, b| {
+        a.max_distance
+            .cmp(&b.max_distance)
+            .then(b.photos.len().cmp(&a.photos.len()))
+    });
+    Ok(groups)
+}
+
+/// COMMAND: Queue a job scoring photos' sharpness and exposure, for
+/// picking the best of a burst or duplicate group. Photos already scored
+/// are skipped. Returns the job id.
+#[tauri::command]
+fn analyze_quality(app: tauri::AppHandle, job_queue: tauri::State<'_, jobs::JobQueue>) -> Result<i64, String> {
+    job_queue.enqueue(&app, jobs::JobKind::Quality, serde_json::json!({}))
+}
+
+/// COMMAND: Photos scoring below `threshold` (default 2.0), worst first.
+/// Likely blurry, though smooth subjects score low too; see quality.rs.
+#[tauri::command]
+fn get_low_quality_photos(
+    db_state: tauri::State<'_, DbState>,
+    threshold: Option<f64>,
+    limit: Option<i64>,
+) -> Result<Vec<db::ScoredPhoto>, String> {
+    let threshold = threshold.unwrap_or(quality::DEFAULT_LOW_QUALITY_THRESHOLD);
+    let limit = limit.unwrap_or(config::MAX_PAGE_SIZE).clamp(1, config::MAX_PAGE_SIZE);
+    with_db(&db_state, "Failed to get low quality photos", |c| db::get_low_quality_photos(c, threshold, limit))
+}
+
+/// COMMAND: Get duplicate groups based on hash similarity.
+/// `kind` limits the result to "exact" (same content hash) or "similar"
+/// (perceptual hash within `threshold`) groups; both when omitted.
+#[tauri::command]
+fn get_duplicate_groups(
+    db_state: tauri::State<'_, DbState>,
+    kind: Option<String>,
+    threshold: Option<u32>,
+) -> Result<Vec<DuplicateGroup>, String> {
+    let (want_exact, want_similar) = match kind.as_deref() {
+        None => (true, true),
+        Some("exact") => (true, false),
+        Some("similar") => (false, true),
+        Some(other) => return Err(format!("Unknown duplicate kind: {}", other)),
+    };
+    let conn = db_conn(&db_state)?;
+
+    // Get all photos with their hashes
+    let photos_with_hash = db::get_all_photos_with_dhash(&conn)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    // Also get full photo metadata for later
+    let all_photos = db::get_all_photos(&conn)
+        .map_err(|e| format!("Failed to get photo metadata: {}", e))?;
+
+    // Create a map for quick lookup
+    let photo_map: HashMap<String, PhotoMetadata> = all_photos
+        .into_iter()
+        .map(|p| (p.path.clone(), p))
+        .collect();
+
+    let mut groups: Vec<DuplicateGroup> = Vec::new();
+    let mut processed_paths: std::collections::HashSet<String> = std::collections::HashSet::new();
+    let mut group_id: u32 = 0;
+
+    // First, find exact duplicates (same content_hash)
+    let mut hash_groups: HashMap<String, Vec<String>> = HashMap::new();
+    for (path, _, content_hash) in &photos_with_hash {
+        if let Some(hash) = content_hash {
+            hash_groups.entry(hash.clone()).or_default().push(path.clone());
+        }
+    }
+
+    for (_, paths) in hash_groups {
+        // Exact copies are always set aside so they don't show up as "similar"
+        if paths.len() > 1 {
+            let photos: Vec<PhotoMetadata> = paths.iter()
+                .filter_map(|p| photo_map.get(p).cloned())
+                .collect();
+
+            if photos.len() > 1 {
+                for path in &paths {
+                    processed_paths.insert(path.clone());
+                }
+
+                if want_exact {
+                    groups.push(DuplicateGroup {
+                        group_id,
+                        group_type: "exact".to_string(),
+                        photos,
+                        similarity_score: 1.0,
+                        members: Vec::new(),
+                        suggested_keeper: String::new(),
+                        sharpest: None,
+                    });
+                    group_id += 1;
+                }
+            }
+        }
+    }
+
+    // Then, find similar photos (perceptual hash within threshold)
+    // Use provided threshold or fall back to configured default
+    let effective_threshold = match threshold {
+        Some(t) if t > 0 => t,
+        _ => config::DUPLICATE_HAMMING_THRESHOLD,
+    };
+
+    for i in 0..photos_with_hash.len() {
+        if !want_similar {
+            break;
+        }
+        let (path_i, hash_i, _) = &photos_with_hash[i];
+
+        if processed_paths.contains(path_i) {
+            continue;
+        }
+
+        if let Some(h_i) = hash_i {
+            let mut similar_paths: Vec<String> = vec![path_i.clone()];
+
+            for j in (i + 1)..photos_with_hash.len() {
+                let (path_j, hash_j, _) = &photos_with_hash[j];
+
+                if processed_paths.contains(path_j) {
+                    continue;
+                }
+
+                if let Some(h_j) = hash_j {
+                    let distance = hamming_distance(*h_i as u64, *h_j as u64);
+                    if distance <= effective_threshold && distance > 0 {
+                        similar_paths.push(path_j.clone());
+                    }
+                }
+            }
+
+            if similar_paths.len() > 1 {
+                let photos: Vec<PhotoMetadata> = similar_paths.iter()
+                    .filter_map(|p| photo_map.get(p).cloned())
+                    .collect();
+
+                if photos.len() > 1 {
+                    for path in &similar_paths {
+                        processed_paths.insert(path.clone());
+                    }
+
+                    // Calculate average similarity score
+                    let similarity = 1.0 - (effective_threshold as f32 / 64.0);
+
+                    groups.push(DuplicateGroup {
+                        group_id,
+                        group_type: "similar".to_string(),
+                        photos,
+                        similarity_score: similarity,
+                        members: Vec::new(),
+                        suggested_keeper: String::new(),
+                        sharpest: None,
+                    });
+                    group_id += 1;
+                }
+            }
+        }
+    }
+
+    // Sort groups: exact duplicates first, then by number of photos
+    groups.sort_by(|a, b| {
+        if a.group_type != b.group_type {
+            a.group_type.cmp(&b.group_type) // "exact" comes before "similar"
+        } else {
+            b.photos.len().cmp(&a.photos.len()) // More photos first
+        }
+    });
+
+    let paths: Vec<String> = groups.iter().flat_map(|g| g.photos.iter().map(|p| p.path.clone())).collect();
+    let details = db::get_duplicate_member_details(&conn, &paths)
+        .map_err(|e| format!("Failed to get duplicate details: {}", e))?;
+    let quality = db::get_quality_scores(&conn, &paths).map_err(|e| format!("Failed to get quality scores: {}", e))?;
+    for group in &mut groups {
+        describe_duplicate_group(group, &details, &quality);
+    }
+
+    Ok(groups)
+}
+
+/// One group to resolve: every copy shown to the user, and the one to keep
+#[derive(Deserialize)]
+pub struct DuplicateResolutionRequest {
+    pub group: Vec<String>,
+    pub keep: String,
+}
+
+#[derive(Serialize, Default)]
+pub struct ResolveDuplicatesSummary {
+    /// Pass one to `undo_duplicate_resolution`; one per group that changed anything
+    pub resolution_ids: Vec<i64>,
+    /// Copies moved to the trash
+    pub trashed: u32,
+    /// Copies left alone because another group in the batch keeps them
+    pub kept_elsewhere: Vec<String>,
+}
+
+/// Resolve each group in turn. A photo kept by any group in the batch is
+/// never discarded by another, and a copy discarded once isn't merged again.
+fn resolve_duplicate_batch(
+    conn: &rusqlite::Connection,
+    requests: &[DuplicateResolutionRequest],
+) -> Result<ResolveDuplicatesSummary, String> {
+    for request in requests {
+        if !request.group.contains(&request.keep) {
+            return Err(format!("{} is not part of the group it should be kept from", request.keep));
+        }
+    }
+    let keepers: std::collections::HashSet<&str> = requests.iter().map(|r| r.keep.as_str()).collect();
+
+    let mut summary = ResolveDuplicatesSummary::default();
+    let mut discarded = std::collections::HashSet::new();
+    for request in requests {
+        let mut discard = Vec::new();
+        for path in request.group.iter().filter(|p| **p != request.keep) {
+            if keepers.contains(path.as_str()) {
+                if !summary.kept_elsewhere.contains(path) {
+                    summary.kept_elsewhere.push(path.clone());
+                }
+            } else if discarded.insert(path.clone()) {
+                discard.push(path.clone());
+            }
+        }
+
+        // One transaction, so a failed trash doesn't leave the merge half done
+        let tx = conn.unchecked_transaction().map_err(|e| format!("Failed to merge duplicates: {}", e))?;
+        let Some(resolution_id) = db::merge_duplicates(&tx, &request.keep, &discard)
+            .map_err(|e| format!("Failed to merge duplicates: {}", e))?
+        else {
+            continue;
+        };
+        let trashed = trash_photos(&tx, &discard)?.len() as u32;
+        tx.commit().map_err(|e| format!("Failed to merge duplicates: {}", e))?;
+        summary.resolution_ids.push(resolution_id);
+        summary.trashed += trashed;
+        info!("Kept {} over {} duplicate(s)", request.keep, discard.len());
+    }
+    if !summary.resolution_ids.is_empty() {
+        log_operation(conn, &Operation::ResolveDuplicates { resolution_ids: summary.resolution_ids.clone() });
+    }
+    Ok(summary)
+}
+
+/// COMMAND: Keep one photo of a duplicate group and trash the rest.
+/// The kept photo takes over the others' albums, tags, album covers and
+/// favorite flag first, so nothing the user organized is lost. Each group
+/// is recorded and can be reverted with `undo_duplicate_resolution` until
+/// the trash is emptied.
+#[tauri::command]
+fn resolve_duplicates(
+    db_state: tauri::State<'_, DbState>,
+    group: Vec<String>,
+    keep: String,
+) -> Result<ResolveDuplicatesSummary, String> {
+    let conn = db_conn(&db_state)?;
+    resolve_duplicate_batch(&conn, &[DuplicateResolutionRequest { group, keep }])
+}
+
+/// COMMAND: Resolve several duplicate groups at once, as `resolve_duplicates`
+/// does for one. A photo kept in one group survives even if another group
+/// in the batch lists it as a copy to discard.
+#[tauri::command]
+fn resolve_duplicate_groups(
+    db_state: tauri::State<'_, DbState>,
+    resolutions: Vec<DuplicateResolutionRequest>,
+) -> Result<ResolveDuplicatesSummary, String> {
+    let conn = db_conn(&db_state)?;
+    resolve_duplicate_batch(&conn, &resolutions)
+}
+
+/// COMMAND: Revert one resolution: restore its discarded copies from the
+/// trash and take back what the kept photo gained from them. Returns how
+/// many copies were restored.
+#[tauri::command]
+fn undo_duplicate_resolution(db_state: tauri::State<'_, DbState>, resolution_id: i64) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+    let paths = discarded_duplicates(&conn, resolution_id)?;
+    revert_duplicate_resolution(&conn, resolution_id, &paths)
+}
+
+/// Current paths of the copies a resolution discarded, or why it can't be undone.
+fn discarded_duplicates(conn: &rusqlite::Connection, resolution_id: i64) -> Result<Vec<String>, String> {
+    let discarded = db::get_discarded_duplicates(conn, resolution_id)
+        .map_err(|e| format!("Failed to read duplicate resolution: {}", e))?
+        .ok_or_else(|| "That duplicate resolution no longer exists".to_string())?;
+    discarded
+        .into_iter()
+        .collect::<Option<Vec<String>>>()
+        .ok_or_else(|| "Can't undo: the discarded copies were permanently deleted".to_string())
+}
+
+/// Restore a resolution's discarded copies (`paths`) and take back the merge.
+fn revert_duplicate_resolution(conn: &rusqlite::Connection, resolution_id: i64, paths: &[String]) -> Result<u32, String> {
+    let restored = restore_trashed(conn, paths)?;
+    db::undo_duplicate_merge(conn, resolution_id)
+        .map_err(|e| format!("Failed to undo duplicate resolution: {}", e))?;
+    Ok(restored)
+}
+
+/// COMMAND: Classify every photo as screenshot or not and return the screenshots.
+/// Imports already classify new files; this backfills rows from before that
+/// and applies heuristic changes. Works from stored metadata and only reads
+/// a file whose dimensions were never recorded. Photos the user marked with
+/// `set_is_screenshot` keep their verdict.
+#[tauri::command]
+async fn scan_for_screenshots(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let candidates = db::get_screenshot_candidates(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    let total = candidates.len() as u32;
+    let processed = AtomicU32::new(0);
+
+    let _ = window.emit("screenshot_scan_progress", ScanProgress {
+        total,
+        processed: 0,
+        phase: "analyzing".to_string(),
+    });
+
+    let changes: Vec<(String, bool)> = candidates
+        .into_par_iter()
+        .filter_map(|(mut photo, was_screenshot)| {
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 50 == 0 || current == total {
+                let _ = window.emit("screenshot_scan_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "analyzing".to_string(),
+                });
+            }
+
+            let mut is_screenshot = media::is_screenshot(&photo);
+            if !is_screenshot && (photo.width == 0 || photo.height == 0) {
+                if let Some((width, height)) = media::read_dimensions(Path::new(&photo.path)) {
+                    photo.width = width;
+                    photo.height = height;
+                    is_screenshot = media::is_screenshot(&photo);
+                }
+            }
+            (is_screenshot != was_screenshot).then_some((photo.path, is_screenshot))
+        })
+        .collect();
+
+    let conn = db_conn(&db_state)?;
+    let changed = db::set_screenshot_flags(&conn, &changes)
+        .map_err(|e| format!("Failed to save screenshot flags: {}", e))?;
+    info!("Screenshot scan changed {} of {} photos", changed, total);
+
+    let _ = window.emit("screenshot_scan_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    db::get_screenshots(&conn).map_err(|e| format!("Failed to get screenshots: {}", e))
+}
+
+/// COMMAND: Mark a photo as a screenshot or not, overriding detection for good
+#[tauri::command]
+fn set_is_screenshot(db_state: tauri::State<'_, DbState>, path: String, is_screenshot: bool) -> Result<(), String> {
+    if with_db_write(&db_state, "Failed to set screenshot flag", |c| db::set_screenshot_override(c, &path, is_screenshot))? {
+        Ok(())
+    } else {
+        Err(format!("Photo not found: {}", path))
+    }
+}
+
+/// COMMAND: Get all detected screenshots
+#[tauri::command]
+fn get_screenshots(db_state: tauri::State<'_, DbState>) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get screenshots", |c| db::get_screenshots(c))
+}
+
+/// COMMAND: Archive photos (move to archive folder, set archived_at)
+#[tauri::command]
+fn archive_photos(db_state: tauri::State<'_, DbState>, paths: Vec<String>) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    let library_path = db::get_library_path(&conn)?;
+    let archive_path = db::get_archive_path(&conn)?;
+
+    for path_str in paths {
+        let source = Path::new(&path_str);
+        if !source.exists() {
+            warn!("File not found for archiving: {}", path_str);
+            continue;
+        }
+
+        // Create relative path structure in archive
+        let relative_path = source.strip_prefix(&library_path)
+            .unwrap_or(Path::new(source.file_name().unwrap_or_default()));
+
+        let mut dest = archive_path.clone();
+        if let Some(parent) = relative_path.parent() {
+            dest.push(parent);
+            fs::create_dir_all(&dest).ok();
+        }
+        dest.push(relative_path.file_name().unwrap_or_default());
+
+        // Move file to archive
+        match fs::rename(&source, &dest) {
+            Ok(_) => {
+                // Update database with new path and archived_at timestamp
+                let canonical_dest = library::canonical_path(&dest)
+                    .unwrap_or_else(|_| library::stored_path(&dest));
+
+                // First update the path in the database
+                let _ = conn.execute(
+                    "UPDATE photos SET path = ?1 WHERE path = ?2",
+                    rusqlite::params![canonical_dest, path_str],
+                );
+
+                // Then set archived_at
+                db::archive_photo(&conn, &canonical_dest)
+                    .map_err(|e| format!("Failed to archive in DB: {}", e))?;
+
+                debug!("Archived: {} -> {}", path_str, canonical_dest);
+            }
+            Err(e) => {
+                error!("Failed to move file to archive: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// COMMAND: Restore photos from archive
+#[tauri::command]
+fn restore_photos(db_state: tauri::State<'_, DbState>, paths: Vec<String>) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    let library_path = db::get_library_path(&conn)?;
+    let archive_path = db::get_archive_path(&conn)?;
+
+    for path_str in paths {
+        let source = Path::new(&path_str);
+        if !source.exists() {
+            warn!("File not found for restoration: {}", path_str);
+            continue;
+        }
+
+        // Restore to original location in library
+        let relative_path = source.strip_prefix(&archive_path)
+            .unwrap_or(Path::new(source.file_name().unwrap_or_default()));
+
+        let mut dest = library_path.clone();
+        if let Some(parent) = relative_path.parent() {
+            dest.push(parent);
+            fs::create_dir_all(&dest).ok();
+        }
+        dest.push(relative_path.file_name().unwrap_or_default());
+
+        // Move file back to library
+        match fs::rename(&source, &dest) {
+            Ok(_) => {
+                let canonical_dest = library::canonical_path(&dest)
+                    .unwrap_or_else(|_| library::stored_path(&dest));
+
+                // Update path in database
+                let _ = conn.execute(
+                    "UPDATE photos SET path = ?1 WHERE path = ?2",
+                    rusqlite::params![canonical_dest, path_str],
+                );
+
+                // Clear archived_at
+                db::restore_photo(&conn, &canonical_dest)
+                    .map_err(|e| format!("Failed to restore in DB: {}", e))?;
+
+                debug!("Restored: {} -> {}", path_str, canonical_dest);
+            }
+            Err(e) => {
+                error!("Failed to restore file: {}", e);
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// COMMAND: Get photos archived for deletion, with days until deletion
+#[tauri::command]
+fn get_deletion_archive(db_state: tauri::State<'_, DbState>) -> Result<Vec<ArchivedPhoto>, String> {
+    let conn = db_conn(&db_state)?;
+    let archived = db::get_deletion_archive(&conn)
+        .map_err(|e| format!("Failed to get archived photos: {}", e))?;
+
+    let now = chrono::Utc::now().timestamp();
+
+    let result: Vec<ArchivedPhoto> = archived
+        .into_iter()
+        .map(|(photo, archived_at)| {
+            let days_passed = (now - archived_at) / (24 * 60 * 60);
+            let days_until_deletion = (config::ARCHIVE_DELETION_DAYS - days_passed).max(0);
+
+            ArchivedPhoto {
+                photo,
+                archived_at,
+                days_until_deletion,
+            }
+        })
+        .collect();
+
+    Ok(result)
+}
+
+/// COMMAND: Clean up old archived photos (older than configured days)
+#[tauri::command]
+fn cleanup_old_archives(db_state: tauri::State<'_, DbState>) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+
+    let old_paths = db::get_old_archived_photos(&conn, config::ARCHIVE_DELETION_DAYS)
+        .map_err(|e| format!("Failed to get old archives: {}", e))?;
+
+    let mut deleted_count: u32 = 0;
+
+    for path_str in old_paths {
+        let path = Path::new(&path_str);
+
+        // Delete file from filesystem
+        if path.exists() {
+            if let Err(e) = library::delete_file_permanently(path) {
+                error!("Failed to delete file {}: {}", path_str, e);
+                continue;
+            }
+        }
+
+        // Delete from database, and the thumbnails if nothing else shares them
+        match db::permanently_delete_photo(&conn, &path_str) {
+            Ok(Some(hash)) => thumbnails::remove_thumbnails(&hash),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to delete from DB {}: {}", path_str, e);
+                continue;
+            }
+        }
+
+        debug!("Permanently deleted: {}", path_str);
+        deleted_count += 1;
+    }
+
+    Ok(deleted_count)
+}
+
+// ============================================================================
+// Trash Commands
+// ============================================================================
+
+#[derive(Serialize, Clone)]
+pub struct TrashedPhoto {
+    /// `photo.path` is the original location; pass it to `restore_from_trash`
+    pub photo: PhotoMetadata,
+    /// Where the file sits now, for display. None when the file lives outside
+    /// the library and was left in place.
+    pub trash_path: Option<String>,
+    pub deleted_at: i64,
+    pub days_until_purge: i64,
+}
+
+#[derive(Serialize, Clone, Default)]
+pub struct TrashPurgeSummary {
+    /// Photos gone for good
+    pub purged: u32,
+    /// Trashed files that couldn't be disposed of; they stay in the trash
+    pub failed: Vec<DeleteFailure>,
+}
+
+/// Permanently remove trashed photos deleted before `cutoff`. Files inside
+/// `.trash` go to the system trash in `system_trash` mode and are deleted
+/// otherwise; photos that were outside the library only lose their
+/// database row.
+fn purge_trash(conn: &rusqlite::Connection, cutoff: i64) -> Result<TrashPurgeSummary, String> {
+    let entries = db::get_trashed_before(conn, cutoff)
+        .map_err(|e| format!("Failed to read trash: {}", e))?;
+    let mode = deletion_mode(conn);
+
+    let mut summary = TrashPurgeSummary::default();
+    for (path, trash_path) in entries {
+        if let Some(file) = trash_path.as_deref().map(Path::new) {
+            if file.exists() {
+                if let library::Disposal::Kept(error) = library::dispose_file(file, mode, library::send_to_system_trash) {
+                    error!("Failed to dispose of trashed file {}: {}", file.display(), error);
+                    summary.failed.push(DeleteFailure { path, error });
+                    continue;
+                }
+            }
+        }
+        match db::permanently_delete_photo(conn, &path) {
+            Ok(Some(hash)) => thumbnails::remove_thumbnails(&hash),
+            Ok(None) => {}
+            Err(e) => {
+                error!("Failed to delete from DB {}: {}", path, e);
+                continue;
+            }
+        }
+        summary.purged += 1;
+    }
+    Ok(summary)
+}
+
+/// COMMAND: Move photos to Terra's trash.
+/// Library files move into `<library>/.trash`; files elsewhere stay put and
+/// are only hidden. Album and tag memberships are kept for restore.
+#[tauri::command]
+fn move_to_trash(db_state: tauri::State<'_, DbState>, paths: Vec<String>) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+    let trashed = trash_photos(&conn, &paths)?;
+    log_trashing(&conn, &trashed);
+    Ok(trashed.len() as u32)
+}
+
+/// Log photos the user moved to the trash, for `undo_last_operation`.
+fn log_trashing(conn: &rusqlite::Connection, trashed: &[String]) {
+    let photo_ids = db::get_photo_ids(conn, trashed).unwrap_or_default();
+    if !photo_ids.is_empty() {
+        log_operation(conn, &Operation::MoveToTrash { photo_ids });
+    }
+}
+
+/// Trash each of `paths` as `move_to_trash` describes; returns the ones trashed.
+fn trash_photos(conn: &rusqlite::Connection, paths: &[String]) -> Result<Vec<String>, String> {
+    let library_path = db::get_library_path(conn)?;
+    let trash_root = db::get_trash_path(conn)?;
+
+    let mut trashed = Vec::new();
+    for path_str in paths {
+        let source = Path::new(path_str);
+        let trash_path = if source.exists() && is_path_in_managed_library(conn, source) {
+            let relative = source
+                .strip_prefix(&library_path)
+                .unwrap_or(Path::new(source.file_name().unwrap_or_default()));
+            let dest = library::unique_destination(trash_root.join(relative));
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            if let Err(e) = fs::rename(source, &dest) {
+                error!("Failed to move {} to trash: {}", path_str, e);
+                continue;
+            }
+            Some(dest.to_string_lossy().to_string())
+        } else {
+            None
+        };
+
+        db::trash_photo(conn, path_str, trash_path.as_deref())
+            .map_err(|e| format!("Failed to trash in DB: {}", e))?;
+        debug!("Trashed: {}", path_str);
+        trashed.push(path_str.clone());
+    }
+
+    Ok(trashed)
+}
+
+/// COMMAND: Restore trashed photos to their original locations.
+/// Takes the original paths (`photo.path` from `get_trashed_photos`). If the
+/// original spot has been taken since, the file is restored beside it.
+#[tauri::command]
+fn restore_from_trash(db_state: tauri::State<'_, DbState>, paths: Vec<String>) -> Result<u32, String> {
+    let conn = db_conn(&db_state)?;
+    restore_trashed(&conn, &paths)
+}
+
+/// Restore each of `paths` as `restore_from_trash` describes; returns how many were.
+fn restore_trashed(conn: &rusqlite::Connection, paths: &[String]) -> Result<u32, String> {
+    let entries = db::get_trash_entries(conn, paths)
+        .map_err(|e| format!("Failed to read trash: {}", e))?;
+
+    let mut restored: u32 = 0;
+    for (path, trash_path) in entries {
+        let mut final_path = path.clone();
+
+        if let Some(trash_path) = trash_path {
+            let dest = library::unique_destination(PathBuf::from(&path));
+            if let Some(parent) = dest.parent() {
+                fs::create_dir_all(parent).ok();
+            }
+            if let Err(e) = fs::rename(&trash_path, &dest) {
+                error!("Failed to restore {} from trash: {}", path, e);
+                continue;
+            }
+            final_path = dest.to_string_lossy().to_string();
+            if final_path != path {
+                db::update_photo_path(conn, &path, &final_path)
+                    .map_err(|e| format!("Failed to update restored path: {}", e))?;
+            }
+        }
+
+        db::untrash_photo(conn, &final_path)
+            .map_err(|e| format!("Failed to restore in DB: {}", e))?;
+        debug!("Restored from trash: {}", final_path);
+        restored += 1;
+    }
+
+    Ok(restored)
+}
+
+/// COMMAND: Permanently delete everything in the trash, honouring the
+/// `deletion_mode` setting. Files that can't be disposed of stay in the
+/// trash and are listed in `failed`.
+#[tauri::command]
+fn empty_trash(db_state: tauri::State<'_, DbState>) -> Result<TrashPurgeSummary, String> {
+    let conn = db_conn(&db_state)?;
+    purge_trash(&conn, i64::MAX)
+}
+
+/// COMMAND: Get trashed photos with days until the automatic purge
+#[tauri::command]
+fn get_trashed_photos(db_state: tauri::State<'_, DbState>) -> Result<Vec<TrashedPhoto>, String> {
+    let trashed = with_db(&db_state, "Failed to get trashed photos", |c| db::get_trashed_photos(c))?;
+    let now = chrono::Utc::now().timestamp();
+
+    Ok(trashed
+        .into_iter()
+        .map(|(photo, trash_path, deleted_at)| {
+            let days_passed = (now - deleted_at) / (24 * 60 * 60);
+            TrashedPhoto {
+                photo,
+                trash_path,
+                deleted_at,
+                days_until_purge: (config::TRASH_RETENTION_DAYS - days_passed).max(0),
+            }
+        })
+        .collect())
+}
+
+// ============================================================================
+// TerraForm (Review Mode) Commands
+// ============================================================================
+
+/// COMMAND: Get all unreviewed photos for TerraForm
+#[tauri::command]
+fn get_unreviewed_photos(
+    db_state: tauri::State<'_, DbState>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get unreviewed photos", |c| db::get_unreviewed_photos(c))
+}
+
+/// COMMAND: Mark a photo as reviewed
+#[tauri::command]
+fn mark_photo_reviewed(db_state: tauri::State<'_, DbState>, path: String) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to mark photo reviewed", |c| db::mark_photo_reviewed(c, &path))
+}
+
+/// COMMAND: Get count of unreviewed photos
+#[tauri::command]
+fn get_unreviewed_count(db_state: tauri::State<'_, DbState>) -> Result<i64, String> {
+    with_db(&db_state, "Failed to get unreviewed count", |c| db::get_unreviewed_count(c))
+}
+
+/// COMMAND: Unmark a photo as reviewed (for undo)
+#[tauri::command]
+fn unmark_photo_reviewed(db_state: tauri::State<'_, DbState>, path: String) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to unmark photo reviewed", |c| db::unmark_photo_reviewed(c, &path))
+}
+
+// ============================================================================
+// Tag Commands
+// ============================================================================
+
+/// COMMAND: Create a new tag
+#[tauri::command]
+fn create_tag(
+    db_state: tauri::State<'_, DbState>,
+    name: String,
+    color: String,
+) -> Result<i64, String> {
+    with_db_write(&db_state, "Failed to create tag", |c| db::create_tag(c, &name, &color))
+}
+
+/// COMMAND: Update a tag
+#[tauri::command]
+fn update_tag(
+    db_state: tauri::State<'_, DbState>,
+    id: i64,
+    name: String,
+    color: String,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to update tag", |c| db::update_tag(c, id, &name, &color))
+}
+
+/// COMMAND: Delete a tag
+#[tauri::command]
+fn delete_tag(db_state: tauri::State<'_, DbState>, id: i64) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to delete tag", |c| db::delete_tag(c, id))
+}
+
+/// COMMAND: Rename a tag; fails if another tag already has the name
+#[tauri::command]
+fn rename_tag(db_state: tauri::State<'_, DbState>, id: i64, name: String) -> Result<(), String> {
+    let name = name.trim();
+    if name.is_empty() {
+        return Err("Tag name cannot be empty".to_string());
+    }
+    if with_db_write(&db_state, "Failed to rename tag", |c| db::rename_tag(c, id, name))? {
+        Ok(())
+    } else {
+        Err(format!("A tag named \"{}\" already exists", name))
+    }
+}
+
+/// COMMAND: Get all tags with photo counts (alias of get_all_tags)
+#[tauri::command]
+fn get_tags(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::Tag>, String> {
+    with_db(&db_state, "Failed to get tags", |c| db::get_all_tags(c))
+}
+
+/// COMMAND: Get all tags
+#[tauri::command]
+fn get_all_tags(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::Tag>, String> {
+    with_db(&db_state, "Failed to get tags", |c| db::get_all_tags(c))
+}
+
+/// COMMAND: Get tags for a specific photo
+#[tauri::command]
+fn get_tags_for_photo(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<Vec<db::Tag>, String> {
+    with_db(&db_state, "Failed to get tags for photo", |c| db::get_tags_for_photo(c, &path))
+}
+
+/// COMMAND: Add tags to photos
+#[tauri::command]
+fn add_tags_to_photos(
+    db_state: tauri::State<'_, DbState>,
+    tag_ids: Vec<i64>,
+    photo_paths: Vec<String>,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to add tags", |c| db::add_tags_to_photos(c, &tag_ids, &photo_paths))
+}
 
-            if similar_paths.len() > 1 {
-                let photos: Vec<PhotoMetadata> = similar_paths.iter()
-                    .filter_map(|p| photo_map.get(p).cloned())
-                    .collect();
+/// COMMAND: Remove a tag from a photo
+#[tauri::command]
+fn remove_tag_from_photo(
+    db_state: tauri::State<'_, DbState>,
+    tag_id: i64,
+    photo_path: String,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to remove tag", |c| db::remove_tag_from_photo(c, tag_id, &photo_path))
+}
 
-                if photos.len() > 1 {
-                    for path in &similar_paths {
-                        processed_paths.insert(path.clone());
-                    }
+/// COMMAND: Remove tags from photos (bulk)
+#[tauri::command]
+fn remove_tags_from_photos(
+    db_state: tauri::State<'_, DbState>,
+    tag_ids: Vec<i64>,
+    photo_paths: Vec<String>,
+) -> Result<(), String> {
+    with_db_write(&db_state, "Failed to remove tags", |c| db::remove_tags_from_photos(c, &tag_ids, &photo_paths))
+}
 
-                    // Calculate average similarity score
-                    let similarity = 1.0 - (effective_threshold as f32 / 64.0);
+/// COMMAND: Get photos carrying a single tag
+#[tauri::command]
+fn get_photos_by_tag(db_state: tauri::State<'_, DbState>, tag_id: i64) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get photos by tag", |c| {
+        let mut photos = db::get_photos_by_tags(c, &[tag_id], false)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-                    groups.push(DuplicateGroup {
-                        group_id,
-                        group_type: "similar".to_string(),
-                        photos,
-                        similarity_score: similarity,
-                    });
-                    group_id += 1;
-                }
-            }
-        }
-    }
+/// COMMAND: Get photos by tags
+#[tauri::command]
+fn get_photos_by_tags(
+    db_state: tauri::State<'_, DbState>,
+    tag_ids: Vec<i64>,
+    match_all: bool,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get photos by tags", |c| {
+        let mut photos = db::get_photos_by_tags(c, &tag_ids, match_all)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-    // Sort groups: exact duplicates first, then by number of photos
-    groups.sort_by(|a, b| {
-        if a.group_type != b.group_type {
-            a.group_type.cmp(&b.group_type) // "exact" comes before "similar"
-        } else {
-            b.photos.len().cmp(&a.photos.len()) // More photos first
-        }
-    });
+/// COMMAND: Search tags for autocomplete
+#[tauri::command]
+fn search_tags(db_state: tauri::State<'_, DbState>, query: String) -> Result<Vec<db::Tag>, String> {
+    with_db(&db_state, "Failed to search tags", |c| db::search_tags(c, &query))
+}
 
-    Ok(groups)
+/// COMMAND: Queue a job tagging photos with the on-device classifier.
+/// Predictions below `threshold` (default 0.3) are dropped. Photos the
+/// current model already looked at are skipped. Returns the job id.
+#[tauri::command]
+fn auto_tag_photos(
+    app: tauri::AppHandle,
+    job_queue: tauri::State<'_, jobs::JobQueue>,
+    threshold: Option<f32>,
+) -> Result<i64, String> {
+    let threshold = threshold.unwrap_or(auto_tag::DEFAULT_THRESHOLD);
+    if !(threshold > 0.0 && threshold <= 1.0) {
+        return Err("Threshold must be above 0 and at most 1".to_string());
+    }
+    job_queue.enqueue(&app, jobs::JobKind::AutoTag, serde_json::json!({ "threshold": threshold }))
 }
 
-/// COMMAND: Scan for screenshots
+/// COMMAND: Whether auto-tagging can run, where its model files go and how
+/// far the current model has got
 #[tauri::command]
-async fn scan_for_screenshots(window: tauri::Window) -> Result<Vec<PhotoMetadata>, String> {
-    let conn = db_conn()?;
+fn get_auto_tag_status(db_state: tauri::State<'_, DbState>) -> Result<auto_tag::AutoTagStatus, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(auto_tag::status(&conn))
+}
 
-    // Get all non-archived photos
-    let all_photos = db::get_all_photos(&conn)
-        .map_err(|e| format!("Failed to get photos: {}", e))?;
+/// COMMAND: Remove the auto-tagger's tags, from one tag or, without
+/// `tag_id`, all of them. Tags the user added stay. Returns how many were removed.
+#[tauri::command]
+fn remove_auto_tags(db_state: tauri::State<'_, DbState>, tag_id: Option<i64>) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to remove auto tags", |c| db::remove_auto_tags(c, tag_id))
+}
 
-    let total = all_photos.len() as u32;
-    let processed = Arc::new(AtomicU32::new(0));
+// ============================================================================
+// Faces and People
+// ============================================================================
 
-    // Emit initial progress
-    let _ = window.emit("screenshot_scan_progress", ScanProgress {
-        total,
-        processed: 0,
-        phase: "analyzing".to_string(),
-    });
+/// COMMAND: Queue a job finding faces with the on-device detector. Photos
+/// the current model already scanned are skipped. Returns the job id.
+#[tauri::command]
+fn detect_faces(app: tauri::AppHandle, job_queue: tauri::State<'_, jobs::JobQueue>) -> Result<i64, String> {
+    job_queue.enqueue(&app, jobs::JobKind::DetectFaces, serde_json::json!({}))
+}
 
-    // Check each photo for screenshot characteristics
-    let screenshots: Vec<PhotoMetadata> = all_photos
-        .into_iter()
-        .filter(|photo| {
-            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
-            if current % 50 == 0 || current == total {
-                let _ = window.emit("screenshot_scan_progress", ScanProgress {
-                    total,
-                    processed: current,
-                    phase: "analyzing".to_string(),
-                });
-            }
+/// COMMAND: Whether face detection can run and where its model goes
+#[tauri::command]
+fn get_face_detection_status() -> faces::FaceDetectionStatus {
+    faces::status()
+}
 
-            let is_screenshot = detect_screenshot(&photo.name, photo.width, photo.height);
+/// COMMAND: Faces found on a photo, with who they are when named
+#[tauri::command]
+fn get_faces(db_state: tauri::State<'_, DbState>, photo_path: String) -> Result<Vec<db::Face>, String> {
+    with_db(&db_state, "Failed to get faces", |c| db::get_faces(c, &photo_path))
+}
 
-            // Update database
-            if is_screenshot {
-                let _ = db::update_photo_screenshot_flag(&conn, &photo.path, true);
-            }
+/// COMMAND: Name a face as a person, or clear its name when `person_id` is null
+#[tauri::command]
+fn assign_face(db_state: tauri::State<'_, DbState>, face_id: i64, person_id: Option<i64>) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    if let Some(id) = person_id {
+        if !db::person_exists(&conn, id).map_err(|e| format!("Failed to assign face: {}", e))? {
+            return Err(format!("No person with id {}", id));
+        }
+    }
+    match db::assign_face(&conn, face_id, person_id) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(format!("No face with id {}", face_id)),
+        Err(e) => Err(format!("Failed to assign face: {}", e)),
+    }
+}
 
-            is_screenshot
-        })
-        .collect();
+/// COMMAND: Add a person, or find the one already called `name`. Returns their id.
+#[tauri::command]
+fn create_person(db_state: tauri::State<'_, DbState>, name: String) -> Result<i64, String> {
+    if name.trim().is_empty() {
+        return Err("A person needs a name".to_string());
+    }
+    with_db_write(&db_state, "Failed to create person", |c| db::create_person(c, &name))
+}
 
-    // Emit completion
-    let _ = window.emit("screenshot_scan_progress", ScanProgress {
-        total,
-        processed: total,
-        phase: "complete".to_string(),
-    });
+/// COMMAND: Everyone named on photos, with photo counts and a face to show
+#[tauri::command]
+fn get_people(db_state: tauri::State<'_, DbState>) -> Result<Vec<db::Person>, String> {
+    with_db(&db_state, "Failed to get people", db::get_people)
+}
 
-    Ok(screenshots)
+/// COMMAND: Group unnamed faces that look alike into suggested people,
+/// replacing the last run's clusters. Needs face embeddings, which the
+/// face job stores once the embedder model is installed. Named faces are
+/// never touched.
+#[tauri::command]
+async fn cluster_faces(db_state: tauri::State<'_, DbState>) -> Result<db::FaceClusters, String> {
+    let (face_ids, vectors): (Vec<i64>, Vec<Vec<f32>>) =
+        with_db(&db_state, "Failed to get faces", db::get_unnamed_face_embeddings)?.into_iter().unzip();
+    // Seconds of CPU work; keep it off the async runtime's threads
+    let clusters: Vec<Vec<i64>> = tauri::async_runtime::spawn_blocking(move || {
+        faces::cluster(&vectors)
+            .into_iter()
+            .map(|members| members.into_iter().map(|i| face_ids[i]).collect())
+            .collect()
+    })
+    .await
+    .map_err(|e| format!("Face clustering failed: {}", e))?;
+    with_db_write(&db_state, "Failed to save face clusters", |c| {
+        db::set_face_clusters(c, &clusters)?;
+        db::get_face_clusters(c, faces::CLUSTER_SAMPLES)
+    })
 }
 
-/// COMMAND: Get all detected screenshots
+/// COMMAND: The clusters from the last cluster_faces run, less faces named since
 #[tauri::command]
-fn get_screenshots() -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get screenshots", |c| db::get_screenshots(c))
+fn get_face_clusters(db_state: tauri::State<'_, DbState>) -> Result<db::FaceClusters, String> {
+    with_db(&db_state, "Failed to get face clusters", |c| db::get_face_clusters(c, faces::CLUSTER_SAMPLES))
 }
 
-/// COMMAND: Archive photos (move to archive folder, set archived_at)
+/// COMMAND: Name every face in a cluster as one person. Returns how many
+/// faces were named.
 #[tauri::command]
-fn archive_photos(paths: Vec<String>) -> Result<(), String> {
-    let conn = db_conn()?;
-    let archive_path = db::get_archive_path();
+fn assign_cluster_to_person(db_state: tauri::State<'_, DbState>, cluster_id: i64, person_id: i64) -> Result<usize, String> {
+    let conn = db_conn(&db_state)?;
+    if !db::person_exists(&conn, person_id).map_err(|e| format!("Failed to assign cluster: {}", e))? {
+        return Err(format!("No person with id {}", person_id));
+    }
+    db::assign_cluster_to_person(&conn, cluster_id, person_id).map_err(|e| format!("Failed to assign cluster: {}", e))
+}
 
-    for path_str in paths {
-        let source = Path::new(&path_str);
-        if !source.exists() {
-            warn!("File not found for archiving: {}", path_str);
-            continue;
-        }
+/// COMMAND: Photos a person is named on, newest first
+#[tauri::command]
+fn get_photos_by_person(db_state: tauri::State<'_, DbState>, person_id: i64) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get photos", |c| {
+        let mut photos = db::get_photos_by_person(c, person_id)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
+}
 
-        // Create relative path structure in archive
-        let library_path = db::get_library_path();
-        let relative_path = source.strip_prefix(&library_path)
-            .unwrap_or(Path::new(source.file_name().unwrap_or_default()));
+// ============================================================================
+// Culling
+// ============================================================================
 
-        let mut dest = archive_path.clone();
-        if let Some(parent) = relative_path.parent() {
-            dest.push(parent);
-            fs::create_dir_all(&dest).ok();
-        }
-        dest.push(relative_path.file_name().unwrap_or_default());
+/// COMMAND: Flag photos as picked or rejected; `none` clears the flag.
+/// Rejected photos leave the timeline. Returns how many were updated.
+#[tauri::command]
+fn set_flag(db_state: tauri::State<'_, DbState>, paths: Vec<String>, flag: db::PhotoFlag) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to set flags", |c| db::set_photos_flag(c, &paths, flag))
+}
 
-        // Move file to archive
-        match fs::rename(&source, &dest) {
-            Ok(_) => {
-                // Update database with new path and archived_at timestamp
-                let canonical_dest = dest.canonicalize()
-                    .unwrap_or(dest.clone())
-                    .to_string_lossy()
-                    .to_string();
+/// COMMAND: Unflagged photos matching `filter`, oldest first, for stepping
+/// through a shoot in review mode
+#[tauri::command]
+fn get_cull_queue(
+    db_state: tauri::State<'_, DbState>,
+    filter: Option<db::PhotoQuery>,
+) -> Result<Vec<PhotoMetadata>, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get cull queue", |c| db::get_cull_queue(c, &filter))
+}
 
-                // First update the path in the database
-                let _ = conn.execute(
-                    "UPDATE photos SET path = ?1 WHERE path = ?2",
-                    rusqlite::params![canonical_dest, path_str],
-                );
+/// COMMAND: Picked, rejected and unflagged counts of the photos matching `filter`
+#[tauri::command]
+fn get_cull_progress(
+    db_state: tauri::State<'_, DbState>,
+    filter: Option<db::PhotoQuery>,
+) -> Result<db::CullProgress, String> {
+    let filter = filter.unwrap_or_default();
+    with_db(&db_state, "Failed to get cull progress", |c| db::get_cull_progress(c, &filter))
+}
 
-                // Then set archived_at
-                db::archive_photo(&conn, &canonical_dest)
-                    .map_err(|e| format!("Failed to archive in DB: {}", e))?;
+#[derive(Serialize, Debug)]
+pub struct CullSummary {
+    /// Rejected photos outside the trash when the command ran
+    pub rejected: usize,
+    pub trashed: usize,
+}
 
-                debug!("Archived: {} -> {}", path_str, canonical_dest);
-            }
-            Err(e) => {
-                error!("Failed to move file to archive: {}", e);
-            }
-        }
+/// COMMAND: Move every rejected photo to the trash as one undoable
+/// operation. With `delete_rejected` false nothing changes and only the
+/// count comes back, for the confirmation prompt.
+#[tauri::command]
+fn apply_cull(db_state: tauri::State<'_, DbState>, delete_rejected: bool) -> Result<CullSummary, String> {
+    let conn = db_conn(&db_state)?;
+    let rejected = db::get_rejected_paths(&conn).map_err(|e| format!("Failed to get rejected photos: {}", e))?;
+    if !delete_rejected {
+        return Ok(CullSummary { rejected: rejected.len(), trashed: 0 });
     }
+    let trashed = trash_photos(&conn, &rejected)?;
+    log_trashing(&conn, &trashed);
+    info!("Moved {} of {} rejected photos to the trash", trashed.len(), rejected.len());
+    Ok(CullSummary { rejected: rejected.len(), trashed: trashed.len() })
+}
 
-    Ok(())
+// ============================================================================
+// Stacks
+// ============================================================================
+
+/// COMMAND: Every member of a stack, primary first, for expanding it in the grid
+#[tauri::command]
+fn get_stack_members(db_state: tauri::State<'_, DbState>, stack_id: i64) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get stack members", |c| {
+        let mut photos = db::get_stack_members(c, stack_id)?;
+        db::attach_tags(c, &mut photos)?;
+        Ok(photos)
+    })
 }
 
-/// COMMAND: Restore photos from archive
+/// COMMAND: Stack photos by hand, taking them out of any stacks they were
+/// in. `primary` is the one the grid shows, else the first path. Returns
+/// the new stack's id.
 #[tauri::command]
-fn restore_photos(paths: Vec<String>) -> Result<(), String> {
-    let conn = db_conn()?;
-    let library_path = db::get_library_path();
-    let archive_path = db::get_archive_path();
+fn stack_photos(db_state: tauri::State<'_, DbState>, paths: Vec<String>, primary: Option<String>) -> Result<i64, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::stack_photos(&conn, &paths, primary.as_deref())?)
+}
 
-    for path_str in paths {
-        let source = Path::new(&path_str);
-        if !source.exists() {
-            warn!("File not found for restoration: {}", path_str);
-            continue;
-        }
+/// COMMAND: Take photos out of their stacks; scans won't stack them again.
+/// Returns how many were unstacked.
+#[tauri::command]
+fn unstack_photos(db_state: tauri::State<'_, DbState>, paths: Vec<String>) -> Result<usize, String> {
+    with_db_write(&db_state, "Failed to unstack photos", |c| db::unstack_photos(c, &paths))
+}
 
-        // Restore to original location in library
-        let relative_path = source.strip_prefix(&archive_path)
-            .unwrap_or(Path::new(source.file_name().unwrap_or_default()));
+/// COMMAND: Choose which member the grid shows for a stack
+#[tauri::command]
+fn set_stack_primary(db_state: tauri::State<'_, DbState>, stack_id: i64, path: String) -> Result<(), String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::set_stack_primary(&conn, stack_id, &path)?)
+}
 
-        let mut dest = library_path.clone();
-        if let Some(parent) = relative_path.parent() {
-            dest.push(parent);
-            fs::create_dir_all(&dest).ok();
-        }
-        dest.push(relative_path.file_name().unwrap_or_default());
+// ============================================================================
+// Undo Log
+// ============================================================================
 
-        // Move file back to library
-        match fs::rename(&source, &dest) {
-            Ok(_) => {
-                let canonical_dest = dest.canonicalize()
-                    .unwrap_or(dest.clone())
-                    .to_string_lossy()
-                    .to_string();
+/// Record an operation for `undo_last_operation`. The change itself has
+/// already happened, so failing to log it is only a warning.
+fn log_operation(conn: &rusqlite::Connection, operation: &Operation) {
+    if let Err(e) = db::record_operation(conn, operation) {
+        warn!("Failed to log {} for undo: {}", operation.kind(), e);
+    }
+}
 
-                // Update path in database
-                let _ = conn.execute(
-                    "UPDATE photos SET path = ?1 WHERE path = ?2",
-                    rusqlite::params![canonical_dest, path_str],
-                );
+/// Why an operation couldn't be undone.
+enum UndoError {
+    /// What it touched has changed since, so it never can be
+    Changed(String),
+    /// Something went wrong this time; it stays in the log
+    Failed(String),
+}
 
-                // Clear archived_at
-                db::restore_photo(&conn, &canonical_dest)
-                    .map_err(|e| format!("Failed to restore in DB: {}", e))?;
+/// Current paths (and trashed flags) of `ids`, or an error if any is gone.
+fn live_photo_paths(conn: &rusqlite::Connection, ids: &[i64]) -> Result<Vec<(String, bool)>, UndoError> {
+    let found = db::get_photo_paths_by_id(conn, ids)
+        .map_err(|e| UndoError::Failed(format!("Failed to read photos: {}", e)))?;
+    let missing = found.iter().filter(|p| p.is_none()).count();
+    match missing {
+        0 => Ok(found.into_iter().flatten().collect()),
+        _ if ids.len() == 1 => Err(UndoError::Changed("Can't undo: the photo was permanently deleted".to_string())),
+        n => Err(UndoError::Changed(format!("Can't undo: {} of the {} photos were permanently deleted", n, ids.len()))),
+    }
+}
 
-                debug!("Restored: {} -> {}", path_str, canonical_dest);
+/// Reverse `operation`. Checks that everything it touched is still as it
+/// left it before changing anything.
+fn undo_operation(conn: &rusqlite::Connection, operation: &Operation) -> Result<(), UndoError> {
+    match operation {
+        Operation::MoveToTrash { photo_ids } => {
+            let photos = live_photo_paths(conn, photo_ids)?;
+            if photos.iter().any(|(_, trashed)| !trashed) {
+                return Err(UndoError::Changed("Can't undo: some of the photos were already restored".to_string()));
             }
-            Err(e) => {
-                error!("Failed to restore file: {}", e);
+            let paths: Vec<String> = photos.into_iter().map(|(path, _)| path).collect();
+            let restored = restore_trashed(conn, &paths).map_err(UndoError::Failed)?;
+            if (restored as usize) < paths.len() {
+                // The rest can't be found again by a second undo
+                return Err(UndoError::Changed(format!("Restored {} of {} photos; see the log", restored, paths.len())));
+            }
+        }
+        Operation::RemoveFromAlbum { album_id, members } => {
+            let ids: Vec<i64> = members.iter().map(|&(id, _)| id).collect();
+            live_photo_paths(conn, &ids)?;
+            let restored = db::restore_album_members(conn, *album_id, members)
+                .map_err(|e| UndoError::Failed(format!("Failed to restore album: {}", e)))?;
+            if !restored {
+                return Err(UndoError::Changed("Can't undo: the album was deleted".to_string()));
+            }
+        }
+        Operation::SetFavorites { previous, is_favorite } => {
+            let ids: Vec<i64> = previous.iter().map(|&(id, _)| id).collect();
+            live_photo_paths(conn, &ids)?;
+            let restored = db::restore_favorite_flags(conn, previous, *is_favorite)
+                .map_err(|e| UndoError::Failed(format!("Failed to restore favorites: {}", e)))?;
+            if !restored {
+                return Err(UndoError::Changed("Can't undo: favorites have changed since".to_string()));
+            }
+        }
+        Operation::SetPhotoDate { previous, new_date_taken, moved } => {
+            let (path, _) = live_photo_paths(conn, &[previous.photo_id])?.remove(0);
+            let restored = db::restore_previous_date(conn, previous, *new_date_taken)
+                .map_err(|e| UndoError::Failed(format!("Failed to restore date: {}", e)))?;
+            if !restored {
+                return Err(UndoError::Changed("Can't undo: the photo's date has changed since".to_string()));
+            }
+            // Refiled back only if it's still where the date change put it
+            if let Some(moved) = moved.as_ref().filter(|m| m.to == path) {
+                let dest = library::unique_destination(PathBuf::from(&moved.from));
+                if let Err(e) = refile_photo(conn, &path, &dest) {
+                    warn!("{}", e);
+                }
+            }
+        }
+        Operation::ResolveDuplicates { resolution_ids } => {
+            let discarded = resolution_ids
+                .iter()
+                .map(|&id| discarded_duplicates(conn, id).map(|paths| (id, paths)))
+                .collect::<Result<Vec<_>, String>>()
+                .map_err(UndoError::Changed)?;
+            for (id, paths) in discarded.iter().rev() {
+                revert_duplicate_resolution(conn, *id, paths).map_err(UndoError::Failed)?;
+            }
+        }
+        Operation::DateShift { shift_id, .. } => {
+            let exists = db::get_date_shift_moves(conn, *shift_id)
+                .map_err(|e| UndoError::Failed(format!("Failed to read date shift: {}", e)))?
+                .is_some();
+            if !exists {
+                return Err(UndoError::Changed("Can't undo: the date shift was already undone".to_string()));
+            }
+            revert_date_shift(conn, *shift_id).map_err(UndoError::Failed)?;
+        }
+        Operation::BatchRename { renames } => {
+            let ids: Vec<i64> = renames.iter().map(|(id, _)| *id).collect();
+            let current = live_photo_paths(conn, &ids)?;
+            // Only photos still under the name the batch gave them go back
+            let returning: Vec<(&operations::FileMove, String)> = renames
+                .iter()
+                .zip(current)
+                .filter(|((_, moved), (path, _))| *path == moved.to)
+                .map(|((_, moved), (path, _))| (moved, path))
+                .collect();
+            // Names they leave are free, as rename_files_and_rows moves them all aside first
+            let vacated: std::collections::HashSet<PathBuf> = returning.iter().map(|(_, path)| PathBuf::from(path)).collect();
+            let mut claimed: std::collections::HashSet<PathBuf> = std::collections::HashSet::new();
+            let reverts: Vec<(String, String)> = returning
+                .into_iter()
+                .map(|(moved, path)| {
+                    let dest = library::unique_destination_avoiding(PathBuf::from(&moved.from), |p| {
+                        claimed.contains(p)
+                            || (!vacated.contains(p)
+                                && (p.exists() || db::photo_exists(conn, &p.to_string_lossy()).unwrap_or(false)))
+                    });
+                    claimed.insert(dest.clone());
+                    (path, dest.to_string_lossy().to_string())
+                })
+                .collect();
+            if reverts.is_empty() {
+                return Err(UndoError::Changed("Can't undo: the photos have been renamed again".to_string()));
             }
+            rename_files_and_rows(conn, &reverts).map_err(UndoError::Failed)?;
         }
     }
-
     Ok(())
 }
 
-/// COMMAND: Get archived photos with days until deletion
+/// COMMAND: List recent operations `undo_last_operation` can reverse,
+/// newest first (20 unless `limit` says otherwise).
 #[tauri::command]
-fn get_archived_photos() -> Result<Vec<ArchivedPhoto>, String> {
-    let conn = db_conn()?;
-    let archived = db::get_archived_photos(&conn)
-        .map_err(|e| format!("Failed to get archived photos: {}", e))?;
+fn get_recent_operations(
+    db_state: tauri::State<'_, DbState>,
+    limit: Option<i64>,
+) -> Result<Vec<db::OperationSummary>, String> {
+    with_db(&db_state, "Failed to read the undo history", |c| db::get_recent_operations(c, limit.unwrap_or(20)))
+}
 
-    let now = chrono::Utc::now().timestamp();
+/// COMMAND: Undo the newest logged operation: moving to the trash, removing
+/// from an album, setting favorites or a date, resolving duplicates,
+/// shifting dates or a batch rename. Returns the operation undone. If what it touched has
+/// changed since (a photo permanently deleted, an album deleted, a date set
+/// again), errors saying so and drops it from the log, so the next undo
+/// reaches the one before.
+#[tauri::command]
+fn undo_last_operation(db_state: tauri::State<'_, DbState>) -> Result<db::OperationSummary, String> {
+    let conn = db_conn(&db_state)?;
+    let Some((summary, operation)) =
+        db::get_last_operation(&conn).map_err(|e| format!("Failed to read the undo history: {}", e))?
+    else {
+        return Err("Nothing to undo".to_string());
+    };
+    let undone = match &operation {
+        Some(operation) => undo_operation(&conn, operation),
+        None => Err(UndoError::Changed("Can't undo: the operation was recorded by a newer version of Terra".to_string())),
+    };
+    match undone {
+        Ok(()) => {
+            db::delete_operation(&conn, summary.id).map_err(|e| format!("Failed to update the undo history: {}", e))?;
+            info!("Undid operation {}: {}", summary.id, summary.description);
+            Ok(summary)
+        }
+        Err(UndoError::Changed(message)) => {
+            warn!("Dropping operation {} from the undo log: {}", summary.id, message);
+            let _ = db::delete_operation(&conn, summary.id);
+            Err(message)
+        }
+        Err(UndoError::Failed(message)) => Err(message),
+    }
+}
 
-    let result: Vec<ArchivedPhoto> = archived
-        .into_iter()
-        .map(|(photo, archived_at)| {
-            let days_passed = (now - archived_at) / (24 * 60 * 60);
-            let days_until_deletion = (config::ARCHIVE_DELETION_DAYS - days_passed).max(0);
+// ============================================================================
+// Settings Commands
+// ============================================================================
 
-            ArchivedPhoto {
-                photo,
-                archived_at,
-                days_until_deletion,
-            }
-        })
-        .collect();
+/// COMMAND: Get the current library path.
+/// Errors if the configured folder is unavailable (e.g. drive not mounted).
+#[tauri::command]
+fn get_library_path(db_state: tauri::State<'_, DbState>) -> Result<String, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::get_library_path(&conn)?.to_string_lossy().to_string())
+}
 
-    Ok(result)
+/// COMMAND: Get the current library path (older name for get_library_path)
+#[tauri::command]
+fn get_library_path_command(db_state: tauri::State<'_, DbState>) -> Result<String, String> {
+    get_library_path(db_state)
 }
 
-/// COMMAND: Clean up old archived photos (older than configured days)
+/// COMMAND: Set the library path for new imports.
+/// The folder must exist and be writable. Photos already in the old library
+/// keep their paths and are still treated as library files.
 #[tauri::command]
-fn cleanup_old_archives() -> Result<u32, String> {
-    let conn = db_conn()?;
+fn set_library_path(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<String, String> {
+    let root = library::validate_library_root(Path::new(&path))?;
+    with_db_write(&db_state, "Failed to set library path", |c| db::set_library_path(c, &root))?;
+    info!("Library path set to {}", root.display());
+    watcher::sync(&app);
+    Ok(root.to_string_lossy().to_string())
+}
 
-    let old_paths = db::get_old_archived_photos(&conn, config::ARCHIVE_DELETION_DAYS)
-        .map_err(|e| format!("Failed to get old archives: {}", e))?;
+/// Payload of `relocation_progress` events. Bytes count this run only.
+#[derive(Serialize, Clone)]
+pub struct RelocationProgress {
+    /// "copying", then "cleaning_up" once the library has switched over
+    pub phase: String,
+    pub files_done: u32,
+    pub total_files: u32,
+    pub bytes_moved: u64,
+    pub total_bytes: u64,
+    pub current_file: String,
+}
 
-    let mut deleted_count: u32 = 0;
+#[derive(Serialize, Default)]
+pub struct RelocationSummary {
+    pub new_root: String,
+    /// Files copied by this run
+    pub files: u32,
+    pub bytes: u64,
+    /// Photo rows now pointing into the new root
+    pub photos_updated: u32,
+    /// An interrupted relocation was picked up where it stopped
+    pub resumed: bool,
+}
 
-    for path_str in old_paths {
-        let path = Path::new(&path_str);
+/// Every regular file under the relocation's source and where it goes.
+/// Symlinks stay behind.
+fn relocation_files(relocation: &db::Relocation) -> Vec<db::RelocationFile> {
+    WalkDir::new(&relocation.from)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter_map(|e| {
+            let relative = e.path().strip_prefix(&relocation.from).ok()?;
+            Some(db::RelocationFile {
+                source: e.path().to_string_lossy().to_string(),
+                dest: relocation.to.join(relative).to_string_lossy().to_string(),
+                size: e.metadata().ok()?.len(),
+            })
+        })
+        .collect()
+}
 
-        // Delete file from filesystem
-        if path.exists() {
-            if let Err(e) = fs::remove_file(path) {
-                error!("Failed to delete file {}: {}", path_str, e);
-                continue;
+/// COMMAND: Move the whole managed library to `new_root` (e.g. a bigger drive)
+/// and make it the library path, keeping the YYYY/MM layout. Every file is
+/// copied and verified first; then stored paths switch over in one
+/// transaction, and only then are the originals deleted. If interrupted, call
+/// it again with the same folder to continue. Refuses to start unless the
+/// destination is empty and has room, or while a scan or import is running.
+/// It counts as one itself, so auto scans wait, and the watcher drops changes
+/// until it's done. Emits `relocation_progress` events.
+#[tauri::command]
+async fn relocate_library(
+    app: tauri::AppHandle,
+    window: tauri::Window,
+    scan_state: tauri::State<'_, ScanState>,
+    db_state: tauri::State<'_, DbState>,
+    new_root: String,
+) -> Result<RelocationSummary, String> {
+    let to = library::validate_library_root(Path::new(&new_root))?;
+    let Some(_active) = scan_state.try_begin() else {
+        return Err("A scan or import is running; try again when it finishes".to_string());
+    };
+    let _paused = app.state::<watcher::WatcherState>().inner().pause();
+    let unfinished = db::get_relocation(&*db_conn(&db_state)?);
+    let mut summary = RelocationSummary {
+        new_root: to.to_string_lossy().to_string(),
+        resumed: unfinished.is_some(),
+        ..Default::default()
+    };
+    let relocation = match unfinished {
+        Some(relocation) if relocation.to == to => relocation,
+        Some(relocation) => {
+            return Err(format!(
+                "Moving the library to {} isn't finished; run it again with that folder first",
+                relocation.to.display()
+            ))
+        }
+        None => {
+            let from = library::canonical_path(&db::get_library_path(&*db_conn(&db_state)?)?)
+                .map(PathBuf::from)
+                .map_err(|e| format!("Failed to resolve the library folder: {}", e))?;
+            if to.starts_with(&from) || from.starts_with(&to) {
+                return Err(format!("{} overlaps the current library {}", to.display(), from.display()));
+            }
+            // Hidden entries are volume bookkeeping (.Spotlight-V100, .fseventsd)
+            let occupied = fs::read_dir(&to)
+                .map_err(|e| format!("Cannot read {}: {}", to.display(), e))?
+                .filter_map(|e| e.ok())
+                .any(|e| !e.file_name().to_string_lossy().starts_with('.'));
+            if occupied {
+                return Err(format!("{} isn't empty; choose an empty folder", to.display()));
             }
+            db::Relocation { from, to }
         }
+    };
+    info!("Relocating library {} -> {}", relocation.from.display(), relocation.to.display());
+
+    let switched = db::get_library_path(&*db_conn(&db_state)?).is_ok_and(|root| root == relocation.to);
+    if !switched {
+        // Walk again after each pass: files imported meanwhile are picked up
+        // instead of being left behind when the paths switch
+        loop {
+            let pending = {
+                let conn = db_conn(&db_state)?;
+                db::track_relocation(&conn, &relocation, &relocation_files(&relocation))
+                    .map_err(|e| format!("Failed to record the files to move: {}", e))?;
+                db::get_pending_relocation_files(&conn)
+                    .map_err(|e| format!("Failed to load the files to move: {}", e))?
+            };
+            if pending.is_empty() {
+                break;
+            }
 
-        // Delete from database
-        if let Err(e) = db::permanently_delete_photo(&conn, &path_str) {
-            error!("Failed to delete from DB {}: {}", path_str, e);
-            continue;
+            let total_bytes: u64 = pending.iter().map(|f| f.size).sum();
+            let available = fs2::available_space(&relocation.to)
+                .map_err(|e| format!("Cannot read free space on {}: {}", relocation.to.display(), e))?;
+            if total_bytes > available {
+                return Err(format!(
+                    "Not enough free space on {}: {} bytes needed, {} available",
+                    relocation.to.display(),
+                    total_bytes,
+                    available
+                ));
+            }
+
+            let total_files = pending.len() as u32;
+            let (mut files_done, mut bytes_moved) = (0u32, 0u64);
+            for file in &pending {
+                let source = Path::new(&file.source);
+                let dest = Path::new(&file.dest);
+                if !source.exists() {
+                    db::forget_relocation_file(&*db_conn(&db_state)?, &file.source)
+                        .map_err(|e| format!("Failed to update relocation progress: {}", e))?;
+                    continue;
+                }
+                if let Some(parent) = dest.parent() {
+                    fs::create_dir_all(parent).map_err(|e| format!("Failed to create {}: {}", parent.display(), e))?;
+                }
+                let hash = media::calculate_hash(source);
+                let bytes = library::copy_verified(source, dest, hash.as_deref())
+                    .map_err(|e| format!("Failed to copy {}: {}", file.source, e))?;
+                db::mark_relocation_copied(&*db_conn(&db_state)?, &file.source)
+                    .map_err(|e| format!("Failed to update relocation progress: {}", e))?;
+
+                files_done += 1;
+                bytes_moved += bytes;
+                summary.files += 1;
+                summary.bytes += bytes;
+                let _ = window.emit("relocation_progress", RelocationProgress {
+                    phase: "copying".to_string(),
+                    files_done,
+                    total_files,
+                    bytes_moved,
+                    total_bytes,
+                    current_file: file.source.clone(),
+                });
+            }
         }
 
-        debug!("Permanently deleted: {}", path_str);
-        deleted_count += 1;
+        summary.photos_updated = db::switch_library_root(&*db_conn(&db_state)?, &relocation)
+            .map_err(|e| format!("Failed to switch the library over: {}", e))? as u32;
+        info!("Library now at {}; {} photos updated", relocation.to.display(), summary.photos_updated);
+        watcher::sync(&app);
     }
 
-    Ok(deleted_count)
-}
-
-// ============================================================================
-// TerraForm (Review Mode) Commands
-// ============================================================================
+    let sources = db::get_copied_relocation_sources(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to load moved files: {}", e))?;
+    let total_files = sources.len() as u32;
+    for (i, source) in sources.iter().enumerate() {
+        match fs::remove_file(source) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => warn!("Couldn't remove {} after moving it: {}", source, e),
+        }
+        let files_done = i as u32 + 1;
+        if files_done % 25 == 0 || files_done == total_files {
+            let _ = window.emit("relocation_progress", RelocationProgress {
+                phase: "cleaning_up".to_string(),
+                files_done,
+                total_files,
+                bytes_moved: summary.bytes,
+                total_bytes: summary.bytes,
+                current_file: source.clone(),
+            });
+        }
+    }
+    // Remove the emptied YYYY/MM folders, but not the old root itself
+    for entry in WalkDir::new(&relocation.from).min_depth(1).contents_first(true).into_iter().filter_map(|e| e.ok()) {
+        if entry.file_type().is_dir() {
+            let _ = fs::remove_dir(entry.path());
+        }
+    }
+    db::finish_relocation(&*db_conn(&db_state)?).map_err(|e| format!("Failed to finish relocation: {}", e))?;
 
-/// COMMAND: Get all unreviewed photos for TerraForm
-#[tauri::command]
-fn get_unreviewed_photos() -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get unreviewed photos", |c| db::get_unreviewed_photos(c))
+    info!("Relocated {} files ({} bytes) to {}", summary.files, summary.bytes, summary.new_root);
+    Ok(summary)
 }
 
-/// COMMAND: Mark a photo as reviewed
+/// COMMAND: Folders watched for new photos in addition to the library
 #[tauri::command]
-fn mark_photo_reviewed(path: String) -> Result<(), String> {
-    with_db("Failed to mark photo reviewed", |c| db::mark_photo_reviewed(c, &path))
+fn get_watch_folders(db_state: tauri::State<'_, DbState>) -> Result<Vec<String>, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::get_watch_folders(&conn)
+        .iter()
+        .map(|p| p.to_string_lossy().to_string())
+        .collect())
 }
 
-/// COMMAND: Get count of unreviewed photos
+/// COMMAND: Watch a folder so files added there are imported automatically.
+/// Returns the canonical path stored.
 #[tauri::command]
-fn get_unreviewed_count() -> Result<i64, String> {
-    with_db("Failed to get unreviewed count", |c| db::get_unreviewed_count(c))
+fn add_watch_folder(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<String, String> {
+    let folder = library::canonical_path(Path::new(&path))
+        .map(PathBuf::from)
+        .map_err(|e| format!("Cannot access {}: {}", path, e))?;
+    if !folder.is_dir() {
+        return Err(format!("{} is not a folder", folder.display()));
+    }
+    with_db_write(&db_state, "Failed to add watch folder", |c| db::add_watch_folder(c, &folder))?;
+    watcher::sync(&app);
+    Ok(folder.to_string_lossy().to_string())
 }
 
-/// COMMAND: Unmark a photo as reviewed (for undo)
+/// COMMAND: Stop watching a folder. Its photos stay in the library.
+/// Returns false if the folder wasn't being watched.
 #[tauri::command]
-fn unmark_photo_reviewed(path: String) -> Result<(), String> {
-    with_db("Failed to unmark photo reviewed", |c| db::unmark_photo_reviewed(c, &path))
+fn remove_watch_folder(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<bool, String> {
+    // Not canonicalized: the folder may be on a drive that's unplugged
+    let removed = with_db_write(&db_state, "Failed to remove watch folder", |c| {
+        db::remove_watch_folder(c, Path::new(&path))
+    })?;
+    watcher::sync(&app);
+    Ok(removed)
 }
 
-// ============================================================================
-// Tag Commands
-// ============================================================================
-
-/// COMMAND: Create a new tag
+/// COMMAND: Get a setting value as stored (raw JSON text)
 #[tauri::command]
-fn create_tag(name: String, color: String) -> Result<i64, String> {
-    with_db("Failed to create tag", |c| db::create_tag(c, &name, &color))
+fn get_setting_command(
+    db_state: tauri::State<'_, DbState>,
+    key: String,
+) -> Result<Option<String>, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::get_setting(&conn, &key))
 }
 
-/// COMMAND: Update a tag
+/// COMMAND: Get a setting value, or null if it has never been set
 #[tauri::command]
-fn update_tag(id: i64, name: String, color: String) -> Result<(), String> {
-    with_db("Failed to update tag", |c| db::update_tag(c, id, &name, &color))
+fn get_setting(
+    db_state: tauri::State<'_, DbState>,
+    key: String,
+) -> Result<Option<serde_json::Value>, String> {
+    let conn = db_conn(&db_state)?;
+    Ok(db::get_setting(&conn, &key).map(|raw| db::decode_setting(&raw)))
 }
 
-/// COMMAND: Delete a tag
+/// COMMAND: Store a setting; any JSON value (bool, number, string, list) round-trips
 #[tauri::command]
-fn delete_tag(id: i64) -> Result<(), String> {
-    with_db("Failed to delete tag", |c| db::delete_tag(c, id))
+fn set_setting(
+    db_state: tauri::State<'_, DbState>,
+    key: String,
+    value: serde_json::Value,
+) -> Result<(), String> {
+    if key == library::DELETION_MODE_SETTING {
+        value.as_str().unwrap_or_default().parse::<library::DeletionMode>()?;
+    }
+    if key == exclusions::SCAN_EXCLUSIONS_SETTING {
+        serde_json::from_value::<exclusions::ScanExclusions>(value.clone())
+            .map_err(|e| format!("Invalid scan exclusions: {}", e))?
+            .compile()?;
+    }
+    if key == logging::LOG_LEVEL_SETTING {
+        logging::parse_level(value.as_str().unwrap_or_default())?;
+    }
+    if key == auto_scan::AUTO_SCAN_SETTING {
+        serde_json::from_value::<auto_scan::AutoScanSettings>(value.clone())
+            .map_err(|e| format!("Invalid auto scan settings: {}", e))?
+            .validate()?;
+    }
+    with_db_write(&db_state, "Failed to save setting", |c| db::set_setting(c, &key, &value.to_string()))?;
+    if key == DEFAULT_UTC_OFFSET_SETTING {
+        media::set_default_utc_offset(default_utc_offset(Some(value.to_string())));
+    }
+    if key == logging::LOG_LEVEL_SETTING {
+        logging::apply_level(value.as_str());
+        info!("Log level set to {}", log::max_level());
+    }
+    Ok(())
 }
 
-/// COMMAND: Get all tags
+/// COMMAND: Where the log file is, for users attaching it to a bug report
 #[tauri::command]
-fn get_all_tags() -> Result<Vec<db::Tag>, String> {
-    with_db("Failed to get tags", |c| db::get_all_tags(c))
+fn get_log_file_path() -> Result<String, String> {
+    Ok(logging::log_file_path().to_string_lossy().to_string())
 }
 
-/// COMMAND: Get tags for a specific photo
+/// COMMAND: The last `lines` log lines (200 by default, at most
+/// logging::MAX_RECENT_LINES), oldest first, for "Copy diagnostics".
 #[tauri::command]
-fn get_tags_for_photo(path: String) -> Result<Vec<db::Tag>, String> {
-    with_db("Failed to get tags for photo", |c| db::get_tags_for_photo(c, &path))
+fn get_recent_logs(lines: Option<usize>) -> Result<Vec<String>, String> {
+    let count = lines.unwrap_or(200).min(logging::MAX_RECENT_LINES);
+    logging::recent_lines(&logging::log_file_path(), count).map_err(|e| format!("Failed to read log file: {}", e))
 }
 
-/// COMMAND: Add tags to photos
+/// COMMAND: What folder scans skip: hidden entries, the user's glob
+/// patterns and files under a size in bytes. Folders in
+/// exclusions::BUILTIN_EXCLUDED_DIRS and app bundles are always skipped.
 #[tauri::command]
-fn add_tags_to_photos(tag_ids: Vec<i64>, photo_paths: Vec<String>) -> Result<(), String> {
-    with_db("Failed to add tags", |c| db::add_tags_to_photos(c, &tag_ids, &photo_paths))
+fn get_scan_exclusions(db_state: tauri::State<'_, DbState>) -> Result<exclusions::ScanExclusions, String> {
+    Ok(scan_exclusions(&*db_conn(&db_state)?))
 }
 
-/// COMMAND: Remove a tag from a photo
+/// COMMAND: Replace the scan exclusions. Rejects patterns that aren't valid
+/// globs. Applies to the next scan; files already imported stay.
 #[tauri::command]
-fn remove_tag_from_photo(tag_id: i64, photo_path: String) -> Result<(), String> {
-    with_db("Failed to remove tag", |c| db::remove_tag_from_photo(c, tag_id, &photo_path))
+fn set_scan_exclusions(
+    db_state: tauri::State<'_, DbState>,
+    settings: exclusions::ScanExclusions,
+) -> Result<(), String> {
+    settings.compile()?;
+    let value = serde_json::to_string(&settings).map_err(|e| format!("Failed to save scan exclusions: {}", e))?;
+    with_db_write(&db_state, "Failed to save scan exclusions", |c| {
+        db::set_setting(c, exclusions::SCAN_EXCLUSIONS_SETTING, &value)
+    })
 }
 
-/// COMMAND: Get photos by tags
+/// COMMAND: Turn scheduled rescans of the watch folders on or off and set
+/// how many minutes apart they run. The next run is an interval from now.
 #[tauri::command]
-fn get_photos_by_tags(tag_ids: Vec<i64>, match_all: bool) -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get photos by tags", |c| db::get_photos_by_tags(c, &tag_ids, match_all))
+fn set_auto_scan(
+    db_state: tauri::State<'_, DbState>,
+    auto_scan_state: tauri::State<'_, auto_scan::AutoScanState>,
+    enabled: bool,
+    interval_minutes: u32,
+) -> Result<auto_scan::AutoScanStatus, String> {
+    let settings = auto_scan::AutoScanSettings { enabled, interval_minutes };
+    settings.validate()?;
+    let value = serde_json::to_string(&settings).map_err(|e| format!("Failed to save auto scan settings: {}", e))?;
+    with_db_write(&db_state, "Failed to save auto scan settings", |c| {
+        db::set_setting(c, auto_scan::AUTO_SCAN_SETTING, &value)
+    })?;
+    auto_scan_state.reschedule(&settings);
+    Ok(auto_scan_state.status(&settings))
 }
 
-/// COMMAND: Search tags for autocomplete
+/// COMMAND: Whether scheduled scans are on, when the last ran and the next
+/// will, and what the last one found (including folders it couldn't scan).
 #[tauri::command]
-fn search_tags(query: String) -> Result<Vec<db::Tag>, String> {
-    with_db("Failed to search tags", |c| db::search_tags(c, &query))
+fn get_auto_scan_status(
+    db_state: tauri::State<'_, DbState>,
+    auto_scan_state: tauri::State<'_, auto_scan::AutoScanState>,
+) -> Result<auto_scan::AutoScanStatus, String> {
+    let settings = auto_scan::load_settings(&*db_conn(&db_state)?);
+    Ok(auto_scan_state.status(&settings))
 }
 
-// ============================================================================
-// Settings Commands
-// ============================================================================
-
-/// COMMAND: Get the current library path
-#[tauri::command]
-fn get_library_path_command() -> Result<String, String> {
-    Ok(db::get_library_path().to_string_lossy().to_string())
-}
+/// Setting holding the UTC offset, in minutes, assumed for EXIF times that
+/// don't record one. Unset or null means the machine's timezone.
+const DEFAULT_UTC_OFFSET_SETTING: &str = "default_utc_offset_minutes";
 
-/// COMMAND: Set the library path
-#[tauri::command]
-fn set_library_path(path: String) -> Result<(), String> {
-    with_db("Failed to set library path", |c| db::set_setting(c, "library_path", &path))
+/// Decode a stored default_utc_offset_minutes value.
+fn default_utc_offset(raw: Option<String>) -> Option<i32> {
+    raw.and_then(|raw| db::decode_setting(&raw).as_i64())
+        .and_then(|minutes| i32::try_from(minutes).ok())
+        .filter(|minutes| minutes.abs() <= 14 * 60)
 }
 
-/// COMMAND: Get a setting value
+/// COMMAND: Get every stored setting keyed by name
 #[tauri::command]
-fn get_setting_command(key: String) -> Result<Option<String>, String> {
-    let conn = db_conn()?;
-    Ok(db::get_setting(&conn, &key))
+fn get_all_settings(
+    db_state: tauri::State<'_, DbState>,
+) -> Result<HashMap<String, serde_json::Value>, String> {
+    let settings = with_db(&db_state, "Failed to get settings", |c| db::get_all_settings(c))?;
+    Ok(settings
+        .into_iter()
+        .map(|(key, raw)| {
+            let value = db::decode_setting(&raw);
+            (key, value)
+        })
+        .collect())
 }
 
 // ============================================================================
@@ -931,32 +6095,85 @@ fn get_setting_command(key: String) -> Result<Option<String>, String> {
 
 /// COMMAND: Get all smart collections with counts
 #[tauri::command]
-fn get_smart_collections() -> Result<Vec<db::SmartCollection>, String> {
-    with_db("Failed to get smart collections", |c| db::get_smart_collections(c))
+fn get_smart_collections(
+    db_state: tauri::State<'_, DbState>,
+) -> Result<Vec<db::SmartCollection>, String> {
+    with_db(&db_state, "Failed to get smart collections", |c| db::get_smart_collections(c))
 }
 
 /// COMMAND: Get photos for a smart collection
 #[tauri::command]
-fn get_smart_collection_photos(collection_id: String) -> Result<Vec<PhotoMetadata>, String> {
-    with_db("Failed to get collection photos", |c| db::get_smart_collection_photos(c, &collection_id))
+fn get_smart_collection_photos(
+    db_state: tauri::State<'_, DbState>,
+    collection_id: String,
+) -> Result<Vec<PhotoMetadata>, String> {
+    with_db(&db_state, "Failed to get collection photos", |c| db::get_smart_collection_photos(c, &collection_id))
 }
 
 // ============================================================================
 // Storage Analytics Commands
 // ============================================================================
 
-/// COMMAND: Get storage analytics
+/// Size of the drive the library is on.
+#[derive(Serialize)]
+pub struct StorageInfo {
+    pub library_path: String,
+    pub total_bytes: u64,
+    /// Space this user can write; below the raw free space on volumes that reserve some
+    pub available_bytes: u64,
+}
+
+/// COMMAND: Total and free space on the library's drive, for a capacity bar.
+/// Errors if the library folder is unavailable (e.g. drive not mounted).
+#[tauri::command]
+fn get_storage_info(db_state: tauri::State<'_, DbState>) -> Result<StorageInfo, String> {
+    let library_path = db::get_library_path(&*db_conn(&db_state)?)?;
+    let space_error = |e: std::io::Error| format!("Cannot read space on {}: {}", library_path.display(), e);
+    Ok(StorageInfo {
+        total_bytes: fs2::total_space(&library_path).map_err(space_error)?,
+        available_bytes: fs2::available_space(&library_path).map_err(space_error)?,
+        library_path: library_path.to_string_lossy().to_string(),
+    })
+}
+
+/// COMMAND: Photo, video, favorite and album counts, date range and size for
+/// the sidebar, with trashed and hidden photos counted separately.
 #[tauri::command]
-fn get_storage_analytics() -> Result<db::StorageAnalytics, String> {
-    with_db("Failed to get storage analytics", |c| db::get_storage_analytics(c))
+fn get_library_summary(db_state: tauri::State<'_, DbState>) -> Result<db::LibrarySummary, String> {
+    with_db(&db_state, "Failed to get library summary", |c| db::get_library_summary(c))
 }
 
-/// COMMAND: Populate file sizes for all photos (one-time migration)
+/// COMMAND: Bytes and counts by media type, year and month, the `limit`
+/// largest files (config::LARGEST_FILES_SHOWN by default), space held by
+/// exact duplicates, and the trash's size apart from the rest.
 #[tauri::command]
-async fn populate_file_sizes(window: tauri::Window) -> Result<ScanProgress, String> {
-    let conn = db_conn()?;
+fn get_storage_analytics(
+    db_state: tauri::State<'_, DbState>,
+    limit: Option<i64>,
+) -> Result<db::StorageAnalytics, String> {
+    let limit = limit.unwrap_or(config::LARGEST_FILES_SHOWN).clamp(0, config::MAX_PAGE_SIZE);
+    with_db(&db_state, "Failed to get storage analytics", |c| {
+        db::get_storage_analytics(c, media::default_utc_offset(), limit)
+    })
+}
 
-    let paths = db::get_photos_without_file_size(&conn)
+/// A photo's size and MIME type as update_file_info_batch takes them; a
+/// missing file has no size.
+pub(crate) fn file_info(path: &str) -> (String, Option<i64>, Option<String>) {
+    let size = fs::metadata(path).ok().map(|m| m.len() as i64);
+    let mime_type = media::file_format(Path::new(path)).as_deref().and_then(media::mime_type).map(str::to_string);
+    (path.to_string(), size, mime_type)
+}
+
+/// COMMAND: Fill in file sizes and MIME types for photos missing them,
+/// e.g. rows imported before they were recorded. Emits `file_size_progress`;
+/// a photo whose file is missing keeps a NULL size.
+#[tauri::command]
+async fn populate_file_sizes(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<ScanProgress, String> {
+    let paths = db::get_photos_without_file_size(&*db_conn(&db_state)?)
         .map_err(|e| format!("Failed to get photos: {}", e))?;
 
     let total = paths.len() as u32;
@@ -969,11 +6186,11 @@ async fn populate_file_sizes(window: tauri::Window) -> Result<ScanProgress, Stri
         phase: "calculating".to_string(),
     });
 
-    // Process in parallel
-    let results: Vec<(String, Option<u64>)> = paths
+    // Process in parallel; a missing file keeps a NULL size
+    let results: Vec<(String, Option<i64>, Option<String>)> = paths
         .par_iter()
         .map(|path| {
-            let size = fs::metadata(path).ok().map(|m| m.len());
+            let info = file_info(path);
 
             let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
             if current % 50 == 0 || current == total {
@@ -984,7 +6201,7 @@ async fn populate_file_sizes(window: tauri::Window) -> Result<ScanProgress, Stri
                 });
             }
 
-            (path.clone(), size)
+            info
         })
         .collect();
 
@@ -995,11 +6212,8 @@ async fn populate_file_sizes(window: tauri::Window) -> Result<ScanProgress, Stri
         phase: "saving".to_string(),
     });
 
-    for (path, size) in results {
-        if let Some(s) = size {
-            let _ = db::update_photo_file_size(&conn, &path, s as i64);
-        }
-    }
+    db::update_file_info_batch(&*db_conn(&db_state)?, &results)
+        .map_err(|e| format!("Failed to save file sizes: {}", e))?;
 
     let _ = window.emit("file_size_progress", ScanProgress {
         total,
@@ -1014,15 +6228,124 @@ async fn populate_file_sizes(window: tauri::Window) -> Result<ScanProgress, Stri
     })
 }
 
+/// COMMAND: Re-read EXIF camera make/model for photos already in the database.
+/// Backfills rows imported before the scanner captured camera info.
+/// Emits `reextract_progress` events every 50 photos; returns how many rows gained camera info.
+#[tauri::command]
+async fn reextract_metadata(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<u32, String> {
+    let paths = db::get_all_photo_paths(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get photos: {}", e))?;
+
+    let total = paths.len() as u32;
+    let processed = Arc::new(AtomicU32::new(0));
+
+    let results: Vec<(String, Option<String>, Option<String>)> = paths
+        .par_iter()
+        .map(|path| {
+            let (make, model) = media::extract_camera(Path::new(path));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 50 == 0 || current == total {
+                let _ = window.emit("reextract_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "reading".to_string(),
+                });
+            }
+
+            (path.clone(), make, model)
+        })
+        .collect();
+
+    let conn = db_conn(&db_state)?;
+    let mut updated: u32 = 0;
+    for (path, make, model) in results {
+        if make.is_none() && model.is_none() {
+            continue;
+        }
+        if db::update_camera_info(&conn, &path, make.as_deref(), model.as_deref()).is_ok() {
+            updated += 1;
+        }
+    }
+
+    let _ = window.emit("reextract_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    Ok(updated)
+}
+
+/// COMMAND: Read container metadata (size, duration, creation time) for videos
+/// imported before the scanner parsed MP4/MOV/MKV headers.
+/// Emits `video_metadata_progress` events every 20 videos; returns how many rows were updated.
+#[tauri::command]
+async fn reextract_video_metadata(
+    window: tauri::Window,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<u32, String> {
+    let paths: Vec<String> = db::get_paths_missing_dimensions_or_duration(&*db_conn(&db_state)?)
+        .map_err(|e| format!("Failed to get videos: {}", e))?
+        .into_iter()
+        .filter(|p| media::is_video(Path::new(p)))
+        .collect();
+
+    let total = paths.len() as u32;
+    let processed = Arc::new(AtomicU32::new(0));
+
+    let results: Vec<(String, Option<video::VideoInfo>)> = paths
+        .par_iter()
+        .map(|path| {
+            let info = video::read_video_info(Path::new(path));
+
+            let current = processed.fetch_add(1, Ordering::SeqCst) + 1;
+            if current % 20 == 0 || current == total {
+                let _ = window.emit("video_metadata_progress", ScanProgress {
+                    total,
+                    processed: current,
+                    phase: "reading".to_string(),
+                });
+            }
+
+            (path.clone(), info)
+        })
+        .collect();
+
+    let conn = db_conn(&db_state)?;
+    let mut updated: u32 = 0;
+    for (path, info) in results {
+        let Some(info) = info else { continue };
+        if db::update_video_info(&conn, &path, info.width, info.height, info.duration_ms, info.created_at).is_ok() {
+            updated += 1;
+        }
+    }
+
+    let _ = window.emit("video_metadata_progress", ScanProgress {
+        total,
+        processed: total,
+        phase: "complete".to_string(),
+    });
+
+    Ok(updated)
+}
+
 // ============================================================================
 // Metadata Enrichment Commands
 // ============================================================================
 
 /// COMMAND: Enrich a single photo's metadata via the Python exiftool wrapper.
 #[tauri::command]
-fn enrich_photo_metadata(app: tauri::AppHandle, path: String) -> Result<(), String> {
+fn enrich_photo_metadata(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<(), String> {
     let meta = enrich_path(&app, &path)?;
-    let conn = db_conn()?;
+    let conn = db_conn(&db_state)?;
     db::update_enriched_metadata(&conn, &path, &meta)
         .map_err(|e| format!("Failed to save enriched metadata: {}", e))
 }
@@ -1031,15 +6354,19 @@ fn enrich_photo_metadata(app: tauri::AppHandle, path: String) -> Result<(), Stri
 /// Emits `metadata_enrich_progress` events every 10 photos.
 /// Returns the count of photos successfully enriched.
 #[tauri::command]
-async fn enrich_all_metadata(app: tauri::AppHandle) -> Result<usize, String> {
+async fn enrich_all_metadata(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<usize, String> {
     use rayon::prelude::*;
     use std::sync::atomic::{AtomicUsize, Ordering};
     use std::sync::Arc;
     use tauri::Emitter;
 
-    let conn = db_conn()?;
+    let conn = db_conn(&db_state)?;
     let paths = db::get_photos_without_enrichment(&conn)
         .map_err(|e| format!("Failed to get unenriched photos: {}", e))?;
+    drop(conn);
 
     let total = paths.len();
     let enriched_count = Arc::new(AtomicUsize::new(0));
@@ -1054,7 +6381,7 @@ async fn enrich_all_metadata(app: tauri::AppHandle) -> Result<usize, String> {
         .collect();
 
     // Write results sequentially and emit progress events.
-    let write_conn = db_conn()?;
+    let write_conn = db_conn(&db_state)?;
     for (i, (path, result)) in results.into_iter().enumerate() {
         if let Ok(meta) = result {
             if db::update_enriched_metadata(&write_conn, &path, &meta).is_ok() {
@@ -1073,16 +6400,37 @@ async fn enrich_all_metadata(app: tauri::AppHandle) -> Result<usize, String> {
     Ok(enriched_count.load(Ordering::Relaxed))
 }
 
+/// COMMAND: Show a photo selected in Finder, Explorer or the Linux file manager.
 #[tauri::command]
-fn reveal_in_finder(path: String) -> Result<(), String> {
-    if !Path::new(&path).exists() {
-        return Err(format!("Path does not exist: {}", path));
+async fn reveal_in_file_manager(app: tauri::AppHandle, path: String) -> Result<(), String> {
+    let path = PathBuf::from(path);
+    if !path.exists() {
+        return Err(format!("Path does not exist: {}", path.display()));
     }
-    std::process::Command::new("open")
-        .args(["-R", &path])
-        .spawn()
-        .map_err(|e| format!("Failed to reveal in Finder: {}", e))?;
-    Ok(())
+    external::reveal(&app, &path).await
+}
+
+/// COMMAND: Open a photo in the system's default app for its type, e.g. to
+/// edit it. Follow with watch_for_external_edit to pick up what it saves.
+#[tauri::command]
+async fn open_with_default_app(app: tauri::AppHandle, path: String) -> Result<(), String> {
+    let path = PathBuf::from(path);
+    if !path.exists() {
+        return Err(format!("Path does not exist: {}", path.display()));
+    }
+    external::open_with_default_app(&app, &path).await
+}
+
+/// COMMAND: Watch a library photo while another app edits it. Each save
+/// updates its dimensions and hash, replaces its thumbnails and emits
+/// `photo_changed`. Watching ends an hour after the last save.
+#[tauri::command]
+fn watch_for_external_edit(app: tauri::AppHandle, db_state: tauri::State<'_, DbState>, path: String) -> Result<(), String> {
+    let known = with_db(&db_state, "Failed to look up photo", |c| db::get_file_stats(c, &path))?;
+    if known.is_none() {
+        return Err(format!("{} is not in the library", path));
+    }
+    external::watch_for_edit(&app, Path::new(&path))
 }
 
 /// COMMAND: Return the canonical thumbnail cache root as an absolute path.
@@ -1093,56 +6441,370 @@ fn get_thumb_cache_root() -> Result<String, String> {
     Ok(thumbnails::thumb_cache_root().to_string_lossy().into_owned())
 }
 
+/// COMMAND: Queue a background job (hash_backfill, file_sizes,
+/// perceptual_hashes or thumbnails) and return its id. Progress arrives as
+/// `job_progress` events; an identical job still waiting is reused.
+#[tauri::command]
+fn enqueue_job(
+    app: tauri::AppHandle,
+    job_queue: tauri::State<'_, jobs::JobQueue>,
+    kind: jobs::JobKind,
+    params: Option<serde_json::Value>,
+) -> Result<i64, String> {
+    job_queue.enqueue(&app, kind, params.unwrap_or_else(|| serde_json::json!({})))
+}
+
+/// COMMAND: The `limit` most recent jobs (50 by default), newest first,
+/// with status, progress and any error.
+#[tauri::command]
+fn get_jobs(db_state: tauri::State<'_, DbState>, limit: Option<i64>) -> Result<Vec<db::JobRecord>, String> {
+    let limit = limit.unwrap_or(50).clamp(1, config::MAX_PAGE_SIZE);
+    with_db(&db_state, "Failed to get jobs", |c| db::get_jobs(c, limit))
+}
+
+/// COMMAND: Cancel a queued job, or stop a running one after its current
+/// chunk; what it saved so far is kept. False if it already finished.
+#[tauri::command]
+fn cancel_job(app: tauri::AppHandle, job_queue: tauri::State<'_, jobs::JobQueue>, id: i64) -> Result<bool, String> {
+    job_queue.cancel(&app, id)
+}
+
+/// Set while a thumbnail backfill or purge runs, so the post-scan prewarm,
+/// the Settings button and a purge never work on the cache at once.
+static THUMBNAILS_RUNNING: AtomicBool = AtomicBool::new(false);
+
+/// Content hash for a photo, computing and storing it if the row has none
+/// yet. Thumbnails are keyed by it.
+fn thumbnail_hash(db_state: &DbState, path: &str) -> Result<String, String> {
+    let stored_hash = db::get_content_hash(&*db_conn(db_state)?, path)
+        .map_err(|e| format!("Failed to look up photo: {}", e))?;
+    if let Some(hash) = stored_hash {
+        return Ok(hash);
+    }
+    let hash = media::calculate_hash(Path::new(path)).ok_or_else(|| format!("Failed to read {}", path))?;
+    db::update_content_hashes_batch(&*db_conn(db_state)?, &[(path.to_string(), hash.clone())])
+        .map_err(|e| format!("Failed to save content hash: {}", e))?;
+    Ok(hash)
+}
+
+/// COMMAND: Make (or reuse) the poster thumbnail for one video via ffmpeg.
+/// Returns the thumbnail path. Errors are `{ kind, message }` objects; kind
+/// `ffmpeg_missing` means ffmpeg needs installing.
+#[tauri::command]
+async fn generate_video_thumbnail(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+) -> Result<String, thumbnails::VideoThumbError> {
+    let source = Path::new(&path);
+    if !media::is_video(source) {
+        return Err(format!("Not a video: {}", path).into());
+    }
+
+    let hash = thumbnail_hash(&db_state, &path)?;
+    let dest = thumbnails::generate_video_thumbnail(&app, source, &hash, thumbnails::THUMB_SIZE).await?;
+    db::set_thumb_status(&*db_conn(&db_state)?, &path, "ready")
+        .map_err(|e| format!("Failed to save thumbnail status: {}", e))?;
+    Ok(dest.to_string_lossy().into_owned())
+}
+
+/// COMMAND: Get a cached thumbnail for a photo or video, generating it on
+/// first request. `size` is rounded up to 256, 512 or 1024 (default 256).
+/// Returns the file path, ready for convertFileSrc.
+#[tauri::command]
+async fn get_thumbnail(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    size: Option<u32>,
+) -> Result<String, String> {
+    let size = thumbnails::bucket_size(size.unwrap_or(thumbnails::THUMB_SIZE));
+    let source = Path::new(&path);
+    let hash = thumbnail_hash(&db_state, &path)?;
+    let edits = with_db(&db_state, "Failed to look up edits", |c| db::get_photo_edits(c, &path))?;
+    // A cached grid thumb may be an embedded preview; leave its status alone
+    let cached = thumbnails::thumb_path(&hash, size).exists();
+
+    let generated = if media::is_video(source) {
+        thumbnails::generate_video_thumbnail(&app, source, &hash, size)
+            .await
+            .map_err(|e| e.to_string())
+    } else if let Some(edits) = &edits {
+        thumbnails::generate_edited(source, &hash, edits, size)
+    } else {
+        thumbnails::generate_thumbnail(source, &hash, size)
+    };
+
+    // thumb_status tracks the unedited grid size the frontend builds URLs for
+    if size == thumbnails::THUMB_SIZE && !cached && edits.is_none() {
+        let status = if generated.is_ok() { "ready" } else { "failed" };
+        let _ = db::set_thumb_status(&*db_conn(&db_state)?, &path, status);
+    }
+    Ok(generated?.to_string_lossy().into_owned())
+}
+
+/// COMMAND: Save a crop and rotation recipe for a photo. The file on disk is
+/// never changed: thumbnails and `render_edited` apply the recipe, and
+/// exports can ask for the edited version. A recipe that changes nothing
+/// clears the edits.
+#[tauri::command]
+fn set_photo_edits(db_state: tauri::State<'_, DbState>, path: String, edits: edits::PhotoEdits) -> Result<(), String> {
+    edits.validate()?;
+    let conn = db_conn(&db_state)?;
+    drop_edited_renders(&conn, &path);
+    let saved = db::set_photo_edits(&conn, &path, &edits).map_err(|e| format!("Failed to save edits: {}", e))?;
+    if !saved {
+        return Err(format!("{} is not in the library", path));
+    }
+    Ok(())
+}
+
+/// COMMAND: A photo's crop and rotation recipe, or null if it has none.
+#[tauri::command]
+fn get_photo_edits(db_state: tauri::State<'_, DbState>, path: String) -> Result<Option<edits::PhotoEdits>, String> {
+    with_db(&db_state, "Failed to get edits", |c| db::get_photo_edits(c, &path))
+}
+
+/// COMMAND: Go back to the original. Returns whether the photo had edits.
+#[tauri::command]
+fn clear_photo_edits(db_state: tauri::State<'_, DbState>, path: String) -> Result<bool, String> {
+    let conn = db_conn(&db_state)?;
+    drop_edited_renders(&conn, &path);
+    db::clear_photo_edits(&conn, &path).map_err(|e| format!("Failed to clear edits: {}", e))
+}
+
+/// Delete the cached renders of a photo's current edits, which are about to
+/// change.
+fn drop_edited_renders(conn: &rusqlite::Connection, path: &str) {
+    if let (Ok(Some(edits)), Ok(Some(hash))) = (db::get_photo_edits(conn, path), db::get_content_hash(conn, path)) {
+        thumbnails::remove_thumbnails(&thumbnails::edited_key(&hash, &edits));
+    }
+}
+
+/// Long edge of `render_edited` output when none is asked for.
+const EDITED_RENDER_SIZE: u32 = 2048;
+
+/// COMMAND: The photo with its edits applied, as a cached JPEG no larger
+/// than `max_size` on its long side (default 2048). A photo without edits
+/// renders upright as it is. Returns the file path, ready for convertFileSrc.
+#[tauri::command]
+async fn render_edited(
+    db_state: tauri::State<'_, DbState>,
+    path: String,
+    max_size: Option<u32>,
+) -> Result<String, String> {
+    let hash = thumbnail_hash(&db_state, &path)?;
+    let edits = with_db(&db_state, "Failed to get edits", |c| db::get_photo_edits(c, &path))?.unwrap_or_default();
+    let size = max_size.unwrap_or(EDITED_RENDER_SIZE).max(1);
+    let rendered = thumbnails::generate_edited(Path::new(&path), &hash, &edits, size)?;
+    Ok(rendered.to_string_lossy().into_owned())
+}
+
+/// COMMAND: Delete every cached thumbnail and mark all photos as needing one.
+/// Returns how many thumbnail files were removed.
+#[tauri::command]
+fn purge_thumbnails(db_state: tauri::State<'_, DbState>) -> Result<usize, String> {
+    // Held for the purge, so no backfill starts writing into the cache meanwhile
+    if THUMBNAILS_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Thumbnail generation is running; try again when it finishes".to_string());
+    }
+    let result = thumbnails::purge_cache()
+        .map_err(|e| format!("Failed to purge thumbnails: {}", e))
+        .and_then(|removed| {
+            with_db_write(&db_state, "Failed to reset thumbnail status", |c| db::clear_thumb_statuses(c))?;
+            Ok(removed)
+        });
+    THUMBNAILS_RUNNING.store(false, Ordering::SeqCst);
+    let removed = result?;
+    info!("Purged {} thumbnails", removed);
+    Ok(removed)
+}
+
 /// COMMAND: Backfill thumbnails for every photo lacking one.
+/// Images are resized in parallel; videos get an ffmpeg poster frame one at
+/// a time (ffmpeg is multi-threaded itself). Without ffmpeg, videos stay
+/// pending so a later run picks them up.
 /// Emits `thumbnail_progress` events every 20 items.
 /// Returns the count of thumbnails successfully generated.
 #[tauri::command]
-async fn generate_missing_thumbnails(app: tauri::AppHandle) -> Result<usize, String> {
-    use rayon::prelude::*;
-    use std::sync::atomic::{AtomicUsize, Ordering};
-    use std::sync::Arc;
-    use tauri::Emitter;
+async fn generate_missing_thumbnails(
+    app: tauri::AppHandle,
+    db_state: tauri::State<'_, DbState>,
+) -> Result<usize, String> {
+    let progress = |processed: usize, total: usize| {
+        let _ = app.emit("thumbnail_progress", serde_json::json!({ "processed": processed, "total": total }));
+    };
+    backfill_thumbnails(&app, &db_state, false, &progress, &AtomicBool::new(false)).await
+}
+
+/// Queue the background jobs a scan or import that added photos needs:
+/// thumbnails, so the first browse doesn't fall back to full-size
+/// originals, placeholders, and place names. The thumbnails job makes a
+/// fast pass with embedded EXIF previews where cameras wrote them, then a
+/// full pass replaces those with proper thumbnails. The placeholders job
+/// then covers thumbnails the grid made on demand meanwhile.
+fn spawn_post_import_jobs(app: &tauri::AppHandle) {
+    let queue = app.state::<jobs::JobQueue>();
+    let queued = queue
+        .enqueue(app, jobs::JobKind::Thumbnails, serde_json::json!({ "prewarm": true }))
+        .and_then(|_| queue.enqueue(app, jobs::JobKind::Placeholders, serde_json::json!({})))
+        .and_then(|_| queue.enqueue(app, jobs::JobKind::Geocode, serde_json::json!({})));
+    if let Err(e) = queued {
+        debug!("Post-import jobs skipped: {}", e);
+    }
+}
+
+/// Reports (processed, total) every 20 items
+type ThumbnailProgress<'a> = &'a (dyn Fn(usize, usize) + Sync);
+
+/// One backfill at a time; see `THUMBNAILS_RUNNING`. Stops early, keeping
+/// what it made, once `cancelled` is set.
+async fn backfill_thumbnails(
+    app: &tauri::AppHandle,
+    db_state: &DbState,
+    use_embedded: bool,
+    progress: ThumbnailProgress<'_>,
+    cancelled: &AtomicBool,
+) -> Result<usize, String> {
+    if THUMBNAILS_RUNNING.swap(true, Ordering::SeqCst) {
+        return Err("Thumbnail generation is already running".to_string());
+    }
+    let result = run_thumbnail_backfill(app, db_state, use_embedded, progress, cancelled).await;
+    THUMBNAILS_RUNNING.store(false, Ordering::SeqCst);
+    result
+}
 
-    let conn = db_conn()?;
+/// With `use_embedded`, images with an EXIF preview get that instead of a
+/// full decode ('embedded'). Without it, earlier 'embedded' thumbnails are
+/// upgraded too.
+async fn run_thumbnail_backfill(
+    app: &tauri::AppHandle,
+    db_state: &DbState,
+    use_embedded: bool,
+    progress: ThumbnailProgress<'_>,
+    cancelled: &AtomicBool,
+) -> Result<usize, String> {
+    use std::sync::atomic::AtomicUsize;
+
+    let conn = db_conn(db_state)?;
     let photos = db::get_photos_without_thumbnails(&conn)
         .map_err(|e| format!("Failed to query photos: {}", e))?;
+    let to_upgrade = if use_embedded {
+        Vec::new()
+    } else {
+        db::get_photos_with_embedded_thumbnails(&conn)
+            .map_err(|e| format!("Failed to query photos: {}", e))?
+    };
     drop(conn);
 
-    let total = photos.len();
+    let (videos, missing_images): (Vec<_>, Vec<_>) = photos
+        .into_iter()
+        .partition(|(path, _)| media::is_video(Path::new(path)));
+    let images: Vec<(String, String, bool)> = missing_images
+        .into_iter()
+        .map(|(path, hash)| (path, hash, false))
+        .chain(to_upgrade.into_iter().map(|(path, hash)| (path, hash, true)))
+        .collect();
+
+    let total = images.len() + videos.len();
     if total == 0 {
         return Ok(0);
     }
-
     let count = Arc::new(AtomicUsize::new(0));
+    let emit_progress = |processed: usize| {
+        if processed % 20 == 0 || processed == total {
+            progress(processed, total);
+        }
+    };
 
-    // Decode + resize in parallel; each thread is CPU-bound on JPEG.
-    let results: Vec<(String, &'static str)> = photos
+    // Decode + resize in parallel; each thread is CPU-bound on JPEG. The
+    // placeholder comes from the same bitmap, so nothing is decoded twice.
+    // None = an upgrade failed and the embedded preview stays as it was.
+    type Outcome = (String, Option<&'static str>, Option<placeholders::Placeholder>);
+    let decoded = AtomicUsize::new(0);
+    let results: Vec<Outcome> = images
         .par_iter()
-        .map(|(path, hash)| {
-            let src = Path::new(path);
-            if media::is_video(src) {
-                return (path.clone(), "unsupported");
-            }
-            match thumbnails::generate_thumbnail(src, hash, thumbnails::THUMB_SIZE) {
-                Ok(_) => (path.clone(), "ready"),
-                Err(_) => (path.clone(), "failed"),
+        .map(|(path, hash, upgrade)| {
+            emit_progress(decoded.fetch_add(1, Ordering::Relaxed) + 1);
+            if cancelled.load(Ordering::Relaxed) {
+                return (path.clone(), None, None);
             }
+            let source = Path::new(path);
+            let embedded = if use_embedded && !*upgrade {
+                thumbnails::generate_embedded_thumbnail(source, hash, thumbnails::THUMB_SIZE).ok()
+            } else {
+                None
+            };
+            let (status, placeholder) = if *upgrade {
+                match thumbnails::upgrade_thumbnail(source, hash, thumbnails::THUMB_SIZE) {
+                    Ok((_, placeholder)) => (Some("ready"), placeholder),
+                    Err(_) => (None, None),
+                }
+            } else if let Some((_, placeholder)) = embedded {
+                (Some("embedded"), placeholder)
+            } else {
+                match thumbnails::generate_thumbnail_with_placeholder(source, hash, thumbnails::THUMB_SIZE) {
+                    Ok((_, placeholder)) => (Some("ready"), placeholder),
+                    Err(_) => (Some("failed"), None),
+                }
+            };
+            (path.clone(), status, placeholder)
         })
         .collect();
 
-    // Persist results sequentially; SQLite handles serialized writes best.
-    let write_conn = db_conn()?;
-    for (i, (path, status)) in results.into_iter().enumerate() {
-        let _ = db::set_thumb_status(&write_conn, &path, status);
-        if status == "ready" {
-            count.fetch_add(1, Ordering::Relaxed);
+    // Persist results in one transaction each; SQLite handles batched writes best.
+    let mut statuses = Vec::new();
+    let mut placeholder_rows = Vec::new();
+    for (path, status, placeholder) in results {
+        if let Some(placeholder) = placeholder {
+            placeholder_rows.push((path.clone(), placeholder));
+        }
+        if let Some(status) = status {
+            if status != "failed" {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
+            statuses.push((path, status));
+        }
+    }
+    let write_conn = db_conn(db_state)?;
+    if let Err(e) = db::set_thumb_statuses_batch(&write_conn, &statuses) {
+        warn!("Failed to save thumbnail statuses: {}", e);
+    }
+    if let Err(e) = db::set_placeholders_batch(&write_conn, &placeholder_rows) {
+        warn!("Failed to save placeholders: {}", e);
+    }
+    drop(write_conn);
+
+    let mut processed = images.len();
+    for (path, hash) in &videos {
+        if cancelled.load(Ordering::Relaxed) {
+            break;
         }
-        if (i + 1) % 20 == 0 || i + 1 == total {
-            let _ = app.emit(
-                "thumbnail_progress",
-                serde_json::json!({ "processed": i + 1, "total": total }),
-            );
+        let status = match thumbnails::generate_video_thumbnail(app, Path::new(path), hash, thumbnails::THUMB_SIZE).await {
+            Ok(poster) => {
+                // The poster frame stands in for the video's placeholder
+                if let Ok(placeholder) = placeholders::from_file(&poster) {
+                    let _ = db::set_placeholders_batch(&*db_conn(db_state)?, &[(path.clone(), placeholder)]);
+                }
+                Some("ready")
+            }
+            Err(thumbnails::VideoThumbError::FfmpegMissing(msg)) => {
+                warn!("Skipping {} video thumbnails: {}", videos.len(), msg);
+                break;
+            }
+            Err(thumbnails::VideoThumbError::Failed(msg)) => {
+                debug!("Video thumbnail failed for {}: {}", path, msg);
+                Some("failed")
+            }
+        };
+        if let Some(status) = status {
+            let _ = db::set_thumb_status(&*db_conn(db_state)?, path, status);
+            if status == "ready" {
+                count.fetch_add(1, Ordering::Relaxed);
+            }
         }
+        processed += 1;
+        emit_progress(processed);
     }
 
     Ok(count.load(Ordering::Relaxed))
@@ -1150,39 +6812,189 @@ async fn generate_missing_thumbnails(app: tauri::AppHandle) -> Result<usize, Str
 
 #[cfg_attr(mobile, tauri::mobile_entry_point)]
 pub fn run() {
-    // Initialize logging. Set RUST_LOG=debug for verbose output.
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
+    // Log to terra.log at the stored level (info until the database is open)
+    logging::init();
     log::info!("Terra starting up...");
 
+    // One connection for the app's lifetime; schema setup runs here, not per command.
+    let conn = db::init_database().unwrap_or_else(|e| panic!("Failed to open Terra database: {}", e));
+
+    logging::apply_level(Some(&db::get_setting_or(&conn, logging::LOG_LEVEL_SETTING, String::new())));
+
+    media::set_default_utc_offset(default_utc_offset(db::get_setting(&conn, DEFAULT_UTC_OFFSET_SETTING)));
+
+    let trash_cutoff = chrono::Utc::now().timestamp() - config::TRASH_RETENTION_DAYS * 24 * 60 * 60;
+    match purge_trash(&conn, trash_cutoff) {
+        Ok(summary) if summary.purged == 0 && summary.failed.is_empty() => {}
+        Ok(summary) => info!(
+            "Purged {} photos trashed more than {} days ago ({} kept)",
+            summary.purged,
+            config::TRASH_RETENTION_DAYS,
+            summary.failed.len()
+        ),
+        Err(e) => warn!("Trash purge failed: {}", e),
+    }
+
+    let retention_days = db::get_setting_or(&conn, "operation_retention_days", config::OPERATION_RETENTION_DAYS);
+    match db::prune_operations(&conn, chrono::Utc::now().timestamp() - retention_days * 24 * 60 * 60) {
+        Ok(0) => {}
+        Ok(n) => info!("Pruned {} operations older than {} days from the undo log", n, retention_days),
+        Err(e) => warn!("Undo log prune failed: {}", e),
+    }
+
+    match refresh_photo_availability(&conn) {
+        Ok(summary) if !summary.offline_roots.is_empty() => {
+            info!("Offline drives: {}", summary.offline_roots.join(", "))
+        }
+        Ok(_) => {}
+        Err(e) => warn!("Availability check failed: {}", e),
+    }
+
     tauri::Builder::default()
         .plugin(tauri_plugin_shell::init())
         .plugin(tauri_plugin_dialog::init())
+        .manage(ScanState::default())
+        .manage(ExportState::default())
+        .manage(DbState::new(conn))
+        .manage(watcher::WatcherState::default())
+        .manage(external::EditWatchState::default())
+        .manage(jobs::JobQueue::default())
+        .manage(auto_scan::AutoScanState::default())
+        .setup(|app| {
+            watcher::start(app.handle());
+            app.state::<jobs::JobQueue>().resume(app.handle());
+            auto_scan::start(app.handle());
+            Ok(())
+        })
         .invoke_handler(tauri::generate_handler![
             scan_directory,
+            rescan_directory,
+            import_takeout,
+            import_meta_export,
+            get_missing_photos,
+            refresh_availability,
+            cancel_scan,
             get_all_photos,
+            get_photos_page,
+            get_photo_count,
+            query_photos,
+            get_adjacent_photos,
+            get_random_photos,
+            get_shuffled_page,
+            get_timeline_buckets,
+            get_photos_in_bucket,
             upload_photos,
+            import_apple_photos,
             toggle_favorite,
+            toggle_favorite_by_id,
+            set_favorites,
+            set_hidden,
+            get_hidden_photos,
+            set_archived,
+            get_archived_photos,
+            get_import_sessions,
+            get_session_photos,
+            undo_import_session,
+            set_photo_rating,
+            set_photos_rating,
+            get_photos_by_min_rating,
+            set_color_label,
+            set_flag,
+            get_cull_queue,
+            get_cull_progress,
+            apply_cull,
+            get_stack_members,
+            stack_photos,
+            unstack_photos,
+            set_stack_primary,
+            set_photo_description,
+            get_photo_details,
+            get_live_photo_video,
+            get_date_discrepancies,
+            get_undated_photos,
+            set_photo_date,
+            shift_photo_dates,
+            preview_date_shift,
+            apply_date_shift,
+            undo_date_shift,
+            // Undo log
+            get_recent_operations,
+            undo_last_operation,
+            rotate_photo,
+            rotate_photos,
+            export_photos,
+            export_album,
+            export_album_zip,
+            export_metadata,
+            import_metadata,
+            import_digikam,
+            cancel_export,
             create_album,
             delete_album,
             get_albums,
+            rename_album,
+            set_album_order,
+            set_album_parent,
             add_to_album,
+            add_to_album_by_id,
             remove_from_album,
             get_album_photos,
+            analyze_trips,
+            create_album_from_suggestion,
+            dismiss_suggestion,
+            get_album_stats,
+            create_smart_album,
+            update_smart_album,
+            delete_smart_album,
+            get_smart_albums,
+            get_smart_album_photos,
+            get_smart_album_stats,
             set_album_cover,
             delete_photos,
+            rename_photo,
+            preview_batch_rename,
+            batch_rename,
+            delete_photos_by_id,
             get_duplicates,
             search_photos,
+            rebuild_search_index,
             get_locations,
+            geocode_photos,
+            get_places,
+            get_photos_by_place,
+            geotag_from_gpx,
+            get_photos_with_location,
+            get_map_clusters,
+            get_cameras,
+            get_photos_by_camera,
             // Duplicate and screenshot detection
             scan_for_duplicates,
             get_duplicate_groups,
+            resolve_duplicates,
+            resolve_duplicate_groups,
+            undo_duplicate_resolution,
+            find_duplicates,
+            backfill_hashes,
+            verify_library,
+            check_library_health,
+            repair_library,
+            compute_perceptual_hashes,
+            find_similar_photos,
+            analyze_quality,
+            get_low_quality_photos,
             scan_for_screenshots,
             get_screenshots,
+            set_is_screenshot,
             // Archive management
             archive_photos,
             restore_photos,
-            get_archived_photos,
+            get_deletion_archive,
             cleanup_old_archives,
+            // Trash
+            move_to_trash,
+            restore_from_trash,
+            empty_trash,
+            get_trashed_photos,
             // TerraForm (Review Mode)
             get_unreviewed_photos,
             mark_photo_reviewed,
@@ -1191,31 +7003,78 @@ pub fn run() {
             // Tags
             create_tag,
             update_tag,
+            rename_tag,
             delete_tag,
+            get_tags,
             get_all_tags,
             get_tags_for_photo,
             add_tags_to_photos,
             remove_tag_from_photo,
+            remove_tags_from_photos,
+            get_photos_by_tag,
             get_photos_by_tags,
             search_tags,
+            auto_tag_photos,
+            get_auto_tag_status,
+            remove_auto_tags,
+            detect_faces,
+            get_face_detection_status,
+            get_faces,
+            assign_face,
+            create_person,
+            get_people,
+            get_photos_by_person,
+            cluster_faces,
+            get_face_clusters,
+            assign_cluster_to_person,
             // Settings
+            get_library_path,
             get_library_path_command,
             set_library_path,
+            relocate_library,
+            get_watch_folders,
+            add_watch_folder,
+            remove_watch_folder,
             get_setting_command,
+            get_setting,
+            set_setting,
+            get_all_settings,
+            get_scan_exclusions,
+            set_scan_exclusions,
+            get_log_file_path,
+            get_recent_logs,
+            enqueue_job,
+            get_jobs,
+            cancel_job,
+            set_auto_scan,
+            get_auto_scan_status,
             // Smart Collections
             get_smart_collections,
             get_smart_collection_photos,
             // Storage Analytics
             get_storage_analytics,
+            get_library_summary,
+            get_storage_info,
             populate_file_sizes,
             // Metadata Enrichment
+            reextract_metadata,
+            reextract_video_metadata,
             enrich_photo_metadata,
             enrich_all_metadata,
             // Thumbnails
             get_thumb_cache_root,
+            generate_video_thumbnail,
+            get_thumbnail,
+            set_photo_edits,
+            get_photo_edits,
+            clear_photo_edits,
+            render_edited,
+            purge_thumbnails,
             generate_missing_thumbnails,
-            // Finder integration
-            reveal_in_finder
+            // File manager and external editors
+            reveal_in_file_manager,
+            open_with_default_app,
+            watch_for_external_edit
         ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
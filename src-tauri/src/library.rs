@@ -0,0 +1,849 @@
+//! Filesystem policy for the managed library: which files Terra may delete,
+//! and collision-free destinations when moving files around.
+//!
+//! No database access; callers resolve the library/archive roots and pass
+//! them in, which keeps the rules testable against temp directories.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use filetime::FileTime;
+use serde::Serialize;
+use unicode_normalization::UnicodeNormalization;
+
+use crate::media;
+
+/// What delete_photos does with a photo's file once its row is removed.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum FileAction {
+    Delete,
+    /// Original left on disk (scanned in place from outside the library)
+    Keep,
+}
+
+/// True if `path` resolves to a file inside one of `roots`.
+/// Symlinks are never followed out of the library: the resolved target must
+/// be inside, and for a link the link itself must be inside too.
+pub(crate) fn is_within_roots(path: &Path, roots: &[&Path]) -> bool {
+    let roots: Vec<PathBuf> = roots.iter().filter_map(|r| r.canonicalize().ok()).collect();
+    let inside = |p: &Path| roots.iter().any(|root| p.starts_with(root));
+
+    let Ok(target) = path.canonicalize() else {
+        return false;
+    };
+    if !inside(&target) {
+        return false;
+    }
+
+    match fs::symlink_metadata(path) {
+        Ok(meta) if meta.file_type().is_symlink() => path
+            .parent()
+            .and_then(|dir| dir.canonicalize().ok())
+            .is_some_and(|dir| inside(&dir)),
+        Ok(_) => true,
+        Err(_) => false,
+    }
+}
+
+/// Library files are deleted; anything else is kept unless the user
+/// explicitly asked to remove originals too.
+pub(crate) fn file_action_on_delete(path: &Path, roots: &[&Path], also_delete_file: bool) -> FileAction {
+    if also_delete_file || is_within_roots(path, roots) {
+        FileAction::Delete
+    } else {
+        FileAction::Keep
+    }
+}
+
+/// The one place Terra removes a photo file from disk. Everything else moves
+/// files (to the archive or trash) so they can be brought back.
+/// On a symlink this removes the link, never its target.
+pub(crate) fn delete_file_permanently(path: &Path) -> io::Result<()> {
+    fs::remove_file(path)
+}
+
+/// Setting choosing how deleted photos leave Terra (see `DeletionMode`).
+pub(crate) const DELETION_MODE_SETTING: &str = "deletion_mode";
+
+/// How delete_photos and empty_trash get rid of files.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) enum DeletionMode {
+    /// delete_photos moves photos into Terra's trash; emptying it deletes them
+    TerraTrash,
+    /// Files go to the system Trash / Recycle Bin, where other tools can recover them
+    SystemTrash,
+    /// Files are deleted outright
+    #[default]
+    Permanent,
+}
+
+impl std::str::FromStr for DeletionMode {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "terra_trash" => Ok(DeletionMode::TerraTrash),
+            "system_trash" => Ok(DeletionMode::SystemTrash),
+            "permanent" => Ok(DeletionMode::Permanent),
+            other => Err(format!("Unknown deletion mode: {}", other)),
+        }
+    }
+}
+
+/// What became of a file handed to `dispose_file`.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum Disposal {
+    Deleted,
+    SystemTrashed,
+    /// Still on disk; the message says why
+    Kept(String),
+}
+
+/// Remove `path` from disk as `mode` says, using `send_to_trash` for the
+/// system trash. If the system trash refuses the file (some Linux desktops,
+/// network mounts) it is kept rather than deleted outright. Terra's own
+/// trash has no say here: its files are already in it.
+pub(crate) fn dispose_file(
+    path: &Path,
+    mode: DeletionMode,
+    send_to_trash: impl FnOnce(&Path) -> Result<(), String>,
+) -> Disposal {
+    match mode {
+        DeletionMode::SystemTrash => match send_to_trash(path) {
+            Ok(()) => Disposal::SystemTrashed,
+            Err(e) => Disposal::Kept(format!("Couldn't move it to the system trash ({}); the file was kept", e)),
+        },
+        DeletionMode::TerraTrash | DeletionMode::Permanent => match delete_file_permanently(path) {
+            Ok(()) => Disposal::Deleted,
+            Err(e) => Disposal::Kept(format!("Failed to delete file: {}", e)),
+        },
+    }
+}
+
+/// Move `path` to the system Trash / Recycle Bin.
+pub(crate) fn send_to_system_trash(path: &Path) -> Result<(), String> {
+    trash::delete(path).map_err(|e| e.to_string())
+}
+
+/// Text stored for a file's path; the one place paths are prepared for the
+/// database. Both fixes keep one file from showing up under two spellings:
+/// - Windows: without the `\\?\` prefix canonicalize adds, which the asset
+///   protocol's scope never matches, and with `\` separators throughout.
+/// - macOS: names composed (NFC). HFS+ hands back decomposed names while
+///   most tools write composed ones, and either form opens the same file.
+///   Elsewhere the two forms are different files.
+pub(crate) fn stored_path(path: &Path) -> String {
+    storage_form(&path.to_string_lossy(), cfg!(windows), cfg!(target_os = "macos"))
+}
+
+/// stored_path's rules applied to path text, as on a Windows and/or macOS host.
+fn storage_form(text: &str, windows: bool, macos: bool) -> String {
+    let text = if windows { windows_form(text) } else { text.to_string() };
+    if macos {
+        compose_unicode(&text)
+    } else {
+        text
+    }
+}
+
+/// A Windows path without its verbatim prefix (`\\?\C:\x` becomes `C:\x`,
+/// `\\?\UNC\server\share` becomes `\\server\share`), with `\` separators.
+pub(crate) fn windows_form(text: &str) -> String {
+    let plain = if let Some(share) = text.strip_prefix(r"\\?\UNC\") {
+        format!(r"\\{}", share)
+    } else {
+        text.strip_prefix(r"\\?\").unwrap_or(text).to_string()
+    };
+    plain.replace('/', "\\")
+}
+
+/// `text` in Unicode normalization form C
+pub(crate) fn compose_unicode(text: &str) -> String {
+    text.nfc().collect()
+}
+
+/// `path` resolved through symlinks and `..`, as stored_path text.
+pub(crate) fn canonical_path(path: &Path) -> io::Result<String> {
+    path.canonicalize().map(|p| stored_path(&p))
+}
+
+/// Check that `path` can serve as the library root: an existing directory
+/// Terra can write to. Returns the canonical path to store.
+pub(crate) fn validate_library_root(path: &Path) -> Result<PathBuf, String> {
+    if !path.is_dir() {
+        return Err(format!("{} is not an existing folder", path.display()));
+    }
+    let probe = path.join(".terra-write-test");
+    fs::write(&probe, b"")
+        .map_err(|e| format!("Terra can't write to {}: {}", path.display(), e))?;
+    let _ = fs::remove_file(&probe);
+    canonical_path(path)
+        .map(PathBuf::from)
+        .map_err(|e| format!("Failed to resolve {}: {}", path.display(), e))
+}
+
+/// How a file got into the library.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Transfer {
+    /// Copied; the original is untouched
+    Copied,
+    /// Renamed into place (same filesystem)
+    Moved,
+    /// Copied across filesystems, verified, then the original deleted
+    CopiedThenDeleted,
+    /// An identical file was already at the destination; nothing was written
+    Existing,
+}
+
+impl Transfer {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            Transfer::Copied => "copied",
+            Transfer::Moved => "moved",
+            Transfer::CopiedThenDeleted => "copied_then_deleted",
+            Transfer::Existing => "existing",
+        }
+    }
+}
+
+/// Move `source` to `dest` (usually a placeholder from `create_unique`, which
+/// is replaced). A rename is tried first; when that fails, e.g. across
+/// filesystems, the file is copied and verified before the original goes.
+/// Returns how it was done and the file's size.
+pub(crate) fn move_file(source: &Path, dest: &Path) -> io::Result<(Transfer, u64)> {
+    if fs::rename(source, dest).is_ok() {
+        // Never an error past this point: the original is already gone
+        return Ok((Transfer::Moved, fs::metadata(dest).map(|m| m.len()).unwrap_or(0)));
+    }
+    copy_verify_delete(source, dest)
+}
+
+/// Copy, confirm size and SHA-256 match, then delete the original. A copy
+/// that doesn't match is removed and the original kept. If the original
+/// can't be deleted (read-only card) the verified copy stays and the result
+/// is `Copied`.
+fn copy_verify_delete(source: &Path, dest: &Path) -> io::Result<(Transfer, u64)> {
+    let hash = media::calculate_hash(source)
+        .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, format!("can't read {}", source.display())))?;
+    let bytes = copy_verified(source, dest, Some(&hash))?;
+    match fs::remove_file(source) {
+        Ok(()) => Ok((Transfer::CopiedThenDeleted, bytes)),
+        Err(e) => {
+            log::warn!("Copied {} but couldn't remove the original: {}", source.display(), e);
+            Ok((Transfer::Copied, bytes))
+        }
+    }
+}
+
+/// Copy `source` over `dest` and check the result: the length always, and
+/// the SHA-256 when `expected_hash` is given. A copy that doesn't match
+/// (flaky USB enclosures truncate) is retried once, then removed and
+/// reported as `InvalidData`. The copy keeps the source's modified time.
+pub(crate) fn copy_verified(source: &Path, dest: &Path, expected_hash: Option<&str>) -> io::Result<u64> {
+    let expected_len = fs::metadata(source)?.len();
+    for attempt in 1..=2 {
+        let bytes = fs::copy(source, dest)?;
+        let len_ok = bytes == expected_len && fs::metadata(dest)?.len() == expected_len;
+        let hash_ok = expected_hash.map_or(true, |h| media::calculate_hash(dest).as_deref() == Some(h));
+        if len_ok && hash_ok {
+            preserve_mtime(source, dest);
+            return Ok(bytes);
+        }
+        log::warn!("Copy of {} didn't verify (attempt {})", source.display(), attempt);
+    }
+    let _ = fs::remove_file(dest);
+    Err(io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("copy of {} didn't match the original; original kept", source.display()),
+    ))
+}
+
+/// Give `dest` the modified time of `source`. A fresh copy would otherwise
+/// date from now, which breaks the mtime fallback for undated files.
+/// Best effort: some filesystems (FAT cards, network shares) refuse it.
+fn preserve_mtime(source: &Path, dest: &Path) {
+    let result = fs::metadata(source)
+        .and_then(|meta| filetime::set_file_mtime(dest, FileTime::from_last_modification_time(&meta)));
+    if let Err(e) = result {
+        log::warn!("Couldn't keep the modified time of {}: {}", source.display(), e);
+    }
+}
+
+/// The `<root>/YYYY/MM` folder a library file dated `timestamp` is filed in.
+pub(crate) fn month_folder(root: &Path, timestamp: i64) -> Option<PathBuf> {
+    let date = chrono::DateTime::from_timestamp(timestamp, 0)?;
+    Some(root.join(date.format("%Y").to_string()).join(date.format("%m").to_string()))
+}
+
+/// Where a file filed by month under `root` belongs once its date is
+/// `timestamp`. None when it is already in the right folder, or isn't in a
+/// `YYYY/MM` folder of the library at all.
+pub(crate) fn refiled_destination(path: &Path, root: &Path, timestamp: i64) -> Option<PathBuf> {
+    let folder = path.parent()?.strip_prefix(root).ok()?;
+    let parts: Vec<&str> = folder.iter().filter_map(|part| part.to_str()).collect();
+    let is_month_folder = matches!(parts.as_slice(), [year, month]
+        if year.len() == 4 && month.len() == 2 && year.chars().chain(month.chars()).all(|c| c.is_ascii_digit()));
+    if !is_month_folder {
+        return None;
+    }
+    let target = month_folder(root, timestamp)?;
+    (path.parent()? != target).then(|| target.join(path.file_name().unwrap_or_default()))
+}
+
+/// A user-given name (an album's) made safe as a single folder name on any
+/// OS: path separators and characters Windows rejects become `_`, and
+/// leading and trailing dots and spaces are dropped.
+pub(crate) fn folder_name(name: &str) -> String {
+    let cleaned: String = name
+        .chars()
+        .map(|c| if matches!(c, '/' | '\\' | ':' | '*' | '?' | '"' | '<' | '>' | '|') || c.is_control() { '_' } else { c })
+        .collect();
+    let cleaned = cleaned.trim_matches(|c: char| c == '.' || c.is_whitespace());
+    if cleaned.is_empty() {
+        "Untitled".to_string()
+    } else {
+        cleaned.to_string()
+    }
+}
+
+/// Characters this platform won't take in a file name. Windows rejects more
+/// than the separators and NUL everyone rejects.
+const ILLEGAL_NAME_CHARS: &[char] =
+    if cfg!(windows) { &['/', '\\', ':', '*', '?', '"', '<', '>', '|', '\0'] } else { &['/', '\0'] };
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_WINDOWS_NAMES: [&str; 22] = [
+    "con", "prn", "aux", "nul", "com1", "com2", "com3", "com4", "com5", "com6", "com7", "com8", "com9", "lpt1",
+    "lpt2", "lpt3", "lpt4", "lpt5", "lpt6", "lpt7", "lpt8", "lpt9",
+];
+
+/// The file name a photo at `original` gets when renamed to `new_name`.
+/// The original extension is kept (and added if `new_name` lacks it)
+/// unless `change_extension` is set, when `new_name` is used as given.
+/// Errors name what is wrong with `new_name`.
+pub(crate) fn renamed_file_name(original: &Path, new_name: &str, change_extension: bool) -> Result<String, String> {
+    let trimmed = new_name.trim();
+    if trimmed.is_empty() || trimmed == "." || trimmed == ".." {
+        return Err("Enter a file name".to_string());
+    }
+    if let Some(c) = trimmed.chars().find(|c| ILLEGAL_NAME_CHARS.contains(c) || c.is_control()) {
+        return Err(format!("File names can't contain {:?}", c));
+    }
+    if cfg!(windows) && trimmed.ends_with('.') {
+        return Err("File names can't end with a dot".to_string());
+    }
+
+    let name = match original.extension().map(|e| e.to_string_lossy()) {
+        Some(ext) if !change_extension => {
+            let suffix = format!(".{}", ext);
+            let has_suffix = trimmed.len() > suffix.len()
+                && trimmed.is_char_boundary(trimmed.len() - suffix.len())
+                && trimmed[trimmed.len() - suffix.len()..].eq_ignore_ascii_case(&suffix);
+            if has_suffix {
+                trimmed.to_string()
+            } else {
+                format!("{}{}", trimmed, suffix)
+            }
+        }
+        _ => trimmed.to_string(),
+    };
+    let stem = name.split('.').next().unwrap_or_default().to_lowercase();
+    if cfg!(windows) && RESERVED_WINDOWS_NAMES.contains(&stem.as_str()) {
+        return Err(format!("{} is reserved by Windows", name));
+    }
+    Ok(name)
+}
+
+/// Return `dest`, or `name_1.ext`, `name_2.ext`, ... if it is already taken.
+pub(crate) fn unique_destination(dest: PathBuf) -> PathBuf {
+    unique_destination_avoiding(dest, |path| path.exists())
+}
+
+/// Like `unique_destination`, with `taken` deciding which names are in use,
+/// so a batch can also avoid the names it has handed out itself.
+pub(crate) fn unique_destination_avoiding(dest: PathBuf, taken: impl Fn(&Path) -> bool) -> PathBuf {
+    if !taken(&dest) {
+        return dest;
+    }
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let ext = dest
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut counter = 1;
+    loop {
+        let candidate = dest.with_file_name(format!("{}_{}{}", stem, counter, ext));
+        if !taken(&candidate) {
+            return candidate;
+        }
+        counter += 1;
+    }
+}
+
+/// Where renaming `source` to `dest` puts it: `dest`, or a numbered name
+/// beside it when `taken` says the name is in use. None when `dest` is
+/// `source` itself, so there is nothing to rename. On a filesystem that
+/// ignores case, a case-only change is "taken" by the file itself and goes
+/// ahead as asked.
+pub(crate) fn rename_destination(
+    source: &Path,
+    dest: PathBuf,
+    case_insensitive: bool,
+    taken: impl Fn(&Path) -> bool,
+) -> Option<PathBuf> {
+    if dest == source {
+        return None;
+    }
+    if case_insensitive && dest.to_string_lossy().eq_ignore_ascii_case(&source.to_string_lossy()) {
+        return Some(dest);
+    }
+    Some(unique_destination_avoiding(dest, taken))
+}
+
+/// Like `unique_destination`, but claims the name by creating the file with
+/// create_new (O_EXCL), so concurrent copies into one folder never pick the
+/// same name. Returns the chosen path and the open, empty file.
+pub(crate) fn create_unique(dest: &Path) -> io::Result<(PathBuf, fs::File)> {
+    let stem = dest.file_stem().unwrap_or_default().to_string_lossy().to_string();
+    let ext = dest
+        .extension()
+        .map(|e| format!(".{}", e.to_string_lossy()))
+        .unwrap_or_default();
+    let mut candidate = dest.to_path_buf();
+    let mut counter = 1;
+    loop {
+        match fs::OpenOptions::new().write(true).create_new(true).open(&candidate) {
+            Ok(file) => return Ok((candidate, file)),
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {
+                candidate = dest.with_file_name(format!("{}_{}{}", stem, counter, ext));
+                counter += 1;
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// The mount point of the removable drive `path` is on: a Windows drive, or
+/// a volume under /Volumes, /mnt, /media/<user> or /run/media/<user>. None
+/// for anything on the boot volume, which can't go away.
+pub(crate) fn volume_root(path: &Path) -> Option<PathBuf> {
+    use std::path::Component;
+
+    let mut components = path.components();
+    let first = components.next()?;
+    if let Component::Prefix(prefix) = first {
+        return Some(PathBuf::from(prefix.as_os_str()).join(std::path::MAIN_SEPARATOR_STR));
+    }
+    if first != Component::RootDir {
+        return None;
+    }
+
+    let names: Vec<&str> = components.filter_map(|c| c.as_os_str().to_str()).collect();
+    let depth = match names.as_slice() {
+        ["Volumes", ..] | ["mnt", ..] => 2,
+        ["media", ..] => 3,
+        ["run", "media", ..] => 4,
+        _ => return None,
+    };
+    // The photo itself can't be the mount point
+    if names.len() <= depth {
+        return None;
+    }
+    let mut root = PathBuf::from(std::path::MAIN_SEPARATOR_STR);
+    root.extend(&names[..depth]);
+    Some(root)
+}
+
+/// Whether `a` and `b` are on the same filesystem, so a move between them
+/// is a rename. False if either can't be read.
+pub(crate) fn same_device(a: &Path, b: &Path) -> bool {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        matches!((fs::metadata(a), fs::metadata(b)), (Ok(a), Ok(b)) if a.dev() == b.dev())
+    }
+    #[cfg(not(unix))]
+    {
+        // By drive letter or share; a volume mounted in a folder isn't told apart
+        use std::path::Component;
+        let drive = |p: &Path| match p.components().next() {
+            Some(Component::Prefix(prefix)) => Some(prefix.as_os_str().to_ascii_lowercase()),
+            _ => None,
+        };
+        a.exists() && b.exists() && drive(a).is_some() && drive(a) == drive(b)
+    }
+}
+
+/// Bytes copying (or, with `moving`, moving) `sources` into `dest_root`
+/// adds to its drive. Files moved within that drive are renamed and add
+/// nothing; unreadable ones are left out.
+pub(crate) fn space_needed<'a>(sources: impl IntoIterator<Item = &'a Path>, dest_root: &Path, moving: bool) -> u64 {
+    sources
+        .into_iter()
+        .filter(|source| !(moving && same_device(source, dest_root)))
+        .filter_map(|source| fs::metadata(source).ok())
+        .map(|meta| meta.len())
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Fresh (library, external) directory pair under the system temp dir.
+    fn setup(name: &str) -> (PathBuf, PathBuf) {
+        let base = std::env::temp_dir().join(format!("terra-library-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&base);
+        let library = base.join("library");
+        let external = base.join("external");
+        fs::create_dir_all(&library).unwrap();
+        fs::create_dir_all(&external).unwrap();
+        (library, external)
+    }
+
+    fn cleanup(library: &Path) {
+        let _ = fs::remove_dir_all(library.parent().unwrap());
+    }
+
+    #[test]
+    fn test_renaming_to_the_current_name_does_nothing() {
+        let source = Path::new("/lib/IMG_0001.jpg");
+        // The file itself holds its name, on any filesystem
+        for case_insensitive in [false, true] {
+            assert_eq!(rename_destination(source, source.to_path_buf(), case_insensitive, |_| true), None);
+        }
+        assert_eq!(
+            rename_destination(source, PathBuf::from("/lib/img_0001.jpg"), true, |_| true),
+            Some(PathBuf::from("/lib/img_0001.jpg"))
+        );
+        assert_eq!(
+            rename_destination(source, PathBuf::from("/lib/img_0001.jpg"), false, |p| p.ends_with("img_0001.jpg")),
+            Some(PathBuf::from("/lib/img_0001_1.jpg"))
+        );
+        assert_eq!(
+            rename_destination(source, PathBuf::from("/lib/beach.jpg"), false, |_| false),
+            Some(PathBuf::from("/lib/beach.jpg"))
+        );
+    }
+
+    #[test]
+    fn test_moves_within_a_drive_need_no_space() {
+        let (library, external) = setup("space");
+        let a = external.join("a.jpg");
+        let b = external.join("b.jpg");
+        fs::write(&a, [0u8; 10]).unwrap();
+        fs::write(&b, [0u8; 5]).unwrap();
+        let missing = external.join("missing.jpg");
+        let sources = [a.as_path(), b.as_path(), missing.as_path()];
+
+        assert!(same_device(&a, &library));
+        assert!(!same_device(&missing, &library));
+        assert_eq!(space_needed(sources, &library, false), 15);
+        assert_eq!(space_needed(sources, &library, true), 0);
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_volume_root_finds_the_mount_point() {
+        assert_eq!(volume_root(Path::new("/Volumes/SSD/2023/a.jpg")), Some(PathBuf::from("/Volumes/SSD")));
+        assert_eq!(volume_root(Path::new("/mnt/photos/a.jpg")), Some(PathBuf::from("/mnt/photos")));
+        assert_eq!(volume_root(Path::new("/media/sam/SSD/a.jpg")), Some(PathBuf::from("/media/sam/SSD")));
+        assert_eq!(volume_root(Path::new("/run/media/sam/SSD/a.jpg")), Some(PathBuf::from("/run/media/sam/SSD")));
+        // Boot volume, or too shallow to hold a mount point and a file
+        assert_eq!(volume_root(Path::new("/Users/sam/Pictures/a.jpg")), None);
+        assert_eq!(volume_root(Path::new("/Volumes/a.jpg")), None);
+        assert_eq!(volume_root(Path::new("relative/a.jpg")), None);
+    }
+
+    #[test]
+    fn test_folder_name_is_a_single_safe_component() {
+        assert_eq!(folder_name("Trip: Rome/Florence"), "Trip_ Rome_Florence");
+        assert_eq!(folder_name("../x"), "_x");
+        assert_eq!(folder_name(".."), "Untitled");
+        assert_eq!(folder_name(" Best of 2024 "), "Best of 2024");
+    }
+
+    #[test]
+    fn test_renamed_file_name_keeps_the_extension() {
+        let original = Path::new("/lib/DSC_0042.JPG");
+        assert_eq!(renamed_file_name(original, "2019-06-01 Lake Tahoe", false).unwrap(), "2019-06-01 Lake Tahoe.JPG");
+        assert_eq!(renamed_file_name(original, " tahoe.jpg ", false).unwrap(), "tahoe.jpg");
+        // A dot in the name isn't taken for an extension
+        assert_eq!(renamed_file_name(original, "Mr. Smith", false).unwrap(), "Mr. Smith.JPG");
+        assert_eq!(renamed_file_name(original, "tahoe.jpeg", true).unwrap(), "tahoe.jpeg");
+        assert!(renamed_file_name(original, "  ", false).is_err());
+        assert!(renamed_file_name(original, "..", false).is_err());
+        assert!(renamed_file_name(original, "a/b", false).is_err());
+        assert!(renamed_file_name(original, "tab\there", false).is_err());
+        assert_eq!(renamed_file_name(Path::new("/lib/README"), "notes", false).unwrap(), "notes");
+    }
+
+    #[test]
+    fn test_refiled_destination_follows_the_new_month() {
+        let root = Path::new("/lib");
+        let photo = Path::new("/lib/2024/03/a.jpg");
+        // 2019-07-17
+        assert_eq!(refiled_destination(photo, root, 1_563_359_400), Some(PathBuf::from("/lib/2019/07/a.jpg")));
+        // 2024-03-10: already filed correctly
+        assert_eq!(refiled_destination(photo, root, 1_710_072_000), None);
+        // Not filed by month, or not in the library
+        assert_eq!(refiled_destination(Path::new("/lib/Imports/a.jpg"), root, 1_563_359_400), None);
+        assert_eq!(refiled_destination(Path::new("/elsewhere/2024/03/a.jpg"), root, 1_563_359_400), None);
+    }
+
+    #[test]
+    fn test_unique_destination_avoiding_skips_claimed_names() {
+        let claimed = [PathBuf::from("/lib/2023/11/a.jpg"), PathBuf::from("/lib/2023/11/a_1.jpg")];
+        let taken = |p: &Path| claimed.iter().any(|c| c == p);
+        assert_eq!(unique_destination_avoiding(PathBuf::from("/lib/2023/11/a.jpg"), taken), PathBuf::from("/lib/2023/11/a_2.jpg"));
+        assert_eq!(unique_destination_avoiding(PathBuf::from("/lib/2023/11/b.jpg"), taken), PathBuf::from("/lib/2023/11/b.jpg"));
+    }
+
+    #[test]
+    fn test_library_file_is_deleted() {
+        let (library, _external) = setup("inside");
+        let photo = library.join("2024").join("a.jpg");
+        fs::create_dir_all(photo.parent().unwrap()).unwrap();
+        fs::write(&photo, b"x").unwrap();
+
+        assert!(is_within_roots(&photo, &[&library]));
+        assert_eq!(file_action_on_delete(&photo, &[&library], false), FileAction::Delete);
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_scanned_external_file_is_kept_unless_requested() {
+        let (library, external) = setup("external");
+        let photo = external.join("b.jpg");
+        fs::write(&photo, b"x").unwrap();
+
+        assert!(!is_within_roots(&photo, &[&library]));
+        assert_eq!(file_action_on_delete(&photo, &[&library], false), FileAction::Keep);
+        assert_eq!(file_action_on_delete(&photo, &[&library], true), FileAction::Delete);
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_deletion_modes_parse() {
+        assert_eq!("system_trash".parse::<DeletionMode>(), Ok(DeletionMode::SystemTrash));
+        assert_eq!("terra_trash".parse::<DeletionMode>(), Ok(DeletionMode::TerraTrash));
+        assert_eq!("permanent".parse::<DeletionMode>(), Ok(DeletionMode::Permanent));
+        assert!("shred".parse::<DeletionMode>().is_err());
+    }
+
+    #[test]
+    fn test_system_trash_failure_keeps_the_file() {
+        let (library, _external) = setup("dispose");
+        let photo = library.join("a.jpg");
+        fs::write(&photo, b"x").unwrap();
+
+        let refused = dispose_file(&photo, DeletionMode::SystemTrash, |_| Err("no trash on this mount".to_string()));
+        assert!(matches!(refused, Disposal::Kept(ref e) if e.contains("no trash on this mount")));
+        assert!(photo.exists());
+
+        let mut trashed = None;
+        let disposal = dispose_file(&photo, DeletionMode::SystemTrash, |p| {
+            trashed = Some(p.to_path_buf());
+            Ok(())
+        });
+        assert_eq!(disposal, Disposal::SystemTrashed);
+        assert_eq!(trashed.as_deref(), Some(photo.as_path()));
+        // Left to the (mocked) trash, not deleted by Terra
+        assert!(photo.exists());
+
+        let disposal = dispose_file(&photo, DeletionMode::Permanent, |_| panic!("permanent mode used the trash"));
+        assert_eq!(disposal, Disposal::Deleted);
+        assert!(!photo.exists());
+        assert!(matches!(dispose_file(&photo, DeletionMode::Permanent, |_| Ok(())), Disposal::Kept(_)));
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_sibling_directory_with_shared_prefix_is_outside() {
+        let (library, _external) = setup("prefix");
+        let sibling = library.with_file_name("library-old");
+        fs::create_dir_all(&sibling).unwrap();
+        let photo = sibling.join("c.jpg");
+        fs::write(&photo, b"x").unwrap();
+
+        assert!(!is_within_roots(&photo, &[&library]));
+        cleanup(&library);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_symlinks_never_lead_out_of_the_library() {
+        use std::os::unix::fs::symlink;
+
+        let (library, external) = setup("symlink");
+        let outside = external.join("original.jpg");
+        fs::write(&outside, b"x").unwrap();
+        let inside = library.join("inside.jpg");
+        fs::write(&inside, b"x").unwrap();
+
+        // Link in the library pointing at an external original
+        let link_out = library.join("link-out.jpg");
+        symlink(&outside, &link_out).unwrap();
+        assert!(!is_within_roots(&link_out, &[&library]));
+
+        // Link outside the library pointing at a library file
+        let link_in = external.join("link-in.jpg");
+        symlink(&inside, &link_in).unwrap();
+        assert!(!is_within_roots(&link_in, &[&library]));
+
+        // Symlinked directory inside the library that leads elsewhere
+        let linked_dir = library.join("linked-dir");
+        symlink(&external, &linked_dir).unwrap();
+        assert!(!is_within_roots(&linked_dir.join("original.jpg"), &[&library]));
+
+        // Deleting a link removes the link, not its target
+        delete_file_permanently(&link_out).unwrap();
+        assert!(outside.exists());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_windows_form_strips_verbatim_prefixes() {
+        assert_eq!(windows_form(r"\\?\C:\Users\me\Pictures\a.jpg"), r"C:\Users\me\Pictures\a.jpg");
+        assert_eq!(windows_form(r"\\?\UNC\nas\photos\a.jpg"), r"\\nas\photos\a.jpg");
+        assert_eq!(windows_form(r"C:/Users/me/a.jpg"), r"C:\Users\me\a.jpg");
+        assert_eq!(windows_form(r"\\nas\photos\a.jpg"), r"\\nas\photos\a.jpg");
+    }
+
+    #[test]
+    fn test_storage_form_by_platform() {
+        let decomposed = "/Users/me/cafe\u{301}.jpg";
+        assert_eq!(storage_form(decomposed, false, false), decomposed);
+        assert_eq!(storage_form(decomposed, false, true), "/Users/me/caf\u{e9}.jpg");
+        assert_eq!(storage_form(r"\\?\D:\Fotos\a.jpg", true, false), r"D:\Fotos\a.jpg");
+        // Only Windows paths lose a prefix; on Unix a backslash is part of a name
+        assert_eq!(storage_form(r"/tmp/a\b.jpg", false, false), r"/tmp/a\b.jpg");
+    }
+
+    #[cfg(windows)]
+    #[test]
+    fn test_canonical_path_has_no_verbatim_prefix() {
+        let (library, _) = setup("canonical");
+        let photo = library.join("a.jpg");
+        fs::write(&photo, b"x").unwrap();
+        let stored = canonical_path(&photo).unwrap();
+        assert!(!stored.starts_with(r"\\?\"), "{}", stored);
+        assert!(stored.ends_with(r"library\a.jpg"), "{}", stored);
+        assert!(Path::new(&stored).is_file());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_validate_library_root() {
+        let (library, external) = setup("validate");
+        assert_eq!(validate_library_root(&library).unwrap(), PathBuf::from(canonical_path(&library).unwrap()));
+        assert!(!library.join(".terra-write-test").exists());
+
+        assert!(validate_library_root(&library.join("missing")).is_err());
+        let file = external.join("file.jpg");
+        fs::write(&file, b"x").unwrap();
+        assert!(validate_library_root(&file).is_err());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_move_file_renames_within_a_filesystem() {
+        let (library, external) = setup("move");
+        let source = external.join("e.jpg");
+        fs::write(&source, b"photo bytes").unwrap();
+        let (dest, _file) = create_unique(&library.join("e.jpg")).unwrap();
+
+        assert_eq!(move_file(&source, &dest).unwrap().0, Transfer::Moved);
+        assert!(!source.exists());
+        assert_eq!(fs::read(&dest).unwrap(), b"photo bytes");
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_copy_verify_delete_removes_original_after_verifying() {
+        let (library, external) = setup("copy-verify");
+        let source = external.join("f.jpg");
+        fs::write(&source, b"more photo bytes").unwrap();
+        let (dest, _file) = create_unique(&library.join("f.jpg")).unwrap();
+
+        assert_eq!(copy_verify_delete(&source, &dest).unwrap(), (Transfer::CopiedThenDeleted, 16));
+        assert!(!source.exists());
+        assert_eq!(fs::read(&dest).unwrap(), b"more photo bytes");
+
+        // A missing original fails without touching anything else
+        assert!(copy_verify_delete(&source, &library.join("g.jpg")).is_err());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_copy_verified_rejects_a_wrong_hash() {
+        let (library, external) = setup("copy-verified");
+        let source = external.join("h.jpg");
+        fs::write(&source, b"abc").unwrap();
+        let hash = media::calculate_hash(&source).unwrap();
+
+        let good = library.join("good.jpg");
+        assert_eq!(copy_verified(&source, &good, Some(&hash)).unwrap(), 3);
+        assert_eq!(copy_verified(&source, &library.join("fast.jpg"), None).unwrap(), 3);
+
+        let bad = library.join("bad.jpg");
+        let err = copy_verified(&source, &bad, Some("not-the-hash")).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        assert!(!bad.exists());
+        assert!(source.exists());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_copy_verified_keeps_the_source_mtime() {
+        let (library, external) = setup("copy-mtime");
+        let source = external.join("old.jpg");
+        fs::write(&source, b"2009").unwrap();
+        let taken = FileTime::from_unix_time(1_234_567_890, 0);
+        filetime::set_file_mtime(&source, taken).unwrap();
+
+        let dest = library.join("old.jpg");
+        copy_verified(&source, &dest, None).unwrap();
+        let copied = FileTime::from_last_modification_time(&fs::metadata(&dest).unwrap());
+        assert_eq!(copied.unix_seconds(), taken.unix_seconds());
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_create_unique_is_safe_across_threads() {
+        let (library, _external) = setup("create-unique");
+        let dest = library.join("IMG_0001.jpg");
+        fs::write(&dest, b"existing").unwrap();
+
+        let handles: Vec<_> = (0..8)
+            .map(|_| {
+                let dest = dest.clone();
+                std::thread::spawn(move || create_unique(&dest).unwrap().0)
+            })
+            .collect();
+        let mut paths: Vec<PathBuf> = handles.into_iter().map(|h| h.join().unwrap()).collect();
+        paths.sort();
+        paths.dedup();
+
+        assert_eq!(paths.len(), 8);
+        assert!(!paths.contains(&dest));
+        assert!(paths.contains(&library.join("IMG_0001_1.jpg")));
+        assert_eq!(fs::read(&dest).unwrap(), b"existing");
+        cleanup(&library);
+    }
+
+    #[test]
+    fn test_unique_destination_appends_counter() {
+        let (library, _external) = setup("unique");
+        let dest = library.join("d.jpg");
+        assert_eq!(unique_destination(dest.clone()), dest);
+
+        fs::write(&dest, b"x").unwrap();
+        assert_eq!(unique_destination(dest.clone()), library.join("d_1.jpg"));
+        fs::write(library.join("d_1.jpg"), b"x").unwrap();
+        assert_eq!(unique_destination(dest), library.join("d_2.jpg"));
+        cleanup(&library);
+    }
+}
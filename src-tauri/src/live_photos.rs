@@ -0,0 +1,208 @@
+//! Live Photos: an iPhone still (HEIC or JPEG) plus a 2-3 second video
+//! with the same file stem. Pairs are found by name and, when both halves
+//! carry Apple's content identifier, confirmed by it.
+//!
+//! No database access; callers record pairs with `db::set_live_photo_pairs`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::heif;
+use crate::video;
+
+const STILL_EXTENSIONS: [&str; 4] = ["heic", "heif", "jpg", "jpeg"];
+/// Takeout exports the motion part as MP4
+const MOTION_EXTENSIONS: [&str; 2] = ["mov", "mp4"];
+/// Without content identifiers to compare, longer videos aren't Live Photos.
+const MAX_MOTION_MS: i64 = 5_000;
+/// JPEG EXIF, maker note included, sits in the first APP1 segment.
+const EXIF_SEARCH_BYTES: u64 = 128 * 1024;
+const APPLE_MAKER_NOTE: &[u8] = b"Apple iOS\0";
+/// Maker note tag holding the content identifier shared with the video.
+const TAG_CONTENT_IDENTIFIER: u16 = 0x0011;
+
+fn has_extension(path: &Path, extensions: &[&str]) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| extensions.contains(&ext.to_lowercase().as_str()))
+}
+
+/// Find Live Photo pairs among `paths`, as (still, video). The other half
+/// may be any file next to the given one, so a pair is found whichever half
+/// is imported first.
+pub(crate) fn find_pairs<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<(String, String)> {
+    let mut by_dir: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    for path in paths.into_iter().map(Path::new) {
+        if !has_extension(path, &STILL_EXTENSIONS) && !has_extension(path, &MOTION_EXTENSIONS) {
+            continue;
+        }
+        if let (Some(dir), Some(stem)) = (path.parent(), path.file_stem()) {
+            by_dir
+                .entry(dir.to_path_buf())
+                .or_default()
+                .insert(stem.to_string_lossy().to_lowercase());
+        }
+    }
+
+    let mut pairs = Vec::new();
+    for (dir, stems) in by_dir {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        // Real names from the listing, so case matches what was imported
+        let mut groups: HashMap<String, (Vec<PathBuf>, Vec<PathBuf>)> = HashMap::new();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            let Some(stem) = path.file_stem().map(|s| s.to_string_lossy().to_lowercase()) else {
+                continue;
+            };
+            if !stems.contains(&stem) {
+                continue;
+            }
+            let group = groups.entry(stem).or_default();
+            if has_extension(&path, &STILL_EXTENSIONS) {
+                group.0.push(path);
+            } else if has_extension(&path, &MOTION_EXTENSIONS) {
+                group.1.push(path);
+            }
+        }
+        for (stills, videos) in groups.into_values() {
+            // Anything but one of each is ambiguous
+            if let ([still], [video]) = (stills.as_slice(), videos.as_slice()) {
+                if is_pair(still, video) {
+                    pairs.push((still.to_string_lossy().to_string(), video.to_string_lossy().to_string()));
+                }
+            }
+        }
+    }
+    pairs
+}
+
+/// Confirm a same-stem still and video belong together.
+fn is_pair(still: &Path, video: &Path) -> bool {
+    match (still_content_identifier(still), video::content_identifier(video)) {
+        (Some(a), Some(b)) => a.eq_ignore_ascii_case(&b),
+        _ => video::read_video_info(video)
+            .and_then(|info| info.duration_ms)
+            .is_some_and(|ms| ms <= MAX_MOTION_MS),
+    }
+}
+
+/// The content identifier in a still's Apple maker note.
+fn still_content_identifier(path: &Path) -> Option<String> {
+    let exif = if heif::is_heif(path) {
+        heif::read_exif(path)?
+    } else {
+        let mut head = Vec::new();
+        fs::File::open(path).ok()?.take(EXIF_SEARCH_BYTES).read_to_end(&mut head).ok()?;
+        head
+    };
+    let start = exif.windows(APPLE_MAKER_NOTE.len()).position(|w| w == APPLE_MAKER_NOTE)?;
+    maker_note_content_identifier(&exif[start..])
+}
+
+/// Read tag 0x0011 from an Apple maker note: the "Apple iOS" header, a
+/// version, "MM", then a big-endian IFD whose offsets count from the header.
+fn maker_note_content_identifier(note: &[u8]) -> Option<String> {
+    let be_u16 = |at: usize| Some(u16::from_be_bytes(note.get(at..at + 2)?.try_into().ok()?));
+    let be_u32 = |at: usize| Some(u32::from_be_bytes(note.get(at..at + 4)?.try_into().ok()?));
+    if note.get(12..14)? != b"MM" {
+        return None;
+    }
+    let count = usize::from(be_u16(14)?);
+    let entry = (0..count).map(|i| 16 + i * 12).find(|&at| be_u16(at) == Some(TAG_CONTENT_IDENTIFIER))?;
+    // ASCII
+    if be_u16(entry + 2)? != 2 {
+        return None;
+    }
+    let len = be_u32(entry + 4)? as usize;
+    let value = if len <= 4 {
+        note.get(entry + 8..entry + 8 + len)?
+    } else {
+        let offset = be_u32(entry + 8)? as usize;
+        note.get(offset..offset.checked_add(len)?)?
+    };
+    let id = std::str::from_utf8(value).ok()?.trim_end_matches('\0');
+    (!id.is_empty()).then(|| id.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const UUID: &str = "1A2B3C4D-0000-4000-8000-0123456789AB";
+
+    /// An Apple maker note with a single content identifier entry.
+    fn maker_note(id: &str) -> Vec<u8> {
+        let mut note = APPLE_MAKER_NOTE.to_vec();
+        note.extend_from_slice(&[0, 1]);
+        note.extend_from_slice(b"MM");
+        note.extend_from_slice(&1u16.to_be_bytes());
+        let value_at = 16 + 12 + 4;
+        note.extend_from_slice(&TAG_CONTENT_IDENTIFIER.to_be_bytes());
+        note.extend_from_slice(&2u16.to_be_bytes());
+        note.extend_from_slice(&(id.len() as u32 + 1).to_be_bytes());
+        note.extend_from_slice(&(value_at as u32).to_be_bytes());
+        note.extend_from_slice(&0u32.to_be_bytes());
+        note.extend_from_slice(id.as_bytes());
+        note.push(0);
+        note
+    }
+
+    /// A JPEG whose APP1 holds just enough EXIF to carry the maker note.
+    fn jpeg_with_note(id: &str) -> Vec<u8> {
+        let mut app1 = b"Exif\0\0MM\0*\0\0\0\x08".to_vec();
+        app1.extend(maker_note(id));
+        let mut out = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        out.extend(((app1.len() + 2) as u16).to_be_bytes());
+        out.extend(app1);
+        out.extend_from_slice(&[0xFF, 0xD9]);
+        out
+    }
+
+    #[test]
+    fn reads_the_maker_note_content_identifier() {
+        assert_eq!(maker_note_content_identifier(&maker_note(UUID)).as_deref(), Some(UUID));
+        assert_eq!(maker_note_content_identifier(b"Apple iOS\0\0\x01II\0\0"), None);
+    }
+
+    /// A MOV with only a movie header, `seconds` long.
+    fn mov(seconds: u32) -> Vec<u8> {
+        let atom = |kind: &[u8], body: &[u8]| {
+            let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+            out.extend_from_slice(kind);
+            out.extend_from_slice(body);
+            out
+        };
+        let mut mvhd = vec![0u8; 100];
+        mvhd[12..16].copy_from_slice(&600u32.to_be_bytes());
+        mvhd[16..20].copy_from_slice(&(600 * seconds).to_be_bytes());
+        let mut out = atom(b"ftyp", b"qt  \0\0\0\0");
+        out.extend(atom(b"moov", &atom(b"mvhd", &mvhd)));
+        out
+    }
+
+    #[test]
+    fn pairs_short_same_stem_videos_with_their_stills() {
+        let dir = std::env::temp_dir().join(format!("terra-live-photos-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("IMG_0001.JPG"), jpeg_with_note(UUID)).unwrap();
+        fs::write(dir.join("IMG_0001.MOV"), mov(3)).unwrap();
+        // A regular clip that happens to share a name
+        fs::write(dir.join("IMG_0002.JPG"), b"jpeg").unwrap();
+        fs::write(dir.join("IMG_0002.MOV"), mov(60)).unwrap();
+        fs::write(dir.join("IMG_0003.HEIC"), b"heic").unwrap();
+
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        // Either half finds the pair, and it is reported once
+        let pairs = find_pairs([path("IMG_0001.MOV"), path("IMG_0001.JPG"), path("IMG_0002.JPG"), path("IMG_0003.HEIC")]
+            .iter()
+            .map(String::as_str));
+        assert_eq!(pairs, vec![(path("IMG_0001.JPG"), path("IMG_0001.MOV"))]);
+
+        assert_eq!(still_content_identifier(&dir.join("IMG_0001.JPG")).as_deref(), Some(UUID));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
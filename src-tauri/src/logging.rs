@@ -0,0 +1,161 @@
+//! Log output: a file next to the database that users can attach to bug
+//! reports, rotated before it grows past MAX_LOG_BYTES, plus stderr in
+//! debug builds.
+//!
+//! Terra's own records pass env_logger's filter at every level, so the
+//! `log_level` setting decides through `log::set_max_level`. The log macros
+//! check that first, so a disabled per-photo `debug!` costs one comparison
+//! and never formats its message. Other crates log warnings and errors only.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+use log::LevelFilter;
+
+/// Setting holding the level name: error, warn, info, debug or trace
+pub const LOG_LEVEL_SETTING: &str = "log_level";
+
+/// Level until the setting says otherwise
+pub const DEFAULT_LOG_LEVEL: LevelFilter = LevelFilter::Info;
+
+/// Size at which terra.log is renamed terra.log.1 (replacing the previous
+/// one) and a fresh file started
+const MAX_LOG_BYTES: u64 = 5 * 1024 * 1024;
+
+const LOG_FILE_NAME: &str = "terra.log";
+
+/// Most lines get_recent_logs returns
+pub const MAX_RECENT_LINES: usize = 5000;
+
+/// terra.log in the Terra data directory, beside the database
+pub fn log_file_path() -> PathBuf {
+    crate::db::get_db_path().with_file_name(LOG_FILE_NAME)
+}
+
+fn rotated_path(path: &Path) -> PathBuf {
+    path.with_extension("log.1")
+}
+
+/// Install the logger. RUST_LOG can still narrow the output per module.
+pub fn init() {
+    let file = RotatingFile::open(log_file_path(), MAX_LOG_BYTES);
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Warn)
+        .filter_module(env!("CARGO_CRATE_NAME"), LevelFilter::Trace)
+        .parse_env("RUST_LOG")
+        .write_style(env_logger::WriteStyle::Never)
+        .target(env_logger::Target::Pipe(Box::new(file)))
+        .init();
+    log::set_max_level(DEFAULT_LOG_LEVEL);
+}
+
+/// Parse a level name as stored in LOG_LEVEL_SETTING
+pub fn parse_level(name: &str) -> Result<LevelFilter, String> {
+    match name.trim().to_lowercase().as_str() {
+        "error" => Ok(LevelFilter::Error),
+        "warn" => Ok(LevelFilter::Warn),
+        "info" => Ok(LevelFilter::Info),
+        "debug" => Ok(LevelFilter::Debug),
+        "trace" => Ok(LevelFilter::Trace),
+        _ => Err(format!("Unknown log level '{}': use error, warn, info, debug or trace", name)),
+    }
+}
+
+/// Switch to the stored level, or the default if it's unset or unreadable
+pub fn apply_level(name: Option<&str>) {
+    log::set_max_level(name.and_then(|n| parse_level(n).ok()).unwrap_or(DEFAULT_LOG_LEVEL));
+}
+
+/// The last `count` lines logged, reaching into the rotated file when the
+/// current one is shorter.
+pub fn recent_lines(path: &Path, count: usize) -> io::Result<Vec<String>> {
+    let mut text = String::new();
+    for file in [rotated_path(path), path.to_path_buf()] {
+        match fs::read(&file) {
+            Ok(bytes) => text.push_str(&String::from_utf8_lossy(&bytes)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e),
+        }
+    }
+    let lines: Vec<&str> = text.lines().collect();
+    Ok(lines[lines.len().saturating_sub(count)..].iter().map(|l| l.to_string()).collect())
+}
+
+/// Appends to the log file, rotating it at `max_bytes`. If the file can't be
+/// opened records still reach stderr in debug builds and are otherwise dropped.
+struct RotatingFile {
+    path: PathBuf,
+    file: Option<File>,
+    written: u64,
+    max_bytes: u64,
+}
+
+impl RotatingFile {
+    fn open(path: PathBuf, max_bytes: u64) -> Self {
+        let file = OpenOptions::new().create(true).append(true).open(&path).ok();
+        let written = file.as_ref().and_then(|f| f.metadata().ok()).map_or(0, |m| m.len());
+        RotatingFile { path, file, written, max_bytes }
+    }
+
+    fn rotate(&mut self) {
+        self.file = None;
+        let _ = fs::rename(&self.path, rotated_path(&self.path));
+        self.file = OpenOptions::new().create(true).append(true).open(&self.path).ok();
+        self.written = 0;
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if cfg!(debug_assertions) {
+            let _ = io::stderr().write_all(buf);
+        }
+        if self.written > 0 && self.written + buf.len() as u64 > self.max_bytes {
+            self.rotate();
+        }
+        if let Some(file) = self.file.as_mut() {
+            file.write_all(buf)?;
+            self.written += buf.len() as u64;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.as_mut().map_or(Ok(()), |f| f.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_level_names() {
+        assert_eq!(parse_level("debug"), Ok(LevelFilter::Debug));
+        assert_eq!(parse_level(" WARN "), Ok(LevelFilter::Warn));
+        assert!(parse_level("verbose").unwrap_err().contains("verbose"));
+    }
+
+    #[test]
+    fn rotates_and_reads_recent_lines_across_files() {
+        let dir = std::env::temp_dir().join(format!("terra-logging-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(LOG_FILE_NAME);
+        assert!(recent_lines(&path, 10).unwrap().is_empty());
+
+        let mut log = RotatingFile::open(path.clone(), 20);
+        for i in 0..5 {
+            log.write_all(format!("line {}\n", i).as_bytes()).unwrap();
+        }
+        log.flush().unwrap();
+        // 7 bytes a line: rotated after the second and fourth
+        assert_eq!(fs::read_to_string(&path).unwrap(), "line 4\n");
+        assert_eq!(fs::read_to_string(rotated_path(&path)).unwrap(), "line 2\nline 3\n");
+        assert_eq!(recent_lines(&path, 2).unwrap(), vec!["line 3", "line 4"]);
+        assert_eq!(recent_lines(&path, 10).unwrap().len(), 3);
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -8,9 +8,12 @@
 use std::fs;
 use std::io::Read;
 use std::path::Path;
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::sync::atomic::{AtomicI32, Ordering};
+use std::time::UNIX_EPOCH;
 
-use chrono::NaiveDateTime;
+use chrono::{Datelike, FixedOffset, Local, NaiveDate, NaiveDateTime, Offset, TimeZone};
+use image::metadata::Orientation;
+use image::{ImageDecoder, ImageReader};
 use image_hasher::{HashAlg, HasherConfig};
 use lazy_static::lazy_static;
 use log::{debug, warn};
@@ -19,17 +22,49 @@ use reverse_geocoder::{Locations, ReverseGeocoder};
 use sha2::{Digest, Sha256};
 
 use crate::config;
+use crate::db;
+use crate::heif;
+use crate::library;
+use crate::raw;
+use crate::takeout;
+use crate::video;
 use crate::PhotoMetadata;
 
 // Cached regexes and reverse-geocoder data. Building the geocoder requires
 // loading the world city database; doing it once at startup pays for itself
 // after the first photo.
 lazy_static! {
-    static ref DATE_REGEX: Regex = Regex::new(r"(\d{4})[_-](\d{2})[_-](\d{2})").unwrap();
-    static ref TIME_REGEX: Regex = Regex::new(r"_(\d{2})(\d{2})(\d{2})").unwrap();
+    /// Filename date conventions, most specific first. Groups: y, m, d and,
+    /// when the name has a time, H, M, S and an optional AM/PM marker p.
+    static ref FILENAME_DATE_PATTERNS: Vec<Regex> = [
+        // macOS screenshots: "Screenshot 2023-01-15 at 14.30.45", older
+        // "Screen Shot 2020-01-15 at 2.30.45 PM"
+        r"(?P<y>\d{4})-(?P<m>\d{2})-(?P<d>\d{2}) at (?P<H>\d{1,2})\.(?P<M>\d{2})\.(?P<S>\d{2})(?:\s?(?P<p>[AP]M))?",
+        // Android and Pixel: IMG_20230115_143045, PXL_20230115_143045123,
+        // Screenshot_20230115-143045
+        r"(?:^|\D)(?P<y>\d{4})(?P<m>\d{2})(?P<d>\d{2})[_-](?P<H>\d{2})(?P<M>\d{2})(?P<S>\d{2})",
+        // Separated dates, optionally with a time: 2017-11-26_030858,
+        // IMG_2023_06_15, 2023-01-15 14.30.45
+        r"(?P<y>\d{4})[_-](?P<m>\d{2})[_-](?P<d>\d{2})(?:[_ T-](?P<H>\d{2})[.:_-]?(?P<M>\d{2})[.:_-]?(?P<S>\d{2}))?",
+        // WhatsApp: IMG-20230115-WA0012
+        r"-(?P<y>\d{4})(?P<m>\d{2})(?P<d>\d{2})-WA\d+",
+        // A bare compact date: 20230115.jpg, IMG_20230115.jpg
+        r"(?:^|\D)(?P<y>\d{4})(?P<m>\d{2})(?P<d>\d{2})(?:\D|$)",
+    ]
+    .iter()
+    .map(|pattern| Regex::new(pattern).unwrap())
+    .collect();
+    static ref XMP_DESCRIPTION_REGEX: Regex =
+        Regex::new(r"(?s)<dc:description\b[^>]*>\s*<rdf:(?:Alt|Bag|Seq)\b[^>]*>\s*<rdf:li\b[^>]*>(.*?)</rdf:li>")
+            .unwrap();
+    static ref XMP_RATING_REGEX: Regex =
+        Regex::new(r#"xmp:Rating\s*(?:=\s*["'](-?\d+)["']|>\s*(-?\d+)\s*<)"#).unwrap();
+    static ref XMP_LABEL_REGEX: Regex =
+        Regex::new(r#"xmp:Label\s*(?:=\s*"([^"]*)"|=\s*'([^']*)'|>([^<]*)<)"#).unwrap();
     pub(crate) static ref GEOCODER_LOCATIONS: Locations = Locations::from_memory();
+    // Anchored: a name that only contains "capture" further in is usually a photo
     static ref SCREENSHOT_REGEX: Regex =
-        Regex::new(r"(?i)(screenshot|screen[\s_-]?shot|capture|snip|grab)").unwrap();
+        Regex::new(r"(?i)^(screenshot|screen[\s_-]?shot|capture|snip|grab)").unwrap();
 }
 
 // Common phone/laptop screenshot dimensions. Hardcoded because the list
@@ -56,8 +91,12 @@ const SCREENSHOT_DIMENSIONS: &[(u32, u32)] = &[
 ];
 
 /// Parse an EXIF DateTimeOriginal field (format: `2023:01:15 14:30:45`).
-pub(crate) fn parse_exif_datetime(datetime_str: &str) -> Option<i64> {
+/// The result is the camera's wall-clock time; see `exif_timestamp`.
+pub(crate) fn parse_exif_datetime(datetime_str: &str) -> Option<NaiveDateTime> {
     let datetime_str = datetime_str.trim_end_matches('\0').trim();
+    if is_placeholder_datetime(datetime_str) {
+        return None;
+    }
     let cleaned = datetime_str.replace(':', "-");
     let parts: Vec<&str> = cleaned.split(' ').collect();
 
@@ -71,7 +110,7 @@ pub(crate) fn parse_exif_datetime(datetime_str: &str) -> Option<i64> {
     let combined = format!("{} {}", date_part, time_part);
 
     match NaiveDateTime::parse_from_str(&combined, "%Y-%m-%d %H:%M:%S") {
-        Ok(dt) => Some(dt.and_utc().timestamp()),
+        Ok(dt) => Some(dt),
         Err(e) => {
             debug!("Failed to parse EXIF datetime '{}': {}", combined, e);
             None
@@ -79,47 +118,163 @@ pub(crate) fn parse_exif_datetime(datetime_str: &str) -> Option<i64> {
     }
 }
 
-/// Try to extract a date from a filename like `2017-11-26_030858.jpeg`.
-pub(crate) fn parse_filename_date(filename: &str) -> Option<i64> {
-    let caps = DATE_REGEX.captures(filename)?;
-    let year: i32 = caps.get(1)?.as_str().parse().ok()?;
-    let month: u32 = caps.get(2)?.as_str().parse().ok()?;
-    let day: u32 = caps.get(3)?.as_str().parse().ok()?;
-
-    if year < config::MIN_VALID_YEAR
-        || year > config::MAX_VALID_YEAR
-        || month < 1
-        || month > 12
-        || day < 1
-        || day > 31
-    {
+/// Cameras with an unset clock and some editors write `0000:00:00 00:00:00`
+/// or a blank `    :  :     :  :  ` instead of leaving the tag out.
+fn is_placeholder_datetime(datetime_str: &str) -> bool {
+    datetime_str.chars().all(|c| matches!(c, '0' | ':' | ' '))
+}
+
+/// Whether an EXIF timestamp is a believable capture time: not before 1970
+/// and at most a day ahead of now (timezone slop).
+fn is_plausible_exif_timestamp(timestamp: i64, path: &Path) -> bool {
+    let latest = chrono::Utc::now().timestamp() + 24 * 60 * 60;
+    let plausible = (0..=latest).contains(&timestamp);
+    if !plausible {
+        debug!("Ignoring implausible EXIF date {} in {:?}", timestamp, path.file_name());
+    }
+    plausible
+}
+
+/// Parse an EXIF OffsetTime value (`+09:00`, `-05:00`) into minutes east of UTC.
+pub(crate) fn parse_exif_offset(offset_str: &str) -> Option<i32> {
+    let offset_str = offset_str.trim_end_matches('\0').trim();
+    let (sign, rest) = match offset_str.as_bytes().first()? {
+        b'+' => (1, &offset_str[1..]),
+        b'-' => (-1, &offset_str[1..]),
+        _ => return None,
+    };
+    let (hours, minutes) = rest.split_once(':')?;
+    let hours: i32 = hours.parse().ok()?;
+    let minutes: i32 = minutes.parse().ok()?;
+    // Real offsets run from -12:00 to +14:00
+    if hours > 14 || !(0..60).contains(&minutes) {
         return None;
     }
+    Some(sign * (hours * 60 + minutes))
+}
 
-    let (hour, min, sec) = if let Some(time_caps) = TIME_REGEX.captures(filename) {
-        (
-            time_caps.get(1)?.as_str().parse().ok()?,
-            time_caps.get(2)?.as_str().parse().ok()?,
-            time_caps.get(3)?.as_str().parse().ok()?,
-        )
-    } else {
-        (0, 0, 0)
+/// Sentinel for "use the machine's timezone" in DEFAULT_UTC_OFFSET.
+const LOCAL_TIMEZONE: i32 = i32::MIN;
+
+/// UTC offset, in minutes, assumed for EXIF times that don't record one.
+/// Mirrors the `default_utc_offset_minutes` setting.
+static DEFAULT_UTC_OFFSET: AtomicI32 = AtomicI32::new(LOCAL_TIMEZONE);
+
+/// Set the offset assumed for EXIF times without one; None means the
+/// machine's local timezone.
+pub(crate) fn set_default_utc_offset(minutes: Option<i32>) {
+    DEFAULT_UTC_OFFSET.store(minutes.unwrap_or(LOCAL_TIMEZONE), Ordering::Relaxed);
+}
+
+/// The offset assumed for EXIF times without one, or None for the machine's
+/// local timezone.
+pub(crate) fn default_utc_offset() -> Option<i32> {
+    let default = DEFAULT_UTC_OFFSET.load(Ordering::Relaxed);
+    (default != LOCAL_TIMEZONE).then_some(default)
+}
+
+/// Convert an EXIF wall-clock time to a Unix timestamp, using the offset
+/// recorded with it or else the default assumption. Returns the timestamp
+/// and the offset used, in minutes east of UTC.
+pub(crate) fn exif_timestamp(wall_clock: NaiveDateTime, offset_minutes: Option<i32>) -> Option<(i64, i32)> {
+    let offset_minutes = offset_minutes.or_else(default_utc_offset);
+    match offset_minutes {
+        Some(minutes) => {
+            let dt = FixedOffset::east_opt(minutes * 60)?.from_local_datetime(&wall_clock).single()?;
+            Some((dt.timestamp(), minutes))
+        }
+        // A time skipped by a DST change doesn't exist locally; the earlier
+        // reading of a repeated one is as good as the later
+        None => {
+            let dt = Local.from_local_datetime(&wall_clock).earliest()?;
+            Some((dt.timestamp(), dt.offset().local_minus_utc() / 60))
+        }
+    }
+}
+
+/// Where a photo's date_taken came from, as stored in `date_source`.
+/// Embedded metadata (EXIF, or the video container's creation time)
+pub(crate) const DATE_SOURCE_EXIF: &str = "exif";
+/// Another app's record: a Google Takeout sidecar, an Instagram/Facebook
+/// post, or a digiKam database
+pub(crate) const DATE_SOURCE_SIDECAR: &str = "sidecar";
+pub(crate) const DATE_SOURCE_FILENAME: &str = "filename";
+/// File modified time: often the copy or download date, not the capture
+pub(crate) const DATE_SOURCE_MTIME: &str = "mtime";
+pub(crate) const DATE_SOURCE_UNKNOWN: &str = "unknown";
+/// Set by the user; rescans keep it
+pub(crate) const DATE_SOURCE_MANUAL: &str = "manual";
+
+/// date_taken of a photo with no usable date. Sorts after every real date
+/// in a newest-first timeline.
+pub(crate) const UNKNOWN_DATE: i64 = 0;
+
+/// Whether a date from this source can be trusted as the capture time.
+pub(crate) fn is_confident_date_source(source: &str) -> bool {
+    matches!(source, DATE_SOURCE_EXIF | DATE_SOURCE_SIDECAR | DATE_SOURCE_FILENAME | DATE_SOURCE_MANUAL)
+}
+
+/// The inverse of `exif_timestamp`: the wall-clock time to write into EXIF
+/// for a timestamp, in `offset_minutes` or else the default assumption.
+/// Returns the time and the offset used.
+pub(crate) fn exif_wall_clock(timestamp: i64, offset_minutes: Option<i32>) -> Option<(NaiveDateTime, i32)> {
+    let offset_minutes = offset_minutes.or_else(default_utc_offset);
+    let offset = match offset_minutes {
+        Some(minutes) => FixedOffset::east_opt(minutes * 60)?,
+        None => Local.timestamp_opt(timestamp, 0).single()?.offset().fix(),
     };
+    let dt = offset.timestamp_opt(timestamp, 0).single()?;
+    Some((dt.naive_local(), offset.local_minus_utc() / 60))
+}
 
-    let date_str = format!(
-        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
-        year, month, day, hour, min, sec
-    );
-    NaiveDateTime::parse_from_str(&date_str, "%Y-%m-%d %H:%M:%S")
-        .ok()
+/// How far a filename date may stray from the embedded one before the
+/// difference is recorded as a discrepancy.
+const DATE_MISMATCH_SECS: i64 = 365 * 24 * 60 * 60;
+
+/// Try to extract a date from a filename like `2017-11-26_030858.jpeg`,
+/// `IMG_20230115_143045.jpg` or `Screenshot 2023-01-15 at 14.30.45.png`.
+pub(crate) fn parse_filename_date(filename: &str) -> Option<i64> {
+    let latest = chrono::Utc::now().timestamp() + 24 * 60 * 60;
+    FILENAME_DATE_PATTERNS
+        .iter()
+        .filter_map(|pattern| pattern.captures(filename))
+        .filter_map(|caps| filename_date_from(&caps))
+        .find(|dt| dt.year() >= config::MIN_VALID_YEAR && dt.and_utc().timestamp() <= latest)
         .map(|dt| {
-            debug!("Extracted date from filename '{}': {}", filename, date_str);
+            debug!("Extracted date from filename '{}': {}", filename, dt);
             dt.and_utc().timestamp()
         })
 }
 
-fn extract_exif_date(path: &Path) -> Option<i64> {
-    let exif_data = match rexif::parse_file(path) {
+fn filename_date_from(caps: &regex::Captures) -> Option<NaiveDateTime> {
+    let number = |name: &str| caps.name(name).and_then(|m| m.as_str().parse::<u32>().ok());
+    let date = NaiveDate::from_ymd_opt(caps.name("y")?.as_str().parse().ok()?, number("m")?, number("d")?)?;
+    let Some(mut hour) = number("H") else {
+        return date.and_hms_opt(0, 0, 0);
+    };
+    match caps.name("p").map(|m| m.as_str()) {
+        Some("PM") if hour < 12 => hour += 12,
+        Some("AM") if hour == 12 => hour = 0,
+        _ => {}
+    }
+    date.and_hms_opt(hour, number("M")?, number("S")?)
+}
+
+/// Parse a file's EXIF. HEIC keeps it in an item of the container that
+/// rexif doesn't look in, so it is pulled out first.
+fn read_exif(path: &Path) -> Result<rexif::ExifData, rexif::ExifError> {
+    if heif::is_heif(path) {
+        if let Some(tiff) = heif::read_exif(path) {
+            return rexif::parse_buffer(&tiff);
+        }
+    }
+    rexif::parse_file(path)
+}
+
+/// The capture time from EXIF as (Unix timestamp, UTC offset in minutes).
+/// DateTimeOriginal is preferred over DateTime, each with its own offset tag.
+fn extract_exif_date(path: &Path) -> Option<(i64, i32)> {
+    let exif_data = match read_exif(path) {
         Ok(data) => data,
         Err(e) => {
             debug!("Failed to parse EXIF for {:?}: {}", path.file_name(), e);
@@ -127,23 +282,186 @@ fn extract_exif_date(path: &Path) -> Option<i64> {
         }
     };
 
+    let (mut original, mut modified) = (None, None);
+    let (mut original_offset, mut modified_offset) = (None, None);
     for entry in &exif_data.entries {
-        if entry.tag == rexif::ExifTag::DateTimeOriginal || entry.tag == rexif::ExifTag::DateTime {
-            if let rexif::TagValue::Ascii(ref s) = entry.value {
-                let trimmed = s.trim_end_matches('\0').trim();
-                if let Some(timestamp) = parse_exif_datetime(trimmed) {
-                    debug!("Found EXIF date for {:?}: {}", path.file_name(), trimmed);
-                    return Some(timestamp);
-                }
+        let rexif::TagValue::Ascii(ref s) = entry.value else {
+            continue;
+        };
+        match entry.tag {
+            rexif::ExifTag::DateTimeOriginal => original = parse_exif_datetime(s),
+            rexif::ExifTag::DateTime => modified = parse_exif_datetime(s),
+            _ if entry.ifd.tag == EXIF_OFFSET_TIME_ORIGINAL_TAG => original_offset = parse_exif_offset(s),
+            _ if entry.ifd.tag == EXIF_OFFSET_TIME_TAG => modified_offset = parse_exif_offset(s),
+            _ => {}
+        }
+    }
+
+    let found = [original.map(|dt| (dt, original_offset)), modified.map(|dt| (dt, modified_offset))]
+        .into_iter()
+        .flatten()
+        .filter_map(|(wall_clock, offset)| exif_timestamp(wall_clock, offset))
+        .find(|&(timestamp, _)| is_plausible_exif_timestamp(timestamp, path));
+    match found {
+        Some((timestamp, offset)) => {
+            debug!("Found EXIF date for {:?}: {} (offset {})", path.file_name(), timestamp, offset);
+            Some((timestamp, offset))
+        }
+        None => {
+            debug!("No EXIF date found for {:?}", path.file_name());
+            None
+        }
+    }
+}
+
+/// Normalize an EXIF ASCII value: strip NUL padding and whitespace, and
+/// treat an empty result as absent.
+pub(crate) fn clean_exif_string(raw: &str) -> Option<String> {
+    let cleaned = raw.trim_end_matches('\0').trim();
+    if cleaned.is_empty() {
+        None
+    } else {
+        Some(cleaned.to_string())
+    }
+}
+
+/// Read the camera Make and Model tags. Either may be absent.
+pub(crate) fn extract_camera(path: &Path) -> (Option<String>, Option<String>) {
+    let exif_data = match read_exif(path) {
+        Ok(data) => data,
+        Err(_) => return (None, None),
+    };
+
+    let mut make = None;
+    let mut model = None;
+    for entry in &exif_data.entries {
+        if let rexif::TagValue::Ascii(ref s) = entry.value {
+            match entry.tag {
+                rexif::ExifTag::Make => make = clean_exif_string(s),
+                rexif::ExifTag::Model => model = clean_exif_string(s),
+                _ => {}
             }
         }
     }
+    (make, model)
+}
+
+/// Placeholder captions some cameras write into ImageDescription.
+const BOILERPLATE_DESCRIPTIONS: &[&str] = &[
+    "OLYMPUS DIGITAL CAMERA",
+    "SONY DSC",
+    "DCIM",
+    "Default",
+];
+
+/// How much of the file to search for an XMP packet. JPEG/HEIC/TIFF writers
+/// put it near the start; sidecar-less RAW files rarely carry a caption.
+const XMP_SEARCH_BYTES: u64 = 256 * 1024;
+
+/// EXIF tag 0x4746 (Rating, 0-5). rexif has no name for it.
+const EXIF_RATING_TAG: u16 = 0x4746;
+
+/// EXIF 2.31 OffsetTime and OffsetTimeOriginal (`+09:00`), the UTC offsets
+/// of DateTime and DateTimeOriginal. rexif has no names for them.
+const EXIF_OFFSET_TIME_TAG: u16 = 0x9010;
+const EXIF_OFFSET_TIME_ORIGINAL_TAG: u16 = 0x9011;
+
+/// Leading bytes of the file as text, for the XMP parsers below.
+pub(crate) fn read_xmp_head(path: &Path) -> Option<String> {
+    let mut head = Vec::new();
+    fs::File::open(path).ok()?.take(XMP_SEARCH_BYTES).read_to_end(&mut head).ok()?;
+    Some(String::from_utf8_lossy(&head).into_owned())
+}
+
+/// Caption embedded in the file: XMP dc:description (what Lightroom and
+/// Photos write) first, then EXIF ImageDescription minus camera boilerplate.
+pub(crate) fn extract_description(path: &Path, xmp: Option<&str>) -> Option<String> {
+    xmp.and_then(parse_xmp_description).or_else(|| {
+        let exif_data = read_exif(path).ok()?;
+        exif_data.entries.iter().find_map(|entry| match (&entry.tag, &entry.value) {
+            (rexif::ExifTag::ImageDescription, rexif::TagValue::Ascii(s)) => clean_exif_string(s)
+                .filter(|d| !BOILERPLATE_DESCRIPTIONS.iter().any(|b| d.eq_ignore_ascii_case(b))),
+            _ => None,
+        })
+    })
+}
+
+/// Star rating set in Lightroom/Photos: XMP xmp:Rating, then the EXIF
+/// Rating tag. 0 when absent.
+pub(crate) fn extract_rating(path: &Path, xmp: Option<&str>) -> u8 {
+    xmp.and_then(parse_xmp_rating)
+        .or_else(|| {
+            let exif_data = read_exif(path).ok()?;
+            exif_data.entries.iter().find_map(|entry| match &entry.value {
+                rexif::TagValue::U16(v) if entry.ifd.tag == EXIF_RATING_TAG => {
+                    v.first().map(|r| clamp_rating(i64::from(*r)))
+                }
+                _ => None,
+            })
+        })
+        .unwrap_or(0)
+}
+
+/// Read xmp:Rating in either attribute or element form. Lightroom writes
+/// -1 for rejected photos; that and anything out of range map into 0-5.
+pub(crate) fn parse_xmp_rating(xmp: &str) -> Option<u8> {
+    let caps = XMP_RATING_REGEX.captures(xmp)?;
+    let value: i64 = caps.get(1).or_else(|| caps.get(2))?.as_str().parse().ok()?;
+    Some(clamp_rating(value))
+}
+
+fn clamp_rating(value: i64) -> u8 {
+    value.clamp(0, 5) as u8
+}
+
+/// Read xmp:Label in either attribute or element form. Lightroom writes
+/// the color name; Bridge writes its label names, which default to Select,
+/// Second, Approved, Review and To Do for red through purple. Renamed
+/// Bridge labels aren't recognised.
+pub(crate) fn parse_xmp_label(xmp: &str) -> Option<db::ColorLabel> {
+    let caps = XMP_LABEL_REGEX.captures(xmp)?;
+    let label = caps.get(1).or_else(|| caps.get(2)).or_else(|| caps.get(3))?.as_str().trim().to_lowercase();
+    match label.as_str() {
+        "red" | "select" => Some(db::ColorLabel::Red),
+        "yellow" | "second" => Some(db::ColorLabel::Yellow),
+        "green" | "approved" => Some(db::ColorLabel::Green),
+        "blue" | "review" => Some(db::ColorLabel::Blue),
+        "purple" | "to do" => Some(db::ColorLabel::Purple),
+        _ => None,
+    }
+}
+
+/// Pull the first dc:description entry out of an XMP packet.
+pub(crate) fn parse_xmp_description(xmp: &str) -> Option<String> {
+    let raw = XMP_DESCRIPTION_REGEX.captures(xmp)?.get(1)?.as_str();
+    let text = raw
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&#xA;", "\n")
+        .replace("&amp;", "&");
+    let text = text.trim();
+    if text.is_empty() {
+        None
+    } else {
+        Some(text.to_string())
+    }
+}
 
-    debug!("No EXIF date found for {:?}", path.file_name());
-    None
+/// File size in bytes and modified time in Unix seconds, for change detection.
+pub(crate) fn file_stats(path: &Path) -> Option<(i64, i64)> {
+    let meta = fs::metadata(path).ok()?;
+    let mtime = meta
+        .modified()
+        .ok()?
+        .duration_since(UNIX_EPOCH)
+        .ok()?
+        .as_secs() as i64;
+    Some((meta.len() as i64, mtime))
 }
 
-fn get_file_modified_time(path: &Path) -> Option<i64> {
+pub(crate) fn get_file_modified_time(path: &Path) -> Option<i64> {
     fs::metadata(path)
         .ok()?
         .modified()
@@ -153,10 +471,13 @@ fn get_file_modified_time(path: &Path) -> Option<i64> {
         .map(|d| d.as_secs() as i64)
 }
 
-fn calculate_hash(path: &Path) -> Option<String> {
+/// SHA-256 of the file contents as hex, read in 1 MiB chunks so large
+/// videos are never held in memory.
+pub(crate) fn calculate_hash(path: &Path) -> Option<String> {
     let mut file = fs::File::open(path).ok()?;
     let mut hasher = Sha256::new();
-    let mut buffer = [0; 1024 * 1024];
+    // Heap buffer: this runs on rayon workers, whose stacks are small
+    let mut buffer = vec![0u8; 1024 * 1024];
 
     loop {
         let count = file.read(&mut buffer).ok()?;
@@ -169,69 +490,95 @@ fn calculate_hash(path: &Path) -> Option<String> {
     Some(hex::encode(hasher.finalize()))
 }
 
+/// Convert an EXIF degrees/minutes/seconds triple to decimal degrees.
+/// Returns None for malformed values (zero denominators, negative or
+/// out-of-range minutes/seconds) instead of silently producing 0.0.
+pub(crate) fn dms_to_decimal(degrees: f64, minutes: f64, seconds: f64) -> Option<f64> {
+    if !degrees.is_finite() || !minutes.is_finite() || !seconds.is_finite() {
+        return None;
+    }
+    if degrees < 0.0 || !(0.0..60.0).contains(&minutes) || !(0.0..60.0).contains(&seconds) {
+        return None;
+    }
+    Some(degrees + minutes / 60.0 + seconds / 3600.0)
+}
+
+/// Apply hemisphere signs and sanity-check a coordinate pair.
+/// Cameras without a fix often write 0/0 into the GPS IFD, so exactly
+/// (0, 0) is treated as "no location" rather than a point off Africa.
+pub(crate) fn finalize_gps(lat: f64, lon: f64, south: bool, west: bool) -> Option<(f64, f64)> {
+    let lat = if south { -lat } else { lat };
+    let lon = if west { -lon } else { lon };
+    if lat == 0.0 && lon == 0.0 {
+        return None;
+    }
+    if !(-90.0..=90.0).contains(&lat) || !(-180.0..=180.0).contains(&lon) {
+        return None;
+    }
+    Some((lat, lon))
+}
+
+fn rational_triple_to_decimal(values: &[rexif::URational]) -> Option<f64> {
+    if values.len() != 3 {
+        return None;
+    }
+    dms_to_decimal(values[0].value(), values[1].value(), values[2].value())
+}
+
 fn extract_gps(path: &Path) -> Option<(f64, f64)> {
     let filename = path.file_name().and_then(|s| s.to_str()).unwrap_or("unknown");
-    let exif_data = rexif::parse_file(path).ok()?;
+    let exif_data = read_exif(path).ok()?;
 
     let mut lat: Option<f64> = None;
     let mut lon: Option<f64> = None;
-    let mut lat_ref = 1.0;
-    let mut lon_ref = 1.0;
+    let mut south = false;
+    let mut west = false;
 
     for entry in &exif_data.entries {
         match entry.tag {
             rexif::ExifTag::GPSLatitude => {
                 if let rexif::TagValue::URational(ref values) = entry.value {
-                    if values.len() == 3 {
-                        lat = Some(
-                            values[0].value()
-                                + values[1].value() / 60.0
-                                + values[2].value() / 3600.0,
-                        );
-                    }
+                    lat = rational_triple_to_decimal(values);
                 }
             }
             rexif::ExifTag::GPSLongitude => {
                 if let rexif::TagValue::URational(ref values) = entry.value {
-                    if values.len() == 3 {
-                        lon = Some(
-                            values[0].value()
-                                + values[1].value() / 60.0
-                                + values[2].value() / 3600.0,
-                        );
-                    }
+                    lon = rational_triple_to_decimal(values);
                 }
             }
             rexif::ExifTag::GPSLatitudeRef => {
                 if let rexif::TagValue::Ascii(ref s) = entry.value {
-                    if s.starts_with('S') {
-                        lat_ref = -1.0;
-                    }
+                    south = s.trim().starts_with('S');
                 }
             }
             rexif::ExifTag::GPSLongitudeRef => {
                 if let rexif::TagValue::Ascii(ref s) = entry.value {
-                    if s.starts_with('W') {
-                        lon_ref = -1.0;
-                    }
+                    west = s.trim().starts_with('W');
                 }
             }
             _ => {}
         }
     }
 
-    if let (Some(l), Some(ln)) = (lat, lon) {
-        let final_lat = l * lat_ref;
-        let final_lon = ln * lon_ref;
-        debug!("Found GPS coordinates for {}: ({}, {})", filename, final_lat, final_lon);
-        Some((final_lat, final_lon))
-    } else {
-        debug!("No GPS data in EXIF for {}", filename);
-        None
+    match (lat, lon) {
+        (Some(l), Some(ln)) => {
+            let coords = finalize_gps(l, ln, south, west);
+            match coords {
+                Some((final_lat, final_lon)) => {
+                    debug!("Found GPS coordinates for {}: ({}, {})", filename, final_lat, final_lon)
+                }
+                None => debug!("Ignoring zeroed or out-of-range GPS for {}", filename),
+            }
+            coords
+        }
+        _ => {
+            debug!("No GPS data in EXIF for {}", filename);
+            None
+        }
     }
 }
 
-fn get_location_name(lat: f64, lon: f64, geocoder: &ReverseGeocoder) -> Option<String> {
+pub(crate) fn get_location_name(lat: f64, lon: f64, geocoder: &ReverseGeocoder) -> Option<String> {
     let search_result = geocoder.search((lat, lon))?;
     let location = format!("{}, {}", search_result.record.name, search_result.record.admin1);
     debug!("Reverse geocoded ({}, {}) -> {}", lat, lon, location);
@@ -241,65 +588,102 @@ fn get_location_name(lat: f64, lon: f64, geocoder: &ReverseGeocoder) -> Option<S
 /// Read an image file and produce a `PhotoMetadata` record.
 ///
 /// Date extraction tries (in order): EXIF DateTimeOriginal → filename
-/// pattern → file modified time → current time. Width/height are decoded
-/// from the image header for photos and left as `0,0` for videos.
+/// pattern → file modified time → current time. Takeout sidecars are only
+/// looked for by the Takeout import, which passes them to
+/// `process_image_with`. Width/height are decoded from the image header for
+/// photos and left as `0,0` for videos.
 pub(crate) fn process_image(path: &Path, geocoder: Option<&ReverseGeocoder>) -> Option<PhotoMetadata> {
+    process_image_with(path, geocoder, None)
+}
+
+/// `process_image` with an already-matched Takeout sidecar, or what a Meta
+/// export's JSON says in the same shape. Its caption and location fill in
+/// for missing embedded ones, and its date for a missing embedded one, or
+/// for any when the export re-encoded the file.
+pub(crate) fn process_image_with(
+    path: &Path,
+    geocoder: Option<&ReverseGeocoder>,
+    sidecar: Option<&takeout::Sidecar>,
+) -> Option<PhotoMetadata> {
     let name = path.file_name()?.to_string_lossy().to_string();
 
-    let canonical_path = match path.canonicalize() {
+    let canonical_path = match library::canonical_path(path) {
         Ok(p) => {
-            debug!("Canonicalized path for {}: {}", name, p.display());
-            p.to_string_lossy().to_string()
+            debug!("Canonicalized path for {}: {}", name, p);
+            p
         }
         Err(e) => {
             warn!("Could not canonicalize path {:?}: {}", path, e);
-            path.to_string_lossy().to_string()
+            library::stored_path(path)
         }
     };
 
-    let date_taken = extract_exif_date(path)
-        .or_else(|| {
-            let filename_date = parse_filename_date(&name);
-            if filename_date.is_some() {
-                debug!("Extracted date from filename for {}", name);
-            }
-            filename_date
-        })
-        .or_else(|| {
-            let mtime = get_file_modified_time(path);
-            if mtime.is_some() {
-                debug!("Using file modified time for {}", name);
-            }
-            mtime
-        })
-        .unwrap_or_else(|| {
-            let now = SystemTime::now()
-                .duration_since(UNIX_EPOCH)
-                .unwrap()
-                .as_secs() as i64;
-            warn!("No date found for {}, using current time: {}", name, now);
-            now
-        });
+    // Videos carry their creation time, size and length in the container
+    let video_info = if is_video(path) { video::read_video_info(path) } else { None };
+    // Container times are UTC; EXIF times are wall-clock with an offset
+    let (embedded_date, tz_offset_minutes) = match &video_info {
+        _ if sidecar.is_some_and(|s| s.re_encoded && s.taken_at.is_some()) => (None, None),
+        Some(info) => (info.created_at, None),
+        None => match extract_exif_date(path) {
+            Some((timestamp, offset)) => (Some(timestamp), Some(offset)),
+            None => (None, None),
+        },
+    };
+
+    let filename_date = parse_filename_date(&name);
+    // Embedded dates win; a name that disagrees by over a year is kept for review
+    let filename_date_mismatch = match (embedded_date, filename_date) {
+        (Some(embedded), Some(from_name)) if (embedded - from_name).abs() > DATE_MISMATCH_SECS => {
+            debug!("Filename date of {} disagrees with its embedded date; keeping the embedded one", name);
+            Some(from_name)
+        }
+        _ => None,
+    };
+
+    // Most trustworthy first; a photo with none of these is flagged rather
+    // than stamped with the import time
+    let sidecar_date = sidecar.and_then(|s| s.taken_at);
+    let (date_taken, date_source) = if let Some(date) = embedded_date {
+        (date, DATE_SOURCE_EXIF)
+    } else if let Some(date) = sidecar_date {
+        debug!("Using export JSON date for {}", name);
+        (date, DATE_SOURCE_SIDECAR)
+    } else if let Some(date) = filename_date {
+        debug!("Extracted date from filename for {}", name);
+        (date, DATE_SOURCE_FILENAME)
+    } else if let Some(mtime) = get_file_modified_time(path) {
+        debug!("Using file modified time for {}", name);
+        (mtime, DATE_SOURCE_MTIME)
+    } else {
+        debug!("No date found for {}; it will be listed as undated", name);
+        (UNKNOWN_DATE, DATE_SOURCE_UNKNOWN)
+    };
 
     let (width, height) = if is_video(path) {
-        (0, 0)
+        video_info.as_ref().map(|v| (v.width, v.height)).unwrap_or_else(|| {
+            debug!("No container dimensions for video {}", name);
+            (0, 0)
+        })
     } else {
-        match image::open(path) {
-            Ok(img) => (img.width(), img.height()),
-            Err(e) => {
-                warn!("Failed to read image dimensions for {}: {}", name, e);
-                (0, 0)
-            }
-        }
+        read_dimensions(path).unwrap_or_else(|| {
+            debug!("Failed to read image dimensions for {}", name);
+            (0, 0)
+        })
     };
 
     let content_hash = calculate_hash(path);
+    let (camera_make, camera_model) = extract_camera(path);
+    let xmp = read_xmp_head(path);
+    let description = extract_description(path, xmp.as_deref())
+        .or_else(|| sidecar.and_then(|s| s.description.clone()));
+    let rating = extract_rating(path, xmp.as_deref());
+    let color_label = xmp.as_deref().and_then(parse_xmp_label);
 
     let mut latitude = None;
     let mut longitude = None;
     let mut location_name = None;
 
-    if let Some((lat, lon)) = extract_gps(path) {
+    if let Some((lat, lon)) = extract_gps(path).or_else(|| sidecar.and_then(|s| s.gps)) {
         latitude = Some(lat);
         longitude = Some(lon);
         if let Some(geo) = geocoder {
@@ -307,6 +691,7 @@ pub(crate) fn process_image(path: &Path, geocoder: Option<&ReverseGeocoder>) ->
         }
     }
 
+    let format = file_format(path);
     Some(PhotoMetadata {
         path: canonical_path,
         name,
@@ -318,37 +703,148 @@ pub(crate) fn process_image(path: &Path, geocoder: Option<&ReverseGeocoder>) ->
         latitude,
         longitude,
         location_name,
-        camera_make: None,
-        camera_model: None,
+        camera_make,
+        camera_model,
         lens_model: None,
         iso: None,
         aperture: None,
         shutter_us: None,
         focal_length_mm: None,
         orientation: None,
-        duration_ms: None,
+        duration_ms: video_info.and_then(|v| v.duration_ms),
         codec: None,
         thumb_status: None,
+        is_missing: false,
+        is_offline: false,
+        blurhash: None,
+        dominant_color: None,
+        color_label,
+        flag: None,
+        stack_id: None,
+        edited_key: None,
+        stack_count: None,
+        rating,
+        tags: Vec::new(),
+        description,
+        file_size: fs::metadata(path).ok().map(|m| m.len() as i64),
+        mime_type: format.as_deref().and_then(mime_type).map(str::to_string),
+        file_format: format,
+        paired_video_path: None,
+        filename_date_mismatch,
+        tz_offset_minutes,
+        date_source: Some(date_source.to_string()),
+        date_confident: Some(is_confident_date_source(date_source)),
+        id: None,
+    })
+}
+
+/// Lowercase container format of a file, from its extension ("jpeg", "cr2").
+pub(crate) fn file_format(path: &Path) -> Option<String> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    Some(match ext.as_str() {
+        "jpg" => "jpeg".to_string(),
+        "tif" => "tiff".to_string(),
+        _ => ext,
     })
 }
 
+/// MIME type of a `file_format` value, or None for formats Terra doesn't know.
+pub(crate) fn mime_type(format: &str) -> Option<&'static str> {
+    Some(match format {
+        "jpeg" => "image/jpeg",
+        "png" => "image/png",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "bmp" => "image/bmp",
+        "tiff" => "image/tiff",
+        "heic" => "image/heic",
+        "heif" => "image/heif",
+        "avif" => "image/avif",
+        "cr2" => "image/x-canon-cr2",
+        "nef" => "image/x-nikon-nef",
+        "arw" => "image/x-sony-arw",
+        "dng" => "image/x-adobe-dng",
+        "mp4" => "video/mp4",
+        "mov" => "video/quicktime",
+        "avi" => "video/x-msvideo",
+        "webm" => "video/webm",
+        "mkv" => "video/x-matroska",
+        _ => return None,
+    })
+}
+
+/// Read image width/height as displayed, from the file header without
+/// decoding pixels: an EXIF orientation that turns the picture a quarter
+/// (5-8) swaps them. Scans and rotations both store this, so a rescan
+/// agrees with what a rotation recorded. Falls back to a full decode only
+/// when the header can't be parsed. HEIC sizes come from the container,
+/// already rotated for display. RAW sizes are those of the largest embedded
+/// preview.
+pub(crate) fn read_dimensions(path: &Path) -> Option<(u32, u32)> {
+    if heif::is_heif(path) {
+        return heif::dimensions(path);
+    }
+    if raw::is_raw(path) {
+        return raw::dimensions(path);
+    }
+    let decoder = ImageReader::open(path)
+        .ok()
+        .and_then(|r| r.with_guessed_format().ok())
+        .and_then(|r| r.into_decoder().ok());
+    if let Some(mut decoder) = decoder {
+        let (width, height) = decoder.dimensions();
+        let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+        return Some(displayed_dimensions(width, height, orientation));
+    }
+
+    debug!("Header dimension read failed for {:?}, decoding", path.file_name());
+    image::open(path).ok().map(|img| (img.width(), img.height()))
+}
+
+/// Width and height once `orientation` is applied.
+fn displayed_dimensions(width: u32, height: u32, orientation: Orientation) -> (u32, u32) {
+    match orientation {
+        Orientation::Rotate90 | Orientation::Rotate270 | Orientation::Rotate90FlipH | Orientation::Rotate270FlipH => {
+            (height, width)
+        }
+        _ => (width, height),
+    }
+}
+
+/// Decode an image for hashing. RAW files decode their embedded preview.
+fn open_image(path: &Path) -> Option<image::DynamicImage> {
+    if raw::is_raw(path) {
+        let preview = raw::read_preview(path)?;
+        return image::load_from_memory_with_format(&preview.jpeg, image::ImageFormat::Jpeg).ok();
+    }
+    #[cfg(feature = "heif")]
+    if heif::is_heif(path) {
+        return heif::decode(path).ok();
+    }
+    image::open(path).ok()
+}
+
+/// `file_format` values of videos. The one list of what counts as a video:
+/// is_video checks files against it, and db.rs stored rows.
+pub(crate) const VIDEO_FORMATS: &[&str] = &["mp4", "mov", "avi", "webm", "mkv"];
+
 pub(crate) fn is_video(path: &Path) -> bool {
-    path.extension()
-        .and_then(|s| s.to_str())
-        .map(|ext| matches!(ext.to_lowercase().as_str(), "mp4" | "mov" | "avi" | "webm" | "mkv"))
-        .unwrap_or(false)
+    file_format(path).is_some_and(|format| VIDEO_FORMATS.contains(&format.as_str()))
 }
 
 /// Compute a 64-bit perceptual hash (dHash) for an image. Used for similar-
 /// duplicate detection via Hamming distance.
 pub(crate) fn compute_dhash(path: &Path) -> Option<u64> {
-    let img = image::open(path).ok()?;
+    let img = open_image(path)?;
     let hasher = HasherConfig::new()
         .hash_alg(HashAlg::Gradient)
         .hash_size(8, 8)
         .to_hasher();
 
-    let hash = hasher.hash_image(&img);
+    hash_to_u64(&hasher.hash_image(&img))
+}
+
+fn hash_to_u64(hash: &image_hasher::ImageHash) -> Option<u64> {
     let bytes = hash.as_bytes();
     if bytes.len() >= 8 {
         Some(u64::from_be_bytes([
@@ -360,11 +856,115 @@ pub(crate) fn compute_dhash(path: &Path) -> Option<u64> {
     }
 }
 
+/// DCT perceptual hash (pHash) of an image as stored and at 90°, 180° and 270°.
+/// The image is shrunk to a small grayscale square first, so rotating it is
+/// exact and hashing four variants costs barely more than one. Survives
+/// recompression and resizing, which change the content hash.
+pub(crate) fn compute_phash_rotations(path: &Path) -> Option<[u64; 4]> {
+    let img = open_image(path)?;
+    let small = img
+        .resize_exact(64, 64, image::imageops::FilterType::Triangle)
+        .grayscale();
+    phash_rotations_of(&small)
+}
+
+fn phash_rotations_of(img: &image::DynamicImage) -> Option<[u64; 4]> {
+    let hasher = HasherConfig::new()
+        .hash_alg(HashAlg::Mean)
+        .preproc_dct()
+        .hash_size(8, 8)
+        .to_hasher();
+
+    let variants = [img.clone(), img.rotate90(), img.rotate180(), img.rotate270()];
+    let mut hashes = [0u64; 4];
+    for (slot, variant) in hashes.iter_mut().zip(variants.iter()) {
+        *slot = hash_to_u64(&hasher.hash_image(variant))?;
+    }
+    Some(hashes)
+}
+
 /// Number of differing bits between two 64-bit hashes.
 pub(crate) fn hamming_distance(hash1: u64, hash2: u64) -> u32 {
     (hash1 ^ hash2).count_ones()
 }
 
+/// Distance from `upright` to the closest rotation of another photo.
+pub(crate) fn rotation_distance(upright: u64, rotations: &[u64; 4]) -> u32 {
+    rotations
+        .iter()
+        .map(|&h| hamming_distance(upright, h))
+        .min()
+        .unwrap_or(64)
+}
+
+/// Cluster photos whose pHashes (any 90° rotation) are within `max_distance`.
+/// Returns index groups of two or more; members may be linked through a chain
+/// of near matches rather than all being pairwise close.
+///
+/// Avoids comparing every pair: the 64 bits are split into `max_distance + 1`
+/// blocks, and two hashes within the distance must agree exactly on at least
+/// one block, so only photos sharing a block value are compared.
+pub(crate) fn group_similar_hashes(hashes: &[[u64; 4]], max_distance: u32) -> Vec<Vec<usize>> {
+    let blocks = (max_distance as usize + 1).min(64);
+    let ranges: Vec<(u32, u64)> = (0..blocks)
+        .map(|b| {
+            let start = b * 64 / blocks;
+            let end = (b + 1) * 64 / blocks;
+            let width = end - start;
+            let mask = if width == 64 { u64::MAX } else { (1u64 << width) - 1 };
+            (start as u32, mask)
+        })
+        .collect();
+    let block_value = |hash: u64, b: usize| (hash >> ranges[b].0) & ranges[b].1;
+
+    // Index every rotation so an upright photo can find a rotated copy
+    let mut buckets: std::collections::HashMap<(usize, u64), Vec<usize>> = std::collections::HashMap::new();
+    for (i, rotations) in hashes.iter().enumerate() {
+        for &hash in rotations {
+            for b in 0..blocks {
+                let bucket = buckets.entry((b, block_value(hash, b))).or_default();
+                if bucket.last() != Some(&i) {
+                    bucket.push(i);
+                }
+            }
+        }
+    }
+
+    let mut parent: Vec<usize> = (0..hashes.len()).collect();
+    fn find(parent: &mut [usize], mut x: usize) -> usize {
+        while parent[x] != x {
+            parent[x] = parent[parent[x]];
+            x = parent[x];
+        }
+        x
+    }
+
+    for (j, rotations) in hashes.iter().enumerate() {
+        let upright = rotations[0];
+        for b in 0..blocks {
+            let Some(candidates) = buckets.get(&(b, block_value(upright, b))) else {
+                continue;
+            };
+            for &i in candidates {
+                if i == j || rotation_distance(upright, &hashes[i]) > max_distance {
+                    continue;
+                }
+                let (ri, rj) = (find(&mut parent, i), find(&mut parent, j));
+                if ri != rj {
+                    parent[ri] = rj;
+                }
+            }
+        }
+    }
+
+    let mut groups: std::collections::HashMap<usize, Vec<usize>> = std::collections::HashMap::new();
+    for i in 0..hashes.len() {
+        let root = find(&mut parent, i);
+        groups.entry(root).or_default().push(i);
+    }
+    groups.into_values().filter(|g| g.len() > 1).collect()
+}
+
 /// Heuristic test for whether an image is likely a screenshot.
 /// Combines filename patterns, exact-dimension match against common phone /
 /// laptop screen sizes, and a tall-aspect-ratio fallback.
@@ -397,6 +997,19 @@ pub(crate) fn detect_screenshot(name: &str, width: u32, height: u32) -> bool {
     false
 }
 
+/// Whether an imported photo looks like a screenshot: its name says so, or
+/// it is a PNG without camera EXIF at a screen's size, which is how phones
+/// and desktops save captures. Needs only stored metadata, never the file.
+pub(crate) fn is_screenshot(photo: &PhotoMetadata) -> bool {
+    if SCREENSHOT_REGEX.is_match(&photo.name) {
+        return true;
+    }
+    let uncamera_png = photo.file_format.as_deref() == Some("png")
+        && photo.camera_make.is_none()
+        && photo.camera_model.is_none();
+    uncamera_png && detect_screenshot(&photo.name, photo.width, photo.height)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -407,20 +1020,14 @@ mod tests {
     #[test]
     fn parses_standard_format() {
         let result = parse_exif_datetime("2023:01:15 14:30:45");
-        let expected = NaiveDateTime::parse_from_str("2023-01-15 14:30:45", "%Y-%m-%d %H:%M:%S")
-            .unwrap()
-            .and_utc()
-            .timestamp();
+        let expected = NaiveDateTime::parse_from_str("2023-01-15 14:30:45", "%Y-%m-%d %H:%M:%S").unwrap();
         assert_eq!(result, Some(expected));
     }
 
     #[test]
     fn parses_with_null_terminator() {
         let result = parse_exif_datetime("2023:01:15 14:30:45\0");
-        let expected = NaiveDateTime::parse_from_str("2023-01-15 14:30:45", "%Y-%m-%d %H:%M:%S")
-            .unwrap()
-            .and_utc()
-            .timestamp();
+        let expected = NaiveDateTime::parse_from_str("2023-01-15 14:30:45", "%Y-%m-%d %H:%M:%S").unwrap();
         assert_eq!(result, Some(expected));
     }
 
@@ -434,6 +1041,121 @@ mod tests {
         assert!(parse_exif_datetime("").is_none());
     }
 
+    #[test]
+    fn wall_clock_round_trips_through_exif_timestamp() {
+        let wall_clock = parse_exif_datetime("2023:01:15 14:30:45").unwrap();
+        for offset in [540, -300, 0] {
+            let (timestamp, _) = exif_timestamp(wall_clock, Some(offset)).unwrap();
+            assert_eq!(exif_wall_clock(timestamp, Some(offset)), Some((wall_clock, offset)));
+        }
+    }
+
+    #[test]
+    fn rejects_placeholder_dates() {
+        assert!(parse_exif_datetime("0000:00:00 00:00:00").is_none());
+        assert!(parse_exif_datetime("0000:00:00 00:00:00\0").is_none());
+        assert!(parse_exif_datetime("    :  :     :  :  ").is_none());
+        assert!(is_placeholder_datetime(""));
+        assert!(!is_placeholder_datetime("2000:01:01 00:00:00"));
+    }
+
+    #[test]
+    fn rejects_dates_far_in_the_future() {
+        let tmp = std::env::temp_dir().join(format!("terra-media-future-{}.jpg", std::process::id()));
+        fs::write(&tmp, jpeg_with_capture_time("2099:01:01 12:00:00", Some("+00:00"))).unwrap();
+        assert_eq!(extract_exif_date(&tmp), None);
+        let _ = fs::remove_file(&tmp);
+
+        assert!(!is_plausible_exif_timestamp(-1, &tmp));
+        assert!(is_plausible_exif_timestamp(chrono::Utc::now().timestamp() + 60 * 60, &tmp));
+    }
+
+    #[test]
+    fn parses_exif_offsets() {
+        assert_eq!(parse_exif_offset("+09:00"), Some(540));
+        assert_eq!(parse_exif_offset("-05:00\0"), Some(-300));
+        assert_eq!(parse_exif_offset("+05:30"), Some(330));
+        assert_eq!(parse_exif_offset("   :  "), None);
+        assert_eq!(parse_exif_offset("09:00"), None);
+    }
+
+    #[test]
+    fn recorded_offsets_give_the_true_instant() {
+        let wall_clock = parse_exif_datetime("2023:01:15 14:30:45").unwrap();
+        let utc = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp();
+        assert_eq!(exif_timestamp(wall_clock, Some(540)), Some((utc("2023-01-15 05:30:45"), 540)));
+        assert_eq!(exif_timestamp(wall_clock, Some(-300)), Some((utc("2023-01-15 19:30:45"), -300)));
+    }
+
+    /// A JPEG carrying DateTimeOriginal and, optionally, OffsetTimeOriginal
+    /// in its Exif IFD.
+    fn jpeg_with_capture_time(datetime: &str, offset: Option<&str>) -> Vec<u8> {
+        let entries = if offset.is_some() { 2u16 } else { 1 };
+        let exif_ifd = 26u32;
+        let values_at = exif_ifd + 2 + 12 * u32::from(entries) + 4;
+        let mut tiff = b"MM\0*".to_vec();
+        tiff.extend_from_slice(&8u32.to_be_bytes());
+        tiff.extend_from_slice(&1u16.to_be_bytes());
+        tiff.extend_from_slice(&0x8769u16.to_be_bytes()); // ExifIFD pointer
+        tiff.extend_from_slice(&4u16.to_be_bytes());
+        tiff.extend_from_slice(&1u32.to_be_bytes());
+        tiff.extend_from_slice(&exif_ifd.to_be_bytes());
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+
+        tiff.extend_from_slice(&entries.to_be_bytes());
+        tiff.extend_from_slice(&0x9003u16.to_be_bytes()); // DateTimeOriginal
+        tiff.extend_from_slice(&2u16.to_be_bytes());
+        tiff.extend_from_slice(&20u32.to_be_bytes());
+        tiff.extend_from_slice(&values_at.to_be_bytes());
+        if offset.is_some() {
+            tiff.extend_from_slice(&EXIF_OFFSET_TIME_ORIGINAL_TAG.to_be_bytes());
+            tiff.extend_from_slice(&2u16.to_be_bytes());
+            tiff.extend_from_slice(&7u32.to_be_bytes());
+            tiff.extend_from_slice(&(values_at + 20).to_be_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_be_bytes());
+        tiff.extend_from_slice(datetime.as_bytes());
+        tiff.push(0);
+        if let Some(offset) = offset {
+            tiff.extend_from_slice(offset.as_bytes());
+            tiff.push(0);
+        }
+
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((tiff.len() + 8) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend(tiff);
+        jpeg.extend_from_slice(&[0xFF, 0xD9]);
+        jpeg
+    }
+
+    #[test]
+    fn exif_dates_use_offset_time_original() {
+        let utc = |s: &str| NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp();
+        let cases = [
+            ("tokyo", "+09:00", utc("2023-01-15 05:30:45"), 540),
+            ("new-york", "-05:00", utc("2023-01-15 19:30:45"), -300),
+        ];
+        for (name, offset, expected, minutes) in cases {
+            let tmp = std::env::temp_dir().join(format!("terra-media-tz-{}-{}.jpg", name, std::process::id()));
+            fs::write(&tmp, jpeg_with_capture_time("2023:01:15 14:30:45", Some(offset))).unwrap();
+            assert_eq!(extract_exif_date(&tmp), Some((expected, minutes)), "{}", offset);
+            let _ = fs::remove_file(&tmp);
+        }
+    }
+
+    #[test]
+    fn exif_dates_without_an_offset_use_the_default() {
+        let tmp = std::env::temp_dir().join(format!("terra-media-tz-none-{}.jpg", std::process::id()));
+        fs::write(&tmp, jpeg_with_capture_time("2023:01:15 14:30:45", None)).unwrap();
+        let wall_clock = parse_exif_datetime("2023:01:15 14:30:45").unwrap();
+        // The default is process-wide, so only the local-timezone case is checked here
+        assert_eq!(extract_exif_date(&tmp), exif_timestamp(wall_clock, None));
+        let (timestamp, offset) = extract_exif_date(&tmp).unwrap();
+        assert_eq!(timestamp + i64::from(offset) * 60, wall_clock.and_utc().timestamp());
+        let _ = fs::remove_file(&tmp);
+    }
+
     // parse_filename_date
 
     #[test]
@@ -466,6 +1188,103 @@ mod tests {
         assert!(parse_filename_date("1800-01-01.jpg").is_none());
     }
 
+    #[test]
+    fn filename_date_formats() {
+        let cases: &[(&str, Option<&str>)] = &[
+            ("IMG_20230115_143045.jpg", Some("2023-01-15 14:30:45")),
+            ("20230115_143045.jpg", Some("2023-01-15 14:30:45")),
+            ("VID_20230115_143045.mp4", Some("2023-01-15 14:30:45")),
+            ("PXL_20230115_143045123.jpg", Some("2023-01-15 14:30:45")),
+            ("PXL_20230115_143045123.NIGHT.jpg", Some("2023-01-15 14:30:45")),
+            ("Screenshot_20230115-143045.png", Some("2023-01-15 14:30:45")),
+            ("Screenshot_20230115-143045_Chrome.jpg", Some("2023-01-15 14:30:45")),
+            ("Screenshot 2023-01-15 at 14.30.45.png", Some("2023-01-15 14:30:45")),
+            ("Screen Shot 2020-01-15 at 2.30.45 PM.png", Some("2020-01-15 14:30:45")),
+            ("Screen Shot 2020-01-15 at 12.05.00 AM.png", Some("2020-01-15 00:05:00")),
+            ("IMG-20230115-WA0012.jpg", Some("2023-01-15 00:00:00")),
+            ("VID-20230115-WA0003.mp4", Some("2023-01-15 00:00:00")),
+            ("2023-01-15 14.30.45.jpg", Some("2023-01-15 14:30:45")),
+            ("2017-11-26_030858.jpeg", Some("2017-11-26 03:08:58")),
+            ("IMG_2023_06_15.jpg", Some("2023-06-15 00:00:00")),
+            ("20230115.jpg", Some("2023-01-15 00:00:00")),
+            ("signal-2023-01-15-143045.jpg", Some("2023-01-15 14:30:45")),
+            // Not dates
+            ("DSC_4032.jpg", None),
+            ("IMG_4032.HEIC", None),
+            ("3024x4032.jpg", None),
+            ("wallpaper_1920x1080.png", None),
+            ("IMG_20231345_143045.jpg", None),
+            ("IMG_20230230.jpg", None),
+            ("12345678.jpg", None),
+            ("scan_19850704.jpg", None),
+            ("IMG_20990101_000000.jpg", None),
+            // Former false positives
+            ("file_1234-56-78.jpg", None),
+            ("2045-11-30_invoice.png", None),
+            ("2023-02-31_party.jpg", None),
+            ("2023-01-15_trip_143045.jpg", Some("2023-01-15 00:00:00")),
+            ("IMG_2023_06_15_copy_120000.jpg", Some("2023-06-15 00:00:00")),
+        ];
+        for (name, expected) in cases {
+            let expected = expected.map(|s| {
+                NaiveDateTime::parse_from_str(s, "%Y-%m-%d %H:%M:%S").unwrap().and_utc().timestamp()
+            });
+            assert_eq!(parse_filename_date(name), expected, "{}", name);
+        }
+    }
+
+    // clean_exif_string
+
+    #[test]
+    fn exif_string_strips_nul_padding() {
+        assert_eq!(clean_exif_string("Apple\0\0").as_deref(), Some("Apple"));
+        assert_eq!(clean_exif_string("  SONY  ").as_deref(), Some("SONY"));
+    }
+
+    #[test]
+    fn exif_string_empty_is_none() {
+        assert!(clean_exif_string("\0").is_none());
+        assert!(clean_exif_string("   ").is_none());
+    }
+
+    // dms_to_decimal / finalize_gps
+
+    #[test]
+    fn dms_converts_to_decimal_degrees() {
+        let v = dms_to_decimal(37.0, 46.0, 30.0).unwrap();
+        assert!((v - 37.775).abs() < 1e-9);
+    }
+
+    #[test]
+    fn dms_rejects_zero_denominator_values() {
+        assert!(dms_to_decimal(f64::NAN, 0.0, 0.0).is_none());
+        assert!(dms_to_decimal(12.0, f64::INFINITY, 0.0).is_none());
+    }
+
+    #[test]
+    fn dms_rejects_out_of_range_minutes() {
+        assert!(dms_to_decimal(12.0, 75.0, 0.0).is_none());
+    }
+
+    #[test]
+    fn gps_southern_western_hemispheres_are_negative() {
+        let (lat, lon) = finalize_gps(33.8688, 151.2093, true, false).unwrap();
+        assert!(lat < 0.0 && lon > 0.0);
+        let (lat, lon) = finalize_gps(40.7128, 74.0060, false, true).unwrap();
+        assert!(lat > 0.0 && lon < 0.0);
+    }
+
+    #[test]
+    fn gps_zeroed_coordinates_are_none() {
+        assert!(finalize_gps(0.0, 0.0, false, false).is_none());
+        assert!(finalize_gps(0.0, 0.0, true, true).is_none());
+    }
+
+    #[test]
+    fn gps_out_of_range_is_none() {
+        assert!(finalize_gps(95.0, 10.0, false, false).is_none());
+    }
+
     // hamming_distance
 
     #[test]
@@ -526,6 +1345,224 @@ mod tests {
         assert!(!detect_screenshot("vacation_trip.jpg", 3024, 4032));
     }
 
+    #[test]
+    fn mime_types_follow_the_file_format() {
+        let mime = |name: &str| file_format(Path::new(name)).as_deref().and_then(mime_type);
+        assert_eq!(mime("/p/IMG_1.JPG"), Some("image/jpeg"));
+        assert_eq!(mime("/p/IMG_1.HEIC"), Some("image/heic"));
+        assert_eq!(mime("/p/clip.mov"), Some("video/quicktime"));
+        assert_eq!(mime("/p/raw.nef"), Some("image/x-nikon-nef"));
+        assert_eq!(mime("/p/notes.txt"), None);
+        // Videos and only videos get a video type
+        for format in ["jpeg", "png", "gif", "webp", "bmp", "tiff", "heic", "heif", "avif", "cr2", "nef", "arw", "dng"] {
+            assert!(!mime_type(format).unwrap().starts_with("video/"), "{}", format);
+        }
+        for format in VIDEO_FORMATS {
+            assert!(mime_type(format).unwrap().starts_with("video/"), "{}", format);
+        }
+    }
+
+    #[test]
+    fn screenshot_name_must_start_with_the_keyword() {
+        assert!(!detect_screenshot("motion_capture_rig.jpg", 4000, 3000));
+    }
+
+    #[test]
+    fn screen_sized_png_without_camera_is_screenshot() {
+        let mut photo = PhotoMetadata {
+            path: "/pics/IMG_0003.png".to_string(),
+            name: "IMG_0003.png".to_string(),
+            date_taken: 1700000000,
+            width: 1170,
+            height: 2532,
+            is_favorite: false,
+            content_hash: None,
+            latitude: None,
+            longitude: None,
+            location_name: None,
+            camera_make: None,
+            camera_model: None,
+            lens_model: None,
+            iso: None,
+            aperture: None,
+            shutter_us: None,
+            focal_length_mm: None,
+            orientation: None,
+            duration_ms: None,
+            codec: None,
+            thumb_status: None,
+            is_missing: false,
+            is_offline: false,
+            blurhash: None,
+            dominant_color: None,
+            color_label: None,
+            flag: None,
+            stack_id: None,
+            edited_key: None,
+            stack_count: None,
+            rating: 0,
+            tags: Vec::new(),
+            description: None,
+            file_format: Some("png".to_string()),
+            file_size: None,
+            mime_type: None,
+            paired_video_path: None,
+            filename_date_mismatch: None,
+            tz_offset_minutes: None,
+            date_source: None,
+            date_confident: None,
+            id: None,
+        };
+        assert!(is_screenshot(&photo));
+
+        photo.camera_make = Some("Canon".to_string());
+        assert!(!is_screenshot(&photo));
+        photo.camera_make = None;
+        photo.file_format = Some("jpeg".to_string());
+        assert!(!is_screenshot(&photo));
+        photo.file_format = Some("png".to_string());
+        photo.width = 1000;
+        photo.height = 700;
+        assert!(!is_screenshot(&photo));
+
+        // The name alone is enough, whatever the format
+        photo.name = "Screenshot_20240101-101010.jpg".to_string();
+        photo.file_format = Some("jpeg".to_string());
+        assert!(is_screenshot(&photo));
+    }
+
+    // read_dimensions
+
+    fn write_test_png(name: &str, width: u32, height: u32) -> std::path::PathBuf {
+        let tmp = std::env::temp_dir().join(format!("terra-media-{}-{}.png", name, std::process::id()));
+        let img: image::ImageBuffer<image::Rgb<u8>, Vec<u8>> =
+            image::ImageBuffer::from_pixel(width, height, image::Rgb([10, 20, 30]));
+        img.save(&tmp).expect("write test image");
+        tmp
+    }
+
+    #[test]
+    fn read_dimensions_matches_full_decode() {
+        let tmp = write_test_png("dims", 640, 480);
+        assert_eq!(read_dimensions(&tmp), Some((640, 480)));
+        let decoded = image::open(&tmp).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (640, 480));
+        let _ = fs::remove_file(&tmp);
+    }
+
+    #[test]
+    fn quarter_turn_orientations_swap_dimensions() {
+        assert_eq!(displayed_dimensions(4000, 3000, Orientation::NoTransforms), (4000, 3000));
+        assert_eq!(displayed_dimensions(4000, 3000, Orientation::Rotate180), (4000, 3000));
+        assert_eq!(displayed_dimensions(4000, 3000, Orientation::FlipVertical), (4000, 3000));
+        assert_eq!(displayed_dimensions(4000, 3000, Orientation::Rotate90), (3000, 4000));
+        assert_eq!(displayed_dimensions(4000, 3000, Orientation::Rotate270FlipH), (3000, 4000));
+    }
+
+    #[test]
+    fn read_dimensions_none_for_garbage() {
+        let tmp = std::env::temp_dir().join(format!("terra-media-garbage-{}.jpg", std::process::id()));
+        fs::write(&tmp, b"definitely not a jpeg").unwrap();
+        assert!(read_dimensions(&tmp).is_none());
+        let _ = fs::remove_file(&tmp);
+    }
+
+    #[test]
+    fn heic_dimensions_and_exif_come_from_the_container() {
+        use crate::heif::tests::{exif_tiff, heic_fixture, write_fixture};
+
+        let tmp = write_fixture("media", &heic_fixture(4032, 3024, 1, &exif_tiff()));
+        assert_eq!(read_dimensions(&tmp), Some((3024, 4032)));
+        let wall_clock = parse_exif_datetime("2019:07:17 10:30:00").unwrap();
+        assert_eq!(extract_exif_date(&tmp), exif_timestamp(wall_clock, None));
+        let _ = fs::remove_file(&tmp);
+    }
+
+    #[test]
+    fn test_calculate_hash_matches_sha256_across_chunks() {
+        let tmp = std::env::temp_dir().join(format!("terra-media-hash-{}.bin", std::process::id()));
+        fs::write(&tmp, b"abc").unwrap();
+        assert_eq!(
+            calculate_hash(&tmp).as_deref(),
+            Some("ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad")
+        );
+
+        // Larger than one read buffer, identical content hashes identically
+        let big = vec![7u8; 3 * 1024 * 1024 + 17];
+        fs::write(&tmp, &big).unwrap();
+        let first = calculate_hash(&tmp);
+        let copy = tmp.with_extension("copy");
+        fs::write(&copy, &big).unwrap();
+        assert!(first.is_some());
+        assert_eq!(first, calculate_hash(&copy));
+
+        let _ = fs::remove_file(&tmp);
+        let _ = fs::remove_file(&copy);
+    }
+
+    #[test]
+    fn test_phash_rotations_match_rotated_copy() {
+        let img = image::DynamicImage::ImageLuma8(image::ImageBuffer::from_fn(64, 64, |x, y| {
+            // Asymmetric pattern so each rotation hashes differently
+            image::Luma([((x * 3 + y * y / 16) % 256) as u8])
+        }));
+        let original = phash_rotations_of(&img).unwrap();
+        let rotated = phash_rotations_of(&img.rotate90()).unwrap();
+
+        assert_eq!(rotation_distance(rotated[0], &original), 0);
+        assert_eq!(rotation_distance(original[0], &rotated), 0);
+    }
+
+    #[test]
+    fn test_group_similar_hashes_links_near_and_rotated() {
+        let base = 0x0123_4567_89ab_cdefu64;
+        let hashes = vec![
+            [base, 1, 2, 3],
+            // 3 bits away from photo 0
+            [base ^ 0b111, 4, 5, 6],
+            // Upright differs completely, but its 180° rotation is photo 0
+            [!base, 7, base, 8],
+            // Unrelated
+            [0xffff_0000_ffff_0000, 9, 10, 11],
+        ];
+
+        let mut groups = group_similar_hashes(&hashes, 4);
+        assert_eq!(groups.len(), 1);
+        groups[0].sort();
+        assert_eq!(groups[0], vec![0, 1, 2]);
+
+        // A stricter threshold drops the 3-bit neighbour but keeps the rotation
+        let mut strict = group_similar_hashes(&hashes, 2);
+        assert_eq!(strict.len(), 1);
+        strict[0].sort();
+        assert_eq!(strict[0], vec![0, 2]);
+    }
+
+    /// Benchmark: header parse vs full decode. Run with
+    /// `cargo test --release bench_read_dimensions -- --ignored --nocapture`.
+    #[test]
+    #[ignore]
+    fn bench_read_dimensions_vs_full_decode() {
+        let tmp = write_test_png("bench", 4000, 3000);
+        let iterations = 20;
+
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            read_dimensions(&tmp).unwrap();
+        }
+        let header = start.elapsed();
+
+        let start = std::time::Instant::now();
+        for _ in 0..iterations {
+            image::open(&tmp).unwrap();
+        }
+        let decode = start.elapsed();
+
+        println!("header: {:?}, full decode: {:?} ({} iterations)", header, decode, iterations);
+        assert!(header < decode);
+        let _ = fs::remove_file(&tmp);
+    }
+
     // is_video
 
     #[test]
@@ -547,4 +1584,75 @@ mod tests {
     fn no_extension_is_not_video() {
         assert!(!is_video(Path::new("noextension")));
     }
+
+    // parse_xmp_description
+
+    #[test]
+    fn reads_xmp_description_alt_entry() {
+        let xmp = r#"<x:xmpmeta><rdf:RDF><rdf:Description>
+            <dc:description><rdf:Alt>
+              <rdf:li xml:lang="x-default">Grandma&apos;s 90th, back porch &amp; yard</rdf:li>
+            </rdf:Alt></dc:description>
+        </rdf:Description></rdf:RDF></x:xmpmeta>"#;
+        assert_eq!(
+            parse_xmp_description(xmp).as_deref(),
+            Some("Grandma's 90th, back porch & yard")
+        );
+    }
+
+    #[test]
+    fn empty_xmp_description_is_none() {
+        let xmp = r#"<dc:description><rdf:Alt><rdf:li xml:lang="x-default"> </rdf:li></rdf:Alt></dc:description>"#;
+        assert!(parse_xmp_description(xmp).is_none());
+    }
+
+    // parse_xmp_rating
+
+    #[test]
+    fn reads_xmp_rating_attribute() {
+        assert_eq!(parse_xmp_rating(r#"<rdf:Description xmp:Rating="4" xmp:Label="Red"/>"#), Some(4));
+    }
+
+    #[test]
+    fn reads_xmp_rating_element() {
+        assert_eq!(parse_xmp_rating("<xmp:Rating>3</xmp:Rating>"), Some(3));
+    }
+
+    #[test]
+    fn rejected_and_out_of_range_ratings_are_clamped() {
+        assert_eq!(parse_xmp_rating(r#"xmp:Rating="-1""#), Some(0));
+        assert_eq!(parse_xmp_rating(r#"xmp:Rating="9""#), Some(5));
+    }
+
+    #[test]
+    fn missing_xmp_rating_is_none() {
+        assert!(parse_xmp_rating(r#"<rdf:Description xmp:Label="Red"/>"#).is_none());
+    }
+
+    // parse_xmp_label
+
+    #[test]
+    fn reads_lightroom_xmp_labels() {
+        assert_eq!(parse_xmp_label(r#"<rdf:Description xmp:Rating="4" xmp:Label="Red"/>"#), Some(db::ColorLabel::Red));
+        assert_eq!(parse_xmp_label("<xmp:Label>Purple</xmp:Label>"), Some(db::ColorLabel::Purple));
+    }
+
+    #[test]
+    fn reads_bridge_label_names() {
+        assert_eq!(parse_xmp_label(r#"xmp:Label="Approved""#), Some(db::ColorLabel::Green));
+        assert_eq!(parse_xmp_label("xmp:Label='To Do'"), Some(db::ColorLabel::Purple));
+    }
+
+    #[test]
+    fn unknown_or_missing_xmp_labels_are_none() {
+        assert!(parse_xmp_label(r#"xmp:Label="Client picks""#).is_none());
+        assert!(parse_xmp_label(r#"xmp:Label="""#).is_none());
+        assert!(parse_xmp_label(r#"<rdf:Description xmp:Rating="4"/>"#).is_none());
+    }
+
+    #[test]
+    fn xmp_without_description_is_none() {
+        let xmp = r#"<dc:title><rdf:Alt><rdf:li xml:lang="x-default">Title</rdf:li></rdf:Alt></dc:title>"#;
+        assert!(parse_xmp_description(xmp).is_none());
+    }
 }
@@ -0,0 +1,296 @@
+//! Instagram and Facebook data exports ("Download your information"):
+//! finding the JSON that describes each media file (posts, stories, albums,
+//! messages) and reading its capture time and caption. Meta strips EXIF, so
+//! the JSON is the only record of when most of it was taken.
+//!
+//! Layouts change between exports, so rather than expecting particular
+//! files, every JSON file is searched for objects whose `uri` (or `path`)
+//! names a file. Dates and captions an object lacks are taken from the post
+//! or message around it.
+//!
+//! No database access; `import_meta_export` in `lib.rs` does the importing.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json::{Map, Value};
+use walkdir::WalkDir;
+
+use crate::media;
+use crate::takeout::Sidecar;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Service {
+    Instagram,
+    Facebook,
+}
+
+impl Service {
+    pub(crate) fn parse(service: &str) -> Result<Service, String> {
+        match service.to_ascii_lowercase().as_str() {
+            "instagram" => Ok(Service::Instagram),
+            "facebook" => Ok(Service::Facebook),
+            _ => Err(format!("Unknown service \"{}\"; use instagram or facebook", service)),
+        }
+    }
+
+    /// photos.source_type of what it imports
+    pub(crate) fn source_type(self) -> &'static str {
+        match self {
+            Service::Instagram => "instagram",
+            Service::Facebook => "facebook",
+        }
+    }
+}
+
+/// What an export's JSON says about its media files.
+#[derive(Debug, Default)]
+pub(crate) struct ExportIndex {
+    /// By the file's path under the export folder
+    pub media: HashMap<PathBuf, Sidecar>,
+    /// References to a file another post or message already named
+    pub repeated: u32,
+}
+
+/// Meta writes text as UTF-8 bytes escaped one per character ("Ã©"
+/// for "é"). Undo that; text that isn't double-encoded is returned as is.
+pub(crate) fn fix_mojibake(text: &str) -> String {
+    let bytes: Option<Vec<u8>> = text.chars().map(|c| u8::try_from(u32::from(c)).ok()).collect();
+    bytes.and_then(|bytes| String::from_utf8(bytes).ok()).unwrap_or_else(|| text.to_string())
+}
+
+/// A non-blank string field, repaired.
+fn text(map: &Map<String, Value>, key: &str) -> Option<String> {
+    let value = map.get(key)?.as_str()?.trim();
+    (!value.is_empty()).then(|| fix_mojibake(value))
+}
+
+/// Seconds since the epoch, written as a number or an ISO date.
+fn seconds(value: &Value) -> Option<i64> {
+    let seconds = match value {
+        Value::Number(n) => n.as_i64(),
+        Value::String(s) => chrono::DateTime::parse_from_rfc3339(s)
+            .map(|d| d.timestamp())
+            .ok()
+            .or_else(|| chrono::NaiveDateTime::parse_from_str(s, "%Y-%m-%dT%H:%M:%S").ok().map(|d| d.and_utc().timestamp())),
+        _ => None,
+    };
+    seconds.filter(|&t| t > 0)
+}
+
+/// When a post, message or media object happened, by its own fields.
+fn timestamp(map: &Map<String, Value>) -> Option<i64> {
+    ["taken_at", "creation_timestamp", "timestamp"]
+        .iter()
+        .find_map(|key| map.get(*key).and_then(seconds))
+        .or_else(|| map.get("timestamp_ms").and_then(Value::as_i64).filter(|&t| t > 0).map(|t| t / 1000))
+}
+
+/// The camera's capture time and position, which Facebook sometimes keeps
+/// from the stripped EXIF under `media_metadata`.
+fn exif_data(map: &Map<String, Value>) -> (Option<i64>, Option<(f64, f64)>) {
+    let exif = map
+        .get("media_metadata")
+        .and_then(|m| m.get("photo_metadata").or_else(|| m.get("video_metadata")))
+        .and_then(|m| m.get("exif_data"))
+        .and_then(Value::as_array);
+    let Some(exif) = exif else {
+        return (None, None);
+    };
+    let taken = exif.iter().find_map(|e| e.get("taken_timestamp").and_then(seconds));
+    let gps = exif.iter().find_map(|e| {
+        let lat = e.get("latitude")?.as_f64()?;
+        let lon = e.get("longitude")?.as_f64()?;
+        media::finalize_gps(lat, lon, false, false)
+    });
+    (taken, gps)
+}
+
+/// The caption a post or media object carries. Instagram puts it in
+/// `title` (`caption` in old exports); Facebook's `title` is an album
+/// name, and captions are a `description` or a post's `data[].post`.
+fn caption(map: &Map<String, Value>, service: Service) -> Option<String> {
+    match service {
+        Service::Instagram => text(map, "title").or_else(|| text(map, "caption")),
+        Service::Facebook => text(map, "description").or_else(|| {
+            map.get("data")?.as_array()?.iter().find_map(|d| text(d.as_object()?, "post"))
+        }),
+    }
+}
+
+/// What media inside an object inherits when it doesn't say itself.
+#[derive(Clone, Default)]
+struct Context {
+    taken_at: Option<i64>,
+    caption: Option<String>,
+}
+
+/// Media referenced anywhere under `value`, as (uri, what's known about it).
+fn collect(value: &Value, inherited: &Context, service: Service, found: &mut Vec<(String, Sidecar)>) {
+    match value {
+        Value::Array(items) => {
+            for item in items {
+                collect(item, inherited, service, found);
+            }
+        }
+        Value::Object(map) => {
+            let context = Context {
+                taken_at: timestamp(map).or(inherited.taken_at),
+                caption: caption(map, service).or_else(|| inherited.caption.clone()),
+            };
+            let uri = ["uri", "path"].iter().find_map(|key| map.get(*key)?.as_str());
+            if let Some(uri) = uri.filter(|u| !u.contains("://") && Path::new(u).extension().is_some()) {
+                let (exif_taken, gps) = exif_data(map);
+                found.push((
+                    uri.to_string(),
+                    Sidecar {
+                        title: None,
+                        taken_at: exif_taken.or(context.taken_at),
+                        description: context.caption.clone(),
+                        gps,
+                        // Meta transcodes uploads; a video's container time is the upload's
+                        re_encoded: true,
+                    },
+                ));
+            }
+            for (key, child) in map {
+                if !(child.is_object() || child.is_array()) {
+                    continue;
+                }
+                // A conversation's title is who it was with, not a caption
+                if key == "messages" {
+                    let bare = Context { caption: None, ..context.clone() };
+                    collect(child, &bare, service, found);
+                } else {
+                    collect(child, &context, service, found);
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Where `uri` from a JSON file in `json_dir` points. Uris are relative to
+/// the export's top folder, which may be `root` or a folder above the JSON
+/// when the export was unpacked into a subfolder or re-rooted.
+fn resolve(root: &Path, json_dir: &Path, uri: &str) -> PathBuf {
+    let uri = uri.trim_start_matches('/');
+    json_dir
+        .ancestors()
+        .take_while(|dir| dir.starts_with(root))
+        .map(|dir| dir.join(uri))
+        .find(|path| path.is_file())
+        .unwrap_or_else(|| root.join(uri))
+}
+
+/// Read every JSON file under `root`. A file several posts or messages
+/// refer to is kept once, with its earliest date and first caption.
+pub(crate) fn read_export(root: &Path, service: Service) -> ExportIndex {
+    let mut index = ExportIndex::default();
+    let json_files = WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .filter(|e| e.path().extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")));
+    for entry in json_files {
+        let Some(value) = fs::read_to_string(entry.path()).ok().and_then(|json| serde_json::from_str::<Value>(&json).ok())
+        else {
+            continue;
+        };
+        let mut found = Vec::new();
+        collect(&value, &Context::default(), service, &mut found);
+        let json_dir = entry.path().parent().unwrap_or(root);
+        for (uri, sidecar) in found {
+            let path = resolve(root, json_dir, &uri);
+            match index.media.get_mut(&path) {
+                Some(known) => {
+                    index.repeated += 1;
+                    known.taken_at = match (known.taken_at, sidecar.taken_at) {
+                        (Some(a), Some(b)) => Some(a.min(b)),
+                        (a, b) => a.or(b),
+                    };
+                    known.description = known.description.take().or(sidecar.description);
+                    known.gps = known.gps.or(sidecar.gps);
+                }
+                None => {
+                    index.media.insert(path, sidecar);
+                }
+            }
+        }
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn collected(json: &str, service: Service) -> Vec<(String, Sidecar)> {
+        let mut found = Vec::new();
+        collect(&serde_json::from_str(json).unwrap(), &Context::default(), service, &mut found);
+        found
+    }
+
+    #[test]
+    fn repairs_double_encoded_text() {
+        assert_eq!(fix_mojibake("Caf\u{00c3}\u{00a9} \u{00f0}\u{009f}\u{0098}\u{008d}"), "Café 😍");
+        assert_eq!(fix_mojibake("plain"), "plain");
+        // Already proper text, or Latin-1 that isn't UTF-8 underneath
+        assert_eq!(fix_mojibake("Café 😍"), "Café 😍");
+        assert_eq!(fix_mojibake("\u{00e9}t\u{00e9}"), "\u{00e9}t\u{00e9}");
+    }
+
+    #[test]
+    fn instagram_posts_give_media_their_post_date_and_caption() {
+        let posts = r#"[
+            {"media": [{"uri": "media/posts/201906/a.jpg", "creation_timestamp": 1559399405, "title": "Beach day"}]},
+            {"title": "Two of them", "creation_timestamp": 1559400000,
+             "media": [{"uri": "media/posts/201906/b.jpg", "title": ""}, {"uri": "media/posts/201906/c.mp4"}]},
+            {"media": [{"uri": "https://example.com/x.jpg", "creation_timestamp": 1}]}
+        ]"#;
+        let found = collected(posts, Service::Instagram);
+        assert_eq!(found.len(), 3);
+        assert_eq!(found[0].0, "media/posts/201906/a.jpg");
+        assert_eq!(found[0].1.taken_at, Some(1_559_399_405));
+        assert_eq!(found[0].1.description.as_deref(), Some("Beach day"));
+        assert_eq!(found[1].1.taken_at, Some(1_559_400_000));
+        assert_eq!(found[2].1.description.as_deref(), Some("Two of them"));
+    }
+
+    #[test]
+    fn facebook_posts_use_exif_dates_and_post_text() {
+        let posts = r#"[{
+            "timestamp": 1600000000, "title": "Me updated my status.",
+            "data": [{"post": "Hiking Ã©tÃ©"}],
+            "attachments": [{"data": [{"media": {"uri": "posts/media/x.jpg", "creation_timestamp": 1600000100,
+                "title": "Mobile Uploads",
+                "media_metadata": {"photo_metadata": {"exif_data": [{"taken_timestamp": 1590000000,
+                    "latitude": 47.6, "longitude": -122.3}]}}}}]}]
+        }]"#;
+        let found = collected(posts, Service::Facebook);
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].1.taken_at, Some(1_590_000_000));
+        assert_eq!(found[0].1.description.as_deref(), Some("Hiking été"));
+        assert_eq!(found[0].1.gps, Some((47.6, -122.3)));
+    }
+
+    #[test]
+    fn message_media_takes_the_message_time_but_not_the_thread_title() {
+        let thread = r#"{"title": "Alex", "messages": [
+            {"timestamp_ms": 1600000000123, "content": "look", "photos": [{"uri": "messages/inbox/alex/photos/p.jpg"}]}
+        ]}"#;
+        let found = collected(thread, Service::Instagram);
+        assert_eq!(found[0].1.taken_at, Some(1_600_000_000));
+        assert_eq!(found[0].1.description, None);
+    }
+
+    #[test]
+    fn old_exports_use_taken_at_and_path() {
+        let media = r#"{"photos": [{"caption": "Old one", "taken_at": "2015-03-01T10:00:00", "path": "photos/201503/o.jpg"}]}"#;
+        let found = collected(media, Service::Instagram);
+        assert_eq!(found[0].0, "photos/201503/o.jpg");
+        assert_eq!(found[0].1.taken_at, Some(1_425_204_000));
+        assert_eq!(found[0].1.description.as_deref(), Some("Old one"));
+    }
+}
@@ -0,0 +1,357 @@
+//! Library metadata export: everything Terra knows about each photo (dates,
+//! favorites, ratings, albums, tags, location, camera) as a JSON document
+//! or a CSV table, for backup and for other tools. Records are written one
+//! at a time as they're read, so a large library is never held in memory.
+//! A JSON export can be read back in to restore them.
+//!
+//! JSON is `{"schema_version", "exported_at", "photos": [...]}` with album
+//! and tag names as arrays. CSV has one row per photo, the schema version
+//! and export time repeated on each, and album and tag names joined with
+//! ";" (a ";" or "\" inside a name is escaped with "\").
+//!
+//! No database access; `export_metadata` in `lib.rs` feeds it rows from
+//! `db::get_photo_records`, and `import_metadata` hands what it reads
+//! to `db::import_photo_records`.
+
+use std::io::{self, Read, Write};
+
+use serde::{Deserialize, Serialize};
+
+use crate::db::{ColorLabel, PhotoFlag};
+use crate::media;
+
+/// Bumped when a field changes meaning or is removed; new fields don't.
+pub(crate) const SCHEMA_VERSION: u32 = 1;
+
+/// One photo as exported. Every field is optional on the way back in, so
+/// older or hand-edited exports still load.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct PhotoRecord {
+    pub id: i64,
+    pub path: String,
+    pub name: String,
+    /// Unix timestamp
+    pub date_taken: i64,
+    /// Capture time on the photo's own clock, RFC 3339; None if undated
+    pub date: Option<String>,
+    pub tz_offset_minutes: Option<i32>,
+    pub date_source: Option<String>,
+    pub width: u32,
+    pub height: u32,
+    pub file_format: Option<String>,
+    pub file_size: Option<i64>,
+    pub content_hash: Option<String>,
+    pub is_favorite: bool,
+    pub rating: u8,
+    pub color_label: Option<ColorLabel>,
+    pub flag: Option<PhotoFlag>,
+    pub is_hidden: bool,
+    pub is_archived: bool,
+    pub description: Option<String>,
+    pub latitude: Option<f64>,
+    pub longitude: Option<f64>,
+    pub location_name: Option<String>,
+    pub camera_make: Option<String>,
+    pub camera_model: Option<String>,
+    pub lens_model: Option<String>,
+    pub iso: Option<i32>,
+    pub aperture: Option<f64>,
+    pub shutter_us: Option<i64>,
+    pub focal_length_mm: Option<f64>,
+    pub duration_ms: Option<i64>,
+    pub albums: Vec<String>,
+    pub tags: Vec<String>,
+}
+
+/// `date_taken` as RFC 3339 on the photo's own clock, or None for a photo
+/// whose date is unknown.
+pub(crate) fn record_date(date_taken: i64, tz_offset_minutes: Option<i32>, date_source: Option<&str>) -> Option<String> {
+    if date_source == Some(media::DATE_SOURCE_UNKNOWN) {
+        return None;
+    }
+    let (wall_clock, offset_minutes) = media::exif_wall_clock(date_taken, tz_offset_minutes)?;
+    let offset = chrono::FixedOffset::east_opt(offset_minutes * 60)?;
+    Some(wall_clock.and_local_timezone(offset).single()?.to_rfc3339())
+}
+
+/// A JSON export as read back in.
+#[derive(Deserialize)]
+struct ExportDocument {
+    schema_version: u32,
+    #[serde(default)]
+    photos: Vec<PhotoRecord>,
+}
+
+/// Read the records of a JSON export. Errors on anything else, including
+/// an export from a newer Terra whose fields may mean something else.
+pub(crate) fn read_export(reader: impl Read) -> Result<Vec<PhotoRecord>, String> {
+    let document: ExportDocument = serde_json::from_reader(io::BufReader::new(reader))
+        .map_err(|e| format!("Not a Terra metadata export: {}", e))?;
+    if document.schema_version > SCHEMA_VERSION {
+        return Err(format!(
+            "The export is from a newer version of Terra (schema {}, this one reads up to {})",
+            document.schema_version, SCHEMA_VERSION
+        ));
+    }
+    Ok(document.photos)
+}
+
+/// How an import treats metadata a photo already has.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ImportMode {
+    /// Only fill what's empty: an unrated photo gets the rating, a rated
+    /// one keeps its own. Tags and albums are added to.
+    Merge,
+    /// The export wins: fields are replaced, and tags and albums the
+    /// export doesn't list are removed.
+    Overwrite,
+}
+
+impl ImportMode {
+    pub(crate) fn parse(mode: &str) -> Result<ImportMode, String> {
+        match mode.to_ascii_lowercase().as_str() {
+            "merge" => Ok(ImportMode::Merge),
+            "overwrite" => Ok(ImportMode::Overwrite),
+            _ => Err(format!("Unknown import mode \"{}\"; use merge or overwrite", mode)),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum ExportFormat {
+    Json,
+    Csv,
+}
+
+impl ExportFormat {
+    pub(crate) fn parse(format: &str) -> Result<ExportFormat, String> {
+        match format.to_ascii_lowercase().as_str() {
+            "json" => Ok(ExportFormat::Json),
+            "csv" => Ok(ExportFormat::Csv),
+            _ => Err(format!("Unknown export format \"{}\"; use json or csv", format)),
+        }
+    }
+}
+
+const CSV_COLUMNS: [&str; 34] = [
+    "schema_version",
+    "exported_at",
+    "id",
+    "path",
+    "name",
+    "date_taken",
+    "date",
+    "tz_offset_minutes",
+    "date_source",
+    "width",
+    "height",
+    "file_format",
+    "file_size",
+    "content_hash",
+    "is_favorite",
+    "rating",
+    "color_label",
+    "flag",
+    "is_hidden",
+    "is_archived",
+    "description",
+    "latitude",
+    "longitude",
+    "location_name",
+    "camera_make",
+    "camera_model",
+    "lens_model",
+    "iso",
+    "aperture",
+    "shutter_us",
+    "focal_length_mm",
+    "duration_ms",
+    "albums",
+    "tags",
+];
+
+/// A CSV cell, quoted when it holds a separator, quote or line break.
+fn csv_cell(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// Names joined with ";", escaping the separator and the escape.
+fn join_names(names: &[String]) -> String {
+    names.iter().map(|name| name.replace('\\', "\\\\").replace(';', "\\;")).collect::<Vec<_>>().join(";")
+}
+
+fn opt<T: ToString>(value: &Option<T>) -> String {
+    value.as_ref().map(T::to_string).unwrap_or_default()
+}
+
+fn csv_row(record: &PhotoRecord, exported_at: &str) -> Vec<String> {
+    vec![
+        SCHEMA_VERSION.to_string(),
+        exported_at.to_string(),
+        record.id.to_string(),
+        record.path.clone(),
+        record.name.clone(),
+        record.date_taken.to_string(),
+        opt(&record.date),
+        opt(&record.tz_offset_minutes),
+        opt(&record.date_source),
+        record.width.to_string(),
+        record.height.to_string(),
+        opt(&record.file_format),
+        opt(&record.file_size),
+        opt(&record.content_hash),
+        record.is_favorite.to_string(),
+        record.rating.to_string(),
+        record.color_label.and_then(ColorLabel::stored).unwrap_or_default().to_string(),
+        record.flag.and_then(PhotoFlag::stored).unwrap_or_default().to_string(),
+        record.is_hidden.to_string(),
+        record.is_archived.to_string(),
+        opt(&record.description),
+        opt(&record.latitude),
+        opt(&record.longitude),
+        opt(&record.location_name),
+        opt(&record.camera_make),
+        opt(&record.camera_model),
+        opt(&record.lens_model),
+        opt(&record.iso),
+        opt(&record.aperture),
+        opt(&record.shutter_us),
+        opt(&record.focal_length_mm),
+        opt(&record.duration_ms),
+        join_names(&record.albums),
+        join_names(&record.tags),
+    ]
+}
+
+/// Writes records to `out` as they come. Call `finish` to close the
+/// document; a JSON export cut short isn't valid JSON.
+pub(crate) struct MetadataWriter<W: Write> {
+    out: W,
+    format: ExportFormat,
+    exported_at: String,
+    written: usize,
+}
+
+impl<W: Write> MetadataWriter<W> {
+    /// Start a document, writing its header. `exported_at` is RFC 3339.
+    pub(crate) fn new(mut out: W, format: ExportFormat, exported_at: &str) -> io::Result<Self> {
+        match format {
+            ExportFormat::Json => write!(
+                out,
+                "{{\"schema_version\":{},\"exported_at\":{},\"photos\":[",
+                SCHEMA_VERSION,
+                serde_json::to_string(exported_at)?
+            )?,
+            ExportFormat::Csv => writeln!(out, "{}", CSV_COLUMNS.join(","))?,
+        }
+        Ok(MetadataWriter { out, format, exported_at: exported_at.to_string(), written: 0 })
+    }
+
+    pub(crate) fn write(&mut self, record: &PhotoRecord) -> io::Result<()> {
+        match self.format {
+            ExportFormat::Json => {
+                let separator: &[u8] = if self.written == 0 { b"\n" } else { b",\n" };
+                self.out.write_all(separator)?;
+                serde_json::to_writer(&mut self.out, record)?;
+            }
+            ExportFormat::Csv => {
+                let cells: Vec<String> = csv_row(record, &self.exported_at).iter().map(|cell| csv_cell(cell)).collect();
+                writeln!(self.out, "{}", cells.join(","))?;
+            }
+        }
+        self.written += 1;
+        Ok(())
+    }
+
+    /// Close the document and flush it. Returns how many records it holds.
+    pub(crate) fn finish(mut self) -> io::Result<usize> {
+        if self.format == ExportFormat::Json {
+            self.out.write_all(b"\n]}\n")?;
+        }
+        self.out.flush()?;
+        Ok(self.written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> PhotoRecord {
+        PhotoRecord {
+            id: 7,
+            path: "/lib/a, b.jpg".to_string(),
+            name: "a, b.jpg".to_string(),
+            date_taken: 1_559_399_405,
+            tz_offset_minutes: Some(120),
+            is_favorite: true,
+            color_label: Some(ColorLabel::Red),
+            description: Some("Said \"cheese\"".to_string()),
+            albums: vec!["Trips; 2019".to_string(), "Family".to_string()],
+            tags: vec!["beach".to_string()],
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn json_export_reads_back() {
+        let mut out = Vec::new();
+        let mut writer = MetadataWriter::new(&mut out, ExportFormat::Json, "2024-01-01T00:00:00+00:00").unwrap();
+        writer.write(&record()).unwrap();
+        writer.write(&PhotoRecord { id: 8, ..Default::default() }).unwrap();
+        assert_eq!(writer.finish().unwrap(), 2);
+
+        let doc: serde_json::Value = serde_json::from_slice(&out).unwrap();
+        assert_eq!(doc["schema_version"], SCHEMA_VERSION);
+        assert_eq!(doc["exported_at"], "2024-01-01T00:00:00+00:00");
+        let photos: Vec<PhotoRecord> = serde_json::from_value(doc["photos"].clone()).unwrap();
+        assert_eq!(photos, vec![record(), PhotoRecord { id: 8, ..Default::default() }]);
+
+        let empty = MetadataWriter::new(Vec::new(), ExportFormat::Json, "2024-01-01T00:00:00+00:00").unwrap();
+        assert_eq!(empty.finish().unwrap(), 0);
+    }
+
+    #[test]
+    fn csv_export_quotes_and_joins() {
+        let mut out = Vec::new();
+        let mut writer = MetadataWriter::new(&mut out, ExportFormat::Csv, "2024-01-01T00:00:00+00:00").unwrap();
+        writer.write(&record()).unwrap();
+        writer.finish().unwrap();
+        let text = String::from_utf8(out).unwrap();
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines[0].split(',').count(), 34);
+        assert!(lines[0].ends_with(",albums,tags"));
+        assert!(lines[1].starts_with("1,2024-01-01T00:00:00+00:00,7,\"/lib/a, b.jpg\",\"a, b.jpg\","));
+        assert!(lines[1].contains(",red,"));
+        assert!(lines[1].contains(",\"Said \"\"cheese\"\"\","));
+        assert!(lines[1].ends_with(",Trips\\; 2019;Family,beach"));
+    }
+
+    #[test]
+    fn reads_back_exports_it_understands() {
+        let mut out = Vec::new();
+        let mut writer = MetadataWriter::new(&mut out, ExportFormat::Json, "2024-01-01T00:00:00+00:00").unwrap();
+        writer.write(&record()).unwrap();
+        writer.finish().unwrap();
+        assert_eq!(read_export(out.as_slice()).unwrap(), vec![record()]);
+
+        let sparse = r#"{"schema_version": 1, "photos": [{"path": "/p/a.jpg", "rating": 4}]}"#;
+        let records = read_export(sparse.as_bytes()).unwrap();
+        assert_eq!(records[0].rating, 4);
+        assert!(records[0].tags.is_empty());
+        assert!(read_export(r#"{"schema_version": 99, "photos": []}"#.as_bytes()).is_err());
+        assert!(read_export("[]".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn record_dates_use_the_photo_clock() {
+        assert_eq!(record_date(1_559_399_405, Some(120), Some("exif")).as_deref(), Some("2019-06-01T16:30:05+02:00"));
+        assert_eq!(record_date(0, None, Some(media::DATE_SOURCE_UNKNOWN)), None);
+        assert!(ExportFormat::parse("xml").is_err());
+    }
+}
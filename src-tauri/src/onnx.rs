@@ -0,0 +1,104 @@
+//! The ONNX models Terra can run on device (auto_tag.rs, faces.rs): where
+//! their files go and a thin layer over the tract runtime. Running one needs
+//! the `auto-tag` or `faces` cargo feature; without either, loading fails
+//! with a message saying so.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use sha2::{Digest, Sha256};
+
+/// `models/` beside the database, where downloaded model files go
+pub fn models_dir() -> PathBuf {
+    crate::db::get_db_path().with_file_name("models")
+}
+
+/// First 16 hex digits of the SHA-256 of `files`, so replacing a model
+/// changes its version
+pub fn files_version(files: &[&Path]) -> Result<String, String> {
+    let mut hasher = Sha256::new();
+    for file in files {
+        hasher.update(fs::read(file).map_err(|e| format!("Failed to read {}: {}", file.display(), e))?);
+    }
+    Ok(hex::encode(hasher.finalize())[..16].to_string())
+}
+
+/// Whether this build has the runtime. Either feature brings it in, and
+/// with it every model can run, so each model's status asks this.
+pub fn runtime_available() -> bool {
+    cfg!(any(feature = "auto-tag", feature = "faces"))
+}
+
+#[cfg(any(feature = "auto-tag", feature = "faces"))]
+pub type Model = tract_onnx::prelude::TypedRunnableModel<tract_onnx::prelude::TypedModel>;
+
+#[cfg(not(any(feature = "auto-tag", feature = "faces")))]
+pub type Model = ();
+
+/// Load a model taking one f32 input of `shape`
+#[cfg(any(feature = "auto-tag", feature = "faces"))]
+pub fn load(path: &Path, shape: [usize; 4]) -> Result<Model, String> {
+    use tract_onnx::prelude::*;
+
+    tract_onnx::onnx()
+        .model_for_path(path)
+        .and_then(|model| model.with_input_fact(0, f32::fact(shape).into()))
+        .and_then(|model| model.into_optimized())
+        .and_then(|model| model.into_runnable())
+        .map_err(|e| format!("Failed to load {}: {}", path.display(), e))
+}
+
+#[cfg(not(any(feature = "auto-tag", feature = "faces")))]
+pub fn load(_path: &Path, _shape: [usize; 4]) -> Result<Model, String> {
+    Err("Terra was built without on-device models (the `auto-tag` and `faces` features)".to_string())
+}
+
+/// Run `model` on `input` laid out as `shape`. Returns every output, flattened.
+#[cfg(any(feature = "auto-tag", feature = "faces"))]
+pub fn run(model: &Model, input: &[f32], shape: [usize; 4]) -> Result<Vec<Vec<f32>>, String> {
+    use tract_onnx::prelude::*;
+
+    let run = || -> TractResult<Vec<Vec<f32>>> {
+        let tensor = Tensor::from_shape(&shape, input)?;
+        let outputs = model.run(tvec!(tensor.into()))?;
+        outputs
+            .iter()
+            .map(|output| Ok(output.to_array_view::<f32>()?.iter().copied().collect()))
+            .collect()
+    };
+    run().map_err(|e| format!("Model failed: {}", e))
+}
+
+#[cfg(not(any(feature = "auto-tag", feature = "faces")))]
+pub fn run(_model: &Model, _input: &[f32], _shape: [usize; 4]) -> Result<Vec<Vec<f32>>, String> {
+    Err("Terra was built without on-device models (the `auto-tag` and `faces` features)".to_string())
+}
+
+/// Planar RGB (channels, rows, columns) with each channel mapped through
+/// `(value / 255 - mean) / std`
+pub fn planar_rgb(rgb: &image::RgbImage, mean: [f32; 3], std: [f32; 3]) -> Vec<f32> {
+    let plane = (rgb.width() * rgb.height()) as usize;
+    let mut data = vec![0.0; 3 * plane];
+    for (i, pixel) in rgb.pixels().enumerate() {
+        for c in 0..3 {
+            data[c * plane + i] = (f32::from(pixel[c]) / 255.0 - mean[c]) / std[c];
+        }
+    }
+    data
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalizes_pixels_into_planes() {
+        let (mean, std) = ([0.485, 0.456, 0.406], [0.229, 0.224, 0.225]);
+        let rgb = image::RgbImage::from_raw(2, 1, vec![255, 0, 0, 0, 0, 255]).unwrap();
+        let data = planar_rgb(&rgb, mean, std);
+        assert_eq!(data.len(), 6);
+        assert!((data[0] - (1.0 - mean[0]) / std[0]).abs() < 1e-6);
+        assert!((data[1] - -mean[0] / std[0]).abs() < 1e-6);
+        assert!((data[5] - (1.0 - mean[2]) / std[2]).abs() < 1e-6);
+    }
+}
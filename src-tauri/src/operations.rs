@@ -0,0 +1,136 @@
+//! The undo log: what each destructive command changed, kept long enough for
+//! `undo_last_operation` to put it back. Photos are recorded by id, so a
+//! photo that moves or is refiled afterwards is still found.
+
+use serde::{Deserialize, Serialize};
+
+/// A logged change, with the state it replaced.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operation {
+    /// Photos moved to Terra's trash
+    MoveToTrash { photo_ids: Vec<i64> },
+    /// Album memberships removed, as (photo id, added_at)
+    RemoveFromAlbum { album_id: i64, members: Vec<(i64, i64)> },
+    /// Each photo's favorite flag before it was set, as (photo id, flag),
+    /// and the flag they were all given
+    SetFavorites { previous: Vec<(i64, bool)>, is_favorite: bool },
+    /// A photo's date before it was set by hand, and where its file was
+    /// refiled if it moved to another month's folder
+    SetPhotoDate {
+        previous: PreviousDate,
+        new_date_taken: i64,
+        moved: Option<FileMove>,
+    },
+    /// Duplicate groups resolved, undone as `undo_duplicate_resolution` does
+    ResolveDuplicates { resolution_ids: Vec<i64> },
+    /// A date shift, undone as `undo_date_shift` does
+    DateShift { shift_id: i64, photo_ids: Vec<i64> },
+    /// Files renamed by `batch_rename`, as (photo id, move)
+    BatchRename { renames: Vec<(i64, FileMove)> },
+}
+
+/// A photo's date fields as stored, for putting back exactly.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct PreviousDate {
+    pub photo_id: i64,
+    pub date_taken: i64,
+    pub date_source: Option<String>,
+    pub date_confident: Option<i64>,
+    pub filename_date_mismatch: Option<i64>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FileMove {
+    pub from: String,
+    pub to: String,
+}
+
+impl Operation {
+    /// The `kind` it is stored and listed under.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Operation::MoveToTrash { .. } => "move_to_trash",
+            Operation::RemoveFromAlbum { .. } => "remove_from_album",
+            Operation::SetFavorites { .. } => "set_favorites",
+            Operation::SetPhotoDate { .. } => "set_photo_date",
+            Operation::ResolveDuplicates { .. } => "resolve_duplicates",
+            Operation::DateShift { .. } => "date_shift",
+            Operation::BatchRename { .. } => "batch_rename",
+        }
+    }
+
+    /// Ids of the photos it touched, where it records them.
+    pub fn photo_ids(&self) -> Vec<i64> {
+        match self {
+            Operation::MoveToTrash { photo_ids } | Operation::DateShift { photo_ids, .. } => photo_ids.clone(),
+            Operation::RemoveFromAlbum { members, .. } => members.iter().map(|&(id, _)| id).collect(),
+            Operation::SetFavorites { previous, .. } => previous.iter().map(|&(id, _)| id).collect(),
+            Operation::SetPhotoDate { previous, .. } => vec![previous.photo_id],
+            Operation::BatchRename { renames } => renames.iter().map(|(id, _)| *id).collect(),
+            Operation::ResolveDuplicates { .. } => Vec::new(),
+        }
+    }
+
+    /// A line for the undo menu, e.g. "Moved 3 photos to the trash".
+    pub fn describe(&self) -> String {
+        let photos = |n: usize| if n == 1 { "1 photo".to_string() } else { format!("{} photos", n) };
+        match self {
+            Operation::MoveToTrash { photo_ids } => format!("Moved {} to the trash", photos(photo_ids.len())),
+            Operation::RemoveFromAlbum { members, .. } => format!("Removed {} from an album", photos(members.len())),
+            Operation::SetFavorites { previous, .. } => format!("Changed favorites on {}", photos(previous.len())),
+            Operation::SetPhotoDate { .. } => "Set a photo's date".to_string(),
+            Operation::ResolveDuplicates { resolution_ids } => match resolution_ids.len() {
+                1 => "Resolved a duplicate group".to_string(),
+                n => format!("Resolved {} duplicate groups", n),
+            },
+            Operation::DateShift { photo_ids, .. } => format!("Shifted the dates of {}", photos(photo_ids.len())),
+            Operation::BatchRename { renames } => format!("Renamed {}", photos(renames.len())),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stored_kind_matches_the_serialized_tag() {
+        let operations = [
+            Operation::MoveToTrash { photo_ids: vec![1, 2] },
+            Operation::RemoveFromAlbum { album_id: 3, members: vec![(1, 100)] },
+            Operation::SetFavorites { previous: vec![(1, false)], is_favorite: true },
+            Operation::SetPhotoDate {
+                previous: PreviousDate {
+                    photo_id: 1,
+                    date_taken: 100,
+                    date_source: Some("exif".to_string()),
+                    date_confident: Some(1),
+                    filename_date_mismatch: None,
+                },
+                new_date_taken: 200,
+                moved: Some(FileMove { from: "/lib/2020/01/a.jpg".to_string(), to: "/lib/2021/01/a.jpg".to_string() }),
+            },
+            Operation::ResolveDuplicates { resolution_ids: vec![4] },
+            Operation::DateShift { shift_id: 5, photo_ids: vec![1] },
+            Operation::BatchRename {
+                renames: vec![(1, FileMove { from: "/lib/DSC_0042.jpg".to_string(), to: "/lib/2019-06-01_001.jpg".to_string() })],
+            },
+        ];
+        for operation in operations {
+            let json = serde_json::to_value(&operation).unwrap();
+            assert_eq!(json["kind"], operation.kind());
+            assert_eq!(serde_json::from_value::<Operation>(json).unwrap(), operation);
+        }
+    }
+
+    #[test]
+    fn describes_operations_for_the_undo_menu() {
+        assert_eq!(Operation::MoveToTrash { photo_ids: vec![1] }.describe(), "Moved 1 photo to the trash");
+        assert_eq!(
+            Operation::SetFavorites { previous: vec![(1, true), (2, false)], is_favorite: false }.describe(),
+            "Changed favorites on 2 photos"
+        );
+        assert_eq!(Operation::ResolveDuplicates { resolution_ids: vec![1, 2] }.describe(), "Resolved 2 duplicate groups");
+    }
+}
@@ -0,0 +1,155 @@
+//! Placeholders the grid paints while a thumbnail loads: a BlurHash
+//! (https://blurha.sh) of the picture and its dominant color. Both come
+//! from the small upright bitmap the thumbnail was rendered from, or the
+//! cached thumbnail itself for photos thumbnailed before, so an original is
+//! never decoded just for this. Videos use their poster frame.
+
+use std::path::Path;
+
+use image::DynamicImage;
+use serde::Serialize;
+
+/// BlurHash components across and down; 4×3 suits most photo shapes
+const COMPONENTS_X: u32 = 4;
+const COMPONENTS_Y: u32 = 3;
+/// Longest side the image is reduced to first. A BlurHash keeps only the
+/// lowest frequencies, so more pixels change nothing visible.
+const SAMPLE_SIZE: u32 = 32;
+
+const BASE83: &[u8] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Placeholder {
+    pub blurhash: String,
+    /// "#rrggbb"
+    pub dominant_color: String,
+}
+
+pub fn from_image(img: &DynamicImage) -> Placeholder {
+    let small = if img.width().max(img.height()) > SAMPLE_SIZE {
+        img.thumbnail(SAMPLE_SIZE, SAMPLE_SIZE)
+    } else {
+        img.clone()
+    };
+    let rgb = small.to_rgb8();
+    Placeholder { blurhash: blurhash(&rgb), dominant_color: dominant_color(&rgb) }
+}
+
+/// From a cached thumbnail or poster frame
+pub fn from_file(path: &Path) -> Result<Placeholder, String> {
+    let img = image::open(path).map_err(|e| format!("Failed to open {}: {}", path.display(), e))?;
+    Ok(from_image(&img))
+}
+
+fn srgb_to_linear(value: u8) -> f64 {
+    let v = f64::from(value) / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f64) -> u32 {
+    let v = value.clamp(0.0, 1.0);
+    let srgb = if v <= 0.003_130_8 { v * 12.92 } else { 1.055 * v.powf(1.0 / 2.4) - 0.055 };
+    (srgb * 255.0 + 0.5) as u32
+}
+
+fn sign_pow(value: f64, exponent: f64) -> f64 {
+    value.abs().powf(exponent).copysign(value)
+}
+
+fn push_base83(out: &mut String, value: u32, digits: u32) {
+    for i in (0..digits).rev() {
+        out.push(BASE83[(value / 83u32.pow(i) % 83) as usize] as char);
+    }
+}
+
+/// The BlurHash of `rgb`, as the reference encoder computes it
+fn blurhash(rgb: &image::RgbImage) -> String {
+    let (width, height) = rgb.dimensions();
+    let linear: Vec<[f64; 3]> = rgb.pixels().map(|p| [0, 1, 2].map(|c| srgb_to_linear(p[c]))).collect();
+
+    let mut factors: Vec<[f64; 3]> = Vec::with_capacity((COMPONENTS_X * COMPONENTS_Y) as usize);
+    for j in 0..COMPONENTS_Y {
+        for i in 0..COMPONENTS_X {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut sum = [0.0; 3];
+            for y in 0..height {
+                let basis_y = (std::f64::consts::PI * f64::from(j) * f64::from(y) / f64::from(height)).cos();
+                for x in 0..width {
+                    let basis = basis_y * (std::f64::consts::PI * f64::from(i) * f64::from(x) / f64::from(width)).cos();
+                    for (s, v) in sum.iter_mut().zip(linear[(y * width + x) as usize]) {
+                        *s += basis * v;
+                    }
+                }
+            }
+            let scale = normalisation / f64::from(width * height).max(1.0);
+            factors.push(sum.map(|s| s * scale));
+        }
+    }
+
+    let mut hash = String::new();
+    push_base83(&mut hash, (COMPONENTS_X - 1) + (COMPONENTS_Y - 1) * 9, 1);
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let max_ac = ac.iter().flatten().fold(0.0f64, |max, v| max.max(v.abs()));
+    let quantised_max = (max_ac * 166.0 - 0.5).floor().clamp(0.0, 82.0) as u32;
+    let max_value = if ac.is_empty() { 1.0 } else { f64::from(quantised_max + 1) / 166.0 };
+    push_base83(&mut hash, if ac.is_empty() { 0 } else { quantised_max }, 1);
+    push_base83(&mut hash, (linear_to_srgb(dc[0]) << 16) + (linear_to_srgb(dc[1]) << 8) + linear_to_srgb(dc[2]), 4);
+    for component in ac {
+        let [r, g, b] = component.map(|v| (sign_pow(v / max_value, 0.5) * 9.0 + 9.5).floor().clamp(0.0, 18.0) as u32);
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    hash
+}
+
+/// Mean color of the most common of 4096 color bins (four bits a channel),
+/// so a small bright subject doesn't tint a mostly dark frame
+fn dominant_color(rgb: &image::RgbImage) -> String {
+    let mut bins = vec![(0u32, [0u32; 3]); 4096];
+    for p in rgb.pixels() {
+        let bin = (usize::from(p[0] >> 4) << 8) | (usize::from(p[1] >> 4) << 4) | usize::from(p[2] >> 4);
+        bins[bin].0 += 1;
+        for (sum, &v) in bins[bin].1.iter_mut().zip(&p.0) {
+            *sum += u32::from(v);
+        }
+    }
+    // The first bin wins a tie, so the result never depends on order
+    let (count, sums) = bins.iter().fold((0, [0; 3]), |best, bin| if bin.0 > best.0 { *bin } else { best });
+    if count == 0 {
+        return "#000000".to_string();
+    }
+    let [r, g, b] = sums.map(|s| (s + count / 2) / count);
+    format!("#{:02x}{:02x}{:02x}", r, g, b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_size_and_average_color() {
+        let red = DynamicImage::ImageRgb8(image::RgbImage::from_pixel(8, 8, image::Rgb([255, 0, 0])));
+        let placeholder = from_image(&red);
+        // 4×3 components is "L"; the average color 0xff0000 is "TI:j"
+        assert_eq!(&placeholder.blurhash[..1], "L");
+        assert_eq!(&placeholder.blurhash[2..6], "TI:j");
+        assert_eq!(placeholder.dominant_color, "#ff0000");
+        assert_eq!(from_image(&red), placeholder);
+    }
+
+    #[test]
+    fn dominant_color_follows_the_larger_area() {
+        let mut img = image::RgbImage::from_pixel(10, 10, image::Rgb([20, 40, 200]));
+        for x in 0..10 {
+            for y in 0..3 {
+                img.put_pixel(x, y, image::Rgb([250, 250, 250]));
+            }
+        }
+        let placeholder = from_image(&DynamicImage::ImageRgb8(img));
+        assert_eq!(placeholder.dominant_color, "#1428c8");
+        assert_eq!(placeholder.blurhash.len(), 6 + 2 * 11);
+    }
+}
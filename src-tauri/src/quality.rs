@@ -0,0 +1,132 @@
+//! Image quality scores for culling: which frame of a burst or set of
+//! near-duplicates is sharpest, and which shots across the library are
+//! blurry.
+//!
+//! The photo is scaled to fit ANALYSIS_SIZE and made grayscale. Sharpness
+//! is the variance of its Laplacian (4-neighbour kernel): edges give large
+//! responses, blur smooths them away. Clipping is the share of pixels at
+//! pure black or white. The score combines them:
+//!
+//!   quality_score = log10(1 + sharpness) × (1 − clipped_fraction)
+//!
+//! so it runs from 0 (flat or fully clipped) to about 4. Blurry shots
+//! typically land below 2, crisp ones above 2.5. Busy subjects (foliage,
+//! text) have more edges than smooth ones (skies, skin) however sharp they
+//! are, so scores are only comparable between photos of similar content,
+//! such as a burst or a duplicate group. Sums are kept in integers, so a
+//! given image always gets the same score.
+
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::thumbnails;
+
+/// Longest side the photo is analysed at, so scores don't depend on resolution
+const ANALYSIS_SIZE: u32 = 512;
+/// Gray levels counted as clipped at each end
+const CLIP_LOW: u8 = 2;
+const CLIP_HIGH: u8 = 253;
+
+/// Default for get_low_quality_photos: below this a photo is likely blurry
+pub const DEFAULT_LOW_QUALITY_THRESHOLD: f64 = 2.0;
+
+/// How sharp and well exposed a photo is; see the module docs for the scale
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+pub struct QualityScore {
+    /// Variance of the Laplacian
+    pub sharpness: f64,
+    /// Share of pixels at pure black or white, 0 to 1
+    pub clipped_fraction: f64,
+    pub score: f64,
+}
+
+/// Score one photo, from its cached thumbnail when it has one
+pub fn analyze(path: &Path, content_hash: Option<&str>) -> Result<QualityScore, String> {
+    let source = match content_hash {
+        Some(hash) => thumbnails::generate_thumbnail(path, hash, thumbnails::bucket_size(ANALYSIS_SIZE))?,
+        None => path.to_path_buf(),
+    };
+    let img = image::open(&source).map_err(|e| format!("Failed to open {}: {}", source.display(), e))?;
+    let img = if img.width().max(img.height()) > ANALYSIS_SIZE {
+        img.resize(ANALYSIS_SIZE, ANALYSIS_SIZE, image::imageops::FilterType::Triangle)
+    } else {
+        img
+    };
+    Ok(score(&img.to_luma8()))
+}
+
+pub fn score(gray: &image::GrayImage) -> QualityScore {
+    let sharpness = laplacian_variance(gray);
+    let clipped_fraction = clipped_fraction(gray);
+    QualityScore { sharpness, clipped_fraction, score: (1.0 + sharpness).log10() * (1.0 - clipped_fraction) }
+}
+
+/// Variance of the 4-neighbour Laplacian over the interior pixels. Images
+/// too small to have an interior count as flat.
+fn laplacian_variance(gray: &image::GrayImage) -> f64 {
+    let (width, height) = gray.dimensions();
+    if width < 3 || height < 3 {
+        return 0.0;
+    }
+    let at = |x: u32, y: u32| i32::from(gray.get_pixel(x, y)[0]);
+    let (mut sum, mut sum_sq, mut n) = (0i64, 0i64, 0i64);
+    for y in 1..height - 1 {
+        for x in 1..width - 1 {
+            let response = at(x - 1, y) + at(x + 1, y) + at(x, y - 1) + at(x, y + 1) - 4 * at(x, y);
+            sum += i64::from(response);
+            sum_sq += i64::from(response) * i64::from(response);
+            n += 1;
+        }
+    }
+    // Integer sums keep the result exact whatever the order
+    let mean = sum as f64 / n as f64;
+    sum_sq as f64 / n as f64 - mean * mean
+}
+
+fn clipped_fraction(gray: &image::GrayImage) -> f64 {
+    let total = gray.pixels().len();
+    if total == 0 {
+        return 0.0;
+    }
+    let clipped = gray.pixels().filter(|p| p[0] <= CLIP_LOW || p[0] >= CLIP_HIGH).count();
+    clipped as f64 / total as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn checkerboard(side: u32, cell: u32) -> image::GrayImage {
+        image::GrayImage::from_fn(side, side, |x, y| image::Luma([if (x / cell + y / cell) % 2 == 0 { 60 } else { 190 }]))
+    }
+
+    #[test]
+    fn flat_images_have_no_sharpness() {
+        let flat = image::GrayImage::from_pixel(32, 32, image::Luma([128]));
+        assert_eq!(score(&flat), QualityScore { sharpness: 0.0, clipped_fraction: 0.0, score: 0.0 });
+    }
+
+    #[test]
+    fn blurring_lowers_the_score() {
+        let sharp = checkerboard(64, 4);
+        let blurred = image::imageops::blur(&sharp, 2.0);
+        let (a, b) = (score(&sharp), score(&blurred));
+        assert!(a.score > b.score, "{:?} should beat {:?}", a, b);
+        // The same image always scores the same
+        assert_eq!(score(&sharp), a);
+    }
+
+    #[test]
+    fn clipping_is_penalized() {
+        let mut clipped = checkerboard(64, 4);
+        for y in 0..32 {
+            for x in 0..64 {
+                clipped.put_pixel(x, y, image::Luma([255]));
+            }
+        }
+        let result = score(&clipped);
+        assert!((result.clipped_fraction - 0.5).abs() < 1e-9);
+        assert!((result.score - (1.0 + result.sharpness).log10() * 0.5).abs() < 1e-9);
+    }
+}
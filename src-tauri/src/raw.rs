@@ -0,0 +1,381 @@
+//! Camera RAW files. CR2, NEF, ARW and DNG are all TIFF containers: EXIF
+//! parses as usual, and each carries one or more embedded JPEG previews
+//! that stand in for the sensor data, which Terra doesn't demosaic.
+//!
+//! Previews are found by walking IFD0, its `next` chain and SubIFDs, and
+//! taking the largest baseline JPEG. The raw data itself is often stored as
+//! lossless JPEG, which is skipped by its frame marker.
+
+use std::collections::HashSet;
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+pub(crate) const RAW_EXTENSIONS: [&str; 4] = ["cr2", "nef", "arw", "dng"];
+
+const TAG_COMPRESSION: u16 = 0x0103;
+const TAG_STRIP_OFFSETS: u16 = 0x0111;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_STRIP_BYTE_COUNTS: u16 = 0x0117;
+const TAG_SUB_IFDS: u16 = 0x014A;
+const TAG_JPEG_OFFSET: u16 = 0x0201;
+const TAG_JPEG_LENGTH: u16 = 0x0202;
+/// Walking more IFDs than this means a corrupt or hostile file.
+const MAX_IFDS: usize = 32;
+/// Enough of a preview to reach its frame header.
+const JPEG_HEADER_BYTES: usize = 64 * 1024;
+const MAX_PREVIEW_BYTES: u64 = 64 * 1024 * 1024;
+
+pub(crate) fn is_raw(path: &Path) -> bool {
+    path.extension()
+        .and_then(|s| s.to_str())
+        .is_some_and(|ext| RAW_EXTENSIONS.contains(&ext.to_lowercase().as_str()))
+}
+
+/// The largest embedded preview.
+pub(crate) struct Preview {
+    pub jpeg: Vec<u8>,
+    /// EXIF orientation (1-8) of the RAW, which previews share
+    pub orientation: u8,
+}
+
+/// Size of the largest embedded preview, without reading its pixels.
+pub(crate) fn dimensions(path: &Path) -> Option<(u32, u32)> {
+    let mut tiff = TiffFile::open(path)?;
+    let best = find_previews(&mut tiff).into_iter().max_by_key(|c| c.area())?;
+    Some((best.width, best.height))
+}
+
+/// Read the largest embedded preview JPEG.
+pub(crate) fn read_preview(path: &Path) -> Option<Preview> {
+    let mut tiff = TiffFile::open(path)?;
+    let best = find_previews(&mut tiff).into_iter().max_by_key(|c| c.area())?;
+    let jpeg = tiff.read_at(best.offset, usize::try_from(best.length).ok()?)?;
+    let ifd0 = tiff.first_ifd().and_then(|offset| tiff.ifd(offset));
+    let orientation = ifd0
+        .and_then(|ifd| tiff.single_value(&ifd, TAG_ORIENTATION))
+        .and_then(|o| u8::try_from(o).ok())
+        .filter(|o| (1..=8).contains(o))
+        .unwrap_or(1);
+    Some(Preview { jpeg, orientation })
+}
+
+struct Candidate {
+    offset: u64,
+    length: u64,
+    width: u32,
+    height: u32,
+}
+
+impl Candidate {
+    fn area(&self) -> u64 {
+        u64::from(self.width) * u64::from(self.height)
+    }
+}
+
+struct Entry {
+    tag: u16,
+    kind: u16,
+    count: u32,
+    /// The value itself when it fits in four bytes, else its offset
+    value: [u8; 4],
+}
+
+struct Ifd {
+    entries: Vec<Entry>,
+    next: u32,
+}
+
+/// TIFF structures read on demand, so a 50 MB RAW isn't loaded for a preview.
+struct TiffFile {
+    file: File,
+    len: u64,
+    little_endian: bool,
+}
+
+impl TiffFile {
+    fn open(path: &Path) -> Option<Self> {
+        let mut file = File::open(path).ok()?;
+        let len = file.metadata().ok()?.len();
+        let mut header = [0u8; 4];
+        file.read_exact(&mut header).ok()?;
+        let little_endian = match &header {
+            b"II*\0" => true,
+            b"MM\0*" => false,
+            _ => return None,
+        };
+        Some(TiffFile { file, len, little_endian })
+    }
+
+    fn read_at(&mut self, offset: u64, len: usize) -> Option<Vec<u8>> {
+        if offset.checked_add(len as u64)? > self.len {
+            return None;
+        }
+        self.file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut buf = vec![0u8; len];
+        self.file.read_exact(&mut buf).ok()?;
+        Some(buf)
+    }
+
+    fn u16(&self, b: &[u8]) -> u16 {
+        let b = [b[0], b[1]];
+        if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) }
+    }
+
+    fn u32(&self, b: &[u8]) -> u32 {
+        let b = [b[0], b[1], b[2], b[3]];
+        if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) }
+    }
+
+    fn first_ifd(&mut self) -> Option<u32> {
+        let b = self.read_at(4, 4)?;
+        Some(self.u32(&b))
+    }
+
+    fn ifd(&mut self, offset: u32) -> Option<Ifd> {
+        let count = {
+            let b = self.read_at(u64::from(offset), 2)?;
+            self.u16(&b)
+        };
+        let body = self.read_at(u64::from(offset) + 2, usize::from(count) * 12 + 4)?;
+        let entries = body
+            .chunks_exact(12)
+            .take(usize::from(count))
+            .map(|e| Entry {
+                tag: self.u16(&e[0..2]),
+                kind: self.u16(&e[2..4]),
+                count: self.u32(&e[4..8]),
+                value: [e[8], e[9], e[10], e[11]],
+            })
+            .collect();
+        let next = self.u32(&body[usize::from(count) * 12..]);
+        Some(Ifd { entries, next })
+    }
+
+    /// SHORT or LONG values of a tag.
+    fn tag_values(&mut self, ifd: &Ifd, tag: u16) -> Option<Vec<u32>> {
+        let entry = ifd.entries.iter().find(|e| e.tag == tag)?;
+        let size = match entry.kind {
+            3 => 2,
+            4 | 13 => 4,
+            _ => return None,
+        };
+        let count = usize::try_from(entry.count).ok().filter(|&c| c <= 1024)?;
+        let bytes = if count * size <= 4 {
+            entry.value[..count * size].to_vec()
+        } else {
+            let offset = self.u32(&entry.value);
+            self.read_at(u64::from(offset), count * size)?
+        };
+        Some(
+            bytes
+                .chunks_exact(size)
+                .map(|b| if size == 2 { u32::from(self.u16(b)) } else { self.u32(b) })
+                .collect(),
+        )
+    }
+
+    fn single_value(&mut self, ifd: &Ifd, tag: u16) -> Option<u32> {
+        match self.tag_values(ifd, tag)?.as_slice() {
+            [value] => Some(*value),
+            _ => None,
+        }
+    }
+}
+
+/// Every baseline JPEG stored in the file's IFDs.
+fn find_previews(tiff: &mut TiffFile) -> Vec<Candidate> {
+    let mut found = Vec::new();
+    let mut queue: Vec<u32> = tiff.first_ifd().into_iter().collect();
+    let mut seen = HashSet::new();
+    while let Some(offset) = queue.pop() {
+        if offset == 0 || seen.len() >= MAX_IFDS || !seen.insert(offset) {
+            continue;
+        }
+        let Some(ifd) = tiff.ifd(offset) else {
+            continue;
+        };
+        queue.push(ifd.next);
+        queue.extend(tiff.tag_values(&ifd, TAG_SUB_IFDS).unwrap_or_default());
+
+        // Old- and new-style JPEG strips; raw data stored as lossless JPEG
+        // passes this and is rejected by its frame marker below
+        let strip = if matches!(tiff.single_value(&ifd, TAG_COMPRESSION), Some(6 | 7)) {
+            (tiff.single_value(&ifd, TAG_STRIP_OFFSETS), tiff.single_value(&ifd, TAG_STRIP_BYTE_COUNTS))
+        } else {
+            (None, None)
+        };
+        let ranges = [
+            (tiff.single_value(&ifd, TAG_JPEG_OFFSET), tiff.single_value(&ifd, TAG_JPEG_LENGTH)),
+            strip,
+        ];
+        for (offset, length) in ranges {
+            let (Some(offset), Some(length)) = (offset, length) else {
+                continue;
+            };
+            let (offset, length) = (u64::from(offset), u64::from(length));
+            if length == 0 || length > MAX_PREVIEW_BYTES {
+                continue;
+            }
+            let head_len = usize::try_from(length).unwrap_or(usize::MAX).min(JPEG_HEADER_BYTES);
+            let Some(head) = tiff.read_at(offset, head_len) else {
+                continue;
+            };
+            if let Some((width, height)) = baseline_jpeg_size(&head) {
+                found.push(Candidate { offset, length, width, height });
+            }
+        }
+    }
+    found
+}
+
+/// Width and height from a JPEG's frame header, if it is one a normal
+/// decoder handles (baseline, extended or progressive Huffman).
+fn baseline_jpeg_size(jpeg: &[u8]) -> Option<(u32, u32)> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        if *jpeg.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *jpeg.get(pos + 1)?;
+        if marker == 0xFF {
+            // Fill byte
+            pos += 1;
+            continue;
+        }
+        let len = usize::from(u16::from_be_bytes([*jpeg.get(pos + 2)?, *jpeg.get(pos + 3)?]));
+        match marker {
+            0xC0..=0xC2 => {
+                let frame = jpeg.get(pos + 4..pos + 9)?;
+                let height = u32::from(u16::from_be_bytes([frame[1], frame[2]]));
+                let width = u32::from(u16::from_be_bytes([frame[3], frame[4]]));
+                return (width > 0 && height > 0).then_some((width, height));
+            }
+            // Lossless, hierarchical and arithmetic-coded frames
+            0xC3 | 0xC5..=0xC7 | 0xC9..=0xCB | 0xCD..=0xCF => return None,
+            0xDA | 0xD9 => return None,
+            _ => pos += 2 + len,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::codecs::jpeg::JpegEncoder;
+
+    fn jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = image::RgbImage::from_pixel(width, height, image::Rgb([200, 100, 50]));
+        let mut out = Vec::new();
+        JpegEncoder::new(&mut out).encode_image(&img).unwrap();
+        out
+    }
+
+    /// Lossless-JPEG-looking raw data: SOI, then an SOF3 frame
+    fn lossless(width: u16, height: u16) -> Vec<u8> {
+        let mut out = vec![0xFF, 0xD8, 0xFF, 0xC3, 0, 11, 14];
+        out.extend_from_slice(&height.to_be_bytes());
+        out.extend_from_slice(&width.to_be_bytes());
+        out.extend_from_slice(&[1, 1, 0x11, 0]);
+        out
+    }
+
+    fn entry(tag: u16, kind: u16, count: u32, value: u32) -> Vec<u8> {
+        [&tag.to_le_bytes()[..], &kind.to_le_bytes(), &count.to_le_bytes(), &value.to_le_bytes()].concat()
+    }
+
+    fn ifd(entries: &[Vec<u8>], next: u32) -> Vec<u8> {
+        let mut out = (entries.len() as u16).to_le_bytes().to_vec();
+        for e in entries {
+            out.extend_from_slice(e);
+        }
+        out.extend_from_slice(&next.to_le_bytes());
+        out
+    }
+
+    /// A little-endian TIFF laid out like a CR2: IFD0 with a small preview
+    /// and orientation, a SubIFD with a bigger preview as a JPEG strip, and
+    /// a bigger-still SubIFD holding lossless raw data.
+    fn raw_fixture() -> Vec<u8> {
+        let small = jpeg(16, 12);
+        let big = jpeg(64, 48);
+        let sensor = lossless(6000, 4000);
+
+        // IFD0 (4 entries) at 8, SubIFDs A and B (3 entries each) after it, then data
+        let (ifd0_at, sub_a_at, sub_b_at, data_at) = (8u32, 62u32, 104u32, 146u32);
+        let small_at = data_at;
+        let big_at = small_at + small.len() as u32;
+        let sensor_at = big_at + big.len() as u32;
+        let sub_ifds_at = sensor_at + sensor.len() as u32;
+
+        let ifd0 = ifd(
+            &[
+                entry(TAG_ORIENTATION, 3, 1, 6),
+                entry(TAG_SUB_IFDS, 4, 2, sub_ifds_at),
+                entry(TAG_JPEG_OFFSET, 4, 1, small_at),
+                entry(TAG_JPEG_LENGTH, 4, 1, small.len() as u32),
+            ],
+            0,
+        );
+        let sub_a = ifd(
+            &[
+                entry(TAG_COMPRESSION, 3, 1, 6),
+                entry(TAG_STRIP_OFFSETS, 4, 1, big_at),
+                entry(TAG_STRIP_BYTE_COUNTS, 4, 1, big.len() as u32),
+            ],
+            0,
+        );
+        let sub_b = ifd(
+            &[
+                entry(TAG_COMPRESSION, 3, 1, 6),
+                entry(TAG_STRIP_OFFSETS, 4, 1, sensor_at),
+                entry(TAG_STRIP_BYTE_COUNTS, 4, 1, sensor.len() as u32),
+            ],
+            0,
+        );
+
+        let mut out = b"II*\0".to_vec();
+        out.extend_from_slice(&ifd0_at.to_le_bytes());
+        out.extend(ifd0);
+        assert_eq!(out.len() as u32, sub_a_at);
+        out.extend(sub_a);
+        assert_eq!(out.len() as u32, sub_b_at);
+        out.extend(sub_b);
+        assert_eq!(out.len() as u32, data_at);
+        out.extend(small);
+        out.extend(big);
+        out.extend(sensor);
+        out.extend_from_slice(&sub_a_at.to_le_bytes());
+        out.extend_from_slice(&sub_b_at.to_le_bytes());
+        out
+    }
+
+    #[test]
+    fn picks_the_largest_baseline_preview() {
+        let path = std::env::temp_dir().join(format!("terra-raw-{}.cr2", std::process::id()));
+        std::fs::write(&path, raw_fixture()).unwrap();
+
+        assert_eq!(dimensions(&path), Some((64, 48)));
+        let preview = read_preview(&path).unwrap();
+        assert_eq!(preview.orientation, 6);
+        let decoded = image::load_from_memory(&preview.jpeg).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (64, 48));
+        let _ = std::fs::remove_file(&path);
+    }
+
+    #[test]
+    fn lossless_frames_are_not_previews() {
+        assert_eq!(baseline_jpeg_size(&lossless(6000, 4000)), None);
+        assert_eq!(baseline_jpeg_size(&jpeg(8, 6)), Some((8, 6)));
+    }
+
+    #[test]
+    fn recognises_raw_extensions() {
+        assert!(is_raw(Path::new("/DCIM/IMG_0001.CR2")));
+        assert!(is_raw(Path::new("/DCIM/DSC_0001.nef")));
+        assert!(!is_raw(Path::new("/DCIM/IMG_0001.JPG")));
+    }
+}
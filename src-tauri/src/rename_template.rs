@@ -0,0 +1,344 @@
+//! Batch rename templates such as "{date:YYYY-MM-DD}_{seq:3}", rendered
+//! into a file name per photo. Tokens:
+//!
+//! - `{date}` / `{date:FORMAT}`: capture date, `YYYY-MM-DD` by default
+//! - `{time}` / `{time:FORMAT}`: capture time, `HHmmss` by default
+//! - `{seq}` / `{seq:WIDTH}`: position in the batch from 1, zero-padded
+//! - `{camera}`: camera model, else make
+//! - `{original}`: the current file name without its extension
+//!
+//! Formats are built from YYYY, YY, MM, DD, HH, mm and ss; dates are read on
+//! the photo's own wall clock. No database access: `plan` orders a batch and
+//! resolves its collisions against an existence check the caller supplies.
+
+use std::collections::HashSet;
+use std::path::Path;
+
+use serde::Serialize;
+
+use crate::library;
+use crate::media;
+use crate::PhotoMetadata;
+
+/// Longest file name most filesystems take, in bytes
+const MAX_NAME_BYTES: usize = 255;
+/// Kept free when truncating, for a `_1`-style collision suffix
+const SUFFIX_ROOM: usize = 6;
+/// What `{date}` and `{time}` become for a photo with no known date
+const UNDATED: &str = "undated";
+/// What `{camera}` becomes for a photo without camera metadata
+const UNKNOWN_CAMERA: &str = "Unknown camera";
+
+#[derive(Debug, PartialEq)]
+enum Part {
+    Text(String),
+    /// A chrono format string
+    Date(String),
+    Time(String),
+    Seq(usize),
+    Camera,
+    Original,
+}
+
+#[derive(Debug, PartialEq)]
+pub(crate) struct Template {
+    parts: Vec<Part>,
+}
+
+/// A name a template rendered for one photo.
+pub(crate) struct RenderedName {
+    pub name: String,
+    /// The name was cut short to fit MAX_NAME_BYTES
+    pub truncated: bool,
+}
+
+/// A YYYY/MM/DD/HH/mm/ss format as a chrono format string.
+fn chrono_format(format: &str) -> String {
+    const FIELDS: [(&str, &str); 7] =
+        [("YYYY", "%Y"), ("YY", "%y"), ("MM", "%m"), ("DD", "%d"), ("HH", "%H"), ("mm", "%M"), ("ss", "%S")];
+    let mut out = String::new();
+    let mut rest = format;
+    while let Some(c) = rest.chars().next() {
+        if let Some((field, spec)) = FIELDS.iter().find(|(field, _)| rest.starts_with(field)) {
+            out.push_str(spec);
+            rest = &rest[field.len()..];
+        } else {
+            if c == '%' {
+                out.push('%');
+            }
+            out.push(c);
+            rest = &rest[c.len_utf8()..];
+        }
+    }
+    out
+}
+
+impl Template {
+    /// Parse `template`, naming the first token it doesn't understand.
+    pub(crate) fn parse(template: &str) -> Result<Template, String> {
+        let mut parts = Vec::new();
+        let mut text = String::new();
+        let mut rest = template;
+        while let Some(start) = rest.find(['{', '}']) {
+            text.push_str(&rest[..start]);
+            if rest[start..].starts_with('}') {
+                return Err("Unmatched } in the template".to_string());
+            }
+            let end = rest[start..].find('}').ok_or("Unclosed { in the template")? + start;
+            let token = &rest[start + 1..end];
+            let (name, arg) = match token.split_once(':') {
+                Some((name, arg)) => (name, Some(arg)),
+                None => (token, None),
+            };
+            let part = match (name, arg) {
+                ("date", arg) => Part::Date(chrono_format(arg.unwrap_or("YYYY-MM-DD"))),
+                ("time", arg) => Part::Time(chrono_format(arg.unwrap_or("HHmmss"))),
+                ("seq", None) => Part::Seq(1),
+                ("seq", Some(width)) => Part::Seq(
+                    width
+                        .parse()
+                        .ok()
+                        .filter(|w| (1..=9).contains(w))
+                        .ok_or_else(|| format!("{{seq:{}}} needs a width from 1 to 9", width))?,
+                ),
+                ("camera", None) => Part::Camera,
+                ("original", None) => Part::Original,
+                _ => return Err(format!("Unknown template token {{{}}}", token)),
+            };
+            if !text.is_empty() {
+                parts.push(Part::Text(std::mem::take(&mut text)));
+            }
+            parts.push(part);
+            rest = &rest[end + 1..];
+        }
+        text.push_str(rest);
+        if !text.is_empty() {
+            parts.push(Part::Text(text));
+        }
+        if parts.is_empty() {
+            return Err("Enter a template".to_string());
+        }
+        Ok(Template { parts })
+    }
+
+    /// The file name for `photo` as the `seq`th of its batch. Its extension
+    /// is kept, and an over-long name is cut short before it. Errors if the
+    /// result isn't a valid file name.
+    pub(crate) fn render(&self, photo: &PhotoMetadata, seq: usize) -> Result<RenderedName, String> {
+        let source = Path::new(&photo.path);
+        let undated = photo.date_source.as_deref() == Some(media::DATE_SOURCE_UNKNOWN);
+        let wall_clock = (!undated)
+            .then(|| media::exif_wall_clock(photo.date_taken, photo.tz_offset_minutes))
+            .flatten()
+            .map(|(dt, _)| dt);
+
+        let mut stem = String::new();
+        for part in &self.parts {
+            match part {
+                Part::Text(text) => stem.push_str(text),
+                Part::Date(format) | Part::Time(format) => match wall_clock {
+                    Some(dt) => stem.push_str(&dt.format(format).to_string()),
+                    None => stem.push_str(UNDATED),
+                },
+                Part::Seq(width) => stem.push_str(&format!("{:0width$}", seq, width = width)),
+                Part::Camera => {
+                    let camera = photo.camera_model.as_deref().or(photo.camera_make.as_deref()).map(str::trim);
+                    match camera.filter(|c| !c.is_empty()) {
+                        Some(camera) => stem.push_str(&library::folder_name(camera)),
+                        None => stem.push_str(UNKNOWN_CAMERA),
+                    }
+                }
+                Part::Original => stem.push_str(&source.file_stem().unwrap_or_default().to_string_lossy()),
+            }
+        }
+
+        let ext = source.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+        let limit = (MAX_NAME_BYTES - SUFFIX_ROOM).saturating_sub(ext.len());
+        let truncated = stem.len() > limit;
+        if truncated {
+            let cut = (0..=limit).rev().find(|&i| stem.is_char_boundary(i)).unwrap_or(0);
+            stem.truncate(cut);
+        }
+        let name = library::renamed_file_name(source, &stem, false)?;
+        Ok(RenderedName { name, truncated })
+    }
+}
+
+/// One photo's part in a batch rename, as previewed and as applied.
+#[derive(Serialize, Clone, Debug)]
+pub struct BatchRenamePlan {
+    pub id: i64,
+    pub path: String,
+    /// Same as `path` if the template gives the name it already has
+    pub new_path: String,
+    pub new_name: String,
+    /// The rendered name was taken, on disk or earlier in the batch, so
+    /// it got a `_1`-style suffix
+    pub collided: bool,
+    /// The rendered name was too long and was cut short before the extension
+    pub truncated: bool,
+}
+
+/// Render `template` for each of `photos` in capture order, undated photos
+/// last and ties broken by name, so `{seq}` and collision suffixes come out
+/// the same every time. A name is taken if `exists` says so or an earlier
+/// photo got it; the names the batch's own photos have now are free, since
+/// they are all moved aside before any takes its new one. `case_insensitive`
+/// is whether the filesystem ignores case.
+pub(crate) fn plan(
+    template: &Template,
+    mut photos: Vec<PhotoMetadata>,
+    case_insensitive: bool,
+    exists: impl Fn(&Path) -> bool,
+) -> Result<Vec<BatchRenamePlan>, String> {
+    photos.sort_by_cached_key(|p| {
+        let undated = p.date_source.as_deref() == Some(media::DATE_SOURCE_UNKNOWN);
+        (undated, p.date_taken, p.name.to_lowercase(), p.id)
+    });
+    photos.dedup_by_key(|p| p.id);
+    let key = |path: &Path| {
+        let path = path.to_string_lossy();
+        if case_insensitive {
+            path.to_lowercase()
+        } else {
+            path.into_owned()
+        }
+    };
+    let vacated: HashSet<String> = photos.iter().map(|p| key(Path::new(&p.path))).collect();
+    let mut claimed: HashSet<String> = HashSet::new();
+    photos
+        .iter()
+        .enumerate()
+        .map(|(i, photo)| {
+            let rendered = template.render(photo, i + 1).map_err(|e| format!("{}: {}", photo.name, e))?;
+            let dest = library::unique_destination_avoiding(Path::new(&photo.path).with_file_name(&rendered.name), |p| {
+                let k = key(p);
+                claimed.contains(&k) || (!vacated.contains(&k) && exists(p))
+            });
+            claimed.insert(key(&dest));
+            let new_name = dest.file_name().unwrap_or_default().to_string_lossy().to_string();
+            Ok(BatchRenamePlan {
+                // Rows read from the table always have one
+                id: photo.id.unwrap_or_default(),
+                path: photo.path.clone(),
+                new_path: dest.to_string_lossy().to_string(),
+                collided: new_name != rendered.name,
+                new_name,
+                truncated: rendered.truncated,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(path: &str) -> PhotoMetadata {
+        PhotoMetadata {
+            path: path.to_string(),
+            name: Path::new(path).file_name().unwrap().to_string_lossy().to_string(),
+            // 2019-06-01 14:30:05 UTC
+            date_taken: 1_559_399_405,
+            tz_offset_minutes: Some(0),
+            date_source: Some("exif".to_string()),
+            camera_model: Some("EOS R5".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn renders_every_token() {
+        let template = Template::parse("{date:YYYY-MM-DD}_{time}_{seq:3}_{camera}_{original}").unwrap();
+        let rendered = template.render(&photo("/p/DSC_0042.JPG"), 7).unwrap();
+        assert_eq!(rendered.name, "2019-06-01_143005_007_EOS R5_DSC_0042.JPG");
+        assert!(!rendered.truncated);
+        assert_eq!(Template::parse("{date}").unwrap().render(&photo("/p/a.jpg"), 1).unwrap().name, "2019-06-01.jpg");
+    }
+
+    #[test]
+    fn fills_in_missing_metadata() {
+        let mut undated = photo("/p/a.jpg");
+        undated.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        undated.camera_model = None;
+        let name = Template::parse("{date} {camera}").unwrap().render(&undated, 1).unwrap().name;
+        assert_eq!(name, "undated Unknown camera.jpg");
+    }
+
+    #[test]
+    fn truncates_long_names_before_the_extension() {
+        let long = format!("/p/{}.jpg", "é".repeat(200));
+        let rendered = Template::parse("{original}").unwrap().render(&photo(&long), 1).unwrap();
+        assert!(rendered.truncated);
+        assert!(rendered.name.len() <= MAX_NAME_BYTES - SUFFIX_ROOM);
+        assert!(rendered.name.ends_with("é.jpg"));
+    }
+
+    #[test]
+    fn rejects_bad_templates() {
+        assert!(Template::parse("").is_err());
+        assert!(Template::parse("{nope}").is_err());
+        assert!(Template::parse("{date").is_err());
+        assert!(Template::parse("date}").is_err());
+        assert!(Template::parse("{seq:0}").is_err());
+        assert!(Template::parse("{camera:x}").is_err());
+        assert!(Template::parse("a/{seq}").unwrap().render(&photo("/p/a.jpg"), 1).is_err());
+    }
+
+    fn numbered(path: &str, id: i64, date_taken: i64) -> PhotoMetadata {
+        PhotoMetadata { id: Some(id), date_taken, ..photo(path) }
+    }
+
+    #[test]
+    fn plans_in_capture_order_with_undated_photos_last() {
+        let template = Template::parse("{camera}").unwrap();
+        let mut undated = numbered("/p/a0.jpg", 3, 0);
+        undated.date_source = Some(media::DATE_SOURCE_UNKNOWN.to_string());
+        // b and c share a timestamp; the name breaks the tie, and a photo
+        // listed twice is planned once
+        let photos = vec![undated, numbered("/p/c.jpg", 2, 5), numbered("/p/b.jpg", 1, 5), numbered("/p/b.jpg", 1, 5)];
+        let planned = plan(&template, photos, false, |p| p == Path::new("/p/EOS R5.jpg")).unwrap();
+        let names: Vec<(&str, &str, bool)> =
+            planned.iter().map(|p| (p.path.as_str(), p.new_name.as_str(), p.collided)).collect();
+        assert_eq!(
+            names,
+            vec![("/p/b.jpg", "EOS R5_1.jpg", true), ("/p/c.jpg", "EOS R5_2.jpg", true), ("/p/a0.jpg", "EOS R5_3.jpg", true)]
+        );
+    }
+
+    #[test]
+    fn plans_reuse_names_the_batch_vacates() {
+        let template = Template::parse("{date}_{seq:3}").unwrap();
+        let day = 1_559_399_405;
+        // Renamed once before; a photo taken earlier joins, and _003 belongs
+        // to a file outside the batch
+        let photos = vec![
+            numbered("/p/2019-06-01_001.jpg", 1, day + 10),
+            numbered("/p/2019-06-01_002.jpg", 2, day + 20),
+            numbered("/p/new.jpg", 3, day),
+        ];
+        let on_disk = ["/p/2019-06-01_001.jpg", "/p/2019-06-01_002.jpg", "/p/new.jpg", "/p/2019-06-01_003.jpg"];
+        let planned = plan(&template, photos, false, |p| on_disk.iter().any(|d| Path::new(d) == p)).unwrap();
+        let moves: Vec<(&str, &str)> = planned.iter().map(|p| (p.path.as_str(), p.new_name.as_str())).collect();
+        assert_eq!(
+            moves,
+            vec![
+                ("/p/new.jpg", "2019-06-01_001.jpg"),
+                ("/p/2019-06-01_001.jpg", "2019-06-01_002.jpg"),
+                ("/p/2019-06-01_002.jpg", "2019-06-01_003_1.jpg"),
+            ]
+        );
+    }
+
+    #[test]
+    fn plans_case_only_renames_where_case_is_ignored() {
+        let template = Template::parse("DSC_{seq:4}").unwrap();
+        let photos = || vec![numbered("/p/dsc_0001.jpg", 1, 5)];
+        // As a filesystem that ignores case sees it
+        let exists = |p: &Path| p.to_string_lossy().eq_ignore_ascii_case("/p/dsc_0001.jpg");
+        let planned = plan(&template, photos(), true, exists).unwrap();
+        assert_eq!((planned[0].new_name.as_str(), planned[0].collided), ("DSC_0001.jpg", false));
+        let planned = plan(&template, photos(), false, exists).unwrap();
+        assert_eq!((planned[0].new_name.as_str(), planned[0].collided), ("DSC_0001_1.jpg", true));
+    }
+}
@@ -0,0 +1,105 @@
+//! Reading another app's SQLite database (digiKam's, Photos') without
+//! touching it: the database and its WAL files are copied into a temporary
+//! folder of their own and the copy is opened read-only. The app may have
+//! the original open, and importers must never write to it.
+//!
+//! No access to Terra's database.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use rusqlite::{Connection, OpenFlags};
+
+/// Numbers snapshots taken by this process, so concurrent imports never
+/// share (and delete) each other's folder
+static NEXT_SNAPSHOT: AtomicU64 = AtomicU64::new(0);
+
+/// A read-only copy of a database, removed when dropped.
+pub(crate) struct Snapshot {
+    conn: Option<Connection>,
+    dir: PathBuf,
+}
+
+impl Snapshot {
+    /// Copy `database` (and its `-wal`/`-shm` files, when there are any)
+    /// into a new temporary folder and open the copy read-only.
+    pub(crate) fn take(database: &Path) -> io::Result<Snapshot> {
+        let dir = new_dir()?;
+        // From here on, dropping the snapshot removes the folder
+        let mut snapshot = Snapshot { conn: None, dir };
+        let name = database.file_name().ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "no file name"))?;
+        let copy = snapshot.dir.join(name);
+        fs::copy(database, &copy)?;
+        for suffix in ["-wal", "-shm"] {
+            let mut side = database.as_os_str().to_owned();
+            side.push(suffix);
+            let mut side_copy = copy.as_os_str().to_owned();
+            side_copy.push(suffix);
+            if Path::new(&side).is_file() {
+                let _ = fs::copy(&side, &side_copy);
+            }
+        }
+        let conn = Connection::open_with_flags(&copy, OpenFlags::SQLITE_OPEN_READ_ONLY).map_err(io::Error::other)?;
+        snapshot.conn = Some(conn);
+        Ok(snapshot)
+    }
+
+    pub(crate) fn conn(&self) -> &Connection {
+        self.conn.as_ref().expect("a snapshot is open until dropped")
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        // Closed first; Windows won't delete an open file
+        drop(self.conn.take());
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+/// A temporary folder no other snapshot uses.
+fn new_dir() -> io::Result<PathBuf> {
+    loop {
+        let n = NEXT_SNAPSHOT.fetch_add(1, Ordering::Relaxed);
+        let dir = std::env::temp_dir().join(format!("terra-snapshot-{}-{}", std::process::id(), n));
+        match fs::create_dir(&dir) {
+            // Left behind by an earlier run with the same pid
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => continue,
+            result => return result.map(|_| dir),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn snapshots_read_a_copy_and_clean_up() {
+        let source_dir = std::env::temp_dir().join(format!("terra-snapshot-test-{}", std::process::id()));
+        fs::create_dir_all(&source_dir).unwrap();
+        let database = source_dir.join("app.db");
+        let conn = Connection::open(&database).unwrap();
+        conn.execute_batch("CREATE TABLE t (v INTEGER); INSERT INTO t VALUES (7);").unwrap();
+
+        // Two at once, as with concurrent imports, get folders of their own
+        let first = Snapshot::take(&database).unwrap();
+        let second = Snapshot::take(&database).unwrap();
+        assert_ne!(first.dir, second.dir);
+        let value: i64 = first.conn().query_row("SELECT v FROM t", [], |row| row.get(0)).unwrap();
+        assert_eq!(value, 7);
+        assert!(first.conn().execute("INSERT INTO t VALUES (8)", []).is_err());
+
+        let dir = first.dir.clone();
+        drop(first);
+        assert!(!dir.exists());
+        assert!(second.dir.exists());
+        drop(second);
+
+        assert!(Snapshot::take(&source_dir.join("missing.db")).is_err());
+        drop(conn);
+        let _ = fs::remove_dir_all(&source_dir);
+    }
+}
@@ -0,0 +1,102 @@
+//! Stacks: files of one shot shown as a single item in the grid, such as a
+//! RAW and the JPEG the camera wrote beside it, or the "-edited" copy Google
+//! Takeout exports next to the original. Members share a directory and a
+//! file stem, ignoring case and the "-edited" suffix. Videos are left out;
+//! a still's same-stem video is a Live Photo (see live_photos.rs).
+//!
+//! No database access; callers record stacks with `db::create_stacks`.
+
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::media;
+use crate::raw;
+
+/// Google Photos' name for an edit saved beside the original
+const EDITED_SUFFIX: &str = "-edited";
+
+/// What members of one stack have in common: the lowercased stem without
+/// EDITED_SUFFIX. None for videos and files without a stem.
+fn stack_key(path: &Path) -> Option<String> {
+    if media::is_video(path) {
+        return None;
+    }
+    let stem = path.file_stem()?.to_string_lossy().to_lowercase();
+    let key = stem.strip_suffix(EDITED_SUFFIX).unwrap_or(&stem);
+    (!key.is_empty()).then(|| key.to_string())
+}
+
+fn is_edited(path: &Path) -> bool {
+    path.file_stem().is_some_and(|stem| stem.to_string_lossy().to_lowercase().ends_with(EDITED_SUFFIX))
+}
+
+/// Order of preference for the member the grid shows: an edit, then a
+/// rendered image over a RAW, then by name
+fn primary_order(path: &Path) -> (bool, bool, String) {
+    (!is_edited(path), raw::is_raw(path), path.to_string_lossy().to_string())
+}
+
+/// Find stacks among `paths`, each listed primary first. Like
+/// `live_photos::find_pairs`, other members may be any file next to a given
+/// one, so a stack is found whichever member is imported first.
+pub(crate) fn find_stacks<'a>(paths: impl IntoIterator<Item = &'a str>) -> Vec<Vec<String>> {
+    let mut by_dir: HashMap<PathBuf, HashSet<String>> = HashMap::new();
+    for path in paths.into_iter().map(Path::new) {
+        if let (Some(dir), Some(key)) = (path.parent(), stack_key(path)) {
+            by_dir.entry(dir.to_path_buf()).or_default().insert(key);
+        }
+    }
+
+    let mut stacks = Vec::new();
+    for (dir, keys) in by_dir {
+        let Ok(entries) = fs::read_dir(&dir) else {
+            continue;
+        };
+        // Real names from the listing, so case matches what was imported
+        let mut groups: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for path in entries.filter_map(|e| e.ok()).map(|e| e.path()) {
+            if let Some(key) = stack_key(&path).filter(|key| keys.contains(key)) {
+                if path.is_file() {
+                    groups.entry(key).or_default().push(path);
+                }
+            }
+        }
+        for mut members in groups.into_values().filter(|members| members.len() > 1) {
+            members.sort_by_cached_key(|path| primary_order(path));
+            stacks.push(members.into_iter().map(|p| p.to_string_lossy().to_string()).collect());
+        }
+    }
+    stacks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stacks_raw_jpeg_and_edited_copies() {
+        let dir = std::env::temp_dir().join(format!("terra-stacks-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["IMG_0042.CR2", "IMG_0042.JPG", "IMG_0042-edited.jpg", "IMG_0043.JPG", "IMG_0044.HEIC", "IMG_0044.MOV"] {
+            fs::write(dir.join(name), b"x").unwrap();
+        }
+
+        let path = |name: &str| dir.join(name).to_string_lossy().to_string();
+        // Any member finds the stack, and it is reported once
+        let stacks = find_stacks([path("IMG_0042.CR2"), path("IMG_0042.JPG"), path("IMG_0043.JPG"), path("IMG_0044.HEIC")]
+            .iter()
+            .map(String::as_str));
+        assert_eq!(stacks, vec![vec![path("IMG_0042-edited.jpg"), path("IMG_0042.JPG"), path("IMG_0042.CR2")]]);
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn edited_suffix_is_ignored_only_at_the_end() {
+        assert_eq!(stack_key(Path::new("/p/IMG_1-edited.jpg")).as_deref(), Some("img_1"));
+        assert_eq!(stack_key(Path::new("/p/-edited-copy.jpg")).as_deref(), Some("-edited-copy"));
+        assert_eq!(stack_key(Path::new("/p/-edited.jpg")), None);
+        assert_eq!(stack_key(Path::new("/p/IMG_1.MOV")), None);
+    }
+}
@@ -0,0 +1,268 @@
+//! Google Photos Takeout support: matching media files to their JSON
+//! sidecars and reading capture time, caption and location from them.
+//! Takeout strips EXIF from many files, so the sidecar is often the only
+//! record of when a photo was taken.
+//!
+//! No database access; `import_takeout` in `lib.rs` does the importing.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::media;
+
+/// Takeout cuts sidecar file names, ".json" included, to this many characters.
+const JSON_NAME_LIMIT: usize = 51;
+/// Shortest a media file name Takeout cut short can be
+const TRUNCATED_NAME_MIN: usize = JSON_NAME_LIMIT - ".json".len();
+/// Newer exports put this between the media name and ".json".
+const SUPPLEMENTAL_SUFFIX: &str = ".supplemental-metadata";
+/// Suffixes Takeout gives edited copies, which share the original's sidecar.
+const EDITED_SUFFIXES: [&str; 5] = ["-edited", "-bearbeitet", "-modifié", "-editado", "-modificato"];
+/// Per-folder album description, by export language.
+const ALBUM_METADATA_NAMES: [&str; 3] = ["metadata.json", "metadaten.json", "métadonnées.json"];
+
+/// What a sidecar says about its photo.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub(crate) struct Sidecar {
+    /// Original file name, untruncated
+    pub title: Option<String>,
+    pub taken_at: Option<i64>,
+    pub description: Option<String>,
+    pub gps: Option<(f64, f64)>,
+    /// The export re-encoded the file, so a date embedded in it is when
+    /// that happened and `taken_at` wins over it
+    pub re_encoded: bool,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct SidecarJson {
+    title: Option<String>,
+    description: Option<String>,
+    photo_taken_time: Option<TakeoutTime>,
+    geo_data: Option<GeoData>,
+    geo_data_exif: Option<GeoData>,
+}
+
+#[derive(Deserialize)]
+struct TakeoutTime {
+    /// Seconds since the epoch, as a string
+    timestamp: String,
+}
+
+#[derive(Deserialize)]
+struct GeoData {
+    latitude: f64,
+    longitude: f64,
+}
+
+/// Parse a photo sidecar. Returns None for JSON that isn't one (album
+/// metadata, print orders), recognised by the missing photoTakenTime.
+pub(crate) fn parse_sidecar(json: &str) -> Option<Sidecar> {
+    let raw: SidecarJson = serde_json::from_str(json).ok()?;
+    let taken = raw.photo_taken_time?;
+    // Google writes 0/0 when it has no location
+    let gps = [raw.geo_data, raw.geo_data_exif]
+        .into_iter()
+        .flatten()
+        .find_map(|g| media::finalize_gps(g.latitude, g.longitude, false, false));
+    Some(Sidecar {
+        title: raw.title.filter(|t| !t.is_empty()),
+        taken_at: taken.timestamp.trim().parse::<i64>().ok().filter(|&t| t > 0),
+        description: raw.description.map(|d| d.trim().to_string()).filter(|d| !d.is_empty()),
+        gps,
+        re_encoded: false,
+    })
+}
+
+/// Sidecar names Takeout may have used for `file_name`, most likely first.
+/// Covers the name-length cut, "(1)" counters that Takeout moves after the
+/// extension (`IMG_1(1).jpg` -> `IMG_1.jpg(1).json`) and edited copies,
+/// which use the original's sidecar.
+pub(crate) fn sidecar_candidates(file_name: &str) -> Vec<String> {
+    let (stem, ext) = match file_name.rfind('.') {
+        Some(i) if i > 0 => file_name.split_at(i),
+        _ => (file_name, ""),
+    };
+    let stem = EDITED_SUFFIXES
+        .iter()
+        .find_map(|suffix| stem.strip_suffix(suffix))
+        .unwrap_or(stem);
+    let (stem, counter) = split_counter(stem);
+
+    let original = format!("{}{}", stem, ext);
+    let mut candidates = vec![
+        truncated_json_name(&original, counter),
+        truncated_json_name(&format!("{}{}", original, SUPPLEMENTAL_SUFFIX), counter),
+        // Some exports drop the media extension
+        format!("{}{}.json", stem, counter),
+    ];
+    candidates.dedup();
+    candidates
+}
+
+/// Split a trailing "(n)" duplicate counter off a file stem.
+fn split_counter(stem: &str) -> (&str, &str) {
+    if let Some(open) = stem.rfind('(') {
+        let inner = &stem[open + 1..];
+        if let Some(digits) = inner.strip_suffix(')') {
+            if !digits.is_empty() && digits.bytes().all(|b| b.is_ascii_digit()) {
+                return stem.split_at(open);
+            }
+        }
+    }
+    (stem, "")
+}
+
+fn truncated_json_name(base: &str, counter: &str) -> String {
+    let limit = JSON_NAME_LIMIT - ".json".len() - counter.chars().count();
+    let base: String = base.chars().take(limit).collect();
+    format!("{}{}.json", base, counter)
+}
+
+/// Find and read the sidecar next to `media`, if there is one.
+pub(crate) fn find_sidecar(media: &Path) -> Option<Sidecar> {
+    let dir = media.parent()?;
+    let name = media.file_name()?.to_string_lossy();
+    sidecar_candidates(&name)
+        .into_iter()
+        .map(|candidate| dir.join(candidate))
+        .filter(|path| path.is_file())
+        .find_map(|path| read_sidecar(&path))
+}
+
+fn read_sidecar(path: &Path) -> Option<Sidecar> {
+    parse_sidecar(&fs::read_to_string(path).ok()?)
+}
+
+/// Every sidecar in `dir` by the file name it describes. The fallback for
+/// media whose own name was truncated, so no candidate name matches.
+pub(crate) fn title_index(dir: &Path) -> HashMap<String, Sidecar> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return HashMap::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext.eq_ignore_ascii_case("json")))
+        .filter_map(|p| read_sidecar(&p))
+        .filter_map(|s| Some((s.title.clone()?, s)))
+        .collect()
+}
+
+/// Look `file_name` up in a title index: exactly, or, when it is long enough
+/// to have been cut short, as a truncated form of a longer title with the
+/// same extension.
+pub(crate) fn match_title<'a>(index: &'a HashMap<String, Sidecar>, file_name: &str) -> Option<&'a Sidecar> {
+    if let Some(sidecar) = index.get(file_name) {
+        return Some(sidecar);
+    }
+    if file_name.chars().count() < TRUNCATED_NAME_MIN {
+        return None;
+    }
+    let path = Path::new(file_name);
+    let stem = path.file_stem()?.to_string_lossy();
+    let ext = path.extension()?.to_string_lossy().to_lowercase();
+    let mut matches = index.iter().filter(|(title, _)| {
+        let title = Path::new(title.as_str());
+        title.extension().is_some_and(|e| e.to_string_lossy().to_lowercase() == ext)
+            && title.file_stem().is_some_and(|s| {
+                let s = s.to_string_lossy();
+                s.len() > stem.len() && s.starts_with(stem.as_ref())
+            })
+    });
+    // Two long names with the same prefix are ambiguous
+    match (matches.next(), matches.next()) {
+        (Some((_, sidecar)), None) => Some(sidecar),
+        _ => None,
+    }
+}
+
+/// Album name from the folder's metadata.json, for Takeout album folders.
+/// Year folders ("Photos from 2019") have none.
+pub(crate) fn album_title(dir: &Path) -> Option<String> {
+    #[derive(Deserialize)]
+    struct AlbumJson {
+        title: Option<String>,
+    }
+    ALBUM_METADATA_NAMES
+        .iter()
+        .map(|name| dir.join(name))
+        .find(|p| p.is_file())
+        .and_then(|p| fs::read_to_string(p).ok())
+        .and_then(|json| serde_json::from_str::<AlbumJson>(&json).ok())
+        .and_then(|album| album.title)
+        .map(|t| t.trim().to_string())
+        .filter(|t| !t.is_empty())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIDECAR: &str = r#"{
+        "title": "IMG_1234.jpg",
+        "description": "  Lake day ",
+        "photoTakenTime": { "timestamp": "1563380400", "formatted": "Jul 17, 2019" },
+        "geoData": { "latitude": 0.0, "longitude": 0.0, "altitude": 0.0 },
+        "geoDataExif": { "latitude": 46.5, "longitude": -121.75, "altitude": 0.0 }
+    }"#;
+
+    #[test]
+    fn parses_time_caption_and_the_first_real_location() {
+        let sidecar = parse_sidecar(SIDECAR).unwrap();
+        assert_eq!(sidecar.title.as_deref(), Some("IMG_1234.jpg"));
+        assert_eq!(sidecar.taken_at, Some(1_563_380_400));
+        assert_eq!(sidecar.description.as_deref(), Some("Lake day"));
+        assert_eq!(sidecar.gps, Some((46.5, -121.75)));
+    }
+
+    #[test]
+    fn album_metadata_is_not_a_sidecar() {
+        assert!(parse_sidecar(r#"{"title": "Rome", "description": ""}"#).is_none());
+        assert!(parse_sidecar("not json").is_none());
+    }
+
+    #[test]
+    fn candidates_cover_takeout_naming_quirks() {
+        assert_eq!(
+            sidecar_candidates("IMG_1234.jpg"),
+            vec!["IMG_1234.jpg.json", "IMG_1234.jpg.supplemental-metadata.json", "IMG_1234.json"]
+        );
+        assert!(sidecar_candidates("IMG_1234(1).jpg").contains(&"IMG_1234.jpg(1).json".to_string()));
+        assert!(sidecar_candidates("IMG_1234-edited.jpg").contains(&"IMG_1234.jpg.json".to_string()));
+
+        // The supplemental name is cut to 51 characters
+        let long = sidecar_candidates("PXL_20230704_183015123.NIGHT.jpg");
+        assert!(long.contains(&"PXL_20230704_183015123.NIGHT.jpg.supplemental-.json".to_string()));
+        assert!(long.iter().all(|c| c.chars().count() <= JSON_NAME_LIMIT));
+    }
+
+    #[test]
+    fn finds_sidecars_on_disk_and_by_title() {
+        let dir = std::env::temp_dir().join(format!("terra-takeout-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("IMG_1234.jpg.json"), SIDECAR).unwrap();
+        let long_title = SIDECAR.replace("IMG_1234.jpg", "Screenshot_20190717-103000_Very Long App Name.png");
+        fs::write(dir.join("Screenshot_20190717-103000_Very Long App Na.json"), long_title).unwrap();
+        fs::write(dir.join("metadata.json"), r#"{"title": "Lake trip"}"#).unwrap();
+
+        assert!(find_sidecar(&dir.join("IMG_1234-edited.jpg")).is_some());
+        assert!(find_sidecar(&dir.join("IMG_9999.jpg")).is_none());
+
+        let index = title_index(&dir);
+        assert_eq!(index.len(), 2);
+        assert!(match_title(&index, "Screenshot_20190717-103000_Very Long App Nam.png").is_some());
+        assert!(match_title(&index, "Screenshot_20190717-103000_Very Long App Nam.jpg").is_none());
+        // Too short to have been cut, so not a prefix match
+        assert!(match_title(&index, "IMG_12.jpg").is_none());
+        assert!(match_title(&index, "Screenshot_20190717.png").is_none());
+
+        assert_eq!(album_title(&dir).as_deref(), Some("Lake trip"));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
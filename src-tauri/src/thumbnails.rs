@@ -1,13 +1,31 @@
 use std::fs;
+use std::io::{self, Read};
 use std::path::{Path, PathBuf};
 
 use image::codecs::jpeg::JpegEncoder;
-use image::ImageReader;
+use image::metadata::Orientation;
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use serde::Serialize;
+use tauri_plugin_shell::ShellExt;
 
+use crate::edits::PhotoEdits;
+use crate::heif;
 use crate::media;
+use crate::placeholders::{self, Placeholder};
+use crate::raw;
 
 pub const THUMB_SIZE: u32 = 256;
+/// Sizes the cache holds. Requests are rounded up to one of these so the
+/// grid, zoomed grid and viewer share cached files instead of each making
+/// their own.
+pub const THUMB_SIZES: [u32; 3] = [THUMB_SIZE, 512, 1024];
 const JPEG_QUALITY: u8 = 80;
+/// Video posters are taken this far in, past black lead-in frames.
+const VIDEO_FRAME_SECONDS: &str = "1";
+/// Where Homebrew and MacPorts install ffmpeg. Apps launched from Finder
+/// don't inherit the shell PATH, so these are checked explicitly.
+const FFMPEG_LOCATIONS: &[&str] = &["/opt/homebrew/bin/ffmpeg", "/usr/local/bin/ffmpeg", "/opt/local/bin/ffmpeg"];
 
 /// Root directory for the on-disk thumbnail cache.
 /// Lives under the same Terra data dir as the SQLite DB.
@@ -32,39 +50,529 @@ pub fn thumb_path(content_hash: &str, size: u32) -> PathBuf {
     path
 }
 
+/// Smallest cached size at least as large as `requested`, capped at the
+/// largest bucket.
+pub fn bucket_size(requested: u32) -> u32 {
+    THUMB_SIZES
+        .iter()
+        .copied()
+        .find(|&size| size >= requested)
+        .unwrap_or(THUMB_SIZES[THUMB_SIZES.len() - 1])
+}
+
+/// Remove every cached size of a thumbnail. Used when the last photo with
+/// this content is deleted.
+pub fn remove_thumbnails(content_hash: &str) {
+    let prefix = if content_hash.len() >= 2 { &content_hash[..2] } else { content_hash };
+    let Ok(sizes) = fs::read_dir(thumb_cache_root()) else {
+        return;
+    };
+    for size_dir in sizes.flatten() {
+        let file = size_dir.path().join(prefix).join(format!("{}.jpg", content_hash));
+        if file.exists() {
+            let _ = fs::remove_file(file);
+        }
+    }
+}
+
+/// Delete the whole thumbnail cache. Returns how many thumbnails were removed.
+pub fn purge_cache() -> io::Result<usize> {
+    purge_dir(&thumb_cache_root())
+}
+
+fn purge_dir(root: &Path) -> io::Result<usize> {
+    let count = walkdir::WalkDir::new(root)
+        .into_iter()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_file())
+        .count();
+    for entry in fs::read_dir(root)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            fs::remove_dir_all(&path)?;
+        } else {
+            fs::remove_file(&path)?;
+        }
+    }
+    Ok(count)
+}
+
+/// Why a video poster couldn't be made. Serialized as `{ kind, message }` so
+/// the frontend can tell "install ffmpeg" apart from a broken file.
+#[derive(Debug, Serialize)]
+#[serde(tag = "kind", content = "message", rename_all = "snake_case")]
+pub enum VideoThumbError {
+    FfmpegMissing(String),
+    Failed(String),
+}
+
+impl std::fmt::Display for VideoThumbError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VideoThumbError::FfmpegMissing(msg) | VideoThumbError::Failed(msg) => f.write_str(msg),
+        }
+    }
+}
+
+impl From<String> for VideoThumbError {
+    fn from(msg: String) -> Self {
+        VideoThumbError::Failed(msg)
+    }
+}
+
+/// Locate an ffmpeg binary on PATH or in the usual package-manager locations.
+pub fn find_ffmpeg() -> Option<PathBuf> {
+    let on_path = std::env::var_os("PATH")
+        .map(|paths| std::env::split_paths(&paths).map(|dir| dir.join("ffmpeg")).collect::<Vec<_>>())
+        .unwrap_or_default();
+    on_path
+        .into_iter()
+        .chain(FFMPEG_LOCATIONS.iter().map(PathBuf::from))
+        .find(|candidate| candidate.is_file())
+}
+
+/// Write a poster frame for a video into the thumbnail cache, using ffmpeg
+/// through the shell plugin. Idempotent like `generate_thumbnail`.
+pub async fn generate_video_thumbnail(
+    app: &tauri::AppHandle,
+    source: &Path,
+    content_hash: &str,
+    size: u32,
+) -> Result<PathBuf, VideoThumbError> {
+    let dest = thumb_path(content_hash, size);
+    if dest.exists() {
+        return Ok(dest);
+    }
+
+    let ffmpeg = find_ffmpeg().ok_or_else(|| {
+        VideoThumbError::FfmpegMissing(
+            "ffmpeg is needed for video thumbnails. Install it (e.g. `brew install ffmpeg`) and try again.".to_string(),
+        )
+    })?;
+
+    // Write beside the final path and rename, so a killed ffmpeg never
+    // leaves a truncated JPEG that later counts as a cache hit.
+    let partial = dest.with_extension("partial.jpg");
+    let scale = format!("scale={0}:{0}:force_original_aspect_ratio=decrease", size);
+    // Clips shorter than the seek point produce no frame; retry from the start.
+    let mut stderr = String::new();
+    for seek in [VIDEO_FRAME_SECONDS, "0"] {
+        let output = app
+            .shell()
+            .command(&ffmpeg)
+            .args(["-nostdin", "-y", "-loglevel", "error", "-ss", seek, "-i"])
+            .arg(source)
+            .args(["-frames:v", "1", "-vf", scale.as_str(), "-q:v", "4"])
+            .arg(&partial)
+            .output()
+            .await
+            .map_err(|e| format!("failed to run ffmpeg: {}", e))?;
+
+        if output.status.success() && partial.exists() {
+            fs::rename(&partial, &dest)
+                .map_err(|e| format!("failed to move thumbnail into place: {}", e))?;
+            return Ok(dest);
+        }
+        stderr = String::from_utf8_lossy(&output.stderr).trim().to_string();
+    }
+
+    let _ = fs::remove_file(&partial);
+    Err(VideoThumbError::Failed(format!("ffmpeg could not read {}: {}", source.display(), stderr)))
+}
+
 /// Generate a thumbnail for one image and write it to the cache.
 /// Returns the destination path. Idempotent: if the thumbnail already exists, returns immediately.
-/// Videos are unsupported here; callers should detect them and skip.
+/// The EXIF orientation is applied, so thumbnails display upright without CSS.
+/// Videos go through `generate_video_thumbnail` instead.
 pub fn generate_thumbnail(source: &Path, content_hash: &str, size: u32) -> Result<PathBuf, String> {
     let dest = thumb_path(content_hash, size);
     if dest.exists() {
         return Ok(dest);
     }
+    render_thumbnail(source, &dest, size)?;
+    Ok(dest)
+}
+
+/// `generate_thumbnail`, also giving the photo's grid placeholder, made
+/// from the bitmap just rendered (or the cached file when one existed)
+pub fn generate_thumbnail_with_placeholder(
+    source: &Path,
+    content_hash: &str,
+    size: u32,
+) -> Result<(PathBuf, Option<Placeholder>), String> {
+    let dest = thumb_path(content_hash, size);
+    let rendered = if dest.exists() { None } else { render_thumbnail(source, &dest, size)? };
+    let placeholder = placeholder_for(rendered.as_ref(), &dest);
+    Ok((dest, placeholder))
+}
+
+/// Replace a cached thumbnail (typically an embedded EXIF one) with a full
+/// decode-and-resize, returning it with its placeholder. The old file stays
+/// in place until the new one is ready.
+pub fn upgrade_thumbnail(source: &Path, content_hash: &str, size: u32) -> Result<(PathBuf, Option<Placeholder>), String> {
+    let dest = thumb_path(content_hash, size);
+    let rendered = render_thumbnail(source, &dest, size)?;
+    let placeholder = placeholder_for(rendered.as_ref(), &dest);
+    Ok((dest, placeholder))
+}
+
+/// From `rendered` when the thumbnail was decoded here, else read back from
+/// `dest` (a cache hit, or HEIC converted by sips)
+fn placeholder_for(rendered: Option<&DynamicImage>, dest: &Path) -> Option<Placeholder> {
+    match rendered {
+        Some(img) => Some(placeholders::from_image(img)),
+        None => placeholders::from_file(dest).ok(),
+    }
+}
+
+fn render_thumbnail(source: &Path, dest: &Path, size: u32) -> Result<Option<DynamicImage>, String> {
+    render(source, dest, Scale::Thumbnail(size), JPEG_QUALITY)
+}
+
+/// Write `source` to `dest` as an upright JPEG no larger than `max_edge` on
+/// its long side, at `quality` (1-100). Smaller images keep their size.
+/// For exports; unlike thumbnails this uses a slower, sharper filter.
+pub fn render_resized(source: &Path, dest: &Path, max_edge: u32, quality: u8) -> Result<(), String> {
+    render(source, dest, Scale::ShrinkToFit(max_edge), quality.clamp(1, 100)).map(|_| ())
+}
+
+/// How an image is scaled on its way to JPEG.
+#[derive(Clone, Copy)]
+enum Scale {
+    /// Fit the box, fast
+    Thumbnail(u32),
+    /// Fit the box if larger, with Lanczos resampling
+    ShrinkToFit(u32),
+}
+
+impl Scale {
+    fn apply(self, img: &DynamicImage) -> DynamicImage {
+        match self {
+            Scale::Thumbnail(size) => img.thumbnail(size, size),
+            Scale::ShrinkToFit(size) if img.width() > size || img.height() > size => {
+                img.resize(size, size, FilterType::Lanczos3)
+            }
+            Scale::ShrinkToFit(_) => img.clone(),
+        }
+    }
+}
 
+/// Returns the image written, unless another program did the conversion
+fn render(source: &Path, dest: &Path, scale: Scale, quality: u8) -> Result<Option<DynamicImage>, String> {
     if media::is_video(source) {
-        return Err(format!("video thumbnails not implemented: {}", source.display()));
+        return Err(format!("use generate_video_thumbnail for video: {}", source.display()));
+    }
+    if heif::is_heif(source) {
+        return render_heif(source, dest, scale, quality);
+    }
+    let img = decode_scaled(source, scale)?;
+    write_jpeg(&img, dest, quality)?;
+    Ok(Some(img))
+}
+
+/// `source` decoded, scaled and turned upright. RAW files give their
+/// largest embedded preview; the sensor data isn't decoded. Not for HEIC.
+fn decode_scaled(source: &Path, scale: Scale) -> Result<DynamicImage, String> {
+    if raw::is_raw(source) {
+        let preview = raw::read_preview(source)
+            .ok_or_else(|| format!("no embedded preview in {}", source.display()))?;
+        let img = image::load_from_memory_with_format(&preview.jpeg, ImageFormat::Jpeg)
+            .map_err(|e| format!("failed to decode preview of {}: {}", source.display(), e))?;
+        let mut resized = scale.apply(&img);
+        resized.apply_orientation(Orientation::from_exif(preview.orientation).unwrap_or(Orientation::NoTransforms));
+        return Ok(resized);
     }
 
     let reader = ImageReader::open(source)
         .map_err(|e| format!("failed to open {}: {}", source.display(), e))?
         .with_guessed_format()
         .map_err(|e| format!("failed to detect format: {}", e))?;
-    let img = reader
-        .decode()
+    let mut decoder = reader
+        .into_decoder()
+        .map_err(|e| format!("failed to decode {}: {}", source.display(), e))?;
+    let orientation = decoder.orientation().unwrap_or(Orientation::NoTransforms);
+    let img = DynamicImage::from_decoder(decoder)
         .map_err(|e| format!("failed to decode {}: {}", source.display(), e))?;
 
-    let resized = img.thumbnail(size, size);
+    // Rotate after resizing; it's the same result on far fewer pixels.
+    let mut resized = scale.apply(&img);
+    resized.apply_orientation(orientation);
+    Ok(resized)
+}
 
-    let mut out = fs::File::create(&dest)
-        .map_err(|e| format!("failed to create {}: {}", dest.display(), e))?;
-    let mut encoder = JpegEncoder::new_with_quality(&mut out, JPEG_QUALITY);
-    encoder
-        .encode_image(&resized)
-        .map_err(|e| format!("failed to encode JPEG: {}", e))?;
+/// Write `source` with `edits` applied to `dest` as a JPEG no larger than
+/// `max_edge` on its long side. The original is only read.
+pub fn render_edited(source: &Path, dest: &Path, edits: &PhotoEdits, max_edge: u32, quality: u8) -> Result<(), String> {
+    if media::is_video(source) {
+        return Err(format!("videos can't be edited: {}", source.display()));
+    }
+    // Shrink first, but keep enough pixels for the crop to fill `max_edge`
+    let working = Scale::ShrinkToFit((max_edge as f32 / edits.crop_scale()).ceil() as u32);
+    let img = if heif::is_heif(source) { decode_heif(source, working)? } else { decode_scaled(source, working)? };
+    let edited = edits.apply(img);
+    write_jpeg(&Scale::ShrinkToFit(max_edge).apply(&edited), dest, quality.clamp(1, 100))
+}
 
+/// Cached render of `source` with `edits` at `size`, keyed by the photo's
+/// content and the recipe so a changed recipe never shows a stale render.
+/// Idempotent like `generate_thumbnail`.
+pub fn generate_edited(source: &Path, content_hash: &str, edits: &PhotoEdits, size: u32) -> Result<PathBuf, String> {
+    let dest = thumb_path(&edited_key(content_hash, edits), size);
+    if dest.exists() {
+        return Ok(dest);
+    }
+    render_edited(source, &dest, edits, size, JPEG_QUALITY)?;
     Ok(dest)
 }
 
+/// Cache name of a photo's renders with `edits` applied. Pass to
+/// `remove_thumbnails` when the recipe changes.
+pub fn edited_key(content_hash: &str, edits: &PhotoEdits) -> String {
+    format!("{}-{}", content_hash, edits.fingerprint())
+}
+
+#[cfg(feature = "heif")]
+fn decode_heif(source: &Path, scale: Scale) -> Result<DynamicImage, String> {
+    Ok(scale.apply(&heif::decode(source)?))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(source: &Path, _scale: Scale) -> Result<DynamicImage, String> {
+    Err(format!("editing HEIC needs Terra built with the `heif` feature: {}", source.display()))
+}
+
+/// HEIC through libheif, which applies the container's rotation itself.
+#[cfg(feature = "heif")]
+fn render_heif(source: &Path, dest: &Path, scale: Scale, quality: u8) -> Result<Option<DynamicImage>, String> {
+    let img = scale.apply(&heif::decode(source)?);
+    write_jpeg(&img, dest, quality)?;
+    Ok(Some(img))
+}
+
+/// Without libheif, macOS's own `sips` converts HEIC.
+#[cfg(all(not(feature = "heif"), target_os = "macos"))]
+fn render_heif(source: &Path, dest: &Path, scale: Scale, quality: u8) -> Result<Option<DynamicImage>, String> {
+    // sips can't tell shrinking from fitting; either way the long edge is capped
+    let (Scale::Thumbnail(size) | Scale::ShrinkToFit(size)) = scale;
+    let partial = dest.with_extension("partial.jpg");
+    let output = std::process::Command::new("/usr/bin/sips")
+        .args(["-s", "format", "jpeg", "-s", "formatOptions", &quality.to_string()])
+        .args(["-Z", &size.to_string()])
+        .arg(source)
+        .arg("--out")
+        .arg(&partial)
+        .output()
+        .map_err(|e| format!("failed to run sips: {}", e))?;
+    if !output.status.success() || !partial.exists() {
+        let _ = fs::remove_file(&partial);
+        return Err(format!(
+            "sips could not convert {}: {}",
+            source.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        ));
+    }
+    fs::rename(&partial, dest).map_err(|e| format!("failed to move thumbnail into place: {}", e))?;
+    Ok(None)
+}
+
+#[cfg(all(not(feature = "heif"), not(target_os = "macos")))]
+fn render_heif(source: &Path, _dest: &Path, _scale: Scale, _quality: u8) -> Result<Option<DynamicImage>, String> {
+    Err(format!("HEIC thumbnails need Terra built with the `heif` feature: {}", source.display()))
+}
+
+/// Turn an image's pixels `degrees` (90, 180 or 270) clockwise and save it
+/// over the original in its own format. For formats without an orientation
+/// to change; JPEGs are rotated losslessly by `exif_write::rotate_jpeg`.
+pub fn rotate_pixels(path: &Path, degrees: u16) -> Result<(), String> {
+    if media::is_video(path) || heif::is_heif(path) || raw::is_raw(path) {
+        return Err(format!("can't rotate {}", path.display()));
+    }
+    let format = ImageFormat::from_path(path).map_err(|e| format!("can't rotate {}: {}", path.display(), e))?;
+    let img = image::open(path).map_err(|e| format!("failed to decode {}: {}", path.display(), e))?;
+    let rotated = match degrees {
+        90 => img.rotate90(),
+        180 => img.rotate180(),
+        270 => img.rotate270(),
+        _ => return Err(format!("can't rotate by {} degrees", degrees)),
+    };
+
+    let partial = path.with_extension("terra-tmp");
+    if let Err(e) = rotated.save_with_format(&partial, format) {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("failed to encode {}: {}", path.display(), e));
+    }
+    fs::rename(&partial, path).map_err(|e| format!("failed to replace {}: {}", path.display(), e))
+}
+
+/// Encode to a sibling temp file and rename, so readers never see a partial
+/// JPEG and an existing thumbnail is replaced atomically.
+fn write_jpeg(img: &DynamicImage, dest: &Path, quality: u8) -> Result<(), String> {
+    let partial = dest.with_extension("partial.jpg");
+    let mut out = fs::File::create(&partial)
+        .map_err(|e| format!("failed to create {}: {}", partial.display(), e))?;
+    let mut encoder = JpegEncoder::new_with_quality(&mut out, quality);
+    if let Err(e) = encoder.encode_image(img) {
+        let _ = fs::remove_file(&partial);
+        return Err(format!("failed to encode JPEG: {}", e));
+    }
+    fs::rename(&partial, dest).map_err(|e| format!("failed to move thumbnail into place: {}", e))
+}
+
+// ============================================================================
+// Embedded EXIF thumbnails
+// ============================================================================
+
+/// How much of a JPEG to read looking for EXIF. APP1 is at most 64 KB and
+/// sits right after SOI, or after a JFIF APP0.
+const EXIF_SEARCH_BYTES: u64 = 128 * 1024;
+const TAG_ORIENTATION: u16 = 0x0112;
+const TAG_THUMBNAIL_OFFSET: u16 = 0x0201;
+const TAG_THUMBNAIL_LENGTH: u16 = 0x0202;
+
+/// Write the camera's embedded EXIF preview (usually ~160px) as the cached
+/// thumbnail instead of decoding the full image. Orders of magnitude faster,
+/// but low resolution: callers mark it 'embedded' and upgrade it later with
+/// `upgrade_thumbnail`. Errors when the file has no usable preview. Returns
+/// the thumbnail with its placeholder.
+pub fn generate_embedded_thumbnail(source: &Path, content_hash: &str, size: u32) -> Result<(PathBuf, Option<Placeholder>), String> {
+    let dest = thumb_path(content_hash, size);
+    if dest.exists() {
+        let placeholder = placeholder_for(None, &dest);
+        return Ok((dest, placeholder));
+    }
+
+    let (bytes, orientation) = read_embedded_thumbnail(source)
+        .ok_or_else(|| format!("no embedded thumbnail in {}", source.display()))?;
+    let mut img = image::load_from_memory_with_format(&bytes, ImageFormat::Jpeg)
+        .map_err(|e| format!("corrupt embedded thumbnail in {}: {}", source.display(), e))?;
+    if img.width() > size || img.height() > size {
+        img = img.thumbnail(size, size);
+    }
+    // The preview is stored with the same orientation as the main image.
+    img.apply_orientation(orientation);
+    write_jpeg(&img, &dest, JPEG_QUALITY)?;
+    Ok((dest, Some(placeholders::from_image(&img))))
+}
+
+/// The EXIF preview JPEG bytes and the image orientation, if present.
+fn read_embedded_thumbnail(source: &Path) -> Option<(Vec<u8>, Orientation)> {
+    if heif::is_heif(source) {
+        return parse_exif_thumbnail(&heif::read_exif(source)?);
+    }
+    let mut head = Vec::new();
+    fs::File::open(source)
+        .ok()?
+        .take(EXIF_SEARCH_BYTES)
+        .read_to_end(&mut head)
+        .ok()?;
+    parse_embedded_thumbnail(&head)
+}
+
+/// Pull the IFD1 preview out of a JPEG's EXIF block.
+fn parse_embedded_thumbnail(jpeg: &[u8]) -> Option<(Vec<u8>, Orientation)> {
+    parse_exif_thumbnail(exif_segment(jpeg)?)
+}
+
+/// Walk the TIFF structure of an EXIF block directly (IFD0 -> next IFD ->
+/// JPEGInterchangeFormat) for the preview.
+fn parse_exif_thumbnail(exif: &[u8]) -> Option<(Vec<u8>, Orientation)> {
+    let tiff = Tiff::new(exif)?;
+    let ifd0 = tiff.u32(4)? as usize;
+    let orientation = tiff
+        .tag_value(ifd0, TAG_ORIENTATION)
+        .and_then(|v| Orientation::from_exif(u8::try_from(v).ok()?))
+        .unwrap_or(Orientation::NoTransforms);
+
+    let ifd1 = tiff.next_ifd(ifd0)?;
+    let offset = tiff.tag_value(ifd1, TAG_THUMBNAIL_OFFSET)? as usize;
+    let length = tiff.tag_value(ifd1, TAG_THUMBNAIL_LENGTH)? as usize;
+    let bytes = tiff.data.get(offset..offset.checked_add(length)?)?;
+    if !bytes.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    Some((bytes.to_vec(), orientation))
+}
+
+/// Body of the APP1 "Exif" segment (the TIFF data), scanning markers up to
+/// the start of the image data.
+fn exif_segment(jpeg: &[u8]) -> Option<&[u8]> {
+    if !jpeg.starts_with(&[0xFF, 0xD8]) {
+        return None;
+    }
+    let mut pos = 2;
+    loop {
+        if *jpeg.get(pos)? != 0xFF {
+            return None;
+        }
+        let marker = *jpeg.get(pos + 1)?;
+        // Start of scan / end of image: no EXIF before the pixels
+        if marker == 0xDA || marker == 0xD9 {
+            return None;
+        }
+        let len = u16::from_be_bytes([*jpeg.get(pos + 2)?, *jpeg.get(pos + 3)?]) as usize;
+        if len < 2 {
+            return None;
+        }
+        let body = jpeg.get(pos + 4..pos + 2 + len)?;
+        if marker == 0xE1 {
+            if let Some(tiff) = body.strip_prefix(b"Exif\0\0") {
+                return Some(tiff);
+            }
+        }
+        pos += 2 + len;
+    }
+}
+
+/// Minimal TIFF reader over an EXIF block; offsets are relative to its start.
+struct Tiff<'a> {
+    data: &'a [u8],
+    little_endian: bool,
+}
+
+impl<'a> Tiff<'a> {
+    fn new(data: &'a [u8]) -> Option<Self> {
+        let little_endian = match data.get(0..2)? {
+            b"II" => true,
+            b"MM" => false,
+            _ => return None,
+        };
+        let tiff = Tiff { data, little_endian };
+        (tiff.u16(2)? == 42).then_some(tiff)
+    }
+
+    fn u16(&self, at: usize) -> Option<u16> {
+        let b: [u8; 2] = self.data.get(at..at + 2)?.try_into().ok()?;
+        Some(if self.little_endian { u16::from_le_bytes(b) } else { u16::from_be_bytes(b) })
+    }
+
+    fn u32(&self, at: usize) -> Option<u32> {
+        let b: [u8; 4] = self.data.get(at..at + 4)?.try_into().ok()?;
+        Some(if self.little_endian { u32::from_le_bytes(b) } else { u32::from_be_bytes(b) })
+    }
+
+    /// Value of a SHORT or LONG tag in the IFD at `ifd`.
+    fn tag_value(&self, ifd: usize, tag: u16) -> Option<u32> {
+        let count = self.u16(ifd)? as usize;
+        (0..count).map(|i| ifd + 2 + i * 12).find_map(|entry| {
+            if self.u16(entry)? != tag {
+                return None;
+            }
+            match self.u16(entry + 2)? {
+                3 => self.u16(entry + 8).map(u32::from),
+                4 => self.u32(entry + 8),
+                _ => None,
+            }
+        })
+    }
+
+    /// Offset of the IFD chained after the one at `ifd`, if any.
+    fn next_ifd(&self, ifd: usize) -> Option<usize> {
+        let count = self.u16(ifd)? as usize;
+        let next = self.u32(ifd + 2 + count * 12)? as usize;
+        (next != 0 && next != ifd).then_some(next)
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -121,6 +629,142 @@ mod tests {
         let _ = fs::remove_file(&first);
     }
 
+    #[test]
+    fn remove_thumbnails_clears_every_size() {
+        let hash = format!("test_thumb_remove_{}", std::process::id());
+        let small = thumb_path(&hash, 64);
+        let large = thumb_path(&hash, 256);
+        fs::write(&small, b"x").unwrap();
+        fs::write(&large, b"x").unwrap();
+
+        remove_thumbnails(&hash);
+        assert!(!small.exists());
+        assert!(!large.exists());
+    }
+
+    #[test]
+    fn bucket_size_rounds_up_to_cached_sizes() {
+        assert_eq!(bucket_size(0), 256);
+        assert_eq!(bucket_size(200), 256);
+        assert_eq!(bucket_size(256), 256);
+        assert_eq!(bucket_size(300), 512);
+        assert_eq!(bucket_size(1024), 1024);
+        assert_eq!(bucket_size(4000), 1024);
+    }
+
+    #[test]
+    fn purge_dir_removes_everything_under_root() {
+        let root = std::env::temp_dir().join(format!("terra-thumb-purge-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        fs::create_dir_all(root.join("256").join("ab")).unwrap();
+        fs::create_dir_all(root.join("512").join("cd")).unwrap();
+        fs::write(root.join("256").join("ab").join("ab1.jpg"), b"x").unwrap();
+        fs::write(root.join("256").join("ab").join("ab2.jpg"), b"x").unwrap();
+        fs::write(root.join("512").join("cd").join("cd1.jpg"), b"x").unwrap();
+
+        assert_eq!(purge_dir(&root).unwrap(), 3);
+        assert!(root.exists());
+        assert_eq!(fs::read_dir(&root).unwrap().count(), 0);
+        let _ = fs::remove_dir_all(&root);
+    }
+
+    #[test]
+    fn video_thumb_error_serializes_with_kind() {
+        let json = serde_json::to_value(VideoThumbError::FfmpegMissing("install ffmpeg".into())).unwrap();
+        assert_eq!(json, serde_json::json!({ "kind": "ffmpeg_missing", "message": "install ffmpeg" }));
+    }
+
+    fn encode_jpeg(width: u32, height: u32) -> Vec<u8> {
+        let img = DynamicImage::ImageRgb8(ImageBuffer::from_pixel(width, height, Rgb([10, 120, 200])));
+        let mut buf = Vec::new();
+        JpegEncoder::new(&mut buf).encode_image(&img).unwrap();
+        buf
+    }
+
+    /// A JPEG whose EXIF has an orientation in IFD0 and `preview` in IFD1.
+    fn jpeg_with_exif_preview(preview: &[u8], orientation: u16) -> Vec<u8> {
+        let mut tiff: Vec<u8> = b"II".to_vec();
+        tiff.extend_from_slice(&42u16.to_le_bytes());
+        tiff.extend_from_slice(&8u32.to_le_bytes());
+        // IFD0 at 8: one entry, then the IFD1 link at 22
+        tiff.extend_from_slice(&1u16.to_le_bytes());
+        tiff.extend_from_slice(&TAG_ORIENTATION.to_le_bytes());
+        tiff.extend_from_slice(&3u16.to_le_bytes());
+        tiff.extend_from_slice(&1u32.to_le_bytes());
+        tiff.extend_from_slice(&orientation.to_le_bytes());
+        tiff.extend_from_slice(&[0, 0]);
+        tiff.extend_from_slice(&26u32.to_le_bytes());
+        // IFD1 at 26: offset + length, preview data right after at 56
+        tiff.extend_from_slice(&2u16.to_le_bytes());
+        for (tag, value) in [(TAG_THUMBNAIL_OFFSET, 56u32), (TAG_THUMBNAIL_LENGTH, preview.len() as u32)] {
+            tiff.extend_from_slice(&tag.to_le_bytes());
+            tiff.extend_from_slice(&4u16.to_le_bytes());
+            tiff.extend_from_slice(&1u32.to_le_bytes());
+            tiff.extend_from_slice(&value.to_le_bytes());
+        }
+        tiff.extend_from_slice(&0u32.to_le_bytes());
+        assert_eq!(tiff.len(), 56);
+        tiff.extend_from_slice(preview);
+
+        let main = encode_jpeg(800, 600);
+        let mut jpeg = vec![0xFF, 0xD8, 0xFF, 0xE1];
+        jpeg.extend_from_slice(&((2 + 6 + tiff.len()) as u16).to_be_bytes());
+        jpeg.extend_from_slice(b"Exif\0\0");
+        jpeg.extend_from_slice(&tiff);
+        jpeg.extend_from_slice(&main[2..]);
+        jpeg
+    }
+
+    #[test]
+    fn parse_embedded_thumbnail_reads_ifd1_preview() {
+        let preview = encode_jpeg(40, 30);
+        let (bytes, orientation) = parse_embedded_thumbnail(&jpeg_with_exif_preview(&preview, 6)).unwrap();
+        assert_eq!(bytes, preview);
+        assert_eq!(orientation, Orientation::Rotate90);
+
+        assert!(parse_embedded_thumbnail(&encode_jpeg(40, 30)).is_none());
+        assert!(parse_embedded_thumbnail(b"not a jpeg").is_none());
+    }
+
+    #[test]
+    fn generate_embedded_thumbnail_applies_orientation() {
+        let tmp = std::env::temp_dir().join(format!("terra-thumb-exif-{}.jpg", std::process::id()));
+        fs::write(&tmp, jpeg_with_exif_preview(&encode_jpeg(40, 30), 6)).unwrap();
+
+        let hash = format!("test_thumb_embedded_{}", std::process::id());
+        let (dest, placeholder) = generate_embedded_thumbnail(&tmp, &hash, 256).unwrap();
+        let decoded = ImageReader::open(&dest).unwrap().decode().unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (30, 40));
+        assert!(placeholder.is_some());
+
+        // Upgrading replaces it with a full-size render of the main image
+        let (_, placeholder) = upgrade_thumbnail(&tmp, &hash, 256).unwrap();
+        assert!(placeholder.is_some());
+        let upgraded = ImageReader::open(&dest).unwrap().decode().unwrap();
+        assert!(upgraded.width() > 30);
+
+        let _ = fs::remove_file(&tmp);
+        let _ = fs::remove_file(&dest);
+    }
+
+    #[test]
+    fn generate_embedded_thumbnail_falls_back_on_missing_or_corrupt_preview() {
+        let plain = std::env::temp_dir().join(format!("terra-thumb-noexif-{}.jpg", std::process::id()));
+        fs::write(&plain, encode_jpeg(64, 48)).unwrap();
+        let hash = format!("test_thumb_noexif_{}", std::process::id());
+        assert!(generate_embedded_thumbnail(&plain, &hash, 256).is_err());
+        assert!(!thumb_path(&hash, 256).exists());
+
+        let corrupt = std::env::temp_dir().join(format!("terra-thumb-badexif-{}.jpg", std::process::id()));
+        fs::write(&corrupt, jpeg_with_exif_preview(&[0xFF, 0xD8, 0x00, 0x01, 0x02], 1)).unwrap();
+        let hash = format!("test_thumb_badexif_{}", std::process::id());
+        assert!(generate_embedded_thumbnail(&corrupt, &hash, 256).is_err());
+        assert!(!thumb_path(&hash, 256).exists());
+
+        let _ = fs::remove_file(&plain);
+        let _ = fs::remove_file(&corrupt);
+    }
+
     #[test]
     fn generate_thumbnail_rejects_videos() {
         let tmp = std::env::temp_dir().join(format!("terra-thumb-vid-{}.mp4", std::process::id()));
@@ -129,4 +773,25 @@ mod tests {
         assert!(err.contains("video"));
         let _ = fs::remove_file(&tmp);
     }
+
+    #[test]
+    fn rotate_pixels_turns_the_file_in_its_own_format() {
+        let tmp = std::env::temp_dir().join(format!("terra-rotate-{}.png", std::process::id()));
+        let mut img: ImageBuffer<Rgb<u8>, Vec<u8>> = ImageBuffer::from_pixel(40, 20, Rgb([0, 0, 0]));
+        img.put_pixel(0, 0, Rgb([255, 0, 0]));
+        img.save(&tmp).unwrap();
+
+        rotate_pixels(&tmp, 90).unwrap();
+        let turned = image::open(&tmp).unwrap().to_rgb8();
+        assert_eq!(turned.dimensions(), (20, 40));
+        // The top-left corner ends up top-right after a clockwise turn
+        assert_eq!(turned.get_pixel(19, 0), &Rgb([255, 0, 0]));
+
+        rotate_pixels(&tmp, 270).unwrap();
+        let back = image::open(&tmp).unwrap().to_rgb8();
+        assert_eq!(back.dimensions(), (40, 20));
+        assert_eq!(back.get_pixel(0, 0), &Rgb([255, 0, 0]));
+        assert!(rotate_pixels(&tmp, 45).is_err());
+        let _ = fs::remove_file(&tmp);
+    }
 }
@@ -0,0 +1,299 @@
+//! Trip detection for album suggestions like "Trip: Mar 3–12, Lisbon".
+//!
+//! Home is the most photographed half-degree cell. A day is away when most
+//! of its geotagged photos are over AWAY_KM from home, and a trip is a run
+//! of away days that no home day or gap of more than MAX_GAP_DAYS breaks;
+//! days without GPS inside the run belong to it. A library with no GPS at
+//! all falls back to date density: bursts of shooting between quiet spells,
+//! well above the usual photos per day.
+//!
+//! A suggestion's id is its first and last day, so the same stretch of
+//! photos gets the same id on every run and stays dismissed.
+
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+
+use chrono::{Datelike, NaiveDate};
+use serde::{Deserialize, Serialize};
+
+use crate::db::TripPhoto;
+
+/// Distance from home beyond which a photo counts as taken away
+const AWAY_KM: f64 = 100.0;
+/// Grid cell size for finding home, about 50 km
+const HOME_CELL_DEGREES: f64 = 0.5;
+/// Most days without photos inside one trip
+const MAX_GAP_DAYS: i64 = 2;
+const MIN_TRIP_DAYS: i64 = 2;
+const MIN_TRIP_PHOTOS: usize = 10;
+/// Without GPS, how many times the median photos per day a burst needs
+const DENSITY_FACTOR: f64 = 3.0;
+const REPRESENTATIVE_PHOTOS: usize = 4;
+
+/// Days from 0001-01-01 to 1970-01-01
+const UNIX_EPOCH_DAYS_FROM_CE: i64 = 719_163;
+
+/// A proposed album for one trip
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TripSuggestion {
+    /// Stable across runs: "trip-<first day>-<last day>", days since 1970
+    pub id: String,
+    pub title: String,
+    /// date_taken of the first and last photo
+    pub start: i64,
+    pub end: i64,
+    /// Most photographed city and its country, once geocoded
+    pub city: Option<String>,
+    pub country: Option<String>,
+    pub photo_count: usize,
+    /// A few photos spread over the trip, for a preview strip
+    pub representative_paths: Vec<String>,
+}
+
+/// Photos taken on one local calendar day
+struct Day<'a> {
+    day: i64,
+    photos: &'a [&'a TripPhoto],
+}
+
+/// Local calendar day of a photo, as days since 1970-01-01
+fn day_of(photo: &TripPhoto) -> i64 {
+    let offset = i64::from(photo.tz_offset_minutes.unwrap_or(0)) * 60;
+    (photo.date_taken + offset).div_euclid(86_400)
+}
+
+fn distance_km((lat1, lon1): (f64, f64), (lat2, lon2): (f64, f64)) -> f64 {
+    let (dlat, dlon) = ((lat2 - lat1).to_radians(), (lon2 - lon1).to_radians());
+    let a = (dlat / 2.0).sin().powi(2) + lat1.to_radians().cos() * lat2.to_radians().cos() * (dlon / 2.0).sin().powi(2);
+    6371.0 * 2.0 * a.sqrt().asin()
+}
+
+fn position(photo: &TripPhoto) -> Option<(f64, f64)> {
+    photo.latitude.zip(photo.longitude)
+}
+
+/// Mean position of the photos in the most photographed grid cell
+fn home(photos: &[&TripPhoto]) -> Option<(f64, f64)> {
+    let mut cells: HashMap<(i64, i64), (usize, f64, f64)> = HashMap::new();
+    for (lat, lon) in photos.iter().filter_map(|p| position(p)) {
+        let key = ((lat / HOME_CELL_DEGREES).floor() as i64, (lon / HOME_CELL_DEGREES).floor() as i64);
+        let cell = cells.entry(key).or_insert((0, 0.0, 0.0));
+        *cell = (cell.0 + 1, cell.1 + lat, cell.2 + lon);
+    }
+    // Ties go to the lowest cell, so the answer doesn't depend on hash order
+    let (_, (count, lat, lon)) = cells.into_iter().max_by_key(|&(key, (count, _, _))| (count, std::cmp::Reverse(key)))?;
+    Some((lat / count as f64, lon / count as f64))
+}
+
+/// Runs of away days, as inclusive ranges of indices into `days`
+fn away_runs(days: &[Day], home: (f64, f64)) -> Vec<RangeInclusive<usize>> {
+    let mut runs = Vec::new();
+    let mut current: Option<(usize, usize)> = None;
+    for (i, day) in days.iter().enumerate() {
+        if i > 0 && day.day - days[i - 1].day > MAX_GAP_DAYS + 1 {
+            runs.extend(current.take().map(|(first, last)| first..=last));
+        }
+        let far = day.photos.iter().filter_map(|p| position(p)).filter(|&at| distance_km(at, home) > AWAY_KM).count();
+        let geotagged = day.photos.iter().filter(|p| position(p).is_some()).count();
+        if geotagged == 0 {
+            continue;
+        }
+        if far * 2 > geotagged {
+            current = Some(current.map_or((i, i), |(first, _)| (first, i)));
+        } else {
+            runs.extend(current.take().map(|(first, last)| first..=last));
+        }
+    }
+    runs.extend(current.map(|(first, last)| first..=last));
+    runs
+}
+
+/// Bursts of days busier than DENSITY_FACTOR times the median day
+fn dense_runs(days: &[Day]) -> Vec<RangeInclusive<usize>> {
+    let mut counts: Vec<usize> = days.iter().map(|d| d.photos.len()).collect();
+    counts.sort_unstable();
+    let Some(&median) = counts.get(counts.len() / 2) else {
+        return Vec::new();
+    };
+    let mut sessions = Vec::new();
+    let mut first = 0;
+    for i in 1..=days.len() {
+        if i == days.len() || days[i].day - days[i - 1].day > MAX_GAP_DAYS + 1 {
+            sessions.push(first..=i - 1);
+            first = i;
+        }
+    }
+    sessions
+        .into_iter()
+        .filter(|session| {
+            let photos: usize = days[session.clone()].iter().map(|d| d.photos.len()).sum();
+            photos as f64 / session.clone().count() as f64 >= DENSITY_FACTOR * median as f64
+        })
+        .collect()
+}
+
+fn date_of(day: i64) -> Option<NaiveDate> {
+    NaiveDate::from_num_days_from_ce_opt(i32::try_from(day + UNIX_EPOCH_DAYS_FROM_CE).ok()?)
+}
+
+/// "Mar 3–12", "Mar 30–Apr 2" or "Dec 28, 2023–Jan 3, 2024"
+fn date_range(first: i64, last: i64) -> String {
+    let (Some(from), Some(to)) = (date_of(first), date_of(last)) else {
+        return String::new();
+    };
+    if from.year() != to.year() {
+        format!("{}–{}", from.format("%b %-d, %Y"), to.format("%b %-d, %Y"))
+    } else if from.month() != to.month() {
+        format!("{}–{}", from.format("%b %-d"), to.format("%b %-d"))
+    } else {
+        format!("{}–{}", from.format("%b %-d"), to.format("%-d"))
+    }
+}
+
+fn suggestion(days: &[Day]) -> TripSuggestion {
+    let photos: Vec<&TripPhoto> = days.iter().flat_map(|d| d.photos.iter().copied()).collect();
+    let (first_day, last_day) = (days[0].day, days[days.len() - 1].day);
+
+    let mut places: HashMap<(&str, &str), usize> = HashMap::new();
+    for photo in &photos {
+        if let (Some(city), Some(country)) = (&photo.place_city, &photo.place_country) {
+            *places.entry((city.as_str(), country.as_str())).or_default() += 1;
+        }
+    }
+    let place = places.into_iter().max_by_key(|&(place, count)| (count, std::cmp::Reverse(place))).map(|(place, _)| place);
+
+    let title = match place {
+        Some((city, _)) => format!("Trip: {}, {}", date_range(first_day, last_day), city),
+        None => format!("Trip: {}", date_range(first_day, last_day)),
+    };
+    let picks = REPRESENTATIVE_PHOTOS.min(photos.len());
+    TripSuggestion {
+        id: format!("trip-{}-{}", first_day, last_day),
+        title,
+        start: photos.iter().map(|p| p.date_taken).min().unwrap_or_default(),
+        end: photos.iter().map(|p| p.date_taken).max().unwrap_or_default(),
+        city: place.map(|(city, _)| city.to_string()),
+        country: place.map(|(_, country)| country.to_string()),
+        photo_count: photos.len(),
+        representative_paths: (0..picks).map(|i| photos[i * photos.len() / picks].path.clone()).collect(),
+    }
+}
+
+/// Trips found among `photos` (dated ones, in any order), oldest first
+pub fn suggest_trips(photos: &[TripPhoto]) -> Vec<TripSuggestion> {
+    let mut sorted: Vec<&TripPhoto> = photos.iter().collect();
+    sorted.sort_by_key(|p| (day_of(p), p.date_taken));
+
+    let mut days: Vec<Day> = Vec::new();
+    let mut start = 0;
+    for i in 1..=sorted.len() {
+        if i == sorted.len() || day_of(sorted[i]) != day_of(sorted[start]) {
+            days.push(Day { day: day_of(sorted[start]), photos: &sorted[start..i] });
+            start = i;
+        }
+    }
+
+    let runs = match home(&sorted) {
+        Some(home) => away_runs(&days, home),
+        None => dense_runs(&days),
+    };
+    runs.into_iter()
+        .map(|run| &days[run])
+        .filter(|run| {
+            run[run.len() - 1].day - run[0].day + 1 >= MIN_TRIP_DAYS
+                && run.iter().map(|d| d.photos.len()).sum::<usize>() >= MIN_TRIP_PHOTOS
+        })
+        .map(suggestion)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: i64 = 86_400;
+    /// 2024-03-01T12:00:00Z
+    const MARCH_1: i64 = 1_709_294_400;
+
+    fn photo(n: usize, day: i64, at: Option<(f64, f64)>, city: Option<&str>) -> TripPhoto {
+        TripPhoto {
+            path: format!("/t/{}-{}.jpg", day, n),
+            date_taken: MARCH_1 + day * DAY + n as i64 * 60,
+            tz_offset_minutes: Some(0),
+            latitude: at.map(|a| a.0),
+            longitude: at.map(|a| a.1),
+            place_city: city.map(str::to_string),
+            place_country: city.map(|_| "Portugal".to_string()),
+        }
+    }
+
+    const HOME: (f64, f64) = (51.5, -0.12);
+    const LISBON: (f64, f64) = (38.72, -9.14);
+
+    fn days_at(photos: &mut Vec<TripPhoto>, days: RangeInclusive<i64>, per_day: usize, at: Option<(f64, f64)>, city: Option<&str>) {
+        for day in days {
+            photos.extend((0..per_day).map(|n| photo(n, day, at, city)));
+        }
+    }
+
+    #[test]
+    fn finds_a_trip_away_from_home_with_its_dominant_city() {
+        let mut photos = Vec::new();
+        days_at(&mut photos, 0..=1, 20, Some(HOME), None);
+        // Mar 3–12 in Lisbon, a day without GPS in the middle
+        days_at(&mut photos, 2..=5, 5, Some(LISBON), Some("Lisbon"));
+        days_at(&mut photos, 6..=6, 5, None, None);
+        days_at(&mut photos, 7..=11, 3, Some(LISBON), Some("Lisbon"));
+        days_at(&mut photos, 20..=25, 20, Some(HOME), None);
+
+        let trips = suggest_trips(&photos);
+        assert_eq!(trips.len(), 1);
+        let trip = &trips[0];
+        assert_eq!(trip.title, "Trip: Mar 3–12, Lisbon");
+        assert_eq!(trip.photo_count, 4 * 5 + 5 + 5 * 3);
+        assert_eq!((trip.start, trip.end), (MARCH_1 + 2 * DAY, MARCH_1 + 11 * DAY + 2 * 60));
+        assert_eq!(trip.representative_paths.len(), REPRESENTATIVE_PHOTOS);
+        assert_eq!(trip.representative_paths[0], "/t/2-0.jpg");
+
+        // Stable ids: the same trip among more photos elsewhere keeps its id
+        let mut more = photos.clone();
+        days_at(&mut more, 40..=45, 20, Some(HOME), None);
+        assert_eq!(suggest_trips(&more)[0].id, trip.id);
+    }
+
+    #[test]
+    fn gaps_and_home_days_split_trips_and_short_ones_are_dropped() {
+        let mut photos = Vec::new();
+        days_at(&mut photos, 0..=10, 20, Some(HOME), None);
+        days_at(&mut photos, 11..=12, 10, Some(LISBON), None);
+        // Four empty days split the two stays
+        days_at(&mut photos, 17..=18, 10, Some(LISBON), None);
+        days_at(&mut photos, 19..=19, 10, Some(HOME), None);
+        // A single away day
+        days_at(&mut photos, 21..=21, 30, Some(LISBON), None);
+
+        let trips = suggest_trips(&photos);
+        assert_eq!(trips.iter().map(|t| t.title.as_str()).collect::<Vec<_>>(), vec!["Trip: Mar 12–13", "Trip: Mar 18–19"]);
+        assert_ne!(trips[0].id, trips[1].id);
+    }
+
+    #[test]
+    fn without_gps_bursts_of_photos_become_trips() {
+        let mut photos = Vec::new();
+        for day in (0..=24).step_by(3).chain((40..=70).step_by(3)) {
+            days_at(&mut photos, day..=day, 2, None, None);
+        }
+        days_at(&mut photos, 30..=33, 15, None, None);
+
+        let trips = suggest_trips(&photos);
+        assert_eq!(trips.len(), 1);
+        assert_eq!(trips[0].title, "Trip: Mar 31–Apr 3");
+        assert_eq!(trips[0].photo_count, 60);
+    }
+
+    #[test]
+    fn formats_ranges_across_months_and_years() {
+        let dec_28_2023 = 19_719;
+        assert_eq!(date_range(dec_28_2023, dec_28_2023 + 6), "Dec 28, 2023–Jan 3, 2024");
+    }
+}
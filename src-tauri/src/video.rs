@@ -0,0 +1,496 @@
+//! Container metadata for videos: dimensions, duration and creation time.
+//!
+//! MP4/MOV are read with a minimal atom walker (moov > mvhd, trak > tkhd);
+//! MKV/WebM with a minimal EBML walker over the Info and Tracks elements.
+//! Only headers are read, never sample data. No database access.
+
+use std::fs::File;
+use std::io::{Read, Seek, SeekFrom};
+use std::path::Path;
+
+/// Seconds between the QuickTime epoch (1904-01-01) and the Unix epoch.
+const QUICKTIME_EPOCH_OFFSET: i64 = 2_082_844_800;
+/// Nanoseconds between the Matroska epoch (2001-01-01) and the Unix epoch.
+const MATROSKA_EPOCH_OFFSET_NS: i64 = 978_307_200 * 1_000_000_000;
+/// Largest moov atom we'll load. Real ones are a few hundred KB at most.
+const MAX_MOOV_BYTES: u64 = 64 * 1024 * 1024;
+/// QuickTime metadata key Apple writes on Live Photo videos.
+const CONTENT_IDENTIFIER_KEY: &str = "com.apple.quicktime.content.identifier";
+/// MKV/WebM put Info and Tracks ahead of the clusters, within the first few KB.
+const MKV_HEAD_BYTES: u64 = 1024 * 1024;
+
+#[derive(Debug, Default, PartialEq)]
+pub(crate) struct VideoInfo {
+    /// Display size, with rotation already applied (portrait phone videos
+    /// are stored landscape plus a rotation matrix).
+    pub width: u32,
+    pub height: u32,
+    pub duration_ms: Option<i64>,
+    /// Unix seconds, when the container records it
+    pub created_at: Option<i64>,
+}
+
+/// Read container metadata from a video file. None if the format isn't
+/// recognised or the headers are unreadable.
+pub(crate) fn read_video_info(path: &Path) -> Option<VideoInfo> {
+    let ext = path.extension()?.to_str()?.to_lowercase();
+    let mut file = File::open(path).ok()?;
+    match ext.as_str() {
+        "mp4" | "mov" | "m4v" => read_mp4(&mut file),
+        "mkv" | "webm" => {
+            let mut head = Vec::new();
+            file.take(MKV_HEAD_BYTES).read_to_end(&mut head).ok()?;
+            parse_mkv(&head)
+        }
+        _ => None,
+    }
+}
+
+// ============================================================================
+// MP4 / QuickTime
+// ============================================================================
+
+fn read_mp4<R: Read + Seek>(reader: &mut R) -> Option<VideoInfo> {
+    parse_moov(&read_moov(reader)?)
+}
+
+/// Walk top-level atoms, skipping mdat by seeking, until moov is found.
+fn read_moov<R: Read + Seek>(reader: &mut R) -> Option<Vec<u8>> {
+    let file_len = reader.seek(SeekFrom::End(0)).ok()?;
+    let mut offset = 0u64;
+    while file_len.saturating_sub(offset) >= 8 {
+        reader.seek(SeekFrom::Start(offset)).ok()?;
+        let mut header = [0u8; 16];
+        reader.read_exact(&mut header[..8]).ok()?;
+        let size32 = u32::from_be_bytes(header[..4].try_into().ok()?) as u64;
+        let kind = &header[4..8];
+        let (size, header_len) = match size32 {
+            0 => (file_len - offset, 8),
+            1 => {
+                reader.read_exact(&mut header[8..16]).ok()?;
+                (u64::from_be_bytes(header[8..16].try_into().ok()?), 16)
+            }
+            n => (n, 8),
+        };
+        if size < header_len {
+            return None;
+        }
+
+        if kind == b"moov" {
+            let body_len = size - header_len;
+            if body_len > MAX_MOOV_BYTES {
+                return None;
+            }
+            let mut body = vec![0u8; body_len as usize];
+            reader.read_exact(&mut body).ok()?;
+            return Some(body);
+        }
+        // A crafted 64-bit size can run past u64; that's no file to parse
+        offset = offset.checked_add(size)?;
+    }
+    None
+}
+
+/// The `com.apple.quicktime.content.identifier` of an iPhone MOV: the UUID
+/// that ties a Live Photo's video to its still.
+pub(crate) fn content_identifier(path: &Path) -> Option<String> {
+    let mut file = File::open(path).ok()?;
+    let moov = read_moov(&mut file)?;
+    let (_, meta) = atoms(&moov).find(|(kind, _)| *kind == b"meta")?;
+    metadata_value(meta, CONTENT_IDENTIFIER_KEY)
+}
+
+/// Look up a QuickTime metadata item (meta > keys + ilst) by key name.
+fn metadata_value(meta: &[u8], key: &str) -> Option<String> {
+    // QuickTime meta atoms have no version/flags; ISO ones do
+    let meta = if meta.get(4..8) == Some(b"hdlr".as_slice()) { meta } else { meta.get(4..)? };
+    let (_, keys) = atoms(meta).find(|(kind, _)| *kind == b"keys")?;
+    let (_, ilst) = atoms(meta).find(|(kind, _)| *kind == b"ilst")?;
+
+    // keys: version/flags, count, then (size, namespace, name) entries
+    let count = be_u32(keys, 4)?;
+    let mut at = 8;
+    let mut index = None;
+    for i in 1..=count {
+        let size = be_u32(keys, at)? as usize;
+        let name = keys.get(at + 8..at + size)?;
+        if name == key.as_bytes() {
+            index = Some(i);
+            break;
+        }
+        at += size.max(8);
+    }
+    let index = index?.to_be_bytes();
+
+    // ilst items are typed by their 1-based key index and hold a data atom:
+    // type indicator, locale, then the value
+    let (_, item) = atoms(ilst).find(|(kind, _)| *kind == &index)?;
+    let (_, data) = atoms(item).find(|(kind, _)| *kind == b"data")?;
+    let value = std::str::from_utf8(data.get(8..)?).ok()?.trim_end_matches('\0');
+    (!value.is_empty()).then(|| value.to_string())
+}
+
+/// Iterate the child atoms of a container body as (type, body) pairs.
+fn atoms(mut data: &[u8]) -> impl Iterator<Item = (&[u8], &[u8])> {
+    std::iter::from_fn(move || {
+        if data.len() < 8 {
+            return None;
+        }
+        let size32 = u32::from_be_bytes(data[..4].try_into().ok()?) as usize;
+        let (size, header_len) = match size32 {
+            0 => (data.len(), 8),
+            1 if data.len() >= 16 => (u64::from_be_bytes(data[8..16].try_into().ok()?) as usize, 16),
+            n => (n, 8),
+        };
+        if size < header_len || size > data.len() {
+            return None;
+        }
+        let kind = &data[4..8];
+        let body = &data[header_len..size];
+        data = &data[size..];
+        Some((kind, body))
+    })
+}
+
+fn be_u32(data: &[u8], at: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(at..at + 4)?.try_into().ok()?))
+}
+
+fn be_u64(data: &[u8], at: usize) -> Option<u64> {
+    Some(u64::from_be_bytes(data.get(at..at + 8)?.try_into().ok()?))
+}
+
+fn parse_moov(moov: &[u8]) -> Option<VideoInfo> {
+    let mut info = VideoInfo::default();
+    let mut found = false;
+    for (kind, body) in atoms(moov) {
+        match kind {
+            b"mvhd" => {
+                if let Some((created, duration_ms)) = parse_mvhd(body) {
+                    info.created_at = created;
+                    info.duration_ms = duration_ms;
+                    found = true;
+                }
+            }
+            b"trak" if info.width == 0 => {
+                if let Some((w, h)) = atoms(body).find(|(k, _)| *k == b"tkhd").and_then(|(_, b)| parse_tkhd(b)) {
+                    info.width = w;
+                    info.height = h;
+                    found = true;
+                }
+            }
+            _ => {}
+        }
+    }
+    found.then_some(info)
+}
+
+/// (creation time in Unix seconds, duration in ms) from a movie header.
+fn parse_mvhd(body: &[u8]) -> Option<(Option<i64>, Option<i64>)> {
+    let version = *body.first()?;
+    let (created, timescale, duration) = if version == 1 {
+        (be_u64(body, 4)?, be_u32(body, 20)?, be_u64(body, 24)?)
+    } else {
+        (be_u32(body, 4)? as u64, be_u32(body, 12)?, be_u32(body, 16)? as u64)
+    };
+
+    // Zero means "not set"; some cameras also write a bogus epoch-zero date.
+    let created = (created > QUICKTIME_EPOCH_OFFSET as u64)
+        .then(|| created as i64 - QUICKTIME_EPOCH_OFFSET);
+    let unknown_duration = duration == 0 || duration == u32::MAX as u64 || duration == u64::MAX;
+    let duration_ms = (timescale > 0 && !unknown_duration)
+        .then(|| (duration as u128 * 1000 / timescale as u128) as i64);
+    Some((created, duration_ms))
+}
+
+/// Display width/height from a track header; None for tracks without a
+/// picture (audio, timecode).
+fn parse_tkhd(body: &[u8]) -> Option<(u32, u32)> {
+    let version = *body.first()?;
+    let matrix_at = if version == 1 { 52 } else { 40 };
+    let a = be_u32(body, matrix_at)? as i32;
+    let d = be_u32(body, matrix_at + 16)? as i32;
+    // 16.16 fixed point
+    let width = be_u32(body, matrix_at + 36)? >> 16;
+    let height = be_u32(body, matrix_at + 40)? >> 16;
+    if width == 0 || height == 0 {
+        return None;
+    }
+    // A 90/270 degree rotation zeroes the matrix diagonal.
+    if a == 0 && d == 0 {
+        Some((height, width))
+    } else {
+        Some((width, height))
+    }
+}
+
+// ============================================================================
+// Matroska / WebM
+// ============================================================================
+
+const EBML_SEGMENT: u32 = 0x1853_8067;
+const EBML_INFO: u32 = 0x1549_A966;
+const EBML_TIMECODE_SCALE: u32 = 0x2A_D7B1;
+const EBML_DURATION: u32 = 0x4489;
+const EBML_DATE_UTC: u32 = 0x4461;
+const EBML_TRACKS: u32 = 0x1654_AE6B;
+const EBML_TRACK_ENTRY: u32 = 0xAE;
+const EBML_VIDEO: u32 = 0xE0;
+const EBML_PIXEL_WIDTH: u32 = 0xB0;
+const EBML_PIXEL_HEIGHT: u32 = 0xBA;
+const EBML_CLUSTER: u32 = 0x1F43_B675;
+
+/// Read an EBML variable-length integer. IDs keep their length-marker bit;
+/// sizes drop it. Returns (value, bytes used, all value bits set).
+fn read_vint(data: &[u8], keep_marker: bool) -> Option<(u64, usize, bool)> {
+    let first = *data.first()?;
+    let len = first.leading_zeros() as usize + 1;
+    if len > 8 || data.len() < len {
+        return None;
+    }
+    let mut value = if keep_marker { first as u64 } else { (first as u64) & (0xFF >> len) };
+    for byte in &data[1..len] {
+        value = (value << 8) | *byte as u64;
+    }
+    let all_ones = !keep_marker && value == (1u64 << (7 * len)) - 1;
+    Some((value, len, all_ones))
+}
+
+/// Iterate EBML elements as (id, body). An unknown-size element runs to the
+/// end of `data`; a truncated final element yields what is present.
+fn ebml_elements(mut data: &[u8]) -> impl Iterator<Item = (u32, &[u8])> {
+    std::iter::from_fn(move || {
+        let (id, id_len, _) = read_vint(data, true)?;
+        let (size, size_len, unknown) = read_vint(&data[id_len..], false)?;
+        let start = id_len + size_len;
+        let end = if unknown { data.len() } else { (start as u64 + size).min(data.len() as u64) as usize };
+        let body = &data[start..end];
+        data = &data[end..];
+        Some((id as u32, body))
+    })
+}
+
+fn ebml_uint(body: &[u8]) -> u64 {
+    body.iter().take(8).fold(0, |acc, b| (acc << 8) | *b as u64)
+}
+
+fn ebml_float(body: &[u8]) -> Option<f64> {
+    match body.len() {
+        4 => Some(f32::from_be_bytes(body.try_into().ok()?) as f64),
+        8 => Some(f64::from_be_bytes(body.try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn parse_mkv(head: &[u8]) -> Option<VideoInfo> {
+    let (_, segment) = ebml_elements(head).find(|(id, _)| *id == EBML_SEGMENT)?;
+    let mut info = VideoInfo::default();
+    let mut timecode_scale_ns = 1_000_000u64;
+    let mut duration_ticks = None;
+    let mut found = false;
+
+    for (id, body) in ebml_elements(segment) {
+        match id {
+            EBML_INFO => {
+                for (child, value) in ebml_elements(body) {
+                    match child {
+                        EBML_TIMECODE_SCALE => timecode_scale_ns = ebml_uint(value).max(1),
+                        EBML_DURATION => duration_ticks = ebml_float(value),
+                        EBML_DATE_UTC if value.len() == 8 => {
+                            let ns = i64::from_be_bytes(value.try_into().ok()?);
+                            info.created_at = Some((ns + MATROSKA_EPOCH_OFFSET_NS) / 1_000_000_000);
+                        }
+                        _ => {}
+                    }
+                }
+                found = true;
+            }
+            EBML_TRACKS if info.width == 0 => {
+                for (_, entry) in ebml_elements(body).filter(|(id, _)| *id == EBML_TRACK_ENTRY) {
+                    let Some((_, video)) = ebml_elements(entry).find(|(id, _)| *id == EBML_VIDEO) else {
+                        continue;
+                    };
+                    for (child, value) in ebml_elements(video) {
+                        match child {
+                            EBML_PIXEL_WIDTH => info.width = ebml_uint(value) as u32,
+                            EBML_PIXEL_HEIGHT => info.height = ebml_uint(value) as u32,
+                            _ => {}
+                        }
+                    }
+                    found = true;
+                    break;
+                }
+            }
+            EBML_CLUSTER => break,
+            _ => {}
+        }
+    }
+
+    info.duration_ms = duration_ticks
+        .filter(|t| t.is_finite() && *t > 0.0)
+        .map(|t| (t * timecode_scale_ns as f64 / 1_000_000.0) as i64);
+    found.then_some(info)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Cursor;
+
+    fn atom(kind: &[u8; 4], body: &[u8]) -> Vec<u8> {
+        let mut out = ((body.len() + 8) as u32).to_be_bytes().to_vec();
+        out.extend_from_slice(kind);
+        out.extend_from_slice(body);
+        out
+    }
+
+    fn mvhd_v0(created: u32, timescale: u32, duration: u32) -> Vec<u8> {
+        let mut body = vec![0u8; 100];
+        body[4..8].copy_from_slice(&created.to_be_bytes());
+        body[12..16].copy_from_slice(&timescale.to_be_bytes());
+        body[16..20].copy_from_slice(&duration.to_be_bytes());
+        atom(b"mvhd", &body)
+    }
+
+    fn tkhd_v0(width: u32, height: u32, rotated: bool) -> Vec<u8> {
+        let mut body = vec![0u8; 84];
+        let (a, b, c, d): (i32, i32, i32, i32) =
+            if rotated { (0, 0x10000, -0x10000, 0) } else { (0x10000, 0, 0, 0x10000) };
+        body[40..44].copy_from_slice(&a.to_be_bytes());
+        body[44..48].copy_from_slice(&b.to_be_bytes());
+        body[52..56].copy_from_slice(&c.to_be_bytes());
+        body[56..60].copy_from_slice(&d.to_be_bytes());
+        body[76..80].copy_from_slice(&(width << 16).to_be_bytes());
+        body[80..84].copy_from_slice(&(height << 16).to_be_bytes());
+        atom(b"tkhd", &body)
+    }
+
+    fn mp4(tracks: &[Vec<u8>]) -> Vec<u8> {
+        // 2024-06-01 12:00:00 UTC in QuickTime seconds
+        let created = (1_717_243_200 + QUICKTIME_EPOCH_OFFSET) as u32;
+        let mut moov = mvhd_v0(created, 600, 600 * 12 + 300);
+        for track in tracks {
+            moov.extend(atom(b"trak", track));
+        }
+        let mut file = atom(b"ftyp", b"isom\0\0\0\0");
+        file.extend(atom(b"mdat", &[0u8; 64]));
+        file.extend(atom(b"moov", &moov));
+        file
+    }
+
+    #[test]
+    fn reads_mp4_created_duration_and_size_after_mdat() {
+        let audio = tkhd_v0(0, 0, false);
+        let video = tkhd_v0(1920, 1080, false);
+        let info = read_mp4(&mut Cursor::new(mp4(&[audio, video]))).unwrap();
+        assert_eq!(
+            info,
+            VideoInfo { width: 1920, height: 1080, duration_ms: Some(12_500), created_at: Some(1_717_243_200) }
+        );
+    }
+
+    #[test]
+    fn rotated_mp4_reports_portrait_size() {
+        let info = read_mp4(&mut Cursor::new(mp4(&[tkhd_v0(1920, 1080, true)]))).unwrap();
+        assert_eq!((info.width, info.height), (1080, 1920));
+    }
+
+    #[test]
+    fn unset_mp4_creation_time_is_none() {
+        let moov = [mvhd_v0(0, 1000, 5000), atom(b"trak", &tkhd_v0(640, 480, false))].concat();
+        let info = parse_moov(&moov).unwrap();
+        assert_eq!(info.created_at, None);
+        assert_eq!(info.duration_ms, Some(5000));
+    }
+
+    #[test]
+    fn reads_the_live_photo_content_identifier() {
+        let uuid = "1A2B3C4D-0000-4000-8000-0123456789AB";
+        let name = CONTENT_IDENTIFIER_KEY.as_bytes();
+        let mut keys = vec![0, 0, 0, 0, 0, 0, 0, 2];
+        for key in [b"com.apple.quicktime.location.ISO6709".as_slice(), name] {
+            keys.extend(((key.len() + 8) as u32).to_be_bytes());
+            keys.extend_from_slice(b"mdta");
+            keys.extend_from_slice(key);
+        }
+        let mut data = vec![0, 0, 0, 1, 0, 0, 0, 0];
+        data.extend_from_slice(uuid.as_bytes());
+        let ilst = atom(&2u32.to_be_bytes(), &atom(b"data", &data));
+        let mut meta = atom(b"hdlr", &[0u8; 24]);
+        meta.extend(atom(b"keys", &keys));
+        meta.extend(atom(b"ilst", &ilst));
+
+        let mut moov = mvhd_v0(0, 600, 1800);
+        moov.extend(atom(b"meta", &meta));
+        let mut file = atom(b"ftyp", b"qt  \0\0\0\0");
+        file.extend(atom(b"moov", &moov));
+
+        let path = std::env::temp_dir().join(format!("terra-live-{}.mov", std::process::id()));
+        std::fs::write(&path, &file).unwrap();
+        assert_eq!(content_identifier(&path).as_deref(), Some(uuid));
+        let _ = std::fs::remove_file(&path);
+
+        let plain = std::env::temp_dir().join(format!("terra-plain-{}.mov", std::process::id()));
+        std::fs::write(&plain, mp4(&[])).unwrap();
+        assert_eq!(content_identifier(&plain), None);
+        let _ = std::fs::remove_file(&plain);
+    }
+
+    #[test]
+    fn non_mp4_bytes_are_none() {
+        assert!(read_mp4(&mut Cursor::new(b"not a video at all".to_vec())).is_none());
+    }
+
+    #[test]
+    fn atom_sizes_past_u64_stop_parsing() {
+        let mut file = atom(b"ftyp", b"isom\0\0\0\0");
+        // A 64-bit sized atom claiming nearly all of u64, then one more
+        let huge_at = file.len() as u64;
+        file.extend(1u32.to_be_bytes());
+        file.extend_from_slice(b"free");
+        file.extend((u64::MAX - huge_at + 8).to_be_bytes());
+        file.extend(atom(b"moov", &mvhd_v0(0, 600, 600)));
+        assert!(read_mp4(&mut Cursor::new(file)).is_none());
+    }
+
+    fn ebml(id: u32, body: &[u8]) -> Vec<u8> {
+        let id_bytes = id.to_be_bytes();
+        let skip = id_bytes.iter().position(|b| *b != 0).unwrap();
+        let mut out = id_bytes[skip..].to_vec();
+        // 8-byte size vint
+        out.push(0x01);
+        out.extend_from_slice(&(body.len() as u64).to_be_bytes()[1..]);
+        out.extend_from_slice(body);
+        out
+    }
+
+    #[test]
+    fn reads_mkv_dimensions_and_duration() {
+        let info_el = [
+            ebml(EBML_TIMECODE_SCALE, &1_000_000u32.to_be_bytes()),
+            ebml(EBML_DURATION, &4500.0f64.to_be_bytes()),
+        ]
+        .concat();
+        let video = [ebml(EBML_PIXEL_WIDTH, &[0x07, 0x80]), ebml(EBML_PIXEL_HEIGHT, &[0x04, 0x38])].concat();
+        let tracks = ebml(EBML_TRACK_ENTRY, &ebml(EBML_VIDEO, &video));
+        let segment_body = [ebml(EBML_INFO, &info_el), ebml(EBML_TRACKS, &tracks)].concat();
+
+        // EBML header, then a Segment with unknown size as live recorders write
+        let mut file = ebml(0x1A45_DFA3, &[]);
+        file.extend_from_slice(&EBML_SEGMENT.to_be_bytes());
+        file.push(0xFF);
+        file.extend(segment_body);
+
+        let info = parse_mkv(&file).unwrap();
+        assert_eq!(info, VideoInfo { width: 1920, height: 1080, duration_ms: Some(4500), created_at: None });
+    }
+
+    #[test]
+    fn read_vint_handles_ids_sizes_and_unknown() {
+        assert_eq!(read_vint(&[0x1A, 0x45, 0xDF, 0xA3], true), Some((0x1A45_DFA3, 4, false)));
+        assert_eq!(read_vint(&[0x82], false), Some((2, 1, false)));
+        assert_eq!(read_vint(&[0x40, 0x02], false), Some((2, 2, false)));
+        assert_eq!(read_vint(&[0xFF], false), Some((0x7F, 1, true)));
+        assert!(read_vint(&[0x00], false).is_none());
+    }
+}
@@ -0,0 +1,319 @@
+//! Background file watcher over the library root and user-registered watch
+//! folders. Events are debounced, then new and modified media files are
+//! imported and vanished ones flagged missing, as `rescan_directory` would.
+//!
+//! Folders that can't be watched (an unmounted drive) are retried
+//! periodically, and a watched folder that disappears is dropped until it
+//! comes back, so its files aren't flagged missing in the meantime.
+
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, RecvTimeoutError};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use log::{debug, error, info, warn};
+use notify::{Event, EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+use rayon::prelude::*;
+use reverse_geocoder::ReverseGeocoder;
+use serde::Serialize;
+use tauri::{AppHandle, Emitter, Manager};
+
+use crate::db::{self, DbState};
+use crate::exclusions;
+use crate::library;
+use crate::live_photos;
+use crate::media::{self, GEOCODER_LOCATIONS};
+
+/// Quiet period that ends a burst of events (e.g. a folder being copied in).
+const DEBOUNCE: Duration = Duration::from_secs(2);
+/// Changes are applied at least this often, even while events keep coming.
+const MAX_BATCH_DELAY: Duration = Duration::from_secs(10);
+/// How often folders that couldn't be watched are tried again.
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// The running watcher and the folders it is currently attached to.
+#[derive(Default)]
+pub struct WatcherState {
+    inner: Mutex<Option<ActiveWatcher>>,
+    paused: AtomicBool,
+}
+
+impl WatcherState {
+    /// Drop changes instead of applying them until the guard drops, while
+    /// something else (a library relocation) rewrites files and rows itself.
+    pub fn pause(&self) -> Paused<'_> {
+        self.paused.store(true, Ordering::SeqCst);
+        Paused(self)
+    }
+}
+
+/// See WatcherState::pause
+pub struct Paused<'a>(&'a WatcherState);
+
+impl Drop for Paused<'_> {
+    fn drop(&mut self) {
+        self.0.paused.store(false, Ordering::SeqCst);
+    }
+}
+
+struct ActiveWatcher {
+    watcher: RecommendedWatcher,
+    watched: HashSet<PathBuf>,
+}
+
+/// Payload of the `library_changed` event.
+#[derive(Serialize, Clone)]
+pub struct LibraryChanged {
+    /// Files imported or re-read because they were added or modified
+    pub updated: u32,
+    /// Rows flagged missing because their file was removed
+    pub missing: u32,
+}
+
+/// Start watching. Called once from `run()`; failure to create the watcher
+/// is logged and leaves Terra working without live updates.
+pub fn start(app: &AppHandle) {
+    let (tx, rx) = channel();
+    let watcher = match notify::recommended_watcher(tx) {
+        Ok(w) => w,
+        Err(e) => {
+            error!("Failed to start file watcher: {}", e);
+            return;
+        }
+    };
+    if let Ok(mut inner) = app.state::<WatcherState>().inner.lock() {
+        *inner = Some(ActiveWatcher { watcher, watched: HashSet::new() });
+    }
+    sync(app);
+
+    let app = app.clone();
+    std::thread::spawn(move || run_worker(&app, rx));
+}
+
+/// Attach to every folder that should be watched and is available, and
+/// detach from ones that were removed from settings or went away.
+/// Call after the library path or the watch folders change.
+pub fn sync(app: &AppHandle) {
+    let wanted = match app.state::<DbState>().0.lock() {
+        Ok(conn) => watch_roots(&conn),
+        Err(e) => {
+            error!("File watcher couldn't read settings: {}", e);
+            return;
+        }
+    };
+
+    let state = app.state::<WatcherState>();
+    let Ok(mut inner) = state.inner.lock() else {
+        return;
+    };
+    let Some(active) = inner.as_mut() else {
+        return;
+    };
+
+    let stale: Vec<PathBuf> = active
+        .watched
+        .iter()
+        .filter(|p| !wanted.contains(p) || !p.is_dir())
+        .cloned()
+        .collect();
+    for path in stale {
+        let _ = active.watcher.unwatch(&path);
+        active.watched.remove(&path);
+        debug!("Stopped watching {}", path.display());
+    }
+
+    for path in wanted {
+        if active.watched.contains(&path) || !path.is_dir() {
+            continue;
+        }
+        match active.watcher.watch(&path, RecursiveMode::Recursive) {
+            Ok(()) => {
+                info!("Watching {}", path.display());
+                active.watched.insert(path);
+            }
+            Err(e) => warn!("Can't watch {} yet: {}", path.display(), e),
+        }
+    }
+}
+
+/// The library root (when available) plus the registered watch folders.
+fn watch_roots(conn: &rusqlite::Connection) -> Vec<PathBuf> {
+    let mut roots = db::get_watch_folders(conn);
+    if let Ok(library) = db::get_library_path(conn) {
+        roots.insert(0, library);
+    }
+    roots
+}
+
+fn run_worker(app: &AppHandle, rx: Receiver<notify::Result<Event>>) {
+    let mut pending: HashSet<PathBuf> = HashSet::new();
+    let mut batch_started: Option<Instant> = None;
+    let mut last_retry = Instant::now();
+
+    loop {
+        let flush = match rx.recv_timeout(DEBOUNCE) {
+            Ok(Ok(event)) => {
+                if !matches!(event.kind, EventKind::Access(_)) {
+                    pending.extend(event.paths);
+                    batch_started.get_or_insert_with(Instant::now);
+                }
+                batch_started.is_some_and(|t| t.elapsed() >= MAX_BATCH_DELAY)
+            }
+            Ok(Err(e)) => {
+                warn!("File watcher error: {}", e);
+                false
+            }
+            Err(RecvTimeoutError::Timeout) => true,
+            Err(RecvTimeoutError::Disconnected) => break,
+        };
+
+        if flush && !pending.is_empty() {
+            if app.state::<WatcherState>().paused.load(Ordering::SeqCst) {
+                debug!("Watcher paused; dropping {} change(s)", pending.len());
+                pending.clear();
+            } else {
+                apply_changes(app, pending.drain().collect());
+            }
+            batch_started = None;
+        }
+        if last_retry.elapsed() >= RETRY_INTERVAL {
+            sync(app);
+            last_retry = Instant::now();
+        }
+    }
+}
+
+/// Import added/modified media files and flag removed ones as missing.
+fn apply_changes(app: &AppHandle, paths: Vec<PathBuf>) {
+    let db_state = app.state::<DbState>();
+    let (roots, rules) = match db_state.0.lock() {
+        Ok(conn) => (watch_roots(&conn), crate::scan_rules(&conn)),
+        Err(_) => return,
+    };
+
+    let mut files: Vec<PathBuf> = Vec::new();
+    let mut gone: Vec<PathBuf> = Vec::new();
+    for path in paths {
+        // Changes inside excluded folders (caches, app bundles) are ignored
+        let root = roots.iter().find(|root| path.starts_with(root));
+        let excluded = root
+            .and_then(|root| path.strip_prefix(root).ok())
+            .is_some_and(|relative| rules.excludes_within(relative));
+        if excluded || (path.is_symlink() && !rules.follows_symlinks()) {
+            continue;
+        }
+        if path.is_dir() {
+            // A folder moved in may not report its contents individually.
+            // Its path patterns apply from the watched root, as in a scan.
+            let walked = exclusions::walk_files_within(root.map_or(&*path, |r| r.as_path()), &path, &rules);
+            files.extend(walked.into_iter().map(|e| e.into_path()).filter(|p| crate::is_media_file(p)));
+        } else if path.is_file() {
+            if crate::is_media_file(&path) && std::fs::metadata(&path).map_or(false, |m| !rules.too_small(m.len())) {
+                files.push(path);
+            }
+        } else {
+            gone.push(path);
+        }
+    }
+    let files: HashSet<PathBuf> = files.into_iter().filter_map(|p| library::canonical_path(&p).ok().map(PathBuf::from)).collect();
+
+    // Skip files whose size and mtime already match, e.g. Terra's own moves
+    let changed: Vec<PathBuf> = match db_state.0.lock() {
+        Ok(conn) => files
+            .into_iter()
+            .filter(|p| {
+                let known = db::get_file_stats(&conn, &p.to_string_lossy()).ok().flatten();
+                match (known, media::file_stats(p)) {
+                    (Some((Some(size), Some(mtime))), Some(stats)) => stats != (size, mtime),
+                    _ => true,
+                }
+            })
+            .collect(),
+        Err(_) => return,
+    };
+
+    let geocoder = ReverseGeocoder::new(&GEOCODER_LOCATIONS);
+    let photos: Vec<crate::PhotoMetadata> = changed
+        .par_iter()
+        .filter_map(|p| media::process_image(p, Some(&geocoder)))
+        .collect();
+    let stats: Vec<(String, i64, i64)> = photos
+        .iter()
+        .filter_map(|p| media::file_stats(Path::new(&p.path)).map(|(size, mtime)| (p.path.clone(), size, mtime)))
+        .collect();
+
+    let live_pairs = live_photos::find_pairs(photos.iter().map(|p| p.path.as_str()));
+
+    let Ok(conn) = db_state.0.lock() else {
+        return;
+    };
+    let mut changes = LibraryChanged { updated: 0, missing: 0 };
+    if !photos.is_empty() {
+        match db::insert_photos_batch(&conn, &photos, "watch") {
+            Ok(failures) => {
+                for (path, err) in &failures {
+                    error!("Failed to insert {}: {}", path, err);
+                }
+                changes.updated = (photos.len() - failures.len()) as u32;
+            }
+            Err(e) => error!("Failed to save watched files: {}", e),
+        }
+        let _ = db::update_file_stats_batch(&conn, &stats);
+        crate::record_live_photo_pairs(&conn, &live_pairs);
+    }
+
+    for path in gone {
+        // The whole folder went away (drive unmounted): not a deletion
+        if !root_available(&path, &roots) {
+            continue;
+        }
+        let path_str = path.to_string_lossy().to_string();
+        let mut prefix = path_str.clone();
+        prefix.push(std::path::MAIN_SEPARATOR);
+        let under: Vec<String> = db::get_file_stats_under(&conn, &prefix)
+            .map(|m| m.into_keys().filter(|p| !Path::new(p).exists()).collect())
+            .unwrap_or_default();
+        for missing in std::iter::once(path_str).chain(under) {
+            if db::flag_photo_missing(&conn, &missing).unwrap_or(false) {
+                changes.missing += 1;
+            }
+        }
+    }
+    drop(conn);
+
+    if changes.updated == 0 && changes.missing == 0 {
+        return;
+    }
+    info!("Watcher: {} files imported or updated, {} missing", changes.updated, changes.missing);
+    let _ = app.emit("library_changed", changes.clone());
+    if changes.updated > 0 {
+        crate::spawn_post_import_jobs(app);
+    }
+}
+
+/// True if `path` is under a watched root that still exists. A vanished
+/// root means an unmount, not that its files were deleted.
+fn root_available(path: &Path, roots: &[PathBuf]) -> bool {
+    roots
+        .iter()
+        .filter(|root| path.starts_with(root))
+        .any(|root| root.is_dir())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn removals_under_a_vanished_root_are_not_deletions() {
+        let present = std::env::temp_dir();
+        let unmounted = PathBuf::from("/Volumes/terra-test-missing-drive");
+        let roots = vec![present.clone(), unmounted.clone()];
+
+        assert!(root_available(&present.join("a.jpg"), &roots));
+        assert!(!root_available(&unmounted.join("DCIM").join("b.jpg"), &roots));
+        assert!(!root_available(Path::new("/elsewhere/c.jpg"), &roots));
+    }
+}
@@ -0,0 +1,193 @@
+//! Writing an album out as a ZIP archive. Photos are stored, not
+//! compressed (they are compressed already), under names that lead with
+//! when they were taken so the archive sorts by date.
+//!
+//! No database access; `export_album_zip` in `lib.rs` gathers the photos.
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use chrono::{Datelike, NaiveDateTime, Timelike};
+use log::warn;
+
+use crate::media;
+use crate::{ExportFailure, PhotoMetadata, ZipExportResult};
+
+/// Stream `photos` into a ZIP at `dest` under `folder/`. The archive is
+/// written beside `dest` and renamed into place once complete, so a failed
+/// or cancelled export never leaves half an archive.
+pub(crate) fn write_album_zip(
+    photos: &[PhotoMetadata],
+    folder: &str,
+    dest: &Path,
+    cancelled: &AtomicBool,
+    mut progress: impl FnMut(u32, &str, &'static str),
+) -> io::Result<ZipExportResult> {
+    let partial = dest.with_extension("zip.partial");
+    let file = fs::File::create(&partial)?;
+    let mut zip = zip::ZipWriter::new(io::BufWriter::new(file));
+    let mut result = ZipExportResult::default();
+    let mut names = HashSet::new();
+
+    let written = (|| -> io::Result<()> {
+        for (i, photo) in photos.iter().enumerate() {
+            if cancelled.load(Ordering::Relaxed) {
+                result.cancelled = true;
+                return Ok(());
+            }
+            let current = i as u32 + 1;
+            let mut source = match fs::File::open(&photo.path) {
+                Ok(source) => source,
+                Err(e) => {
+                    warn!("Left {} out of the archive: {}", photo.path, e);
+                    result.skipped.push(ExportFailure { path: photo.path.clone(), error: format!("Source file missing: {}", e) });
+                    progress(current, &photo.path, "skipped");
+                    continue;
+                }
+            };
+            let size = source.metadata()?.len();
+            let name = zip_member_name(folder, photo, &mut names);
+            let mut options = zip::write::SimpleFileOptions::default()
+                .compression_method(zip::CompressionMethod::Stored)
+                .large_file(size >= u64::from(u32::MAX));
+            if let Some(modified) = zip_timestamp(photo) {
+                options = options.last_modified_time(modified);
+            }
+            zip.start_file(name, options).map_err(io::Error::other)?;
+            result.bytes += io::copy(&mut source, &mut zip)?;
+            result.added += 1;
+            progress(current, &photo.path, "exported");
+        }
+        Ok(())
+    })();
+
+    let finished = written.and_then(|_| zip.finish().map_err(io::Error::other)).and_then(|mut out| io::Write::flush(&mut out));
+    match finished {
+        Ok(()) if !result.cancelled => {
+            fs::rename(&partial, dest)?;
+            result.path = dest.to_string_lossy().to_string();
+            Ok(result)
+        }
+        Ok(()) => {
+            let _ = fs::remove_file(&partial);
+            Ok(result)
+        }
+        Err(e) => {
+            let _ = fs::remove_file(&partial);
+            Err(e)
+        }
+    }
+}
+
+/// When a photo was taken on the clock where it was taken; the names and
+/// times in the archive should read as they do in the library.
+fn wall_clock(photo: &PhotoMetadata) -> Option<NaiveDateTime> {
+    if photo.date_taken == media::UNKNOWN_DATE {
+        return None;
+    }
+    media::exif_wall_clock(photo.date_taken, photo.tz_offset_minutes).map(|(date, _)| date)
+}
+
+/// `Album/2024-06-01_143045_IMG_0001.jpg`, unique among `taken`. Undated
+/// photos keep their plain name.
+fn zip_member_name(folder: &str, photo: &PhotoMetadata, taken: &mut HashSet<String>) -> String {
+    let prefix = wall_clock(photo).map(|date| format!("{}_", date.format("%Y-%m-%d_%H%M%S"))).unwrap_or_default();
+    let path = Path::new(&photo.name);
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = path.extension().map(|e| format!(".{}", e.to_string_lossy())).unwrap_or_default();
+    let mut name = format!("{}/{}{}{}", folder, prefix, stem, ext);
+    let mut counter = 1;
+    while !taken.insert(name.to_lowercase()) {
+        name = format!("{}/{}{}_{}{}", folder, prefix, stem, counter, ext);
+        counter += 1;
+    }
+    name
+}
+
+/// A photo's date as a ZIP modification time; ZIP can only hold 1980-2107.
+fn zip_timestamp(photo: &PhotoMetadata) -> Option<zip::DateTime> {
+    let date = wall_clock(photo)?;
+    zip::DateTime::from_date_and_time(
+        u16::try_from(date.year()).ok()?,
+        date.month() as u8,
+        date.day() as u8,
+        date.hour() as u8,
+        date.minute() as u8,
+        date.second() as u8,
+    )
+    .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn photo(path: &str, name: &str, date_taken: i64) -> PhotoMetadata {
+        PhotoMetadata { path: path.to_string(), name: name.to_string(), date_taken, ..Default::default() }
+    }
+
+    #[test]
+    fn member_names_lead_with_the_local_time_taken() {
+        let mut taken = HashSet::new();
+        // 2024-06-01 12:30:45 UTC, taken two hours east of it
+        let mut dated = photo("/a/IMG_0001.jpg", "IMG_0001.jpg", 1_717_245_045);
+        dated.tz_offset_minutes = Some(120);
+        assert_eq!(zip_member_name("Trip", &dated, &mut taken), "Trip/2024-06-01_143045_IMG_0001.jpg");
+        let stamp = zip_timestamp(&dated).unwrap();
+        assert_eq!((stamp.year(), stamp.month(), stamp.day(), stamp.hour()), (2024, 6, 1, 14));
+
+        // Same name from another folder, differing only in case
+        assert_eq!(zip_member_name("Trip", &dated, &mut taken), "Trip/2024-06-01_143045_IMG_0001_1.jpg");
+        let mut shouting = dated.clone();
+        shouting.name = "img_0001.JPG".to_string();
+        assert_eq!(zip_member_name("Trip", &shouting, &mut taken), "Trip/2024-06-01_143045_img_0001_1.JPG");
+
+        let undated = photo("/a/scan.png", "scan.png", media::UNKNOWN_DATE);
+        assert_eq!(zip_member_name("Trip", &undated, &mut taken), "Trip/scan.png");
+        assert!(zip_timestamp(&undated).is_none());
+    }
+
+    #[test]
+    fn archives_skip_missing_files_and_vanish_when_cancelled() {
+        let dir = std::env::temp_dir().join(format!("terra-zip-export-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let source = dir.join("a.jpg");
+        fs::write(&source, b"jpeg bytes").unwrap();
+        let mut present = photo(&source.to_string_lossy(), "a.jpg", 1_717_245_045);
+        present.tz_offset_minutes = Some(0);
+        let missing = photo(&dir.join("gone.jpg").to_string_lossy(), "gone.jpg", 1_717_245_045);
+        let photos = [present, missing];
+
+        let dest = dir.join("Trip.zip");
+        let mut reported = Vec::new();
+        let result = write_album_zip(&photos, "Trip", &dest, &AtomicBool::new(false), |n, _, status| {
+            reported.push((n, status));
+        })
+        .unwrap();
+        assert_eq!(result.added, 1);
+        assert_eq!(result.bytes, 10);
+        assert_eq!(result.skipped.len(), 1);
+        assert_eq!(reported, vec![(1, "exported"), (2, "skipped")]);
+        assert!(!dir.join("Trip.zip.partial").exists());
+
+        let mut archive = zip::ZipArchive::new(fs::File::open(&dest).unwrap()).unwrap();
+        assert_eq!(archive.len(), 1);
+        let mut member = archive.by_index(0).unwrap();
+        assert_eq!(member.name(), "Trip/2024-06-01_123045_a.jpg");
+        let mut contents = Vec::new();
+        io::Read::read_to_end(&mut member, &mut contents).unwrap();
+        assert_eq!(contents, b"jpeg bytes");
+
+        let cancelled_dest = dir.join("Cancelled.zip");
+        let result = write_album_zip(&photos, "Trip", &cancelled_dest, &AtomicBool::new(true), |_, _, _| {}).unwrap();
+        assert!(result.cancelled);
+        assert!(result.path.is_empty());
+        assert!(!cancelled_dest.exists());
+        assert!(!dir.join("Cancelled.zip.partial").exists());
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
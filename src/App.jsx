@@ -1,4 +1,4 @@
-import { useState, useCallback, useRef } from 'react';
+import { useState, useEffect, useCallback, useRef } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { Copy, MonitorSmartphone } from 'lucide-react';
 
@@ -15,6 +15,7 @@ import ScanModal from './components/ScanModal';
 import DuplicateReviewGallery from './components/DuplicateReviewGallery';
 import ScreenshotReviewGallery from './components/ScreenshotReviewGallery';
 import ArchiveView from './components/ArchiveView';
+import HiddenView from './components/HiddenView';
 import SettingsModal from './components/SettingsModal';
 import { TagCreateModal, TagAssignPopover } from './components/TagManager';
 import StorageAnalytics from './components/StorageAnalytics';
@@ -33,18 +34,23 @@ const AppLayout = () => {
     albums, handleCreateAlbum, handleAddToAlbum,
     tags, selectedTagIds, setSelectedTagIds, loadTags,
     setStatusWithTimeout, loadPhotosFromDatabase,
+    hideScreenshots, toggleHideScreenshots,
     handleUploadPhotos, handleToggleFavorite, handleDeleteSelected,
-    loadAlbums,
+    loadAlbums, librarySummary,
     // cleanup
     showDuplicateScan, showScreenshotScan,
     showDuplicateReview, setShowDuplicateReview,
     showScreenshotReview, setShowScreenshotReview,
     showArchive, setShowArchive,
+    showHidden, setShowHidden, hiddenPhotos,
     scanProgress, scanPhase, duplicateGroups, screenshots, archivedPhotos,
     handleScanForDuplicates, handleDuplicateScanComplete,
     handleScanForScreenshots, handleScreenshotScanComplete,
     handleArchivePhotos, handleRestorePhotos, handleOpenArchive,
     refreshDuplicateGroups, refreshScreenshots, loadArchivedPhotos,
+    lastResolution, handleResolveDuplicates, handleUndoResolveDuplicates,
+    handleNotScreenshot,
+    handleOpenHidden, handleHidePhotos, handleUnhidePhotos, loadHiddenPhotos,
   } = useAppContext();
 
   const {
@@ -104,12 +110,27 @@ const AppLayout = () => {
 
   const onModalReveal = useCallback(async (photo) => {
     try {
-      await invoke('reveal_in_finder', { path: photo.path });
+      await invoke('reveal_in_file_manager', { path: photo.path });
     } catch (err) {
       console.error('Failed to reveal in Finder:', err);
     }
   }, []);
 
+  // Saves from the other app come back as photo_changed events
+  const onModalEditExternally = useCallback(async (photo) => {
+    try {
+      await invoke('open_with_default_app', { path: photo.path });
+      await invoke('watch_for_external_edit', { path: photo.path });
+    } catch (err) {
+      console.error('Failed to open in external app:', err);
+    }
+  }, []);
+
+  // Keep the open photo in step with edits made to it (rotation, external apps)
+  useEffect(() => {
+    setSelectedPhoto((current) => (current && photos.find((p) => p.path === current.path)) || current);
+  }, [photos]);
+
   const onAddToAlbum = async (albumId) => {
     try {
       await handleAddToAlbum(albumId, selectedPhotos, (count) => {
@@ -122,6 +143,26 @@ const AppLayout = () => {
     }
   };
 
+  const onRotateSelected = async () => {
+    try {
+      const rotated = await invoke('rotate_photos', { paths: Array.from(selectedPhotos), degrees: 90 });
+      setStatusWithTimeout(`Rotated ${rotated.length} photo${rotated.length === 1 ? '' : 's'}`);
+    } catch (err) {
+      console.error('Failed to rotate photos:', err);
+    }
+  };
+
+  const onHideSelected = async () => {
+    await handleHidePhotos(Array.from(selectedPhotos));
+    clearSelection();
+    loadAlbums();
+  };
+
+  const onUnhide = async (paths) => {
+    await handleUnhidePhotos(paths);
+    loadAlbums();
+  };
+
   const onDeleteSelected = () => {
     handleDeleteSelected(selectedPhotos, clearSelection, loadAlbums, loadLocations);
   };
@@ -132,6 +173,7 @@ const AppLayout = () => {
 
       <Sidebar
         photos={photos}
+        librarySummary={librarySummary}
         viewMode={viewMode}
         setViewMode={setViewMode}
         searchQuery={searchQuery}
@@ -153,7 +195,10 @@ const AppLayout = () => {
         onSettings={() => setShowSettings(true)}
         onScanDuplicates={handleScanForDuplicates}
         onScanScreenshots={handleScanForScreenshots}
+        hideScreenshots={hideScreenshots}
+        onToggleHideScreenshots={toggleHideScreenshots}
         onOpenArchive={handleOpenArchive}
+        onOpenHidden={handleOpenHidden}
         onOpenTerraForm={() => setShowTerraForm(true)}
         onOpenStorageAnalytics={() => setShowStorageAnalytics(true)}
       />
@@ -178,6 +223,8 @@ const AppLayout = () => {
         selectedPhotos={selectedPhotos}
         onAddToAlbum={() => setShowAddToAlbum(true)}
         onTagAssign={() => setShowTagAssign(true)}
+        onRotate={onRotateSelected}
+        onHide={onHideSelected}
         onDelete={onDeleteSelected}
         onCancel={clearSelection}
         onEnterSelectionMode={() => setSelectionMode(true)}
@@ -192,6 +239,7 @@ const AppLayout = () => {
         onArchive={onModalArchive}
         onDelete={onModalDelete}
         onReveal={onModalReveal}
+        onEditExternally={onModalEditExternally}
         onAddToAlbum={() => { /* TODO(phase-A): wire single-photo add-to-album from modal */ }}
         onTagAssign={() => { /* TODO(phase-A): wire single-photo tag-assign from modal */ }}
       />
@@ -203,7 +251,7 @@ const AppLayout = () => {
       <AddToAlbumModal
         isOpen={showAddToAlbum}
         onClose={() => setShowAddToAlbum(false)}
-        albums={albums}
+        albums={albums.filter(album => album.kind !== 'smart')}
         onSelect={onAddToAlbum}
       />
 
@@ -229,6 +277,9 @@ const AppLayout = () => {
         duplicateGroups={duplicateGroups}
         onArchive={handleArchivePhotos}
         onRefresh={refreshDuplicateGroups}
+        onResolve={handleResolveDuplicates}
+        onUndoResolve={handleUndoResolveDuplicates}
+        canUndo={!!lastResolution}
       />
       <ScreenshotReviewGallery
         isOpen={showScreenshotReview}
@@ -236,6 +287,7 @@ const AppLayout = () => {
         screenshots={screenshots}
         onArchive={handleArchivePhotos}
         onRefresh={refreshScreenshots}
+        onNotScreenshot={handleNotScreenshot}
       />
       <ArchiveView
         isOpen={showArchive}
@@ -244,6 +296,13 @@ const AppLayout = () => {
         onRestore={handleRestorePhotos}
         onRefresh={loadArchivedPhotos}
       />
+      <HiddenView
+        isOpen={showHidden}
+        onClose={() => setShowHidden(false)}
+        hiddenPhotos={hiddenPhotos}
+        onUnhide={onUnhide}
+        onRefresh={loadHiddenPhotos}
+      />
 
       <SettingsModal
         isOpen={showSettings}
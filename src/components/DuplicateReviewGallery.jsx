@@ -1,8 +1,9 @@
 import { useState } from 'react';
 import { convertFileSrc } from '@tauri-apps/api/core';
-import { X, Copy, ChevronDown, CheckCircle, RotateCcw, Archive } from 'lucide-react';
+import { X, Copy, ChevronDown, CheckCircle, RotateCcw, Archive, Undo2 } from 'lucide-react';
+import { formatBytes } from '../utils/photoHelpers';
 
-const DuplicateReviewGallery = ({ isOpen, onClose, duplicateGroups, onArchive, onRefresh }) => {
+const DuplicateReviewGallery = ({ isOpen, onClose, duplicateGroups, onArchive, onRefresh, onResolve, onUndoResolve, canUndo }) => {
   const [selectedGroup, setSelectedGroup] = useState(null);
   const [selectedForArchive, setSelectedForArchive] = useState(new Set());
   const [showConfirm, setShowConfirm] = useState(false);
@@ -55,6 +56,15 @@ const DuplicateReviewGallery = ({ isOpen, onClose, duplicateGroups, onArchive, o
                 Archive {selectedForArchive.size} photos
               </button>
             )}
+            {canUndo && (
+              <button
+                onClick={onUndoResolve}
+                className="flex items-center gap-2 px-4 py-2 bg-white/5 hover:bg-white/10 text-white/60 hover:text-white rounded-lg text-sm font-medium transition-colors"
+              >
+                <Undo2 size={16} />
+                Undo keep
+              </button>
+            )}
             <button
               onClick={onRefresh}
               className="p-2 rounded-lg bg-white/5 hover:bg-white/10 text-white/60 hover:text-white transition-colors"
@@ -138,10 +148,12 @@ const DuplicateReviewGallery = ({ isOpen, onClose, duplicateGroups, onArchive, o
                   {selectedGroup === group.group_id && (
                     <div className="mt-4 pt-4 border-t border-white/10">
                       <p className="text-xs text-white/40 mb-3">
-                        Click photos to select for archiving. Keep at least one!
+                        Click photos to select for archiving, or keep one and move the rest to trash. Albums, tags and favorites carry over to the photo you keep.
                       </p>
                       <div className="grid grid-cols-2 md:grid-cols-3 lg:grid-cols-4 gap-4">
-                        {group.photos.map((photo) => (
+                        {group.photos.map((photo, idx) => {
+                          const member = group.members?.[idx];
+                          return (
                           <div
                             key={photo.path}
                             onClick={() => togglePhotoSelection(photo.path)}
@@ -161,14 +173,44 @@ const DuplicateReviewGallery = ({ isOpen, onClose, duplicateGroups, onArchive, o
                                 <Archive size={32} className="text-red-400" />
                               </div>
                             )}
+                            <div className="absolute top-2 left-2 flex flex-col items-start gap-1">
+                              {group.suggested_keeper === photo.path && (
+                                <div className="px-1.5 py-0.5 rounded bg-emerald-500/80 text-[10px] font-medium text-white">
+                                  Suggested
+                                </div>
+                              )}
+                              {group.sharpest === photo.path && (
+                                <div className="px-1.5 py-0.5 rounded bg-sky-500/80 text-[10px] font-medium text-white">
+                                  Sharpest
+                                </div>
+                              )}
+                            </div>
+                            {onResolve && (
+                              <button
+                                onClick={(e) => {
+                                  e.stopPropagation();
+                                  onResolve(group.photos.map((p) => p.path), photo.path);
+                                }}
+                                className="absolute top-2 right-2 px-2 py-1 rounded bg-black/70 hover:bg-emerald-500 text-[10px] font-medium text-white transition-colors"
+                              >
+                                Keep this
+                              </button>
+                            )}
                             <div className="absolute bottom-0 left-0 right-0 bg-black/80 p-2">
                               <p className="text-xs text-white truncate">{photo.name}</p>
                               <p className="text-[10px] text-white/60">
                                 {photo.width}x{photo.height} • {new Date(photo.date_taken * 1000).toLocaleDateString()}
+                                {member?.file_size != null && ` • ${formatBytes(member.file_size)}`}
                               </p>
+                              {member?.albums.length > 0 && (
+                                <p className="text-[10px] text-white/40 truncate" title={member.albums.join(', ')}>
+                                  In {member.albums.join(', ')}
+                                </p>
+                              )}
                             </div>
                           </div>
-                        ))}
+                          );
+                        })}
                       </div>
                     </div>
                   )}
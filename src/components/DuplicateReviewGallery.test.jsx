@@ -38,6 +38,40 @@ describe('DuplicateReviewGallery', () => {
     expect(screen.getByText('Exact Match')).toBeInTheDocument();
   });
 
+  it('keeps the chosen copy of a group', async () => {
+    const onResolve = vi.fn();
+    const groups = [{
+      ...mockGroups[0],
+      suggested_keeper: '/b.jpg',
+      members: [
+        { path: '/a.jpg', file_size: 1024, width: 1920, height: 1080, albums: [], metadata_fields: 1 },
+        { path: '/b.jpg', file_size: 2048, width: 1920, height: 1080, albums: ['Trip'], metadata_fields: 2 },
+      ],
+    }];
+    render(
+      <DuplicateReviewGallery isOpen={true} onClose={vi.fn()} duplicateGroups={groups} onArchive={vi.fn()} onRefresh={vi.fn()} onResolve={onResolve} />
+    );
+    const user = userEvent.setup();
+    await user.click(screen.getByText('2 photos'));
+
+    expect(screen.getByText('Suggested')).toBeInTheDocument();
+    expect(screen.getByText('In Trip')).toBeInTheDocument();
+    await user.click(screen.getAllByText('Keep this')[1]);
+    expect(onResolve).toHaveBeenCalledWith(['/a.jpg', '/b.jpg'], '/b.jpg');
+  });
+
+  it('marks the sharpest frame of a similar group', async () => {
+    const groups = [{ ...mockGroups[0], group_type: 'similar', suggested_keeper: '/a.jpg', sharpest: '/b.jpg' }];
+    render(
+      <DuplicateReviewGallery isOpen={true} onClose={vi.fn()} duplicateGroups={groups} onArchive={vi.fn()} onRefresh={vi.fn()} />
+    );
+    const user = userEvent.setup();
+    await user.click(screen.getByText('2 photos'));
+
+    expect(screen.getByText('Sharpest').closest('[class*="aspect-square"]')).toHaveTextContent('b.jpg');
+    expect(screen.getByText('Suggested')).toBeInTheDocument();
+  });
+
   it('calls onClose when close button clicked', async () => {
     const onClose = vi.fn();
     render(
@@ -0,0 +1,106 @@
+import { useState } from 'react';
+import { convertFileSrc } from '@tauri-apps/api/core';
+import { X, RotateCcw, Eye, EyeOff } from 'lucide-react';
+
+const HiddenView = ({ isOpen, onClose, hiddenPhotos, onUnhide, onRefresh }) => {
+  const [selectedForUnhide, setSelectedForUnhide] = useState(new Set());
+
+  if (!isOpen) return null;
+
+  const togglePhotoSelection = (path) => {
+    const newSet = new Set(selectedForUnhide);
+    if (newSet.has(path)) {
+      newSet.delete(path);
+    } else {
+      newSet.add(path);
+    }
+    setSelectedForUnhide(newSet);
+  };
+
+  const handleUnhide = () => {
+    onUnhide(Array.from(selectedForUnhide));
+    setSelectedForUnhide(new Set());
+  };
+
+  return (
+    <div className="fixed inset-0 z-50 bg-black/95 backdrop-blur-md animate-in fade-in duration-200 overflow-hidden">
+      <div className="h-full flex flex-col">
+        <div className="flex items-center justify-between p-6 border-b border-white/10">
+          <div>
+            <h2 className="text-2xl font-bold text-white flex items-center gap-3">
+              <EyeOff size={24} className="text-white/60" />
+              Hidden
+            </h2>
+            <p className="text-white/60 text-sm mt-1">
+              {hiddenPhotos.length} hidden photos • Not shown in the library, albums or search
+            </p>
+          </div>
+          <div className="flex items-center gap-3">
+            {selectedForUnhide.size > 0 && (
+              <button
+                onClick={handleUnhide}
+                className="flex items-center gap-2 px-4 py-2 bg-emerald-500/20 hover:bg-emerald-500/30 text-emerald-400 rounded-lg text-sm font-medium transition-colors border border-emerald-500/30"
+              >
+                <Eye size={16} />
+                Unhide {selectedForUnhide.size}
+              </button>
+            )}
+            <button
+              onClick={onRefresh}
+              className="p-2 rounded-lg bg-white/5 hover:bg-white/10 text-white/60 hover:text-white transition-colors"
+              title="Refresh"
+            >
+              <RotateCcw size={20} />
+            </button>
+            <button
+              onClick={onClose}
+              className="p-2 rounded-lg bg-white/5 hover:bg-white/10 text-white/60 hover:text-white transition-colors"
+            >
+              <X size={20} />
+            </button>
+          </div>
+        </div>
+
+        <div className="flex-1 overflow-y-auto p-6">
+          {hiddenPhotos.length === 0 ? (
+            <div className="h-full flex flex-col items-center justify-center text-white/40">
+              <EyeOff size={64} className="mb-4 text-white/20" />
+              <p className="text-xl font-medium text-white">No hidden photos</p>
+              <p className="text-sm mt-2">Photos you hide will appear here.</p>
+            </div>
+          ) : (
+            <div className="grid grid-cols-2 md:grid-cols-4 lg:grid-cols-6 gap-4">
+              {hiddenPhotos.map((photo) => (
+                <div
+                  key={photo.path}
+                  onClick={() => togglePhotoSelection(photo.path)}
+                  className={`relative aspect-square rounded-lg overflow-hidden cursor-pointer border-2 transition-all ${
+                    selectedForUnhide.has(photo.path)
+                      ? 'border-emerald-500 ring-2 ring-emerald-500/30'
+                      : 'border-transparent hover:border-white/30'
+                  }`}
+                >
+                  <img
+                    src={convertFileSrc(photo.path)}
+                    alt={photo.name}
+                    className="w-full h-full object-cover"
+                  />
+                  {selectedForUnhide.has(photo.path) && (
+                    <div className="absolute inset-0 bg-emerald-500/20 flex items-center justify-center">
+                      <Eye size={32} className="text-emerald-400" />
+                    </div>
+                  )}
+                  <div className="absolute bottom-0 left-0 right-0 bg-black/80 p-2">
+                    <p className="text-xs text-white truncate">{photo.name}</p>
+                  </div>
+                </div>
+              ))}
+            </div>
+          )}
+        </div>
+      </div>
+    </div>
+  );
+};
+
+export default HiddenView;
@@ -0,0 +1,38 @@
+import { describe, it, expect, vi } from 'vitest';
+import { render, screen } from '@testing-library/react';
+import userEvent from '@testing-library/user-event';
+import HiddenView from './HiddenView';
+
+const mockHidden = [
+  { path: '/a.jpg', name: 'a.jpg' },
+  { path: '/b.jpg', name: 'b.jpg' },
+];
+
+describe('HiddenView', () => {
+  it('returns null when not open', () => {
+    const { container } = render(
+      <HiddenView isOpen={false} onClose={vi.fn()} hiddenPhotos={[]} onUnhide={vi.fn()} onRefresh={vi.fn()} />
+    );
+    expect(container.innerHTML).toBe('');
+  });
+
+  it('shows empty message', () => {
+    render(
+      <HiddenView isOpen={true} onClose={vi.fn()} hiddenPhotos={[]} onUnhide={vi.fn()} onRefresh={vi.fn()} />
+    );
+    expect(screen.getByText('No hidden photos')).toBeInTheDocument();
+  });
+
+  it('unhides the selected photos', async () => {
+    const onUnhide = vi.fn();
+    render(
+      <HiddenView isOpen={true} onClose={vi.fn()} hiddenPhotos={mockHidden} onUnhide={onUnhide} onRefresh={vi.fn()} />
+    );
+    expect(screen.getByText(/2 hidden photos/)).toBeInTheDocument();
+
+    const user = userEvent.setup();
+    await user.click(screen.getByAltText('b.jpg'));
+    await user.click(screen.getByText('Unhide 1'));
+    expect(onUnhide).toHaveBeenCalledWith(['/b.jpg']);
+  });
+});
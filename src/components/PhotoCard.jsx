@@ -1,16 +1,24 @@
-import { useContext } from 'react';
-import { CheckCircle, Heart, Play } from 'lucide-react';
+import { useContext, useState } from 'react';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
+import { CheckCircle, HardDrive, Heart, Play } from 'lucide-react';
 import { AppContext } from '../contexts/AppContext';
-import { getThumbnailUrl } from '../utils/photoHelpers';
+import { getThumbnailUrl, isRawFormat } from '../utils/photoHelpers';
+import { blurhashToDataUrl } from '../utils/blurhash';
+
+const BROKEN_IMAGE = 'data:image/svg+xml,<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100" viewBox="0 0 24 24" fill="none" stroke="%23666" stroke-width="1"><rect x="3" y="3" width="18" height="18" rx="2"/><circle cx="8.5" cy="8.5" r="1.5"/><path d="m21 15-5-5L5 21"/></svg>';
 
 const PhotoCard = ({ photo, isSelected, selectionMode, onPhotoClick, onToggleSelection }) => {
   // Tolerate missing provider so isolated component tests don't need to wrap in AppProvider.
   const ctx = useContext(AppContext);
   const cardSrc = getThumbnailUrl(photo, ctx?.thumbCacheRoot ?? null);
+  // Painted under the thumbnail until it loads; absent until the backend has computed them
+  const [loaded, setLoaded] = useState(false);
+  const placeholder = loaded ? null : blurhashToDataUrl(photo.blurhash);
 
   return (
     <div
       onClick={(e) => onPhotoClick(photo, e)}
+      style={photo.dominant_color ? { backgroundColor: photo.dominant_color } : undefined}
       className={`group relative aspect-square rounded-lg overflow-hidden cursor-pointer bg-white/5 border transition-all duration-300 hover:shadow-[0_0_30px_rgba(52,211,153,0.1)] ${isSelected ? 'border-emerald-500 ring-2 ring-emerald-500/50' : 'border-white/5 hover:border-white/30'}`}
     >
       <div
@@ -28,14 +36,32 @@ const PhotoCard = ({ photo, isSelected, selectionMode, onPhotoClick, onToggleSel
         <CheckCircle size={20} fill={isSelected ? "currentColor" : "none"} />
       </div>
 
+      {placeholder && (
+        <div
+          aria-hidden="true"
+          className="absolute inset-0 bg-cover bg-center"
+          style={{ backgroundImage: `url(${placeholder})` }}
+        />
+      )}
+
       <img
         src={cardSrc}
         alt={photo.name}
         loading="lazy"
-        className={`w-full h-full object-cover transition-transform duration-700 group-hover:scale-110 opacity-80 group-hover:opacity-100 ${isSelected ? 'scale-95' : ''}`}
+        onLoad={() => setLoaded(true)}
+        className={`relative w-full h-full object-cover transition-transform duration-700 group-hover:scale-110 ${photo.is_offline ? 'grayscale opacity-40' : 'opacity-80 group-hover:opacity-100'} ${isSelected ? 'scale-95' : ''}`}
         onError={(e) => {
-          e.target.onerror = null;
-          e.target.src = 'data:image/svg+xml,<svg xmlns="http://www.w3.org/2000/svg" width="100" height="100" viewBox="0 0 24 24" fill="none" stroke="%23666" stroke-width="1"><rect x="3" y="3" width="18" height="18" rx="2"/><circle cx="8.5" cy="8.5" r="1.5"/><path d="m21 15-5-5L5 21"/></svg>';
+          const img = e.target;
+          // An edited render isn't cached until something asks for it
+          if (photo.edited_key && !img.dataset.rendering) {
+            img.dataset.rendering = 'true';
+            invoke('get_thumbnail', { path: photo.path })
+              .then((rendered) => { img.src = convertFileSrc(rendered); })
+              .catch(() => { img.src = BROKEN_IMAGE; });
+            return;
+          }
+          img.onerror = null;
+          img.src = BROKEN_IMAGE;
         }}
       />
 
@@ -45,6 +71,22 @@ const PhotoCard = ({ photo, isSelected, selectionMode, onPhotoClick, onToggleSel
         </div>
       )}
 
+      {photo.is_offline && (
+        <div
+          title="On a drive that isn't connected"
+          className="absolute bottom-2 left-2 z-10 flex items-center gap-1 rounded px-1.5 py-0.5 bg-black/50 backdrop-blur-sm border border-white/10 text-[9px] font-mono font-bold tracking-wider text-white/60"
+        >
+          <HardDrive size={10} />
+          OFFLINE
+        </div>
+      )}
+
+      {isRawFormat(photo) && (
+        <div className="absolute bottom-2 right-2 z-10 rounded px-1.5 py-0.5 bg-black/50 backdrop-blur-sm border border-white/10 text-[9px] font-mono font-bold tracking-wider text-white/80 group-hover:opacity-0 transition-opacity">
+          RAW
+        </div>
+      )}
+
       <div className="absolute inset-0 bg-gradient-to-t from-black/80 via-transparent to-transparent opacity-0 group-hover:opacity-100 transition-opacity duration-300 flex flex-col justify-end p-3">
         <p className="text-xs font-mono text-white truncate">{photo.name}</p>
         <p className="text-[10px] font-mono text-white/60">{new Date(photo.date * 1000).toLocaleDateString()}</p>
@@ -70,6 +70,56 @@ describe('PhotoCard', () => {
     expect(container.querySelector('.fill-white')).toBeInTheDocument();
   });
 
+  it('badges RAW files only', () => {
+    const rawPhoto = { ...mockPhoto, name: 'IMG_0001.CR2', file_format: 'cr2' };
+    const { rerender } = render(
+      <PhotoCard
+        photo={rawPhoto}
+        isSelected={false}
+        selectionMode={false}
+        onPhotoClick={vi.fn()}
+        onToggleSelection={vi.fn()}
+      />
+    );
+    expect(screen.getByText('RAW')).toBeInTheDocument();
+
+    rerender(
+      <PhotoCard
+        photo={{ ...mockPhoto, file_format: 'jpeg' }}
+        isSelected={false}
+        selectionMode={false}
+        onPhotoClick={vi.fn()}
+        onToggleSelection={vi.fn()}
+      />
+    );
+    expect(screen.queryByText('RAW')).not.toBeInTheDocument();
+  });
+
+  it('greys out photos on an offline drive', () => {
+    const { rerender } = render(
+      <PhotoCard
+        photo={{ ...mockPhoto, is_offline: true }}
+        isSelected={false}
+        selectionMode={false}
+        onPhotoClick={vi.fn()}
+        onToggleSelection={vi.fn()}
+      />
+    );
+    expect(screen.getByText('OFFLINE')).toBeInTheDocument();
+    expect(screen.getByAltText('sunset.jpg').className).toContain('grayscale');
+
+    rerender(
+      <PhotoCard
+        photo={mockPhoto}
+        isSelected={false}
+        selectionMode={false}
+        onPhotoClick={vi.fn()}
+        onToggleSelection={vi.fn()}
+      />
+    );
+    expect(screen.queryByText('OFFLINE')).not.toBeInTheDocument();
+  });
+
   it('calls onPhotoClick when card is clicked', async () => {
     const onPhotoClick = vi.fn();
     render(
@@ -86,4 +136,17 @@ describe('PhotoCard', () => {
     await user.click(screen.getByAltText('sunset.jpg'));
     expect(onPhotoClick).toHaveBeenCalled();
   });
+
+  it('paints the dominant color behind the thumbnail', () => {
+    const { container } = render(
+      <PhotoCard
+        photo={{ ...mockPhoto, dominant_color: '#123456' }}
+        isSelected={false}
+        selectionMode={false}
+        onPhotoClick={vi.fn()}
+        onToggleSelection={vi.fn()}
+      />
+    );
+    expect(container.firstChild.style.backgroundColor).toBe('rgb(18, 52, 86)');
+  });
 });
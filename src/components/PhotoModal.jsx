@@ -1,6 +1,7 @@
 import { useCallback, useEffect, useRef, useState } from 'react';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
 import {
-  X, Heart, Info, FolderPlus, Tag, Archive, Trash2, FolderOpen,
+  X, Heart, Info, FolderPlus, Tag, Archive, Trash2, FolderOpen, Pencil,
   ChevronLeft, ChevronRight, MapPin, ExternalLink,
 } from 'lucide-react';
 import { PhotoTagBar } from './TagManager';
@@ -65,13 +66,30 @@ function InfoRow({ label, children }) {
   );
 }
 
+/** "UTC+09:00" / "UTC-05:30" for an offset in minutes east of UTC. */
+function formatUtcOffset(minutes) {
+  const sign = minutes < 0 ? '-' : '+';
+  const abs = Math.abs(minutes);
+  const pad = n => String(n).padStart(2, '0');
+  return `UTC${sign}${pad(Math.floor(abs / 60))}:${pad(abs % 60)}`;
+}
+
+/** Capture time as the camera's clock showed it, when its offset is known. */
+function formatCaptureDate(photo) {
+  const options = {
+    weekday: 'long', year: 'numeric', month: 'long', day: 'numeric',
+    hour: '2-digit', minute: '2-digit',
+  };
+  if (photo.tz_offset_minutes == null) {
+    return new Date(photo.date * 1000).toLocaleDateString(undefined, options);
+  }
+  const wallClock = new Date((photo.date + photo.tz_offset_minutes * 60) * 1000);
+  const formatted = wallClock.toLocaleDateString(undefined, { ...options, timeZone: 'UTC' });
+  return `${formatted} (${formatUtcOffset(photo.tz_offset_minutes)})`;
+}
+
 function PhotoModalInfoDrawer({ photo, isOpen }) {
-  const dateLong = photo.date
-    ? new Date(photo.date * 1000).toLocaleDateString(undefined, {
-        weekday: 'long', year: 'numeric', month: 'long', day: 'numeric',
-        hour: '2-digit', minute: '2-digit',
-      })
-    : '—';
+  const dateLong = photo.date ? formatCaptureDate(photo) : '—';
 
   const hasDimensions = photo.width && photo.height && (photo.width > 0 || photo.height > 0);
   const hasGps = photo.latitude != null && photo.longitude != null;
@@ -213,10 +231,13 @@ const PhotoModal = ({
   onAddToAlbum,
   onTagAssign,
   onReveal,
+  onEditExternally,
 }) => {
   const [showInfo, setShowInfo] = useState(false);
   const [zoom, setZoom] = useState({ scale: 1, tx: 0, ty: 0 });
   const [isDragging, setIsDragging] = useState(false);
+  const [liveVideo, setLiveVideo] = useState(null);
+  const [playingLive, setPlayingLive] = useState(false);
   const dragStart = useRef(null);
   const imgRef = useRef(null);
   const containerRef = useRef(null);
@@ -231,6 +252,20 @@ const PhotoModal = ({
     setZoom({ scale: 1, tx: 0, ty: 0 });
   }, [photo?.path]);
 
+  // Live Photos: look up the motion video for stills
+  useEffect(() => {
+    setLiveVideo(null);
+    setPlayingLive(false);
+    if (!photo || photo.mediaType === 'video') return;
+    let cancelled = false;
+    invoke('get_live_photo_video', { path: photo.path })
+      .then((videoPath) => {
+        if (!cancelled && typeof videoPath === 'string') setLiveVideo(videoPath);
+      })
+      .catch((err) => console.error('Failed to look up Live Photo video:', err));
+    return () => { cancelled = true; };
+  }, [photo?.path, photo?.mediaType]);
+
   const goTo = useCallback((index) => {
     const clamped = (index + photos.length) % photos.length;
     onSelectPhoto?.(photos[clamped]);
@@ -252,13 +287,14 @@ const PhotoModal = ({
       if (e.key === 'f') { onToggleFavorite?.(photo); return; }
       if (e.key === 'i') { setShowInfo(v => !v); return; }
       if (e.key === 'o') { onReveal?.(photo); return; }
+      if (e.key === 'e') { onEditExternally?.(photo); return; }
       if ((e.key === 'Backspace' || e.key === 'Delete') && !isMeta) { onArchive?.(photo); return; }
       if (e.key === 'Backspace' && isMeta) { onDelete?.(photo); return; }
     };
 
     window.addEventListener('keydown', handleKey);
     return () => window.removeEventListener('keydown', handleKey);
-  }, [photo, currentIndex, goTo, onClose, onToggleFavorite, onArchive, onDelete, onReveal]);
+  }, [photo, currentIndex, goTo, onClose, onToggleFavorite, onArchive, onDelete, onReveal, onEditExternally]);
 
   // Wheel: zoom around cursor (only when not holding Cmd/Ctrl)
   const handleWheel = useCallback((e) => {
@@ -407,6 +443,8 @@ const PhotoModal = ({
       >
         {isVideo ? (
           <VideoPlayer src={photo.url} />
+        ) : playingLive && liveVideo ? (
+          <VideoPlayer src={convertFileSrc(liveVideo)} />
         ) : (
           <img
             ref={imgRef}
@@ -439,6 +477,18 @@ const PhotoModal = ({
               VIDEO
             </span>
           )}
+          {liveVideo && (
+            <button
+              onClick={() => setPlayingLive(v => !v)}
+              aria-label={playingLive ? 'Show still' : 'Play Live Photo'}
+              aria-pressed={playingLive}
+              className={`ml-2 text-[10px] px-1.5 py-0.5 rounded border transition-colors ${playingLive
+                ? 'bg-emerald-500/20 text-emerald-400 border-emerald-500/30'
+                : 'text-white/60 border-white/20 hover:text-white hover:border-white/40'}`}
+            >
+              LIVE
+            </button>
+          )}
         </span>
 
         <ActionButton
@@ -479,6 +529,11 @@ const PhotoModal = ({
           label="Reveal in Finder (O)"
           onClick={() => onReveal?.(photo)}
         />
+        <ActionButton
+          icon={Pencil}
+          label="Edit in External App (E)"
+          onClick={() => onEditExternally?.(photo)}
+        />
       </div>
     </div>
   );
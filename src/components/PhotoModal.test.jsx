@@ -1,6 +1,7 @@
 import { describe, it, expect, vi } from 'vitest';
 import { render, screen, fireEvent } from '@testing-library/react';
 import userEvent from '@testing-library/user-event';
+import { invoke } from '@tauri-apps/api/core';
 import PhotoModal from './PhotoModal';
 
 const mockPhoto = {
@@ -39,6 +40,7 @@ const defaultProps = {
   onAddToAlbum: vi.fn(),
   onTagAssign: vi.fn(),
   onReveal: vi.fn(),
+  onEditExternally: vi.fn(),
 };
 
 describe('PhotoModal', () => {
@@ -65,6 +67,23 @@ describe('PhotoModal', () => {
     expect(screen.getByText('VIDEO')).toBeInTheDocument();
   });
 
+  it('plays the paired video of a Live Photo', async () => {
+    invoke.mockResolvedValueOnce('/pics/sunset.mov');
+    const { container } = render(<PhotoModal {...defaultProps} />);
+    const live = await screen.findByLabelText('Play Live Photo');
+    expect(invoke).toHaveBeenCalledWith('get_live_photo_video', { path: '/pics/sunset.jpg' });
+
+    const user = userEvent.setup();
+    await user.click(live);
+    expect(container.querySelector('video')).toBeInTheDocument();
+    expect(screen.getByLabelText('Show still')).toBeInTheDocument();
+  });
+
+  it('has no LIVE control for ordinary photos', () => {
+    render(<PhotoModal {...defaultProps} />);
+    expect(screen.queryByText('LIVE')).not.toBeInTheDocument();
+  });
+
   it('calls onClose when X button clicked', async () => {
     const onClose = vi.fn();
     render(<PhotoModal {...defaultProps} onClose={onClose} />);
@@ -184,6 +203,16 @@ describe('PhotoModal', () => {
     expect(onReveal).toHaveBeenCalledWith(mockPhoto);
   });
 
+  it('calls onEditExternally on e key and from its button', async () => {
+    const onEditExternally = vi.fn();
+    render(<PhotoModal {...defaultProps} onEditExternally={onEditExternally} />);
+    fireEvent.keyDown(window, { key: 'e' });
+    expect(onEditExternally).toHaveBeenCalledWith(mockPhoto);
+    const user = userEvent.setup();
+    await user.click(screen.getByLabelText('Edit in External App (E)'));
+    expect(onEditExternally).toHaveBeenCalledTimes(2);
+  });
+
   it('calls onDelete when Delete button clicked', async () => {
     const onDelete = vi.fn();
     render(<PhotoModal {...defaultProps} onDelete={onDelete} />);
@@ -219,6 +248,17 @@ describe('PhotoModal', () => {
     expect(screen.getByText('managed')).toBeInTheDocument();
   });
 
+  it('shows capture time in the timezone it was taken in', async () => {
+    // 2023-11-14 22:13 UTC is 07:13 the next morning in Tokyo
+    const photo = { ...mockPhoto, tz_offset_minutes: 540 };
+    render(<PhotoModal {...defaultProps} photo={photo} />);
+    const user = userEvent.setup();
+    await user.click(screen.getByLabelText('Info (I)'));
+    const date = screen.getByText(/UTC\+09:00/);
+    expect(date.textContent).toMatch(/15/);
+    expect(date.textContent).toMatch(/07:13/);
+  });
+
   it('shows GPS link when coordinates present', async () => {
     const photo = { ...mockPhoto, latitude: 37.7749, longitude: -122.4194 };
     render(<PhotoModal {...defaultProps} photo={photo} />);
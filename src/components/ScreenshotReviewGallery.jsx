@@ -2,7 +2,7 @@ import { useState } from 'react';
 import { convertFileSrc } from '@tauri-apps/api/core';
 import { X, MonitorSmartphone, CheckCircle, RotateCcw, Archive } from 'lucide-react';
 
-const ScreenshotReviewGallery = ({ isOpen, onClose, screenshots, onArchive, onRefresh }) => {
+const ScreenshotReviewGallery = ({ isOpen, onClose, screenshots, onArchive, onRefresh, onNotScreenshot }) => {
   const [selectedForArchive, setSelectedForArchive] = useState(new Set());
   const [showConfirm, setShowConfirm] = useState(false);
 
@@ -118,6 +118,17 @@ const ScreenshotReviewGallery = ({ isOpen, onClose, screenshots, onArchive, onRe
                       <Archive size={32} className="text-red-400" />
                     </div>
                   )}
+                  {onNotScreenshot && (
+                    <button
+                      onClick={(e) => {
+                        e.stopPropagation();
+                        onNotScreenshot(photo.path);
+                      }}
+                      className="absolute bottom-2 left-2 right-2 px-2 py-1 rounded bg-black/70 hover:bg-black/90 text-[10px] font-medium text-white/80 hover:text-white transition-colors"
+                    >
+                      Not a screenshot
+                    </button>
+                  )}
                   <div className="absolute top-2 right-2">
                     <div className={`w-5 h-5 rounded-full border-2 flex items-center justify-center ${
                       selectedForArchive.has(photo.path)
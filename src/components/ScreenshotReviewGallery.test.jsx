@@ -37,4 +37,15 @@ describe('ScreenshotReviewGallery', () => {
     expect(screen.getByText('Select All')).toBeInTheDocument();
     expect(screen.getByText('Select None')).toBeInTheDocument();
   });
+
+  it('marks a false positive without selecting it', async () => {
+    const onNotScreenshot = vi.fn();
+    render(
+      <ScreenshotReviewGallery isOpen={true} onClose={vi.fn()} screenshots={mockScreenshots} onArchive={vi.fn()} onRefresh={vi.fn()} onNotScreenshot={onNotScreenshot} />
+    );
+    const user = userEvent.setup();
+    await user.click(screen.getAllByText('Not a screenshot')[1]);
+    expect(onNotScreenshot).toHaveBeenCalledWith('/s2.png');
+    expect(screen.queryByText(/Archive \d/)).not.toBeInTheDocument();
+  });
 });
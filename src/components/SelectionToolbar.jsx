@@ -1,10 +1,12 @@
-import { Folder, Tag, Trash2, X, CheckCircle } from 'lucide-react';
+import { Folder, Tag, RotateCw, EyeOff, Trash2, X, CheckCircle } from 'lucide-react';
 
 const SelectionToolbar = ({
   selectionMode,
   selectedPhotos,
   onAddToAlbum,
   onTagAssign,
+  onRotate,
+  onHide,
   onDelete,
   onCancel,
   onEnterSelectionMode,
@@ -26,6 +28,16 @@ const SelectionToolbar = ({
           <span className="text-[10px] uppercase tracking-wider">Tag</span>
         </button>
 
+        <button onClick={onRotate} disabled={selectedPhotos.size === 0} className="flex flex-col items-center space-y-1 text-white/60 hover:text-white transition-colors disabled:opacity-30">
+          <RotateCw size={20} />
+          <span className="text-[10px] uppercase tracking-wider">Rotate</span>
+        </button>
+
+        <button onClick={onHide} disabled={selectedPhotos.size === 0} className="flex flex-col items-center space-y-1 text-white/60 hover:text-white transition-colors disabled:opacity-30">
+          <EyeOff size={20} />
+          <span className="text-[10px] uppercase tracking-wider">Hide</span>
+        </button>
+
         <button onClick={onDelete} disabled={selectedPhotos.size === 0} className="flex flex-col items-center space-y-1 text-white/60 hover:text-red-400 transition-colors disabled:opacity-30">
           <Trash2 size={20} />
           <span className="text-[10px] uppercase tracking-wider">Delete</span>
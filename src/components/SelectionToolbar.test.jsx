@@ -54,6 +54,46 @@ describe('SelectionToolbar', () => {
     expect(onCancel).toHaveBeenCalled();
   });
 
+  it('calls onHide when Hide clicked', async () => {
+    const onHide = vi.fn();
+    render(
+      <SelectionToolbar
+        selectionMode={true}
+        selectedPhotos={new Set(['a'])}
+        onAddToAlbum={vi.fn()}
+        onTagAssign={vi.fn()}
+        onHide={onHide}
+        onDelete={vi.fn()}
+        onCancel={vi.fn()}
+        onEnterSelectionMode={vi.fn()}
+      />
+    );
+
+    const user = userEvent.setup();
+    await user.click(screen.getByText('Hide'));
+    expect(onHide).toHaveBeenCalled();
+  });
+
+  it('calls onRotate when Rotate clicked', async () => {
+    const onRotate = vi.fn();
+    render(
+      <SelectionToolbar
+        selectionMode={true}
+        selectedPhotos={new Set(['a'])}
+        onAddToAlbum={vi.fn()}
+        onTagAssign={vi.fn()}
+        onRotate={onRotate}
+        onDelete={vi.fn()}
+        onCancel={vi.fn()}
+        onEnterSelectionMode={vi.fn()}
+      />
+    );
+
+    const user = userEvent.setup();
+    await user.click(screen.getByText('Rotate'));
+    expect(onRotate).toHaveBeenCalled();
+  });
+
   it('disables action buttons when no photos selected', () => {
     render(
       <SelectionToolbar
@@ -2,7 +2,7 @@ import React, { useState, useEffect } from 'react';
 import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
-import { X, Settings, FolderOpen, AlertTriangle, Sparkles, Image as ImageIcon } from 'lucide-react';
+import { X, Settings, FolderOpen, AlertTriangle, Sparkles, Image as ImageIcon, ClipboardCopy } from 'lucide-react';
 
 const SettingsModal = ({ isOpen, onClose, libraryPath, onLibraryPathChange, onPhotosChanged }) => {
   const [currentPath, setCurrentPath] = useState(libraryPath || '');
@@ -18,10 +18,39 @@ const SettingsModal = ({ isOpen, onClose, libraryPath, onLibraryPathChange, onPh
   const [thumbResult, setThumbResult] = useState(null);
   const [thumbError, setThumbError] = useState(null);
 
+  const [exclusions, setExclusions] = useState(null);
+  const [patternsText, setPatternsText] = useState('');
+  const [exclusionsStatus, setExclusionsStatus] = useState(null);
+
+  const [autoScan, setAutoScan] = useState(null);
+  const [autoScanError, setAutoScanError] = useState(null);
+
+  const [logLevel, setLogLevel] = useState('info');
+  const [diagnosticsStatus, setDiagnosticsStatus] = useState(null);
+
   useEffect(() => {
     setCurrentPath(libraryPath || '');
   }, [libraryPath]);
 
+  useEffect(() => {
+    if (!isOpen) return;
+    invoke('get_scan_exclusions')
+      .then((loaded) => {
+        setExclusions(loaded);
+        setPatternsText(loaded.patterns.join('\n'));
+        setExclusionsStatus(null);
+      })
+      .catch((err) => console.error('Failed to load scan exclusions:', err));
+    invoke('get_auto_scan_status')
+      .then(setAutoScan)
+      .catch((err) => console.error('Failed to load auto scan status:', err));
+    setAutoScanError(null);
+    invoke('get_setting', { key: 'log_level' })
+      .then((level) => setLogLevel(level || 'info'))
+      .catch((err) => console.error('Failed to load log level:', err));
+    setDiagnosticsStatus(null);
+  }, [isOpen]);
+
   useEffect(() => {
     if (!enrichRunning) return;
     let unlisten;
@@ -42,6 +71,51 @@ const SettingsModal = ({ isOpen, onClose, libraryPath, onLibraryPathChange, onPh
 
   if (!isOpen) return null;
 
+  const handleSaveExclusions = async () => {
+    const settings = {
+      ...exclusions,
+      patterns: patternsText.split('\n').map(p => p.trim()).filter(Boolean),
+    };
+    try {
+      await invoke('set_scan_exclusions', { settings });
+      setExclusions(settings);
+      setExclusionsStatus({ ok: true, message: 'Saved. Applies to the next scan.' });
+    } catch (err) {
+      setExclusionsStatus({ ok: false, message: typeof err === 'string' ? err : err?.message ?? 'Failed to save' });
+    }
+  };
+
+  const handleAutoScanChange = async (enabled, intervalMinutes) => {
+    try {
+      setAutoScan(await invoke('set_auto_scan', { enabled, intervalMinutes }));
+      setAutoScanError(null);
+    } catch (err) {
+      setAutoScanError(typeof err === 'string' ? err : err?.message ?? 'Failed to save');
+    }
+  };
+
+  const handleLogLevelChange = async (level) => {
+    try {
+      await invoke('set_setting', { key: 'log_level', value: level });
+      setLogLevel(level);
+    } catch (err) {
+      console.error('Failed to set log level:', err);
+    }
+  };
+
+  const handleCopyDiagnostics = async () => {
+    try {
+      const [logPath, lines] = await Promise.all([
+        invoke('get_log_file_path'),
+        invoke('get_recent_logs', { lines: 500 }),
+      ]);
+      await navigator.clipboard.writeText(`Log file: ${logPath}\n\n${lines.join('\n')}`);
+      setDiagnosticsStatus({ ok: true, message: `Copied the last ${lines.length} log lines.` });
+    } catch (err) {
+      setDiagnosticsStatus({ ok: false, message: typeof err === 'string' ? err : err?.message ?? 'Failed to copy diagnostics' });
+    }
+  };
+
   const handleChangePath = async () => {
     try {
       const selected = await open({ directory: true });
@@ -137,11 +211,105 @@ const SettingsModal = ({ isOpen, onClose, libraryPath, onLibraryPathChange, onPh
             </div>
           </div>
 
+          {/* Scan Exclusions */}
+          {exclusions && (
+            <div className="pt-4 border-t border-white/10">
+              <label className="block text-sm font-medium text-white/70 mb-2">Scanning</label>
+              <p className="text-xs text-white/40 mb-3">
+                Cache and app folders (node_modules, .git, @eaDir, Lightroom previews, Photos libraries) are always skipped.
+              </p>
+              <label className="flex items-center gap-2 text-sm text-white/70 mb-3">
+                <input
+                  type="checkbox"
+                  checked={exclusions.skip_hidden}
+                  onChange={(e) => setExclusions({ ...exclusions, skip_hidden: e.target.checked })}
+                />
+                Skip hidden folders and files
+              </label>
+              <label className="flex items-center gap-2 text-sm text-white/70 mb-3">
+                <input
+                  type="checkbox"
+                  checked={exclusions.follow_symlinks}
+                  onChange={(e) => setExclusions({ ...exclusions, follow_symlinks: e.target.checked })}
+                />
+                Follow symbolic links
+              </label>
+              <label className="block text-xs text-white/50 mb-1">Skip files smaller than (KB)</label>
+              <input
+                type="number"
+                min="0"
+                value={Math.round(exclusions.min_file_size / 1024)}
+                onChange={(e) => setExclusions({ ...exclusions, min_file_size: Math.max(0, Number(e.target.value) || 0) * 1024 })}
+                className="w-24 mb-3 bg-white/5 border border-white/10 rounded-lg px-3 py-1.5 text-sm text-white/80 font-mono"
+              />
+              <label className="block text-xs text-white/50 mb-1">Exclude patterns, one per line (e.g. *.tmp or Exports/*)</label>
+              <textarea
+                value={patternsText}
+                onChange={(e) => setPatternsText(e.target.value)}
+                rows={3}
+                className="w-full bg-white/5 border border-white/10 rounded-lg px-3 py-2 text-sm text-white/80 font-mono"
+              />
+              <div className="mt-2 flex items-center gap-3">
+                <button
+                  onClick={handleSaveExclusions}
+                  className="px-4 py-2 bg-white/5 hover:bg-white/10 border border-white/10 hover:border-white/20 rounded-lg text-sm text-white/80 hover:text-white transition-colors"
+                >
+                  Save Exclusions
+                </button>
+                {exclusionsStatus && (
+                  <span className={`text-xs font-mono ${exclusionsStatus.ok ? 'text-emerald-400' : 'text-red-300'}`}>
+                    {exclusionsStatus.message}
+                  </span>
+                )}
+              </div>
+            </div>
+          )}
+
+          {/* Scheduled Scans */}
+          {autoScan && (
+            <div className="pt-4 border-t border-white/10">
+              <label className="block text-sm font-medium text-white/70 mb-2">Scheduled Scans</label>
+              <p className="text-xs text-white/40 mb-3">
+                Rescan watch folders on a schedule, for folders other apps fill, like a camera-uploads folder.
+              </p>
+              <div className="flex items-center gap-3 text-sm text-white/70">
+                <label className="flex items-center gap-2">
+                  <input
+                    type="checkbox"
+                    checked={autoScan.enabled}
+                    onChange={(e) => handleAutoScanChange(e.target.checked, autoScan.interval_minutes)}
+                  />
+                  Every
+                </label>
+                <input
+                  type="number"
+                  min="5"
+                  defaultValue={autoScan.interval_minutes}
+                  onBlur={(e) => handleAutoScanChange(autoScan.enabled, Number(e.target.value) || autoScan.interval_minutes)}
+                  className="w-20 bg-white/5 border border-white/10 rounded-lg px-3 py-1.5 text-sm text-white/80 font-mono"
+                />
+                minutes
+              </div>
+              <div className="mt-2 text-xs text-white/40 font-mono space-y-0.5">
+                {autoScan.last_result && (
+                  <div>
+                    Last run {new Date(autoScan.last_run * 1000).toLocaleString()}:{' '}
+                    {autoScan.last_result.skipped
+                      ?? `${autoScan.last_result.added} added, ${autoScan.last_result.updated} updated, ${autoScan.last_result.missing} missing`}
+                    {autoScan.last_result.failed.length > 0 && `, ${autoScan.last_result.failed.length} folder(s) unavailable`}
+                  </div>
+                )}
+                {autoScan.next_run && <div>Next run {new Date(autoScan.next_run * 1000).toLocaleString()}</div>}
+              </div>
+              {autoScanError && <div className="mt-2 text-xs text-red-300 font-mono">{autoScanError}</div>}
+            </div>
+          )}
+
           {/* Thumbnails */}
           <div className="pt-4 border-t border-white/10">
             <label className="block text-sm font-medium text-white/70 mb-2">Thumbnails</label>
             <p className="text-xs text-white/40 mb-3">
-              Generate cached 256² thumbnails for each photo. Required for snappy gallery scrolling on large libraries; videos and undecodable HEICs are skipped automatically.
+              Generate cached 256² thumbnails for each photo. Runs automatically after a scan; use this to catch up older photos. Video thumbnails need ffmpeg installed.
             </p>
 
             <button
@@ -235,6 +403,37 @@ const SettingsModal = ({ isOpen, onClose, libraryPath, onLibraryPathChange, onPh
               </div>
             )}
           </div>
+
+          {/* Diagnostics */}
+          <div className="pt-4 border-t border-white/10">
+            <label className="block text-sm font-medium text-white/70 mb-2">Diagnostics</label>
+            <p className="text-xs text-white/40 mb-3">
+              Copy recent log lines to attach to a bug report. Raise the level to debug for per-photo details.
+            </p>
+            <div className="flex items-center gap-3">
+              <select
+                value={logLevel}
+                onChange={(e) => handleLogLevelChange(e.target.value)}
+                className="bg-black/50 border border-white/10 rounded-lg px-3 py-2 text-sm text-white/80 focus:outline-none focus:border-emerald-400/50"
+              >
+                {['error', 'warn', 'info', 'debug', 'trace'].map((level) => (
+                  <option key={level} value={level}>{level}</option>
+                ))}
+              </select>
+              <button
+                onClick={handleCopyDiagnostics}
+                className="flex items-center gap-2 px-4 py-2 bg-emerald-500/20 hover:bg-emerald-500/30 border border-emerald-400/30 hover:border-emerald-400/50 rounded-lg text-sm text-emerald-200 transition-colors"
+              >
+                <ClipboardCopy size={16} />
+                Copy diagnostics
+              </button>
+            </div>
+            {diagnosticsStatus && (
+              <div className={`mt-2 text-xs font-mono ${diagnosticsStatus.ok ? 'text-emerald-400' : 'text-red-300'}`}>
+                {diagnosticsStatus.message}
+              </div>
+            )}
+          </div>
         </div>
 
         <div className="mt-8 flex justify-end">
@@ -1,15 +1,27 @@
 import { invoke } from '@tauri-apps/api/core';
 import {
   Grid, Calendar, Heart, Plus, Upload, Folder, Copy,
-  MonitorSmartphone, Archive, Eye, BarChart3, Settings,
-  Image as ImageIcon, Cloud, HardDrive, Sun, Moon
+  MonitorSmartphone, Archive, Eye, EyeOff, BarChart3, Settings,
+  Image as ImageIcon, Cloud, HardDrive, Sun, Moon, Sparkles
 } from 'lucide-react';
 import SmartCollections from './SmartCollections';
+import { flattenAlbumTree } from '../utils/albumTree';
 import CloudProviderButton from './CloudProviderButton';
 import { useTheme } from '../contexts/ThemeContext';
 
+const plural = (count, noun) => `${count.toLocaleString()} ${noun}${count === 1 ? '' : 's'}`;
+
+// "12,402 photos · 318 videos · 96 favorites · 14 albums"
+const formatSummary = (summary) => [
+  plural(summary.photo_count, 'photo'),
+  plural(summary.video_count, 'video'),
+  plural(summary.favorite_count, 'favorite'),
+  plural(summary.album_count, 'album'),
+].join(' · ');
+
 const Sidebar = ({
   photos,
+  librarySummary,
   viewMode,
   setViewMode,
   searchQuery,
@@ -31,7 +43,10 @@ const Sidebar = ({
   onSettings,
   onScanDuplicates,
   onScanScreenshots,
+  hideScreenshots,
+  onToggleHideScreenshots,
   onOpenArchive,
+  onOpenHidden,
   onOpenTerraForm,
   onOpenStorageAnalytics,
 }) => {
@@ -60,7 +75,9 @@ const Sidebar = ({
           </div>
         </div>
         <div className="text-xs text-white/40 font-mono mt-1 tracking-widest">LOCAL LIBRARY</div>
-        {photos.length > 0 && (
+        {librarySummary?.photo_count != null ? (
+          <div className="mt-2 text-xs text-emerald-400/60 font-mono">{formatSummary(librarySummary)}</div>
+        ) : photos.length > 0 && (
           <div className="mt-2 text-xs text-emerald-400/60 font-mono">{photos.length} photos</div>
         )}
       </div>
@@ -122,19 +139,24 @@ const Sidebar = ({
           <button onClick={onCreateAlbum} className="hover:text-white transition-colors"><Plus size={14} /></button>
         </div>
         <div className="space-y-1 max-h-48 overflow-y-auto">
-          {albums.map(album => (
-            <button
-              key={album.id}
-              onClick={() => setViewMode(`album:${album.id}`)}
-              className={`w-full flex items-center justify-between px-3 py-2 rounded-lg text-sm transition-all ${viewMode === `album:${album.id}` ? 'bg-white/10 text-white' : 'text-white/60 hover:bg-white/5'} group`}
-            >
-              <div className="flex items-center space-x-3 truncate">
-                <Folder size={16} />
-                <span className="truncate">{album.name}</span>
-              </div>
-              <span className="text-[10px] opacity-50">{album.count}</span>
-            </button>
-          ))}
+          {flattenAlbumTree(albums).map(({ album, depth }) => {
+            // Smart and regular album ids overlap, so each kind has its own view
+            const albumView = `${album.kind === 'smart' ? 'smart' : 'album'}:${album.id}`;
+            return (
+              <button
+                key={albumView}
+                onClick={() => setViewMode(albumView)}
+                style={{ paddingLeft: `${0.75 + depth}rem` }}
+                className={`w-full flex items-center justify-between px-3 py-2 rounded-lg text-sm transition-all ${viewMode === albumView ? 'bg-white/10 text-white' : 'text-white/60 hover:bg-white/5'} group`}
+              >
+                <div className="flex items-center space-x-3 truncate">
+                  {album.kind === 'smart' ? <Sparkles size={16} /> : <Folder size={16} />}
+                  <span className="truncate">{album.name}</span>
+                </div>
+                <span className="text-[10px] opacity-50">{album.total_count ?? album.count}</span>
+              </button>
+            );
+          })}
           {albums.length === 0 && (
             <div className="px-3 py-2 text-xs text-white/30 italic">No albums yet</div>
           )}
@@ -210,9 +232,16 @@ const Sidebar = ({
         <button onClick={onScanScreenshots} className="w-full flex items-center space-x-3 px-3 py-2 rounded-lg text-sm transition-all text-white/60 hover:bg-white/5 hover:text-white">
           <MonitorSmartphone size={18} /> <span>Find Screenshots</span>
         </button>
+        <button onClick={onToggleHideScreenshots} className="w-full flex items-center space-x-3 px-3 py-2 rounded-lg text-sm transition-all text-white/60 hover:bg-white/5 hover:text-white">
+          {hideScreenshots ? <Eye size={18} /> : <EyeOff size={18} />}
+          <span>{hideScreenshots ? 'Show Screenshots' : 'Hide Screenshots'}</span>
+        </button>
         <button onClick={onOpenArchive} className="w-full flex items-center space-x-3 px-3 py-2 rounded-lg text-sm transition-all text-white/60 hover:bg-white/5 hover:text-white">
           <Archive size={18} /> <span>View Archive</span>
         </button>
+        <button onClick={onOpenHidden} className="w-full flex items-center space-x-3 px-3 py-2 rounded-lg text-sm transition-all text-white/60 hover:bg-white/5 hover:text-white">
+          <EyeOff size={18} /> <span>Hidden Photos</span>
+        </button>
         <button onClick={onOpenTerraForm} className="w-full flex items-center justify-between px-3 py-2 rounded-lg text-sm transition-all text-white/60 hover:bg-white/5 hover:text-white">
           <div className="flex items-center space-x-3">
             <Eye size={18} />
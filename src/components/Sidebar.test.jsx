@@ -41,6 +41,12 @@ describe('Sidebar', () => {
     expect(screen.getByText('3 photos')).toBeInTheDocument();
   });
 
+  it('shows the library summary when loaded', () => {
+    const librarySummary = { photo_count: 12402, video_count: 318, favorite_count: 96, album_count: 1 };
+    render(<Sidebar {...defaultProps} photos={[{}]} librarySummary={librarySummary} />);
+    expect(screen.getByText('12,402 photos · 318 videos · 96 favorites · 1 album')).toBeInTheDocument();
+  });
+
   it('shows upload button', () => {
     render(<Sidebar {...defaultProps} />);
     expect(screen.getByText('Upload Photos')).toBeInTheDocument();
@@ -77,6 +83,20 @@ describe('Sidebar', () => {
     expect(screen.getByText('5')).toBeInTheDocument();
   });
 
+  it('opens smart albums in their own view', async () => {
+    const setViewMode = vi.fn();
+    const albums = [
+      { id: 1, name: 'Vacation', count: 5, kind: 'regular' },
+      { id: 1, name: 'Screenshots', count: 2, kind: 'smart' },
+    ];
+    render(<Sidebar {...defaultProps} albums={albums} setViewMode={setViewMode} />);
+    const user = userEvent.setup();
+    await user.click(screen.getByText('Screenshots'));
+    expect(setViewMode).toHaveBeenCalledWith('smart:1');
+    await user.click(screen.getByText('Vacation'));
+    expect(setViewMode).toHaveBeenCalledWith('album:1');
+  });
+
   it('shows empty albums message', () => {
     render(<Sidebar {...defaultProps} albums={[]} />);
     expect(screen.getByText('No albums yet')).toBeInTheDocument();
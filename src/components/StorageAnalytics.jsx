@@ -3,7 +3,7 @@ import { invoke } from '@tauri-apps/api/core';
 import { listen } from '@tauri-apps/api/event';
 import {
   X, BarChart3, HardDrive, Image as ImageIcon, Film,
-  MonitorSmartphone, Copy, AlertTriangle
+  MonitorSmartphone, Copy, AlertTriangle, Trash2
 } from 'lucide-react';
 import {
   PieChart, Pie, Cell, BarChart, Bar, AreaChart, Area,
@@ -17,6 +17,9 @@ const CHART_COLORS = {
   screenshots: '#f97316',
 };
 
+// Period label of undated photos in size_by_year and size_by_month
+const UNKNOWN_PERIOD = 'unknown';
+
 const CustomTooltip = ({ active, payload, label }) => {
   if (!active || !payload?.length) return null;
   return (
@@ -33,14 +36,28 @@ const CustomTooltip = ({ active, payload, label }) => {
 
 const StorageAnalytics = ({ isOpen, onClose, onNavigateToPhoto, onOpenDuplicateReview }) => {
   const [analytics, setAnalytics] = useState(null);
+  const [storageInfo, setStorageInfo] = useState(null);
   const [loading, setLoading] = useState(false);
   const [scanningFileSizes, setScanningFileSizes] = useState(false);
   const [scanProgress, setScanProgress] = useState({ total: 0, processed: 0 });
 
   useEffect(() => {
-    if (isOpen) loadAnalytics();
+    if (isOpen) {
+      loadAnalytics();
+      loadStorageInfo();
+    }
   }, [isOpen]);
 
+  // The library drive's capacity; left out if the drive isn't available
+  const loadStorageInfo = async () => {
+    try {
+      setStorageInfo(await invoke('get_storage_info'));
+    } catch (err) {
+      console.error('Failed to load storage info:', err);
+      setStorageInfo(null);
+    }
+  };
+
   const loadAnalytics = async () => {
     setLoading(true);
     try {
@@ -83,17 +100,24 @@ const StorageAnalytics = ({ isOpen, onClose, onNavigateToPhoto, onOpenDuplicateR
     { name: 'Screenshots', value: analytics.screenshots_size, color: CHART_COLORS.screenshots },
   ].filter(d => d.value > 0) : [];
 
+  // Periods come newest first with undated photos ('unknown') at the end
   const yearData = analytics?.size_by_year?.map(y => ({
-    name: y.year,
+    name: y.year === UNKNOWN_PERIOD ? 'Undated' : y.year,
     size: y.size,
     count: y.count,
-  })).reverse() || [];
+  })) || [];
+  const undatedYear = yearData.length > 0 && analytics.size_by_year.at(-1).year === UNKNOWN_PERIOD ? yearData.pop() : null;
+  yearData.reverse();
+  if (undatedYear) yearData.push(undatedYear);
 
-  const monthData = analytics?.size_by_month?.map(m => ({
-    name: m.month,
-    size: m.size,
-    count: m.count,
-  })).reverse() || [];
+  const monthData = analytics?.size_by_month
+    ?.filter(m => m.month !== UNKNOWN_PERIOD)
+    .slice(0, 12)
+    .map(m => ({
+      name: m.month,
+      size: m.size,
+      count: m.count,
+    })).reverse() || [];
 
   return (
     <div className="fixed inset-0 z-50 bg-black/95 backdrop-blur-md animate-in fade-in duration-200 overflow-hidden">
@@ -108,6 +132,19 @@ const StorageAnalytics = ({ isOpen, onClose, onNavigateToPhoto, onOpenDuplicateR
             <p className="text-white/60 text-sm mt-1">
               {analytics ? `${formatBytes(analytics.total_size_bytes)} total` : 'Loading...'}
             </p>
+            {storageInfo?.total_bytes > 0 && (
+              <div className="mt-3 w-72">
+                <div className="h-1.5 rounded-full bg-white/10 overflow-hidden">
+                  <div
+                    className="h-full bg-emerald-400"
+                    style={{ width: `${Math.min(100, (1 - storageInfo.available_bytes / storageInfo.total_bytes) * 100)}%` }}
+                  />
+                </div>
+                <p className="text-[11px] font-mono text-white/40 mt-1">
+                  {formatBytes(storageInfo.available_bytes)} free of {formatBytes(storageInfo.total_bytes)} on the library drive
+                </p>
+              </div>
+            )}
           </div>
           <button
             onClick={onClose}
@@ -257,7 +294,7 @@ const StorageAnalytics = ({ isOpen, onClose, onNavigateToPhoto, onOpenDuplicateR
               {/* Top Largest Files */}
               {analytics.top_largest_files.length > 0 && (
                 <div className="bg-white/5 border border-white/10 rounded-xl p-5">
-                  <h3 className="text-sm font-medium text-white/70 mb-4">Top 10 Largest Files</h3>
+                  <h3 className="text-sm font-medium text-white/70 mb-4">Top {analytics.top_largest_files.length} Largest Files</h3>
                   <div className="space-y-1">
                     {analytics.top_largest_files.map((file, i) => (
                       <button
@@ -299,6 +336,21 @@ const StorageAnalytics = ({ isOpen, onClose, onNavigateToPhoto, onOpenDuplicateR
                   </div>
                 </div>
               )}
+
+              {/* Trash */}
+              {analytics.trashed_count > 0 && (
+                <div className="bg-white/5 border border-white/10 rounded-xl p-5 flex items-center gap-3">
+                  <Trash2 size={20} className="text-red-400" />
+                  <div>
+                    <p className="text-sm text-white">
+                      {analytics.trashed_count.toLocaleString()} item{analytics.trashed_count === 1 ? '' : 's'} in the trash
+                    </p>
+                    <p className="text-xs text-white/50">
+                      Empty the trash to free {formatBytes(analytics.trashed_size_bytes)}
+                    </p>
+                  </div>
+                </div>
+              )}
             </div>
           )}
         </div>
@@ -305,8 +305,9 @@ export const PhotoTagBar = ({ photoPath }) => {
       {tags.map((tag) => (
         <span
           key={tag.id}
-          className="inline-flex items-center gap-1 px-2 py-0.5 rounded-full text-xs text-white/90 border border-white/10"
+          className={`inline-flex items-center gap-1 px-2 py-0.5 rounded-full text-xs text-white/90 border border-white/10${tag.auto ? ' border-dashed' : ''}`}
           style={{ backgroundColor: tag.color + '30', borderColor: tag.color + '50' }}
+          title={tag.auto ? 'Added automatically' : undefined}
         >
           <span className="w-1.5 h-1.5 rounded-full" style={{ backgroundColor: tag.color }} />
           {tag.name}
@@ -12,6 +12,7 @@ export function AppProvider({ children }) {
   const [tags, setTags] = useState([]);
   const [selectedTagIds, setSelectedTagIds] = useState([]);
   const [thumbCacheRoot, setThumbCacheRoot] = useState(null);
+  const [librarySummary, setLibrarySummary] = useState(null);
 
   const loadAlbums = useCallback(async () => {
     try {
@@ -21,6 +22,14 @@ export function AppProvider({ children }) {
     }
   }, []);
 
+  const loadLibrarySummary = useCallback(async () => {
+    try {
+      setLibrarySummary(await invoke('get_library_summary'));
+    } catch (err) {
+      console.error('Failed to load library summary:', err);
+    }
+  }, []);
+
   const loadTags = useCallback(async () => {
     try {
       setTags(await invoke('get_all_tags'));
@@ -66,6 +75,11 @@ export function AppProvider({ children }) {
       .catch((err) => console.error('Failed to get thumb cache root:', err));
   }, []); // eslint-disable-line react-hooks/exhaustive-deps
 
+  // Whatever changed the photos or albums also changed the counts
+  useEffect(() => {
+    loadLibrarySummary();
+  }, [photosHook.photos, albums, loadLibrarySummary]);
+
   const value = {
     ...photosHook,
     albums,
@@ -77,6 +91,7 @@ export function AppProvider({ children }) {
     setSelectedTagIds,
     loadTags,
     thumbCacheRoot,
+    librarySummary,
     ...cleanupHook,
   };
 
@@ -100,6 +100,12 @@ export function ViewProvider({ children }) {
           const albumId = parseInt(viewMode.split(':')[1]);
           const result = await invoke('get_album_photos', { albumId });
           setPhotos(processPhotos(result));
+        } else if (viewMode.startsWith('smart:')) {
+          wasFilteredViewRef.current = true;
+          setLoading(true);
+          const id = parseInt(viewMode.split(':')[1]);
+          const result = await invoke('get_smart_album_photos', { id });
+          setPhotos(processPhotos(result));
         } else if (viewMode.startsWith('collection:')) {
           wasFilteredViewRef.current = true;
           setLoading(true);
@@ -14,6 +14,10 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
   const [duplicateGroups, setDuplicateGroups] = useState([]);
   const [screenshots, setScreenshots] = useState([]);
   const [archivedPhotos, setArchivedPhotos] = useState([]);
+  const [showHidden, setShowHidden] = useState(false);
+  const [hiddenPhotos, setHiddenPhotos] = useState([]);
+  // Summary of the last resolve_duplicates call, kept so it can be undone
+  const [lastResolution, setLastResolution] = useState(null);
 
   // Run archive cleanup on startup
   useEffect(() => {
@@ -112,7 +116,7 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
 
   const loadArchivedPhotos = useCallback(async () => {
     try {
-      const result = await invoke('get_archived_photos');
+      const result = await invoke('get_deletion_archive');
       setArchivedPhotos(result);
     } catch (err) {
       console.error("Failed to load archived photos:", err);
@@ -124,6 +128,39 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
     setShowArchive(true);
   }, [loadArchivedPhotos]);
 
+  const loadHiddenPhotos = useCallback(async () => {
+    try {
+      const result = await invoke('get_hidden_photos');
+      setHiddenPhotos(result);
+    } catch (err) {
+      console.error("Failed to load hidden photos:", err);
+    }
+  }, []);
+
+  // Hidden photos are only fetched once the user confirms they want to see them
+  const handleOpenHidden = useCallback(() => {
+    if (!window.confirm('Show hidden photos?')) return;
+    loadHiddenPhotos();
+    setShowHidden(true);
+  }, [loadHiddenPhotos]);
+
+  const setHidden = useCallback(async (paths, hidden) => {
+    try {
+      const updated = await invoke('set_hidden', { paths, hidden });
+      setStatusWithTimeout(`${hidden ? 'Hid' : 'Unhid'} ${updated} photos`);
+      loadPhotosFromDatabase();
+      if (!hidden) loadHiddenPhotos();
+      return updated;
+    } catch (err) {
+      console.error("Failed to update hidden photos:", err);
+      setError(typeof err === 'string' ? err : err?.message ?? 'Failed to update hidden photos');
+      return 0;
+    }
+  }, [loadPhotosFromDatabase, loadHiddenPhotos, setStatusWithTimeout, setError]);
+
+  const handleHidePhotos = useCallback((paths) => setHidden(paths, true), [setHidden]);
+  const handleUnhidePhotos = useCallback((paths) => setHidden(paths, false), [setHidden]);
+
   const refreshDuplicateGroups = useCallback(async () => {
     try {
       const groups = await invoke('get_duplicate_groups', { threshold: CONFIG.DUPLICATE_THRESHOLD });
@@ -133,6 +170,48 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
     }
   }, []);
 
+  const handleResolveDuplicates = useCallback(async (group, keep) => {
+    try {
+      const summary = await invoke('resolve_duplicates', { group, keep });
+      setLastResolution(summary.resolution_ids.length > 0 ? summary : null);
+      setStatusWithTimeout(`Kept 1 photo, moved ${summary.trashed} to trash`);
+      loadPhotosFromDatabase();
+      await refreshDuplicateGroups();
+    } catch (err) {
+      console.error("Failed to resolve duplicates:", err);
+      setError(typeof err === 'string' ? err : err?.message ?? 'Failed to resolve duplicates');
+    }
+  }, [loadPhotosFromDatabase, setStatusWithTimeout, setError, refreshDuplicateGroups]);
+
+  const handleUndoResolveDuplicates = useCallback(async () => {
+    if (!lastResolution) return;
+    try {
+      let restored = 0;
+      for (const resolutionId of lastResolution.resolution_ids) {
+        restored += await invoke('undo_duplicate_resolution', { resolutionId });
+      }
+      setLastResolution(null);
+      setStatusWithTimeout(`Restored ${restored} photos`);
+      loadPhotosFromDatabase();
+      await refreshDuplicateGroups();
+    } catch (err) {
+      console.error("Failed to undo duplicate resolution:", err);
+      setError(typeof err === 'string' ? err : err?.message ?? 'Failed to undo');
+    }
+  }, [lastResolution, loadPhotosFromDatabase, setStatusWithTimeout, setError, refreshDuplicateGroups]);
+
+  // A false positive: remembered so later scans don't flag it again
+  const handleNotScreenshot = useCallback(async (path) => {
+    try {
+      await invoke('set_is_screenshot', { path, isScreenshot: false });
+      setScreenshots((prev) => prev.filter((p) => p.path !== path));
+      loadPhotosFromDatabase();
+    } catch (err) {
+      console.error("Failed to update screenshot flag:", err);
+      setError(typeof err === 'string' ? err : err?.message ?? 'Failed to update screenshot flag');
+    }
+  }, [loadPhotosFromDatabase, setError]);
+
   const refreshScreenshots = useCallback(async () => {
     try {
       const result = await invoke('get_screenshots');
@@ -156,6 +235,10 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
     duplicateGroups,
     screenshots,
     archivedPhotos,
+    showHidden,
+    setShowHidden,
+    hiddenPhotos,
+    lastResolution,
     handleScanForDuplicates,
     handleDuplicateScanComplete,
     handleScanForScreenshots,
@@ -164,7 +247,14 @@ export function useCleanup({ loadPhotosFromDatabase, setStatusWithTimeout, setEr
     handleRestorePhotos,
     handleOpenArchive,
     refreshDuplicateGroups,
+    handleResolveDuplicates,
+    handleUndoResolveDuplicates,
+    handleNotScreenshot,
     refreshScreenshots,
     loadArchivedPhotos,
+    loadHiddenPhotos,
+    handleOpenHidden,
+    handleHidePhotos,
+    handleUnhidePhotos,
   };
 }
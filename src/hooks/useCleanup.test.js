@@ -77,11 +77,56 @@ describe('useCleanup', () => {
     expect(deps.loadPhotosFromDatabase).toHaveBeenCalled();
   });
 
+  it('handleResolveDuplicates keeps one copy and can be undone', async () => {
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'resolve_duplicates') return { resolution_ids: [7], trashed: 2, kept_elsewhere: [] };
+      if (cmd === 'undo_duplicate_resolution') return 2;
+      return [];
+    });
+    const { result } = renderHook(() => useCleanup(deps));
+
+    await act(async () => {
+      await result.current.handleResolveDuplicates(['/a.jpg', '/b.jpg', '/c.jpg'], '/a.jpg');
+    });
+
+    expect(invoke).toHaveBeenCalledWith('resolve_duplicates', { group: ['/a.jpg', '/b.jpg', '/c.jpg'], keep: '/a.jpg' });
+    expect(deps.setStatusWithTimeout).toHaveBeenCalledWith('Kept 1 photo, moved 2 to trash');
+    expect(invoke).toHaveBeenCalledWith('get_duplicate_groups', expect.anything());
+    expect(result.current.lastResolution.resolution_ids).toEqual([7]);
+
+    await act(async () => {
+      await result.current.handleUndoResolveDuplicates();
+    });
+
+    expect(invoke).toHaveBeenCalledWith('undo_duplicate_resolution', { resolutionId: 7 });
+    expect(deps.setStatusWithTimeout).toHaveBeenCalledWith('Restored 2 photos');
+    expect(result.current.lastResolution).toBeNull();
+  });
+
+  it('handleNotScreenshot overrides the flag and drops the photo from review', async () => {
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'scan_for_screenshots') return [{ path: '/s.png' }, { path: '/t.png' }];
+      return undefined;
+    });
+    const { result } = renderHook(() => useCleanup(deps));
+
+    await act(async () => {
+      await result.current.handleScanForScreenshots();
+    });
+    await act(async () => {
+      await result.current.handleNotScreenshot('/s.png');
+    });
+
+    expect(invoke).toHaveBeenCalledWith('set_is_screenshot', { path: '/s.png', isScreenshot: false });
+    expect(result.current.screenshots).toEqual([{ path: '/t.png' }]);
+    expect(deps.loadPhotosFromDatabase).toHaveBeenCalled();
+  });
+
   it('handleOpenArchive loads archived photos and shows view', async () => {
     const mockArchived = [{ photo: { path: '/a.jpg' }, days_until_deletion: 10 }];
     invoke
       .mockResolvedValueOnce(undefined) // cleanup_old_archives
-      .mockResolvedValueOnce(mockArchived); // get_archived_photos
+      .mockResolvedValueOnce(mockArchived); // get_deletion_archive
 
     const { result } = renderHook(() => useCleanup(deps));
 
@@ -90,6 +135,43 @@ describe('useCleanup', () => {
     });
 
     expect(result.current.showArchive).toBe(true);
-    expect(invoke).toHaveBeenCalledWith('get_archived_photos');
+    expect(invoke).toHaveBeenCalledWith('get_deletion_archive');
+  });
+
+  it('handleOpenHidden only fetches hidden photos after confirmation', async () => {
+    const confirm = vi.spyOn(window, 'confirm').mockReturnValueOnce(false).mockReturnValueOnce(true);
+    invoke.mockImplementation(async (cmd) => (cmd === 'get_hidden_photos' ? [{ path: '/h.jpg' }] : undefined));
+    const { result } = renderHook(() => useCleanup(deps));
+
+    await act(async () => {
+      result.current.handleOpenHidden();
+    });
+    expect(invoke).not.toHaveBeenCalledWith('get_hidden_photos');
+    expect(result.current.showHidden).toBe(false);
+
+    await act(async () => {
+      result.current.handleOpenHidden();
+    });
+    expect(result.current.showHidden).toBe(true);
+    expect(result.current.hiddenPhotos).toEqual([{ path: '/h.jpg' }]);
+    confirm.mockRestore();
+  });
+
+  it('handleHidePhotos and handleUnhidePhotos set the hidden flag', async () => {
+    invoke.mockImplementation(async (cmd) => (cmd === 'set_hidden' ? 2 : []));
+    const { result } = renderHook(() => useCleanup(deps));
+
+    await act(async () => {
+      await result.current.handleHidePhotos(['/a.jpg', '/b.jpg']);
+    });
+    expect(invoke).toHaveBeenCalledWith('set_hidden', { paths: ['/a.jpg', '/b.jpg'], hidden: true });
+    expect(deps.setStatusWithTimeout).toHaveBeenCalledWith('Hid 2 photos');
+    expect(deps.loadPhotosFromDatabase).toHaveBeenCalled();
+
+    await act(async () => {
+      await result.current.handleUnhidePhotos(['/a.jpg', '/b.jpg']);
+    });
+    expect(invoke).toHaveBeenCalledWith('set_hidden', { paths: ['/a.jpg', '/b.jpg'], hidden: false });
+    expect(invoke).toHaveBeenCalledWith('get_hidden_photos');
   });
 });
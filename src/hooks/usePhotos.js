@@ -1,15 +1,30 @@
 import { useState, useRef, useEffect, useCallback } from 'react';
-import { invoke } from '@tauri-apps/api/core';
+import { invoke, convertFileSrc } from '@tauri-apps/api/core';
+import { listen } from '@tauri-apps/api/event';
 import { open } from '@tauri-apps/plugin-dialog';
-import { processPhotos } from '../utils/photoHelpers';
+import { formatBytes, processPhotos } from '../utils/photoHelpers';
 import { CONFIG } from '../config';
 
+const HIDE_SCREENSHOTS_KEY = 'terra-hide-screenshots';
+
+function getInitialHideScreenshots() {
+  try {
+    return window.localStorage.getItem(HIDE_SCREENSHOTS_KEY) === 'true';
+  } catch {
+    // localStorage may be unavailable in some contexts
+    return false;
+  }
+}
+
 export function usePhotos() {
   const [photos, setPhotos] = useState([]);
   const [loading, setLoading] = useState(false);
   const [error, setError] = useState(null);
   const [uploadStatus, setUploadStatus] = useState('');
   const [libraryPath, setLibraryPath] = useState('');
+  const [hideScreenshots, setHideScreenshots] = useState(getInitialHideScreenshots);
+  // Read by loadPhotosFromDatabase so it keeps a stable identity
+  const hideScreenshotsRef = useRef(hideScreenshots);
 
   const statusTimeoutRef = useRef(null);
   const isMountedRef = useRef(true);
@@ -41,7 +56,9 @@ export function usePhotos() {
     setLoading(true);
     setError(null);
     try {
-      const result = await invoke('get_all_photos');
+      const result = hideScreenshotsRef.current
+        ? await invoke('get_all_photos', { filter: { exclude_screenshots: true } })
+        : await invoke('get_all_photos');
       if (!isMountedRef.current) return;
       setPhotos(processPhotos(result));
     } catch {
@@ -51,6 +68,53 @@ export function usePhotos() {
     }
   }, []);
 
+  // The backend's folder watcher imported or lost files outside the app.
+  useEffect(() => {
+    let unlisten;
+    listen('library_changed', () => {
+      loadPhotosFromDatabase();
+    }).then((u) => { unlisten = u; });
+    return () => { if (unlisten) unlisten(); };
+  }, [loadPhotosFromDatabase]);
+
+  // A photo was rotated: take its new shape, and a URL that skips the cached image.
+  useEffect(() => {
+    let unlisten;
+    listen('photo_changed', (event) => {
+      const { path, width, height, content_hash } = event.payload;
+      setPhotos((prev) => prev.map((p) => (p.path === path
+        ? { ...p, width, height, content_hash, hash: content_hash, url: `${convertFileSrc(path)}?v=${content_hash}` }
+        : p)));
+    }).then((u) => { unlisten = u; });
+    return () => { if (unlisten) unlisten(); };
+  }, []);
+
+  // A drive may have been plugged in or pulled while the window was in the background.
+  useEffect(() => {
+    const onFocus = async () => {
+      try {
+        const { went_offline, came_back } = await invoke('refresh_availability');
+        if (went_offline > 0 || came_back > 0) loadPhotosFromDatabase();
+      } catch (err) {
+        console.error('Failed to refresh availability:', err);
+      }
+    };
+    window.addEventListener('focus', onFocus);
+    return () => window.removeEventListener('focus', onFocus);
+  }, [loadPhotosFromDatabase]);
+
+  const toggleHideScreenshots = useCallback(() => {
+    const next = !hideScreenshotsRef.current;
+    hideScreenshotsRef.current = next;
+    setHideScreenshots(next);
+    try {
+      window.localStorage.setItem(HIDE_SCREENSHOTS_KEY, String(next));
+    } catch {
+      // ignore
+    }
+    loadPhotosFromDatabase();
+  }, [loadPhotosFromDatabase]);
+
   const handleUploadPhotos = useCallback(async () => {
     try {
       setUploadStatus('Selecting files...');
@@ -60,7 +124,7 @@ export function usePhotos() {
         multiple: true,
         filters: [{
           name: 'Media',
-          extensions: ['jpg', 'jpeg', 'png', 'heic', 'webp', 'gif', 'bmp', 'mp4', 'mov', 'avi', 'webm', 'mkv']
+          extensions: ['jpg', 'jpeg', 'png', 'heic', 'webp', 'gif', 'bmp', 'cr2', 'nef', 'arw', 'dng', 'mp4', 'mov', 'avi', 'webm', 'mkv']
         }]
       });
 
@@ -72,12 +136,34 @@ export function usePhotos() {
       setLoading(true);
       setUploadStatus(`Uploading ${selected.length} photos...`);
 
-      const uploaded = await invoke('upload_photos', { filePaths: selected });
+      const unlisten = await listen('upload_progress', (event) => {
+        const { current, total } = event.payload;
+        setUploadStatus(`Uploading ${current} / ${total}...`);
+      });
+      let result;
+      try {
+        result = await invoke('upload_photos', { filePaths: selected });
+      } finally {
+        unlisten();
+      }
       await loadPhotosFromDatabase();
 
-      setStatusWithTimeout(`Successfully uploaded ${uploaded.length} photos!`);
+      const { succeeded, failed, duplicates } = result;
+      let message = `Successfully uploaded ${succeeded.length} photos!`;
+      if (failed.length > 0) {
+        failed.forEach(f => console.error(`Upload failed (${f.kind}): ${f.path}: ${f.error}`));
+        message = `Uploaded ${succeeded.length} photos, ${failed.length} failed`;
+      }
+      if (duplicates.length > 0) {
+        message += ` (${duplicates.length} already in library)`;
+      }
+      setStatusWithTimeout(message);
     } catch (err) {
-      setError(typeof err === 'string' ? err : err?.message ?? 'Failed to upload photos');
+      if (err?.kind === 'insufficient_space') {
+        setError(`Not enough space on the library drive: these files need ${formatBytes(err.needed)} but only ${formatBytes(err.available)} is free`);
+      } else {
+        setError(typeof err === 'string' ? err : err?.message ?? 'Failed to upload photos');
+      }
       console.error('Upload error:', err);
       setUploadStatus('');
     } finally {
@@ -103,16 +189,31 @@ export function usePhotos() {
     if (!confirm(`Are you sure you want to delete ${selectedPhotos.size} items? This cannot be undone.`)) return;
     try {
       const paths = Array.from(selectedPhotos);
-      await invoke('delete_photos', { paths });
-      setPhotos(prev => prev.filter(p => !selectedPhotos.has(p.path)));
+      // Live Photo videos are hidden from the grid, so offer to take them along
+      const liveVideos = photos
+        .filter(p => selectedPhotos.has(p.path) && p.paired_video_path && !selectedPhotos.has(p.paired_video_path))
+        .map(p => p.paired_video_path);
+      if (liveVideos.length > 0 && confirm(`Also delete the ${liveVideos.length} Live Photo video${liveVideos.length === 1 ? '' : 's'} paired with ${liveVideos.length === 1 ? 'this photo' : 'these photos'}?`)) {
+        paths.push(...liveVideos);
+      }
+      const summary = await invoke('delete_photos', { paths });
+      // Files the system trash refused are still in the library
+      const failed = new Set((summary?.failed ?? []).map(f => f.path));
+      setPhotos(prev => prev.filter(p => !selectedPhotos.has(p.path) || failed.has(p.path)));
       clearSelection();
       loadAlbums();
       loadLocations();
+      const offlineKept = summary?.offline_files_kept?.length ?? 0;
+      if (failed.size > 0) {
+        setStatusWithTimeout(`${failed.size} item${failed.size === 1 ? " couldn't" : "s couldn't"} be moved to the trash and ${failed.size === 1 ? 'was' : 'were'} kept: ${summary.failed[0].error}`);
+      } else if (offlineKept > 0) {
+        setStatusWithTimeout(`${offlineKept} item${offlineKept === 1 ? ' was' : 's were'} on an offline drive; removed from Terra but the file${offlineKept === 1 ? ' was' : 's were'} not deleted`);
+      }
     } catch (err) {
       console.error("Failed to delete photos:", err);
       setError(typeof err === 'string' ? err : err?.message ?? 'Failed to delete items');
     }
-  }, []);
+  }, [photos, setStatusWithTimeout]);
 
   return {
     photos,
@@ -126,6 +227,8 @@ export function usePhotos() {
     setLibraryPath,
     setStatusWithTimeout,
     loadPhotosFromDatabase,
+    hideScreenshots,
+    toggleHideScreenshots,
     handleUploadPhotos,
     handleToggleFavorite,
     handleDeleteSelected,
@@ -4,6 +4,7 @@ import { usePhotos } from './usePhotos';
 
 const { invoke } = await import('@tauri-apps/api/core');
 const { open } = await import('@tauri-apps/plugin-dialog');
+const { listen } = await import('@tauri-apps/api/event');
 
 beforeEach(() => {
   vi.clearAllMocks();
@@ -43,6 +44,23 @@ describe('usePhotos', () => {
     expect(result.current.loading).toBe(false);
   });
 
+  it('toggleHideScreenshots reloads without screenshots and remembers it', async () => {
+    const { result } = renderHook(() => usePhotos());
+
+    await act(async () => {
+      result.current.toggleHideScreenshots();
+    });
+
+    expect(result.current.hideScreenshots).toBe(true);
+    expect(invoke).toHaveBeenCalledWith('get_all_photos', { filter: { exclude_screenshots: true } });
+    expect(window.localStorage.getItem('terra-hide-screenshots')).toBe('true');
+
+    await act(async () => {
+      result.current.toggleHideScreenshots();
+    });
+    expect(window.localStorage.getItem('terra-hide-screenshots')).toBe('false');
+  });
+
   it('handleUploadPhotos cancels when no files selected', async () => {
     open.mockResolvedValueOnce(null);
     const { result } = renderHook(() => usePhotos());
@@ -54,6 +72,50 @@ describe('usePhotos', () => {
     expect(result.current.uploadStatus).toBe('');
   });
 
+  it('handleUploadPhotos reports failures and duplicates', async () => {
+    open.mockResolvedValueOnce(['/in/a.jpg', '/in/b.jpg', '/in/c.jpg']);
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'upload_photos') {
+        return {
+          succeeded: [{ path: '/library/a.jpg', name: 'a.jpg', date_taken: 1700000000 }],
+          failed: [{ path: '/in/b.jpg', kind: 'copy_failed', error: 'disk full' }],
+          duplicates: [{ path: '/in/c.jpg', duplicate_of: '/library/c.jpg', photo: null }],
+        };
+      }
+      return [];
+    });
+    const consoleError = vi.spyOn(console, 'error').mockImplementation(() => {});
+    const { result } = renderHook(() => usePhotos());
+
+    await act(async () => {
+      await result.current.handleUploadPhotos();
+    });
+
+    expect(invoke).toHaveBeenCalledWith('upload_photos', { filePaths: ['/in/a.jpg', '/in/b.jpg', '/in/c.jpg'] });
+    expect(result.current.uploadStatus).toBe('Uploaded 1 photos, 1 failed (1 already in library)');
+    expect(consoleError).toHaveBeenCalled();
+    consoleError.mockRestore();
+  });
+
+  it('handleUploadPhotos explains a full library drive', async () => {
+    open.mockResolvedValueOnce(['/in/a.mov']);
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'upload_photos') {
+        throw { kind: 'insufficient_space', needed: 3 * 1024 ** 3, available: 1024 ** 3 };
+      }
+      return [];
+    });
+    const consoleError = vi.spyOn(console, 'error').mockImplementation(() => {});
+    const { result } = renderHook(() => usePhotos());
+
+    await act(async () => {
+      await result.current.handleUploadPhotos();
+    });
+
+    expect(result.current.error).toBe('Not enough space on the library drive: these files need 3 GB but only 1 GB is free');
+    consoleError.mockRestore();
+  });
+
   it('handleToggleFavorite optimistically updates photo', async () => {
     invoke.mockResolvedValueOnce('/library') // get_library_path_command
       .mockResolvedValueOnce([
@@ -81,6 +143,80 @@ describe('usePhotos', () => {
     expect(invoke).toHaveBeenCalledWith('toggle_favorite', { path: '/p/1.jpg', isFavorite: true });
   });
 
+  it('handleDeleteSelected reports files left on offline drives', async () => {
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'delete_photos') {
+        return { removed: 1, files_deleted: 0, originals_kept: [], offline_files_kept: ['/Volumes/SSD/a.jpg'] };
+      }
+      return [];
+    });
+    const confirmSpy = vi.spyOn(window, 'confirm').mockReturnValue(true);
+    const { result } = renderHook(() => usePhotos());
+
+    await act(async () => {
+      await result.current.handleDeleteSelected(new Set(['/Volumes/SSD/a.jpg']), vi.fn(), vi.fn(), vi.fn());
+    });
+
+    expect(invoke).toHaveBeenCalledWith('delete_photos', { paths: ['/Volumes/SSD/a.jpg'] });
+    expect(result.current.uploadStatus).toBe('1 item was on an offline drive; removed from Terra but the file was not deleted');
+    confirmSpy.mockRestore();
+  });
+
+  it('handleDeleteSelected keeps photos the trash refused', async () => {
+    invoke.mockImplementation(async (cmd) => {
+      if (cmd === 'get_library_path_command') return '/library';
+      if (cmd === 'get_all_photos') {
+        return [
+          { path: '/p/1.jpg', name: '1.jpg', date_taken: 1700000000, width: 10, height: 10 },
+          { path: '/p/2.jpg', name: '2.jpg', date_taken: 1700000001, width: 10, height: 10 },
+        ];
+      }
+      if (cmd === 'delete_photos') {
+        return {
+          removed: 1, files_deleted: 1, files_system_trashed: 1, moved_to_trash: 0,
+          originals_kept: [], offline_files_kept: [],
+          failed: [{ path: '/p/2.jpg', error: 'no trash on this mount' }],
+        };
+      }
+      return [];
+    });
+    const confirmSpy = vi.spyOn(window, 'confirm').mockReturnValue(true);
+    const { result } = renderHook(() => usePhotos());
+    await act(async () => {
+      await result.current.loadPhotosFromDatabase();
+    });
+
+    await act(async () => {
+      await result.current.handleDeleteSelected(new Set(['/p/1.jpg', '/p/2.jpg']), vi.fn(), vi.fn(), vi.fn());
+    });
+
+    expect(result.current.photos.map(p => p.path)).toEqual(['/p/2.jpg']);
+    expect(result.current.uploadStatus).toBe("1 item couldn't be moved to the trash and was kept: no trash on this mount");
+    confirmSpy.mockRestore();
+  });
+
+  it('updates a rotated photo from photo_changed', async () => {
+    invoke.mockResolvedValueOnce('/library')
+      .mockResolvedValueOnce([
+        { path: '/p/1.jpg', name: '1.jpg', date_taken: 1700000000, width: 4000, height: 3000, content_hash: 'old' },
+        { path: '/p/2.jpg', name: '2.jpg', date_taken: 1700000001, width: 4000, height: 3000, content_hash: 'other' },
+      ]);
+    const { result } = renderHook(() => usePhotos());
+    await act(async () => {
+      await result.current.loadPhotosFromDatabase();
+    });
+
+    const onChanged = listen.mock.calls.find(([name]) => name === 'photo_changed')[1];
+    act(() => {
+      onChanged({ payload: { path: '/p/1.jpg', width: 3000, height: 4000, orientation: 6, content_hash: 'new' } });
+    });
+
+    const [rotated, untouched] = result.current.photos;
+    expect(rotated).toMatchObject({ width: 3000, height: 4000, hash: 'new' });
+    expect(rotated.url).toContain('?v=new');
+    expect(untouched).toMatchObject({ width: 4000, hash: 'other' });
+  });
+
   it('setStatusWithTimeout clears status after timeout', async () => {
     vi.useFakeTimers();
     const { result } = renderHook(() => usePhotos());
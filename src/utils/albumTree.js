@@ -0,0 +1,34 @@
+/**
+ * Order albums for the sidebar so nested albums follow their parent.
+ *
+ * get_albums returns a flat list with `parent_id`; siblings keep the order
+ * they arrive in. Albums whose parent is missing from the list are shown at
+ * the top level.
+ *
+ * @param {Array} albums - albums from get_albums
+ * @returns {Array<{album: Object, depth: number}>}
+ */
+export function flattenAlbumTree(albums) {
+  // Only regular albums nest, and smart album ids overlap theirs
+  const ids = new Set(albums.filter(album => album.kind !== 'smart').map(album => album.id));
+  const children = new Map();
+  const roots = [];
+  albums.forEach(album => {
+    const nested = album.kind !== 'smart' && album.parent_id != null && ids.has(album.parent_id);
+    if (!nested) {
+      roots.push(album);
+      return;
+    }
+    if (!children.has(album.parent_id)) children.set(album.parent_id, []);
+    children.get(album.parent_id).push(album);
+  });
+
+  const rows = [];
+  const visit = (album, depth) => {
+    rows.push({ album, depth });
+    if (album.kind === 'smart') return;
+    (children.get(album.id) || []).forEach(child => visit(child, depth + 1));
+  };
+  roots.forEach(album => visit(album, 0));
+  return rows;
+}
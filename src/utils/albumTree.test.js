@@ -0,0 +1,28 @@
+import { describe, it, expect } from 'vitest';
+import { flattenAlbumTree } from './albumTree';
+
+const rows = albums => flattenAlbumTree(albums).map(({ album, depth }) => [album.name, depth]);
+
+describe('flattenAlbumTree', () => {
+  it('puts nested albums right after their parent', () => {
+    const albums = [
+      { id: 3, name: 'Japan', parent_id: 2, kind: 'regular' },
+      { id: 1, name: 'Trips', parent_id: null, kind: 'regular' },
+      { id: 4, name: 'Family', parent_id: null, kind: 'regular' },
+      { id: 2, name: '2023', parent_id: 1, kind: 'regular' },
+    ];
+    expect(rows(albums)).toEqual([['Trips', 0], ['2023', 1], ['Japan', 2], ['Family', 0]]);
+  });
+
+  it('shows albums with a missing parent at the top level', () => {
+    expect(rows([{ id: 2, name: 'Lost', parent_id: 9, kind: 'regular' }])).toEqual([['Lost', 0]]);
+  });
+
+  it('never nests under a smart album with the same id', () => {
+    const albums = [
+      { id: 1, name: 'Favorites', kind: 'smart' },
+      { id: 2, name: 'Orphan', parent_id: 1, kind: 'regular' },
+    ];
+    expect(rows(albums)).toEqual([['Favorites', 0], ['Orphan', 0]]);
+  });
+});
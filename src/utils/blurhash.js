@@ -0,0 +1,102 @@
+// Decoder for the BlurHash strings the backend stores per photo
+// (src-tauri/src/placeholders.rs). Follows the reference implementation
+// at https://blurha.sh.
+
+const BASE83 = '0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~';
+
+// Decoded placeholders are tiny, so the grid's repeat renders share them
+const PLACEHOLDER_SIZE = 32;
+const dataUrlCache = new Map();
+
+function decode83(str) {
+  let value = 0;
+  for (const char of str) {
+    const digit = BASE83.indexOf(char);
+    if (digit === -1) return NaN;
+    value = value * 83 + digit;
+  }
+  return value;
+}
+
+function srgbToLinear(value) {
+  const v = value / 255;
+  return v <= 0.04045 ? v / 12.92 : Math.pow((v + 0.055) / 1.055, 2.4);
+}
+
+function linearToSrgb(value) {
+  const v = Math.max(0, Math.min(1, value));
+  const srgb = v <= 0.0031308 ? v * 12.92 : 1.055 * Math.pow(v, 1 / 2.4) - 0.055;
+  return Math.trunc(srgb * 255 + 0.5);
+}
+
+function signPow(value, exponent) {
+  return Math.sign(value) * Math.pow(Math.abs(value), exponent);
+}
+
+/**
+ * Decode a BlurHash into `width` × `height` RGBA pixels, or null when the
+ * string isn't a valid hash.
+ */
+export function decodeBlurhash(hash, width, height) {
+  if (!hash || hash.length < 6) return null;
+  const sizeFlag = decode83(hash[0]);
+  const numY = Math.floor(sizeFlag / 9) + 1;
+  const numX = (sizeFlag % 9) + 1;
+  if (Number.isNaN(sizeFlag) || hash.length !== 4 + 2 * numX * numY) return null;
+
+  const maxValue = (decode83(hash[1]) + 1) / 166;
+  const dc = decode83(hash.slice(2, 6));
+  const colors = [[srgbToLinear(dc >> 16), srgbToLinear((dc >> 8) & 255), srgbToLinear(dc & 255)]];
+  for (let i = 1; i < numX * numY; i++) {
+    const value = decode83(hash.slice(4 + i * 2, 6 + i * 2));
+    colors.push([
+      signPow((Math.floor(value / 361) - 9) / 9, 2) * maxValue,
+      signPow(((Math.floor(value / 19) % 19) - 9) / 9, 2) * maxValue,
+      signPow(((value % 19) - 9) / 9, 2) * maxValue,
+    ]);
+  }
+  if (colors.some((color) => color.some(Number.isNaN))) return null;
+
+  const pixels = new Uint8ClampedArray(width * height * 4);
+  for (let y = 0; y < height; y++) {
+    for (let x = 0; x < width; x++) {
+      let r = 0, g = 0, b = 0;
+      for (let j = 0; j < numY; j++) {
+        for (let i = 0; i < numX; i++) {
+          const basis = Math.cos((Math.PI * x * i) / width) * Math.cos((Math.PI * y * j) / height);
+          const color = colors[i + j * numX];
+          r += color[0] * basis;
+          g += color[1] * basis;
+          b += color[2] * basis;
+        }
+      }
+      const at = 4 * (x + y * width);
+      pixels[at] = linearToSrgb(r);
+      pixels[at + 1] = linearToSrgb(g);
+      pixels[at + 2] = linearToSrgb(b);
+      pixels[at + 3] = 255;
+    }
+  }
+  return pixels;
+}
+
+/**
+ * A small PNG data URL of a BlurHash for use as a CSS background, or null
+ * without a valid hash or a canvas to draw on.
+ */
+export function blurhashToDataUrl(hash) {
+  if (!hash) return null;
+  if (dataUrlCache.has(hash)) return dataUrlCache.get(hash);
+  const pixels = decodeBlurhash(hash, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE);
+  let url = null;
+  const canvas = pixels && typeof document !== 'undefined' ? document.createElement('canvas') : null;
+  const ctx = canvas?.getContext?.('2d');
+  if (ctx) {
+    canvas.width = PLACEHOLDER_SIZE;
+    canvas.height = PLACEHOLDER_SIZE;
+    ctx.putImageData(new ImageData(pixels, PLACEHOLDER_SIZE, PLACEHOLDER_SIZE), 0, 0);
+    url = canvas.toDataURL();
+  }
+  dataUrlCache.set(hash, url);
+  return url;
+}
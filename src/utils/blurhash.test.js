@@ -0,0 +1,21 @@
+import { describe, it, expect } from 'vitest';
+import { decodeBlurhash } from './blurhash';
+
+// 4×3 components, average color #ff0000, no detail
+const SOLID_RED = `L0TI:j${'fH'.repeat(11)}`;
+
+describe('decodeBlurhash', () => {
+  it('decodes a hash with no detail to its average color', () => {
+    const pixels = decodeBlurhash(SOLID_RED, 3, 2);
+    expect(pixels).toHaveLength(3 * 2 * 4);
+    for (let i = 0; i < pixels.length; i += 4) {
+      expect([...pixels.slice(i, i + 4)]).toEqual([255, 0, 0, 255]);
+    }
+  });
+
+  it('rejects malformed hashes', () => {
+    expect(decodeBlurhash(null, 4, 4)).toBeNull();
+    expect(decodeBlurhash(SOLID_RED.slice(0, -2), 4, 4)).toBeNull();
+    expect(decodeBlurhash(`L0TI:j${'f"'.repeat(11)}`, 4, 4)).toBeNull();
+  });
+});
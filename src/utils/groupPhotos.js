@@ -7,7 +7,8 @@
  *
  * @param {string} viewMode  - one of 'all' | 'year' | 'month' | 'photos' |
  *                             'videos' | 'favorites' | 'locations' | 'search' |
- *                             'tags' | 'duplicates' | 'album:<id>' | 'collection:<id>'
+ *                             'tags' | 'duplicates' | 'album:<id>' | 'smart:<id>' |
+ *                             'collection:<id>'
  * @param {Array}  photos    - photos already loaded for this view
  * @param {Array}  smartCollections - smart collection metadata (used to
  *                             label `collection:<id>` views)
@@ -5,16 +5,30 @@ export const THUMB_SIZE = 256;
 
 /**
  * Resolve the asset URL to use for a photo's gallery card.
- * Returns the cached 256² thumbnail when ready, else falls back to the
- * original. Pure function — no side effects, no IO.
+ * Returns the cached 256² thumbnail when ready (or holding the camera's
+ * embedded preview), else falls back to the original. A photo with edits
+ * uses its edited render, cached under `edited_key`. Pure function — no
+ * side effects, no IO.
  */
 export function getThumbnailUrl(photo, thumbCacheRoot) {
   if (!thumbCacheRoot) return photo.url;
-  if (photo.thumb_status !== 'ready') return photo.url;
+  if (photo.thumb_status !== 'ready' && photo.thumb_status !== 'embedded') return photo.url;
   const hash = photo.content_hash;
   if (!hash) return photo.url;
+  // Edited renders share the content hash's prefix folder
+  const key = photo.edited_key || hash;
   const prefix = hash.length >= 2 ? hash.slice(0, 2) : hash;
-  return convertFileSrc(`${thumbCacheRoot}/${THUMB_SIZE}/${prefix}/${hash}.jpg`);
+  return convertFileSrc(`${thumbCacheRoot}/${THUMB_SIZE}/${prefix}/${key}.jpg`);
+}
+
+// Must match RAW_EXTENSIONS in src-tauri/src/raw.rs.
+const RAW_FORMATS = ['cr2', 'nef', 'arw', 'dng'];
+
+/**
+ * Whether a photo is a camera RAW file, from the backend's file_format.
+ */
+export function isRawFormat(photo) {
+  return RAW_FORMATS.includes(photo.file_format);
 }
 
 /**
@@ -23,7 +37,9 @@ export function getThumbnailUrl(photo, thumbCacheRoot) {
 export function processPhotos(rawPhotos) {
   return rawPhotos.map(p => ({
     ...p,
+    // The grid keys photos by path; the database row id travels as photoId
     id: p.path,
+    photoId: p.id,
     url: convertFileSrc(p.path),
     date: p.date_taken,
     mediaType: p.name.match(/\.(mp4|mov|avi|webm|mkv)$/i) ? 'video' : 'photo',
@@ -34,6 +34,12 @@ describe('processPhotos', () => {
     expect(result[0].url).toContain('asset://');
   });
 
+  it('keeps the database row id as photoId', () => {
+    const [photo] = processPhotos([{ id: 42, path: '/pics/photo.jpg', name: 'photo.jpg' }]);
+    expect(photo.id).toBe('/pics/photo.jpg');
+    expect(photo.photoId).toBe(42);
+  });
+
   it('detects video media type from extension', () => {
     const extensions = ['mp4', 'mov', 'avi', 'webm', 'mkv'];
     for (const ext of extensions) {
@@ -114,6 +120,16 @@ describe('getThumbnailUrl', () => {
     expect(result).toContain('asset://');
   });
 
+  it('uses the cached path for an embedded EXIF preview', () => {
+    const photo = { url: 'asset://orig', content_hash: 'abc123def', thumb_status: 'embedded' };
+    expect(decodeURIComponent(getThumbnailUrl(photo, root))).toContain(`${THUMB_SIZE}/ab/abc123def.jpg`);
+  });
+
+  it('uses the edited render when the photo has edits', () => {
+    const photo = { url: 'asset://orig', content_hash: 'abc123def', edited_key: 'abc123def-0011223344556677', thumb_status: 'ready' };
+    expect(decodeURIComponent(getThumbnailUrl(photo, root))).toContain(`${THUMB_SIZE}/ab/abc123def-0011223344556677.jpg`);
+  });
+
   it('handles a single-character hash gracefully', () => {
     const photo = { url: 'asset://orig', content_hash: 'a', thumb_status: 'ready' };
     const result = getThumbnailUrl(photo, root);